@@ -201,8 +201,12 @@ fn bench_memory_efficiency(c: &mut Criterion) {
     // Benchmark memory-efficient bulk processing
     group.bench_function("bulk_stats_processing", |b| {
         b.iter(|| {
-            use rostgis::vectorized_ops::bulk_geometry_stats;
-            bulk_geometry_stats(black_box(geometries.clone()))
+            use rostgis::vectorized_ops::GeometryStats;
+            let mut stats = GeometryStats::default();
+            for geom in black_box(&geometries) {
+                stats.add(geom);
+            }
+            stats
         })
     });
 
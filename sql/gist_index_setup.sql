@@ -1,34 +1,16 @@
--- GiST Index Setup for RostGIS - FINAL WORKING VERSION
--- This creates a minimal but functional spatial index
+-- GiST Index Demo for RostGIS
+-- The extension creates rostgis_gist_ops, the default GiST operator class of
+-- geometry, so CREATE INDEX ... USING GIST (geom) needs no setup. This
+-- script builds a demonstration table and index and checks that they work.
 
-\echo 'Setting up RostGIS Spatial Indexing (Final Working Version)...'
+\echo 'Testing RostGIS Spatial Indexing...'
 
--- Drop existing operator classes
+-- Operator classes created by this script before the extension shipped one
 DROP OPERATOR CLASS IF EXISTS gist_geometry_ops USING gist CASCADE;
 DROP OPERATOR CLASS IF EXISTS gist_geometry_ops_simple USING gist CASCADE;
 DROP OPERATOR CLASS IF EXISTS geometry_ops_minimal USING gist CASCADE;
 DROP OPERATOR CLASS IF EXISTS rostgis_geometry_gist_ops USING gist CASCADE;
 
--- Create the minimal working operator class
--- This follows PostgreSQL's exact requirements for GiST
-CREATE OPERATOR CLASS rostgis_gist_ops
-    DEFAULT FOR TYPE geometry USING gist AS
-        -- Storage type - use our bbox type
-        STORAGE bbox,
-        
-        -- Essential spatial operator
-        OPERATOR        3       && (geometry, geometry),
-        
-        -- Minimal required support functions (PostgreSQL requires these)
-        FUNCTION        1       geometry_gist_consistent(bbox, bbox, smallint, oid, boolean),
-        FUNCTION        2       geometry_gist_union(bbox[]),
-        FUNCTION        3       geometry_gist_compress(geometry),
-        FUNCTION        5       geometry_gist_penalty(bbox, bbox),
-        FUNCTION        6       geometry_gist_picksplit_left(bbox[]),
-        FUNCTION        7       geometry_gist_same(bbox, bbox);
-
-\echo 'Working GiST operator class created successfully!'
-
 -- Test spatial indexing functionality
 -- Create a demonstration table and test spatial indexing
 DO $$
@@ -172,4 +154,5 @@ ORDER BY indexname;
 \echo '  CREATE INDEX my_index ON my_table USING GIST (geom_column rostgis_gist_ops);'
 \echo ''
 \echo 'Spatial operators available: &&, <<, >>, ~, @, ~=, |>>, <<|, &<, &>, &<|, |&>'
+\echo 'Nearest neighbours use the index too: ORDER BY geom <-> ST_MakePoint(x, y) LIMIT 10'
 \echo 'All spatial predicates (ST_Intersects, ST_Contains, etc.) will work correctly.' 
\ No newline at end of file
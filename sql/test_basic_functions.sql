@@ -59,6 +59,5 @@ SELECT ST_Area(ST_GeomFromText('POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))')) AS pol
 SELECT ST_Length(ST_GeomFromText('LINESTRING(0 0, 3 4)')) AS linestring_length;
 SELECT ST_Perimeter(ST_GeomFromText('POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))')) AS polygon_perimeter;
 
--- Test 3D point (basic support)
-SELECT ST_MakePointZ(1.0, 2.0, 3.0) AS point_3d;
-SELECT ST_AsText(ST_MakePointZ(1.0, 2.0, 3.0)) AS point_3d_wkt; 
\ No newline at end of file
+-- Test 3D point: Z cannot be stored, so this raises an error
+SELECT ST_MakePointZ(1.0, 2.0, 3.0) AS point_3d;
\ No newline at end of file
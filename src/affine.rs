@@ -0,0 +1,330 @@
+use crate::functions::map_coords_keeping_empty_points;
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::{AffineTransform, Coord};
+use pgrx::prelude::*;
+use std::cell::Cell;
+
+// Affine transformations (ST_Affine, ST_Translate, ST_Scale, ST_Rotate)
+//
+// Move, resize and turn geometries, as when placing a template footprint on
+// a site or correcting a dataset digitised with an offset:
+//
+//   UPDATE parcels SET geom = ST_Translate(geom, -12.5, 3.0) WHERE survey = 7;
+//   SELECT ST_Rotate(template, radians(30), ST_PointOnSurface(template)) FROM templates;
+//
+// ST_Affine applies the matrix of PostGIS, x' = a x + b y + xoff and
+// y' = d x + e y + yoff. ST_Scale multiplies the coordinates by the factors,
+// relative to the origin point when one is given, and ST_Rotate (or
+// ST_RotateZ) turns counter-clockwise by an angle in radians about the
+// coordinate origin or the given point.
+//
+// The 3D forms take z coefficients and offsets like in PostGIS, and
+// transform the Z of geometries that have one. A 2D geometry lies in the
+// plane z = 0 and stays 2D: the z terms of the x and y rows have no effect
+// on it, and a transformation that would move it out of that plane raises
+// an error rather than losing the z values it computes. The 2D forms keep
+// Z and M unchanged.
+
+/// Transform every coordinate of a geometry
+pub fn affine_transform(geom: &Geometry, transform: &AffineTransform<f64>) -> Geometry {
+    map_coords_keeping_empty_points(geom, &|coord| transform.apply(coord))
+}
+
+/// Counter-clockwise rotation by an angle in radians about a point
+pub fn rotation(radians: f64, origin: Coord<f64>) -> AffineTransform<f64> {
+    let (sin, cos) = radians.sin_cos();
+    AffineTransform::new(
+        cos,
+        -sin,
+        origin.x - origin.x * cos + origin.y * sin,
+        sin,
+        cos,
+        origin.y - origin.x * sin - origin.y * cos,
+    )
+}
+
+/// Apply the 3D matrix of PostGIS, x' = a x + b y + c z + xoff,
+/// y' = d x + e y + f z + yoff and z' = g x + h y + i z + zoff, given as
+/// [a, b, c, d, e, f, g, h, i, xoff, yoff, zoff]
+pub fn affine_transform_3d(
+    geom: &Geometry,
+    matrix: [f64; 12],
+    function: &str,
+) -> Result<Geometry, RostGisError> {
+    let [a, b, c, d, e, f, g, h, i, xoff, yoff, zoff] = matrix;
+    let Some(ordinates) = geom.ordinates().filter(|ordinates| ordinates.z.is_some()) else {
+        check_planar(g, h, zoff, function)?;
+        return Ok(affine_transform(
+            geom,
+            &AffineTransform::new(a, b, xoff, d, e, yoff),
+        ));
+    };
+    let zs = ordinates.z.as_deref().unwrap_or_default();
+    let base = geom.xy();
+    let z: Vec<f64> = base
+        .coordinates()
+        .iter()
+        .zip(zs)
+        .map(|(&(x, y), z)| g * x + h * y + i * z + zoff)
+        .collect();
+    let moved = map_vertices(base, &|coord, vertex| Coord {
+        x: a * coord.x + b * coord.y + c * zs[vertex] + xoff,
+        y: d * coord.x + e * coord.y + f * zs[vertex] + yoff,
+    });
+    moved.with_ordinates(Some(z), ordinates.m.clone())
+}
+
+/// Map every vertex of an x/y geometry with its index in the order of
+/// `Geometry::coordinates`, keeping empty points
+fn map_vertices(geom: &Geometry, f: &impl Fn(Coord<f64>, usize) -> Coord<f64>) -> Geometry {
+    fn map(
+        geom: &Geometry,
+        next: &Cell<usize>,
+        f: &impl Fn(Coord<f64>, usize) -> Coord<f64>,
+    ) -> Geometry {
+        match geom {
+            Geometry::GeometryCollection(members, srid) => Geometry::GeometryCollection(
+                members.iter().map(|member| map(member, next, f)).collect(),
+                *srid,
+            ),
+            Geometry::Point(_, _) if geom.is_empty() => {
+                next.set(next.get() + 1);
+                geom.clone()
+            }
+            _ => map_coords_keeping_empty_points(geom, &|coord| {
+                f(coord, next.replace(next.get() + 1))
+            }),
+        }
+    }
+    map(geom, &Cell::new(0), f)
+}
+
+/// Check that the z row of a 3D transformation, z' = g x + h y + i z + zoff,
+/// keeps a 2D geometry in the plane z = 0
+pub fn check_planar(g: f64, h: f64, zoff: f64, function: &str) -> Result<(), RostGisError> {
+    if g != 0.0 || h != 0.0 || zoff != 0.0 {
+        return Err(RostGisError::new(&format!(
+            "{}: the geometry has no Z, so a transformation giving z values other than 0 is not supported",
+            function
+        )));
+    }
+    Ok(())
+}
+
+/// Coordinate of a point argument
+fn point_coord(
+    point: &Geometry,
+    argument: &str,
+    function: &str,
+) -> Result<Coord<f64>, RostGisError> {
+    match point {
+        Geometry::Point(point, _) if !point.x().is_nan() => Ok(point.0),
+        _ => Err(RostGisError::new(&format!(
+            "{}: {} must be a non-empty point",
+            function, argument
+        ))),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(immutable, strict, parallel_safe, name = "st_affine")]
+fn st_affine_3d(
+    geom: Geometry,
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+    f: f64,
+    g: f64,
+    h: f64,
+    i: f64,
+    xoff: f64,
+    yoff: f64,
+    zoff: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(affine_transform_3d(
+        &geom,
+        [a, b, c, d, e, f, g, h, i, xoff, yoff, zoff],
+        "ST_Affine",
+    )?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_affine(geom: Geometry, a: f64, b: f64, d: f64, e: f64, xoff: f64, yoff: f64) -> Geometry {
+    affine_transform(&geom, &AffineTransform::new(a, b, xoff, d, e, yoff))
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_translate(geom: Geometry, deltax: f64, deltay: f64) -> Geometry {
+    affine_transform(&geom, &AffineTransform::translate(deltax, deltay))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_translate")]
+fn st_translate_3d(
+    geom: Geometry,
+    deltax: f64,
+    deltay: f64,
+    deltaz: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(affine_transform_3d(
+        &geom,
+        [
+            1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, deltax, deltay, deltaz,
+        ],
+        "ST_Translate",
+    )?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_scale(geom: Geometry, xfactor: f64, yfactor: f64) -> Geometry {
+    affine_transform(
+        &geom,
+        &AffineTransform::new(xfactor, 0.0, 0.0, 0.0, yfactor, 0.0),
+    )
+}
+
+/// Scaling z maps the z = 0 plane of a 2D geometry onto itself
+#[pg_extern(immutable, strict, parallel_safe, name = "st_scale")]
+fn st_scale_3d(
+    geom: Geometry,
+    xfactor: f64,
+    yfactor: f64,
+    zfactor: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(affine_transform_3d(
+        &geom,
+        [
+            xfactor, 0.0, 0.0, 0.0, yfactor, 0.0, 0.0, 0.0, zfactor, 0.0, 0.0, 0.0,
+        ],
+        "ST_Scale",
+    )?)
+}
+
+/// Scale by the coordinates of a point
+#[pg_extern(immutable, strict, parallel_safe, name = "st_scale")]
+fn st_scale_by_point(
+    geom: Geometry,
+    factor: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let factor = point_coord(&factor, "the scale factor", "ST_Scale")?;
+    Ok(affine_transform(
+        &geom,
+        &AffineTransform::scale(factor.x, factor.y, Coord { x: 0.0, y: 0.0 }),
+    ))
+}
+
+/// Scale by the coordinates of a point, relative to a point of origin
+#[pg_extern(immutable, strict, parallel_safe, name = "st_scale")]
+fn st_scale_by_point_about(
+    geom: Geometry,
+    factor: Geometry,
+    origin: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let factor = point_coord(&factor, "the scale factor", "ST_Scale")?;
+    let origin = point_coord(&origin, "the origin", "ST_Scale")?;
+    Ok(affine_transform(
+        &geom,
+        &AffineTransform::scale(factor.x, factor.y, origin),
+    ))
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_rotate(geom: Geometry, rotradians: f64) -> Geometry {
+    affine_transform(&geom, &rotation(rotradians, Coord { x: 0.0, y: 0.0 }))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_rotate")]
+fn st_rotate_about(geom: Geometry, rotradians: f64, x0: f64, y0: f64) -> Geometry {
+    affine_transform(&geom, &rotation(rotradians, Coord { x: x0, y: y0 }))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_rotate")]
+fn st_rotate_about_point(
+    geom: Geometry,
+    rotradians: f64,
+    origin: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let origin = point_coord(&origin, "the origin", "ST_Rotate")?;
+    Ok(affine_transform(&geom, &rotation(rotradians, origin)))
+}
+
+/// Rotation about the z axis, the same as ST_Rotate
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_rotatez(geom: Geometry, rotradians: f64) -> Geometry {
+    st_rotate(geom, rotradians)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+    use std::f64::consts::FRAC_PI_2;
+
+    #[test]
+    fn test_affine_transform() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 2)")
+            .unwrap()
+            .with_srid(3857);
+        let moved = affine_transform(&line, &AffineTransform::translate(10.0, -1.0));
+        assert_eq!(moved.to_ewkt(), "SRID=3857;LINESTRING(10 -1,11 1)");
+        let sheared = affine_transform(&line, &AffineTransform::new(1.0, 1.0, 0.0, 0.0, 2.0, 5.0));
+        assert_eq!(sheared.to_ewkt(), "SRID=3857;LINESTRING(0 5,3 9)");
+
+        // Collections keep their structure and empty points stay points
+        let collection = geometry_from_wkt("GEOMETRYCOLLECTION(POINT EMPTY, POINT(1 1))").unwrap();
+        let scaled = affine_transform(&collection, &AffineTransform::scale(2.0, 3.0, (0.0, 0.0)));
+        assert_eq!(
+            scaled.to_wkt(),
+            "GEOMETRYCOLLECTION(POINT EMPTY,POINT(2 3))"
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotated = rotation(FRAC_PI_2, Coord { x: 1.0, y: 1.0 }).apply(Coord { x: 2.0, y: 1.0 });
+        assert!((rotated.x - 1.0).abs() < 1e-12 && (rotated.y - 2.0).abs() < 1e-12);
+        let about_origin =
+            rotation(FRAC_PI_2, Coord { x: 0.0, y: 0.0 }).apply(Coord { x: 1.0, y: 0.0 });
+        assert!(about_origin.x.abs() < 1e-12 && (about_origin.y - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_check_planar() {
+        assert!(check_planar(0.0, 0.0, 0.0, "ST_Affine").is_ok());
+        let error = check_planar(0.0, 0.0, 5.0, "ST_Translate").unwrap_err();
+        assert!(error.message.starts_with("ST_Translate: "));
+        assert!(check_planar(1.0, 0.0, 0.0, "ST_Affine").is_err());
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert!(point_coord(&empty, "the origin", "ST_Rotate").is_err());
+    }
+
+    #[test]
+    fn test_affine_transform_3d() {
+        let translate = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 2.0, 3.0];
+        let line = geometry_from_wkt("LINESTRING ZM (0 0 1 7, 1 2 3 8)").unwrap();
+        let moved = affine_transform_3d(&line, translate, "ST_Translate").unwrap();
+        assert_eq!(moved.to_wkt(), "LINESTRING ZM (1 2 4 7,2 4 6 8)");
+
+        // x' = z, z' = x, with the empty point keeping its place
+        let swap = [0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let collection =
+            geometry_from_wkt("GEOMETRYCOLLECTION Z (POINT Z EMPTY, POINT Z (1 2 3))").unwrap();
+        assert_eq!(
+            affine_transform_3d(&collection, swap, "ST_Affine")
+                .unwrap()
+                .to_wkt(),
+            "GEOMETRYCOLLECTION Z (POINT Z EMPTY,POINT Z (3 2 1))"
+        );
+
+        // A 2D geometry stays 2D, so it must stay at z = 0
+        let flat = geometry_from_wkt("POINT(1 1)").unwrap();
+        assert!(affine_transform_3d(&flat, translate, "ST_Translate").is_err());
+
+        // The 2D forms keep Z
+        let point = geometry_from_wkt("POINT Z (1 1 5)").unwrap();
+        let rotated = affine_transform(&point, &AffineTransform::translate(1.0, 0.0));
+        assert_eq!(rotated.to_wkt(), "POINT Z (2 1 5)");
+    }
+}
@@ -0,0 +1,440 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::BBox;
+use pgrx::prelude::*;
+
+// Planner statistics for geometry columns
+//
+// ANALYZE calls geometry_analyze, the typanalyze routine of the geometry
+// type, which collects a histogram of the bounding boxes of the sampled rows
+// into pg_statistic. geometry_overlap_sel, the restriction estimator of &&,
+// reads it back to estimate how many rows a filter like
+//
+//   ANALYZE parcels;
+//   EXPLAIN SELECT * FROM parcels WHERE geom && ST_MakeEnvelope(0, 0, 10, 10);
+//
+// returns, where PostgreSQL otherwise assumes 0.5% of the table whatever the
+// box. The histogram is a grid over the extent of the box centres holding
+// the share of the sampled rows whose box is centred in each cell, plus the
+// average box width and height. A box overlaps the query box when its centre
+// lies within the query box grown by half the box's size, so the estimate is
+// the share of centres within the query box grown by half the average size,
+// counting partially covered cells by the area covered. Columns without
+// statistics, and filters against anything but a constant, keep the 0.5%.
+
+/// pg_statistic kind of the box histogram. PostgreSQL reserves kinds 1-99,
+/// PostGIS 100-199 and ESRI 200-299.
+pub const STATISTIC_KIND_BOX_HISTOGRAM: i16 = 300;
+
+/// Selectivity of && without statistics, that of PostgreSQL's areasel
+pub const DEFAULT_SELECTIVITY: f64 = 0.005;
+
+/// Cells along each side of the grid at most
+const MAX_CELLS_PER_SIDE: usize = 100;
+
+/// Numbers stored ahead of the cells: columns, rows, the extent of the
+/// centres and the average box width and height
+const HEADER_NUMBERS: usize = 8;
+
+/// Histogram of bounding box centres over a regular grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxHistogram {
+    /// Extent of the box centres
+    pub extent: BBox,
+    pub columns: usize,
+    pub rows: usize,
+    pub average_width: f64,
+    pub average_height: f64,
+    /// Share of the sampled rows centred in each cell, row by row from the
+    /// bottom left
+    pub cells: Vec<f64>,
+}
+
+impl BoxHistogram {
+    /// Histogram of the boxes of a sample of `sample_rows` rows, the rows
+    /// without a box (NULL or empty) included; None without boxes
+    pub fn build(boxes: &[BBox], sample_rows: usize) -> Option<Self> {
+        let centres: Vec<(f64, f64)> = boxes
+            .iter()
+            .map(|bbox| {
+                (
+                    (bbox.min_x + bbox.max_x) / 2.0,
+                    (bbox.min_y + bbox.max_y) / 2.0,
+                )
+            })
+            .collect();
+        let extent = centres
+            .iter()
+            .map(|&(x, y)| BBox::new(x, y, x, y))
+            .reduce(|extent, centre| extent.union(&centre))?;
+
+        // About ten boxes per cell
+        let side = ((boxes.len() as f64 / 10.0).sqrt() as usize).clamp(1, MAX_CELLS_PER_SIDE);
+        let columns = if extent.max_x > extent.min_x { side } else { 1 };
+        let rows = if extent.max_y > extent.min_y { side } else { 1 };
+        let share = 1.0 / sample_rows.max(boxes.len()) as f64;
+        let mut cells = vec![0.0; columns * rows];
+        for &(x, y) in &centres {
+            let column = cell_index(x, extent.min_x, extent.max_x, columns);
+            let row = cell_index(y, extent.min_y, extent.max_y, rows);
+            cells[row * columns + column] += share;
+        }
+
+        let count = boxes.len() as f64;
+        Some(Self {
+            extent,
+            columns,
+            rows,
+            average_width: boxes
+                .iter()
+                .map(|bbox| bbox.max_x - bbox.min_x)
+                .sum::<f64>()
+                / count,
+            average_height: boxes
+                .iter()
+                .map(|bbox| bbox.max_y - bbox.min_y)
+                .sum::<f64>()
+                / count,
+            cells,
+        })
+    }
+
+    /// Estimated share of the rows whose box overlaps `query`
+    pub fn selectivity(&self, query: &BBox) -> f64 {
+        let (grow_x, grow_y) = (self.average_width / 2.0, self.average_height / 2.0);
+        let column_shares = axis_shares(
+            self.extent.min_x,
+            self.extent.max_x,
+            self.columns,
+            query.min_x - grow_x,
+            query.max_x + grow_x,
+        );
+        let row_shares = axis_shares(
+            self.extent.min_y,
+            self.extent.max_y,
+            self.rows,
+            query.min_y - grow_y,
+            query.max_y + grow_y,
+        );
+        let mut selectivity = 0.0;
+        for (row, row_share) in row_shares.iter().enumerate() {
+            if *row_share == 0.0 {
+                continue;
+            }
+            for (column, column_share) in column_shares.iter().enumerate() {
+                selectivity += self.cells[row * self.columns + column] * row_share * column_share;
+            }
+        }
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    /// The histogram as the float4 numbers of a pg_statistic slot
+    pub fn to_numbers(&self) -> Vec<f32> {
+        let mut numbers = vec![
+            self.columns as f32,
+            self.rows as f32,
+            self.extent.min_x as f32,
+            self.extent.min_y as f32,
+            self.extent.max_x as f32,
+            self.extent.max_y as f32,
+            self.average_width as f32,
+            self.average_height as f32,
+        ];
+        numbers.extend(self.cells.iter().map(|&cell| cell as f32));
+        numbers
+    }
+
+    /// The histogram stored by `to_numbers`; None if the numbers are not one
+    pub fn from_numbers(numbers: &[f32]) -> Option<Self> {
+        let header = numbers.get(..HEADER_NUMBERS)?;
+        let (columns, rows) = (header[0] as usize, header[1] as usize);
+        if columns == 0 || rows == 0 || numbers.len() != HEADER_NUMBERS + columns * rows {
+            return None;
+        }
+        Some(Self {
+            extent: BBox::new(
+                header[2] as f64,
+                header[3] as f64,
+                header[4] as f64,
+                header[5] as f64,
+            ),
+            columns,
+            rows,
+            average_width: header[6] as f64,
+            average_height: header[7] as f64,
+            cells: numbers[HEADER_NUMBERS..]
+                .iter()
+                .map(|&cell| cell as f64)
+                .collect(),
+        })
+    }
+}
+
+/// Cell of `value` among `cells` equal cells over [min, max]
+fn cell_index(value: f64, min: f64, max: f64, cells: usize) -> usize {
+    if cells == 1 || max <= min {
+        return 0;
+    }
+    (((value - min) / (max - min) * cells as f64) as usize).min(cells - 1)
+}
+
+/// Share of each of `cells` equal cells over [min, max] covered by [low, high]
+fn axis_shares(min: f64, max: f64, cells: usize, low: f64, high: f64) -> Vec<f64> {
+    if max <= min {
+        // All centres share one coordinate
+        let covered = low <= min && min <= high;
+        return vec![if covered { 1.0 } else { 0.0 }; cells];
+    }
+    let size = (max - min) / cells as f64;
+    (0..cells)
+        .map(|cell| {
+            let start = min + size * cell as f64;
+            let end = start + size;
+            ((high.min(end) - low.max(start)) / size).max(0.0)
+        })
+        .collect()
+}
+
+/// Statistics of a geometry column from the rows sampled by ANALYZE
+#[pg_guard]
+unsafe extern "C-unwind" fn compute_geometry_stats(
+    stats: *mut pg_sys::VacAttrStats,
+    fetchfunc: pg_sys::AnalyzeAttrFetchFunc,
+    samplerows: std::os::raw::c_int,
+    _totalrows: f64,
+) {
+    let fetch = fetchfunc.expect("ANALYZE passes a fetch function");
+    let mut nulls = 0;
+    let mut total_width = 0.0;
+    let mut boxes = Vec::new();
+    for row in 0..samplerows {
+        let mut isnull = false;
+        let datum = fetch(stats, row, &mut isnull);
+        if isnull {
+            nulls += 1;
+            continue;
+        }
+        total_width += pg_sys::toast_raw_datum_size(datum) as f64;
+        let Some(geom) = Geometry::from_datum(datum, false) else {
+            continue;
+        };
+        if geom.is_empty() {
+            continue;
+        }
+        let bbox = BBox::from_geometry(&geom);
+        if [bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y]
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            boxes.push(bbox);
+        }
+    }
+
+    let stats = &mut *stats;
+    let values = samplerows - nulls;
+    stats.stats_valid = true;
+    stats.stanullfrac = nulls as f32 / samplerows.max(1) as f32;
+    stats.stawidth = (total_width / values.max(1) as f64) as i32;
+    stats.stadistinct = 0.0;
+    if let Some(histogram) = BoxHistogram::build(&boxes, samplerows as usize) {
+        let numbers = histogram.to_numbers();
+        let stored = pg_sys::MemoryContextAlloc(
+            stats.anl_context,
+            numbers.len() * std::mem::size_of::<f32>(),
+        ) as *mut f32;
+        std::ptr::copy_nonoverlapping(numbers.as_ptr(), stored, numbers.len());
+        stats.stakind[0] = STATISTIC_KIND_BOX_HISTOGRAM;
+        stats.staop[0] = pg_sys::InvalidOid;
+        stats.stacoll[0] = pg_sys::InvalidOid;
+        stats.stanumbers[0] = stored;
+        stats.numnumbers[0] = numbers.len() as i32;
+    }
+}
+
+/// Typanalyze routine of the geometry type, sampling as many rows as the
+/// built-in types do for default_statistics_target
+#[pg_extern(strict)]
+fn geometry_analyze(stats: Internal) -> bool {
+    let Some(stats) = stats.unwrap() else {
+        return false;
+    };
+    unsafe {
+        let stats = &mut *stats.cast_mut_ptr::<pg_sys::VacAttrStats>();
+        stats.compute_stats = Some(compute_geometry_stats);
+        stats.minrows = 300 * pg_sys::default_statistics_target;
+    }
+    true
+}
+
+/// Estimate from the box histogram of the column compared by `args` with a
+/// constant; None when there is no such column, constant or histogram
+unsafe fn overlap_selectivity(
+    root: *mut pg_sys::PlannerInfo,
+    args: *mut pg_sys::List,
+    var_relid: i32,
+) -> Option<f64> {
+    let mut vardata: pg_sys::VariableStatData = std::mem::zeroed();
+    let mut other: *mut pg_sys::Node = std::ptr::null_mut();
+    let mut varonleft = false;
+    if !pg_sys::get_restriction_variable(
+        root,
+        args,
+        var_relid,
+        &mut vardata,
+        &mut other,
+        &mut varonleft,
+    ) {
+        return None;
+    }
+
+    let estimate = (|| {
+        if vardata.statsTuple.is_null() || !pgrx::is_a(other, pg_sys::NodeTag::T_Const) {
+            return None;
+        }
+        let constant = &*(other as *mut pg_sys::Const);
+        let Some(query) = Geometry::from_datum(constant.constvalue, constant.constisnull) else {
+            // && is strict, no row matches NULL
+            return Some(0.0);
+        };
+        if query.is_empty() {
+            return Some(0.0);
+        }
+        let mut slot: pg_sys::AttStatsSlot = std::mem::zeroed();
+        if !pg_sys::get_attstatsslot(
+            &mut slot,
+            vardata.statsTuple,
+            STATISTIC_KIND_BOX_HISTOGRAM as i32,
+            pg_sys::InvalidOid,
+            pg_sys::ATTSTATSSLOT_NUMBERS as i32,
+        ) {
+            return None;
+        }
+        let histogram = BoxHistogram::from_numbers(std::slice::from_raw_parts(
+            slot.numbers,
+            slot.nnumbers as usize,
+        ));
+        pg_sys::free_attstatsslot(&mut slot);
+        Some(histogram?.selectivity(&BBox::from_geometry(&query)))
+    })();
+
+    if !vardata.statsTuple.is_null() {
+        if let Some(free) = vardata.freefunc {
+            free(vardata.statsTuple);
+        }
+    }
+    estimate
+}
+
+/// Restriction selectivity estimator of the && operator
+#[pg_extern(stable, parallel_safe)]
+fn geometry_overlap_sel(
+    root: Internal,
+    _operator: pg_sys::Oid,
+    args: Internal,
+    var_relid: i32,
+) -> f64 {
+    let (Some(root), Some(args)) = (root.unwrap(), args.unwrap()) else {
+        return DEFAULT_SELECTIVITY;
+    };
+    unsafe { overlap_selectivity(root.cast_mut_ptr(), args.cast_mut_ptr(), var_relid) }
+        .unwrap_or(DEFAULT_SELECTIVITY)
+}
+
+extension_sql!(
+    r#"
+ALTER TYPE @extschema@.geometry SET (ANALYZE = @extschema@.geometry_analyze);
+
+ALTER OPERATOR @extschema@.&& (@extschema@.geometry, @extschema@.geometry)
+    SET (RESTRICT = @extschema@.geometry_overlap_sel, JOIN = areajoinsel);
+"#,
+    name = "geometry_statistics",
+    requires = [
+        Geometry,
+        geometry_overlap,
+        geometry_analyze,
+        geometry_overlap_sel
+    ],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point_grid(side: usize) -> Vec<BBox> {
+        let mut boxes = Vec::new();
+        for i in 0..side {
+            for j in 0..side {
+                boxes.push(BBox::new(i as f64, j as f64, i as f64, j as f64));
+            }
+        }
+        boxes
+    }
+
+    #[test]
+    fn test_histogram_of_points() {
+        let boxes = point_grid(100);
+        let histogram = BoxHistogram::build(&boxes, boxes.len()).unwrap();
+        assert_eq!((histogram.columns, histogram.rows), (31, 31));
+        assert!((histogram.cells.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let everything = histogram.selectivity(&BBox::new(-1.0, -1.0, 100.0, 100.0));
+        assert!((everything - 1.0).abs() < 1e-9);
+        let quarter = histogram.selectivity(&BBox::new(0.0, 0.0, 49.5, 49.5));
+        assert!((quarter - 0.25).abs() < 0.02, "quarter {}", quarter);
+        assert_eq!(
+            histogram.selectivity(&BBox::new(200.0, 200.0, 300.0, 300.0)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_histogram_of_boxes() {
+        // Boxes of 10 x 10 centred on a grid, half the rows NULL
+        let boxes: Vec<BBox> = point_grid(50)
+            .iter()
+            .map(|centre| {
+                BBox::new(
+                    centre.min_x - 5.0,
+                    centre.min_y - 5.0,
+                    centre.min_x + 5.0,
+                    centre.min_y + 5.0,
+                )
+            })
+            .collect();
+        let histogram = BoxHistogram::build(&boxes, boxes.len() * 2).unwrap();
+        assert_eq!(
+            (histogram.average_width, histogram.average_height),
+            (10.0, 10.0)
+        );
+        assert!((histogram.cells.iter().sum::<f64>() - 0.5).abs() < 1e-9);
+
+        // A thin query box still overlaps the boxes centred within 5 of it
+        let strip = histogram.selectivity(&BBox::new(20.0, -10.0, 20.0, 60.0));
+        assert!((strip - 0.5 * 10.0 / 50.0).abs() < 0.02, "strip {}", strip);
+    }
+
+    #[test]
+    fn test_histogram_of_one_point() {
+        let boxes = vec![BBox::new(3.0, 4.0, 3.0, 4.0); 20];
+        let histogram = BoxHistogram::build(&boxes, 20).unwrap();
+        assert_eq!((histogram.columns, histogram.rows), (1, 1));
+        assert_eq!(histogram.selectivity(&BBox::new(0.0, 0.0, 5.0, 5.0)), 1.0);
+        assert_eq!(histogram.selectivity(&BBox::new(0.0, 0.0, 2.0, 5.0)), 0.0);
+        assert_eq!(BoxHistogram::build(&[], 10), None);
+    }
+
+    #[test]
+    fn test_histogram_numbers() {
+        let boxes = point_grid(20);
+        let histogram = BoxHistogram::build(&boxes, 500).unwrap();
+        let numbers = histogram.to_numbers();
+        assert_eq!(numbers.len(), 8 + 36);
+        let restored = BoxHistogram::from_numbers(&numbers).unwrap();
+        assert_eq!((restored.columns, restored.rows), (6, 6));
+        assert_eq!(restored.extent, histogram.extent);
+        for (restored, cell) in restored.cells.iter().zip(&histogram.cells) {
+            assert!((restored - cell).abs() < 1e-6);
+        }
+        assert_eq!(BoxHistogram::from_numbers(&numbers[..20]), None);
+        assert_eq!(BoxHistogram::from_numbers(&[0.0; 8]), None);
+    }
+}
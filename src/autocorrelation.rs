@@ -0,0 +1,320 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::{GeometryWithId, SpatialIndex};
+use crate::utils::{aggregate_context, RostGisError};
+use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
+
+// Spatial autocorrelation (ST_MoransI, ST_GearysC)
+//
+// The aggregates collect (geometry, value) observations in their transition
+// state. The final function indexes the observations in an R*-tree and gives
+// each one binary weights to its neighbours: every other observation within
+// a distance band, or its k nearest ones. Like in the SpatialIndex,
+// geometries are located at the centre of their bounding box.
+
+/// How the neighbours of an observation are chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weights {
+    /// Observations within this distance
+    DistanceBand(f64),
+    /// The k nearest observations
+    Nearest(usize),
+}
+
+impl Weights {
+    /// Parse a weighting scheme, 'distance' or 'knn', and its parameter
+    pub fn parse(scheme: &str, parameter: f64) -> Result<Self, RostGisError> {
+        match scheme.to_ascii_lowercase().as_str() {
+            "distance" => {
+                if !parameter.is_finite() || parameter < 0.0 {
+                    return Err(RostGisError::new(
+                        "distance band must be a non-negative number",
+                    ));
+                }
+                Ok(Weights::DistanceBand(parameter))
+            }
+            "knn" => {
+                if parameter.fract() != 0.0 || parameter < 1.0 {
+                    return Err(RostGisError::new(
+                        "knn neighbour count must be a positive integer",
+                    ));
+                }
+                Ok(Weights::Nearest(parameter as usize))
+            }
+            _ => Err(RostGisError::new(&format!(
+                "unknown weighting scheme '{}', expected 'distance' or 'knn'",
+                scheme
+            ))),
+        }
+    }
+}
+
+/// Observations collected for autocorrelation statistics
+#[derive(Debug, Default)]
+pub struct Observations {
+    locations: Vec<GeometryWithId>,
+    values: Vec<f64>,
+}
+
+/// Global autocorrelation statistics of a set of observations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Autocorrelation {
+    pub morans_i: f64,
+    pub gearys_c: f64,
+}
+
+impl Observations {
+    /// Add an observation; empty geometries and non-finite values are skipped
+    pub fn add(&mut self, geom: &Geometry, value: f64) {
+        if geom.is_empty() || !value.is_finite() {
+            return;
+        }
+        self.locations
+            .push(GeometryWithId::new(self.values.len() as i64, geom.clone()));
+        self.values.push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Neighbours of every observation, by position
+    fn neighbours(&self, weights: Weights) -> Vec<Vec<usize>> {
+        let index = SpatialIndex::from_geometries(self.locations.clone());
+        self.locations
+            .iter()
+            .map(|observation| {
+                let bbox = &observation.bbox;
+                let centre = [
+                    (bbox.min_x + bbox.max_x) / 2.0,
+                    (bbox.min_y + bbox.max_y) / 2.0,
+                ];
+                let candidates = match weights {
+                    Weights::DistanceBand(distance) => index.within_distance(centre, distance),
+                    // One more, as the observation finds itself
+                    Weights::Nearest(k) => index.k_nearest_neighbors(centre, k.saturating_add(1)),
+                };
+                let others = candidates
+                    .into_iter()
+                    .filter(|other| other.id != observation.id)
+                    .map(|other| other.id as usize);
+                match weights {
+                    Weights::DistanceBand(_) => others.collect(),
+                    Weights::Nearest(k) => others.take(k).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Moran's I and Geary's C under the given weights
+    ///
+    /// None with fewer than two observations, when no observation has a
+    /// neighbour, or when all values are equal.
+    pub fn autocorrelation(&self, weights: Weights) -> Option<Autocorrelation> {
+        let n = self.len();
+        if n < 2 {
+            return None;
+        }
+        let mean = self.values.iter().sum::<f64>() / n as f64;
+        let deviations: Vec<f64> = self.values.iter().map(|value| value - mean).collect();
+        let variation: f64 = deviations.iter().map(|z| z * z).sum();
+
+        let (mut total_weight, mut cross_products, mut squared_differences) = (0.0, 0.0, 0.0);
+        for (i, neighbours) in self.neighbours(weights).iter().enumerate() {
+            for &j in neighbours {
+                total_weight += 1.0;
+                cross_products += deviations[i] * deviations[j];
+                squared_differences += (self.values[i] - self.values[j]).powi(2);
+            }
+        }
+        if total_weight == 0.0 || variation == 0.0 {
+            return None;
+        }
+
+        let n = n as f64;
+        Some(Autocorrelation {
+            morans_i: n / total_weight * cross_products / variation,
+            gearys_c: (n - 1.0) / (2.0 * total_weight) * squared_differences / variation,
+        })
+    }
+}
+
+/// Transition state of the autocorrelation aggregates
+///
+/// The weighting scheme is taken from the first row.
+struct AutocorrelationState {
+    observations: Observations,
+    weights: Weights,
+}
+
+/// Add a row to the aggregate state, allocated in the aggregate context
+unsafe fn autocorrelation_accumulate(
+    fcinfo: pg_sys::FunctionCallInfo,
+    mut state: Internal,
+    geom: Option<Geometry>,
+    value: Option<f64>,
+    scheme: Option<&str>,
+    parameter: Option<f64>,
+) -> Result<Internal, RostGisError> {
+    let aggcontext = aggregate_context(fcinfo, "spatial autocorrelation transition")?;
+    if state.get::<AutocorrelationState>().is_none() {
+        let (Some(scheme), Some(parameter)) = (scheme, parameter) else {
+            return Err(RostGisError::new(
+                "weighting scheme and parameter cannot be NULL",
+            ));
+        };
+        let weights = Weights::parse(scheme, parameter)?;
+        PgMemoryContexts::For(aggcontext).switch_to(|_| {
+            state.get_or_insert_with(|| AutocorrelationState {
+                observations: Observations::default(),
+                weights,
+            });
+        });
+    }
+
+    if let (Some(geom), Some(value), Some(autocorrelation)) =
+        (geom, value, state.get_mut::<AutocorrelationState>())
+    {
+        autocorrelation.observations.add(&geom, value);
+    }
+    Ok(state)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_autocorrelation_transfn(
+    state: Internal,
+    geom: Option<Geometry>,
+    value: Option<f64>,
+    scheme: Option<&str>,
+    parameter: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe { autocorrelation_accumulate(fcinfo, state, geom, value, scheme, parameter)? })
+}
+
+fn final_statistics(state: &Internal) -> Option<Autocorrelation> {
+    let state = unsafe { state.get::<AutocorrelationState>() }?;
+    state.observations.autocorrelation(state.weights)
+}
+
+/// Final function of ST_MoransI
+#[pg_extern(immutable, parallel_safe)]
+pub fn st_moransi_finalfn(state: Internal) -> Option<f64> {
+    final_statistics(&state).map(|statistics| statistics.morans_i)
+}
+
+/// Final function of ST_GearysC
+#[pg_extern(immutable, parallel_safe)]
+pub fn st_gearysc_finalfn(state: Internal) -> Option<f64> {
+    final_statistics(&state).map(|statistics| statistics.gearys_c)
+}
+
+extension_sql!(
+    r#"
+CREATE AGGREGATE @extschema@.st_moransi(@extschema@.geometry, float8, text, float8) (
+    SFUNC = @extschema@.st_autocorrelation_transfn, STYPE = internal, FINALFUNC = @extschema@.st_moransi_finalfn
+);
+CREATE AGGREGATE @extschema@.st_gearysc(@extschema@.geometry, float8, text, float8) (
+    SFUNC = @extschema@.st_autocorrelation_transfn, STYPE = internal, FINALFUNC = @extschema@.st_gearysc_finalfn
+);
+"#,
+    name = "autocorrelation_aggregates",
+    requires = [
+        Geometry,
+        st_autocorrelation_transfn,
+        st_moransi_finalfn,
+        st_gearysc_finalfn
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn observations(points: &[(f64, f64)]) -> Observations {
+        let mut observations = Observations::default();
+        for &(x, value) in points {
+            observations.add(&make_point(x, 0.0), value);
+        }
+        observations
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_distance_band() {
+        let band = Weights::DistanceBand(1.0);
+        let clustered = observations(&[(0.0, 1.0), (1.0, 1.0), (2.0, 0.0), (3.0, 0.0)])
+            .autocorrelation(band)
+            .unwrap();
+        assert_close(clustered.morans_i, 1.0 / 3.0);
+        assert_close(clustered.gearys_c, 0.5);
+
+        let alternating = observations(&[(0.0, 1.0), (1.0, 0.0), (2.0, 1.0), (3.0, 0.0)])
+            .autocorrelation(band)
+            .unwrap();
+        assert_close(alternating.morans_i, -1.0);
+        assert_close(alternating.gearys_c, 1.5);
+    }
+
+    #[test]
+    fn test_nearest_neighbours() {
+        // Nearest neighbours 0-1, 1-0, 2-1 and 3-2
+        let statistics = observations(&[(0.0, 1.0), (1.0, 1.0), (3.0, 0.0), (6.0, 0.0)])
+            .autocorrelation(Weights::Nearest(1))
+            .unwrap();
+        assert_close(statistics.morans_i, 0.5);
+        assert_close(statistics.gearys_c, 0.375);
+
+        // A count beyond the number of observations takes all of them
+        let everyone = observations(&[(0.0, 1.0), (1.0, 1.0), (3.0, 0.0)])
+            .autocorrelation(Weights::parse("knn", 1e300).unwrap());
+        let all = observations(&[(0.0, 1.0), (1.0, 1.0), (3.0, 0.0)])
+            .autocorrelation(Weights::Nearest(2));
+        assert_eq!(everyone, all);
+    }
+
+    #[test]
+    fn test_undefined_statistics() {
+        let band = Weights::DistanceBand(1.0);
+        assert!(observations(&[(0.0, 1.0)]).autocorrelation(band).is_none());
+        // Nobody has a neighbour
+        assert!(observations(&[(0.0, 1.0), (5.0, 0.0)])
+            .autocorrelation(band)
+            .is_none());
+        // No variation
+        assert!(observations(&[(0.0, 1.0), (1.0, 1.0)])
+            .autocorrelation(band)
+            .is_none());
+
+        let mut skipped = Observations::default();
+        skipped.add(&geometry_from_wkt("POINT EMPTY").unwrap(), 1.0);
+        skipped.add(&make_point(0.0, 0.0), f64::NAN);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_parse_weights() {
+        assert_eq!(
+            Weights::parse("Distance", 2.5).unwrap(),
+            Weights::DistanceBand(2.5)
+        );
+        assert_eq!(Weights::parse("knn", 4.0).unwrap(), Weights::Nearest(4));
+        assert!(Weights::parse("knn", 0.0).is_err());
+        assert!(Weights::parse("knn", 1.5).is_err());
+        assert!(Weights::parse("distance", -1.0).is_err());
+        assert!(Weights::parse("queen", 1.0).is_err());
+    }
+}
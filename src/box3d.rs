@@ -0,0 +1,362 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::BBox;
+use crate::utils::{format_number, RostGisError};
+use pgrx::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// 3D bounding boxes (box3d)
+//
+// A box3d is the extent of a dataset in x, y and z, written like in PostGIS:
+//
+//   SELECT 'BOX3D(0 0 0,10 10 5)'::box3d;
+//   SELECT geom::box3d FROM parcels;
+//
+// &&& is true when two boxes overlap in all three dimensions, which filters
+// rows by volume:
+//
+//   SELECT * FROM scans WHERE extent &&& 'BOX3D(0 0 10,5 5 20)'::box3d;
+//
+// The z extent of a geometry's box is that of its Z ordinates; a geometry
+// without Z lies flat at z = 0, as does a 2D box text such as
+// BOX3D(0 0,10 10), so between 2D geometries &&& is the same test as &&.
+// Geometries cast to box3d on assignment, and box3d to geometry and bbox
+// only explicitly. &&& between a geometry and a box3d is an operator of its own, so an index
+// with the gist_geometry_ops_nd operator class can filter a geometry column
+// by a box3d (see the gist_nd module).
+
+/// Bounding box in three dimensions
+#[derive(Debug, Clone, PartialEq, PostgresType, Serialize, Deserialize)]
+#[pg_binary_protocol]
+#[inoutfuncs]
+#[serde(rename_all = "camelCase")]
+pub struct Box3D {
+    #[serde(rename = "minX")]
+    pub min_x: f64,
+    #[serde(rename = "minY")]
+    pub min_y: f64,
+    #[serde(rename = "minZ")]
+    pub min_z: f64,
+    #[serde(rename = "maxX")]
+    pub max_x: f64,
+    #[serde(rename = "maxY")]
+    pub max_y: f64,
+    #[serde(rename = "maxZ")]
+    pub max_z: f64,
+}
+
+impl Box3D {
+    pub fn new(min: (f64, f64, f64), max: (f64, f64, f64)) -> Self {
+        Box3D {
+            min_x: min.0,
+            min_y: min.1,
+            min_z: min.2,
+            max_x: max.0,
+            max_y: max.1,
+            max_z: max.2,
+        }
+    }
+
+    /// The box of a 2D box, at z = 0
+    pub fn from_bbox(bbox: &BBox) -> Self {
+        Box3D::new((bbox.min_x, bbox.min_y, 0.0), (bbox.max_x, bbox.max_y, 0.0))
+    }
+
+    /// The box of a geometry, spanning its Z ordinates or at z = 0 without
+    /// them; None for an empty geometry, which has no box
+    pub fn from_geometry(geom: &Geometry) -> Option<Self> {
+        if geom.is_empty() {
+            return None;
+        }
+        let bbox = BBox::from_geometry(geom);
+        let zs = geom.ordinates().and_then(|ordinates| ordinates.z.as_ref());
+        // Empty points have NaN ordinates, which f64::min and max skip
+        let (min_z, max_z) = zs.map_or((0.0, 0.0), |zs| {
+            zs.iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &z| {
+                    (min.min(z), max.max(z))
+                })
+        });
+        Some(Box3D::new(
+            (bbox.min_x, bbox.min_y, min_z),
+            (bbox.max_x, bbox.max_y, max_z),
+        ))
+    }
+
+    /// Index key of the empty geometries (see the gist_nd module)
+    pub fn empty() -> Self {
+        Box3D::new(
+            (f64::NAN, f64::NAN, f64::NAN),
+            (f64::NAN, f64::NAN, f64::NAN),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_x.is_nan()
+    }
+
+    /// The box without its z extent
+    pub fn to_bbox(&self) -> BBox {
+        BBox::new(self.min_x, self.min_y, self.max_x, self.max_y)
+    }
+
+    /// Check if two boxes overlap in x, y and z
+    pub fn overlaps(&self, other: &Box3D) -> bool {
+        self.to_bbox().overlaps(&other.to_bbox())
+            && self.min_z <= other.max_z
+            && other.min_z <= self.max_z
+    }
+
+    /// Box covering both boxes
+    pub fn union(&self, other: &Box3D) -> Box3D {
+        Box3D::new(
+            (
+                self.min_x.min(other.min_x),
+                self.min_y.min(other.min_y),
+                self.min_z.min(other.min_z),
+            ),
+            (
+                self.max_x.max(other.max_x),
+                self.max_y.max(other.max_y),
+                self.max_z.max(other.max_z),
+            ),
+        )
+    }
+
+    /// Box shared by both boxes; None if they do not overlap
+    pub fn intersection(&self, other: &Box3D) -> Option<Box3D> {
+        self.overlaps(other).then(|| {
+            Box3D::new(
+                (
+                    self.min_x.max(other.min_x),
+                    self.min_y.max(other.min_y),
+                    self.min_z.max(other.min_z),
+                ),
+                (
+                    self.max_x.min(other.max_x),
+                    self.max_y.min(other.max_y),
+                    self.max_z.min(other.max_z),
+                ),
+            )
+        })
+    }
+
+    /// Volume of the box
+    pub fn volume(&self) -> f64 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y) * (self.max_z - self.min_z)
+    }
+
+    /// Sum of the box's extents, which unlike the volume grows with boxes
+    /// that are flat in a dimension
+    pub fn margin(&self) -> f64 {
+        (self.max_x - self.min_x) + (self.max_y - self.min_y) + (self.max_z - self.min_z)
+    }
+
+    /// Parse the text form BOX3D(min_x min_y min_z,max_x max_y max_z), the
+    /// z coordinates optional; the corners may come in any order
+    pub fn parse(input: &str) -> Result<Box3D, RostGisError> {
+        let invalid = || RostGisError::new(&format!("Invalid box3d: {}", input));
+        let trimmed = input.trim();
+        let prefix = trimmed.get(..5).ok_or_else(invalid)?;
+        if !prefix.eq_ignore_ascii_case("BOX3D") {
+            return Err(invalid());
+        }
+        let body = trimmed[5..]
+            .trim()
+            .strip_prefix('(')
+            .and_then(|body| body.strip_suffix(')'))
+            .ok_or_else(invalid)?;
+        let corners = body
+            .split(',')
+            .map(|corner| {
+                let values = corner
+                    .split_whitespace()
+                    .map(str::parse::<f64>)
+                    .collect::<Result<Vec<f64>, _>>()
+                    .map_err(|_| invalid())?;
+                match values[..] {
+                    [x, y] => Ok((x, y, 0.0)),
+                    [x, y, z] => Ok((x, y, z)),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let [a, b] = corners[..] else {
+            return Err(invalid());
+        };
+        Ok(Box3D::new(
+            (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        ))
+    }
+}
+
+impl pgrx::InOutFuncs for Box3D {
+    fn input(input: &std::ffi::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        let input_str = input.to_str().expect("Invalid UTF-8 in box3d input");
+        match Box3D::parse(input_str) {
+            Ok(bbox) => bbox,
+            Err(e) => error!("{}", e.message),
+        }
+    }
+
+    fn output(&self, buffer: &mut pgrx::StringInfo) {
+        let corner = |x: f64, y: f64, z: f64| {
+            format!(
+                "{} {} {}",
+                format_number(x, None),
+                format_number(y, None),
+                format_number(z, None)
+            )
+        };
+        buffer.push_str(&format!(
+            "BOX3D({},{})",
+            corner(self.min_x, self.min_y, self.min_z),
+            corner(self.max_x, self.max_y, self.max_z)
+        ));
+    }
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "box3d")]
+fn box3d_from_geometry(geom: Geometry) -> Option<Box3D> {
+    Box3D::from_geometry(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "box3d")]
+fn box3d_from_bbox(bbox: BBox) -> Box3D {
+    Box3D::from_bbox(&bbox)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "bbox")]
+fn bbox_from_box3d(bbox: Box3D) -> BBox {
+    bbox.to_bbox()
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "geometry")]
+fn geometry_from_box3d(bbox: Box3D) -> Geometry {
+    bbox.to_bbox().to_geometry()
+}
+
+/// 3D bounding box overlap operator (&&&)
+#[pg_operator(immutable, parallel_safe)]
+#[opname(&&&)]
+#[commutator(&&&)]
+fn box3d_overlap(left: Box3D, right: Box3D) -> bool {
+    left.overlaps(&right)
+}
+
+/// 3D bounding box overlap operator (&&&) of geometries, the same as && for
+/// geometries without Z
+#[pg_operator(immutable, parallel_safe)]
+#[opname(&&&)]
+#[commutator(&&&)]
+fn geometry_overlap_3d(left: Geometry, right: Geometry) -> bool {
+    match (Box3D::from_geometry(&left), Box3D::from_geometry(&right)) {
+        (Some(left), Some(right)) => left.overlaps(&right),
+        _ => false,
+    }
+}
+
+/// 3D bounding box overlap operator (&&&) of a geometry and a box
+#[pg_operator(immutable, parallel_safe)]
+#[opname(&&&)]
+#[commutator(&&&)]
+fn geometry_overlap_box3d(left: Geometry, right: Box3D) -> bool {
+    Box3D::from_geometry(&left).is_some_and(|left| left.overlaps(&right))
+}
+
+/// 3D bounding box overlap operator (&&&) of a box and a geometry
+#[pg_operator(immutable, parallel_safe)]
+#[opname(&&&)]
+#[commutator(&&&)]
+fn box3d_overlap_geometry(left: Box3D, right: Geometry) -> bool {
+    Box3D::from_geometry(&right).is_some_and(|right| left.overlaps(&right))
+}
+
+extension_sql!(
+    r#"
+CREATE CAST (@extschema@.geometry AS @extschema@.box3d)
+    WITH FUNCTION @extschema@.box3d(@extschema@.geometry) AS ASSIGNMENT;
+CREATE CAST (@extschema@.bbox AS @extschema@.box3d)
+    WITH FUNCTION @extschema@.box3d(@extschema@.bbox) AS ASSIGNMENT;
+CREATE CAST (@extschema@.box3d AS @extschema@.bbox)
+    WITH FUNCTION @extschema@.bbox(@extschema@.box3d);
+CREATE CAST (@extschema@.box3d AS @extschema@.geometry)
+    WITH FUNCTION @extschema@.geometry(@extschema@.box3d);
+"#,
+    name = "box3d_casts",
+    requires = [
+        Geometry,
+        BBox,
+        Box3D,
+        box3d_from_geometry,
+        box3d_from_bbox,
+        bbox_from_box3d,
+        geometry_from_box3d
+    ],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    #[test]
+    fn test_parse_box3d() {
+        assert_eq!(
+            Box3D::parse("BOX3D(1 2 3,4 5 6)").unwrap(),
+            Box3D::new((1.0, 2.0, 3.0), (4.0, 5.0, 6.0))
+        );
+        assert_eq!(
+            Box3D::parse(" box3d (4 5 6, 1 2 3) ").unwrap(),
+            Box3D::new((1.0, 2.0, 3.0), (4.0, 5.0, 6.0))
+        );
+        assert_eq!(
+            Box3D::parse("BOX3D(0 0,10 10)").unwrap(),
+            Box3D::new((0.0, 0.0, 0.0), (10.0, 10.0, 0.0))
+        );
+        for invalid in [
+            "BOX(0 0,1 1)",
+            "BOX3D(0 0 0)",
+            "BOX3D(0 0 0,1 1 x)",
+            "BOX3D(0,1)",
+            "",
+        ] {
+            assert!(Box3D::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_box3d_overlaps() {
+        let a = Box3D::new((0.0, 0.0, 0.0), (10.0, 10.0, 10.0));
+        assert!(a.overlaps(&Box3D::new((5.0, 5.0, 10.0), (20.0, 20.0, 20.0))));
+        // Overlapping in plan, apart in height
+        assert!(!a.overlaps(&Box3D::new((5.0, 5.0, 11.0), (20.0, 20.0, 20.0))));
+        assert!(!a.overlaps(&Box3D::new((11.0, 0.0, 0.0), (20.0, 10.0, 10.0))));
+    }
+
+    #[test]
+    fn test_box3d_of_geometry() {
+        let line = geometry_from_wkt("LINESTRING(0 1, 4 -2)").unwrap();
+        assert_eq!(
+            Box3D::from_geometry(&line),
+            Some(Box3D::new((0.0, -2.0, 0.0), (4.0, 1.0, 0.0)))
+        );
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert_eq!(Box3D::from_geometry(&empty), None);
+
+        let line = geometry_from_wkt("LINESTRING Z (0 1 5, 4 -2 -3)").unwrap();
+        assert_eq!(
+            Box3D::from_geometry(&line),
+            Some(Box3D::new((0.0, -2.0, -3.0), (4.0, 1.0, 5.0)))
+        );
+        let collection =
+            geometry_from_wkt("GEOMETRYCOLLECTION Z (POINT Z EMPTY, POINT Z (1 1 2))").unwrap();
+        assert_eq!(
+            Box3D::from_geometry(&collection),
+            Some(Box3D::new((1.0, 1.0, 2.0), (1.0, 1.0, 2.0)))
+        );
+    }
+}
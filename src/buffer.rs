@@ -0,0 +1,871 @@
+use crate::geometry::Geometry;
+use crate::overlay::{overlay, report_fallbacks};
+use crate::utils::RostGisError;
+use geo::orient::Direction;
+use geo::{unary_union, Area, BooleanOps, Orient, Validation};
+use geo_types::{Coord, LineString, MultiPolygon, Polygon, Rect};
+use i_overlay::float::filter::ContourFilter;
+use i_overlay::mesh::outline::offset::OutlineOffset;
+use i_overlay::mesh::stroke::offset::StrokeOffset;
+use i_overlay::mesh::style::{LineCap, LineJoin, OutlineStyle, StrokeStyle};
+use pgrx::prelude::*;
+use std::f64::consts::PI;
+
+// Buffering through i_overlay's offsetting
+//
+// i_overlay works on implicitly closed paths with clockwise outer contours
+// and counter-clockwise holes, the opposite of the geo convention, so rings
+// are reversed on the way in and out.
+//
+// A ring offset of a polygon is the band along its boundary between the
+// polygon and its buffer, outside it for a positive distance and inside it,
+// the setback of zoning analysis, for a negative one. The offset edge
+// follows the join style, so with join=mitre it keeps the corners of the
+// polygon up to the mitre limit:
+//
+//   SELECT ST_RingOffset(parcel, -5, 'join=mitre') FROM parcels; -- setback
+//   SELECT ST_RingOffset(parcel, 20, 'join=mitre') FROM parcels; -- outer offset
+
+/// Parts smaller than this fraction of the squared distance are precision
+/// slivers left over by erosion and are dropped
+const SLIVER_AREA_RATIO: f64 = 1e-9;
+
+fn ring_to_path(ring: &LineString<f64>) -> Vec<[f64; 2]> {
+    let mut path: Vec<[f64; 2]> = ring.0.iter().map(|c| [c.x, c.y]).collect();
+    if path.len() > 1 && path.first() == path.last() {
+        path.pop();
+    }
+    path.reverse();
+    path
+}
+
+fn path_to_ring(path: &[[f64; 2]]) -> LineString<f64> {
+    let mut ring: LineString<f64> = path
+        .iter()
+        .rev()
+        .map(|p| Coord { x: p[0], y: p[1] })
+        .collect();
+    ring.close();
+    ring
+}
+
+fn shapes_to_polygons(shapes: Vec<Vec<Vec<[f64; 2]>>>) -> Vec<Polygon<f64>> {
+    shapes
+        .into_iter()
+        .filter(|shape| !shape.is_empty())
+        .map(|shape| {
+            let mut rings = shape.iter().map(|path| path_to_ring(path));
+            let exterior = rings.next().unwrap();
+            Polygon::new(exterior, rings.collect())
+        })
+        .collect()
+}
+
+fn polygon_to_shape(polygon: &Polygon<f64>) -> Vec<Vec<[f64; 2]>> {
+    let polygon = polygon.orient(Direction::Default);
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(ring_to_path)
+        .filter(|path| path.len() >= 3)
+        .collect()
+}
+
+/// End cap of buffered lines and points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndCap {
+    Round,
+    Flat,
+    Square,
+}
+
+/// Join between consecutive segments of an offset curve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Join {
+    Round,
+    Mitre,
+    Bevel,
+}
+
+/// Side of a line to buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Both,
+    Left,
+    Right,
+}
+
+/// Buffer parameters, as given by the PostGIS style string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferStyle {
+    /// Segments used to approximate a quarter circle
+    pub quad_segs: i32,
+    pub endcap: EndCap,
+    pub join: Join,
+    /// Maximum ratio of the mitre length to the buffer distance; sharper
+    /// corners are clipped
+    pub mitre_limit: f64,
+    /// Single-sided buffering, only applied to lines
+    pub side: Side,
+}
+
+impl Default for BufferStyle {
+    fn default() -> Self {
+        BufferStyle {
+            quad_segs: 8,
+            endcap: EndCap::Round,
+            join: Join::Round,
+            mitre_limit: 5.0,
+            side: Side::Both,
+        }
+    }
+}
+
+impl BufferStyle {
+    /// Default style with a given number of segments per quarter circle
+    pub fn with_quad_segs(quad_segs: i32) -> Self {
+        BufferStyle {
+            quad_segs,
+            ..Default::default()
+        }
+    }
+
+    /// Parse a style string such as 'quad_segs=4 endcap=flat join=mitre
+    /// mitre_limit=2.5 side=left'
+    ///
+    /// Parameters are blank separated key=value pairs; the spellings
+    /// accepted by PostGIS (butt, miter, miter_limit) are recognized.
+    pub fn parse(params: &str) -> Result<Self, RostGisError> {
+        let mut style = BufferStyle::default();
+        for param in params.split_whitespace() {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                RostGisError::new(&format!("Missing value in buffer parameter '{}'", param))
+            })?;
+            let value = value.to_lowercase();
+            let invalid = || {
+                RostGisError::new(&format!(
+                    "Invalid value '{}' for buffer parameter '{}'",
+                    value, key
+                ))
+            };
+            match key.to_lowercase().as_str() {
+                "quad_segs" => style.quad_segs = value.parse().map_err(|_| invalid())?,
+                "endcap" => {
+                    style.endcap = match value.as_str() {
+                        "round" => EndCap::Round,
+                        "flat" | "butt" => EndCap::Flat,
+                        "square" => EndCap::Square,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "join" => {
+                    style.join = match value.as_str() {
+                        "round" => Join::Round,
+                        "mitre" | "miter" => Join::Mitre,
+                        "bevel" => Join::Bevel,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "mitre_limit" | "miter_limit" => {
+                    style.mitre_limit = value.parse().map_err(|_| invalid())?;
+                    if !style.mitre_limit.is_finite() || style.mitre_limit < 0.0 {
+                        return Err(invalid());
+                    }
+                }
+                "side" => {
+                    style.side = match value.as_str() {
+                        "both" => Side::Both,
+                        "left" => Side::Left,
+                        "right" => Side::Right,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => {
+                    return Err(RostGisError::new(&format!(
+                        "Unknown buffer parameter '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(style)
+    }
+
+    /// Angle of one segment of a quarter circle
+    fn segment_angle(&self) -> f64 {
+        PI / 2.0 / self.quad_segs.max(1) as f64
+    }
+
+    fn line_join(&self) -> LineJoin<f64> {
+        match self.join {
+            Join::Round => LineJoin::Round(self.segment_angle()),
+            // A corner of interior angle a has a mitre 1/sin(a/2) times the
+            // distance long, so the limit gives the sharpest unclipped angle
+            Join::Mitre => LineJoin::Miter(if self.mitre_limit > 1.0 {
+                2.0 * (1.0 / self.mitre_limit).asin()
+            } else {
+                PI
+            }),
+            Join::Bevel => LineJoin::Bevel,
+        }
+    }
+
+    fn line_cap(&self, endcap: EndCap) -> LineCap<[f64; 2], f64> {
+        match endcap {
+            EndCap::Round => LineCap::Round(self.segment_angle()),
+            EndCap::Flat => LineCap::Butt,
+            EndCap::Square => LineCap::Square,
+        }
+    }
+}
+
+/// Regular polygon approximating a circle
+fn circle(center: Coord<f64>, radius: f64, quad_segs: i32) -> Polygon<f64> {
+    let segments = 4 * quad_segs.max(1);
+    let mut ring: LineString<f64> = (0..segments)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f64 / segments as f64;
+            Coord {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        })
+        .collect();
+    ring.close();
+    Polygon::new(ring, vec![])
+}
+
+/// Buffer of a single point for an end cap style
+fn point_buffer(center: Coord<f64>, distance: f64, style: &BufferStyle) -> Option<Polygon<f64>> {
+    match style.endcap {
+        EndCap::Round => Some(circle(center, distance, style.quad_segs)),
+        EndCap::Square => Some(
+            Rect::new(
+                Coord {
+                    x: center.x - distance,
+                    y: center.y - distance,
+                },
+                Coord {
+                    x: center.x + distance,
+                    y: center.y + distance,
+                },
+            )
+            .to_polygon(),
+        ),
+        // A flat cap has no extent along a zero-length line
+        EndCap::Flat => None,
+    }
+}
+
+/// Offset polygons outwards (positive distance) or inwards (negative)
+fn offset_polygons(
+    polygons: &[Polygon<f64>],
+    distance: f64,
+    style: &BufferStyle,
+) -> Vec<Polygon<f64>> {
+    if distance == 0.0 {
+        return polygons.to_vec();
+    }
+    let shapes: Vec<Vec<Vec<[f64; 2]>>> = polygons
+        .iter()
+        .map(polygon_to_shape)
+        .filter(|shape| !shape.is_empty())
+        .collect();
+    if shapes.is_empty() {
+        return vec![];
+    }
+
+    let outline = OutlineStyle::new(distance).line_join(style.line_join());
+    let filter = ContourFilter {
+        min_area: distance * distance * SLIVER_AREA_RATIO,
+        simplify: true,
+    };
+    shapes_to_polygons(shapes.outline_with_filter(outline, filter))
+}
+
+/// Buffer lines on both sides with the style's caps and joins
+fn stroke_lines(
+    lines: &[LineString<f64>],
+    distance: f64,
+    endcap: EndCap,
+    style: &BufferStyle,
+) -> Vec<Polygon<f64>> {
+    let paths: Vec<Vec<[f64; 2]>> = lines
+        .iter()
+        .map(|line| line.0.iter().map(|c| [c.x, c.y]).collect::<Vec<_>>())
+        .filter(|path| path.len() >= 2)
+        .collect();
+    if paths.is_empty() {
+        return vec![];
+    }
+
+    let stroke = StrokeStyle::new(2.0 * distance)
+        .start_cap(style.line_cap(endcap))
+        .end_cap(style.line_cap(endcap))
+        .line_join(style.line_join());
+    shapes_to_polygons(paths.stroke(stroke, false))
+}
+
+/// Buffer the left side of a line
+///
+/// The line is stroked on both sides with flat ends, then everything on its
+/// right is cut away: a strip along each segment and a wedge at each vertex,
+/// reaching far enough to cover mitred corners.
+fn left_side_buffer(
+    line: &LineString<f64>,
+    distance: f64,
+    style: &BufferStyle,
+) -> Result<Vec<Polygon<f64>>, RostGisError> {
+    let both = stroke_lines(std::slice::from_ref(line), distance, EndCap::Flat, style);
+    if both.is_empty() {
+        return Ok(both);
+    }
+
+    let reach = 2.0 * distance * style.mitre_limit.max(1.0);
+    let right_normal = |a: Coord<f64>, b: Coord<f64>| {
+        let d = b - a;
+        let length = d.x.hypot(d.y);
+        Coord {
+            x: d.y / length * reach,
+            y: -d.x / length * reach,
+        }
+    };
+    let coords: Vec<Coord<f64>> = line.0.iter().copied().fold(Vec::new(), |mut coords, c| {
+        if coords.last() != Some(&c) {
+            coords.push(c);
+        }
+        coords
+    });
+
+    let mut right = Vec::new();
+    for pair in coords.windows(2) {
+        let n = right_normal(pair[0], pair[1]);
+        right.push(Polygon::new(
+            LineString(vec![pair[0], pair[1], pair[1] + n, pair[0] + n, pair[0]]),
+            vec![],
+        ));
+    }
+    for triple in coords.windows(3) {
+        let n1 = right_normal(triple[0], triple[1]);
+        let n2 = right_normal(triple[1], triple[2]);
+        let (start, end) = (n1.y.atan2(n1.x), n2.y.atan2(n2.x));
+        let mut sweep = end - start;
+        if sweep > PI {
+            sweep -= 2.0 * PI;
+        } else if sweep < -PI {
+            sweep += 2.0 * PI;
+        }
+        let steps = 2 * style.quad_segs.max(1);
+        let mut wedge = vec![triple[1]];
+        wedge.extend((0..=steps).map(|i| {
+            let angle = start + sweep * i as f64 / steps as f64;
+            // Outer radius past the polygon chord of the arc
+            triple[1]
+                + Coord {
+                    x: 2.0 * reach * angle.cos(),
+                    y: 2.0 * reach * angle.sin(),
+                }
+        }));
+        wedge.push(triple[1]);
+        right.push(Polygon::new(LineString(wedge), vec![]));
+    }
+
+    let right: Vec<Polygon<f64>> = right
+        .iter()
+        .map(|polygon| polygon.orient(Direction::Default))
+        .collect();
+    Ok(overlay(
+        "ST_Buffer",
+        &[MultiPolygon(both), MultiPolygon(right)],
+        |inputs| inputs[0].difference(&unary_union(inputs[1].iter())),
+    )?
+    .0)
+}
+
+/// Side and distance of a buffer, a negative single-sided distance
+/// buffering the opposite side
+fn resolve_side(side: Side, distance: f64) -> (Side, f64) {
+    match side {
+        Side::Both => (side, distance),
+        Side::Left if distance < 0.0 => (Side::Right, -distance),
+        Side::Right if distance < 0.0 => (Side::Left, -distance),
+        side => (side, distance),
+    }
+}
+
+/// Buffer lines according to the style, on one or both sides
+fn buffer_lines(
+    lines: &[LineString<f64>],
+    distance: f64,
+    style: &BufferStyle,
+    parts: &mut Vec<Polygon<f64>>,
+) -> Result<(), RostGisError> {
+    let (side, distance) = resolve_side(style.side, distance);
+    if distance <= 0.0 {
+        return Ok(());
+    }
+
+    match side {
+        Side::Both => parts.extend(stroke_lines(lines, distance, style.endcap, style)),
+        Side::Left => {
+            for line in lines {
+                parts.extend(left_side_buffer(line, distance, style)?);
+            }
+        }
+        Side::Right => {
+            for line in lines {
+                let mut reversed = line.clone();
+                reversed.0.reverse();
+                parts.extend(left_side_buffer(&reversed, distance, style)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Buffer the parts of a geometry, one dimension at a time
+fn buffer_parts(
+    geom: &Geometry,
+    distance: f64,
+    style: &BufferStyle,
+    parts: &mut Vec<Polygon<f64>>,
+) -> Result<(), RostGisError> {
+    match geom {
+        Geometry::Zm(base, _) => buffer_parts(base, distance, style, parts)?,
+        Geometry::Point(point, _) => {
+            if distance > 0.0 && !geom.is_empty() {
+                parts.extend(point_buffer(point.0, distance, style));
+            }
+        }
+        Geometry::MultiPoint(multipoint, _) => {
+            if distance > 0.0 {
+                parts.extend(
+                    multipoint
+                        .0
+                        .iter()
+                        .filter_map(|p| point_buffer(p.0, distance, style)),
+                );
+            }
+        }
+        Geometry::LineString(linestring, _) => {
+            buffer_lines(std::slice::from_ref(linestring), distance, style, parts)?
+        }
+        Geometry::MultiLineString(multilinestring, _) => {
+            buffer_lines(&multilinestring.0, distance, style, parts)?
+        }
+        Geometry::Polygon(polygon, _) => parts.extend(offset_polygons(
+            std::slice::from_ref(polygon),
+            distance,
+            style,
+        )),
+        Geometry::MultiPolygon(multipolygon, _) => {
+            parts.extend(offset_polygons(&multipolygon.0, distance, style))
+        }
+        Geometry::GeometryCollection(geometries, _) => {
+            for child in geometries {
+                buffer_parts(child, distance, style, parts)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wrap buffer output as a Polygon, MultiPolygon or POLYGON EMPTY
+fn polygonal_result(polygons: Vec<Polygon<f64>>, srid: i32) -> Geometry {
+    let mut polygons: Vec<Polygon<f64>> = polygons
+        .into_iter()
+        .filter(|polygon| polygon.unsigned_area() > 0.0 && polygon.is_valid())
+        .collect();
+    match polygons.len() {
+        0 => Geometry::Polygon(Polygon::new(LineString(vec![]), vec![]), srid),
+        1 => Geometry::Polygon(polygons.remove(0), srid),
+        _ => Geometry::MultiPolygon(MultiPolygon(polygons), srid),
+    }
+}
+
+/// Buffer a geometry by a distance (ST_Buffer)
+///
+/// Points and lines only grow, so a zero or negative distance gives an empty
+/// polygon. A negative distance erodes polygons; parts that collapse
+/// entirely are removed instead of being left as invalid slivers, and a
+/// fully eroded input gives POLYGON EMPTY. Curves are approximated with
+/// `quad_segs` segments per quarter circle.
+pub fn buffer(geom: &Geometry, distance: f64, quad_segs: i32) -> Result<Geometry, RostGisError> {
+    buffer_with_style(geom, distance, &BufferStyle::with_quad_segs(quad_segs))
+}
+
+/// Buffer a geometry with explicit end cap, join and side parameters
+///
+/// Single-sided buffers only apply to lines, with the side taken relative
+/// to the line direction; a negative distance then buffers the other side.
+/// Their ends are always flat.
+pub fn buffer_with_style(
+    geom: &Geometry,
+    distance: f64,
+    style: &BufferStyle,
+) -> Result<Geometry, RostGisError> {
+    if !distance.is_finite() {
+        return Err(RostGisError::new("Buffer distance must be finite"));
+    }
+    if style.quad_segs < 1 {
+        return Err(RostGisError::new("quad_segs must be at least 1"));
+    }
+
+    let mut parts = Vec::new();
+    buffer_parts(geom, distance, style, &mut parts)?;
+    // Overlapping parts are merged; a zero buffer this way only normalizes
+    // the areal parts of the input
+    if parts.len() > 1 || distance == 0.0 {
+        parts = overlay("ST_Buffer", &[MultiPolygon(parts)], |inputs| {
+            unary_union(inputs[0].iter())
+        })?
+        .0;
+    }
+    Ok(polygonal_result(parts, geom.srid()))
+}
+
+/// Band along the boundary of a polygon between it and its buffer
+/// (ST_RingOffset)
+///
+/// A positive distance gives the band outside the polygon, a negative one
+/// the band inside it; a band deeper than the polygon is the whole polygon.
+/// Only the quad_segs, join and mitre_limit parameters of the style apply.
+pub fn ring_offset(
+    geom: &Geometry,
+    distance: f64,
+    style: &BufferStyle,
+) -> Result<Geometry, RostGisError> {
+    if !distance.is_finite() {
+        return Err(RostGisError::new("Ring offset distance must be finite"));
+    }
+    if style.quad_segs < 1 {
+        return Err(RostGisError::new("quad_segs must be at least 1"));
+    }
+    if style.side != Side::Both {
+        return Err(RostGisError::new(
+            "ST_RingOffset takes no side, the sign of the distance chooses it",
+        ));
+    }
+    let polygons = match geom {
+        Geometry::Polygon(polygon, _) if !geom.is_empty() => vec![polygon.clone()],
+        Geometry::Polygon(_, _) => vec![],
+        Geometry::MultiPolygon(multipolygon, _) => multipolygon.0.clone(),
+        other => {
+            return Err(RostGisError::new(&format!(
+                "ST_RingOffset requires polygons, got {}",
+                other.geometry_type()
+            )))
+        }
+    };
+    if polygons.is_empty() || distance == 0.0 {
+        return Ok(polygonal_result(vec![], geom.srid()));
+    }
+
+    let offset = offset_polygons(&polygons, distance, style);
+    let [outer, inner] = if distance > 0.0 {
+        [offset, polygons]
+    } else {
+        [polygons, offset]
+    }
+    .map(MultiPolygon);
+    let band = overlay("ST_RingOffset", &[outer, inner], |inputs| {
+        inputs[0].difference(&inputs[1])
+    })?;
+    Ok(polygonal_result(band.0, geom.srid()))
+}
+
+/// Morphological operation built from two opposite buffers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Morphology {
+    /// Buffer inwards: shrink polygons
+    Erode,
+    /// Buffer outwards: grow polygons
+    Dilate,
+    /// Erode then dilate: remove spikes, necks and parts thinner than
+    /// twice the distance
+    Open,
+    /// Dilate then erode: fill notches, gaps and holes narrower than twice
+    /// the distance
+    Close,
+}
+
+impl Morphology {
+    pub fn parse(name: &str) -> Result<Self, RostGisError> {
+        match name.trim().to_lowercase().as_str() {
+            "erode" | "erosion" => Ok(Morphology::Erode),
+            "dilate" | "dilation" => Ok(Morphology::Dilate),
+            "open" | "opening" => Ok(Morphology::Open),
+            "close" | "closing" => Ok(Morphology::Close),
+            _ => Err(RostGisError::new(&format!(
+                "Unknown buffer style '{}', expected erode, dilate, open or close",
+                name
+            ))),
+        }
+    }
+}
+
+/// Apply an erosion/dilation cleanup to a geometry (ST_BufferStyle)
+///
+/// The distance is used as an absolute value, its sign being implied by the
+/// operation.
+pub fn buffer_morphology(
+    geom: &Geometry,
+    distance: f64,
+    operation: Morphology,
+    quad_segs: i32,
+) -> Result<Geometry, RostGisError> {
+    let distance = distance.abs();
+    match operation {
+        Morphology::Erode => buffer(geom, -distance, quad_segs),
+        Morphology::Dilate => buffer(geom, distance, quad_segs),
+        Morphology::Open => buffer(&buffer(geom, -distance, quad_segs)?, distance, quad_segs),
+        Morphology::Close => buffer(&buffer(geom, distance, quad_segs)?, -distance, quad_segs),
+    }
+}
+
+/// PostgreSQL function computing the buffer of a geometry
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn st_buffer(
+    geom: Geometry,
+    radius: f64,
+    quad_segs: default!(i32, 8),
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let buffered = buffer(&geom, radius, quad_segs);
+    report_fallbacks();
+    Ok(buffered?)
+}
+
+/// PostgreSQL function computing the buffer of a geometry with a style
+/// string, e.g. 'endcap=flat join=mitre mitre_limit=2 side=left'
+#[pg_extern(immutable, strict, parallel_safe, name = "st_buffer")]
+pub fn st_buffer_style(
+    geom: Geometry,
+    radius: f64,
+    style: &str,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let buffered = buffer_with_style(&geom, radius, &BufferStyle::parse(style)?);
+    report_fallbacks();
+    Ok(buffered?)
+}
+
+/// PostgreSQL function returning the band along the boundary of a polygon,
+/// outside it for a positive distance and inside it for a negative one,
+/// with an optional style string, e.g. 'join=mitre mitre_limit=2'
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn st_ringoffset(
+    geom: Geometry,
+    distance: f64,
+    params: default!(&str, "''"),
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let band = ring_offset(&geom, distance, &BufferStyle::parse(params)?);
+    report_fallbacks();
+    Ok(band?)
+}
+
+/// PostgreSQL function for morphological cleanup: 'erode', 'dilate', 'open'
+/// or 'close'
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn st_bufferstyle(
+    geom: Geometry,
+    distance: f64,
+    operation: &str,
+    quad_segs: default!(i32, 8),
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let buffered = buffer_morphology(&geom, distance, Morphology::parse(operation)?, quad_segs);
+    report_fallbacks();
+    Ok(buffered?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn area(geom: &Geometry) -> f64 {
+        geom.to_geo().unsigned_area()
+    }
+
+    #[test]
+    fn test_point_buffer() {
+        let buffered = buffer(&make_point(0.0, 0.0), 1.0, 8).unwrap();
+        assert_eq!(buffered.geometry_type(), "ST_Polygon");
+        assert!((area(&buffered) - PI).abs() < 0.05);
+        assert!(buffer(&make_point(0.0, 0.0), -1.0, 8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 10 0)").unwrap();
+        let buffered = buffer(&line, 1.0, 8).unwrap();
+        // Rectangle plus two half discs
+        assert!((area(&buffered) - (20.0 + PI)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_polygon_grow_and_shrink() {
+        let square = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))")
+            .unwrap()
+            .with_srid(3857);
+        let grown = buffer(&square, 1.0, 8).unwrap();
+        assert!((area(&grown) - (100.0 + 40.0 + PI)).abs() < 0.2);
+        assert_eq!(grown.srid(), 3857);
+
+        let shrunk = buffer(&square, -1.0, 8).unwrap();
+        assert!((area(&shrunk) - 64.0).abs() < 1e-6);
+        assert!(crate::functions::geometry_is_valid(&shrunk));
+    }
+
+    #[test]
+    fn test_negative_buffer_collapse() {
+        let square = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").unwrap();
+        let collapsed = buffer(&square, -5.0, 8).unwrap();
+        assert!(collapsed.is_empty());
+        assert_eq!(collapsed.geometry_type(), "ST_Polygon");
+
+        // The thin neck disappears, leaving two valid parts
+        let dumbbell = geometry_from_wkt(
+            "POLYGON((0 0, 10 0, 10 4.5, 20 4.5, 20 0, 30 0, 30 10, 20 10, 20 5.5, 10 5.5, 10 10, 0 10, 0 0))",
+        )
+        .unwrap();
+        let eroded = buffer(&dumbbell, -1.0, 8).unwrap();
+        assert_eq!(eroded.geometry_type(), "ST_MultiPolygon");
+        assert!(crate::functions::geometry_is_valid(&eroded));
+    }
+
+    #[test]
+    fn test_open_and_close() {
+        // A one unit wide spike sticking out of a square
+        let spiky =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 5, 20 5, 20 6, 10 6, 10 10, 0 10, 0 0))")
+                .unwrap();
+        let opened = buffer_morphology(&spiky, 1.0, Morphology::Open, 8).unwrap();
+        assert!(opened.bounding_box().2 < 10.5);
+
+        // A one unit wide notch cut into a square
+        let notched = geometry_from_wkt(
+            "POLYGON((0 0, 10 0, 10 10, 5.5 10, 5.5 2, 4.5 2, 4.5 10, 0 10, 0 0))",
+        )
+        .unwrap();
+        let closed = buffer_morphology(&notched, 1.0, Morphology::Close, 8).unwrap();
+        assert!(area(&closed) > 99.0);
+
+        assert!(Morphology::parse("blur").is_err());
+    }
+
+    #[test]
+    fn test_style_parsing() {
+        let style =
+            BufferStyle::parse("quad_segs=4 endcap=butt join=miter miter_limit=2.5 side=left")
+                .unwrap();
+        assert_eq!(style.quad_segs, 4);
+        assert_eq!(style.endcap, EndCap::Flat);
+        assert_eq!(style.join, Join::Mitre);
+        assert_eq!(style.mitre_limit, 2.5);
+        assert_eq!(style.side, Side::Left);
+        assert_eq!(BufferStyle::parse("").unwrap(), BufferStyle::default());
+
+        assert!(BufferStyle::parse("endcap=pointy").is_err());
+        assert!(BufferStyle::parse("quad_segs").is_err());
+        assert!(BufferStyle::parse("colour=red").is_err());
+    }
+
+    #[test]
+    fn test_end_caps() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 10 0)").unwrap();
+        let flat =
+            buffer_with_style(&line, 1.0, &BufferStyle::parse("endcap=flat").unwrap()).unwrap();
+        assert!((area(&flat) - 20.0).abs() < 1e-6);
+        let square =
+            buffer_with_style(&line, 1.0, &BufferStyle::parse("endcap=square").unwrap()).unwrap();
+        assert!((area(&square) - 24.0).abs() < 1e-6);
+
+        let point = make_point(0.0, 0.0);
+        let square =
+            buffer_with_style(&point, 1.0, &BufferStyle::parse("endcap=square").unwrap()).unwrap();
+        assert!((area(&square) - 4.0).abs() < 1e-9);
+        let flat =
+            buffer_with_style(&point, 1.0, &BufferStyle::parse("endcap=flat").unwrap()).unwrap();
+        assert!(flat.is_empty());
+    }
+
+    #[test]
+    fn test_joins() {
+        let corner = geometry_from_wkt("LINESTRING(0 0, 10 0, 10 10)").unwrap();
+        let joined = |params: &str| {
+            let style = BufferStyle::parse(&format!("endcap=flat {}", params)).unwrap();
+            area(&buffer_with_style(&corner, 1.0, &style).unwrap())
+        };
+        assert!((joined("join=mitre") - 40.0).abs() < 1e-6);
+        assert!((joined("join=bevel") - 39.5).abs() < 1e-6);
+        assert!((joined("join=round") - (39.0 + PI / 4.0)).abs() < 0.01);
+
+        // A right angle mitre is sqrt(2) times the distance, so a lower
+        // limit clips the corner
+        assert!(joined("join=mitre mitre_limit=1.2") < 40.0 - 1e-3);
+
+        let square = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").unwrap();
+        let mitred =
+            buffer_with_style(&square, 1.0, &BufferStyle::parse("join=mitre").unwrap()).unwrap();
+        assert!((area(&mitred) - 144.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_single_sided() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 10 0, 10 10)").unwrap();
+        let left =
+            buffer_with_style(&line, 1.0, &BufferStyle::parse("side=left").unwrap()).unwrap();
+        // Inside the corner, to the left of the line
+        let (xmin, ymin, xmax, ymax) = left.bounding_box();
+        assert!(xmin > -1e-6 && ymin > -1e-6 && xmax < 10.0 + 1e-6 && ymax < 10.0 + 1e-6);
+        assert!((area(&left) - 19.0).abs() < 1e-6);
+
+        let right =
+            buffer_with_style(&line, 1.0, &BufferStyle::parse("side=right").unwrap()).unwrap();
+        let (_, ymin, xmax, _) = right.bounding_box();
+        assert!(ymin < -0.5 && xmax > 10.5);
+        assert!(area(&right) > 20.0);
+
+        // A negative distance buffers the other side
+        let flipped =
+            buffer_with_style(&line, -1.0, &BufferStyle::parse("side=left").unwrap()).unwrap();
+        assert!((area(&flipped) - area(&right)).abs() < 1e-6);
+
+        // Polygons ignore the side
+        let square = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").unwrap();
+        let eroded =
+            buffer_with_style(&square, -1.0, &BufferStyle::parse("side=left").unwrap()).unwrap();
+        assert!((area(&eroded) - 64.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ring_offset() {
+        let square = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").unwrap();
+        let band = |distance: f64, params: &str| {
+            ring_offset(&square, distance, &BufferStyle::parse(params).unwrap()).unwrap()
+        };
+        // The setback is the square less the one eroded by 1
+        let setback = band(-1.0, "join=mitre");
+        assert!((area(&setback) - 36.0).abs() < 1e-6);
+        let Geometry::Polygon(ring, _) = &setback else {
+            panic!("expected a polygon, got {}", setback.geometry_type());
+        };
+        assert_eq!(ring.interiors().len(), 1);
+        let (xmin, _, xmax, _) = setback.bounding_box();
+        assert!(xmin > -1e-6 && xmax < 10.0 + 1e-6);
+
+        // The outer offset keeps square corners with a mitre join
+        let outer = band(1.0, "join=mitre");
+        assert!((area(&outer) - 44.0).abs() < 1e-6);
+        let rounded = band(1.0, "");
+        assert!((area(&rounded) - (40.0 + PI)).abs() < 0.2);
+
+        // A setback deeper than the polygon covers all of it
+        assert!((area(&band(-6.0, "")) - 100.0).abs() < 1e-6);
+        assert!(band(0.0, "").is_empty());
+
+        let style = BufferStyle::default();
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert!(ring_offset(&empty, 1.0, &style).unwrap().is_empty());
+        assert!(ring_offset(&make_point(0.0, 0.0), 1.0, &style).is_err());
+        assert!(ring_offset(&square, 1.0, &BufferStyle::parse("side=left").unwrap()).is_err());
+    }
+}
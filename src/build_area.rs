@@ -0,0 +1,527 @@
+use crate::geometry::Geometry;
+use crate::noding::node;
+use crate::overlay::{overlay, report_fallbacks};
+use crate::utils::{aggregate_context, RostGisError};
+use geo::{unary_union, Area, Contains, InteriorPoint};
+use geo_types::{Coord, LineString, MultiLineString, MultiPolygon, Polygon};
+use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
+use std::collections::{HashMap, HashSet};
+
+// Areas from linework (ST_Polygonize, ST_BuildArea)
+//
+// The linework is noded (ST_Node) and the faces of the resulting planar
+// graph are traced, after removing dangling lines and bridges, which bound
+// no area. A face is a ring with the rings of the linework islands inside it
+// as holes. ST_Polygonize returns every face, e.g. the parcels enclosed by a
+// set of fence lines. ST_BuildArea, like GEOS BuildArea, keeps the faces
+// nested at an even depth in other faces and drops those at an odd depth,
+// so nested rings alternate between area and hole whatever their
+// orientation or the order of the input:
+//
+//   ST_BuildArea(ST_Collect(ring 0..10, ring 2..8, ring 4..6))
+//   = MULTIPOLYGON(((0..10), (2..8)), ((4..6)))
+//
+// Neighbouring faces that are both kept are dissolved into one polygon.
+// Points are ignored, and polygons contribute their rings.
+
+/// Rings of a polygon and lines of a geometry
+fn linework(geom: &Geometry, into: &mut Vec<LineString<f64>>) {
+    let push_polygon = |polygon: &Polygon<f64>, into: &mut Vec<LineString<f64>>| {
+        into.push(polygon.exterior().clone());
+        into.extend(polygon.interiors().iter().cloned());
+    };
+    match geom {
+        Geometry::Zm(base, _) => linework(base, into),
+        Geometry::Point(..) | Geometry::MultiPoint(..) => {}
+        Geometry::LineString(line, _) => into.push(line.clone()),
+        Geometry::MultiLineString(multi_line, _) => into.extend(multi_line.iter().cloned()),
+        Geometry::Polygon(polygon, _) => push_polygon(polygon, into),
+        Geometry::MultiPolygon(multi_polygon, _) => {
+            for polygon in multi_polygon {
+                push_polygon(polygon, into);
+            }
+        }
+        Geometry::GeometryCollection(members, _) => {
+            for member in members {
+                linework(member, into);
+            }
+        }
+    }
+}
+
+/// Planar graph of noded linework: vertices and undirected edges
+struct Graph {
+    coords: Vec<Coord<f64>>,
+    edges: HashSet<(usize, usize)>,
+}
+
+impl Graph {
+    fn new(lines: &MultiLineString<f64>) -> Self {
+        let mut index: HashMap<[u64; 2], usize> = HashMap::new();
+        let mut coords = Vec::new();
+        let mut edges = HashSet::new();
+        let mut vertex = |coord: Coord<f64>| {
+            *index
+                .entry([coord.x.to_bits(), coord.y.to_bits()])
+                .or_insert_with(|| {
+                    coords.push(coord);
+                    coords.len() - 1
+                })
+        };
+        for line in lines {
+            for segment in line.lines() {
+                let (a, b) = (vertex(segment.start), vertex(segment.end));
+                if a != b {
+                    edges.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+        Graph { coords, edges }
+    }
+
+    /// Neighbours of every vertex, counterclockwise
+    fn adjacency(&self) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); self.coords.len()];
+        for &(a, b) in &self.edges {
+            adjacency[a].push(b);
+            adjacency[b].push(a);
+        }
+        for (v, neighbours) in adjacency.iter_mut().enumerate() {
+            let origin = self.coords[v];
+            let angle = |w: &usize| {
+                let d = self.coords[*w] - origin;
+                d.y.atan2(d.x)
+            };
+            neighbours.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+        }
+        adjacency
+    }
+
+    /// Remove dangling lines, repeatedly dropping the edge of a vertex of
+    /// degree one
+    fn remove_dangles(&mut self) {
+        let mut adjacency: Vec<HashSet<usize>> = vec![HashSet::new(); self.coords.len()];
+        for &(a, b) in &self.edges {
+            adjacency[a].insert(b);
+            adjacency[b].insert(a);
+        }
+        let mut ends: Vec<usize> = (0..adjacency.len())
+            .filter(|&v| adjacency[v].len() == 1)
+            .collect();
+        while let Some(v) = ends.pop() {
+            let Some(&w) = adjacency[v].iter().next() else {
+                continue;
+            };
+            adjacency[v].clear();
+            adjacency[w].remove(&v);
+            self.edges.remove(&(v.min(w), v.max(w)));
+            if adjacency[w].len() == 1 {
+                ends.push(w);
+            }
+        }
+    }
+
+    /// Cycles of directed edges, each keeping its face on the left: bounded
+    /// faces are traced counterclockwise, the outside of every connected
+    /// part clockwise
+    fn cycles(&self) -> Vec<Vec<usize>> {
+        let adjacency = self.adjacency();
+        let mut visited = HashSet::new();
+        let mut cycles = Vec::new();
+        for (u, neighbours) in adjacency.iter().enumerate() {
+            for &v in neighbours {
+                if visited.contains(&(u, v)) {
+                    continue;
+                }
+                let mut cycle = Vec::new();
+                let (mut a, mut b) = (u, v);
+                loop {
+                    cycle.push(a);
+                    visited.insert((a, b));
+                    // Turn onto the edge just clockwise of the way back
+                    let around = &adjacency[b];
+                    let back = around.iter().position(|&w| w == a).unwrap();
+                    let next = around[(back + around.len() - 1) % around.len()];
+                    (a, b) = (b, next);
+                    if (a, b) == (u, v) {
+                        break;
+                    }
+                }
+                cycles.push(cycle);
+            }
+        }
+        cycles
+    }
+
+    /// Closed ring through the vertices of a cycle
+    fn ring(&self, cycle: &[usize]) -> LineString<f64> {
+        let mut coords: Vec<Coord<f64>> = cycle.iter().map(|&v| self.coords[v]).collect();
+        coords.push(coords[0]);
+        LineString(coords)
+    }
+}
+
+/// Faces of the planar graph of noded linework, with the islands inside
+/// them as holes
+fn faces(lines: &MultiLineString<f64>) -> Vec<Polygon<f64>> {
+    let mut graph = Graph::new(lines);
+    // A bridge has the same face on both sides, so it is traced twice by
+    // one cycle; removing it can leave new dangles
+    let cycles = loop {
+        graph.remove_dangles();
+        let cycles = graph.cycles();
+        let mut bridges = Vec::new();
+        for cycle in &cycles {
+            let directed: HashSet<(usize, usize)> = (0..cycle.len())
+                .map(|i| (cycle[i], cycle[(i + 1) % cycle.len()]))
+                .collect();
+            bridges.extend(
+                directed
+                    .iter()
+                    .filter(|&&(a, b)| a < b && directed.contains(&(b, a))),
+            );
+        }
+        if bridges.is_empty() {
+            break cycles;
+        }
+        for bridge in bridges {
+            graph.edges.remove(&bridge);
+        }
+    };
+
+    let mut shells = Vec::new();
+    let mut islands = Vec::new();
+    for cycle in &cycles {
+        let ring = graph.ring(cycle);
+        let area = Polygon::new(ring.clone(), vec![]).signed_area();
+        if area > 0.0 {
+            shells.push((Polygon::new(ring, vec![]), area));
+        } else if area < 0.0 {
+            islands.push(ring);
+        }
+    }
+
+    // An island is a hole of the smallest face around it; the outside of
+    // the outermost parts belongs to no face
+    let mut holes: Vec<Vec<LineString<f64>>> = vec![Vec::new(); shells.len()];
+    for island in islands {
+        let point = geo_types::Point(island.0[0]);
+        let around = shells
+            .iter()
+            .enumerate()
+            .filter(|(_, (shell, _))| shell.contains(&point))
+            .min_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b));
+        if let Some((i, _)) = around {
+            holes[i].push(island);
+        }
+    }
+    shells
+        .into_iter()
+        .zip(holes)
+        .map(|((shell, _), holes)| Polygon::new(shell.into_inner().0, holes))
+        .collect()
+}
+
+/// Faces enclosed by linework, once noded
+fn noded_faces(lines: Vec<LineString<f64>>) -> Result<Vec<Polygon<f64>>, RostGisError> {
+    match node(&Geometry::MultiLineString(MultiLineString(lines), 0))? {
+        Geometry::MultiLineString(noded, _) => Ok(faces(&noded)),
+        _ => unreachable!(),
+    }
+}
+
+/// Linework collected by ST_Polygonize
+#[derive(Debug, Default)]
+pub struct Polygonizer {
+    srid: Option<i32>,
+    lines: Vec<LineString<f64>>,
+}
+
+impl Polygonizer {
+    /// Add the linework of a geometry; all inputs must share the same SRID
+    pub fn add(&mut self, geom: &Geometry) -> Result<(), RostGisError> {
+        match self.srid {
+            None => self.srid = Some(geom.srid()),
+            Some(srid) if srid != geom.srid() => {
+                return Err(RostGisError::new(&format!(
+                    "ST_Polygonize: mixed SRIDs {} and {}",
+                    srid,
+                    geom.srid()
+                )))
+            }
+            Some(_) => {}
+        }
+        linework(geom, &mut self.lines);
+        Ok(())
+    }
+
+    /// Collection of the polygons enclosed by the linework added
+    pub fn finish(&self) -> Result<Geometry, RostGisError> {
+        let faces = noded_faces(self.lines.clone())?;
+        let srid = self.srid.unwrap_or(0);
+        Ok(Geometry::GeometryCollection(
+            faces
+                .into_iter()
+                .map(|face| Geometry::Polygon(face, srid))
+                .collect(),
+            srid,
+        ))
+    }
+}
+
+/// Polygons enclosed by the linework of a set of geometries, as a
+/// collection
+pub fn polygonize<'a>(
+    geometries: impl IntoIterator<Item = &'a Geometry>,
+) -> Result<Geometry, RostGisError> {
+    let mut polygonizer = Polygonizer::default();
+    for geom in geometries {
+        polygonizer.add(geom)?;
+    }
+    polygonizer.finish()
+}
+
+/// Areas formed by the linework of a geometry, None when it encloses none
+pub fn build_area(geom: &Geometry) -> Result<Option<Geometry>, RostGisError> {
+    let mut lines = Vec::new();
+    linework(geom, &mut lines);
+    let faces = noded_faces(lines)?;
+
+    // Keep the faces inside an even number of others
+    let shells: Vec<Polygon<f64>> = faces
+        .iter()
+        .map(|face| Polygon::new(face.exterior().clone(), vec![]))
+        .collect();
+    let mut kept: Vec<Polygon<f64>> = Vec::new();
+    for (i, face) in faces.iter().enumerate() {
+        let Some(point) = face.interior_point() else {
+            continue;
+        };
+        let depth = shells
+            .iter()
+            .enumerate()
+            .filter(|&(j, shell)| j != i && shell.contains(&point))
+            .count();
+        if depth % 2 == 0 {
+            kept.push(face.clone());
+        }
+    }
+
+    let mut polygons = if kept.len() > 1 {
+        overlay("ST_BuildArea", &[MultiPolygon(kept)], |inputs| {
+            unary_union(inputs[0].iter())
+        })?
+        .0
+    } else {
+        kept
+    };
+    let srid = geom.srid();
+    Ok(match polygons.len() {
+        0 => None,
+        1 => Some(Geometry::Polygon(polygons.remove(0), srid)),
+        _ => Some(Geometry::MultiPolygon(MultiPolygon(polygons), srid)),
+    })
+}
+
+/// PostgreSQL function forming polygons from the closed linework of a
+/// geometry, with rings nested inside others as holes
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_buildarea(
+    geom: Geometry,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    let area = build_area(&geom);
+    report_fallbacks();
+    Ok(area?)
+}
+
+/// PostgreSQL function for the polygons enclosed by an array of lines, e.g.
+/// `ST_Polygonize(ARRAY[a, b])`
+#[pg_extern(immutable, strict, parallel_safe, name = "st_polygonize")]
+fn st_polygonize_array(
+    geometries: Array<'_, Geometry>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let geometries: Vec<Geometry> = geometries.iter().flatten().collect();
+    Ok(polygonize(&geometries)?)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_polygonize_transfn(
+    mut state: Internal,
+    geom: Option<Geometry>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    unsafe {
+        if state.get::<Polygonizer>().is_none() {
+            let context = aggregate_context(fcinfo, "ST_Polygonize")?;
+            state =
+                PgMemoryContexts::For(context).switch_to(|_| Internal::new(Polygonizer::default()));
+        }
+        if let Some(geom) = geom {
+            state
+                .get_mut::<Polygonizer>()
+                .expect("ST_Polygonize state")
+                .add(&geom)?;
+        }
+    }
+    Ok(state)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_polygonize_finalfn(
+    state: Internal,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    match unsafe { state.get::<Polygonizer>() } {
+        Some(polygonizer) => Ok(Some(polygonizer.finish()?)),
+        None => Ok(None),
+    }
+}
+
+extension_sql!(
+    r#"
+CREATE AGGREGATE @extschema@.st_polygonize(@extschema@.geometry) (
+    SFUNC = @extschema@.st_polygonize_transfn,
+    STYPE = internal,
+    FINALFUNC = @extschema@.st_polygonize_finalfn
+);
+"#,
+    name = "polygonize_aggregate",
+    requires = [Geometry, st_polygonize_transfn, st_polygonize_finalfn]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn area_of(wkt: &str) -> Option<Geometry> {
+        build_area(&geometry_from_wkt(wkt).unwrap()).unwrap()
+    }
+
+    fn polygons(geom: &Geometry) -> Vec<Polygon<f64>> {
+        match geom {
+            Geometry::Polygon(polygon, _) => vec![polygon.clone()],
+            Geometry::MultiPolygon(multi_polygon, _) => multi_polygon.0.clone(),
+            other => panic!("expected polygons, got {}", other.to_wkt()),
+        }
+    }
+
+    #[test]
+    fn test_ring_with_hole() {
+        // The inner ring is a hole whatever its orientation
+        let area =
+            area_of("MULTILINESTRING((0 0, 10 0, 10 10, 0 10, 0 0), (2 2, 8 2, 8 8, 2 8, 2 2))")
+                .unwrap();
+        let polygons = polygons(&area);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].interiors().len(), 1);
+        assert_eq!(polygons[0].unsigned_area(), 64.0);
+    }
+
+    #[test]
+    fn test_nested_island() {
+        let area = area_of(
+            "GEOMETRYCOLLECTION(
+                LINESTRING(4 4, 6 4, 6 6, 4 6, 4 4),
+                LINESTRING(0 0, 0 10, 10 10, 10 0, 0 0),
+                POLYGON((2 2, 8 2, 8 8, 2 8, 2 2)),
+                POINT(20 20))",
+        )
+        .unwrap();
+        let mut polygons = polygons(&area);
+        polygons.sort_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()));
+        assert_eq!(polygons.len(), 2);
+        assert_eq!(polygons[0].unsigned_area(), 4.0);
+        assert_eq!(polygons[0].interiors().len(), 0);
+        assert_eq!(polygons[1].unsigned_area(), 64.0);
+        assert_eq!(polygons[1].interiors().len(), 1);
+    }
+
+    #[test]
+    fn test_linework_pieces() {
+        // A ring made of two lines, with a dangling line and a bridge to a
+        // second ring
+        let area = area_of(
+            "MULTILINESTRING((0 0, 4 0, 4 4), (4 4, 0 4, 0 0), (4 2, 6 2), (2 2, 3 3),
+                             (6 0, 8 0, 8 4, 6 4, 6 0))",
+        )
+        .unwrap();
+        let polygons = polygons(&area);
+        assert_eq!(polygons.len(), 2);
+        assert!(polygons
+            .iter()
+            .all(|polygon| polygon.interiors().is_empty()));
+        assert_eq!(area.srid(), 0);
+        let total: f64 = polygons.iter().map(|polygon| polygon.unsigned_area()).sum();
+        assert_eq!(total, 24.0);
+    }
+
+    #[test]
+    fn test_crossing_rings_dissolved() {
+        let area = area_of("MULTILINESTRING((0 0, 4 0, 4 4, 0 4, 0 0), (2 2, 6 2, 6 6, 2 6, 2 2))")
+            .unwrap();
+        let polygons = polygons(&area);
+        assert_eq!(polygons.len(), 1);
+        assert!((polygons[0].unsigned_area() - 28.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_polygonize() {
+        // Fence lines: a field split in two by a line across it, with a pond
+        // in the western half and a dangling line
+        let lines: Vec<Geometry> = [
+            "LINESTRING(0 0, 10 0, 10 10)",
+            "LINESTRING(10 10, 0 10, 0 0)",
+            "LINESTRING(5 -2, 5 12)",
+            "LINESTRING(1 1, 2 1, 2 2, 1 2, 1 1)",
+            "LINESTRING(8 8, 9 9)",
+        ]
+        .iter()
+        .map(|wkt| geometry_from_wkt(wkt).unwrap().with_srid(3857))
+        .collect();
+        let collection = polygonize(&lines).unwrap();
+        assert_eq!(collection.srid(), 3857);
+        let Geometry::GeometryCollection(members, _) = collection else {
+            panic!("expected a collection");
+        };
+        let mut faces: Vec<(f64, usize)> = members
+            .iter()
+            .map(|member| {
+                let polygon = &polygons(member)[0];
+                (polygon.unsigned_area(), polygon.interiors().len())
+            })
+            .collect();
+        faces.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(faces, vec![(1.0, 0), (49.0, 1), (50.0, 0)]);
+
+        assert_eq!(
+            polygonize(&[geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap()])
+                .unwrap()
+                .to_wkt(),
+            "GEOMETRYCOLLECTION EMPTY"
+        );
+        let mixed = [
+            geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap(),
+            geometry_from_wkt("LINESTRING(0 0, 1 1)")
+                .unwrap()
+                .with_srid(4326),
+        ];
+        assert!(polygonize(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_no_area() {
+        assert!(area_of("LINESTRING(0 0, 1 1, 2 0)").is_none());
+        assert!(area_of("MULTILINESTRING EMPTY").is_none());
+        assert!(area_of("POINT(1 1)").is_none());
+        let srid = build_area(
+            &geometry_from_wkt("LINESTRING(0 0, 1 0, 1 1, 0 0)")
+                .unwrap()
+                .with_srid(4326),
+        )
+        .unwrap()
+        .unwrap()
+        .srid();
+        assert_eq!(srid, 4326);
+    }
+}
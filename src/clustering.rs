@@ -0,0 +1,462 @@
+use crate::functions::geometries_intersect;
+use crate::geometry::Geometry;
+use crate::spatial_index::{BBox, GeometryWithId, SpatialIndex};
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use geo::{Distance, Euclidean};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+/// Number of bits per axis used for the Hilbert grid (2^16 x 2^16 cells)
+pub const HILBERT_ORDER: u32 = 16;
+
+/// Map a cell (x, y) on a 2^order x 2^order grid to its Hilbert curve distance
+pub fn hilbert_index(order: u32, x: u32, y: u32) -> u64 {
+    let n: u64 = 1 << order;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut d: u64 = 0;
+    let mut s = n / 2;
+
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    d
+}
+
+/// Hilbert key of a geometry's bounding box center relative to an extent
+pub fn hilbert_key(geom: &Geometry, extent: &BBox) -> i64 {
+    let (min_x, min_y, max_x, max_y) = geom.bounding_box();
+    let center_x = (min_x + max_x) / 2.0;
+    let center_y = (min_y + max_y) / 2.0;
+
+    let cells = ((1u64 << HILBERT_ORDER) - 1) as f64;
+    let to_cell = |value: f64, lo: f64, hi: f64| -> u32 {
+        if hi <= lo {
+            return 0;
+        }
+        (((value - lo) / (hi - lo)).clamp(0.0, 1.0) * cells) as u32
+    };
+
+    let x = to_cell(center_x, extent.min_x, extent.max_x);
+    let y = to_cell(center_y, extent.min_y, extent.max_y);
+    hilbert_index(HILBERT_ORDER, x, y) as i64
+}
+
+/// PostgreSQL function computing the Hilbert sort key of a geometry within an extent
+#[pg_extern(immutable, parallel_safe)]
+pub fn rostgis_hilbert_key(geom: Geometry, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> i64 {
+    hilbert_key(&geom, &BBox::new(min_x, min_y, max_x, max_y))
+}
+
+/// Rewrite a table in Hilbert-curve order of its geometry column
+///
+/// The table is scanned once in batches to find its extent, copied into a
+/// temporary table ordered by Hilbert key, truncated and then refilled batch by
+/// batch. Progress is reported with NOTICE messages. Returns the number of rows
+/// rewritten, which keep the values of their identity columns. Tables
+/// referenced by foreign keys cannot be truncated and are therefore not
+/// supported.
+#[pg_extern]
+pub fn rostgis_cluster_table(
+    table_name: &str,
+    geom_column: &str,
+    batch_size: default!(i32, 10000),
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    if batch_size <= 0 {
+        return Err("batch_size must be positive".into());
+    }
+
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+
+    // Pass 1: compute the table extent batch by batch
+    let extent = Spi::connect(|client| -> Result<Option<BBox>, pgrx::spi::Error> {
+        let mut cursor = client.open_cursor(
+            &format!(
+                "SELECT {} FROM {} WHERE {} IS NOT NULL",
+                column, relation, column
+            ),
+            &[],
+        );
+        let mut extent: Option<BBox> = None;
+        let mut scanned: i64 = 0;
+
+        loop {
+            let batch = cursor.fetch(batch_size.into())?;
+            if batch.is_empty() {
+                break;
+            }
+            for row in batch {
+                if let Some(geom) = row.get::<Geometry>(1)? {
+                    let bbox = BBox::from_geometry(&geom);
+                    extent = Some(match extent {
+                        Some(current) => current.union(&bbox),
+                        None => bbox,
+                    });
+                }
+                scanned += 1;
+            }
+            notice!(
+                "rostgis_cluster_table: scanned {} rows of {}",
+                scanned,
+                relation
+            );
+        }
+
+        Ok(extent)
+    })?;
+
+    let extent = match extent {
+        Some(extent) => extent,
+        None => return Ok(0),
+    };
+
+    let columns = Spi::get_one_with_args::<String>(
+        "SELECT string_agg(quote_ident(attname), ', ' ORDER BY attnum)
+         FROM pg_attribute
+         WHERE attrelid = $1::regclass AND attnum > 0
+           AND NOT attisdropped AND attgenerated = ''",
+        &[table_name.into()],
+    )?
+    .ok_or("Table has no columns")?;
+
+    // Pass 2: materialize the Hilbert order, then refill the table in batches
+    let schema = extension_schema()?;
+    Spi::run("DROP TABLE IF EXISTS pg_temp.__rostgis_cluster")?;
+    Spi::run(&format!(
+        "CREATE TEMP TABLE __rostgis_cluster AS
+         SELECT {columns}, row_number() OVER (
+             ORDER BY {schema}.rostgis_hilbert_key({column}, {}, {}, {}, {})
+         ) AS __rostgis_ord
+         FROM {relation}",
+        extent.min_x, extent.min_y, extent.max_x, extent.max_y,
+    ))?;
+    Spi::run("CREATE INDEX ON __rostgis_cluster (__rostgis_ord)")?;
+
+    let total = Spi::get_one::<i64>("SELECT count(*) FROM __rostgis_cluster")?.unwrap_or(0);
+
+    Spi::run(&format!("TRUNCATE {}", relation))?;
+
+    let mut written: i64 = 0;
+    while written < total {
+        let upper = written + batch_size as i64;
+        Spi::run(&format!(
+            "INSERT INTO {relation} ({columns}) OVERRIDING SYSTEM VALUE
+             SELECT {columns} FROM __rostgis_cluster
+             WHERE __rostgis_ord > {written} AND __rostgis_ord <= {upper}
+             ORDER BY __rostgis_ord"
+        ))?;
+        written = upper.min(total);
+        notice!(
+            "rostgis_cluster_table: rewrote {} of {} rows of {}",
+            written,
+            total,
+            relation
+        );
+    }
+
+    Spi::run("DROP TABLE __rostgis_cluster")?;
+
+    Ok(total)
+}
+
+/// Find the representative of a union-find set, compressing the path
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Connected components of geometries under a pairwise predicate
+///
+/// Candidate pairs come from an R*-tree on the bounding boxes expanded by
+/// `tolerance`. Cluster ids start at 0 and are numbered in order of first
+/// appearance; NULL inputs get a NULL id and empty geometries form clusters
+/// of their own.
+fn connected_clusters<F>(
+    geoms: &[Option<Geometry>],
+    tolerance: f64,
+    connected: F,
+) -> Vec<Option<i32>>
+where
+    F: Fn(&Geometry, &Geometry) -> bool,
+{
+    let index = SpatialIndex::from_geometries(
+        geoms
+            .iter()
+            .enumerate()
+            .filter_map(|(i, geom)| match geom {
+                Some(geom) if !geom.is_empty() => Some(GeometryWithId::new(i as i64, geom.clone())),
+                _ => None,
+            })
+            .collect(),
+    );
+
+    let mut parents: Vec<usize> = (0..geoms.len()).collect();
+    for item in index.iter() {
+        let i = item.id as usize;
+        let search = BBox::new(
+            item.bbox.min_x - tolerance,
+            item.bbox.min_y - tolerance,
+            item.bbox.max_x + tolerance,
+            item.bbox.max_y + tolerance,
+        );
+        for candidate in index.query_bbox(&search) {
+            let j = candidate.id as usize;
+            if j <= i {
+                continue;
+            }
+            let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+            if root_i != root_j && connected(&item.geometry, &candidate.geometry) {
+                parents[root_j] = root_i;
+            }
+        }
+    }
+
+    let mut ids: Vec<Option<i32>> = vec![None; geoms.len()];
+    let mut next_id = 0;
+    for i in 0..geoms.len() {
+        if geoms[i].is_none() {
+            continue;
+        }
+        let root = find_root(&mut parents, i);
+        let id = match ids[root] {
+            Some(id) => id,
+            None => {
+                next_id += 1;
+                next_id - 1
+            }
+        };
+        ids[root] = Some(id);
+        ids[i] = Some(id);
+    }
+    ids
+}
+
+/// Cluster ids of geometries connected by chains of pairs closer than a
+/// distance (ST_ClusterWithinWin)
+pub fn cluster_within(
+    geoms: &[Option<Geometry>],
+    distance: f64,
+) -> Result<Vec<Option<i32>>, RostGisError> {
+    if distance.is_nan() || distance < 0.0 {
+        return Err(RostGisError::new(
+            "Cluster distance must be zero or positive",
+        ));
+    }
+    Ok(connected_clusters(geoms, distance, |a, b| {
+        Euclidean.distance(&a.to_geo(), &b.to_geo()) <= distance
+    }))
+}
+
+/// Cluster ids of geometries connected by chains of intersecting pairs
+/// (ST_ClusterIntersectingWin)
+pub fn cluster_intersecting(geoms: &[Option<Geometry>]) -> Vec<Option<i32>> {
+    connected_clusters(geoms, 0.0, geometries_intersect)
+}
+
+// Window functions
+//
+// pgrx has no support for window functions, so these are plain V1 C
+// functions declared with CREATE FUNCTION ... WINDOW below. The whole
+// partition is clustered on the first call and the ids are kept in the
+// partition-local memory, laid out as a "computed" flag followed by one slot
+// per row (-1 for NULL).
+
+/// Cluster the partition on first use and return the id of the current row
+unsafe fn window_cluster_id<F>(fcinfo: pg_sys::FunctionCallInfo, cluster: F) -> pg_sys::Datum
+where
+    F: FnOnce(pg_sys::WindowObject, &[Option<Geometry>]) -> Vec<Option<i32>>,
+{
+    let winobj = (*fcinfo).context as pg_sys::WindowObject;
+    let rows = pg_sys::WinGetPartitionRowCount(winobj) as usize;
+    let slots = pg_sys::WinGetPartitionLocalMemory(winobj, (rows + 1) * std::mem::size_of::<i32>())
+        as *mut i32;
+    let slots = std::slice::from_raw_parts_mut(slots, rows + 1);
+
+    if slots[0] == 0 {
+        let mut geoms: Vec<Option<Geometry>> = Vec::with_capacity(rows);
+        for row in 0..rows {
+            let (mut isnull, mut isout) = (false, false);
+            let datum = pg_sys::WinGetFuncArgInPartition(
+                winobj,
+                0,
+                row as i32,
+                pg_sys::WINDOW_SEEK_HEAD as i32,
+                false,
+                &mut isnull,
+                &mut isout,
+            );
+            geoms.push(Geometry::from_datum(datum, isnull || isout));
+        }
+        for (slot, id) in slots[1..].iter_mut().zip(cluster(winobj, &geoms)) {
+            *slot = id.unwrap_or(-1);
+        }
+        slots[0] = 1;
+    }
+
+    let id = slots[pg_sys::WinGetCurrentPosition(winobj) as usize + 1];
+    if id < 0 {
+        (*fcinfo).isnull = true;
+        pg_sys::Datum::from(0)
+    } else {
+        pg_sys::Datum::from(id)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_st_clusterwithinwin_window() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+/// ST_ClusterWithinWin(geometry, distance) window function
+///
+/// # Safety
+/// Only to be called by PostgreSQL as a window function.
+#[pg_guard]
+pub unsafe extern "C-unwind" fn st_clusterwithinwin_window(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> pg_sys::Datum {
+    window_cluster_id(fcinfo, |winobj, geoms| {
+        let mut isnull = false;
+        let datum = pg_sys::WinGetFuncArgCurrent(winobj, 1, &mut isnull);
+        let distance = match f64::from_datum(datum, isnull) {
+            Some(distance) => distance,
+            None => error!("ST_ClusterWithinWin distance must not be NULL"),
+        };
+        match cluster_within(geoms, distance) {
+            Ok(ids) => ids,
+            Err(e) => error!("{}", e),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn pg_finfo_st_clusterintersectingwin_window() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+/// ST_ClusterIntersectingWin(geometry) window function
+///
+/// # Safety
+/// Only to be called by PostgreSQL as a window function.
+#[pg_guard]
+pub unsafe extern "C-unwind" fn st_clusterintersectingwin_window(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> pg_sys::Datum {
+    window_cluster_id(fcinfo, |_, geoms| cluster_intersecting(geoms))
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.st_clusterwithinwin(@extschema@.geometry, float8) RETURNS integer
+    WINDOW IMMUTABLE PARALLEL SAFE
+    LANGUAGE c AS 'MODULE_PATHNAME', 'st_clusterwithinwin_window';
+
+CREATE FUNCTION @extschema@.st_clusterintersectingwin(@extschema@.geometry) RETURNS integer
+    WINDOW IMMUTABLE PARALLEL SAFE
+    LANGUAGE c AS 'MODULE_PATHNAME', 'st_clusterintersectingwin_window';
+"#,
+    name = "cluster_window_functions",
+    requires = [Geometry]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_hilbert_index_order_1() {
+        // The order-1 curve visits (0,0), (0,1), (1,1), (1,0)
+        assert_eq!(hilbert_index(1, 0, 0), 0);
+        assert_eq!(hilbert_index(1, 0, 1), 1);
+        assert_eq!(hilbert_index(1, 1, 1), 2);
+        assert_eq!(hilbert_index(1, 1, 0), 3);
+    }
+
+    #[test]
+    fn test_hilbert_index_is_bijective() {
+        let order = 3;
+        let n = 1u32 << order;
+        let mut seen = vec![false; (n * n) as usize];
+        for x in 0..n {
+            for y in 0..n {
+                let d = hilbert_index(order, x, y) as usize;
+                assert!(!seen[d]);
+                seen[d] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn test_hilbert_key_locality() {
+        let extent = BBox::new(0.0, 0.0, 100.0, 100.0);
+        let a = hilbert_key(&make_point(1.0, 1.0), &extent);
+        let b = hilbert_key(&make_point(1.5, 1.5), &extent);
+        let far = hilbert_key(&make_point(99.0, 1.0), &extent);
+
+        assert!((a - b).abs() < (a - far).abs());
+        assert_eq!(hilbert_key(&make_point(-50.0, 0.0), &extent), 0);
+    }
+
+    #[test]
+    fn test_cluster_within_chains() {
+        // 0-1-2 are chained by gaps of 1, 3 is far away
+        let geoms = vec![
+            Some(make_point(0.0, 0.0)),
+            Some(make_point(10.0, 0.0)),
+            Some(make_point(1.0, 0.0)),
+            None,
+            Some(make_point(2.0, 0.0)),
+        ];
+        let ids = cluster_within(&geoms, 1.0).unwrap();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(0), None, Some(0)]);
+
+        let ids = cluster_within(&geoms, 0.5).unwrap();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(2), None, Some(3)]);
+        assert!(cluster_within(&geoms, -1.0).is_err());
+    }
+
+    #[test]
+    fn test_cluster_within_uses_geometry_distance() {
+        // The point is within 1 of the line but far from its vertices
+        let geoms = vec![
+            Some(geometry_from_wkt("LINESTRING(0 0, 100 0)").unwrap()),
+            Some(make_point(50.0, 0.5)),
+        ];
+        assert_eq!(cluster_within(&geoms, 1.0).unwrap(), vec![Some(0), Some(0)]);
+    }
+
+    #[test]
+    fn test_cluster_intersecting() {
+        let geoms = vec![
+            Some(geometry_from_wkt("POLYGON((0 0, 2 0, 2 2, 0 2, 0 0))").unwrap()),
+            Some(geometry_from_wkt("LINESTRING(5 5, 6 6)").unwrap()),
+            Some(geometry_from_wkt("LINESTRING(1 1, 5 5)").unwrap()),
+            // Overlapping bounding boxes only
+            Some(geometry_from_wkt("LINESTRING(10 0, 20 10)").unwrap()),
+            Some(geometry_from_wkt("LINESTRING(16 0, 20 4)").unwrap()),
+        ];
+        assert_eq!(
+            cluster_intersecting(&geoms),
+            vec![Some(0), Some(0), Some(0), Some(1), Some(2)]
+        );
+    }
+}
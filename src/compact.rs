@@ -0,0 +1,325 @@
+use crate::geometry::Geometry;
+use crate::typmod::geometry_type_code;
+use crate::utils::RostGisError;
+use geo_types::{Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
+
+// Compact geometry body (rostgis.storage_encoding = compact)
+//
+// Like TWKB, coordinates are stored as integers scaled by 10^precision, each
+// one as the difference to the previous coordinate of the geometry,
+// zigzag-encoded and written as a LEB128 varint; counts are varints too.
+// Nearby vertices thus take a byte or two per ordinate instead of eight.
+//
+// Unlike TWKB the encoding is lossless: the encoder picks the smallest
+// precision at which every coordinate survives the round trip bit for bit,
+// and geometries needing more than MAX_PRECISION digits, or holding NaN as
+// empty points do, are left as WKB. The geometry type and SRID live in the
+// serialization header, so the body only holds the structure:
+//
+//   Point            x, y
+//   LineString       number of points, then the points
+//   Polygon          number of rings, then each ring as a LineString
+//   Multi*           number of parts, then each part as above
+//   Collection       number of members, then each member's type code byte
+//                    and body
+
+/// Largest number of decimal digits tried
+pub const MAX_PRECISION: u8 = 15;
+
+/// Largest magnitude of a scaled coordinate, so integers stay exact in f64
+const MAX_SCALED: f64 = 9_007_199_254_740_992.0;
+
+fn scale(precision: u8) -> f64 {
+    10f64.powi(i32::from(precision))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    scale: f64,
+    previous: [i64; 2],
+}
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn count(&mut self, count: usize) {
+        self.varint(count as u64);
+    }
+
+    /// Write a coordinate, or None when it is not exact at this precision
+    fn coord(&mut self, coord: Coord<f64>) -> Option<()> {
+        for (axis, value) in [coord.x, coord.y].into_iter().enumerate() {
+            let scaled = (value * self.scale).round();
+            if scaled.is_nan() || scaled.abs() > MAX_SCALED {
+                return None;
+            }
+            let scaled = scaled as i64;
+            if (scaled as f64 / self.scale).to_bits() != value.to_bits() {
+                return None;
+            }
+            let delta = scaled - self.previous[axis];
+            self.previous[axis] = scaled;
+            self.varint(((delta << 1) ^ (delta >> 63)) as u64);
+        }
+        Some(())
+    }
+
+    fn line(&mut self, line: &LineString<f64>) -> Option<()> {
+        self.count(line.0.len());
+        line.0.iter().try_for_each(|&coord| self.coord(coord))
+    }
+
+    fn polygon(&mut self, polygon: &Polygon<f64>) -> Option<()> {
+        self.count(1 + polygon.interiors().len());
+        self.line(polygon.exterior())?;
+        polygon
+            .interiors()
+            .iter()
+            .try_for_each(|ring| self.line(ring))
+    }
+
+    fn geometry(&mut self, geom: &Geometry) -> Option<()> {
+        match geom {
+            // Z and M have no compact encoding
+            Geometry::Zm(..) => None,
+            Geometry::Point(point, _) => self.coord(point.0),
+            Geometry::LineString(line, _) => self.line(line),
+            Geometry::Polygon(polygon, _) => self.polygon(polygon),
+            Geometry::MultiPoint(points, _) => {
+                self.count(points.0.len());
+                points.iter().try_for_each(|point| self.coord(point.0))
+            }
+            Geometry::MultiLineString(lines, _) => {
+                self.count(lines.0.len());
+                lines.iter().try_for_each(|line| self.line(line))
+            }
+            Geometry::MultiPolygon(polygons, _) => {
+                self.count(polygons.0.len());
+                polygons
+                    .iter()
+                    .try_for_each(|polygon| self.polygon(polygon))
+            }
+            Geometry::GeometryCollection(members, _) => {
+                self.count(members.len());
+                members.iter().try_for_each(|member| {
+                    self.bytes.push(geometry_type_code(member) as u8);
+                    self.geometry(member)
+                })
+            }
+        }
+    }
+}
+
+/// Encode a geometry at the smallest lossless precision, as (precision,
+/// body); None when no precision up to MAX_PRECISION is lossless
+pub fn encode(geom: &Geometry) -> Option<(u8, Vec<u8>)> {
+    if geom.has_z() || geom.has_m() {
+        return None;
+    }
+    (0..=MAX_PRECISION).find_map(|precision| {
+        let mut writer = Writer {
+            bytes: Vec::new(),
+            scale: scale(precision),
+            previous: [0, 0],
+        };
+        writer.geometry(geom).map(|()| (precision, writer.bytes))
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    scale: f64,
+    previous: [i64; 2],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, RostGisError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| RostGisError::new("Compact geometry body is truncated"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, RostGisError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(RostGisError::new("Invalid varint in compact geometry body"))
+    }
+
+    fn count(&mut self) -> Result<usize, RostGisError> {
+        let count = self.varint()? as usize;
+        // Every element takes at least one byte, which bounds allocations
+        if count > self.bytes.len() - self.position {
+            return Err(RostGisError::new("Invalid count in compact geometry body"));
+        }
+        Ok(count)
+    }
+
+    fn coord(&mut self) -> Result<Coord<f64>, RostGisError> {
+        let mut ordinates = [0.0; 2];
+        for (axis, ordinate) in ordinates.iter_mut().enumerate() {
+            let zigzag = self.varint()?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            self.previous[axis] = self.previous[axis].wrapping_add(delta);
+            *ordinate = self.previous[axis] as f64 / self.scale;
+        }
+        Ok(Coord {
+            x: ordinates[0],
+            y: ordinates[1],
+        })
+    }
+
+    fn line(&mut self) -> Result<LineString<f64>, RostGisError> {
+        let count = self.count()?;
+        (0..count)
+            .map(|_| self.coord())
+            .collect::<Result<_, _>>()
+            .map(LineString)
+    }
+
+    fn polygon(&mut self) -> Result<Polygon<f64>, RostGisError> {
+        let rings = self.count()?;
+        if rings == 0 {
+            return Err(RostGisError::new("Compact polygon has no exterior ring"));
+        }
+        let exterior = self.line()?;
+        let interiors = (1..rings).map(|_| self.line()).collect::<Result<_, _>>()?;
+        Ok(Polygon::new(exterior, interiors))
+    }
+
+    fn parts<T>(
+        &mut self,
+        mut part: impl FnMut(&mut Self) -> Result<T, RostGisError>,
+    ) -> Result<Vec<T>, RostGisError> {
+        let count = self.count()?;
+        (0..count).map(|_| part(self)).collect()
+    }
+
+    fn geometry(&mut self, type_code: u8) -> Result<Geometry, RostGisError> {
+        Ok(match type_code {
+            1 => Geometry::Point(Point(self.coord()?), 0),
+            2 => Geometry::LineString(self.line()?, 0),
+            3 => Geometry::Polygon(self.polygon()?, 0),
+            4 => Geometry::MultiPoint(
+                MultiPoint(self.parts(|reader| reader.coord().map(Point))?),
+                0,
+            ),
+            5 => Geometry::MultiLineString(MultiLineString(self.parts(Self::line)?), 0),
+            6 => Geometry::MultiPolygon(MultiPolygon(self.parts(Self::polygon)?), 0),
+            7 => Geometry::GeometryCollection(
+                self.parts(|reader| {
+                    let member_type = reader.byte()?;
+                    reader.geometry(member_type)
+                })?,
+                0,
+            ),
+            _ => {
+                return Err(RostGisError::new(&format!(
+                    "Invalid geometry type code in compact body: {}",
+                    type_code
+                )))
+            }
+        })
+    }
+}
+
+/// Decode a compact body of the given type, with SRID 0
+pub fn decode(type_code: u8, precision: u8, bytes: &[u8]) -> Result<Geometry, RostGisError> {
+    if precision > MAX_PRECISION {
+        return Err(RostGisError::new(&format!(
+            "Invalid compact geometry precision: {}",
+            precision
+        )));
+    }
+    let mut reader = Reader {
+        bytes,
+        position: 0,
+        scale: scale(precision),
+        previous: [0, 0],
+    };
+    let geom = reader.geometry(type_code)?;
+    if reader.position != bytes.len() {
+        return Err(RostGisError::new(
+            "Trailing bytes after compact geometry body",
+        ));
+    }
+    Ok(geom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn roundtrip(wkt: &str) -> (u8, usize) {
+        let geom = geometry_from_wkt(wkt).unwrap();
+        let (precision, body) = encode(&geom).unwrap();
+        let type_code = geometry_type_code(&geom) as u8;
+        assert_eq!(
+            decode(type_code, precision, &body).unwrap(),
+            geom,
+            "{}",
+            wkt
+        );
+        (precision, body.len())
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        assert_eq!(roundtrip("POINT(1 2)"), (0, 2));
+        assert_eq!(roundtrip("POINT(-13.4049537 52.5200066)").0, 7);
+        assert_eq!(
+            roundtrip("LINESTRING(13.4049537 52.5200066, 13.4049612 52.5200101)"),
+            // The second point only takes its small offsets
+            (7, 1 + 9 + 3)
+        );
+        roundtrip("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0), (1 1, 2 1, 2 2, 1 1))");
+        roundtrip("MULTIPOINT((0.5 0.25), (-3 7))");
+        roundtrip("MULTILINESTRING((0 0, 1 1), (2 2, 3 3.125))");
+        roundtrip("MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))");
+        roundtrip("GEOMETRYCOLLECTION(POINT(1 1), LINESTRING(0 0, 0.1 0.2))");
+        roundtrip("LINESTRING EMPTY");
+        roundtrip("POLYGON EMPTY");
+        roundtrip("GEOMETRYCOLLECTION EMPTY");
+    }
+
+    #[test]
+    fn test_not_compactable() {
+        // Empty points are NaN, and most computed values need all 17 digits
+        assert!(encode(&geometry_from_wkt("POINT EMPTY").unwrap()).is_none());
+        let third = Geometry::Point(Point::new(1.0 / 3.0, 0.0), 0);
+        assert!(encode(&third).is_none());
+        let huge = Geometry::Point(Point::new(1e300, 0.0), 0);
+        assert!(encode(&huge).is_none());
+        // Signed zero survives
+        let negative_zero = Geometry::Point(Point::new(-0.0, 0.0), 0);
+        assert!(encode(&negative_zero).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_body() {
+        let geom = geometry_from_wkt("LINESTRING(0 0, 1 1, 2 2)").unwrap();
+        let (precision, body) = encode(&geom).unwrap();
+        assert!(decode(2, precision, &body[..body.len() - 1]).is_err());
+        assert!(decode(2, precision, &[body.clone(), vec![0]].concat()).is_err());
+        assert!(decode(2, MAX_PRECISION + 1, &body).is_err());
+        assert!(decode(9, precision, &body).is_err());
+        // A count larger than the remaining bytes
+        assert!(decode(2, 0, &[0xff, 0xff, 0x03]).is_err());
+    }
+}
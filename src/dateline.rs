@@ -0,0 +1,373 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::{unary_union, BooleanOps, BoundingRect, MapCoords};
+use geo_types::{Coord, LineString, MultiLineString, MultiPolygon, Point, Polygon, Rect};
+
+// Antimeridian handling for longitude/latitude geometries
+//
+// Coordinates are first "unwrapped" so that consecutive vertices never jump
+// by more than 180 degrees (a jump means the edge crosses the antimeridian),
+// then cut at every ±180 + 360k boundary and shifted back into [-180, 180].
+
+/// Index of the 360-degree band a longitude falls in; band 0 is [-180, 180)
+fn band(lon: f64) -> i64 {
+    ((lon + 180.0) / 360.0).floor() as i64
+}
+
+/// Make a coordinate sequence continuous in longitude, starting within 180
+/// degrees of `reference`
+fn unwrap_coords(coords: &[Coord<f64>], reference: f64) -> Result<Vec<Coord<f64>>, RostGisError> {
+    let mut previous = reference;
+    coords
+        .iter()
+        .map(|coord| {
+            if !(coord.x.is_finite() && coord.y.is_finite()) {
+                return Err(RostGisError::new(
+                    "Geometries with non-finite coordinates cannot be split at the antimeridian",
+                ));
+            }
+            let x = (coord.x - previous + 180.0).rem_euclid(360.0) - 180.0 + previous;
+            previous = x;
+            Ok(Coord { x, y: coord.y })
+        })
+        .collect()
+}
+
+fn shift(coord: Coord<f64>, dx: f64) -> Coord<f64> {
+    Coord {
+        x: coord.x + dx,
+        y: coord.y,
+    }
+}
+
+/// Split a line at the antimeridian into pieces that each lie in [-180, 180]
+fn split_line(line: &LineString<f64>) -> Result<Vec<LineString<f64>>, RostGisError> {
+    let first = match line.0.first() {
+        Some(first) => first.x,
+        None => return Ok(vec![]),
+    };
+    let coords = unwrap_coords(&line.0, first)?;
+
+    let mut pieces: Vec<Vec<Coord<f64>>> = vec![vec![coords[0]]];
+    for pair in coords.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let (band_a, band_b) = (band(a.x), band(b.x));
+        if band_a != band_b {
+            let boundary = -180.0 + 360.0 * band_a.max(band_b) as f64;
+            let t = (boundary - a.x) / (b.x - a.x);
+            let crossing = Coord {
+                x: boundary,
+                y: a.y + t * (b.y - a.y),
+            };
+            let current = pieces.last_mut().unwrap();
+            if current.last() != Some(&crossing) {
+                current.push(crossing);
+            }
+            pieces.push(vec![crossing]);
+        }
+        let current = pieces.last_mut().unwrap();
+        if current.last() != Some(&b) {
+            current.push(b);
+        }
+    }
+
+    Ok(pieces
+        .into_iter()
+        .filter(|piece| piece.len() >= 2)
+        .map(|piece| {
+            // The band of the first segment's midpoint identifies the piece
+            let dx = -360.0 * band((piece[0].x + piece[1].x) / 2.0) as f64;
+            LineString(piece.into_iter().map(|c| shift(c, dx)).collect())
+        })
+        .collect())
+}
+
+/// Split a polygon at the antimeridian by clipping it against each band
+fn split_polygon(polygon: &Polygon<f64>) -> Result<Vec<Polygon<f64>>, RostGisError> {
+    let exterior = match polygon.exterior().0.first() {
+        Some(first) => unwrap_coords(&polygon.exterior().0, first.x)?,
+        None => return Ok(vec![polygon.clone()]),
+    };
+    let closes = |ring: &[Coord<f64>]| match (ring.first(), ring.last()) {
+        (Some(first), Some(last)) => (first.x - last.x).abs() < 1e-9,
+        _ => true,
+    };
+    if !closes(&exterior) {
+        return Err(RostGisError::new(
+            "Polygons enclosing a pole cannot be split at the antimeridian",
+        ));
+    }
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .map(|ring| unwrap_coords(&ring.0, exterior[0].x))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !interiors.iter().all(|ring| closes(ring)) {
+        return Err(RostGisError::new(
+            "Polygons enclosing a pole cannot be split at the antimeridian",
+        ));
+    }
+
+    let unwrapped = Polygon::new(
+        LineString(exterior),
+        interiors.into_iter().map(LineString).collect(),
+    );
+    let bounds = match unwrapped.bounding_rect() {
+        Some(bounds) => bounds,
+        None => return Ok(vec![polygon.clone()]),
+    };
+    let first_band = band(bounds.min().x);
+    // A shell ending exactly on a boundary does not reach into the next band
+    let last_band = ((bounds.max().x + 180.0) / 360.0).ceil() as i64 - 1;
+
+    if first_band >= last_band {
+        let dx = -360.0 * first_band as f64;
+        return Ok(vec![unwrapped.map_coords(|c| shift(c, dx))]);
+    }
+
+    let mut pieces = Vec::new();
+    for k in first_band..=last_band {
+        let west = -180.0 + 360.0 * k as f64;
+        let clip = Rect::new(
+            Coord {
+                x: west,
+                y: bounds.min().y - 1.0,
+            },
+            Coord {
+                x: west + 360.0,
+                y: bounds.max().y + 1.0,
+            },
+        )
+        .to_polygon();
+        let dx = -360.0 * k as f64;
+        for piece in unwrapped.intersection(&clip) {
+            pieces.push(piece.map_coords(|c| shift(c, dx)));
+        }
+    }
+    Ok(pieces)
+}
+
+fn normalize_longitude(lon: f64) -> f64 {
+    lon - 360.0 * band(lon) as f64
+}
+
+fn lines_to_geometry(mut lines: Vec<LineString<f64>>, srid: i32) -> Geometry {
+    if lines.len() == 1 {
+        Geometry::LineString(lines.remove(0), srid)
+    } else {
+        Geometry::MultiLineString(MultiLineString(lines), srid)
+    }
+}
+
+fn polygons_to_geometry(mut polygons: Vec<Polygon<f64>>, srid: i32) -> Geometry {
+    if polygons.len() == 1 {
+        Geometry::Polygon(polygons.remove(0), srid)
+    } else {
+        Geometry::MultiPolygon(MultiPolygon(polygons), srid)
+    }
+}
+
+/// Split a longitude/latitude geometry at the ±180° meridian
+///
+/// Lines and polygons crossing the antimeridian are cut into pieces that lie
+/// within [-180, 180] and returned as multi-geometries; geometries that do
+/// not cross it keep their type. Longitudes outside the valid range are
+/// wrapped back into it.
+pub fn split_at_dateline(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    let srid = geom.srid();
+    Ok(match geom {
+        Geometry::Zm(base, _) => split_at_dateline(base)?,
+        Geometry::Point(point, _) => {
+            Geometry::Point(Point::new(normalize_longitude(point.x()), point.y()), srid)
+        }
+        Geometry::MultiPoint(multipoint, _) => Geometry::MultiPoint(
+            multipoint
+                .0
+                .iter()
+                .map(|point| Point::new(normalize_longitude(point.x()), point.y()))
+                .collect(),
+            srid,
+        ),
+        Geometry::LineString(linestring, _) if linestring.0.is_empty() => geom.clone(),
+        Geometry::LineString(linestring, _) => lines_to_geometry(split_line(linestring)?, srid),
+        Geometry::MultiLineString(multilinestring, _) => {
+            let mut lines = Vec::new();
+            for linestring in &multilinestring.0 {
+                lines.extend(split_line(linestring)?);
+            }
+            Geometry::MultiLineString(MultiLineString(lines), srid)
+        }
+        Geometry::Polygon(polygon, _) => polygons_to_geometry(split_polygon(polygon)?, srid),
+        Geometry::MultiPolygon(multipolygon, _) => {
+            let mut polygons = Vec::new();
+            for polygon in &multipolygon.0 {
+                polygons.extend(split_polygon(polygon)?);
+            }
+            Geometry::MultiPolygon(MultiPolygon(polygons), srid)
+        }
+        Geometry::GeometryCollection(geometries, _) => Geometry::GeometryCollection(
+            geometries
+                .iter()
+                .map(split_at_dateline)
+                .collect::<Result<_, _>>()?,
+            srid,
+        ),
+    })
+}
+
+/// Join line pieces whose end point is the start point of another piece
+fn join_lines(mut lines: Vec<LineString<f64>>) -> Vec<LineString<f64>> {
+    let mut joined: Vec<LineString<f64>> = Vec::new();
+    while let Some(mut line) = lines.pop() {
+        while let Some(index) = lines
+            .iter()
+            .position(|other| other.0.first() == line.0.last())
+        {
+            let next = lines.remove(index);
+            line.0.extend(next.0.into_iter().skip(1));
+        }
+        while let Some(index) = lines
+            .iter()
+            .position(|other| other.0.last() == line.0.first())
+        {
+            let mut previous = lines.remove(index);
+            previous.0.extend(line.0.into_iter().skip(1));
+            line = previous;
+        }
+        joined.push(line);
+    }
+    joined.reverse();
+    joined
+}
+
+/// Reassemble pieces produced by [`split_at_dateline`]
+///
+/// When the parts of a geometry touch both sides of the antimeridian, the
+/// western parts are shifted by +360° and the parts are merged again (lines
+/// joined end to end, polygons unioned). The result therefore uses the
+/// continuous 0..360 longitude range, like ST_ShiftLongitude.
+pub fn merge_at_dateline(geom: &Geometry) -> Geometry {
+    let srid = geom.srid();
+    let touches = |lon: f64| geom.coordinates().iter().any(|(x, _)| *x == lon);
+    if !(touches(180.0) && touches(-180.0)) {
+        return geom.clone();
+    }
+
+    let east = |c: Coord<f64>| if c.x < 0.0 { shift(c, 360.0) } else { c };
+
+    match geom {
+        Geometry::MultiLineString(multilinestring, _) => lines_to_geometry(
+            join_lines(
+                multilinestring
+                    .0
+                    .iter()
+                    .map(|line| line.map_coords(east))
+                    .collect(),
+            ),
+            srid,
+        ),
+        Geometry::MultiPolygon(multipolygon, _) => {
+            let shifted: Vec<Polygon<f64>> = multipolygon
+                .0
+                .iter()
+                .map(|polygon| polygon.map_coords(east))
+                .collect();
+            polygons_to_geometry(unary_union(shifted.iter()).0, srid)
+        }
+        Geometry::GeometryCollection(geometries, _) => {
+            Geometry::GeometryCollection(geometries.iter().map(merge_at_dateline).collect(), srid)
+        }
+        _ => geom.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    #[test]
+    fn test_split_line() {
+        let line = geometry_from_wkt("LINESTRING(170 0, -170 10)").unwrap();
+        let split = split_at_dateline(&line).unwrap();
+        assert_eq!(
+            split,
+            Geometry::MultiLineString(
+                MultiLineString(vec![
+                    LineString::from(vec![(170.0, 0.0), (180.0, 5.0)]),
+                    LineString::from(vec![(-180.0, 5.0), (-170.0, 10.0)]),
+                ]),
+                0,
+            )
+        );
+
+        // Reassembly gives the continuous line in the 0..360 range
+        assert_eq!(
+            merge_at_dateline(&split),
+            geometry_from_wkt("LINESTRING(170 0, 180 5, 190 10)").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_line_far_outside_range() {
+        // Unwrapping is a single step however many turns away a vertex is
+        let line = geometry_from_wkt("LINESTRING(170 0, 3600190 10)").unwrap();
+        assert_eq!(
+            split_at_dateline(&line).unwrap(),
+            geometry_from_wkt("MULTILINESTRING((170 0, 180 5), (-180 5, -170 10))").unwrap()
+        );
+        let infinite = Geometry::LineString(
+            LineString::from(vec![(170.0, 0.0), (f64::INFINITY, 10.0)]),
+            4326,
+        );
+        assert!(split_at_dateline(&infinite).is_err());
+    }
+
+    #[test]
+    fn test_split_line_not_crossing() {
+        let line = geometry_from_wkt("LINESTRING(-10 0, 10 10)").unwrap();
+        assert_eq!(split_at_dateline(&line).unwrap(), line);
+    }
+
+    #[test]
+    fn test_split_polygon() {
+        let polygon =
+            geometry_from_wkt("POLYGON((170 -10, -170 -10, -170 10, 170 10, 170 -10))").unwrap();
+        let split = split_at_dateline(&polygon).unwrap();
+        match &split {
+            Geometry::MultiPolygon(multipolygon, _) => {
+                assert_eq!(multipolygon.0.len(), 2);
+                for piece in &multipolygon.0 {
+                    let bounds = piece.bounding_rect().unwrap();
+                    assert!(bounds.min().x >= -180.0 && bounds.max().x <= 180.0);
+                    assert!((bounds.width() - 10.0).abs() < 1e-9);
+                }
+            }
+            other => panic!("expected a MultiPolygon, got {:?}", other),
+        }
+
+        match merge_at_dateline(&split) {
+            Geometry::Polygon(merged, _) => {
+                let bounds = merged.bounding_rect().unwrap();
+                assert!((bounds.min().x - 170.0).abs() < 1e-9);
+                assert!((bounds.max().x - 190.0).abs() < 1e-9);
+            }
+            other => panic!("expected a Polygon, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_split_polygon_around_pole() {
+        let polar = geometry_from_wkt("POLYGON((0 80, 120 80, -120 80, 0 80))").unwrap();
+        assert!(split_at_dateline(&polar).is_err());
+    }
+
+    #[test]
+    fn test_split_normalizes_points() {
+        let point = geometry_from_wkt("POINT(190 5)").unwrap();
+        assert_eq!(
+            split_at_dateline(&point).unwrap(),
+            geometry_from_wkt("POINT(-170 5)").unwrap()
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::geometry::Geometry;
+use pgrx::prelude::*;
+
+// Coordinate dimensions (ST_Force2D, ST_Force3D, ST_Force3DZ, ST_Force3DM,
+// ST_Force4D)
+//
+// Drop or add z and m values, as when loading 2D and 3D sources into one
+// table, under the names PostGIS uses:
+//
+//   INSERT INTO footprints SELECT ST_Force2D(geom) FROM building_models;
+//   INSERT INTO models SELECT ST_Force3D(geom, 12.5) FROM footprints;
+//
+// The added ordinates take the given value (0 by default) and existing ones
+// are kept, so ST_Force4D of a POINT Z keeps its z and adds m. ST_Force3DZ
+// drops m and ST_Force3DM drops z. Empty points take NaN, as when stored.
+
+/// The geometry with a Z ordinate when `z` is Some, and an M ordinate when
+/// `m` is Some, existing values being kept and missing ones set to the
+/// given value
+pub fn force_dimensions(geom: Geometry, z: Option<f64>, m: Option<f64>) -> Geometry {
+    let ordinates = geom.ordinates().cloned().unwrap_or_default();
+    let base = geom.into_xy();
+    let fill = |value: f64| -> Vec<f64> {
+        base.coordinates()
+            .iter()
+            .map(|(x, _)| if x.is_nan() { f64::NAN } else { value })
+            .collect()
+    };
+    let z = z.map(|value| ordinates.z.unwrap_or_else(|| fill(value)));
+    let m = m.map(|value| ordinates.m.unwrap_or_else(|| fill(value)));
+    base.with_ordinates(z, m)
+        .expect("one ordinate per vertex of the geometry")
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_force2d(geom: Geometry) -> Geometry {
+    geom.into_xy()
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_force3d(geom: Geometry, zvalue: default!(f64, 0.0)) -> Geometry {
+    force_dimensions(geom, Some(zvalue), None)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_force3dz(geom: Geometry, zvalue: default!(f64, 0.0)) -> Geometry {
+    force_dimensions(geom, Some(zvalue), None)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_force3dm(geom: Geometry, mvalue: default!(f64, 0.0)) -> Geometry {
+    force_dimensions(geom, None, Some(mvalue))
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_force4d(geom: Geometry, zvalue: default!(f64, 0.0), mvalue: default!(f64, 0.0)) -> Geometry {
+    force_dimensions(geom, Some(zvalue), Some(mvalue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    #[test]
+    fn test_force_dimensions() {
+        let force = |wkt: &str, z: Option<f64>, m: Option<f64>| {
+            force_dimensions(geometry_from_wkt(wkt).unwrap(), z, m).to_wkt()
+        };
+        assert_eq!(
+            force("LINESTRING(0 0, 1 1)", Some(2.0), None),
+            "LINESTRING Z (0 0 2,1 1 2)"
+        );
+        assert_eq!(force("POINT Z (1 2 3)", None, Some(0.0)), "POINT M (1 2 0)");
+        assert_eq!(
+            force("POINT Z (1 2 3)", Some(0.0), Some(4.0)),
+            "POINT ZM (1 2 3 4)"
+        );
+        assert_eq!(force("POINT ZM (1 2 3 4)", None, None), "POINT(1 2)");
+        assert_eq!(force("POINT EMPTY", Some(0.0), None), "POINT Z EMPTY");
+    }
+}
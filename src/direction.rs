@@ -0,0 +1,225 @@
+use crate::geometry::Geometry;
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use geo_types::{Coord, LineString, MultiLineString};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+// Line direction normalization (ST_NormalizeDirection)
+//
+// Network analyses expect every line to be digitized in the direction of
+// flow or travel. A line, or every part of a multi-line on its own, is
+// reversed when it runs against its reference:
+//
+//   Point       the line should end closer to the point than it starts,
+//               e.g. a river network flowing towards its outlet
+//   Line        the line should run the same way as the reference, from
+//               its start towards its end
+//   Azimuth     the line's start-to-end azimuth should be within 90
+//               degrees of the given azimuth (radians, as ST_Azimuth)
+//
+// Lines whose ends are as close to a point, or whose direction is at right
+// angles to the reference, are left as they are, and so are closed lines.
+
+/// What a line's direction is compared with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reference {
+    /// Run towards this position
+    Towards(Coord<f64>),
+    /// Run along this direction vector
+    Along(Coord<f64>),
+}
+
+impl Reference {
+    /// Reference of a point or line geometry
+    pub fn from_geometry(geom: &Geometry) -> Result<Self, RostGisError> {
+        let chord = |line: &LineString<f64>| match (line.0.first(), line.0.last()) {
+            (Some(start), Some(end)) => *end - *start,
+            _ => Coord { x: 0.0, y: 0.0 },
+        };
+        match geom {
+            Geometry::Point(point, _) if !geom.is_empty() => Ok(Reference::Towards(point.0)),
+            Geometry::LineString(line, _) => Ok(Reference::Along(chord(line))),
+            Geometry::MultiLineString(lines, _) => Ok(Reference::Along(
+                lines
+                    .iter()
+                    .map(chord)
+                    .fold(Coord { x: 0.0, y: 0.0 }, |sum, chord| sum + chord),
+            )),
+            _ => Err(RostGisError::new(&format!(
+                "ST_NormalizeDirection reference must be a point or a line, got {}",
+                geom.geometry_type()
+            ))),
+        }
+    }
+
+    /// Reference of an azimuth in radians, clockwise from north
+    pub fn from_azimuth(azimuth: f64) -> Self {
+        let (sin, cos) = azimuth.sin_cos();
+        Reference::Along(Coord { x: sin, y: cos })
+    }
+
+    /// Whether a line from `start` to `end` runs against the reference
+    fn opposes(&self, start: Coord<f64>, end: Coord<f64>) -> bool {
+        let squared = |c: Coord<f64>| c.x * c.x + c.y * c.y;
+        match *self {
+            Reference::Towards(target) => squared(end - target) > squared(start - target),
+            Reference::Along(direction) => {
+                let chord = end - start;
+                chord.x * direction.x + chord.y * direction.y < 0.0
+            }
+        }
+    }
+}
+
+/// One line oriented along the reference
+fn normalize_line(line: &LineString<f64>, reference: &Reference) -> LineString<f64> {
+    match (line.0.first(), line.0.last()) {
+        (Some(&start), Some(&end)) if reference.opposes(start, end) => {
+            LineString(line.0.iter().rev().copied().collect())
+        }
+        _ => line.clone(),
+    }
+}
+
+/// Orient a line, or each part of a multi-line, along a reference
+pub fn normalize_direction(
+    geom: &Geometry,
+    reference: &Reference,
+) -> Result<Geometry, RostGisError> {
+    match geom {
+        Geometry::LineString(line, srid) => {
+            Ok(Geometry::LineString(normalize_line(line, reference), *srid))
+        }
+        Geometry::MultiLineString(lines, srid) => Ok(Geometry::MultiLineString(
+            MultiLineString(
+                lines
+                    .iter()
+                    .map(|line| normalize_line(line, reference))
+                    .collect(),
+            ),
+            *srid,
+        )),
+        _ => Err(RostGisError::new(&format!(
+            "ST_NormalizeDirection requires a LineString or MultiLineString, got {}",
+            geom.geometry_type()
+        ))),
+    }
+}
+
+/// PostgreSQL function orienting a line towards a point or along a line
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_normalizedirection(
+    line: Geometry,
+    reference: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(normalize_direction(
+        &line,
+        &Reference::from_geometry(&reference)?,
+    )?)
+}
+
+/// PostgreSQL function orienting a line along an azimuth in radians
+#[pg_extern(immutable, strict, parallel_safe, name = "st_normalizedirection")]
+fn st_normalizedirection_azimuth(
+    line: Geometry,
+    azimuth: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(normalize_direction(
+        &line,
+        &Reference::from_azimuth(azimuth),
+    )?)
+}
+
+/// Orient all lines of a table column along a reference geometry in place,
+/// returning the number of rows reversed
+///
+/// E.g. `SELECT rostgis_normalize_directions('rivers', 'geom', outlet)`
+/// before building a flow network.
+#[pg_extern]
+pub fn rostgis_normalize_directions(
+    table_name: &str,
+    geom_column: &str,
+    reference: Geometry,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    // Fail on an unusable reference before touching the table
+    Reference::from_geometry(&reference)?;
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+    let schema = extension_schema()?;
+    let reversed = Spi::get_one_with_args::<i64>(
+        &format!(
+            "WITH reversed AS (
+                 UPDATE {relation} SET {column} = {schema}.st_normalizedirection({column}, $1)
+                 WHERE {schema}.st_asbinary({column})
+                     <> {schema}.st_asbinary({schema}.st_normalizedirection({column}, $1))
+                 RETURNING 1)
+             SELECT count(*) FROM reversed"
+        ),
+        &[reference.into()],
+    )?;
+    Ok(reversed.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn normalize(wkt: &str, reference: Reference) -> String {
+        normalize_direction(&geometry_from_wkt(wkt).unwrap(), &reference)
+            .unwrap()
+            .to_wkt()
+    }
+
+    #[test]
+    fn test_towards_point() {
+        let outlet = Reference::from_geometry(&make_point(0.0, 0.0)).unwrap();
+        assert_eq!(
+            normalize("LINESTRING(1 1, 5 5, 9 9)", outlet),
+            "LINESTRING(9 9,5 5,1 1)"
+        );
+        assert_eq!(
+            normalize("LINESTRING(9 9, 1 1)", outlet),
+            "LINESTRING(9 9,1 1)"
+        );
+        // Each part on its own
+        assert_eq!(
+            normalize("MULTILINESTRING((1 0, 3 0), (0 4, 0 2))", outlet),
+            "MULTILINESTRING((3 0,1 0),(0 4,0 2))"
+        );
+    }
+
+    #[test]
+    fn test_along_reference() {
+        let east =
+            Reference::from_geometry(&geometry_from_wkt("LINESTRING(0 0, 10 0)").unwrap()).unwrap();
+        assert_eq!(
+            normalize("LINESTRING(5 5, 4 8, 2 6)", east),
+            "LINESTRING(2 6,4 8,5 5)"
+        );
+        // At right angles, and closed lines, are left alone
+        assert_eq!(
+            normalize("LINESTRING(0 5, 0 0)", east),
+            "LINESTRING(0 5,0 0)"
+        );
+        assert_eq!(
+            normalize("LINESTRING(0 0, 1 0, 1 1, 0 0)", east),
+            "LINESTRING(0 0,1 0,1 1,0 0)"
+        );
+
+        // An azimuth of 180 degrees runs south
+        let south = Reference::from_azimuth(std::f64::consts::PI);
+        assert_eq!(
+            normalize("LINESTRING(0 0, 1 10)", south),
+            "LINESTRING(1 10,0 0)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let polygon = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+        assert!(Reference::from_geometry(&polygon).is_err());
+        assert!(Reference::from_geometry(&geometry_from_wkt("POINT EMPTY").unwrap()).is_err());
+        assert!(normalize_direction(&polygon, &Reference::from_azimuth(0.0)).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::clustering::hilbert_key;
+use crate::geometry::Geometry;
+use crate::overlay::report_fallbacks;
+use crate::spatial_index::BBox;
+use crate::union::UnionAccumulator;
+use crate::utils::{quote_ident, quote_table, RostGisError};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use pgrx::JsonB;
+
+// Dissolve by attribute (rostgis_dissolve)
+//
+// The union of a table's geometries per group of attribute values, the
+// equivalent of
+//
+//   SELECT county, ST_Union(geom) FROM parcels GROUP BY county;
+//
+// written as
+//
+//   SELECT * FROM rostgis_dissolve('parcels', 'geom', ARRAY['county']);
+//
+// but much faster on large groups. Each group's geometries are sorted along
+// a Hilbert curve over the group's extent before they are unioned in
+// cascaded batches (see the union module), so neighbours are unioned with
+// neighbours and every intermediate union stays small, where the aggregate
+// unions rows in table order into one ever-growing polygon. The table is
+// read through a cursor ordered by the group columns, so one group is held
+// in memory at a time. group_key holds the group's values by column name;
+// rows whose geometry is NULL are skipped.
+
+/// Rows fetched from the table at a time
+const FETCH_BATCH: i64 = 10_000;
+
+/// Union of a group of geometries in Hilbert order; None for no geometries
+pub fn dissolve_group(mut geometries: Vec<Geometry>) -> Result<Option<Geometry>, RostGisError> {
+    let extent = geometries
+        .iter()
+        .filter(|geom| !geom.is_empty())
+        .map(BBox::from_geometry)
+        .reduce(|extent, bbox| extent.union(&bbox));
+    if let Some(extent) = extent {
+        geometries.sort_by_cached_key(|geom| hilbert_key(geom, &extent));
+    }
+    let mut accumulator = UnionAccumulator::default();
+    for geom in &geometries {
+        accumulator.add(geom)?;
+    }
+    accumulator.finish()
+}
+
+/// PostgreSQL function returning the union of a table's geometries for
+/// every combination of values of `group_columns`, with the number of rows
+/// unioned
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn rostgis_dissolve(
+    table_name: &str,
+    geom_column: &str,
+    group_columns: default!(Vec<String>, "'{}'"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(group_key, JsonB),
+            name!(geom, Geometry),
+            name!(members, i64),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+    let (key, order) = Spi::get_two_with_args::<String, String>(
+        "SELECT 'jsonb_build_object(' || string_agg(quote_literal(c) || ', ' || quote_ident(c),
+                                                     ', ' ORDER BY i) || ')',
+                string_agg(quote_ident(c), ', ' ORDER BY i)
+         FROM unnest($1::text[]) WITH ORDINALITY AS u(c, i)",
+        &[group_columns.into()],
+    )?;
+    let key = key.unwrap_or_else(|| "'{}'::jsonb".to_string());
+    let order = order.map(|order| format!("ORDER BY {}", order));
+
+    let groups = Spi::connect(|client| {
+        let mut cursor = client.open_cursor(
+            &format!(
+                "SELECT {key}, {column} FROM {relation} WHERE {column} IS NOT NULL {}",
+                order.unwrap_or_default()
+            ),
+            &[],
+        );
+        let mut groups = Vec::new();
+        let mut current: Option<(JsonB, Vec<Geometry>)> = None;
+        let mut finish = |group: Option<(JsonB, Vec<Geometry>)>| -> Result<(), RostGisError> {
+            if let Some((key, geometries)) = group {
+                let members = geometries.len() as i64;
+                if let Some(union) = dissolve_group(geometries)? {
+                    groups.push((key, union, members));
+                }
+            }
+            Ok(())
+        };
+        loop {
+            let batch = cursor.fetch(FETCH_BATCH)?;
+            if batch.is_empty() {
+                break;
+            }
+            for row in batch {
+                let (Some(key), Some(geom)) = (row.get::<JsonB>(1)?, row.get::<Geometry>(2)?)
+                else {
+                    continue;
+                };
+                match &mut current {
+                    Some((current_key, geometries)) if current_key.0 == key.0 => {
+                        geometries.push(geom)
+                    }
+                    _ => finish(current.replace((key, vec![geom])))?,
+                }
+            }
+        }
+        finish(current)?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(groups)
+    });
+    report_fallbacks();
+    Ok(TableIterator::new(groups?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+    use geo::Area;
+
+    #[test]
+    fn test_dissolve_group() {
+        // A 4 x 4 grid of unit squares, shuffled
+        let mut squares = Vec::new();
+        for i in [3, 0, 2, 1] {
+            for j in [1, 3, 0, 2] {
+                squares.push(
+                    geometry_from_wkt(&format!(
+                        "POLYGON(({i} {j}, {a} {j}, {a} {b}, {i} {b}, {i} {j}))",
+                        a = i + 1,
+                        b = j + 1
+                    ))
+                    .unwrap(),
+                );
+            }
+        }
+        match dissolve_group(squares).unwrap() {
+            Some(Geometry::Polygon(polygon, _)) => {
+                assert!((polygon.unsigned_area() - 16.0).abs() < 1e-9);
+                assert!(polygon.interiors().is_empty());
+            }
+            other => panic!("expected a polygon, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dissolve_group_edge_cases() {
+        assert_eq!(dissolve_group(Vec::new()).unwrap(), None);
+        let apart = vec![
+            geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap(),
+            geometry_from_wkt("POLYGON EMPTY").unwrap(),
+            geometry_from_wkt("POLYGON((5 5, 6 5, 6 6, 5 5))").unwrap(),
+        ];
+        assert_eq!(
+            dissolve_group(apart).unwrap().unwrap().geometry_type(),
+            "ST_MultiPolygon"
+        );
+        let mixed = vec![
+            geometry_from_wkt("POINT(0 0)").unwrap(),
+            geometry_from_wkt("POINT(0 0)").unwrap().with_srid(4326),
+        ];
+        assert!(dissolve_group(mixed).is_err());
+    }
+}
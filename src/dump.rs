@@ -0,0 +1,290 @@
+use crate::functions::{geometry_n, num_geometries};
+use crate::geometry::Geometry;
+use crate::utils::{extension_schema, RostGisError};
+use geo_types::{Coord, Point, Polygon};
+use pgrx::heap_tuple::PgHeapTuple;
+use pgrx::prelude::*;
+
+// Dumping geometries into rows (ST_Dump, ST_DumpPoints, ST_DumpRings)
+//
+// Each function explodes a geometry into one row per part, with the path of
+// the part in the geometry, as in PostGIS:
+//
+//   SELECT id, (ST_Dump(geom)).geom FROM districts;
+//   SELECT d.path[1] AS ring, d.path[2] AS vertex, d.geom
+//   FROM parcels p, ST_DumpPoints(p.geom) d;
+//
+// ST_Dump returns the members of multi-geometries and collections, with
+// nested collections flattened and the path holding the position at every
+// level; any other geometry is returned with an empty path, unless it is
+// empty, which gives no rows as an empty collection does. ST_DumpPoints
+// returns every vertex as a point: the path ends with the number of the
+// vertex in its linestring or ring, after the number of the ring, from 1,
+// for polygons and the positions of the members for collections (a point
+// is vertex 1 of itself). ST_DumpRings returns the rings of a polygon as
+// polygons, the exterior with path {0} and the holes numbered from 1.
+// Parts keep the SRID of the whole.
+//
+// The rows are of the composite type geometry_dump, as in PostGIS, so
+// queries and PL/pgSQL code written for it work unchanged:
+//
+//   SELECT (dp).path[1], ST_Area((dp).geom)
+//   FROM (SELECT ST_Dump(geom) AS dp FROM districts) AS t;
+//
+//   DECLARE dp geometry_dump;
+
+/// A part of a geometry with its path
+pub type DumpRow = (Vec<i32>, Geometry);
+
+fn is_collection(geom: &Geometry) -> bool {
+    matches!(
+        geom,
+        Geometry::MultiPoint(..)
+            | Geometry::MultiLineString(..)
+            | Geometry::MultiPolygon(..)
+            | Geometry::GeometryCollection(..)
+    )
+}
+
+/// Run `f` on every member of a collection with its position pushed on the
+/// path
+fn for_each_member(
+    geom: &Geometry,
+    path: &mut Vec<i32>,
+    mut f: impl FnMut(&Geometry, &mut Vec<i32>),
+) {
+    for n in 1..=num_geometries(geom) {
+        if let Some(member) = geometry_n(geom, n) {
+            path.push(n);
+            f(&member, path);
+            path.pop();
+        }
+    }
+}
+
+/// The atomic parts of a geometry, with their positions in the collections
+/// holding them; none for an empty geometry
+pub fn dump(geom: &Geometry) -> Vec<DumpRow> {
+    fn dump_into(geom: &Geometry, path: &mut Vec<i32>, rows: &mut Vec<DumpRow>) {
+        if is_collection(geom) {
+            for_each_member(geom, path, |member, path| dump_into(member, path, rows));
+        } else {
+            rows.push((path.clone(), geom.clone()));
+        }
+    }
+    let mut rows = Vec::new();
+    if !geom.is_empty() {
+        dump_into(geom, &mut Vec::new(), &mut rows);
+    }
+    rows
+}
+
+/// Push the vertices of a linestring or ring as points, numbered from 1
+/// after `path`
+fn push_vertices<'a>(
+    coords: impl Iterator<Item = &'a Coord<f64>>,
+    path: &[i32],
+    srid: i32,
+    rows: &mut Vec<DumpRow>,
+) {
+    for (i, coord) in coords.enumerate() {
+        let mut vertex_path = path.to_vec();
+        vertex_path.push(i as i32 + 1);
+        rows.push((vertex_path, Geometry::Point(Point::from(*coord), srid)));
+    }
+}
+
+/// The vertices of a geometry as points, each with its path
+pub fn dump_points(geom: &Geometry) -> Vec<DumpRow> {
+    fn points_into(geom: &Geometry, path: &mut Vec<i32>, rows: &mut Vec<DumpRow>) {
+        match geom {
+            Geometry::Point(point, srid) => {
+                if !geom.is_empty() {
+                    push_vertices(std::iter::once(&point.0), path, *srid, rows);
+                }
+            }
+            Geometry::LineString(line, srid) => push_vertices(line.coords(), path, *srid, rows),
+            Geometry::Polygon(polygon, srid) => {
+                let rings = std::iter::once(polygon.exterior()).chain(polygon.interiors());
+                for (ring_index, ring) in rings.enumerate() {
+                    path.push(ring_index as i32 + 1);
+                    push_vertices(ring.coords(), path, *srid, rows);
+                    path.pop();
+                }
+            }
+            _ => for_each_member(geom, path, |member, path| points_into(member, path, rows)),
+        }
+    }
+    let mut rows = Vec::new();
+    points_into(geom, &mut Vec::new(), &mut rows);
+    rows
+}
+
+/// The rings of a polygon as polygons: the exterior with path [0], the
+/// holes from [1]
+pub fn dump_rings(geom: &Geometry) -> Result<Vec<DumpRow>, RostGisError> {
+    let Geometry::Polygon(polygon, srid) = geom else {
+        return Err(RostGisError::new(&format!(
+            "ST_DumpRings: only polygons have rings, not {}",
+            geom.geometry_type()
+        )));
+    };
+    if geom.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .enumerate()
+        .map(|(i, ring)| {
+            (
+                vec![i as i32],
+                Geometry::Polygon(Polygon::new(ring.clone(), Vec::new()), *srid),
+            )
+        })
+        .collect())
+}
+
+extension_sql!(
+    r#"
+CREATE TYPE @extschema@.geometry_dump AS (
+    path integer[],
+    geom @extschema@.geometry
+);
+"#,
+    name = "geometry_dump",
+    requires = [Geometry],
+);
+
+/// Dumped parts as geometry_dump rows
+#[allow(clippy::type_complexity)]
+fn dump_tuples(
+    rows: Vec<DumpRow>,
+) -> Result<
+    SetOfIterator<'static, pgrx::composite_type!('static, "geometry_dump")>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    // Qualified, so rows can be built whatever the caller's search_path
+    let type_name = format!("{}.geometry_dump", extension_schema()?);
+    let tuples = rows
+        .into_iter()
+        .map(|(path, geom)| {
+            let mut tuple = PgHeapTuple::new_composite_type(&type_name)?;
+            tuple.set_by_name("path", path)?;
+            tuple.set_by_name("geom", geom)?;
+            Ok(tuple)
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error + Send + Sync>>>()?;
+    Ok(SetOfIterator::new(tuples))
+}
+
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe, requires = ["geometry_dump"])]
+fn st_dump(
+    geom: Geometry,
+) -> Result<
+    SetOfIterator<'static, pgrx::composite_type!('static, "geometry_dump")>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    dump_tuples(dump(&geom))
+}
+
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe, requires = ["geometry_dump"])]
+fn st_dumppoints(
+    geom: Geometry,
+) -> Result<
+    SetOfIterator<'static, pgrx::composite_type!('static, "geometry_dump")>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    dump_tuples(dump_points(&geom))
+}
+
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe, requires = ["geometry_dump"])]
+fn st_dumprings(
+    geom: Geometry,
+) -> Result<
+    SetOfIterator<'static, pgrx::composite_type!('static, "geometry_dump")>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    dump_tuples(dump_rings(&geom)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn rows(rows: Vec<DumpRow>) -> Vec<(Vec<i32>, String)> {
+        rows.into_iter()
+            .map(|(path, geom)| (path, geom.to_ewkt()))
+            .collect()
+    }
+
+    #[test]
+    fn test_dump() {
+        let geom = geometry_from_wkt(
+            "GEOMETRYCOLLECTION(POINT(1 2), MULTILINESTRING((0 0, 1 1), (2 2, 3 3)))",
+        )
+        .unwrap()
+        .with_srid(4326);
+        assert_eq!(
+            rows(dump(&geom)),
+            vec![
+                (vec![1], "SRID=4326;POINT(1 2)".to_string()),
+                (vec![2, 1], "SRID=4326;LINESTRING(0 0,1 1)".to_string()),
+                (vec![2, 2], "SRID=4326;LINESTRING(2 2,3 3)".to_string()),
+            ]
+        );
+        let polygon = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+        assert_eq!(dump(&polygon), vec![(Vec::new(), polygon.clone())]);
+        for empty in ["GEOMETRYCOLLECTION EMPTY", "POINT EMPTY", "POLYGON EMPTY"] {
+            assert!(dump(&geometry_from_wkt(empty).unwrap()).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_dump_points() {
+        let polygon =
+            geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))").unwrap();
+        let points = rows(dump_points(&polygon));
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[1], (vec![1, 2], "POINT(4 0)".to_string()));
+        assert_eq!(points[5], (vec![2, 2], "POINT(2 1)".to_string()));
+
+        let multipoint = geometry_from_wkt("MULTIPOINT(1 2, 3 4)")
+            .unwrap()
+            .with_srid(3857);
+        assert_eq!(
+            rows(dump_points(&multipoint)),
+            vec![
+                (vec![1, 1], "SRID=3857;POINT(1 2)".to_string()),
+                (vec![2, 1], "SRID=3857;POINT(3 4)".to_string()),
+            ]
+        );
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert_eq!(
+            rows(dump_points(&line))[1],
+            (vec![2], "POINT(1 1)".to_string())
+        );
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert!(dump_points(&empty).is_empty());
+    }
+
+    #[test]
+    fn test_dump_rings() {
+        let polygon =
+            geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))").unwrap();
+        assert_eq!(
+            rows(dump_rings(&polygon).unwrap()),
+            vec![
+                (vec![0], "POLYGON((0 0,4 0,4 4,0 0))".to_string()),
+                (vec![1], "POLYGON((1 1,2 1,2 2,1 1))".to_string()),
+            ]
+        );
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert!(dump_rings(&empty).unwrap().is_empty());
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(dump_rings(&line).is_err());
+    }
+}
@@ -0,0 +1,649 @@
+use crate::geometry::{Geometry, OrdinateCursor};
+use crate::typmod::geometry_type_code;
+use crate::utils::RostGisError;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use geo_types::{Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
+use std::io::{Cursor, Write};
+
+// Well-Known Binary reading and writing
+//
+// Output is always little-endian. EWKB (PostGIS extended WKB) carries the SRID
+// in the type word; input accepts OGC WKB, ISO WKB (Z/M encoded as +1000s) and
+// EWKB in either byte order. Z and M ordinates are written as ISO type codes
+// in WKB and as type word flags in EWKB, like PostGIS does.
+const EWKB_Z_FLAG: u32 = 0x8000_0000;
+const EWKB_M_FLAG: u32 = 0x4000_0000;
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Encode a geometry as WKB, or as EWKB when `with_srid` is set and the
+/// geometry has a non-zero SRID
+pub fn write_wkb(geom: &Geometry, with_srid: bool) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    write_top(&mut buffer, geom, with_srid);
+    buffer
+}
+
+/// Largest bytea PostgreSQL can hold: 1 GB less one byte, less the
+/// varlena header
+pub const MAX_BYTEA_SIZE: usize = 0x3FFF_FFFF - 4;
+
+/// Size in bytes of the WKB or EWKB of a geometry, without encoding it
+pub fn wkb_size(geom: &Geometry, with_srid: bool) -> usize {
+    if let Geometry::Zm(base, ordinates) = geom {
+        let extra = ordinates.z.iter().chain(ordinates.m.iter()).count();
+        return wkb_size(base, with_srid) + 8 * extra * base.coordinates().len();
+    }
+    let header = if with_srid && geom.srid() != 0 { 9 } else { 5 };
+    let ring = |ring: &LineString<f64>| 4 + 16 * ring.0.len();
+    let polygon = |polygon: &Polygon<f64>| {
+        if polygon.exterior().0.is_empty() {
+            4
+        } else {
+            4 + ring(polygon.exterior()) + polygon.interiors().iter().map(ring).sum::<usize>()
+        }
+    };
+    header
+        + match geom {
+            Geometry::Point(..) => 16,
+            Geometry::LineString(linestring, _) => ring(linestring),
+            Geometry::Polygon(p, _) => polygon(p),
+            Geometry::MultiPoint(multipoint, _) => 4 + 21 * multipoint.0.len(),
+            Geometry::MultiLineString(multilinestring, _) => {
+                4 + multilinestring.iter().map(|l| 5 + ring(l)).sum::<usize>()
+            }
+            Geometry::MultiPolygon(multipolygon, _) => {
+                4 + multipolygon.iter().map(|p| 5 + polygon(p)).sum::<usize>()
+            }
+            Geometry::GeometryCollection(geometries, _) => {
+                4 + geometries
+                    .iter()
+                    .map(|child| wkb_size(child, false))
+                    .sum::<usize>()
+            }
+            Geometry::Zm(..) => unreachable!("handled above"),
+        }
+}
+
+/// Fail with a hint at the alternatives when the WKB of a geometry cannot fit
+/// in one bytea; `function` names the SQL function in the message
+pub fn check_wkb_size(
+    geom: &Geometry,
+    with_srid: bool,
+    function: &str,
+) -> Result<(), RostGisError> {
+    bytea_size_check(wkb_size(geom, with_srid), function)
+}
+
+fn bytea_size_check(size: usize, function: &str) -> Result<(), RostGisError> {
+    if size > MAX_BYTEA_SIZE {
+        return Err(RostGisError::new(&format!(
+            "{}: the geometry needs {} bytes, more than the {} a bytea can hold; \
+             export it in pieces with {}Chunks or split it with ST_Subdivide",
+            function, size, MAX_BYTEA_SIZE, function
+        )));
+    }
+    Ok(())
+}
+
+/// Writer filling a list of buffers of at most `chunk_size` bytes each
+struct ChunkWriter {
+    chunks: Vec<Vec<u8>>,
+    chunk_size: usize,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, mut bytes: &[u8]) -> std::io::Result<usize> {
+        let written = bytes.len();
+        while !bytes.is_empty() {
+            match self.chunks.last_mut() {
+                Some(chunk) if chunk.len() < self.chunk_size => {
+                    let taken = bytes.len().min(self.chunk_size - chunk.len());
+                    chunk.extend_from_slice(&bytes[..taken]);
+                    bytes = &bytes[taken..];
+                }
+                _ => self.chunks.push(Vec::with_capacity(
+                    self.chunk_size.min(bytes.len().max(4096)),
+                )),
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encode a geometry like write_wkb, as consecutive pieces of at most
+/// `chunk_size` bytes, so WKB larger than a bytea can be exported
+pub fn write_wkb_chunks(geom: &Geometry, with_srid: bool, chunk_size: usize) -> Vec<Vec<u8>> {
+    let mut writer = ChunkWriter {
+        chunks: Vec::new(),
+        chunk_size: chunk_size.max(1),
+    };
+    write_top(&mut writer, geom, with_srid);
+    writer.chunks
+}
+
+/// Writer of the WKB or EWKB of an x/y geometry, following each x/y with
+/// the Z and M ordinates of the vertex
+struct WkbWriter<'a, W: Write> {
+    buffer: &'a mut W,
+    ordinates: OrdinateCursor<'a>,
+    ewkb: bool,
+}
+
+fn write_top(buffer: &mut impl Write, geom: &Geometry, with_srid: bool) {
+    let mut writer = WkbWriter {
+        buffer,
+        ordinates: OrdinateCursor::new(geom),
+        ewkb: with_srid,
+    };
+    let srid = (with_srid && geom.srid() != 0).then(|| geom.srid());
+    writer.geometry(geom.xy(), srid);
+}
+
+impl<W: Write> WkbWriter<'_, W> {
+    fn header(&mut self, type_code: u32, srid: Option<i32>) {
+        let (has_z, has_m) = self
+            .ordinates
+            .ordinates()
+            .map_or((false, false), |o| (o.z.is_some(), o.m.is_some()));
+        let mut type_code = type_code;
+        if self.ewkb {
+            if has_z {
+                type_code |= EWKB_Z_FLAG;
+            }
+            if has_m {
+                type_code |= EWKB_M_FLAG;
+            }
+        } else {
+            type_code += 1000 * (has_z as u32 + 2 * has_m as u32);
+        }
+
+        self.buffer.write_u8(1).unwrap(); // little-endian
+        match srid {
+            Some(srid) => {
+                self.buffer
+                    .write_u32::<LittleEndian>(type_code | EWKB_SRID_FLAG)
+                    .unwrap();
+                self.buffer.write_i32::<LittleEndian>(srid).unwrap();
+            }
+            None => self.buffer.write_u32::<LittleEndian>(type_code).unwrap(),
+        }
+    }
+
+    fn count(&mut self, count: usize) {
+        self.buffer.write_u32::<LittleEndian>(count as u32).unwrap();
+    }
+
+    fn coord(&mut self, coord: &Coord<f64>) {
+        self.buffer.write_f64::<LittleEndian>(coord.x).unwrap();
+        self.buffer.write_f64::<LittleEndian>(coord.y).unwrap();
+        let (z, m) = self.ordinates.next();
+        for value in [z, m].into_iter().flatten() {
+            self.buffer.write_f64::<LittleEndian>(value).unwrap();
+        }
+    }
+
+    fn ring(&mut self, ring: &LineString<f64>) {
+        self.count(ring.0.len());
+        for coord in &ring.0 {
+            self.coord(coord);
+        }
+    }
+
+    fn polygon_rings(&mut self, polygon: &Polygon<f64>) {
+        if polygon.exterior().0.is_empty() {
+            self.count(0);
+            return;
+        }
+        self.count(1 + polygon.interiors().len());
+        self.ring(polygon.exterior());
+        for interior in polygon.interiors() {
+            self.ring(interior);
+        }
+    }
+
+    fn geometry(&mut self, geom: &Geometry, srid: Option<i32>) {
+        self.header(geometry_type_code(geom) as u32, srid);
+
+        match geom {
+            Geometry::Point(point, _) => self.coord(&point.0),
+            Geometry::LineString(linestring, _) => self.ring(linestring),
+            Geometry::Polygon(polygon, _) => self.polygon_rings(polygon),
+            Geometry::MultiPoint(multipoint, _) => {
+                self.count(multipoint.0.len());
+                for point in &multipoint.0 {
+                    self.header(1, None);
+                    self.coord(&point.0);
+                }
+            }
+            Geometry::MultiLineString(multilinestring, _) => {
+                self.count(multilinestring.0.len());
+                for linestring in &multilinestring.0 {
+                    self.header(2, None);
+                    self.ring(linestring);
+                }
+            }
+            Geometry::MultiPolygon(multipolygon, _) => {
+                self.count(multipolygon.0.len());
+                for polygon in &multipolygon.0 {
+                    self.header(3, None);
+                    self.polygon_rings(polygon);
+                }
+            }
+            Geometry::GeometryCollection(geometries, _) => {
+                self.count(geometries.len());
+                for child in geometries {
+                    self.geometry(child, None);
+                }
+            }
+            Geometry::Zm(base, _) => self.geometry(base, srid),
+        }
+    }
+}
+
+/// Decode a WKB, ISO WKB or EWKB byte string
+pub fn read_wkb(bytes: &[u8]) -> Result<Geometry, RostGisError> {
+    let mut reader = WkbReader {
+        cursor: Cursor::new(bytes),
+        dimensions: None,
+        z: Vec::new(),
+        m: Vec::new(),
+    };
+    let geom = reader.read_geometry(0)?;
+    if (reader.cursor.position() as usize) != bytes.len() {
+        return Err(RostGisError::new("Trailing bytes after WKB geometry"));
+    }
+    let (has_z, has_m) = reader.dimensions.unwrap_or((false, false));
+    geom.with_ordinates(has_z.then_some(reader.z), has_m.then_some(reader.m))
+}
+
+struct WkbReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    /// Whether the outermost geometry has Z and M, which its members must
+    /// match
+    dimensions: Option<(bool, bool)>,
+    /// Z and M ordinates read so far, in vertex order
+    z: Vec<f64>,
+    m: Vec<f64>,
+}
+
+/// Decoded type word of a WKB geometry
+struct WkbType {
+    base: u32,
+    has_z: bool,
+    has_m: bool,
+    big_endian: bool,
+}
+
+impl WkbReader<'_> {
+    fn truncated(_: std::io::Error) -> RostGisError {
+        RostGisError::new("Unexpected end of WKB input")
+    }
+
+    fn read_u32(&mut self, big_endian: bool) -> Result<u32, RostGisError> {
+        if big_endian {
+            self.cursor.read_u32::<BigEndian>()
+        } else {
+            self.cursor.read_u32::<LittleEndian>()
+        }
+        .map_err(Self::truncated)
+    }
+
+    fn read_f64(&mut self, big_endian: bool) -> Result<f64, RostGisError> {
+        if big_endian {
+            self.cursor.read_f64::<BigEndian>()
+        } else {
+            self.cursor.read_f64::<LittleEndian>()
+        }
+        .map_err(Self::truncated)
+    }
+
+    fn read_count(&mut self, big_endian: bool) -> Result<usize, RostGisError> {
+        let count = self.read_u32(big_endian)? as usize;
+        // Every element needs at least 16 bytes, reject counts that cannot fit
+        let remaining = self.cursor.get_ref().len() - self.cursor.position() as usize;
+        if count > remaining {
+            return Err(RostGisError::new("Invalid element count in WKB input"));
+        }
+        Ok(count)
+    }
+
+    fn read_type(&mut self) -> Result<(WkbType, Option<i32>), RostGisError> {
+        let big_endian = match self.cursor.read_u8().map_err(Self::truncated)? {
+            0 => true,
+            1 => false,
+            other => {
+                return Err(RostGisError::new(&format!(
+                    "Invalid WKB byte order marker: {}",
+                    other
+                )))
+            }
+        };
+
+        let raw = self.read_u32(big_endian)?;
+        let srid = if raw & EWKB_SRID_FLAG != 0 {
+            Some(self.read_u32(big_endian)? as i32)
+        } else {
+            None
+        };
+
+        let mut has_z = raw & EWKB_Z_FLAG != 0;
+        let mut has_m = raw & EWKB_M_FLAG != 0;
+        let mut base = raw & 0x0FFF_FFFF;
+        // ISO WKB: 1000s for Z, 2000s for M, 3000s for ZM
+        match base / 1000 {
+            1 => has_z = true,
+            2 => has_m = true,
+            3 => {
+                has_z = true;
+                has_m = true;
+            }
+            _ => {}
+        }
+        base %= 1000;
+
+        match self.dimensions {
+            None => self.dimensions = Some((has_z, has_m)),
+            Some(dimensions) if dimensions != (has_z, has_m) => {
+                return Err(RostGisError::new("Mixed dimensions in WKB geometry"))
+            }
+            Some(_) => {}
+        }
+
+        Ok((
+            WkbType {
+                base,
+                has_z,
+                has_m,
+                big_endian,
+            },
+            srid,
+        ))
+    }
+
+    fn read_coord(&mut self, wkb_type: &WkbType) -> Result<Coord<f64>, RostGisError> {
+        let x = self.read_f64(wkb_type.big_endian)?;
+        let y = self.read_f64(wkb_type.big_endian)?;
+        if wkb_type.has_z {
+            let z = self.read_f64(wkb_type.big_endian)?;
+            self.z.push(z);
+        }
+        if wkb_type.has_m {
+            let m = self.read_f64(wkb_type.big_endian)?;
+            self.m.push(m);
+        }
+        Ok(Coord { x, y })
+    }
+
+    fn read_ring(&mut self, wkb_type: &WkbType) -> Result<LineString<f64>, RostGisError> {
+        let count = self.read_count(wkb_type.big_endian)?;
+        let mut coords = Vec::with_capacity(count);
+        for _ in 0..count {
+            coords.push(self.read_coord(wkb_type)?);
+        }
+        Ok(LineString(coords))
+    }
+
+    /// Read a polygon ring, closing it here rather than in Polygon::new so
+    /// the closing vertex gets ordinates too
+    fn read_closed_ring(&mut self, wkb_type: &WkbType) -> Result<LineString<f64>, RostGisError> {
+        let first = (self.z.len(), self.m.len());
+        let mut ring = self.read_ring(wkb_type)?;
+        if !ring.is_closed() {
+            ring.0.push(ring.0[0]);
+            if wkb_type.has_z {
+                self.z.push(self.z[first.0]);
+            }
+            if wkb_type.has_m {
+                self.m.push(self.m[first.1]);
+            }
+        }
+        Ok(ring)
+    }
+
+    fn read_polygon_rings(&mut self, wkb_type: &WkbType) -> Result<Polygon<f64>, RostGisError> {
+        let count = self.read_count(wkb_type.big_endian)?;
+        if count == 0 {
+            return Ok(Polygon::new(LineString(vec![]), vec![]));
+        }
+        let exterior = self.read_closed_ring(wkb_type)?;
+        let mut interiors = Vec::with_capacity(count - 1);
+        for _ in 1..count {
+            interiors.push(self.read_closed_ring(wkb_type)?);
+        }
+        Ok(Polygon::new(exterior, interiors))
+    }
+
+    fn read_geometry(&mut self, depth: usize) -> Result<Geometry, RostGisError> {
+        if depth > 32 {
+            return Err(RostGisError::new("WKB geometry nesting is too deep"));
+        }
+
+        let (wkb_type, srid) = self.read_type()?;
+        let srid = srid.unwrap_or(0);
+
+        let geom = match wkb_type.base {
+            1 => Geometry::Point(Point(self.read_coord(&wkb_type)?), srid),
+            2 => Geometry::LineString(self.read_ring(&wkb_type)?, srid),
+            3 => Geometry::Polygon(self.read_polygon_rings(&wkb_type)?, srid),
+            4..=7 => {
+                let count = self.read_count(wkb_type.big_endian)?;
+                let mut children = Vec::with_capacity(count);
+                for _ in 0..count {
+                    children.push(self.read_geometry(depth + 1)?);
+                }
+                collect_children(wkb_type.base, children, srid)?
+            }
+            other => {
+                return Err(RostGisError::new(&format!(
+                    "Unsupported WKB geometry type: {}",
+                    other
+                )))
+            }
+        };
+
+        Ok(geom)
+    }
+}
+
+/// Assemble the members of a multi-geometry, checking their types
+pub(crate) fn collect_children(
+    base: u32,
+    children: Vec<Geometry>,
+    srid: i32,
+) -> Result<Geometry, RostGisError> {
+    let mismatch = || RostGisError::new("Invalid member type in WKB multi-geometry");
+
+    Ok(match base {
+        4 => Geometry::MultiPoint(
+            MultiPoint(
+                children
+                    .into_iter()
+                    .map(|g| match g {
+                        Geometry::Point(p, _) => Ok(p),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            srid,
+        ),
+        5 => Geometry::MultiLineString(
+            MultiLineString(
+                children
+                    .into_iter()
+                    .map(|g| match g {
+                        Geometry::LineString(ls, _) => Ok(ls),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            srid,
+        ),
+        6 => Geometry::MultiPolygon(
+            MultiPolygon(
+                children
+                    .into_iter()
+                    .map(|g| match g {
+                        Geometry::Polygon(p, _) => Ok(p),
+                        _ => Err(mismatch()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            srid,
+        ),
+        _ => Geometry::GeometryCollection(
+            children.into_iter().map(|g| g.with_srid(srid)).collect(),
+            srid,
+        ),
+    })
+}
+
+/// Read the byte order and geometry type word of a WKB buffer without
+/// decoding the rest of it
+pub fn peek_wkb_type(bytes: &[u8]) -> Option<u32> {
+    let raw = match bytes.first()? {
+        0 => BigEndian::read_u32(bytes.get(1..5)?),
+        1 => LittleEndian::read_u32(bytes.get(1..5)?),
+        _ => return None,
+    };
+    Some((raw & 0x0FFF_FFFF) % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_point_wkb() {
+        let wkb = write_wkb(&make_point(1.0, 2.0), false);
+        assert_eq!(
+            crate::utils::bytes_to_hex(&wkb),
+            "0101000000000000000000f03f0000000000000040"
+        );
+        assert_eq!(read_wkb(&wkb).unwrap(), make_point(1.0, 2.0));
+    }
+
+    #[test]
+    fn test_wkb_size() {
+        for wkt in [
+            "POINT(1 2)",
+            "LINESTRING(0 0, 1 1, 2 0)",
+            "POLYGON((0 0, 4 0, 4 4, 0 4, 0 0), (1 1, 2 1, 2 2, 1 1))",
+            "POLYGON EMPTY",
+            "MULTIPOINT((0 0), (1 1))",
+            "MULTILINESTRING((0 0, 1 1), (2 2, 3 3, 4 4))",
+            "MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))",
+            "GEOMETRYCOLLECTION(POINT(1 1), LINESTRING(0 0, 1 1))",
+        ] {
+            let geom = geometry_from_wkt(wkt).unwrap().with_srid(4326);
+            for with_srid in [false, true] {
+                assert_eq!(
+                    wkb_size(&geom, with_srid),
+                    write_wkb(&geom, with_srid).len(),
+                    "{}",
+                    wkt
+                );
+            }
+        }
+        assert!(check_wkb_size(&make_point(0.0, 0.0), true, "ST_AsEWKB").is_ok());
+        let error = bytea_size_check(MAX_BYTEA_SIZE + 1, "ST_AsEWKB").unwrap_err();
+        assert!(error.to_string().contains("ST_AsEWKBChunks"));
+        assert!(error.to_string().contains("ST_Subdivide"));
+    }
+
+    #[test]
+    fn test_wkb_chunks() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1, 2 0, 3 5, 4 4)")
+            .unwrap()
+            .with_srid(3857);
+        let ewkb = write_wkb(&line, true);
+        for chunk_size in [1, 7, 16, ewkb.len(), 1000] {
+            let chunks = write_wkb_chunks(&line, true, chunk_size);
+            assert_eq!(chunks.len(), ewkb.len().div_ceil(chunk_size));
+            assert!(chunks.iter().all(|chunk| chunk.len() <= chunk_size));
+            assert_eq!(chunks.concat(), ewkb);
+        }
+    }
+
+    #[test]
+    fn test_ewkb_roundtrip() {
+        let polygon = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))")
+            .unwrap()
+            .with_srid(4326);
+        let ewkb = write_wkb(&polygon, true);
+        assert_eq!(read_wkb(&ewkb).unwrap(), polygon);
+
+        // Plain WKB drops the SRID
+        let wkb = write_wkb(&polygon, false);
+        assert_eq!(read_wkb(&wkb).unwrap().srid(), 0);
+        assert_eq!(peek_wkb_type(&wkb), Some(3));
+    }
+
+    #[test]
+    fn test_collection_roundtrip() {
+        let collection = Geometry::GeometryCollection(
+            vec![
+                make_point(1.0, 2.0),
+                geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap(),
+            ],
+            0,
+        );
+        assert_eq!(
+            read_wkb(&write_wkb(&collection, false)).unwrap(),
+            collection
+        );
+    }
+
+    #[test]
+    fn test_big_endian_and_iso_z() {
+        // POINT Z (1 2 3) as big-endian ISO WKB
+        let hex = "00000003e93ff000000000000040000000000000004008000000000000";
+        let bytes = crate::utils::hex_to_bytes(hex).unwrap();
+        let point = read_wkb(&bytes).unwrap();
+        assert_eq!(point, crate::functions::make_point_z(1.0, 2.0, 3.0));
+        assert_eq!(
+            crate::utils::bytes_to_hex(&write_wkb(&point, false)),
+            "01e9030000000000000000f03f00000000000000400000000000000840"
+        );
+    }
+
+    #[test]
+    fn test_zm_roundtrip() {
+        for wkt in [
+            "POINT Z (1 2 3)",
+            "POINT M EMPTY",
+            "LINESTRING M (0 0 5,1 1 6)",
+            "POLYGON ZM ((0 0 1 2,4 0 1 2,4 4 1 2,0 0 3 4),(1 1 0 0,2 1 0 0,1 2 0 0,1 1 0 0))",
+            "MULTIPOINT Z ((0 0 1),(1 1 2))",
+            "GEOMETRYCOLLECTION Z (POINT Z (1 2 3),LINESTRING Z (0 0 0,1 1 1))",
+        ] {
+            let geom = geometry_from_wkt(wkt).unwrap().with_srid(4326);
+            for with_srid in [false, true] {
+                let wkb = write_wkb(&geom, with_srid);
+                assert_eq!(wkb.len(), wkb_size(&geom, with_srid), "{}", wkt);
+                let read = read_wkb(&wkb).unwrap();
+                assert_eq!(read.to_wkt(), geom.to_wkt(), "{}", wkt);
+                assert_eq!(read.srid(), if with_srid { 4326 } else { 0 });
+            }
+        }
+
+        // Members must have the dimensions of their collection
+        let mut mixed = vec![1, 7, 0, 0, 0, 1, 0, 0, 0];
+        mixed.extend(write_wkb(
+            &crate::functions::make_point_z(1.0, 2.0, 3.0),
+            false,
+        ));
+        assert!(read_wkb(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_invalid_wkb() {
+        assert!(read_wkb(&[]).is_err());
+        assert!(read_wkb(&[1, 1, 0, 0, 0, 0]).is_err());
+        assert!(read_wkb(&[7, 1, 0, 0, 0]).is_err());
+    }
+}
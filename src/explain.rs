@@ -0,0 +1,418 @@
+use crate::utils::quote_ident;
+use pgrx::prelude::*;
+use serde_json::Value;
+
+// Spatial EXPLAIN helper
+//
+// Runs EXPLAIN (FORMAT JSON) on a query and reports every spatial clause of
+// the plan with how it was evaluated: as an index condition, as the recheck
+// of a bitmap heap scan, or as a plain row-by-row filter. For nodes holding
+// a && clause the estimated and actual selectivity are compared, since a bad
+// estimate is the usual reason the planner skips a spatial index.
+
+/// Bounding box operators, as printed in plans
+const BBOX_OPERATORS: &[&str] = &[
+    "&&", "~=", "~", "@", "<<", ">>", "&<", "&>", "<<|", "|>>", "&<|", "|&>", "<->",
+];
+
+/// Spatial predicate functions, lower case as printed in plans
+const SPATIAL_FUNCTIONS: &[&str] = &[
+    "st_intersects",
+    "st_contains",
+    "st_within",
+    "st_dwithin",
+    "st_covers",
+    "st_coveredby",
+    "st_touches",
+    "st_crosses",
+    "st_overlaps",
+    "st_equals",
+    "st_disjoint",
+];
+
+/// Estimates this many times off are flagged as misestimated
+const MISESTIMATE_RATIO: f64 = 10.0;
+
+/// How a clause is evaluated by a plan node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClauseAccess {
+    /// Index condition or index ordering: answered by the index
+    Index,
+    /// Recheck condition of a bitmap heap scan: the index found candidate
+    /// rows and the clause is evaluated again on them
+    Recheck,
+    /// Evaluated on every row reaching the node, without index support
+    Filter,
+}
+
+impl ClauseAccess {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClauseAccess::Index => "index",
+            ClauseAccess::Recheck => "recheck",
+            ClauseAccess::Filter => "filter",
+        }
+    }
+}
+
+/// One spatial clause of a plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpatialClause {
+    pub node: String,
+    pub relation: Option<String>,
+    pub clause: String,
+    pub access: ClauseAccess,
+    /// Rows estimated and returned per loop by the node
+    pub estimated_rows: Option<f64>,
+    pub actual_rows: Option<f64>,
+    /// Fraction of the relation selected by the node, for && clauses
+    pub estimated_selectivity: Option<f64>,
+    pub actual_selectivity: Option<f64>,
+    pub note: Option<String>,
+}
+
+/// Whether a clause involves a spatial operator or predicate
+pub fn is_spatial_clause(clause: &str) -> bool {
+    let lower = clause.to_lowercase();
+    SPATIAL_FUNCTIONS
+        .iter()
+        .any(|name| lower.contains(&format!("{}(", name)))
+        || BBOX_OPERATORS
+            .iter()
+            .any(|op| clause.contains(&format!(" {} ", op)))
+}
+
+fn strip_outer_parens(mut text: &str) -> &str {
+    loop {
+        text = text.trim();
+        if !(text.starts_with('(') && text.ends_with(')')) {
+            return text;
+        }
+        // Only strip when the opening parenthesis closes at the very end
+        let mut depth = 0;
+        for (i, c) in text.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 && i < text.len() - 1 {
+                return text;
+            }
+        }
+        text = &text[1..text.len() - 1];
+    }
+}
+
+/// Split a plan condition into its top-level AND terms
+pub fn split_conjunction(condition: &str) -> Vec<String> {
+    let condition = strip_outer_parens(condition);
+    let mut terms = Vec::new();
+    let (mut depth, mut in_string, mut start) = (0i32, false, 0);
+    let bytes = condition.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' => in_string = !in_string,
+            b'(' if !in_string => depth += 1,
+            b')' if !in_string => depth -= 1,
+            b' ' if !in_string && depth == 0 && condition[i..].starts_with(" AND ") => {
+                terms.push(strip_outer_parens(&condition[start..i]).to_string());
+                start = i + 5;
+                i += 4;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    terms.push(strip_outer_parens(&condition[start..]).to_string());
+    terms.retain(|term| !term.is_empty());
+    terms
+}
+
+fn number(node: &Value, key: &str) -> Option<f64> {
+    node.get(key).and_then(Value::as_f64)
+}
+
+fn walk_plan<Q, F>(
+    node: &Value,
+    inherited_relation: Option<&str>,
+    quote: &Q,
+    reltuples: &F,
+    out: &mut Vec<SpatialClause>,
+) where
+    Q: Fn(&str) -> String,
+    F: Fn(&str) -> Option<f64>,
+{
+    let node_type = node
+        .get("Node Type")
+        .and_then(Value::as_str)
+        .unwrap_or("?")
+        .to_string();
+    let relation = node
+        .get("Relation Name")
+        .and_then(Value::as_str)
+        .map(|name| match node.get("Schema").and_then(Value::as_str) {
+            Some(schema) => format!("{}.{}", quote(schema), quote(name)),
+            None => quote(name),
+        })
+        .or_else(|| inherited_relation.map(str::to_string));
+    let node_name = match node.get("Index Name").and_then(Value::as_str) {
+        Some(index) => format!("{} using {}", node_type, index),
+        None => node_type.clone(),
+    };
+
+    let estimated_rows = number(node, "Plan Rows");
+    let actual_rows = number(node, "Actual Rows");
+    let total = relation.as_deref().and_then(reltuples).filter(|&n| n > 0.0);
+
+    for (key, access) in [
+        ("Index Cond", ClauseAccess::Index),
+        ("Order By", ClauseAccess::Index),
+        ("Recheck Cond", ClauseAccess::Recheck),
+        ("Filter", ClauseAccess::Filter),
+        ("Join Filter", ClauseAccess::Filter),
+    ] {
+        let Some(condition) = node.get(key).and_then(Value::as_str) else {
+            continue;
+        };
+        for clause in split_conjunction(condition) {
+            if !is_spatial_clause(&clause) {
+                continue;
+            }
+
+            let bbox_clause = clause.contains(" && ");
+            let (estimated_selectivity, actual_selectivity) = match total {
+                Some(total) if bbox_clause => (
+                    estimated_rows.map(|rows| (rows / total).min(1.0)),
+                    actual_rows.map(|rows| (rows / total).min(1.0)),
+                ),
+                _ => (None, None),
+            };
+
+            let note = match access {
+                ClauseAccess::Filter if node_type == "Seq Scan" => {
+                    Some("sequential scan: no spatial index used".to_string())
+                }
+                ClauseAccess::Filter if key == "Join Filter" => {
+                    Some("join filter: evaluated for every row pair".to_string())
+                }
+                ClauseAccess::Recheck => number(node, "Rows Removed by Index Recheck")
+                    .filter(|&removed| removed > 0.0)
+                    .map(|removed| format!("{} rows removed by recheck", removed)),
+                _ => match (estimated_rows, actual_rows) {
+                    (Some(estimated), Some(actual)) if bbox_clause => {
+                        let (estimated, actual) = (estimated.max(1.0), actual.max(1.0));
+                        if estimated / actual > MISESTIMATE_RATIO
+                            || actual / estimated > MISESTIMATE_RATIO
+                        {
+                            Some("selectivity misestimated: run ANALYZE".to_string())
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                },
+            };
+
+            out.push(SpatialClause {
+                node: node_name.clone(),
+                relation: relation.clone(),
+                clause,
+                access,
+                estimated_rows,
+                actual_rows,
+                estimated_selectivity,
+                actual_selectivity,
+                note,
+            });
+        }
+    }
+
+    // Bitmap index scans report no relation, they inherit their heap scan's
+    let child_relation =
+        if node_type == "Bitmap Heap Scan" || node_type == "BitmapAnd" || node_type == "BitmapOr" {
+            relation.as_deref()
+        } else {
+            None
+        };
+    if let Some(children) = node.get("Plans").and_then(Value::as_array) {
+        for child in children {
+            walk_plan(child, child_relation, quote, reltuples, out);
+        }
+    }
+}
+
+/// Collect the spatial clauses of an EXPLAIN (FORMAT JSON) document
+///
+/// `quote` quotes the identifiers of relation names for SQL, and
+/// `reltuples` gives the row count of a relation so named, used to turn
+/// row counts into selectivities.
+pub fn spatial_clauses<Q, F>(explain: &Value, quote: Q, reltuples: F) -> Vec<SpatialClause>
+where
+    Q: Fn(&str) -> String,
+    F: Fn(&str) -> Option<f64>,
+{
+    let mut clauses = Vec::new();
+    let statements = match explain {
+        Value::Array(statements) => statements.iter().collect(),
+        other => vec![other],
+    };
+    for statement in statements {
+        if let Some(plan) = statement.get("Plan") {
+            walk_plan(plan, None, &quote, &reltuples, &mut clauses);
+        }
+    }
+    clauses
+}
+
+/// PostgreSQL function annotating the spatial clauses of a query plan
+///
+/// With `analyze` (the default) the query is executed to obtain actual row
+/// counts, so data-modifying statements should be explained with
+/// `analyze => false`.
+#[allow(clippy::type_complexity)]
+#[pg_extern(volatile)]
+pub fn rostgis_explain_spatial(
+    query: &str,
+    analyze: default!(bool, true),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(node, String),
+            name!(relation, Option<String>),
+            name!(clause, String),
+            name!(access, String),
+            name!(estimated_rows, Option<f64>),
+            name!(actual_rows, Option<f64>),
+            name!(estimated_selectivity, Option<f64>),
+            name!(actual_selectivity, Option<f64>),
+            name!(note, Option<String>),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let options = if analyze {
+        "ANALYZE, FORMAT JSON"
+    } else {
+        "FORMAT JSON"
+    };
+    let explain = Spi::get_one::<pgrx::Json>(&format!("EXPLAIN ({}) {}", options, query))?
+        .ok_or("EXPLAIN returned no plan")?;
+
+    let quote = |name: &str| quote_ident(name).unwrap_or_else(|_| name.to_string());
+    let clauses = spatial_clauses(&explain.0, quote, |relation| {
+        Spi::get_one_with_args::<f64>(
+            // Qualified, so objects on the caller's search_path cannot
+            // stand in for the catalog's
+            "SELECT reltuples::float8 FROM pg_catalog.pg_class
+             WHERE oid = pg_catalog.to_regclass($1)",
+            &[relation.into()],
+        )
+        .ok()
+        .flatten()
+    });
+
+    Ok(TableIterator::new(clauses.into_iter().map(|c| {
+        (
+            c.node,
+            c.relation,
+            c.clause,
+            c.access.as_str().to_string(),
+            c.estimated_rows,
+            c.actual_rows,
+            c.estimated_selectivity,
+            c.actual_selectivity,
+            c.note,
+        )
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_conjunction() {
+        assert_eq!(
+            split_conjunction("((geom && 'AND'::geometry) AND st_intersects(geom, g))"),
+            vec!["geom && 'AND'::geometry", "st_intersects(geom, g)"]
+        );
+        assert_eq!(split_conjunction("(id > 3)"), vec!["id > 3"]);
+        assert_eq!(
+            split_conjunction("(a > 1) OR (b < 2)"),
+            vec!["(a > 1) OR (b < 2)"]
+        );
+    }
+
+    #[test]
+    fn test_spatial_clause_detection() {
+        assert!(is_spatial_clause("geom && '010100000000'::geometry"));
+        assert!(is_spatial_clause("ST_DWithin(a.geom, b.geom, 10)"));
+        assert!(!is_spatial_clause("name ~~ 'abc%'::text"));
+        assert!(!is_spatial_clause("id = 3"));
+    }
+
+    #[test]
+    fn test_bitmap_plan_annotation() {
+        let explain: Value = serde_json::from_str(
+            r#"[{"Plan": {
+                "Node Type": "Bitmap Heap Scan", "Relation Name": "parcels",
+                "Plan Rows": 50, "Actual Rows": 480, "Actual Loops": 1,
+                "Recheck Cond": "(geom && '0103'::geometry)",
+                "Rows Removed by Index Recheck": 0,
+                "Filter": "st_intersects(geom, '0103'::geometry)",
+                "Rows Removed by Filter": 20,
+                "Plans": [{
+                    "Node Type": "Bitmap Index Scan", "Index Name": "parcels_geom_idx",
+                    "Plan Rows": 50, "Actual Rows": 600, "Actual Loops": 1,
+                    "Index Cond": "(geom && '0103'::geometry)"
+                }]
+            }}]"#,
+        )
+        .unwrap();
+        let clauses = spatial_clauses(&explain, str::to_string, |relation| {
+            (relation == "parcels").then_some(10000.0)
+        });
+        assert_eq!(clauses.len(), 3);
+
+        let recheck = &clauses[0];
+        assert_eq!(recheck.access, ClauseAccess::Recheck);
+        assert_eq!(recheck.note, None);
+
+        let filter = &clauses[1];
+        assert_eq!(filter.access, ClauseAccess::Filter);
+        assert_eq!(filter.estimated_selectivity, None);
+
+        let index = &clauses[2];
+        assert_eq!(index.node, "Bitmap Index Scan using parcels_geom_idx");
+        assert_eq!(index.relation.as_deref(), Some("parcels"));
+        assert_eq!(index.access, ClauseAccess::Index);
+        assert_eq!(index.estimated_selectivity, Some(0.005));
+        assert_eq!(index.actual_selectivity, Some(0.06));
+        assert_eq!(
+            index.note.as_deref(),
+            Some("selectivity misestimated: run ANALYZE")
+        );
+    }
+
+    #[test]
+    fn test_seq_scan_annotation() {
+        let explain: Value = serde_json::from_str(
+            r#"[{"Plan": {
+                "Node Type": "Seq Scan", "Relation Name": "roads", "Plan Rows": 10,
+                "Filter": "((kind = 'x'::text) AND st_dwithin(geom, '0101'::geometry, '5'::double precision))"
+            }}]"#,
+        )
+        .unwrap();
+        let clauses = spatial_clauses(&explain, str::to_string, |_| None);
+        assert_eq!(clauses.len(), 1);
+        assert_eq!(clauses[0].access, ClauseAccess::Filter);
+        assert_eq!(clauses[0].actual_rows, None);
+        assert_eq!(
+            clauses[0].note.as_deref(),
+            Some("sequential scan: no spatial index used")
+        );
+    }
+}
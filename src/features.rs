@@ -0,0 +1,138 @@
+use crate::utils::RostGisError;
+use pgrx::prelude::*;
+
+// Optional cargo features
+//
+// Functionality with heavy dependencies, or that few deployments need, is
+// behind a cargo feature. All of them are on by default; a minimal build is
+// made with e.g.
+//
+//   cargo pgrx install --no-default-features --features pg16,proj
+//
+// The SQL functions of a disabled feature still exist, so the extension's SQL
+// is the same for every build, but they raise a "not compiled in" error.
+// rostgis_features() reports which features a build has.
+
+/// Optional features: name, whether this build has it, and what it provides
+pub const FEATURES: [(&str, bool, &str); 3] = [
+    (
+        "proj",
+        cfg!(feature = "proj"),
+        "Coordinate reference system transformations through PROJ (ST_GridConvergence, ST_Transform of boxes)",
+    ),
+    (
+        "mvt",
+        cfg!(feature = "mvt"),
+        "Vector tile geometries (ST_AsMVTGeom)",
+    ),
+    (
+        "raster",
+        cfg!(feature = "raster"),
+        "Rasterization of geometries to PNG (rostgis_render)",
+    ),
+];
+
+/// Error raised by a function whose feature this build does not have
+pub fn not_compiled_in(function: &str, feature: &str) -> RostGisError {
+    RostGisError::new(&format!(
+        "{} is not compiled in: RostGIS was built without the \"{}\" feature",
+        function, feature
+    ))
+}
+
+/// PostgreSQL function listing the optional features and whether they are
+/// compiled in
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, parallel_safe)]
+pub fn rostgis_features() -> TableIterator<
+    'static,
+    (
+        name!(feature, String),
+        name!(enabled, bool),
+        name!(description, String),
+    ),
+> {
+    TableIterator::new(FEATURES.iter().map(|&(feature, enabled, description)| {
+        (feature.to_string(), enabled, description.to_string())
+    }))
+}
+
+#[cfg(not(feature = "proj"))]
+#[allow(unused_variables)]
+#[pg_extern(stable, strict, parallel_safe)]
+pub fn st_gridconvergence(
+    point: crate::geometry::Geometry,
+    srid: i32,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    Err(not_compiled_in("ST_GridConvergence", "proj").into())
+}
+
+#[cfg(not(feature = "proj"))]
+#[allow(unused_variables)]
+#[pg_extern(stable, strict, parallel_safe, name = "st_transform")]
+pub fn st_transform_bbox(
+    bbox: crate::spatial_index::BBox,
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<crate::spatial_index::BBox, Box<dyn std::error::Error + Send + Sync>> {
+    Err(not_compiled_in("ST_Transform", "proj").into())
+}
+
+#[cfg(not(feature = "proj"))]
+#[allow(unused_variables)]
+#[pg_extern(stable, strict, parallel_safe, name = "st_transform")]
+pub fn st_transform_bboxes(
+    boxes: Array<'_, crate::spatial_index::BBox>,
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<Vec<Option<crate::spatial_index::BBox>>, Box<dyn std::error::Error + Send + Sync>> {
+    Err(not_compiled_in("ST_Transform", "proj").into())
+}
+
+#[cfg(not(feature = "mvt"))]
+#[allow(unused_variables)]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asmvtgeom(
+    geom: crate::geometry::Geometry,
+    bounds: crate::spatial_index::BBox,
+    extent: default!(i32, 4096),
+    buffer: default!(i32, 256),
+    clip_geom: default!(bool, true),
+    make_valid: default!(bool, true),
+    min_area: default!(f64, 0.0),
+) -> Result<Option<crate::geometry::Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    Err(not_compiled_in("ST_AsMVTGeom", "mvt").into())
+}
+
+#[cfg(not(feature = "raster"))]
+#[allow(unused_variables)]
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_render(
+    geometries: Array<'_, crate::geometry::Geometry>,
+    width: i32,
+    height: i32,
+    style: default!(pgrx::JsonB, "'{}'"),
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    Err(not_compiled_in("rostgis_render", "raster").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        let names: Vec<&str> = FEATURES.iter().map(|&(name, _, _)| name).collect();
+        assert_eq!(names, ["proj", "mvt", "raster"]);
+        assert_eq!(
+            FEATURES[0].1,
+            cfg!(feature = "proj"),
+            "proj is reported as compiled in exactly when it is"
+        );
+        assert_eq!(
+            not_compiled_in("ST_AsMVTGeom", "mvt").to_string(),
+            "RostGIS Error: ST_AsMVTGeom is not compiled in: \
+             RostGIS was built without the \"mvt\" feature"
+        );
+    }
+}
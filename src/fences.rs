@@ -0,0 +1,801 @@
+use crate::geometry::Geometry;
+use crate::table_cache::{current_statement, Statement};
+use crate::utils::{extension_schema, RostGisError};
+use geo::kernels::{Kernel, Orientation, RobustKernel};
+use geo::BoundingRect;
+use geo_types::{Coord, MultiPolygon};
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+use pgrx::spi::Spi;
+use pgrx::{pg_shmem_init, register_xact_callback, PgAtomic, PgLwLock, PgXactCallbackEvent};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Geofences (rostgis_fences)
+//
+// A registry of named polygons for location-event pipelines that test many
+// points against the same fences:
+//
+//   INSERT INTO rostgis_fences VALUES ('depot', ST_MakeEnvelope(0, 0, 100, 100, 3857));
+//   SELECT ST_InFence(position, 'depot') FROM events;
+//   SELECT rostgis_classify_fences(position) FROM events;  -- {depot, ...}
+//
+// The fences are packed into flat arrays: their rings' vertices, their
+// names sorted for binary search, and an R-tree of their bounding boxes
+// packed level by level. With RostGIS in shared_preload_libraries one such
+// copy lives in shared memory, loaded by the first backend that needs it
+// and read by all under a shared lock, so a test costs neither a table
+// access nor a copy per backend.
+//
+// The shared copy is tagged with its database and a generation that a
+// transaction changing rostgis_fences bumps once it has committed. A
+// backend reads the generation, then loads the table with a snapshot taken
+// after it, so what it loads is at least as new as the generation it tags
+// it with. A backend whose database does not hold the shared copy, or
+// whose fences exceed its capacity, keeps its own copy tagged the same way.
+//
+// The transaction changing the fences, repeatable read and serializable
+// transactions, and backends without the shared memory load the fences
+// once per statement instead, with the statement's snapshot. Transactions
+// that changed the fences cannot be prepared, as nothing would bump the
+// generation when they are committed. A point on a fence's boundary is in
+// the fence. Points are only classified into fences of their SRID.
+
+/// Most fences, rings, vertices and name bytes the shared copy holds
+const MAX_FENCES: usize = 1024;
+const MAX_RINGS: usize = 8192;
+const MAX_VERTICES: usize = 65536;
+const MAX_NAME_BYTES: usize = 32768;
+
+/// Children of an R-tree node
+const NODE_SIZE: usize = 16;
+/// Boxes and levels of the R-tree over MAX_FENCES fences, nodes included
+const MAX_BOXES: usize = MAX_FENCES + MAX_FENCES / (NODE_SIZE - 1) + 8;
+const MAX_LEVELS: usize = 8;
+
+/// A packed fence: its SRID and bounding box, and the ranges of its name
+/// bytes and rings
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct FenceEntry {
+    srid: i32,
+    name: [u32; 2],
+    rings: [u32; 2],
+    bbox: [f64; 4],
+}
+
+/// Packed fences, owned by a backend or in shared memory. Fences are
+/// sorted by name; ring `r` spans `vertices[rings[r]..rings[r + 1]]`. The
+/// R-tree stores its leaves, then each level of nodes, in `boxes`; a
+/// leaf's `children` entry is its fence, a node's the first of its
+/// NODE_SIZE children, and `levels` holds where each level ends.
+#[derive(Clone, Copy)]
+pub struct Fences<'a> {
+    entries: &'a [FenceEntry],
+    names: &'a [u8],
+    rings: &'a [u32],
+    vertices: &'a [[f64; 2]],
+    boxes: &'a [[f64; 4]],
+    children: &'a [u32],
+    levels: &'a [u32],
+}
+
+/// Whether a bounding box contains a point, NaN coordinates never being in
+fn box_contains(bbox: &[f64; 4], point: Coord<f64>) -> bool {
+    bbox[0] <= point.x && point.x <= bbox[2] && bbox[1] <= point.y && point.y <= bbox[3]
+}
+
+/// The point of a geometry, which must be a point
+fn point_of(geom: &Geometry, function: &str) -> Result<Option<Coord<f64>>, RostGisError> {
+    match geom.xy() {
+        Geometry::Point(point, _) => Ok((!geom.is_empty()).then_some(point.0)),
+        other => Err(RostGisError::new(&format!(
+            "{} requires a point, got {}",
+            function,
+            other.geometry_type()
+        ))),
+    }
+}
+
+impl<'a> Fences<'a> {
+    fn name(&self, entry: &FenceEntry) -> &'a str {
+        let [start, end] = entry.name;
+        std::str::from_utf8(&self.names[start as usize..end as usize]).unwrap_or_default()
+    }
+
+    /// Ids of the fences whose bounding box contains a point
+    fn candidates(&self, point: Coord<f64>) -> Vec<usize> {
+        let mut found = Vec::new();
+        let Some(&end) = self.levels.last() else {
+            return found;
+        };
+        let mut stack = vec![(end as usize - 1, self.levels.len() - 1)];
+        while let Some((node, level)) = stack.pop() {
+            if !box_contains(&self.boxes[node], point) {
+                continue;
+            }
+            let first = self.children[node] as usize;
+            if level == 0 {
+                found.push(first);
+                continue;
+            }
+            let last = (first + NODE_SIZE).min(self.levels[level - 1] as usize);
+            stack.extend((first..last).map(|child| (child, level - 1)));
+        }
+        found
+    }
+
+    /// Whether a fence contains a point: on one of its rings, or inside an
+    /// odd number of them
+    fn contains(&self, id: usize, point: Coord<f64>) -> bool {
+        let entry = &self.entries[id];
+        if !box_contains(&entry.bbox, point) {
+            return false;
+        }
+        let mut inside = false;
+        for ring in entry.rings[0] as usize..entry.rings[1] as usize {
+            let vertices = &self.vertices[self.rings[ring] as usize..self.rings[ring + 1] as usize];
+            for pair in vertices.windows(2) {
+                let a = Coord::from((pair[0][0], pair[0][1]));
+                let b = Coord::from((pair[1][0], pair[1][1]));
+                let orientation = RobustKernel::orient2d(a, b, point);
+                if orientation == Orientation::Collinear
+                    && box_contains(
+                        &[a.x.min(b.x), a.y.min(b.y), a.x.max(b.x), a.y.max(b.y)],
+                        point,
+                    )
+                {
+                    return true;
+                }
+                // Edges crossing the ray from the point towards +x
+                if (a.y <= point.y && point.y < b.y && orientation == Orientation::CounterClockwise)
+                    || (b.y <= point.y && point.y < a.y && orientation == Orientation::Clockwise)
+                {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// Whether a point is in the named fence
+    pub fn in_fence(&self, point: &Geometry, name: &str) -> Result<bool, RostGisError> {
+        let id = self
+            .entries
+            .binary_search_by(|entry| self.name(entry).cmp(name))
+            .map_err(|_| RostGisError::new(&format!("unknown fence \"{}\"", name)))?;
+        let srid = self.entries[id].srid;
+        if point.srid() != srid {
+            return Err(RostGisError::new(&format!(
+                "ST_InFence: point SRID {} does not match SRID {} of fence \"{}\"",
+                point.srid(),
+                srid,
+                name
+            )));
+        }
+        Ok(point_of(point, "ST_InFence")?.is_some_and(|p| self.contains(id, p)))
+    }
+
+    /// Names of the fences a point is in, sorted
+    pub fn classify(&self, point: &Geometry) -> Result<Vec<&'a str>, RostGisError> {
+        let Some(p) = point_of(point, "rostgis_classify_fences")? else {
+            return Ok(Vec::new());
+        };
+        let mut ids: Vec<usize> = self
+            .candidates(p)
+            .into_iter()
+            .filter(|&id| self.entries[id].srid == point.srid() && self.contains(id, p))
+            .collect();
+        ids.sort_unstable();
+        Ok(ids
+            .into_iter()
+            .map(|id| self.name(&self.entries[id]))
+            .collect())
+    }
+}
+
+/// Registered fences packed by a backend
+pub struct FenceIndex {
+    entries: Vec<FenceEntry>,
+    names: Vec<u8>,
+    rings: Vec<u32>,
+    vertices: Vec<[f64; 2]>,
+    boxes: Vec<[f64; 4]>,
+    children: Vec<u32>,
+    levels: Vec<u32>,
+}
+
+impl FenceIndex {
+    /// Pack named fences, which must be polygons or multipolygons
+    pub fn new(fences: impl IntoIterator<Item = (String, Geometry)>) -> Result<Self, RostGisError> {
+        let mut named = Vec::new();
+        for (name, fence) in fences {
+            let srid = fence.srid();
+            let polygons = match fence.into_xy() {
+                Geometry::Polygon(polygon, _) => MultiPolygon(vec![polygon]),
+                Geometry::MultiPolygon(polygons, _) => polygons,
+                other => {
+                    return Err(RostGisError::new(&format!(
+                        "fence \"{}\" must be a polygon, got {}",
+                        name,
+                        other.geometry_type()
+                    )))
+                }
+            };
+            named.push((name, polygons, srid));
+        }
+        named.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut index = FenceIndex {
+            entries: Vec::new(),
+            names: Vec::new(),
+            rings: vec![0],
+            vertices: Vec::new(),
+            boxes: Vec::new(),
+            children: Vec::new(),
+            levels: Vec::new(),
+        };
+        for (name, polygons, srid) in named {
+            let name_start = index.names.len() as u32;
+            index.names.extend_from_slice(name.as_bytes());
+            let rings_start = index.rings.len() as u32 - 1;
+            for polygon in &polygons {
+                for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+                    if ring.0.is_empty() {
+                        continue;
+                    }
+                    index.vertices.extend(ring.coords().map(|c| [c.x, c.y]));
+                    if !ring.is_closed() {
+                        index.vertices.push([ring.0[0].x, ring.0[0].y]);
+                    }
+                    index.rings.push(index.vertices.len() as u32);
+                }
+            }
+            let bbox = polygons.bounding_rect().map_or([f64::NAN; 4], |rect| {
+                [rect.min().x, rect.min().y, rect.max().x, rect.max().y]
+            });
+            index.entries.push(FenceEntry {
+                srid,
+                name: [name_start, index.names.len() as u32],
+                rings: [rings_start, index.rings.len() as u32 - 1],
+                bbox,
+            });
+        }
+        index.pack_tree();
+        Ok(index)
+    }
+
+    /// Pack the bounding boxes of the non-empty fences into the R-tree:
+    /// sorted into vertical strips by x, each strip by y, then grouped
+    /// NODE_SIZE at a time into the nodes of each level up to the root
+    fn pack_tree(&mut self) {
+        let entries = &self.entries;
+        let centre = |id: &u32, axis: usize| {
+            let bbox = entries[*id as usize].bbox;
+            bbox[axis] + bbox[axis + 2]
+        };
+        let mut leaves: Vec<u32> = (0..entries.len() as u32)
+            .filter(|&id| {
+                let rings = entries[id as usize].rings;
+                rings[0] < rings[1]
+            })
+            .collect();
+        if leaves.is_empty() {
+            return;
+        }
+        leaves.sort_by(|a, b| centre(a, 0).total_cmp(&centre(b, 0)));
+        let strips = (leaves.len().div_ceil(NODE_SIZE) as f64).sqrt().ceil() as usize;
+        for strip in leaves.chunks_mut(strips * NODE_SIZE) {
+            strip.sort_by(|a, b| centre(a, 1).total_cmp(&centre(b, 1)));
+        }
+        for id in leaves {
+            self.boxes.push(self.entries[id as usize].bbox);
+            self.children.push(id);
+        }
+        self.levels.push(self.boxes.len() as u32);
+
+        let mut start = 0;
+        while self.boxes.len() - start > 1 {
+            let end = self.boxes.len();
+            for first in (start..end).step_by(NODE_SIZE) {
+                let last = (first + NODE_SIZE).min(end);
+                let bbox = self.boxes[first..last].iter().fold(
+                    [
+                        f64::INFINITY,
+                        f64::INFINITY,
+                        f64::NEG_INFINITY,
+                        f64::NEG_INFINITY,
+                    ],
+                    |b, c| {
+                        [
+                            b[0].min(c[0]),
+                            b[1].min(c[1]),
+                            b[2].max(c[2]),
+                            b[3].max(c[3]),
+                        ]
+                    },
+                );
+                self.boxes.push(bbox);
+                self.children.push(first as u32);
+            }
+            self.levels.push(self.boxes.len() as u32);
+            start = end;
+        }
+    }
+
+    pub fn fences(&self) -> Fences<'_> {
+        Fences {
+            entries: &self.entries,
+            names: &self.names,
+            rings: &self.rings,
+            vertices: &self.vertices,
+            boxes: &self.boxes,
+            children: &self.children,
+            levels: &self.levels,
+        }
+    }
+}
+
+/// The shared copy of the fences: the arrays of a FenceIndex at fixed
+/// capacities, with the database and generation they were loaded at
+#[repr(C)]
+pub struct SharedFences {
+    database: pg_sys::Oid,
+    generation: u64,
+    entry_count: u32,
+    name_bytes: u32,
+    ring_count: u32,
+    vertex_count: u32,
+    box_count: u32,
+    level_count: u32,
+    entries: [FenceEntry; MAX_FENCES],
+    names: [u8; MAX_NAME_BYTES],
+    rings: [u32; MAX_RINGS + 1],
+    vertices: [[f64; 2]; MAX_VERTICES],
+    boxes: [[f64; 4]; MAX_BOXES],
+    children: [u32; MAX_BOXES],
+    levels: [u32; MAX_LEVELS],
+}
+
+impl Default for SharedFences {
+    fn default() -> Self {
+        SharedFences {
+            database: pg_sys::InvalidOid,
+            generation: 0,
+            entry_count: 0,
+            name_bytes: 0,
+            ring_count: 0,
+            vertex_count: 0,
+            box_count: 0,
+            level_count: 0,
+            entries: [FenceEntry::default(); MAX_FENCES],
+            names: [0; MAX_NAME_BYTES],
+            rings: [0; MAX_RINGS + 1],
+            vertices: [[0.0; 2]; MAX_VERTICES],
+            boxes: [[0.0; 4]; MAX_BOXES],
+            children: [0; MAX_BOXES],
+            levels: [0; MAX_LEVELS],
+        }
+    }
+}
+
+unsafe impl PGRXSharedMemory for SharedFences {}
+
+impl SharedFences {
+    fn fences(&self) -> Fences<'_> {
+        Fences {
+            entries: &self.entries[..self.entry_count as usize],
+            names: &self.names[..self.name_bytes as usize],
+            rings: &self.rings[..self.ring_count as usize],
+            vertices: &self.vertices[..self.vertex_count as usize],
+            boxes: &self.boxes[..self.box_count as usize],
+            children: &self.children[..self.box_count as usize],
+            levels: &self.levels[..self.level_count as usize],
+        }
+    }
+
+    /// Copy in a backend's fences, unless they exceed the capacities
+    fn store(&mut self, database: pg_sys::Oid, generation: u64, index: &FenceIndex) -> bool {
+        if index.entries.len() > MAX_FENCES
+            || index.names.len() > MAX_NAME_BYTES
+            || index.rings.len() > MAX_RINGS + 1
+            || index.vertices.len() > MAX_VERTICES
+            || index.boxes.len() > MAX_BOXES
+            || index.levels.len() > MAX_LEVELS
+        {
+            return false;
+        }
+        self.entries[..index.entries.len()].copy_from_slice(&index.entries);
+        self.names[..index.names.len()].copy_from_slice(&index.names);
+        self.rings[..index.rings.len()].copy_from_slice(&index.rings);
+        self.vertices[..index.vertices.len()].copy_from_slice(&index.vertices);
+        self.boxes[..index.boxes.len()].copy_from_slice(&index.boxes);
+        self.children[..index.children.len()].copy_from_slice(&index.children);
+        self.levels[..index.levels.len()].copy_from_slice(&index.levels);
+        self.entry_count = index.entries.len() as u32;
+        self.name_bytes = index.names.len() as u32;
+        self.ring_count = index.rings.len() as u32;
+        self.vertex_count = index.vertices.len() as u32;
+        self.box_count = index.boxes.len() as u32;
+        self.level_count = index.levels.len() as u32;
+        self.database = database;
+        self.generation = generation;
+        true
+    }
+}
+
+/// Generation of the fences, bumped by each commit changing rostgis_fences
+static GENERATION: PgAtomic<AtomicU64> = unsafe { PgAtomic::new(c"rostgis_fences_generation") };
+static SHARED_FENCES: PgLwLock<SharedFences> = unsafe { PgLwLock::new(c"rostgis_fences") };
+
+/// Whether the shared copy was set up, i.e. RostGIS is preloaded
+static SHARED_READY: AtomicBool = AtomicBool::new(false);
+
+/// Request the shared copy of the fences; only possible while
+/// shared_preload_libraries is being processed
+pub fn init() {
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        pg_shmem_init!(GENERATION);
+        pg_shmem_init!(SHARED_FENCES);
+        SHARED_READY.store(true, Ordering::Relaxed);
+    }
+}
+
+/// When a backend's own copy of the fences was loaded
+#[derive(Clone, Copy, PartialEq)]
+enum LoadedAt {
+    /// At a generation, so current until the next commit changing them
+    Generation(u64),
+    /// By a statement, with its snapshot
+    Statement(Statement),
+}
+
+thread_local! {
+    static LOCAL_FENCES: RefCell<Option<(LoadedAt, Rc<FenceIndex>)>> = const { RefCell::new(None) };
+    /// Whether the current transaction changed rostgis_fences
+    static CHANGED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The backend's own copy of the fences, if it was loaded at `at`
+fn local_fences(at: LoadedAt) -> Option<Rc<FenceIndex>> {
+    LOCAL_FENCES.with(|local| {
+        local
+            .borrow()
+            .as_ref()
+            .filter(|(loaded_at, _)| *loaded_at == at)
+            .map(|(_, index)| index.clone())
+    })
+}
+
+fn keep_local(at: LoadedAt, index: FenceIndex) -> Rc<FenceIndex> {
+    let index = Rc::new(index);
+    LOCAL_FENCES.with(|local| *local.borrow_mut() = Some((at, index.clone())));
+    index
+}
+
+/// Read and pack the registered fences, with the active snapshot
+fn load_fences() -> Result<FenceIndex, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = extension_schema()?;
+    let fences = Spi::connect(|client| {
+        let rows = client.select(
+            &format!("SELECT fence_name, fence FROM {schema}.rostgis_fences"),
+            None,
+            &[],
+        )?;
+        let mut fences = Vec::new();
+        for row in rows {
+            if let (Some(name), Some(fence)) = (row.get::<String>(1)?, row.get::<Geometry>(2)?) {
+                fences.push((name, fence));
+            }
+        }
+        Ok::<_, pgrx::spi::Error>(fences)
+    })?;
+    Ok(FenceIndex::new(fences)?)
+}
+
+/// Run `f` on the registered fences: the shared copy if it is current,
+/// else the backend's own, loaded again when stale
+fn with_fences<R>(
+    f: impl FnOnce(Fences<'_>) -> Result<R, RostGisError>,
+) -> Result<R, Box<dyn std::error::Error + Send + Sync>> {
+    let repeatable = unsafe { pg_sys::XactIsoLevel } >= pg_sys::XACT_REPEATABLE_READ as i32;
+    if !SHARED_READY.load(Ordering::Relaxed) || repeatable || CHANGED.with(Cell::get) {
+        let at = LoadedAt::Statement(current_statement());
+        let index = match local_fences(at) {
+            Some(index) => index,
+            None => keep_local(at, load_fences()?),
+        };
+        return Ok(f(index.fences())?);
+    }
+
+    let database = unsafe { pg_sys::MyDatabaseId };
+    let generation = GENERATION.get().load(Ordering::Acquire);
+    {
+        let shared = SHARED_FENCES.share();
+        if shared.database == database && shared.generation == generation {
+            return Ok(f(shared.fences())?);
+        }
+    }
+    let at = LoadedAt::Generation(generation);
+    if let Some(index) = local_fences(at) {
+        return Ok(f(index.fences())?);
+    }
+
+    // A snapshot taken after reading the generation sees every change
+    // committed before the generation was bumped
+    let index = unsafe {
+        pg_sys::PushActiveSnapshot(pg_sys::GetLatestSnapshot());
+        let index = load_fences();
+        pg_sys::PopActiveSnapshot();
+        index?
+    };
+    let published = {
+        let mut shared = SHARED_FENCES.exclusive();
+        // Fences of another database are only replaced once stale
+        let current = shared.database != pg_sys::InvalidOid
+            && shared.generation == GENERATION.get().load(Ordering::Acquire);
+        !current && shared.store(database, generation, &index)
+    };
+    if published {
+        LOCAL_FENCES.with(|local| local.borrow_mut().take());
+        Ok(f(index.fences())?)
+    } else {
+        Ok(f(keep_local(at, index).fences())?)
+    }
+}
+
+/// PostgreSQL function, run by the statement trigger of rostgis_fences,
+/// making the current transaction bump the fences' generation once it
+/// commits
+#[pg_extern]
+pub fn rostgis_fences_invalidate() {
+    if CHANGED.with(|changed| changed.replace(true)) {
+        return;
+    }
+    register_xact_callback(PgXactCallbackEvent::PrePrepare, || {
+        error!("cannot PREPARE a transaction that has changed rostgis_fences")
+    });
+    register_xact_callback(PgXactCallbackEvent::Commit, || {
+        if SHARED_READY.load(Ordering::Relaxed) {
+            GENERATION.get().fetch_add(1, Ordering::AcqRel);
+        }
+        CHANGED.with(|changed| changed.set(false));
+    });
+    register_xact_callback(PgXactCallbackEvent::Abort, || {
+        CHANGED.with(|changed| changed.set(false));
+    });
+}
+
+/// PostgreSQL function testing whether a point is in a registered fence,
+/// its boundary included
+#[pg_extern(stable, strict)]
+pub fn st_infence(
+    point: Geometry,
+    fence_name: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    with_fences(|fences| fences.in_fence(&point, fence_name))
+}
+
+/// PostgreSQL function returning the names of the registered fences a
+/// point is in, sorted
+#[pg_extern(stable, strict)]
+pub fn rostgis_classify_fences(
+    point: Geometry,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    with_fences(|fences| {
+        Ok(fences
+            .classify(&point)?
+            .into_iter()
+            .map(str::to_string)
+            .collect())
+    })
+}
+
+/// PostgreSQL function classifying an array of points into the registered
+/// fences: one row per point and fence it is in, `i` being the array
+/// subscript. NULL and empty points are in no fence.
+#[allow(clippy::type_complexity)]
+#[pg_extern(stable, strict, name = "rostgis_classify_fences")]
+pub fn rostgis_classify_fences_array(
+    points: Array<'_, Geometry>,
+) -> Result<
+    TableIterator<'static, (name!(i, i32), name!(fence_name, String))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let rows = with_fences(|fences| {
+        let mut rows = Vec::new();
+        for (i, point) in points.iter().enumerate() {
+            let Some(point) = point else {
+                continue;
+            };
+            for name in fences.classify(&point)? {
+                rows.push((i as i32 + 1, name.to_string()));
+            }
+        }
+        Ok(rows)
+    })?;
+    Ok(TableIterator::new(rows))
+}
+
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.rostgis_fences (
+    fence_name text PRIMARY KEY,
+    fence @extschema@.geometry NOT NULL
+        CHECK (@extschema@.st_geometrytype(fence) IN ('ST_Polygon', 'ST_MultiPolygon'))
+);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.rostgis_fences', '');
+
+-- Copies of the fences go stale once a change to them commits
+CREATE FUNCTION @extschema@.rostgis_fences_changed() RETURNS trigger
+LANGUAGE plpgsql AS $$
+BEGIN
+    PERFORM @extschema@.rostgis_fences_invalidate();
+    RETURN NULL;
+END
+$$;
+CREATE TRIGGER rostgis_fences_changed
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON @extschema@.rostgis_fences
+    FOR EACH STATEMENT EXECUTE FUNCTION @extschema@.rostgis_fences_changed();
+
+-- The shared copy may hold the fences of a dropped installation
+SELECT @extschema@.rostgis_fences_invalidate();
+"#,
+    name = "fences",
+    requires = [
+        Geometry,
+        "geometry_header_accessors",
+        rostgis_fences_invalidate
+    ],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn fences() -> FenceIndex {
+        let wkt = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        FenceIndex::new(vec![
+            (
+                "yard".to_string(),
+                wkt("POLYGON((5 5, 20 5, 20 20, 5 20, 5 5))"),
+            ),
+            (
+                "depot".to_string(),
+                wkt(
+                    "MULTIPOLYGON(((0 0, 10 0, 10 10, 0 10, 0 0)), ((30 30, 31 30, 31 31, 30 30)))",
+                ),
+            ),
+            ("closed".to_string(), wkt("POLYGON EMPTY")),
+            (
+                "wgs84".to_string(),
+                wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").with_srid(4326),
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_in_fence() {
+        let index = fences();
+        let fences = index.fences();
+        assert!(fences.in_fence(&make_point(1.0, 1.0), "depot").unwrap());
+        assert!(!fences.in_fence(&make_point(15.0, 15.0), "depot").unwrap());
+        // The boundary is in the fence
+        assert!(fences.in_fence(&make_point(10.0, 5.0), "depot").unwrap());
+        assert!(!fences.in_fence(&make_point(1.0, 1.0), "closed").unwrap());
+        assert!(fences.in_fence(&make_point(1.0, 1.0), "nowhere").is_err());
+        assert!(fences.in_fence(&make_point(1.0, 1.0), "wgs84").is_err());
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(fences.in_fence(&line, "depot").is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let index = fences();
+        let fences = index.fences();
+        assert_eq!(
+            fences.classify(&make_point(7.0, 7.0)).unwrap(),
+            vec!["depot", "yard"]
+        );
+        assert_eq!(
+            fences.classify(&make_point(30.5, 30.2)).unwrap(),
+            vec!["depot"]
+        );
+        assert!(fences.classify(&make_point(50.0, 50.0)).unwrap().is_empty());
+        assert_eq!(
+            fences
+                .classify(&make_point(1.0, 1.0).with_srid(4326))
+                .unwrap(),
+            vec!["wgs84"]
+        );
+        assert!(fences
+            .classify(&geometry_from_wkt("POINT EMPTY").unwrap())
+            .unwrap()
+            .is_empty());
+        assert!(FenceIndex::new(vec![(
+            "line".to_string(),
+            geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap()
+        )])
+        .is_err());
+    }
+
+    #[test]
+    fn test_holes_and_many_fences() {
+        let wkt = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let mut named = vec![(
+            "ring".to_string(),
+            wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (2 2, 8 2, 8 8, 2 8, 2 2))"),
+        )];
+        // Enough fences for an R-tree of four levels
+        for i in 0..300 {
+            let (x, y) = ((i % 20) as f64 * 3.0, (i / 20) as f64 * 3.0);
+            let square = format!(
+                "POLYGON(({x} {y}, {} {y}, {} {}, {x} {}, {x} {y}))",
+                x + 4.0,
+                x + 4.0,
+                y + 4.0,
+                y + 4.0
+            );
+            named.push((format!("f{:03}", i), wkt(&square)));
+        }
+        let index = FenceIndex::new(named.clone()).unwrap();
+        assert_eq!(index.levels.len(), 4);
+        let fences = index.fences();
+        assert!(!fences.in_fence(&make_point(5.0, 5.0), "ring").unwrap());
+        assert!(fences.in_fence(&make_point(2.0, 5.0), "ring").unwrap());
+        assert!(fences.in_fence(&make_point(1.0, 5.0), "ring").unwrap());
+
+        for (x, y) in [
+            (5.0, 5.0),
+            (1.0, 5.0),
+            (3.0, 3.0),
+            (31.5, 17.0),
+            (58.0, 45.0),
+        ] {
+            let point = make_point(x, y);
+            let mut expected: Vec<&str> = named
+                .iter()
+                .filter(|(_, fence)| crate::functions::geometries_intersect(fence, &point))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(fences.classify(&point).unwrap(), expected, "({x} {y})");
+        }
+    }
+
+    #[test]
+    fn test_shared_copy() {
+        // The shared copy is too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let index = fences();
+                let mut shared = Box::<SharedFences>::default();
+                assert!(shared.store(pg_sys::InvalidOid, 7, &index));
+                assert_eq!(shared.generation, 7);
+                assert_eq!(
+                    shared.fences().classify(&make_point(7.0, 7.0)).unwrap(),
+                    vec!["depot", "yard"]
+                );
+                assert!(shared
+                    .fences()
+                    .in_fence(&make_point(10.0, 5.0), "depot")
+                    .unwrap());
+
+                let squares = (0..MAX_FENCES + 1).map(|i| {
+                    let square = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+                    (i.to_string(), square)
+                });
+                let too_many = FenceIndex::new(squares).unwrap();
+                assert!(!shared.store(pg_sys::InvalidOid, 8, &too_many));
+                assert_eq!(shared.generation, 7);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
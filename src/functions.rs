@@ -1,90 +1,230 @@
-use crate::geometry::Geometry;
-use geo::Area;
-use geo_types::{LineString, Point, Polygon};
+use crate::geometry::{Geometry, OrdinateCursor};
+use crate::guc::AxisOrder;
+use crate::utils::{format_number, RostGisError};
+use geo::coordinate_position::CoordPos;
+use geo::dimensions::Dimensions;
+use geo::orient::{Direction, Orient};
+use geo::{
+    unary_union, Area, BooleanOps, Distance, Euclidean, InteriorPoint, Intersects, MapCoords,
+    PreparedGeometry, Relate, Validation,
+};
+use geo_types::{
+    Coord, Line, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon,
+};
+use rstar::{RTree, RTreeObject, AABB};
+use std::f64::consts::PI;
+use std::str::FromStr;
 
-/// Create a Point geometry from WKT string
+/// Parse WKT, or EWKT with a leading `SRID=<srid>;`
+///
+/// Every geometry type is supported, including EMPTY forms and nested
+/// collections, with Z and M ordinates.
 pub fn geometry_from_wkt(
     wkt_str: &str,
 ) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
-    // Simple WKT parser for basic geometries
-    let wkt_str = wkt_str.trim().to_uppercase();
-
-    if wkt_str.starts_with("POINT") {
-        // Parse POINT(x y)
-        if let Some(coords_start) = wkt_str.find('(') {
-            if let Some(coords_end) = wkt_str.find(')') {
-                let coords_str = &wkt_str[coords_start + 1..coords_end];
-                let coords: Vec<&str> = coords_str.split_whitespace().collect();
-                if coords.len() >= 2 {
-                    let x: f64 = coords[0].parse().map_err(|_| "Invalid X coordinate")?;
-                    let y: f64 = coords[1].parse().map_err(|_| "Invalid Y coordinate")?;
-                    return Ok(Geometry::Point(Point::new(x, y), 0));
-                }
-            }
+    Ok(parse_ewkt(wkt_str)?)
+}
+
+/// Parse WKT or EWKT
+pub fn parse_ewkt(text: &str) -> Result<Geometry, RostGisError> {
+    let text = text.trim();
+    let (srid, body) = match text.split_once(';') {
+        Some((prefix, body)) if prefix.trim().to_uppercase().starts_with("SRID=") => {
+            let srid = prefix.trim()[5..]
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| RostGisError::new(&format!("Invalid SRID in EWKT: {}", prefix)))?;
+            (srid, body.trim())
         }
-        return Err("Invalid POINT WKT format".into());
-    }
+        _ => (0, text),
+    };
 
-    if wkt_str.starts_with("LINESTRING") {
-        // Parse LINESTRING(x1 y1, x2 y2, ...)
-        if let Some(coords_start) = wkt_str.find('(') {
-            if let Some(coords_end) = wkt_str.find(')') {
-                let coords_str = &wkt_str[coords_start + 1..coords_end];
-                let mut points = Vec::new();
+    let parsed = wkt::Wkt::<f64>::from_str(body)
+        .map_err(|e| RostGisError::new(&format!("Invalid WKT: {}", e)))?;
 
-                for point_str in coords_str.split(',') {
-                    let coords: Vec<&str> = point_str.trim().split_whitespace().collect();
-                    if coords.len() >= 2 {
-                        let x: f64 = coords[0].parse().map_err(|_| "Invalid X coordinate")?;
-                        let y: f64 = coords[1].parse().map_err(|_| "Invalid Y coordinate")?;
-                        points.push((x, y));
-                    }
-                }
+    let mut vertices = Vec::new();
+    wkt_ordinates(&parsed, &mut vertices);
+    let (has_z, has_m) = match wkt_dimension(&parsed) {
+        wkt::types::Dimension::XY => (false, false),
+        wkt::types::Dimension::XYZ => (true, false),
+        wkt::types::Dimension::XYM => (false, true),
+        wkt::types::Dimension::XYZM => (true, true),
+    };
+    let has_z = has_z || vertices.iter().flatten().any(|c| c.z.is_some());
+    let has_m = has_m || vertices.iter().flatten().any(|c| c.m.is_some());
+    // Empty points have no coordinates and take NaN ordinates
+    let ordinate = |present: bool, select: fn(&wkt::types::Coord<f64>) -> Option<f64>| {
+        present
+            .then(|| {
+                vertices
+                    .iter()
+                    .map(|vertex| match vertex {
+                        Some(c) => select(c)
+                            .ok_or_else(|| RostGisError::new("Mixed dimensions in WKT geometry")),
+                        None => Ok(f64::NAN),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()
+    };
+    let z = ordinate(has_z, |c| c.z)?;
+    let m = ordinate(has_m, |c| c.m)?;
 
-                if points.len() >= 2 {
-                    let linestring = LineString::from(points);
-                    return Ok(Geometry::LineString(linestring, 0));
+    geometry_from_wkt_ast(parsed)?
+        .with_srid(srid)
+        .with_ordinates(z, m)
+}
+
+fn wkt_dimension(parsed: &wkt::Wkt<f64>) -> wkt::types::Dimension {
+    match parsed {
+        wkt::Wkt::Point(g) => g.dimension(),
+        wkt::Wkt::LineString(g) => g.dimension(),
+        wkt::Wkt::Polygon(g) => g.dimension(),
+        wkt::Wkt::MultiPoint(g) => g.dimension(),
+        wkt::Wkt::MultiLineString(g) => g.dimension(),
+        wkt::Wkt::MultiPolygon(g) => g.dimension(),
+        wkt::Wkt::GeometryCollection(g) => g.dimension(),
+    }
+}
+
+/// Collect the coordinates of a parsed WKT geometry in the vertex order of
+/// `geometry_from_wkt_ast`, None for empty points
+fn wkt_ordinates<'a>(
+    parsed: &'a wkt::Wkt<f64>,
+    vertices: &mut Vec<Option<&'a wkt::types::Coord<f64>>>,
+) {
+    fn line<'a>(
+        line: &'a wkt::types::LineString<f64>,
+        vertices: &mut Vec<Option<&'a wkt::types::Coord<f64>>>,
+    ) {
+        vertices.extend(line.coords().iter().map(Some));
+    }
+    // Polygon::new closes open rings by repeating their first vertex
+    fn polygon<'a>(
+        polygon: &'a wkt::types::Polygon<f64>,
+        vertices: &mut Vec<Option<&'a wkt::types::Coord<f64>>>,
+    ) {
+        for ring in polygon.rings() {
+            line(ring, vertices);
+            if let (Some(first), Some(last)) = (ring.coords().first(), ring.coords().last()) {
+                if (first.x, first.y) != (last.x, last.y) {
+                    vertices.push(Some(first));
                 }
             }
         }
-        return Err("Invalid LINESTRING WKT format".into());
     }
 
-    if wkt_str.starts_with("POLYGON") {
-        // Parse POLYGON((x1 y1, x2 y2, ...))
-        if let Some(coords_start) = wkt_str.find("((") {
-            if let Some(coords_end) = wkt_str.rfind("))") {
-                let coords_str = &wkt_str[coords_start + 2..coords_end];
-                let mut points = Vec::new();
+    match parsed {
+        wkt::Wkt::Point(point) => vertices.push(point.coord()),
+        wkt::Wkt::LineString(linestring) => line(linestring, vertices),
+        wkt::Wkt::Polygon(p) => polygon(p, vertices),
+        wkt::Wkt::MultiPoint(multipoint) => {
+            vertices.extend(multipoint.points().iter().map(|point| point.coord()))
+        }
+        wkt::Wkt::MultiLineString(multilinestring) => multilinestring
+            .line_strings()
+            .iter()
+            .for_each(|linestring| line(linestring, vertices)),
+        wkt::Wkt::MultiPolygon(multipolygon) => multipolygon
+            .polygons()
+            .iter()
+            .for_each(|p| polygon(p, vertices)),
+        wkt::Wkt::GeometryCollection(collection) => collection
+            .geometries()
+            .iter()
+            .for_each(|child| wkt_ordinates(child, vertices)),
+    }
+}
 
-                for point_str in coords_str.split(',') {
-                    let coords: Vec<&str> = point_str.trim().split_whitespace().collect();
-                    if coords.len() >= 2 {
-                        let x: f64 = coords[0].parse().map_err(|_| "Invalid X coordinate")?;
-                        let y: f64 = coords[1].parse().map_err(|_| "Invalid Y coordinate")?;
-                        points.push((x, y));
-                    }
-                }
+fn wkt_line(line: &wkt::types::LineString<f64>) -> LineString<f64> {
+    LineString::from(line.coords().iter().map(|c| (c.x, c.y)).collect::<Vec<_>>())
+}
 
-                if points.len() >= 4 {
-                    let polygon = Polygon::new(LineString::from(points), vec![]);
-                    return Ok(Geometry::Polygon(polygon, 0));
-                }
+fn wkt_polygon(polygon: &wkt::types::Polygon<f64>) -> Polygon<f64> {
+    let mut rings = polygon.rings().iter().map(wkt_line);
+    match rings.next() {
+        Some(exterior) => Polygon::new(exterior, rings.collect()),
+        None => Polygon::new(LineString(vec![]), vec![]),
+    }
+}
+
+/// An empty point is stored with NaN coordinates, as in WKB
+fn wkt_point(point: &wkt::types::Point<f64>) -> Point<f64> {
+    match point.coord() {
+        Some(c) => Point::new(c.x, c.y),
+        None => Point::new(f64::NAN, f64::NAN),
+    }
+}
+
+fn geometry_from_wkt_ast(parsed: wkt::Wkt<f64>) -> Result<Geometry, RostGisError> {
+    Ok(match parsed {
+        wkt::Wkt::Point(point) => Geometry::Point(wkt_point(&point), 0),
+        wkt::Wkt::LineString(line) => {
+            let line = wkt_line(&line);
+            if line.0.len() == 1 {
+                return Err(RostGisError::new("A LineString requires at least 2 points"));
             }
+            Geometry::LineString(line, 0)
         }
-        return Err("Invalid POLYGON WKT format".into());
-    }
+        wkt::Wkt::Polygon(polygon) => {
+            let polygon = wkt_polygon(&polygon);
+            let exterior = polygon.exterior();
+            if !exterior.0.is_empty() && exterior.0.len() < 4 {
+                return Err(RostGisError::new(
+                    "A Polygon ring requires at least 4 points",
+                ));
+            }
+            Geometry::Polygon(polygon, 0)
+        }
+        wkt::Wkt::MultiPoint(multipoint) => Geometry::MultiPoint(
+            geo_types::MultiPoint(multipoint.points().iter().map(wkt_point).collect()),
+            0,
+        ),
+        wkt::Wkt::MultiLineString(multilinestring) => Geometry::MultiLineString(
+            geo_types::MultiLineString(
+                multilinestring
+                    .line_strings()
+                    .iter()
+                    .map(wkt_line)
+                    .collect(),
+            ),
+            0,
+        ),
+        wkt::Wkt::MultiPolygon(multipolygon) => Geometry::MultiPolygon(
+            geo_types::MultiPolygon(multipolygon.polygons().iter().map(wkt_polygon).collect()),
+            0,
+        ),
+        wkt::Wkt::GeometryCollection(collection) => Geometry::GeometryCollection(
+            collection
+                .geometries()
+                .iter()
+                .cloned()
+                .map(geometry_from_wkt_ast)
+                .collect::<Result<_, _>>()?,
+            0,
+        ),
+    })
+}
 
-    Err("Unsupported geometry type".into())
+/// Whether a geometry survives its canonical text form unchanged
+///
+/// Compares the stored representation, so NaN coordinates of empty points
+/// compare equal.
+pub fn text_roundtrips(geom: &Geometry) -> bool {
+    match Geometry::from_text(&geom.to_ewkt()) {
+        Ok(parsed) => {
+            crate::serialization::serialize(&parsed) == crate::serialization::serialize(geom)
+        }
+        Err(_) => false,
+    }
 }
 
-/// Create a geometry from WKB hex string
+/// Create a geometry from a hex-encoded WKB or EWKB string
 pub fn geometry_from_wkb(
-    _wkb_hex: &str,
+    wkb_hex: &str,
 ) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
-    // For now, return an error as WKB parsing is complex
-    // This would require implementing a full WKB parser
-    Err("WKB parsing not yet implemented".into())
+    let bytes = crate::utils::hex_to_bytes(wkb_hex.trim())?;
+    Ok(crate::ewkb::read_wkb(&bytes)?)
 }
 
 /// Create a Point geometry
@@ -92,11 +232,69 @@ pub fn make_point(x: f64, y: f64) -> Geometry {
     Geometry::Point(Point::new(x, y), 0)
 }
 
-/// Create a 3D Point geometry (Z coordinate stored as metadata for now)
-pub fn make_point_z(x: f64, y: f64, _z: f64) -> Geometry {
-    // For now, just create a 2D point
-    // Full 3D support would require custom geometry types
-    Geometry::Point(Point::new(x, y), 0)
+/// Create a Point geometry with a Z coordinate
+pub fn make_point_z(x: f64, y: f64, z: f64) -> Geometry {
+    Geometry::Zm(
+        Box::new(make_point(x, y)),
+        crate::geometry::Ordinates {
+            z: Some(vec![z]),
+            m: None,
+        },
+    )
+}
+
+/// Pair up separate X and Y coordinate arrays, checking their lengths match
+/// each other and that of the Z array if any
+fn zip_coordinate_arrays(
+    xs: &[f64],
+    ys: &[f64],
+    zs: Option<&[f64]>,
+) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error + Send + Sync>> {
+    if xs.len() != ys.len() || zs.is_some_and(|zs| zs.len() != xs.len()) {
+        return Err("Coordinate arrays must have the same length".into());
+    }
+    Ok(xs.iter().copied().zip(ys.iter().copied()).collect())
+}
+
+/// Create a LineString geometry from coordinate arrays, with Z if given
+pub fn make_line_from_arrays(
+    xs: &[f64],
+    ys: &[f64],
+    zs: Option<&[f64]>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let points = zip_coordinate_arrays(xs, ys, zs)?;
+    if points.len() < 2 {
+        return Err("A LineString requires at least 2 points".into());
+    }
+    Ok(Geometry::LineString(LineString::from(points), 0)
+        .with_ordinates(zs.map(<[f64]>::to_vec), None)?)
+}
+
+/// Create a Polygon geometry from coordinate arrays, with Z if given,
+/// closing the ring if needed
+pub fn make_polygon_from_arrays(
+    xs: &[f64],
+    ys: &[f64],
+    zs: Option<&[f64]>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let mut points = zip_coordinate_arrays(xs, ys, zs)?;
+    let mut zs = zs.map(<[f64]>::to_vec);
+    if let (Some(first), Some(last)) = (points.first().copied(), points.last().copied()) {
+        let z_open = zs.as_ref().is_some_and(|zs| zs.first() != zs.last());
+        if first != last || z_open {
+            points.push(first);
+            if let Some(zs) = zs.as_mut() {
+                zs.push(zs[0]);
+            }
+        }
+    }
+    if points.len() < 4 {
+        return Err("A Polygon ring requires at least 4 points".into());
+    }
+    Ok(
+        Geometry::Polygon(Polygon::new(LineString::from(points), vec![]), 0)
+            .with_ordinates(zs, None)?,
+    )
 }
 
 /// Convert geometry to WKT string
@@ -104,45 +302,349 @@ pub fn geometry_as_text(geom: Geometry) -> String {
     geom.to_wkt()
 }
 
-/// Convert geometry to WKB hex string
+/// Apply `f` to every coordinate of a geometry, keeping its structure
+///
+/// Empty points are kept as they are rather than going through geo, where
+/// they would become empty multipoints.
+pub fn map_coords_keeping_empty_points(
+    geom: &Geometry,
+    f: &impl Fn(Coord<f64>) -> Coord<f64>,
+) -> Geometry {
+    match geom {
+        Geometry::GeometryCollection(members, srid) => Geometry::GeometryCollection(
+            members
+                .iter()
+                .map(|member| map_coords_keeping_empty_points(member, f))
+                .collect(),
+            *srid,
+        ),
+        Geometry::Point(_, _) if geom.is_empty() => geom.clone(),
+        // The vertices keep their order, and so their Z and M ordinates
+        Geometry::Zm(base, ordinates) => Geometry::Zm(
+            Box::new(map_coords_keeping_empty_points(base, f)),
+            ordinates.clone(),
+        ),
+        _ => Geometry::from_geo(geom.to_geo().map_coords(f), geom.srid()),
+    }
+}
+
+/// Exchange the x and y ordinates of every coordinate
+pub fn swap_axes(geom: &Geometry) -> Geometry {
+    map_coords_keeping_empty_points(geom, &|c| Coord { x: c.y, y: c.x })
+}
+
+/// Convert between the stored x/y order and an exchange axis order; the
+/// conversion is its own inverse
+pub fn with_axis_order(geom: Geometry, axis_order: AxisOrder) -> Geometry {
+    match axis_order {
+        AxisOrder::LonLat => geom,
+        AxisOrder::LatLon => swap_axes(&geom),
+    }
+}
+
+/// Convert geometry to an upper-case hex string of little-endian WKB
 pub fn geometry_as_wkb(geom: Geometry) -> String {
-    // For now, return WKT as WKB is complex to implement
-    // In a full implementation, this would convert to binary WKB format
-    format!("WKB:{}", geom.to_wkt())
+    hex::encode_upper(crate::ewkb::write_wkb(&geom, false))
 }
 
 /// Convert geometry to GeoJSON string
 pub fn geometry_as_geojson(geom: Geometry) -> String {
-    match geom {
+    geometry_as_geojson_with_axis_order(geom, AxisOrder::LonLat, None)
+}
+
+/// Convert geometry to GeoJSON string with an explicit coordinate axis order
+/// and coordinates rounded to at most `precision` decimal places
+///
+/// Empty geometries have no positions, as in PostGIS: `"coordinates":[]`, or
+/// `"geometries":[]` for a collection. Empty points in a multipoint are left
+/// out.
+pub fn geometry_as_geojson_with_axis_order(
+    geom: Geometry,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+) -> String {
+    geojson_geometry(
+        geom.xy(),
+        axis_order,
+        precision,
+        &OrdinateCursor::new(&geom),
+    )
+}
+
+/// GeoJSON of an x/y geometry; positions take the Z of `zs`, M values have
+/// no place in GeoJSON and are left out as in PostGIS
+fn geojson_geometry(
+    geom: &Geometry,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+    zs: &OrdinateCursor,
+) -> String {
+    let coordinates = match geom {
+        Geometry::GeometryCollection(members, _) => {
+            let members: Vec<String> = members
+                .iter()
+                .map(|member| geojson_geometry(member, axis_order, precision, zs))
+                .collect();
+            return format!(
+                r#"{{"type":"GeometryCollection","geometries":[{}]}}"#,
+                members.join(",")
+            );
+        }
+        Geometry::Zm(base, _) => return geojson_geometry(base, axis_order, precision, zs),
         Geometry::Point(point, _) => {
-            format!(
-                r#"{{"type":"Point","coordinates":[{},{}]}}"#,
-                point.x(),
-                point.y()
-            )
+            if geom.is_empty() {
+                zs.skip(1);
+                "[]".to_string()
+            } else {
+                geojson_position(&point.0, axis_order, precision, zs)
+            }
         }
         Geometry::LineString(linestring, _) => {
-            let coords: Vec<String> = linestring
-                .coords()
-                .map(|c| format!("[{},{}]", c.x, c.y))
+            geojson_positions(linestring, axis_order, precision, zs)
+        }
+        Geometry::Polygon(polygon, _) => geojson_rings(polygon, axis_order, precision, zs),
+        Geometry::MultiPoint(multipoint, _) => {
+            let positions: Vec<String> = multipoint
+                .iter()
+                .filter_map(|point| {
+                    if point.x().is_nan() {
+                        zs.skip(1);
+                        return None;
+                    }
+                    Some(geojson_position(&point.0, axis_order, precision, zs))
+                })
                 .collect();
-            format!(
-                r#"{{"type":"LineString","coordinates":[{}]}}"#,
-                coords.join(",")
-            )
+            format!("[{}]", positions.join(","))
         }
-        Geometry::Polygon(polygon, _) => {
-            let exterior: Vec<String> = polygon
-                .exterior()
-                .coords()
-                .map(|c| format!("[{},{}]", c.x, c.y))
+        Geometry::MultiLineString(multilinestring, _) => {
+            let lines: Vec<String> = multilinestring
+                .iter()
+                .map(|linestring| geojson_positions(linestring, axis_order, precision, zs))
                 .collect();
-            format!(
-                r#"{{"type":"Polygon","coordinates":[[{}]]}}"#,
-                exterior.join(",")
-            )
+            format!("[{}]", lines.join(","))
+        }
+        Geometry::MultiPolygon(multipolygon, _) => {
+            let polygons: Vec<String> = multipolygon
+                .iter()
+                .map(|polygon| geojson_rings(polygon, axis_order, precision, zs))
+                .collect();
+            format!("[{}]", polygons.join(","))
+        }
+    };
+    format!(
+        r#"{{"type":"{}","coordinates":{}}}"#,
+        geom.geometry_type().trim_start_matches("ST_"),
+        coordinates
+    )
+}
+
+fn geojson_position(
+    coord: &Coord<f64>,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+    zs: &OrdinateCursor,
+) -> String {
+    let (first, second) = axis_order.apply(coord.x, coord.y);
+    match zs.next().0 {
+        Some(z) => format!(
+            "[{},{},{}]",
+            format_number(first, precision),
+            format_number(second, precision),
+            format_number(z, precision)
+        ),
+        None => format!(
+            "[{},{}]",
+            format_number(first, precision),
+            format_number(second, precision)
+        ),
+    }
+}
+
+fn geojson_positions(
+    linestring: &LineString<f64>,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+    zs: &OrdinateCursor,
+) -> String {
+    let positions: Vec<String> = linestring
+        .coords()
+        .map(|coord| geojson_position(coord, axis_order, precision, zs))
+        .collect();
+    format!("[{}]", positions.join(","))
+}
+
+fn geojson_rings(
+    polygon: &Polygon<f64>,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+    zs: &OrdinateCursor,
+) -> String {
+    if polygon.exterior().0.is_empty() {
+        return "[]".to_string();
+    }
+    let rings: Vec<String> = std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(|ring| geojson_positions(ring, axis_order, precision, zs))
+        .collect();
+    format!("[{}]", rings.join(","))
+}
+
+/// Convert geometry to a GML 3 fragment
+/// The srsName attribute is emitted on the outermost element when the SRID is
+/// set, and srsDimension on every coordinate list when requested;
+/// coordinates are rounded to at most `precision` decimal places. Z values
+/// are written as a third ordinate, M values are left out as in PostGIS.
+pub fn geometry_as_gml(
+    geom: &Geometry,
+    axis_order: AxisOrder,
+    srs_dimension: bool,
+    precision: Option<u32>,
+) -> String {
+    let srs_name = if geom.srid() > 0 {
+        format!(r#" srsName="EPSG:{}""#, geom.srid())
+    } else {
+        String::new()
+    };
+    let writer = GmlWriter {
+        axis_order,
+        srs_dimension,
+        precision,
+        zs: OrdinateCursor::new(geom),
+    };
+    writer.element(geom.xy(), &srs_name)
+}
+
+/// GML output of an x/y geometry, taking the Z of each vertex from `zs`
+struct GmlWriter<'a> {
+    axis_order: AxisOrder,
+    srs_dimension: bool,
+    precision: Option<u32>,
+    zs: OrdinateCursor<'a>,
+}
+
+impl GmlWriter<'_> {
+    fn position(&self, x: f64, y: f64) -> String {
+        let (first, second) = self.axis_order.apply(x, y);
+        let mut position = format!(
+            "{} {}",
+            format_number(first, self.precision),
+            format_number(second, self.precision)
+        );
+        if let Some(z) = self.zs.next().0 {
+            position.push(' ');
+            position.push_str(&format_number(z, self.precision));
+        }
+        position
+    }
+
+    fn dimension(&self) -> &'static str {
+        match (self.srs_dimension, self.zs.has_z()) {
+            (false, _) => "",
+            (true, false) => r#" srsDimension="2""#,
+            (true, true) => r#" srsDimension="3""#,
+        }
+    }
+
+    fn pos_list(&self, linestring: &LineString<f64>) -> String {
+        let positions: Vec<String> = linestring
+            .coords()
+            .map(|c| self.position(c.x, c.y))
+            .collect();
+        format!(
+            "<gml:posList{}>{}</gml:posList>",
+            self.dimension(),
+            positions.join(" ")
+        )
+    }
+
+    fn polygon_body(&self, polygon: &Polygon<f64>) -> String {
+        let mut body = format!(
+            "<gml:exterior><gml:LinearRing>{}</gml:LinearRing></gml:exterior>",
+            self.pos_list(polygon.exterior())
+        );
+        for interior in polygon.interiors() {
+            body.push_str(&format!(
+                "<gml:interior><gml:LinearRing>{}</gml:LinearRing></gml:interior>",
+                self.pos_list(interior)
+            ));
+        }
+        body
+    }
+
+    fn element(&self, geom: &Geometry, srs_name: &str) -> String {
+        match geom {
+            Geometry::Point(point, _) => format!(
+                "<gml:Point{}><gml:pos{}>{}</gml:pos></gml:Point>",
+                srs_name,
+                self.dimension(),
+                self.position(point.x(), point.y())
+            ),
+            Geometry::LineString(linestring, _) => format!(
+                "<gml:LineString{}>{}</gml:LineString>",
+                srs_name,
+                self.pos_list(linestring)
+            ),
+            Geometry::Polygon(polygon, _) => format!(
+                "<gml:Polygon{}>{}</gml:Polygon>",
+                srs_name,
+                self.polygon_body(polygon)
+            ),
+            Geometry::MultiPoint(multipoint, srid) => {
+                let members: String = multipoint
+                    .iter()
+                    .map(|p| {
+                        format!(
+                            "<gml:pointMember>{}</gml:pointMember>",
+                            self.element(&Geometry::Point(*p, *srid), "")
+                        )
+                    })
+                    .collect();
+                format!("<gml:MultiPoint{}>{}</gml:MultiPoint>", srs_name, members)
+            }
+            Geometry::MultiLineString(multilinestring, _) => {
+                let members: String = multilinestring
+                    .iter()
+                    .map(|ls| {
+                        format!(
+                            "<gml:curveMember><gml:LineString>{}</gml:LineString></gml:curveMember>",
+                            self.pos_list(ls)
+                        )
+                    })
+                    .collect();
+                format!("<gml:MultiCurve{}>{}</gml:MultiCurve>", srs_name, members)
+            }
+            Geometry::MultiPolygon(multipolygon, _) => {
+                let members: String = multipolygon
+                    .iter()
+                    .map(|p| {
+                        format!(
+                            "<gml:surfaceMember><gml:Polygon>{}</gml:Polygon></gml:surfaceMember>",
+                            self.polygon_body(p)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "<gml:MultiSurface{}>{}</gml:MultiSurface>",
+                    srs_name, members
+                )
+            }
+            Geometry::GeometryCollection(geometries, _) => {
+                let members: String = geometries
+                    .iter()
+                    .map(|g| {
+                        format!(
+                            "<gml:geometryMember>{}</gml:geometryMember>",
+                            self.element(g, "")
+                        )
+                    })
+                    .collect();
+                format!(
+                    "<gml:MultiGeometry{}>{}</gml:MultiGeometry>",
+                    srs_name, members
+                )
+            }
+            Geometry::Zm(base, _) => self.element(base, srs_name),
         }
-        _ => format!(r#"{{"type":"Feature","geometry":null}}"#),
     }
 }
 
@@ -156,11 +658,118 @@ pub fn geometry_y(geom: Geometry) -> Option<f64> {
     geom.y()
 }
 
-/// Get Z coordinate of a geometry (not implemented)
+/// Get Z coordinate of a geometry (for Point types with Z)
 pub fn geometry_z(geom: Geometry) -> Option<f64> {
     geom.z()
 }
 
+/// Get the X coordinates of all vertices
+pub fn geometry_x_coords(geom: Geometry) -> Vec<f64> {
+    geom.coordinates().into_iter().map(|(x, _)| x).collect()
+}
+
+/// Get the Y coordinates of all vertices
+pub fn geometry_y_coords(geom: Geometry) -> Vec<f64> {
+    geom.coordinates().into_iter().map(|(_, y)| y).collect()
+}
+
+/// Get the Z coordinates of all vertices; None for a geometry without Z
+pub fn geometry_z_coords(geom: Geometry) -> Option<Vec<f64>> {
+    geom.ordinates().and_then(|ordinates| ordinates.z.clone())
+}
+
+/// Number of points of a linestring; None for other geometries
+pub fn line_num_points(geom: &Geometry) -> Option<i32> {
+    match geom {
+        Geometry::LineString(line, _) => Some(line.0.len() as i32),
+        _ => None,
+    }
+}
+
+/// Point `n` of a linestring, numbered from 1, with negative numbers
+/// counting back from the last point as in PostGIS; None for other
+/// geometries, 0 or a number past either end
+pub fn line_point_n(geom: &Geometry, n: i32) -> Option<Geometry> {
+    let Geometry::LineString(line, srid) = geom else {
+        return None;
+    };
+    let index = match n {
+        1.. => n as usize - 1,
+        ..=-1 => line.0.len().checked_sub(n.unsigned_abs() as usize)?,
+        0 => return None,
+    };
+    line.0
+        .get(index)
+        .map(|coord| Geometry::Point(Point::from(*coord), *srid))
+}
+
+/// Number of members of a multi-geometry or collection; 1 for other
+/// geometries, or 0 when empty, as in PostGIS
+pub fn num_geometries(geom: &Geometry) -> i32 {
+    let count = match geom {
+        Geometry::MultiPoint(multipoint, _) => multipoint.0.len(),
+        Geometry::MultiLineString(multilinestring, _) => multilinestring.0.len(),
+        Geometry::MultiPolygon(multipolygon, _) => multipolygon.0.len(),
+        Geometry::GeometryCollection(members, _) => members.len(),
+        _ => usize::from(!geom.is_empty()),
+    };
+    count as i32
+}
+
+/// Member `n` of a multi-geometry or collection, numbered from 1, with the
+/// SRID of the whole; other geometries are their own first member. None for
+/// a number out of range
+pub fn geometry_n(geom: &Geometry, n: i32) -> Option<Geometry> {
+    let index = usize::try_from(n).ok()?.checked_sub(1)?;
+    match geom {
+        Geometry::MultiPoint(multipoint, srid) => multipoint
+            .0
+            .get(index)
+            .map(|point| Geometry::Point(*point, *srid)),
+        Geometry::MultiLineString(multilinestring, srid) => multilinestring
+            .0
+            .get(index)
+            .map(|line| Geometry::LineString(line.clone(), *srid)),
+        Geometry::MultiPolygon(multipolygon, srid) => multipolygon
+            .0
+            .get(index)
+            .map(|polygon| Geometry::Polygon(polygon.clone(), *srid)),
+        Geometry::GeometryCollection(members, srid) => members
+            .get(index)
+            .map(|member| member.clone().with_srid(*srid)),
+        _ => (index == 0 && !geom.is_empty()).then(|| geom.clone()),
+    }
+}
+
+/// Exterior ring of a polygon, as a linestring; None for other geometries
+pub fn exterior_ring(geom: &Geometry) -> Option<Geometry> {
+    let Geometry::Polygon(polygon, srid) = geom else {
+        return None;
+    };
+    Some(Geometry::LineString(polygon.exterior().clone(), *srid))
+}
+
+/// Number of holes of a polygon; None for other geometries
+pub fn num_interior_rings(geom: &Geometry) -> Option<i32> {
+    match geom {
+        Geometry::Polygon(polygon, _) => Some(polygon.interiors().len() as i32),
+        _ => None,
+    }
+}
+
+/// Interior ring `n` of a polygon, numbered from 1, as a linestring; None
+/// for other geometries or a number out of range
+pub fn interior_ring_n(geom: &Geometry, n: i32) -> Option<Geometry> {
+    let Geometry::Polygon(polygon, srid) = geom else {
+        return None;
+    };
+    let index = usize::try_from(n).ok()?.checked_sub(1)?;
+    polygon
+        .interiors()
+        .get(index)
+        .map(|ring| Geometry::LineString(ring.clone(), *srid))
+}
+
 /// Get geometry type as string
 pub fn geometry_type(geom: Geometry) -> String {
     geom.geometry_type().to_string()
@@ -176,21 +785,581 @@ pub fn set_geometry_srid(geom: Geometry, srid: i32) -> Geometry {
     geom.with_srid(srid)
 }
 
-/// Check if two geometries are equal
+/// Check if two geometries are equal; like in PostGIS all empty geometries
+/// are equal to each other, whatever their type
 pub fn geometries_equal(geom1: Geometry, geom2: Geometry) -> bool {
-    geom1 == geom2
+    match (geom1.is_empty(), geom2.is_empty()) {
+        (true, true) => true,
+        (false, false) => geom1 == geom2,
+        _ => false,
+    }
+}
+
+/// Exact intersection test: bounding box pre-filter, then point-in-polygon
+/// and segment intersection tests on the actual shapes
+pub fn geometries_intersect(geom1: &Geometry, geom2: &Geometry) -> bool {
+    if !geom1.bbox_overlaps(geom2) {
+        return false;
+    }
+    geom1.to_geo().intersects(&geom2.to_geo())
+}
+
+/// Whether two geometries cross (ST_Crosses): they share some interior
+/// points, of a lower dimension than the higher-dimensional one, and
+/// neither contains the other
+pub fn geometries_cross(geom1: &Geometry, geom2: &Geometry) -> bool {
+    geom1.bbox_overlaps(geom2) && geom1.to_geo().relate(&geom2.to_geo()).is_crosses()
+}
+
+/// DE-9IM intersection matrix of two geometries as a 9-character string
+pub fn geometry_relate(geom1: &Geometry, geom2: &Geometry) -> String {
+    let matrix = geom1.to_geo().relate(&geom2.to_geo());
+    let positions = [CoordPos::Inside, CoordPos::OnBoundary, CoordPos::Outside];
+
+    let mut result = String::with_capacity(9);
+    for a in positions {
+        for b in positions {
+            result.push(match matrix.get(a, b) {
+                Dimensions::Empty => 'F',
+                Dimensions::ZeroDimensional => '0',
+                Dimensions::OneDimensional => '1',
+                Dimensions::TwoDimensional => '2',
+            });
+        }
+    }
+    result
+}
+
+/// Test the DE-9IM relationship of two geometries against a pattern
+pub fn geometry_relate_pattern(
+    geom1: &Geometry,
+    geom2: &Geometry,
+    pattern: &str,
+) -> Result<bool, RostGisError> {
+    relate_match(&geometry_relate(geom1, geom2), pattern)
+}
+
+/// Test a DE-9IM matrix against a pattern
+///
+/// Matrix cells are `F`, `0`, `1` or `2`. Pattern cells are `T` (any
+/// non-empty intersection), `F` (empty), `*` (anything) or a dimension that
+/// must match exactly.
+pub fn relate_match(matrix: &str, pattern: &str) -> Result<bool, RostGisError> {
+    let matrix: Vec<char> = matrix.chars().map(|c| c.to_ascii_uppercase()).collect();
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_uppercase()).collect();
+    if matrix.len() != 9 || pattern.len() != 9 {
+        return Err(RostGisError::new(
+            "DE-9IM matrix and pattern must be 9 characters long",
+        ));
+    }
+
+    let mut matches = true;
+    for (cell, expected) in matrix.iter().zip(pattern.iter()) {
+        if !matches!(cell, 'F' | '0' | '1' | '2') {
+            return Err(RostGisError::new(&format!(
+                "Invalid character '{}' in DE-9IM matrix",
+                cell
+            )));
+        }
+        matches &= match expected {
+            '*' => true,
+            'T' => *cell != 'F',
+            'F' | '0' | '1' | '2' => cell == expected,
+            other => {
+                return Err(RostGisError::new(&format!(
+                    "Invalid character '{}' in DE-9IM pattern",
+                    other
+                )))
+            }
+        };
+    }
+    Ok(matches)
+}
+
+/// Test many candidates for containment in one geometry
+///
+/// The container is prepared (edges indexed) once and reused for every
+/// candidate; candidates outside its bounding box are rejected without an
+/// exact test. NULL candidates give NULL results.
+pub fn contains_any(container: &Geometry, candidates: &[Option<Geometry>]) -> Vec<Option<bool>> {
+    let prepared = PreparedGeometry::from(container.to_geo());
+    candidates
+        .iter()
+        .map(|candidate| {
+            candidate.as_ref().map(|candidate| {
+                container.bbox_contains(candidate)
+                    && prepared.relate(&candidate.to_geo()).is_contains()
+            })
+        })
+        .collect()
+}
+
+/// OGC validity check; empty geometries are valid
+pub fn geometry_is_valid(geom: &Geometry) -> bool {
+    geom.is_empty() || geom.to_geo().is_valid()
+}
+
+/// Repair algorithm of ST_MakeValid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepairMethod {
+    /// Rebuild each polygon from all its rings with the even-odd rule, so
+    /// self-intersecting rings split into their lobes and overlapping holes
+    /// cancel out
+    #[default]
+    Linework,
+    /// Repair shells and holes separately and subtract the holes from the
+    /// shell, so the shell decides what is inside
+    Structure,
+}
+
+/// Options of ST_MakeValid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RepairOptions {
+    pub method: RepairMethod,
+    /// Keep parts that collapse to a lower dimension (zero-area polygons,
+    /// lines without two distinct points) as lines or points instead of
+    /// dropping them. Collapsed lines are always kept by the linework
+    /// method, as in PostGIS.
+    pub keep_collapsed: bool,
+}
+
+impl RepairOptions {
+    /// Parse a parameter string such as 'method=structure keepcollapsed=true'
+    ///
+    /// Parameters are blank separated key=value pairs, as in PostGIS.
+    pub fn parse(params: &str) -> Result<Self, RostGisError> {
+        let mut options = RepairOptions::default();
+        for param in params.split_whitespace() {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                RostGisError::new(&format!("Missing value in repair parameter '{}'", param))
+            })?;
+            let value = value.to_lowercase();
+            let invalid = || {
+                RostGisError::new(&format!(
+                    "Invalid value '{}' for repair parameter '{}'",
+                    value, key
+                ))
+            };
+            match key.to_lowercase().as_str() {
+                "method" => {
+                    options.method = match value.as_str() {
+                        "linework" => RepairMethod::Linework,
+                        "structure" => RepairMethod::Structure,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "keepcollapsed" => options.keep_collapsed = value.parse().map_err(|_| invalid())?,
+                _ => {
+                    return Err(RostGisError::new(&format!(
+                        "Unknown repair parameter '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Repair an invalid geometry with the default options; valid geometries
+/// are returned unchanged
+pub fn make_valid(geom: &Geometry) -> Geometry {
+    make_valid_with(geom, RepairOptions::default())
+}
+
+/// Repair an invalid geometry. The repaired polygons are unioned so
+/// overlapping parts merge. Lines only need repair when they collapse to a
+/// point; other types are returned unchanged.
+pub fn make_valid_with(geom: &Geometry, options: RepairOptions) -> Geometry {
+    if geometry_is_valid(geom) {
+        return geom.clone();
+    }
+    let keep_collapsed_lines = options.keep_collapsed || options.method == RepairMethod::Linework;
+
+    let srid = geom.srid();
+    match geom {
+        Geometry::Polygon(polygon, _) => {
+            repair_polygons(std::slice::from_ref(polygon), options, srid)
+        }
+        Geometry::MultiPolygon(multi_polygon, _) => {
+            repair_polygons(&multi_polygon.0, options, srid)
+        }
+        Geometry::LineString(line, _) => match collapsed_point(line) {
+            Some(point) if keep_collapsed_lines => point.with_srid(srid),
+            Some(_) => Geometry::LineString(LineString::new(Vec::new()), srid),
+            None => geom.clone(),
+        },
+        Geometry::MultiLineString(lines, _) => {
+            let (collapsed, kept): (Vec<_>, Vec<_>) = lines
+                .iter()
+                .partition(|line| collapsed_point(line).is_some());
+            let kept = kept.into_iter().cloned();
+            if !keep_collapsed_lines || collapsed.is_empty() {
+                return Geometry::MultiLineString(MultiLineString(kept.collect()), srid);
+            }
+            let points = collapsed.into_iter().filter_map(collapsed_point);
+            Geometry::GeometryCollection(
+                kept.map(|line| Geometry::LineString(line, srid))
+                    .chain(points.map(|point| point.with_srid(srid)))
+                    .collect(),
+                srid,
+            )
+        }
+        _ => geom.clone(),
+    }
+}
+
+/// Repair polygons, with their collapsed parts when they are kept
+fn repair_polygons(polygons: &[Polygon], options: RepairOptions, srid: i32) -> Geometry {
+    let nothing = MultiPolygon::<f64>::new(Vec::new());
+    let lobes = |ring: &LineString<f64>| Polygon::new(ring.clone(), Vec::new()).union(&nothing);
+
+    let mut areas = Vec::new();
+    let mut collapsed = Vec::new();
+    for polygon in polygons {
+        let repaired = match options.method {
+            RepairMethod::Linework => polygon.union(&nothing),
+            RepairMethod::Structure => {
+                let holes = polygon
+                    .interiors()
+                    .iter()
+                    .fold(nothing.clone(), |holes, ring| holes.union(&lobes(ring)));
+                lobes(polygon.exterior()).difference(&holes)
+            }
+        };
+        if repaired.0.is_empty() {
+            collapsed.extend(collapsed_linework(polygon.exterior()));
+        }
+        areas.extend(repaired.0);
+    }
+    let repaired = unary_union(MultiPolygon(areas).orient(Direction::Default).0.iter());
+
+    let area = match repaired.0.len() {
+        0 => None,
+        1 => Some(Geometry::Polygon(repaired.0[0].clone(), srid)),
+        _ => Some(Geometry::MultiPolygon(repaired, srid)),
+    };
+    let collapsed = collapsed
+        .into_iter()
+        .filter(|_| options.keep_collapsed)
+        .map(|part| part.with_srid(srid));
+    let mut parts: Vec<Geometry> = area.into_iter().chain(collapsed).collect();
+    match parts.len() {
+        0 => Geometry::MultiPolygon(MultiPolygon::new(Vec::new()), srid),
+        1 => parts.remove(0),
+        _ => Geometry::GeometryCollection(parts, srid),
+    }
+}
+
+/// The point a line without two distinct points collapses to, if it does
+fn collapsed_point(line: &LineString<f64>) -> Option<Geometry> {
+    let first = *line.0.first()?;
+    line.0
+        .iter()
+        .all(|&coord| coord == first)
+        .then_some(Geometry::Point(Point(first), 0))
+}
+
+/// What remains of a zero-area ring: its vertices as a line, without
+/// repeated and closing points, or a point if there is only one
+fn collapsed_linework(ring: &LineString<f64>) -> Option<Geometry> {
+    if let Some(point) = collapsed_point(ring) {
+        return Some(point);
+    }
+    let mut coords = ring.0.clone();
+    coords.dedup();
+    if coords.len() > 2 && coords.first() == coords.last() {
+        coords.pop();
+    }
+    (coords.len() >= 2).then_some(Geometry::LineString(LineString(coords), 0))
+}
+
+/// A point guaranteed to lie on the geometry (ST_PointOnSurface)
+///
+/// Unlike a centroid, the point is inside polygons even when they are
+/// concave or have holes, which makes it suitable for label placement. For
+/// collections the highest-dimensional parts are used. Empty geometries give
+/// an empty point.
+pub fn point_on_surface(geom: &Geometry) -> Geometry {
+    let point = if geom.is_empty() {
+        None
+    } else {
+        geom.to_geo().interior_point()
+    };
+    Geometry::Point(point.unwrap_or(Point::new(f64::NAN, f64::NAN)), geom.srid())
+}
+
+/// Combinatorial boundary of a geometry (ST_Boundary), following SQL/MM
+///
+/// Polygons give their rings, as a LineString for a single ring and a
+/// MultiLineString otherwise. Lines give the endpoints that end an odd
+/// number of lines (the "mod 2" rule), so closed lines have none. Points
+/// have an empty boundary. Empty geometries are returned unchanged.
+pub fn boundary(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    if geom.is_empty() {
+        return Ok(geom.clone());
+    }
+    let srid = geom.srid();
+    let rings = |polygons: &[Polygon<f64>]| -> Vec<LineString<f64>> {
+        polygons
+            .iter()
+            .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()))
+            .filter(|ring| !ring.0.is_empty())
+            .cloned()
+            .collect()
+    };
+    Ok(match geom {
+        Geometry::Zm(base, _) => return boundary(base),
+        Geometry::Point(..) | Geometry::MultiPoint(..) => {
+            Geometry::GeometryCollection(Vec::new(), srid)
+        }
+        Geometry::LineString(line, _) => endpoint_boundary(std::slice::from_ref(line), srid),
+        Geometry::MultiLineString(lines, _) => endpoint_boundary(&lines.0, srid),
+        Geometry::Polygon(polygon, _) => {
+            let mut rings = rings(std::slice::from_ref(polygon));
+            match rings.len() {
+                1 => Geometry::LineString(rings.remove(0), srid),
+                _ => Geometry::MultiLineString(MultiLineString(rings), srid),
+            }
+        }
+        Geometry::MultiPolygon(polygons, _) => {
+            Geometry::MultiLineString(MultiLineString(rings(&polygons.0)), srid)
+        }
+        Geometry::GeometryCollection(..) => {
+            return Err(RostGisError::new(
+                "ST_Boundary does not support GeometryCollection",
+            ))
+        }
+    })
+}
+
+/// Endpoints shared by an odd number of lines, in order of appearance
+fn endpoint_boundary(lines: &[LineString<f64>], srid: i32) -> Geometry {
+    let mut endpoints: Vec<(Coord<f64>, usize)> = Vec::new();
+    for line in lines.iter().filter(|line| !line.0.is_empty()) {
+        for end in [line.0[0], line.0[line.0.len() - 1]] {
+            match endpoints.iter_mut().find(|(coord, _)| *coord == end) {
+                Some((_, count)) => *count += 1,
+                None => endpoints.push((end, 1)),
+            }
+        }
+    }
+    let points = endpoints
+        .into_iter()
+        .filter(|(_, count)| count % 2 == 1)
+        .map(|(coord, _)| Point(coord))
+        .collect();
+    Geometry::MultiPoint(MultiPoint(points), srid)
+}
+
+/// Drop small parts of a geometry (ST_RemoveSmallParts)
+///
+/// Holes with an area below `min_area` are filled, then polygons with an
+/// area below `min_area` and lines shorter than `min_length` are removed.
+/// Points are kept. A polygon or line that is removed itself becomes empty,
+/// and members of a collection that end up empty are left out, so slivers
+/// left over by an overlay can be cleaned up in one call.
+pub fn remove_small_parts(geom: &Geometry, min_area: f64, min_length: f64) -> Geometry {
+    let polygon = |polygon: &Polygon<f64>| -> Option<Polygon<f64>> {
+        let ring_area = |ring: &LineString<f64>| Polygon::new(ring.clone(), vec![]).unsigned_area();
+        let holes = polygon
+            .interiors()
+            .iter()
+            .filter(|ring| ring_area(ring) >= min_area)
+            .cloned()
+            .collect();
+        let kept = Polygon::new(polygon.exterior().clone(), holes);
+        (!kept.exterior().0.is_empty() && kept.unsigned_area() >= min_area).then_some(kept)
+    };
+    let line = |line: &LineString<f64>| -> Option<LineString<f64>> {
+        let length: f64 = line.lines().map(|l| l.delta().x.hypot(l.delta().y)).sum();
+        (!line.0.is_empty() && length >= min_length).then(|| line.clone())
+    };
+    let srid = geom.srid();
+    match geom {
+        Geometry::Zm(base, _) => remove_small_parts(base, min_area, min_length),
+        Geometry::Point(..) | Geometry::MultiPoint(..) => geom.clone(),
+        Geometry::LineString(l, _) => {
+            Geometry::LineString(line(l).unwrap_or(LineString(vec![])), srid)
+        }
+        Geometry::MultiLineString(lines, _) => Geometry::MultiLineString(
+            MultiLineString(lines.iter().filter_map(line).collect()),
+            srid,
+        ),
+        Geometry::Polygon(p, _) => Geometry::Polygon(
+            polygon(p).unwrap_or_else(|| Polygon::new(LineString(vec![]), vec![])),
+            srid,
+        ),
+        Geometry::MultiPolygon(polygons, _) => Geometry::MultiPolygon(
+            MultiPolygon(polygons.iter().filter_map(polygon).collect()),
+            srid,
+        ),
+        Geometry::GeometryCollection(members, _) => Geometry::GeometryCollection(
+            members
+                .iter()
+                .map(|member| remove_small_parts(member, min_area, min_length))
+                .filter(|member| !member.is_empty())
+                .collect(),
+            srid,
+        ),
+    }
 }
 
-/// Calculate distance between two geometries
+/// Move vertices of a geometry in one pass (ST_UpdateVertices)
+///
+/// Each edit gives a vertex number and its new position. Vertices are
+/// numbered from 1 in storage order, the order ST_NPoints counts them in,
+/// and negative numbers count back from the last vertex. The first and last
+/// vertex of a polygon ring move together so the ring stays closed. When a
+/// vertex is edited more than once the last edit wins.
+pub fn update_vertices(
+    geom: &Geometry,
+    edits: &[(i32, Coord<f64>)],
+) -> Result<Geometry, RostGisError> {
+    let mut updated = geom.clone();
+    let mut count = 0;
+    visit_coord_sequences(&mut updated, &mut |coords, _| count += coords.len());
+
+    // The position of every vertex's winning edit in `edits`
+    let mut winner: Vec<Option<usize>> = vec![None; count];
+    for (order, &(vertex, _)) in edits.iter().enumerate() {
+        let index = match vertex {
+            1.. => vertex as usize - 1,
+            ..=-1 => count.wrapping_sub(vertex.unsigned_abs() as usize),
+            0 => usize::MAX,
+        };
+        let slot = winner.get_mut(index).ok_or_else(|| {
+            RostGisError::new(&format!(
+                "Vertex {} out of range, the geometry has {} vertices",
+                vertex, count
+            ))
+        })?;
+        *slot = Some(order);
+    }
+
+    let mut start = 0;
+    visit_coord_sequences(&mut updated, &mut |coords, ring| {
+        let winner = &winner[start..start + coords.len()];
+        for (coord, order) in coords.iter_mut().zip(winner) {
+            if let Some(order) = order {
+                *coord = edits[*order].1;
+            }
+        }
+        if ring && coords.len() > 1 {
+            let last = coords.len() - 1;
+            match winner[0].max(winner[last]) {
+                Some(order) if Some(order) == winner[0] => coords[last] = coords[0],
+                Some(_) => coords[0] = coords[last],
+                None => {}
+            }
+        }
+        start += coords.len();
+    });
+    Ok(updated)
+}
+
+/// Call `f` with every coordinate sequence of a geometry in storage order,
+/// and whether it is a polygon ring; empty points have none
+fn visit_coord_sequences(geom: &mut Geometry, f: &mut dyn FnMut(&mut [Coord<f64>], bool)) {
+    let polygon = |polygon: &mut Polygon<f64>, f: &mut dyn FnMut(&mut [Coord<f64>], bool)| {
+        polygon.exterior_mut(|ring| f(&mut ring.0, true));
+        polygon.interiors_mut(|rings| {
+            for ring in rings {
+                f(&mut ring.0, true);
+            }
+        });
+    };
+    let is_empty = geom.is_empty();
+    match geom {
+        Geometry::Zm(base, _) => visit_coord_sequences(base, f),
+        Geometry::Point(point, _) if !is_empty => f(std::slice::from_mut(&mut point.0), false),
+        Geometry::Point(..) => {}
+        Geometry::LineString(line, _) => f(&mut line.0, false),
+        Geometry::Polygon(p, _) => polygon(p, f),
+        Geometry::MultiPoint(points, _) => {
+            for point in points.iter_mut() {
+                f(std::slice::from_mut(&mut point.0), false);
+            }
+        }
+        Geometry::MultiLineString(lines, _) => {
+            for line in lines.iter_mut() {
+                f(&mut line.0, false);
+            }
+        }
+        Geometry::MultiPolygon(polygons, _) => {
+            for p in polygons.iter_mut() {
+                polygon(p, f);
+            }
+        }
+        Geometry::GeometryCollection(members, _) => {
+            for member in members {
+                visit_coord_sequences(member, f);
+            }
+        }
+    }
+}
+
+/// Calculate distance between two geometries (NaN when either is empty)
 pub fn geometries_distance(geom1: Geometry, geom2: Geometry) -> f64 {
-    match (geom1, geom2) {
-        (Geometry::Point(p1, _), Geometry::Point(p2, _)) => {
-            let dx = p1.x() - p2.x();
-            let dy = p1.y() - p2.y();
-            (dx * dx + dy * dy).sqrt()
+    distance_within(&geom1, &geom2, f64::INFINITY).unwrap_or(f64::NAN)
+}
+
+/// Distance between two geometries if it is at most `max_distance`
+///
+/// None when the geometries are proven to be farther apart, or either is
+/// empty. Geometries whose bounding boxes are too far apart are rejected
+/// without looking at their vertices. Otherwise the segments of the second
+/// geometry go into an R*-tree, and each segment of the first one is only
+/// compared with the segments within the smaller of the bound and the best
+/// distance found so far, which avoids comparing every pair of segments.
+pub fn distance_within(a: &Geometry, b: &Geometry, max_distance: f64) -> Option<f64> {
+    if a.is_empty() || b.is_empty() || max_distance.is_nan() || max_distance < 0.0 {
+        return None;
+    }
+    let (a_box, b_box) = (a.bounding_box(), b.bounding_box());
+    let gap_x = (b_box.0 - a_box.2).max(a_box.0 - b_box.2).max(0.0);
+    let gap_y = (b_box.1 - a_box.3).max(a_box.1 - b_box.3).max(0.0);
+    if gap_x.hypot(gap_y) > max_distance {
+        return None;
+    }
+    if gap_x == 0.0 && gap_y == 0.0 && a.to_geo().intersects(&b.to_geo()) {
+        return Some(0.0);
+    }
+
+    let (a_segments, b_segments) = (segments(a), segments(b));
+    let (near, far) = if a_segments.len() <= b_segments.len() {
+        (a_segments, b_segments)
+    } else {
+        (b_segments, a_segments)
+    };
+    let tree = RTree::bulk_load(far);
+    let mut best = f64::INFINITY;
+    for segment in &near {
+        let bound = best.min(max_distance);
+        let envelope = segment.envelope();
+        let search = AABB::from_corners(
+            Point::new(envelope.lower().x() - bound, envelope.lower().y() - bound),
+            Point::new(envelope.upper().x() + bound, envelope.upper().y() + bound),
+        );
+        for other in tree.locate_in_envelope_intersecting(&search) {
+            best = best.min(Euclidean.distance(segment, other));
         }
-        _ => 0.0, // Simplified for now
     }
+    (best <= max_distance).then_some(best)
+}
+
+/// Segments of a geometry, with points and single-point lines as
+/// zero-length segments
+pub fn segments(geom: &Geometry) -> Vec<Line<f64>> {
+    let mut segments = Vec::new();
+    let mut add = |coords: &[Coord<f64>]| match coords {
+        [] => {}
+        [single] => segments.push(Line::new(*single, *single)),
+        _ => segments.extend(coords.windows(2).map(|pair| Line::new(pair[0], pair[1]))),
+    };
+    let mut geom = geom.clone();
+    visit_coord_sequences(&mut geom, &mut |coords, _| add(coords));
+    segments
 }
 
 /// Calculate area of a geometry
@@ -245,9 +1414,100 @@ pub fn geometry_perimeter(geom: Geometry) -> f64 {
     }
 }
 
+/// Normalize an angle in radians to [0, 2π)
+pub fn normalize_radians(angle: f64) -> f64 {
+    let normalized = angle.rem_euclid(2.0 * PI);
+    // rem_euclid can round up to the modulus for tiny negative inputs
+    if normalized >= 2.0 * PI {
+        0.0
+    } else {
+        normalized
+    }
+}
+
+/// Normalize an angle in degrees to [0, 360)
+pub fn normalize_degrees(angle: f64) -> f64 {
+    let normalized = angle.rem_euclid(360.0);
+    if normalized >= 360.0 {
+        0.0
+    } else {
+        normalized
+    }
+}
+
+/// Coordinates of a non-empty point
+fn point_coords(geom: &Geometry, function: &str) -> Result<Option<(f64, f64)>, RostGisError> {
+    match geom {
+        Geometry::Point(point, _) if !geom.is_empty() => Ok(Some((point.x(), point.y()))),
+        Geometry::Point(..) => Ok(None),
+        _ => Err(RostGisError::new(&format!("{} requires points", function))),
+    }
+}
+
+/// North-based azimuth from one point to another (ST_Azimuth)
+///
+/// The angle is in radians, clockwise from the positive Y axis, in [0, 2π).
+/// Coincident or empty points have no azimuth.
+pub fn azimuth(from: &Geometry, to: &Geometry) -> Result<Option<f64>, RostGisError> {
+    let (Some((x1, y1)), Some((x2, y2))) = (
+        point_coords(from, "ST_Azimuth")?,
+        point_coords(to, "ST_Azimuth")?,
+    ) else {
+        return Ok(None);
+    };
+    if x1 == x2 && y1 == y2 {
+        return Ok(None);
+    }
+    Ok(Some(normalize_radians((x2 - x1).atan2(y2 - y1))))
+}
+
+/// Clockwise angle between two vectors (ST_Angle)
+///
+/// With three points the vectors are P2→P1 and P2→P3, i.e. the angle at P2;
+/// with four they are P1→P2 and P3→P4. The result is in radians in
+/// [0, 2π), and None when a vector has no length.
+pub fn angle(
+    p1: &Geometry,
+    p2: &Geometry,
+    p3: &Geometry,
+    p4: Option<&Geometry>,
+) -> Result<Option<f64>, RostGisError> {
+    let (first, second) = match p4 {
+        Some(p4) => (azimuth(p1, p2)?, azimuth(p3, p4)?),
+        None => (azimuth(p2, p1)?, azimuth(p2, p3)?),
+    };
+    Ok(match (first, second) {
+        (Some(first), Some(second)) => Some(normalize_radians(second - first)),
+        _ => None,
+    })
+}
+
+/// Clockwise angle between two lines, each taken from its start to its end
+/// point
+pub fn angle_between_lines(
+    line1: &Geometry,
+    line2: &Geometry,
+) -> Result<Option<f64>, RostGisError> {
+    let endpoints = |line: &Geometry| match line {
+        Geometry::LineString(ls, srid) => Ok(match (ls.0.first(), ls.0.last()) {
+            (Some(start), Some(end)) => Some((
+                Geometry::Point(Point::from(*start), *srid),
+                Geometry::Point(Point::from(*end), *srid),
+            )),
+            _ => None,
+        }),
+        _ => Err(RostGisError::new("ST_Angle requires points or linestrings")),
+    };
+    match (endpoints(line1)?, endpoints(line2)?) {
+        (Some((a, b)), Some((c, d))) => angle(&a, &b, &c, Some(&d)),
+        _ => Ok(None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use geo::Contains;
 
     #[test]
     fn test_make_point() {
@@ -281,6 +1541,35 @@ mod tests {
         assert!((distance - 5.0).abs() < 1e-10);
     }
 
+    #[test]
+    fn test_distance_within() {
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        let line = geometry_from_wkt("LINESTRING(7 0, 7 10)").unwrap();
+        assert_eq!(distance_within(&square, &line, f64::INFINITY), Some(3.0));
+        assert_eq!(distance_within(&line, &square, 3.0), Some(3.0));
+        assert_eq!(distance_within(&square, &line, 2.9), None);
+        // Rejected on the bounding boxes alone
+        let far = make_point(100.0, 100.0);
+        assert_eq!(distance_within(&square, &far, 10.0), None);
+
+        // Inside the polygon, and on a vertex of the line
+        let inside = make_point(1.0, 1.0);
+        assert_eq!(distance_within(&square, &inside, 0.0), Some(0.0));
+        assert_eq!(
+            distance_within(&line, &make_point(7.0, 10.0), 0.0),
+            Some(0.0)
+        );
+        // Nearest to the middle of a segment
+        let diagonal = geometry_from_wkt("LINESTRING(10 0, 0 10)").unwrap();
+        let distance = distance_within(&diagonal, &make_point(0.0, 0.0), 10.0).unwrap();
+        assert!((distance - 50f64.sqrt()).abs() < 1e-12);
+
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert_eq!(distance_within(&square, &empty, f64::INFINITY), None);
+        assert_eq!(distance_within(&square, &inside, -1.0), None);
+        assert!(geometries_distance(square, empty).is_nan());
+    }
+
     #[test]
     fn test_geometry_as_geojson() {
         let point = make_point(1.0, 2.0);
@@ -288,6 +1577,319 @@ mod tests {
         assert_eq!(geojson, r#"{"type":"Point","coordinates":[1,2]}"#);
     }
 
+    #[test]
+    fn test_writers_roundtrip_every_type() {
+        let samples = [
+            "POINT(1 2)",
+            "LINESTRING(0 0,1 1,2 0)",
+            "POLYGON((0 0,10 0,10 10,0 10,0 0),(2 2,2 4,4 4,2 2))",
+            "MULTIPOINT((0 0),(1.5 -2))",
+            "MULTILINESTRING((0 0,1 1),(2 2,3 3,4 2))",
+            "MULTIPOLYGON(((0 0,1 0,1 1,0 0)),((5 5,9 5,9 9,5 5),(6 6,7 6,7 7,6 6)))",
+            "GEOMETRYCOLLECTION(POINT(1 2),LINESTRING(0 0,1 1),\
+             GEOMETRYCOLLECTION(POLYGON((0 0,1 0,1 1,0 0))))",
+        ];
+        for wkt in samples {
+            let geom = geometry_from_wkt(wkt).unwrap();
+            assert_eq!(geometry_from_wkt(&geom.to_wkt()).unwrap(), geom, "{}", wkt);
+
+            let wkb = crate::utils::hex_to_bytes(&geometry_as_wkb(geom.clone())).unwrap();
+            assert_eq!(crate::ewkb::read_wkb(&wkb).unwrap(), geom, "{}", wkt);
+
+            let geojson: geojson::GeoJson = geometry_as_geojson(geom.clone()).parse().unwrap();
+            let parsed: geo_types::Geometry<f64> = geojson.try_into().unwrap();
+            assert_eq!(parsed, geom.to_geo(), "{}", wkt);
+        }
+
+        let geojson = |wkt: &str| geometry_as_geojson(geometry_from_wkt(wkt).unwrap());
+        assert_eq!(
+            geojson("MULTIPOINT((1 2),(3 4))"),
+            r#"{"type":"MultiPoint","coordinates":[[1,2],[3,4]]}"#
+        );
+        assert_eq!(
+            geojson("GEOMETRYCOLLECTION(POINT(1 2))"),
+            r#"{"type":"GeometryCollection","geometries":[{"type":"Point","coordinates":[1,2]}]}"#
+        );
+        assert_eq!(
+            geojson("MULTIPOLYGON EMPTY"),
+            r#"{"type":"MultiPolygon","coordinates":[]}"#
+        );
+        assert_eq!(
+            geojson("GEOMETRYCOLLECTION EMPTY"),
+            r#"{"type":"GeometryCollection","geometries":[]}"#
+        );
+        assert_eq!(
+            geometry_as_wkb(make_point(1.0, 2.0)),
+            "0101000000000000000000F03F0000000000000040"
+        );
+    }
+
+    #[test]
+    fn test_empty_geometry_semantics() {
+        let empty = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let point = make_point(0.0, 0.0);
+
+        // All empty geometries are equal, and unequal to anything else
+        assert!(geometries_equal(empty("POINT EMPTY"), empty("POINT EMPTY")));
+        assert!(geometries_equal(
+            empty("POLYGON EMPTY"),
+            empty("GEOMETRYCOLLECTION EMPTY")
+        ));
+        assert!(!geometries_equal(empty("POINT EMPTY"), point.clone()));
+
+        // Predicates on empty points are false instead of failing on NaN
+        assert!(!geometries_intersect(&empty("POINT EMPTY"), &point));
+        assert_eq!(geometry_relate(&empty("POINT EMPTY"), &point), "FFFFFF0F2");
+        assert_eq!(
+            contains_any(&point, &[Some(empty("POINT EMPTY"))]),
+            vec![Some(false)]
+        );
+
+        assert_eq!(
+            geometry_as_geojson(empty("POINT EMPTY")),
+            r#"{"type":"Point","coordinates":[]}"#
+        );
+        assert_eq!(
+            geometry_as_geojson(empty("POLYGON EMPTY")),
+            r#"{"type":"Polygon","coordinates":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_make_line_from_arrays() {
+        let line = make_line_from_arrays(&[0.0, 3.0], &[0.0, 4.0], None).unwrap();
+        assert_eq!(geometry_type(line.clone()), "ST_LineString");
+        assert!((geometry_length(line) - 5.0).abs() < 1e-10);
+
+        assert!(make_line_from_arrays(&[0.0, 1.0], &[0.0], None).is_err());
+        assert!(make_line_from_arrays(&[0.0], &[0.0], None).is_err());
+        assert!(make_line_from_arrays(&[0.0, 1.0], &[0.0, 1.0], Some(&[1.0])).is_err());
+        let line = make_line_from_arrays(&[0.0, 1.0], &[0.0, 1.0], Some(&[5.0, 6.0])).unwrap();
+        assert_eq!(line.to_wkt(), "LINESTRING Z (0 0 5,1 1 6)");
+    }
+
+    #[test]
+    fn test_make_polygon_from_arrays() {
+        // The ring is closed automatically
+        let square =
+            make_polygon_from_arrays(&[0.0, 2.0, 2.0, 0.0], &[0.0, 0.0, 2.0, 2.0], None).unwrap();
+        assert_eq!(geometry_type(square.clone()), "ST_Polygon");
+        assert!((geometry_area(square) - 4.0).abs() < 1e-10);
+
+        assert!(make_polygon_from_arrays(&[0.0, 1.0], &[0.0, 1.0], None).is_err());
+        let zs = [1.0, 1.0, 2.0, 2.0];
+        let square =
+            make_polygon_from_arrays(&[0.0, 2.0, 2.0, 0.0], &[0.0, 0.0, 2.0, 2.0], Some(&zs))
+                .unwrap();
+        assert_eq!(
+            square.to_wkt(),
+            "POLYGON Z ((0 0 1,2 0 1,2 2 2,0 2 2,0 0 1))"
+        );
+    }
+
+    #[test]
+    fn test_coordinate_arrays_roundtrip() {
+        let xs = vec![0.0, 1.0, 2.0];
+        let ys = vec![5.0, 6.0, 7.0];
+        let line = make_line_from_arrays(&xs, &ys, None).unwrap();
+
+        assert_eq!(geometry_x_coords(line.clone()), xs);
+        assert_eq!(geometry_y_coords(line.clone()), ys);
+        assert_eq!(geometry_z_coords(line), None);
+
+        let zs = vec![1.0, 2.0, 3.0];
+        let line = make_line_from_arrays(&xs, &ys, Some(&zs)).unwrap();
+        assert_eq!(geometry_z_coords(line), Some(zs));
+    }
+
+    #[test]
+    fn test_remove_small_parts() {
+        let clean = |wkt: &str, min_area: f64, min_length: f64| {
+            remove_small_parts(&geometry_from_wkt(wkt).unwrap(), min_area, min_length).to_wkt()
+        };
+        // A sliver polygon and a pinhole
+        assert_eq!(
+            clean(
+                "MULTIPOLYGON(((0 0, 10 0, 10 10, 0 10, 0 0), (1 1, 1.1 1, 1.1 1.1, 1 1), \
+                 (5 5, 8 5, 8 8, 5 8, 5 5)), ((20 0, 30 0, 30 0.01, 20 0)))",
+                1.0,
+                0.0
+            ),
+            "MULTIPOLYGON(((0 0,10 0,10 10,0 10,0 0),(5 5,8 5,8 8,5 8,5 5)))"
+        );
+        assert_eq!(
+            clean("POLYGON((0 0, 1 0, 1 0.5, 0 0))", 1.0, 0.0),
+            "POLYGON EMPTY"
+        );
+        // Short dangles
+        assert_eq!(
+            clean("MULTILINESTRING((0 0, 10 0), (10 0, 10.5 0))", 0.0, 1.0),
+            "MULTILINESTRING((0 0,10 0))"
+        );
+        assert_eq!(
+            clean(
+                "GEOMETRYCOLLECTION(POINT(1 1), LINESTRING(0 0, 0 0.1), POLYGON((0 0, 4 0, 4 4, 0 0)))",
+                1.0,
+                1.0
+            ),
+            "GEOMETRYCOLLECTION(POINT(1 1),POLYGON((0 0,4 0,4 4,0 0)))"
+        );
+        // Zero thresholds keep everything
+        let wkt = "MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))";
+        assert_eq!(
+            clean(wkt, 0.0, 0.0),
+            geometry_from_wkt(wkt).unwrap().to_wkt()
+        );
+    }
+
+    #[test]
+    fn test_with_axis_order() {
+        let geom = geometry_from_wkt("GEOMETRYCOLLECTION(POINT(13 52), LINESTRING(1 2, 3 4))")
+            .unwrap()
+            .with_srid(4326);
+        let swapped = with_axis_order(geom.clone(), AxisOrder::LatLon);
+        assert_eq!(
+            swapped.to_ewkt(),
+            "SRID=4326;GEOMETRYCOLLECTION(POINT(52 13),LINESTRING(2 1,4 3))"
+        );
+        assert_eq!(with_axis_order(swapped, AxisOrder::LatLon), geom);
+        assert_eq!(with_axis_order(geom.clone(), AxisOrder::LonLat), geom);
+    }
+
+    #[test]
+    fn test_geometry_as_geojson_axis_order() {
+        let point = make_point(1.0, 2.0);
+        let geojson = geometry_as_geojson_with_axis_order(point, AxisOrder::LatLon, None);
+        assert_eq!(geojson, r#"{"type":"Point","coordinates":[2,1]}"#);
+    }
+
+    #[test]
+    fn test_geometry_as_gml() {
+        let point = make_point(1.0, 2.0).with_srid(4326);
+        assert_eq!(
+            geometry_as_gml(&point, AxisOrder::LonLat, true, None),
+            r#"<gml:Point srsName="EPSG:4326"><gml:pos srsDimension="2">1 2</gml:pos></gml:Point>"#
+        );
+        assert_eq!(
+            geometry_as_gml(&point, AxisOrder::LatLon, false, None),
+            r#"<gml:Point srsName="EPSG:4326"><gml:pos>2 1</gml:pos></gml:Point>"#
+        );
+
+        let polygon = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+        assert_eq!(
+            geometry_as_gml(&polygon, AxisOrder::LonLat, false, None),
+            "<gml:Polygon><gml:exterior><gml:LinearRing><gml:posList>0 0 1 0 1 1 0 0</gml:posList></gml:LinearRing></gml:exterior></gml:Polygon>"
+        );
+    }
+
+    #[test]
+    fn test_output_precision() {
+        let line = geometry_from_wkt("LINESTRING(0.123456 -0.0001, 10.5 2)").unwrap();
+        assert_eq!(
+            line.to_wkt_with_precision(Some(3)),
+            "LINESTRING(0.123 0,10.5 2)"
+        );
+        assert_eq!(line.to_wkt(), "LINESTRING(0.123456 -0.0001,10.5 2)");
+        assert_eq!(
+            geometry_as_geojson_with_axis_order(line.clone(), AxisOrder::LonLat, Some(2)),
+            r#"{"type":"LineString","coordinates":[[0.12,0],[10.5,2]]}"#
+        );
+        assert_eq!(
+            geometry_as_gml(&line, AxisOrder::LatLon, false, Some(1)),
+            "<gml:LineString><gml:posList>0 0.1 2 10.5</gml:posList></gml:LineString>"
+        );
+    }
+
+    #[test]
+    fn test_line_accessors() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 2, 3 4)")
+            .unwrap()
+            .with_srid(4326);
+        let point_n = |n: i32| line_point_n(&line, n).map(|point| point.to_ewkt());
+        assert_eq!(line_num_points(&line), Some(3));
+        assert_eq!(point_n(1).as_deref(), Some("SRID=4326;POINT(0 0)"));
+        assert_eq!(point_n(3).as_deref(), Some("SRID=4326;POINT(3 4)"));
+        assert_eq!(point_n(-1), point_n(3));
+        assert_eq!(point_n(-3), point_n(1));
+        for out_of_range in [0, 4, -4] {
+            assert_eq!(point_n(out_of_range), None);
+        }
+
+        let polygon = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+        assert_eq!(line_num_points(&polygon), None);
+        assert_eq!(line_point_n(&polygon, 1), None);
+        let empty = geometry_from_wkt("LINESTRING EMPTY").unwrap();
+        assert_eq!(line_num_points(&empty), Some(0));
+        assert_eq!(line_point_n(&empty, -1), None);
+    }
+
+    #[test]
+    fn test_ring_accessors() {
+        let polygon = geometry_from_wkt(
+            "POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (1 1, 2 1, 2 2, 1 1), (5 5, 6 5, 6 6, 5 5))",
+        )
+        .unwrap()
+        .with_srid(3857);
+        assert_eq!(
+            exterior_ring(&polygon).map(|ring| ring.to_ewkt()),
+            Some("SRID=3857;LINESTRING(0 0,10 0,10 10,0 10,0 0)".to_string())
+        );
+        assert_eq!(num_interior_rings(&polygon), Some(2));
+        assert_eq!(
+            interior_ring_n(&polygon, 2).map(|ring| ring.to_ewkt()),
+            Some("SRID=3857;LINESTRING(5 5,6 5,6 6,5 5)".to_string())
+        );
+        for out_of_range in [0, 3, -1] {
+            assert_eq!(interior_ring_n(&polygon, out_of_range), None);
+        }
+
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert_eq!(
+            exterior_ring(&empty).map(|ring| ring.to_wkt()),
+            Some("LINESTRING EMPTY".to_string())
+        );
+        assert_eq!(num_interior_rings(&empty), Some(0));
+        let multipolygon = geometry_from_wkt("MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)))").unwrap();
+        assert_eq!(exterior_ring(&multipolygon), None);
+        assert_eq!(num_interior_rings(&multipolygon), None);
+        assert_eq!(interior_ring_n(&multipolygon, 1), None);
+    }
+
+    #[test]
+    fn test_collection_accessors() {
+        let multipolygon =
+            geometry_from_wkt("MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))")
+                .unwrap()
+                .with_srid(3857);
+        assert_eq!(num_geometries(&multipolygon), 2);
+        assert_eq!(
+            geometry_n(&multipolygon, 2).map(|member| member.to_ewkt()),
+            Some("SRID=3857;POLYGON((5 5,6 5,6 6,5 5))".to_string())
+        );
+        for out_of_range in [0, 3, -1] {
+            assert_eq!(geometry_n(&multipolygon, out_of_range), None);
+        }
+
+        let collection =
+            geometry_from_wkt("GEOMETRYCOLLECTION(POINT(1 2), LINESTRING(0 0, 1 1))").unwrap();
+        assert_eq!(num_geometries(&collection), 2);
+        assert_eq!(
+            geometry_n(&collection, 2),
+            Some(geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap())
+        );
+
+        // A single geometry is its own only member
+        let point = make_point(1.0, 2.0);
+        assert_eq!(num_geometries(&point), 1);
+        assert_eq!(geometry_n(&point, 1), Some(point.clone()));
+        assert_eq!(geometry_n(&point, 2), None);
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert_eq!(num_geometries(&empty), 0);
+        assert_eq!(geometry_n(&empty, 1), None);
+        let empty = geometry_from_wkt("MULTIPOINT EMPTY").unwrap();
+        assert_eq!(num_geometries(&empty), 0);
+    }
+
     #[test]
     fn test_srid_operations() {
         let point = make_point(1.0, 2.0);
@@ -296,4 +1898,370 @@ mod tests {
         let point_with_srid = set_geometry_srid(point, 4326);
         assert_eq!(geometry_srid(point_with_srid), 4326);
     }
+
+    #[test]
+    fn test_geometries_intersect() {
+        // Diagonal lines whose bounding boxes overlap but which never cross
+        let line1 = geometry_from_wkt("LINESTRING(0 0, 10 10)").unwrap();
+        let line2 = geometry_from_wkt("LINESTRING(6 0, 10 4)").unwrap();
+        assert!(line1.bbox_overlaps(&line2));
+        assert!(!geometries_intersect(&line1, &line2));
+
+        let crossing = geometry_from_wkt("LINESTRING(0 10, 10 0)").unwrap();
+        assert!(geometries_intersect(&line1, &crossing));
+
+        // Point inside the bbox of a concave polygon but outside its shape
+        let concave = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))").unwrap();
+        assert!(!geometries_intersect(&concave, &make_point(5.0, 8.0)));
+        assert!(geometries_intersect(&concave, &make_point(5.0, 1.0)));
+        assert!(geometries_intersect(&make_point(10.0, 5.0), &concave));
+    }
+
+    #[test]
+    fn test_geometry_is_valid() {
+        let square = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        assert!(geometry_is_valid(&square));
+
+        let bowtie = geometry_from_wkt("POLYGON((0 0, 1 1, 1 0, 0 1, 0 0))").unwrap();
+        assert!(!geometry_is_valid(&bowtie));
+    }
+
+    #[test]
+    fn test_make_valid() {
+        let bowtie = geometry_from_wkt("POLYGON((0 0, 2 2, 2 0, 0 2, 0 0))")
+            .unwrap()
+            .with_srid(3857);
+        let repaired = make_valid(&bowtie);
+        assert!(geometry_is_valid(&repaired));
+        assert_eq!(repaired.srid(), 3857);
+        assert!((repaired.to_geo().unsigned_area() - 2.0).abs() < 1e-9);
+
+        // Overlapping parts of a multipolygon are merged
+        let overlapping = geometry_from_wkt(
+            "MULTIPOLYGON(((0 0, 2 0, 2 2, 0 2, 0 0)), ((1 1, 3 1, 3 3, 1 3, 1 1)))",
+        )
+        .unwrap();
+        let merged = make_valid(&overlapping);
+        assert!(geometry_is_valid(&merged));
+        assert_eq!(merged.geometry_type(), "ST_Polygon");
+        assert!((merged.to_geo().unsigned_area() - 7.0).abs() < 1e-9);
+
+        let square = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        assert_eq!(make_valid(&square), square);
+    }
+
+    #[test]
+    fn test_make_valid_options() {
+        // A hole sticking out of its shell, which also overlaps another hole
+        let polygon = geometry_from_wkt(
+            "POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (8 2, 12 2, 12 4, 8 4, 8 2), (1 1, 4 1, 4 4, 1 4, 1 1), (3 3, 6 3, 6 6, 3 6, 3 3))",
+        )
+        .unwrap();
+        assert!(!geometry_is_valid(&polygon));
+        // Linework: the part of the hole outside the shell becomes area and
+        // the overlap of the two holes becomes an island
+        let linework = make_valid(&polygon);
+        assert!(geometry_is_valid(&linework));
+        assert!(
+            (linework.to_geo().unsigned_area() - (100.0 - 4.0 + 4.0 - 17.0 + 1.0)).abs() < 1e-9
+        );
+        // Structure: the shell is kept and all holes are subtracted
+        let structure = RepairOptions::parse("method=structure").unwrap();
+        let repaired = make_valid_with(&polygon, structure);
+        assert!(geometry_is_valid(&repaired));
+        assert!((repaired.to_geo().unsigned_area() - (100.0 - 4.0 - 17.0)).abs() < 1e-9);
+
+        // Collapsed parts are dropped unless asked for
+        let spike =
+            geometry_from_wkt("MULTIPOLYGON(((0 0, 1 0, 1 1, 0 1, 0 0)), ((5 5, 6 6, 5 5)))")
+                .unwrap()
+                .with_srid(4326);
+        assert_eq!(make_valid(&spike).geometry_type(), "ST_Polygon");
+        let keep = RepairOptions::parse("method=structure keepcollapsed=true").unwrap();
+        let kept = make_valid_with(&spike, keep);
+        assert_eq!(kept.srid(), 4326);
+        assert_eq!(
+            kept.to_wkt(),
+            "GEOMETRYCOLLECTION(POLYGON((0 0,1 0,1 1,0 1,0 0)),LINESTRING(5 5,6 6))"
+        );
+
+        // A collapsed line becomes a point, as in PostGIS, unless the
+        // structure method is asked to drop it
+        let stub = geometry_from_wkt("LINESTRING(2 3, 2 3)").unwrap();
+        assert_eq!(make_valid(&stub).to_wkt(), "POINT(2 3)");
+        assert_eq!(make_valid_with(&stub, keep).to_wkt(), "POINT(2 3)");
+        let structure = RepairOptions::parse("method=structure").unwrap();
+        assert_eq!(
+            make_valid_with(&stub, structure).to_wkt(),
+            "LINESTRING EMPTY"
+        );
+        let lines = geometry_from_wkt("MULTILINESTRING((0 0, 1 1), (2 3, 2 3))").unwrap();
+        assert_eq!(
+            make_valid(&lines).to_wkt(),
+            "GEOMETRYCOLLECTION(LINESTRING(0 0,1 1),POINT(2 3))"
+        );
+    }
+
+    #[test]
+    fn test_repair_options_parse() {
+        assert_eq!(RepairOptions::parse("").unwrap(), RepairOptions::default());
+        assert_eq!(
+            RepairOptions::parse("METHOD=Structure  keepcollapsed=false").unwrap(),
+            RepairOptions {
+                method: RepairMethod::Structure,
+                keep_collapsed: false
+            }
+        );
+        assert!(RepairOptions::parse("method=buffer").is_err());
+        assert!(RepairOptions::parse("keepcollapsed=yes").is_err());
+        assert!(RepairOptions::parse("method").is_err());
+        assert!(RepairOptions::parse("tolerance=1").is_err());
+    }
+
+    #[test]
+    fn test_update_vertices() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1, 2 2)")
+            .unwrap()
+            .with_srid(4326);
+        let edits = [
+            (2, Coord { x: 5.0, y: 5.0 }),
+            (-1, Coord { x: 9.0, y: 9.0 }),
+            (2, Coord { x: 1.5, y: 1.0 }),
+        ];
+        let updated = update_vertices(&line, &edits).unwrap();
+        assert_eq!(updated.srid(), 4326);
+        assert_eq!(updated.to_wkt(), "LINESTRING(0 0,1.5 1,9 9)");
+
+        // Vertices are numbered across parts, and rings stay closed
+        let polygons =
+            geometry_from_wkt("MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))")
+                .unwrap();
+        let updated = update_vertices(&polygons, &[(5, Coord { x: 4.0, y: 4.0 })]).unwrap();
+        assert_eq!(
+            updated.to_wkt(),
+            "MULTIPOLYGON(((0 0,1 0,1 1,0 0)),((4 4,6 5,6 6,4 4)))"
+        );
+        let updated = update_vertices(&polygons, &[(-1, Coord { x: 4.0, y: 4.0 })]).unwrap();
+        assert_eq!(
+            updated.to_wkt(),
+            "MULTIPOLYGON(((0 0,1 0,1 1,0 0)),((4 4,6 5,6 6,4 4)))"
+        );
+
+        assert_eq!(update_vertices(&line, &[]).unwrap(), line);
+        assert!(update_vertices(&line, &[(0, Coord { x: 0.0, y: 0.0 })]).is_err());
+        assert!(update_vertices(&line, &[(4, Coord { x: 0.0, y: 0.0 })]).is_err());
+        assert!(update_vertices(&line, &[(-4, Coord { x: 0.0, y: 0.0 })]).is_err());
+    }
+
+    #[test]
+    fn test_point_on_surface() {
+        // The centroid of a U shape lies in its notch
+        let u_shape =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 8 10, 8 2, 2 2, 2 10, 0 10, 0 0))")
+                .unwrap()
+                .with_srid(4326);
+        let point = point_on_surface(&u_shape);
+        assert_eq!(point.srid(), 4326);
+        assert!(u_shape.to_geo().contains(&point.to_geo()));
+
+        let holed =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (1 1, 9 1, 9 9, 1 9, 1 1))")
+                .unwrap();
+        assert!(holed
+            .to_geo()
+            .intersects(&point_on_surface(&holed).to_geo()));
+
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1, 2 0)").unwrap();
+        assert!(line.to_geo().intersects(&point_on_surface(&line).to_geo()));
+
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert_eq!(point_on_surface(&empty).to_wkt(), "POINT EMPTY");
+    }
+
+    #[test]
+    fn test_boundary() {
+        let boundary_wkt = |wkt: &str| boundary(&geometry_from_wkt(wkt).unwrap()).unwrap().to_wkt();
+        assert_eq!(
+            boundary_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))"),
+            "LINESTRING(0 0,1 0,1 1,0 0)"
+        );
+        assert_eq!(
+            boundary_wkt("POLYGON((0 0, 9 0, 9 9, 0 0), (1 1, 2 1, 2 2, 1 1))"),
+            "MULTILINESTRING((0 0,9 0,9 9,0 0),(1 1,2 1,2 2,1 1))"
+        );
+        assert_eq!(
+            boundary_wkt("LINESTRING(1 1, 0 0, -1 1)"),
+            "MULTIPOINT((1 1),(-1 1))"
+        );
+        assert_eq!(
+            boundary_wkt("LINESTRING(0 0, 1 0, 1 1, 0 0)"),
+            "MULTIPOINT EMPTY"
+        );
+        // The shared endpoint ends three lines, an odd number, so is on the
+        // boundary
+        assert_eq!(
+            boundary_wkt("MULTILINESTRING((0 0, 1 0), (1 0, 2 0), (1 0, 1 1))"),
+            "MULTIPOINT((0 0),(1 0),(2 0),(1 1))"
+        );
+        // Here it ends two lines, so is not
+        assert_eq!(
+            boundary_wkt("MULTILINESTRING((0 0, 1 0), (1 0, 2 0))"),
+            "MULTIPOINT((0 0),(2 0))"
+        );
+        assert_eq!(boundary_wkt("POINT(1 2)"), "GEOMETRYCOLLECTION EMPTY");
+
+        let srid = geometry_from_wkt("LINESTRING(0 0, 1 1)")
+            .unwrap()
+            .with_srid(4326);
+        assert_eq!(boundary(&srid).unwrap().srid(), 4326);
+        assert!(boundary(&geometry_from_wkt("GEOMETRYCOLLECTION(POINT(0 0))").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_contains_any() {
+        let concave = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))").unwrap();
+        let candidates = vec![
+            Some(make_point(5.0, 1.0)),
+            Some(make_point(5.0, 8.0)),
+            None,
+            Some(make_point(20.0, 20.0)),
+            Some(geometry_from_wkt("LINESTRING(1 1, 9 1)").unwrap()),
+        ];
+        assert_eq!(
+            contains_any(&concave, &candidates),
+            vec![Some(true), Some(false), None, Some(false), Some(true)]
+        );
+    }
+
+    #[test]
+    fn test_geometry_relate() {
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        let inner = geometry_from_wkt("POLYGON((1 1, 2 1, 2 2, 1 2, 1 1))").unwrap();
+        assert_eq!(geometry_relate(&square, &inner), "212FF1FF2");
+        assert_eq!(geometry_relate(&make_point(0.0, 0.0), &square), "F0FFFF212");
+
+        assert!(geometry_relate_pattern(&square, &inner, "T*****FF*").unwrap());
+        assert!(!geometry_relate_pattern(&inner, &square, "T*****FF*").unwrap());
+        assert!(geometry_relate_pattern(&square, &inner, "T*").is_err());
+    }
+
+    #[test]
+    fn test_relate_match() {
+        assert!(relate_match("212FF1FF2", "T*****FF*").unwrap());
+        assert!(relate_match("212FF1FF2", "2*2ff1ff2").unwrap());
+        assert!(!relate_match("FF2FF1212", "T********").unwrap());
+        assert!(relate_match("212FF1FF2", "T*****FX*").is_err());
+        assert!(relate_match("212FT1FF2", "*********").is_err());
+        assert!(relate_match("212", "T********").is_err());
+    }
+
+    #[test]
+    fn test_wkt_all_types() {
+        let cases = [
+            ("MULTIPOINT((0 0),(1 1))", "ST_MultiPoint"),
+            ("MULTILINESTRING((0 0,1 1),(2 2,3 3))", "ST_MultiLineString"),
+            ("POLYGON((0 0,4 0,4 4,0 0),(1 1,2 1,2 2,1 1))", "ST_Polygon"),
+            (
+                "GEOMETRYCOLLECTION(POINT(1 2),GEOMETRYCOLLECTION(LINESTRING(0 0,1 1)))",
+                "ST_GeometryCollection",
+            ),
+        ];
+        for (wkt, geometry_type) in cases {
+            let geom = geometry_from_wkt(wkt).unwrap();
+            assert_eq!(geom.geometry_type(), geometry_type);
+            assert_eq!(geom.to_wkt(), wkt);
+        }
+
+        assert!(geometry_from_wkt("LINESTRING(0 0)").is_err());
+        assert!(geometry_from_wkt("POLYGON((0 0,1 1))").is_err());
+    }
+
+    #[test]
+    fn test_ewkt_and_empties() {
+        let geom = geometry_from_wkt("SRID=4326;POINT(1.5 -2)").unwrap();
+        assert_eq!(geom.srid(), 4326);
+        assert_eq!(geom.to_ewkt(), "SRID=4326;POINT(1.5 -2)");
+
+        for wkt in [
+            "POINT EMPTY",
+            "LINESTRING EMPTY",
+            "POLYGON EMPTY",
+            "GEOMETRYCOLLECTION EMPTY",
+        ] {
+            let geom = geometry_from_wkt(wkt).unwrap();
+            assert!(geom.is_empty());
+            assert_eq!(geom.to_wkt(), wkt);
+        }
+        assert_eq!(geometry_from_wkt("POINT EMPTY").unwrap().x(), None);
+
+        // Z and M are kept
+        let point = parse_ewkt("POINT Z (1 2 3)").unwrap();
+        assert_eq!(point, make_point_z(1.0, 2.0, 3.0));
+        assert!(point.has_z() && !point.has_m());
+    }
+
+    #[test]
+    fn test_text_roundtrips() {
+        let geoms = [
+            make_point(0.1 + 0.2, 1e-300).with_srid(3857),
+            geometry_from_wkt("POINT EMPTY").unwrap().with_srid(4326),
+            geometry_from_wkt("GEOMETRYCOLLECTION(POINT EMPTY,MULTIPOLYGON(((0 0,1 0,1 1,0 0))))")
+                .unwrap(),
+            geometry_from_wkt("MULTILINESTRING EMPTY").unwrap(),
+        ];
+        for geom in &geoms {
+            assert!(text_roundtrips(geom), "{}", geom.to_ewkt());
+        }
+    }
+
+    #[test]
+    fn test_azimuth() {
+        let origin = make_point(0.0, 0.0);
+        let az = |x, y| azimuth(&origin, &make_point(x, y)).unwrap().unwrap();
+        assert_eq!(az(0.0, 1.0), 0.0);
+        assert!((az(1.0, 0.0) - PI / 2.0).abs() < 1e-12);
+        assert!((az(0.0, -1.0) - PI).abs() < 1e-12);
+        assert!((az(-1.0, 0.0) - 3.0 * PI / 2.0).abs() < 1e-12);
+        assert!((az(-1.0, 1.0).to_degrees() - 315.0).abs() < 1e-9);
+
+        assert_eq!(azimuth(&origin, &origin).unwrap(), None);
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert_eq!(azimuth(&origin, &empty).unwrap(), None);
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(azimuth(&origin, &line).is_err());
+    }
+
+    #[test]
+    fn test_angle() {
+        let p = |x, y| make_point(x, y);
+        // Clockwise from P2->P1 (west) to P2->P3 (north)
+        let right = angle(&p(-1.0, 0.0), &p(0.0, 0.0), &p(0.0, 1.0), None)
+            .unwrap()
+            .unwrap();
+        assert!((right.to_degrees() - 90.0).abs() < 1e-9);
+        let reflex = angle(&p(0.0, 1.0), &p(0.0, 0.0), &p(-1.0, 0.0), None)
+            .unwrap()
+            .unwrap();
+        assert!((reflex.to_degrees() - 270.0).abs() < 1e-9);
+
+        // Vectors east and south
+        let four = angle(&p(0.0, 0.0), &p(1.0, 0.0), &p(5.0, 5.0), Some(&p(5.0, 4.0)))
+            .unwrap()
+            .unwrap();
+        assert!((four.to_degrees() - 90.0).abs() < 1e-9);
+
+        let line1 = geometry_from_wkt("LINESTRING(0 0, 0 1)").unwrap();
+        let line2 = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        let between = angle_between_lines(&line1, &line2).unwrap().unwrap();
+        assert!((between.to_degrees() - 45.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_normalize_angles() {
+        assert_eq!(normalize_degrees(-90.0), 270.0);
+        assert_eq!(normalize_degrees(720.0), 0.0);
+        assert_eq!(normalize_degrees(-1e-20), 0.0);
+        assert!((normalize_radians(-PI / 2.0) - 1.5 * PI).abs() < 1e-12);
+        assert_eq!(normalize_radians(2.0 * PI), 0.0);
+    }
 }
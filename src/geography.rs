@@ -0,0 +1,514 @@
+use crate::functions::parse_ewkt;
+use crate::geometry::{reject_zm, Geometry};
+use crate::utils::{srid, RostGisError};
+use geo::{
+    ChamberlainDuquetteArea, Distance, Geodesic, GeodesicArea, Haversine, InterpolatePoint,
+    Intersects, Length, LinesIter,
+};
+use geo_types::{Line, Point};
+use pgrx::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// PostGIS-compatible Geography type
+/// Coordinates are longitude/latitude in degrees on the WGS84 ellipsoid, and
+/// measurements are returned in meters / square meters.
+#[derive(Debug, Clone, PartialEq, PostgresType, Serialize, Deserialize)]
+#[inoutfuncs]
+pub struct Geography {
+    pub geometry: Geometry,
+}
+
+impl Geography {
+    /// Create a geography from a geometry, validating lon/lat ranges
+    pub fn from_geometry(geom: Geometry) -> Result<Self, RostGisError> {
+        // Distances and areas on the spheroid ignore heights, so Z and M
+        // are refused rather than carried along unused
+        reject_zm(&geom, "the geography type")?;
+        let geom_srid = geom.srid();
+        if geom_srid != srid::UNKNOWN && geom_srid != srid::WGS84 {
+            return Err(RostGisError::new(&format!(
+                "Only lon/lat coordinates (SRID {}) are supported in geography, got SRID {}",
+                srid::WGS84,
+                geom_srid
+            )));
+        }
+
+        // An empty point has NaN coordinates, which are in no range
+        let coordinates = geom.coordinates().into_iter();
+        for (lon, lat) in coordinates.filter(|(lon, lat)| !(lon.is_nan() && lat.is_nan())) {
+            if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+                return Err(RostGisError::new(&format!(
+                    "Coordinate values are out of range [-180 -90, 180 90] for geography: {} {}",
+                    lon, lat
+                )));
+            }
+        }
+
+        Ok(Geography {
+            geometry: geom.with_srid(srid::WGS84),
+        })
+    }
+
+    /// Get the longitude-wrapping bounding box of the geography
+    pub fn bounding_box(&self) -> GeographyBox {
+        GeographyBox::from_coordinates(&self.geometry.coordinates())
+    }
+}
+
+/// Bounding box on the sphere
+/// When `min_lon > max_lon` the box crosses the antimeridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeographyBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl GeographyBox {
+    /// Compute the smallest box covering the coordinates, wrapping around ±180°
+    /// when that gives a narrower longitude range
+    pub fn from_coordinates(coords: &[(f64, f64)]) -> Self {
+        if coords.is_empty() {
+            return GeographyBox {
+                min_lon: 0.0,
+                min_lat: 0.0,
+                max_lon: 0.0,
+                max_lat: 0.0,
+            };
+        }
+
+        let min_lat = coords.iter().map(|c| c.1).fold(f64::INFINITY, f64::min);
+        let max_lat = coords.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max);
+
+        let mut lons: Vec<f64> = coords.iter().map(|c| c.0).collect();
+        lons.sort_by(|a, b| a.total_cmp(b));
+
+        // The covering interval is the complement of the largest gap between
+        // consecutive longitudes, including the gap that wraps around ±180°
+        let mut min_lon = lons[0];
+        let mut max_lon = lons[lons.len() - 1];
+        let mut largest_gap = 360.0 - (max_lon - min_lon);
+        for pair in lons.windows(2) {
+            let gap = pair[1] - pair[0];
+            if gap > largest_gap {
+                largest_gap = gap;
+                min_lon = pair[1];
+                max_lon = pair[0];
+            }
+        }
+
+        GeographyBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        }
+    }
+
+    /// Check if the box crosses the antimeridian
+    pub fn wraps(&self) -> bool {
+        self.min_lon > self.max_lon
+    }
+
+    /// Split the longitude range into non-wrapping intervals
+    fn lon_intervals(&self) -> Vec<(f64, f64)> {
+        if self.wraps() {
+            vec![(self.min_lon, 180.0), (-180.0, self.max_lon)]
+        } else {
+            vec![(self.min_lon, self.max_lon)]
+        }
+    }
+
+    /// Check if two boxes overlap, taking the antimeridian into account
+    pub fn overlaps(&self, other: &GeographyBox) -> bool {
+        if self.max_lat < other.min_lat || other.max_lat < self.min_lat {
+            return false;
+        }
+
+        self.lon_intervals().iter().any(|(a_min, a_max)| {
+            other
+                .lon_intervals()
+                .iter()
+                .any(|(b_min, b_max)| !(a_max < b_min || b_max < a_min))
+        })
+    }
+}
+
+/// Spheroidal distance in meters between two geographies, or `None` when
+/// either is empty
+/// Geographies that intersect, such as a point inside a polygon, are 0 apart;
+/// otherwise the distance is the shortest geodesic from a vertex of one to a
+/// vertex or segment of the other.
+pub fn geography_distance(geog1: &Geography, geog2: &Geography) -> Option<f64> {
+    if geog1.geometry.is_empty() || geog2.geometry.is_empty() {
+        return None;
+    }
+
+    // Intersection is tested on the lon/lat coordinates, which matches the
+    // sphere for the edges of features that do not cross the antimeridian
+    if geog1.geometry.to_geo().intersects(&geog2.geometry.to_geo()) {
+        return Some(0.0);
+    }
+
+    let points1 = vertices(&geog1.geometry);
+    let points2 = vertices(&geog2.geometry);
+    let segments1 = segments(&geog1.geometry);
+    let segments2 = segments(&geog2.geometry);
+
+    let mut min_distance = f64::INFINITY;
+    for point in &points1 {
+        for other in &points2 {
+            min_distance = min_distance.min(Geodesic.distance(*point, *other));
+        }
+        for (start, end) in &segments2 {
+            min_distance = min_distance.min(point_segment_distance(*point, *start, *end));
+        }
+    }
+    for point in &points2 {
+        for (start, end) in &segments1 {
+            min_distance = min_distance.min(point_segment_distance(*point, *start, *end));
+        }
+    }
+
+    Some(min_distance)
+}
+
+/// Vertices of a lon/lat geometry as points
+fn vertices(geom: &Geometry) -> Vec<Point> {
+    geom.coordinates()
+        .into_iter()
+        .map(|(lon, lat)| Point::new(lon, lat))
+        .collect()
+}
+
+/// Segments of the lines and polygon rings of a lon/lat geometry
+fn segments(geom: &Geometry) -> Vec<(Point, Point)> {
+    let lines: Vec<Line> = match geom {
+        Geometry::Zm(base, _) => return segments(base),
+        Geometry::Point(_, _) | Geometry::MultiPoint(_, _) => Vec::new(),
+        Geometry::LineString(linestring, _) => linestring.lines_iter().collect(),
+        Geometry::Polygon(polygon, _) => polygon.lines_iter().collect(),
+        Geometry::MultiLineString(multilinestring, _) => multilinestring.lines_iter().collect(),
+        Geometry::MultiPolygon(multipolygon, _) => multipolygon.lines_iter().collect(),
+        Geometry::GeometryCollection(geometries, _) => {
+            return geometries.iter().flat_map(segments).collect()
+        }
+    };
+    lines
+        .into_iter()
+        .map(|line| (line.start_point(), line.end_point()))
+        .collect()
+}
+
+/// Position along a segment, in meters, to which `point_segment_distance`
+/// narrows down the closest point
+const SEGMENT_TOLERANCE: f64 = 1e-3;
+
+/// Geodesic distance in meters from a point to the geodesic between `start`
+/// and `end`
+/// The distance along a geodesic shorter than half the globe has a single
+/// minimum, which a golden-section search over the segment finds.
+fn point_segment_distance(point: Point, start: Point, end: Point) -> f64 {
+    let distance_at =
+        |ratio: f64| Geodesic.distance(point, Geodesic.point_at_ratio_between(start, end, ratio));
+    let length = Geodesic.distance(start, end);
+    let inverse_golden_ratio = (5f64.sqrt() - 1.0) / 2.0;
+
+    let (mut low, mut high) = (0.0, 1.0);
+    let mut left = high - inverse_golden_ratio * (high - low);
+    let mut right = low + inverse_golden_ratio * (high - low);
+    let (mut left_distance, mut right_distance) = (distance_at(left), distance_at(right));
+    while (high - low) * length > SEGMENT_TOLERANCE {
+        if left_distance < right_distance {
+            high = right;
+            right = left;
+            right_distance = left_distance;
+            left = high - inverse_golden_ratio * (high - low);
+            left_distance = distance_at(left);
+        } else {
+            low = left;
+            left = right;
+            left_distance = right_distance;
+            right = low + inverse_golden_ratio * (high - low);
+            right_distance = distance_at(right);
+        }
+    }
+
+    distance_at((low + high) / 2.0)
+        .min(Geodesic.distance(point, start))
+        .min(Geodesic.distance(point, end))
+}
+
+/// Length in meters of the linear parts of a geography, measured on the
+/// WGS84 spheroid or, when `use_spheroid` is false, on a sphere
+pub fn geography_length(geog: &Geography, use_spheroid: bool) -> f64 {
+    if use_spheroid {
+        spheroid_length(&geog.geometry)
+    } else {
+        sphere_length(&geog.geometry)
+    }
+}
+
+/// Area in square meters of the areal parts of a geography, measured on the
+/// WGS84 spheroid or, when `use_spheroid` is false, on a sphere
+pub fn geography_area(geog: &Geography, use_spheroid: bool) -> f64 {
+    if use_spheroid {
+        spheroid_area(&geog.geometry)
+    } else {
+        sphere_area(&geog.geometry)
+    }
+}
+
+/// Length of a lon/lat geometry measured on the WGS84 ellipsoid
+pub fn spheroid_length(geom: &Geometry) -> f64 {
+    match geom {
+        Geometry::LineString(linestring, _) => Geodesic.length(linestring),
+        Geometry::MultiLineString(multilinestring, _) => Geodesic.length(multilinestring),
+        Geometry::GeometryCollection(geometries, _) => geometries.iter().map(spheroid_length).sum(),
+        _ => 0.0,
+    }
+}
+
+/// Area of a lon/lat geometry measured on the WGS84 ellipsoid
+pub fn spheroid_area(geom: &Geometry) -> f64 {
+    match geom {
+        Geometry::Polygon(polygon, _) => polygon.geodesic_area_unsigned(),
+        Geometry::MultiPolygon(multipolygon, _) => multipolygon.geodesic_area_unsigned(),
+        Geometry::GeometryCollection(geometries, _) => geometries.iter().map(spheroid_area).sum(),
+        _ => 0.0,
+    }
+}
+
+/// Length of a lon/lat geometry measured on a sphere (faster, less accurate)
+pub fn sphere_length(geom: &Geometry) -> f64 {
+    match geom {
+        Geometry::LineString(linestring, _) => Haversine.length(linestring),
+        Geometry::MultiLineString(multilinestring, _) => Haversine.length(multilinestring),
+        Geometry::GeometryCollection(geometries, _) => geometries.iter().map(sphere_length).sum(),
+        _ => 0.0,
+    }
+}
+
+/// Area of a lon/lat geometry measured on a sphere (faster, less accurate)
+pub fn sphere_area(geom: &Geometry) -> f64 {
+    match geom {
+        Geometry::Polygon(polygon, _) => polygon.chamberlain_duquette_unsigned_area(),
+        Geometry::MultiPolygon(multipolygon, _) => {
+            multipolygon.chamberlain_duquette_unsigned_area()
+        }
+        Geometry::GeometryCollection(geometries, _) => geometries.iter().map(sphere_area).sum(),
+        _ => 0.0,
+    }
+}
+
+// ============================================================================
+// POSTGRESQL FUNCTIONS
+// ============================================================================
+
+/// Cast function geometry -> geography
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn geography(geom: Geometry) -> Result<Geography, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Geography::from_geometry(geom)?)
+}
+
+/// Cast function geography -> geometry
+#[pg_extern(immutable, strict, parallel_safe, name = "geometry")]
+pub fn geometry_from_geography(geog: Geography) -> Geometry {
+    geog.geometry
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn st_geogfromtext(wkt: &str) -> Result<Geography, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(Geography::from_geometry(parse_ewkt(wkt)?)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_distance")]
+pub fn st_distance_geography(geog1: Geography, geog2: Geography) -> Option<f64> {
+    geography_distance(&geog1, &geog2)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_length")]
+pub fn st_length_geography(geog: Geography, use_spheroid: default!(bool, true)) -> f64 {
+    geography_length(&geog, use_spheroid)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_area")]
+pub fn st_area_geography(geog: Geography, use_spheroid: default!(bool, true)) -> f64 {
+    geography_area(&geog, use_spheroid)
+}
+
+/// Geodesic length in meters of a lon/lat (SRID 4326) geometry
+#[pg_extern(immutable, strict, parallel_safe, name = "st_length")]
+pub fn st_length_geometry_spheroid(
+    geom: Geometry,
+    use_spheroid: bool,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(geography_length(
+        &Geography::from_geometry(geom)?,
+        use_spheroid,
+    ))
+}
+
+/// Geodesic area in square meters of a lon/lat (SRID 4326) geometry
+#[pg_extern(immutable, strict, parallel_safe, name = "st_area")]
+pub fn st_area_geometry_spheroid(
+    geom: Geometry,
+    use_spheroid: bool,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(geography_area(
+        &Geography::from_geometry(geom)?,
+        use_spheroid,
+    ))
+}
+
+#[pg_extern(stable, strict, parallel_safe, name = "st_astext")]
+pub fn st_astext_geography(
+    geog: Geography,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let precision = crate::guc::output_precision(None)?;
+    Ok(geog.geometry.to_wkt_with_precision(precision))
+}
+
+/// Geography bounding box overlap operator (&&), aware of the antimeridian
+#[pg_operator(immutable, parallel_safe)]
+#[opname(&&)]
+pub fn geography_overlap(left: Geography, right: Geography) -> bool {
+    left.bounding_box().overlaps(&right.bounding_box())
+}
+
+extension_sql!(
+    r#"
+CREATE CAST (@extschema@.geometry AS @extschema@.geography) WITH FUNCTION @extschema@.geography(@extschema@.geometry) AS IMPLICIT;
+CREATE CAST (@extschema@.geography AS @extschema@.geometry) WITH FUNCTION @extschema@.geometry(@extschema@.geography);
+"#,
+    name = "geography_casts",
+    requires = [Geometry, Geography, geography, geometry_from_geography],
+);
+
+/// Input/Output functions for Geography
+impl pgrx::InOutFuncs for Geography {
+    fn input(input: &std::ffi::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        let input_str = input.to_str().expect("Invalid UTF-8 in geography input");
+
+        let geom = match parse_ewkt(input_str) {
+            Ok(geom) => geom,
+            Err(e) => error!("Invalid geography input: {}", e),
+        };
+
+        match Geography::from_geometry(geom) {
+            Ok(geog) => geog,
+            Err(e) => error!("{}", e.message),
+        }
+    }
+
+    fn output(&self, buffer: &mut pgrx::StringInfo) {
+        buffer.push_str(&self.geometry.to_wkt());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point, make_point_z};
+
+    #[test]
+    fn test_geography_validation() {
+        assert!(Geography::from_geometry(make_point(10.0, 50.0)).is_ok());
+        assert!(Geography::from_geometry(make_point(200.0, 50.0)).is_err());
+        assert!(Geography::from_geometry(make_point(10.0, 50.0).with_srid(3857)).is_err());
+        assert!(Geography::from_geometry(make_point_z(10.0, 50.0, 100.0)).is_err());
+
+        let geog = Geography::from_geometry(make_point(10.0, 50.0)).unwrap();
+        assert_eq!(geog.geometry.srid(), 4326);
+    }
+
+    #[test]
+    fn test_geography_distance() {
+        // One degree of longitude along the equator is about 111.32 km
+        let a = Geography::from_geometry(make_point(0.0, 0.0)).unwrap();
+        let b = Geography::from_geometry(make_point(1.0, 0.0)).unwrap();
+        let distance = geography_distance(&a, &b).unwrap();
+        assert!((distance - 111_319.49).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_geography_distance_to_segment() {
+        // The closest point is the middle of the segment, not one of its ends
+        let line = geometry_from_wkt("LINESTRING(0 0, 2 0)").unwrap();
+        let line = Geography::from_geometry(line).unwrap();
+        let point = Geography::from_geometry(make_point(1.0, 0.01)).unwrap();
+        let distance = geography_distance(&point, &line).unwrap();
+        let expected = Geodesic.distance(Point::new(1.0, 0.01), Point::new(1.0, 0.0));
+        assert!((distance - expected).abs() < 0.01);
+        assert_eq!(geography_distance(&line, &point), Some(distance));
+
+        // Two segments closest between an end of one and the middle of the other
+        let other = geometry_from_wkt("LINESTRING(1 0.01, 1 1)").unwrap();
+        let other = Geography::from_geometry(other).unwrap();
+        assert!((geography_distance(&line, &other).unwrap() - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_geography_distance_containment() {
+        let square = geometry_from_wkt("POLYGON((0 0, 2 0, 2 2, 0 2, 0 0))").unwrap();
+        let square = Geography::from_geometry(square).unwrap();
+        let inside = Geography::from_geometry(make_point(1.0, 1.0)).unwrap();
+        assert_eq!(geography_distance(&square, &inside), Some(0.0));
+        assert_eq!(geography_distance(&inside, &square), Some(0.0));
+
+        let outside = Geography::from_geometry(make_point(3.0, 1.0)).unwrap();
+        let distance = geography_distance(&square, &outside).unwrap();
+        assert!((distance - 111_302.6).abs() < 100.0);
+    }
+
+    #[test]
+    fn test_geography_distance_empty() {
+        let empty = Geography::from_geometry(geometry_from_wkt("POINT EMPTY").unwrap()).unwrap();
+        let point = Geography::from_geometry(make_point(1.0, 1.0)).unwrap();
+        assert_eq!(geography_distance(&empty, &point), None);
+        assert_eq!(geography_distance(&point, &empty), None);
+    }
+
+    #[test]
+    fn test_geography_area_and_length() {
+        let square = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        let area = geography_area(&Geography::from_geometry(square).unwrap(), true);
+        assert!((area - 12_308_778_361.0).abs() / area < 1e-3);
+
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 0)").unwrap();
+        let length = geography_length(&Geography::from_geometry(line).unwrap(), true);
+        assert!((length - 111_319.49).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_sphere_measurements_close_to_spheroid() {
+        let square = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        let spheroid = spheroid_area(&square);
+        let sphere = sphere_area(&square);
+        assert!((spheroid - sphere).abs() / spheroid < 0.01);
+
+        let line = geometry_from_wkt("LINESTRING(0 0, 0 1)").unwrap();
+        let spheroid = spheroid_length(&line);
+        let sphere = sphere_length(&line);
+        assert!((spheroid - sphere).abs() / spheroid < 0.01);
+    }
+
+    #[test]
+    fn test_geography_box_wraps_antimeridian() {
+        let bbox = GeographyBox::from_coordinates(&[(179.0, 0.0), (-179.0, 1.0)]);
+        assert!(bbox.wraps());
+        assert_eq!(bbox.min_lon, 179.0);
+        assert_eq!(bbox.max_lon, -179.0);
+
+        let east = GeographyBox::from_coordinates(&[(179.5, 0.5)]);
+        let west = GeographyBox::from_coordinates(&[(-179.5, 0.5)]);
+        let greenwich = GeographyBox::from_coordinates(&[(0.0, 0.5)]);
+        assert!(bbox.overlaps(&east));
+        assert!(bbox.overlaps(&west));
+        assert!(!bbox.overlaps(&greenwich));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::functions::geometry_as_geojson_with_axis_order;
+use crate::geometry::Geometry;
+use crate::guc::{self, AxisOrder};
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use pgrx::JsonB;
+use serde_json::Value;
+
+// GeoJSON feature streams (ST_AsGeoJSONFeature, rostgis_geojsonseq_view)
+//
+// Tools such as tippecanoe read features one per line (GeoJSONSeq, RFC 8142)
+// rather than one FeatureCollection, which a client would have to assemble
+// in memory for a large table. ST_AsGeoJSONFeature writes a row as a
+// Feature on a single line, and rostgis_geojsonseq_view creates a view of a
+// table with one such line per row, which COPY streams without holding the
+// table in memory:
+//
+//   SELECT rostgis_geojsonseq_view('parcels', 'geom', id_column => 'parcel_id');
+//
+//   psql -c "COPY (SELECT feature FROM parcels_geojsonseq) TO STDOUT
+//            WITH (FORMAT csv, QUOTE E'\x01', DELIMITER E'\x02')" \
+//     | tippecanoe -o parcels.mbtiles -P
+//
+// COPY's text format doubles the backslashes of JSON escapes; the csv format
+// with a quote and delimiter that cannot occur in JSON writes the lines
+// unchanged. The properties of a feature are the row's other columns. With
+// record_separator the lines start with the RS character, as RFC 8142
+// writes them; without, they are plain newline-delimited GeoJSON, which
+// tippecanoe, GDAL and most other readers take as well.
+
+/// A GeoJSON Feature on a single line; a missing geometry is written as
+/// null and missing properties as an empty object
+pub fn geojson_feature(
+    geom: Option<&Geometry>,
+    properties: Option<&Value>,
+    id: Option<&Value>,
+    axis_order: AxisOrder,
+    precision: Option<u32>,
+) -> Result<String, RostGisError> {
+    let to_json = |value: &Value| {
+        serde_json::to_string(value)
+            .map_err(|e| RostGisError::new(&format!("Invalid feature JSON: {}", e)))
+    };
+    let geometry = geom.map_or_else(
+        || "null".to_string(),
+        |geom| geometry_as_geojson_with_axis_order(geom.clone(), axis_order, precision),
+    );
+    let properties = match properties {
+        None | Some(Value::Null) => "{}".to_string(),
+        Some(properties @ Value::Object(_)) => to_json(properties)?,
+        Some(_) => {
+            return Err(RostGisError::new(
+                "Feature properties must be a JSON object",
+            ))
+        }
+    };
+    let id = match id {
+        None | Some(Value::Null) => String::new(),
+        Some(id @ (Value::String(_) | Value::Number(_))) => format!(r#","id":{}"#, to_json(id)?),
+        Some(_) => {
+            return Err(RostGisError::new(
+                "Feature id must be a JSON string or number",
+            ))
+        }
+    };
+    Ok(format!(
+        r#"{{"type":"Feature"{},"geometry":{},"properties":{}}}"#,
+        id, geometry, properties
+    ))
+}
+
+/// PostgreSQL function writing a geometry and its properties as a GeoJSON
+/// Feature on one line
+#[pg_extern(stable, parallel_safe)]
+pub fn st_asgeojsonfeature(
+    geom: Option<Geometry>,
+    properties: default!(Option<JsonB>, "NULL"),
+    id: default!(Option<JsonB>, "NULL"),
+    maxdecimaldigits: default!(Option<i32>, "NULL"),
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let axis_order = guc::resolve_axis_order(None, geom.as_ref().map_or(0, Geometry::srid))?;
+    let precision = guc::output_precision(maxdecimaldigits)?;
+    Ok(geojson_feature(
+        geom.as_ref(),
+        properties.as_ref().map(|properties| &properties.0),
+        id.as_ref().map(|id| &id.0),
+        axis_order,
+        precision,
+    )?)
+}
+
+/// Create or replace a view of a table with one GeoJSON Feature per row,
+/// in a text column `feature`, for COPY to stream as GeoJSONSeq
+///
+/// The view is named `<table>_geojsonseq` in the table's schema unless
+/// `view_name` is given. The id of the features is taken from `id_column`
+/// when given; every column but the geometry and id columns becomes a
+/// property. Returns the name of the view.
+#[pg_extern]
+pub fn rostgis_geojsonseq_view(
+    table_name: &str,
+    geom_column: default!(&str, "'geom'"),
+    view_name: default!(Option<&str>, "NULL"),
+    id_column: default!(Option<&str>, "NULL"),
+    maxdecimaldigits: default!(Option<i32>, "NULL"),
+    record_separator: default!(bool, false),
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    // Checked now rather than on the first row read through the view
+    guc::output_precision(maxdecimaldigits)?;
+    let relation = quote_table(table_name)?;
+    let literal = |value: &str| -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(
+            Spi::get_one_with_args::<String>("SELECT quote_literal($1)", &[value.into()])?
+                .unwrap_or_default(),
+        )
+    };
+    let (schema, name) = Spi::get_two_with_args::<String, String>(
+        "SELECT quote_ident(n.nspname), c.relname
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+         WHERE c.oid = $1::regclass",
+        &[table_name.into()],
+    )?;
+    let (schema, name) = schema.zip(name).ok_or("Table not found")?;
+    let view = format!(
+        "{}.{}",
+        schema,
+        quote_ident(view_name.unwrap_or(&format!("{}_geojsonseq", name)))?
+    );
+
+    let mut excluded = vec![literal(geom_column)?];
+    let id = match id_column {
+        Some(id_column) => {
+            excluded.push(literal(id_column)?);
+            format!("to_jsonb(__rostgis_row.{})", quote_ident(id_column)?)
+        }
+        None => "NULL".to_string(),
+    };
+    let precision = maxdecimaldigits.map_or_else(|| "NULL".to_string(), |d| d.to_string());
+    let separator = if record_separator { "E'\\x1e' || " } else { "" };
+    // The view is read with the search_path of whoever copies from it, so
+    // it names the extension's schema
+    let rostgis = extension_schema()?;
+    Spi::run(&format!(
+        "CREATE OR REPLACE VIEW {view} AS
+         SELECT {separator}{rostgis}.st_asgeojsonfeature(
+                    __rostgis_row.{column}, to_jsonb(__rostgis_row) - ARRAY[{excluded}]::text[],
+                    {id}, {precision}) AS feature
+         FROM {relation} __rostgis_row",
+        column = quote_ident(geom_column)?,
+        excluded = excluded.join(", "),
+    ))?;
+    Ok(view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::make_point;
+    use serde_json::json;
+
+    #[test]
+    fn test_geojson_feature() {
+        let point = make_point(1.25, 2.0);
+        let feature = geojson_feature(
+            Some(&point),
+            Some(&json!({"name": "a\nb", "floors": 3})),
+            Some(&json!(7)),
+            AxisOrder::LonLat,
+            None,
+        )
+        .unwrap();
+        assert!(!feature.contains('\n'));
+        let parsed: Value = serde_json::from_str(&feature).unwrap();
+        assert_eq!(
+            parsed,
+            json!({
+                "type": "Feature",
+                "id": 7,
+                "geometry": {"type": "Point", "coordinates": [1.25, 2]},
+                "properties": {"name": "a\nb", "floors": 3}
+            })
+        );
+        assert!(feature.parse::<geojson::Feature>().is_ok());
+
+        // No geometry, properties or id
+        assert_eq!(
+            geojson_feature(None, None, None, AxisOrder::LonLat, Some(1)).unwrap(),
+            r#"{"type":"Feature","geometry":null,"properties":{}}"#
+        );
+        assert_eq!(
+            geojson_feature(Some(&point), None, None, AxisOrder::LonLat, Some(0)).unwrap(),
+            r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[1,2]},"properties":{}}"#
+        );
+
+        let invalid = [(Some(json!([1])), None), (None, Some(json!({"a": 1})))];
+        for (properties, id) in invalid {
+            assert!(geojson_feature(
+                Some(&point),
+                properties.as_ref(),
+                id.as_ref(),
+                AxisOrder::LonLat,
+                None
+            )
+            .is_err());
+        }
+    }
+}
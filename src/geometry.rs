@@ -1,11 +1,13 @@
-use geo_types::{LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
+use crate::utils::format_number;
+use geo_types::{Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
 use pgrx::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
 use std::fmt;
 
 /// PostGIS-compatible Geometry type
 /// This enum represents all supported geometry types
-#[derive(Debug, Clone, PartialEq, PostgresType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PostgresType)]
 #[inoutfuncs]
 pub enum Geometry {
     Point(Point<f64>, i32), // (point, srid)
@@ -15,6 +17,17 @@ pub enum Geometry {
     MultiLineString(MultiLineString<f64>, i32),
     MultiPolygon(MultiPolygon<f64>, i32),
     GeometryCollection(Vec<Geometry>, i32),
+    /// A geometry with Z and/or M ordinates: the x/y geometry, never itself
+    /// of this variant, and the extra ordinates of its vertices
+    Zm(Box<Geometry>, Ordinates),
+}
+
+/// Z and M ordinates of the vertices of a geometry, one per vertex in the
+/// order of `Geometry::coordinates`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Ordinates {
+    pub z: Option<Vec<f64>>,
+    pub m: Option<Vec<f64>>,
 }
 
 impl Geometry {
@@ -28,12 +41,18 @@ impl Geometry {
             Geometry::MultiLineString(_, srid) => *srid,
             Geometry::MultiPolygon(_, srid) => *srid,
             Geometry::GeometryCollection(_, srid) => *srid,
+            Geometry::Zm(base, _) => base.srid(),
         }
     }
 
     /// Set the SRID of the geometry
     pub fn with_srid(mut self, srid: i32) -> Self {
-        match &mut self {
+        self.set_srid(srid);
+        self
+    }
+
+    fn set_srid(&mut self, srid: i32) {
+        match self {
             Geometry::Point(_, s) => *s = srid,
             Geometry::LineString(_, s) => *s = srid,
             Geometry::Polygon(_, s) => *s = srid,
@@ -41,8 +60,53 @@ impl Geometry {
             Geometry::MultiLineString(_, s) => *s = srid,
             Geometry::MultiPolygon(_, s) => *s = srid,
             Geometry::GeometryCollection(_, s) => *s = srid,
+            Geometry::Zm(base, _) => base.set_srid(srid),
+        }
+    }
+
+    /// Attach Z and/or M ordinates to the x/y geometry, one per vertex in the
+    /// order of `coordinates`; with neither, the x/y geometry is returned
+    pub fn with_ordinates(
+        self,
+        z: Option<Vec<f64>>,
+        m: Option<Vec<f64>>,
+    ) -> Result<Geometry, crate::utils::RostGisError> {
+        let base = self.into_xy();
+        if z.is_none() && m.is_none() {
+            return Ok(base);
+        }
+        let count = base.coordinates().len();
+        if z.iter().chain(m.iter()).any(|values| values.len() != count) {
+            return Err(crate::utils::RostGisError::new(&format!(
+                "Expected {} Z or M values, one per vertex",
+                count
+            )));
+        }
+        Ok(Geometry::Zm(Box::new(base), Ordinates { z, m }))
+    }
+
+    /// The x/y geometry, without Z and M ordinates
+    pub fn xy(&self) -> &Geometry {
+        match self {
+            Geometry::Zm(base, _) => base,
+            _ => self,
+        }
+    }
+
+    /// Drop the Z and M ordinates of the geometry
+    pub fn into_xy(self) -> Geometry {
+        match self {
+            Geometry::Zm(base, _) => *base,
+            geom => geom,
+        }
+    }
+
+    /// The Z and M ordinates of the geometry, None when it is 2D
+    pub fn ordinates(&self) -> Option<&Ordinates> {
+        match self {
+            Geometry::Zm(_, ordinates) => Some(ordinates),
+            _ => None,
         }
-        self
     }
 
     /// Get the geometry type as a string (PostGIS compatible)
@@ -55,42 +119,98 @@ impl Geometry {
             Geometry::MultiLineString(_, _) => "ST_MultiLineString",
             Geometry::MultiPolygon(_, _) => "ST_MultiPolygon",
             Geometry::GeometryCollection(_, _) => "ST_GeometryCollection",
+            Geometry::Zm(base, _) => base.geometry_type(),
         }
     }
 
     /// Check if geometry is empty
     pub fn is_empty(&self) -> bool {
         match self {
-            Geometry::Point(_, _) => false, // Points are never empty in this implementation
+            // An empty point has NaN coordinates, as in WKB
+            Geometry::Point(point, _) => point.x().is_nan() && point.y().is_nan(),
             Geometry::LineString(ls, _) => ls.0.is_empty(),
             Geometry::Polygon(p, _) => p.exterior().0.is_empty(),
             Geometry::MultiPoint(mp, _) => mp.0.is_empty(),
             Geometry::MultiLineString(mls, _) => mls.0.is_empty(),
             Geometry::MultiPolygon(mp, _) => mp.0.is_empty(),
             Geometry::GeometryCollection(gc, _) => gc.is_empty(),
+            Geometry::Zm(base, _) => base.is_empty(),
         }
     }
 
     /// Get X coordinate (for Point geometries)
     pub fn x(&self) -> Option<f64> {
-        match self {
-            Geometry::Point(point, _) => Some(point.x()),
+        match self.xy() {
+            Geometry::Point(point, _) if !self.is_empty() => Some(point.x()),
             _ => None,
         }
     }
 
     /// Get Y coordinate (for Point geometries)
     pub fn y(&self) -> Option<f64> {
-        match self {
-            Geometry::Point(point, _) => Some(point.y()),
+        match self.xy() {
+            Geometry::Point(point, _) if !self.is_empty() => Some(point.y()),
             _ => None,
         }
     }
 
-    /// Get Z coordinate (not implemented yet, returns None)
+    /// Get Z coordinate (for Point geometries with Z)
     pub fn z(&self) -> Option<f64> {
-        // Z coordinate support would require extending geo-types or using a different approach
-        None
+        self.point_ordinate(|ordinates| &ordinates.z)
+    }
+
+    /// Get M value (for Point geometries with M)
+    pub fn m(&self) -> Option<f64> {
+        self.point_ordinate(|ordinates| &ordinates.m)
+    }
+
+    fn point_ordinate(&self, select: fn(&Ordinates) -> &Option<Vec<f64>>) -> Option<f64> {
+        match self {
+            Geometry::Zm(base, ordinates)
+                if matches!(**base, Geometry::Point(..)) && !base.is_empty() =>
+            {
+                select(ordinates).as_ref().map(|values| values[0])
+            }
+            _ => None,
+        }
+    }
+
+    /// Check if the geometry carries Z coordinates
+    pub fn has_z(&self) -> bool {
+        self.ordinates()
+            .is_some_and(|ordinates| ordinates.z.is_some())
+    }
+
+    /// Check if the geometry carries M values
+    pub fn has_m(&self) -> bool {
+        self.ordinates()
+            .is_some_and(|ordinates| ordinates.m.is_some())
+    }
+
+    /// Get all vertices of the geometry in storage order
+    pub fn coordinates(&self) -> Vec<(f64, f64)> {
+        use geo::CoordsIter;
+
+        match self {
+            Geometry::Point(point, _) => vec![(point.x(), point.y())],
+            Geometry::LineString(linestring, _) => {
+                linestring.coords_iter().map(|c| (c.x, c.y)).collect()
+            }
+            Geometry::Polygon(polygon, _) => polygon.coords_iter().map(|c| (c.x, c.y)).collect(),
+            Geometry::MultiPoint(multipoint, _) => {
+                multipoint.coords_iter().map(|c| (c.x, c.y)).collect()
+            }
+            Geometry::MultiLineString(multilinestring, _) => {
+                multilinestring.coords_iter().map(|c| (c.x, c.y)).collect()
+            }
+            Geometry::MultiPolygon(multipolygon, _) => {
+                multipolygon.coords_iter().map(|c| (c.x, c.y)).collect()
+            }
+            Geometry::GeometryCollection(geometries, _) => {
+                geometries.iter().flat_map(|g| g.coordinates()).collect()
+            }
+            Geometry::Zm(base, _) => base.coordinates(),
+        }
     }
 
     /// Calculate the bounding box of the geometry
@@ -159,14 +279,46 @@ impl Geometry {
 
                 (min_x, min_y, max_x, max_y)
             }
+            Geometry::Zm(base, _) => base.bounding_box(),
+        }
+    }
+
+    /// Bounding box as seen by the box operators, None for empty geometries,
+    /// which no box operator matches (as in PostGIS)
+    pub(crate) fn operator_bbox(&self) -> Option<(f64, f64, f64, f64)> {
+        let bbox = self.bounding_box();
+        // NaN or inverted boxes come from collections of empty members
+        (!self.is_empty() && bbox.0 <= bbox.2 && bbox.1 <= bbox.3).then_some(bbox)
+    }
+
+    /// Whether both geometries have a box the box operators can compare
+    pub fn bbox_comparable(&self, other: &Geometry) -> bool {
+        self.operator_bbox().is_some() && other.operator_bbox().is_some()
+    }
+
+    /// Check if the bounding boxes are the same (the ~= operator); all empty
+    /// geometries have the same box
+    pub fn bbox_same(&self, other: &Geometry) -> bool {
+        match (self.operator_bbox(), other.operator_bbox()) {
+            (Some((min_x1, min_y1, max_x1, max_y1)), Some((min_x2, min_y2, max_x2, max_y2))) => {
+                (min_x1 - min_x2).abs() < f64::EPSILON
+                    && (min_y1 - min_y2).abs() < f64::EPSILON
+                    && (max_x1 - max_x2).abs() < f64::EPSILON
+                    && (max_y1 - max_y2).abs() < f64::EPSILON
+            }
+            (None, None) => true,
+            _ => false,
         }
     }
 
     /// Check if this geometry's bounding box overlaps with another's
     /// This is the && operator implementation for spatial indexing
     pub fn bbox_overlaps(&self, other: &Geometry) -> bool {
-        let (min_x1, min_y1, max_x1, max_y1) = self.bounding_box();
-        let (min_x2, min_y2, max_x2, max_y2) = other.bounding_box();
+        let (Some((min_x1, min_y1, max_x1, max_y1)), Some((min_x2, min_y2, max_x2, max_y2))) =
+            (self.operator_bbox(), other.operator_bbox())
+        else {
+            return false;
+        };
 
         // Two rectangles overlap if they overlap in both X and Y dimensions
         !(max_x1 < min_x2 || max_x2 < min_x1 || max_y1 < min_y2 || max_y2 < min_y1)
@@ -174,8 +326,11 @@ impl Geometry {
 
     /// Check if this geometry's bounding box contains another's
     pub fn bbox_contains(&self, other: &Geometry) -> bool {
-        let (min_x1, min_y1, max_x1, max_y1) = self.bounding_box();
-        let (min_x2, min_y2, max_x2, max_y2) = other.bounding_box();
+        let (Some((min_x1, min_y1, max_x1, max_y1)), Some((min_x2, min_y2, max_x2, max_y2))) =
+            (self.operator_bbox(), other.operator_bbox())
+        else {
+            return false;
+        };
 
         min_x1 <= min_x2 && min_y1 <= min_y2 && max_x1 >= max_x2 && max_y1 >= max_y2
     }
@@ -187,30 +342,94 @@ impl Geometry {
 
     /// Check if this geometry's bounding box is to the left of another's
     pub fn bbox_left(&self, other: &Geometry) -> bool {
-        let (_, _, max_x1, _) = self.bounding_box();
-        let (min_x2, _, _, _) = other.bounding_box();
-        max_x1 < min_x2
+        match (self.operator_bbox(), other.operator_bbox()) {
+            (Some((_, _, max_x1, _)), Some((min_x2, _, _, _))) => max_x1 < min_x2,
+            _ => false,
+        }
     }
 
     /// Check if this geometry's bounding box is to the right of another's
     pub fn bbox_right(&self, other: &Geometry) -> bool {
-        let (min_x1, _, _, _) = self.bounding_box();
-        let (_, _, max_x2, _) = other.bounding_box();
-        min_x1 > max_x2
+        match (self.operator_bbox(), other.operator_bbox()) {
+            (Some((min_x1, _, _, _)), Some((_, _, max_x2, _))) => min_x1 > max_x2,
+            _ => false,
+        }
     }
 
     /// Check if this geometry's bounding box is below another's
     pub fn bbox_below(&self, other: &Geometry) -> bool {
-        let (_, _, _, max_y1) = self.bounding_box();
-        let (_, min_y2, _, _) = other.bounding_box();
-        max_y1 < min_y2
+        match (self.operator_bbox(), other.operator_bbox()) {
+            (Some((_, _, _, max_y1)), Some((_, min_y2, _, _))) => max_y1 < min_y2,
+            _ => false,
+        }
     }
 
     /// Check if this geometry's bounding box is above another's
     pub fn bbox_above(&self, other: &Geometry) -> bool {
-        let (_, min_y1, _, _) = self.bounding_box();
-        let (_, _, _, max_y2) = other.bounding_box();
-        min_y1 > max_y2
+        match (self.operator_bbox(), other.operator_bbox()) {
+            (Some((_, min_y1, _, _)), Some((_, _, _, max_y2))) => min_y1 > max_y2,
+            _ => false,
+        }
+    }
+}
+
+impl Geometry {
+    /// Convert to a geo-types geometry so the geo algorithms can be applied
+    ///
+    /// geo has no empty point, and its algorithms reject the NaN coordinates
+    /// of ours, so an empty point becomes an empty MultiPoint.
+    pub fn to_geo(&self) -> geo_types::Geometry<f64> {
+        match self {
+            Geometry::Point(_, _) if self.is_empty() => {
+                geo_types::Geometry::MultiPoint(geo_types::MultiPoint(Vec::new()))
+            }
+            Geometry::Point(point, _) => geo_types::Geometry::Point(*point),
+            Geometry::LineString(linestring, _) => {
+                geo_types::Geometry::LineString(linestring.clone())
+            }
+            Geometry::Polygon(polygon, _) => geo_types::Geometry::Polygon(polygon.clone()),
+            Geometry::MultiPoint(multipoint, _) => {
+                geo_types::Geometry::MultiPoint(multipoint.clone())
+            }
+            Geometry::MultiLineString(multilinestring, _) => {
+                geo_types::Geometry::MultiLineString(multilinestring.clone())
+            }
+            Geometry::MultiPolygon(multipolygon, _) => {
+                geo_types::Geometry::MultiPolygon(multipolygon.clone())
+            }
+            Geometry::GeometryCollection(geometries, _) => geo_types::Geometry::GeometryCollection(
+                geo_types::GeometryCollection(geometries.iter().map(|g| g.to_geo()).collect()),
+            ),
+            Geometry::Zm(base, _) => base.to_geo(),
+        }
+    }
+
+    /// Build a geometry from a geo-types geometry with the given SRID
+    pub fn from_geo(geom: geo_types::Geometry<f64>, srid: i32) -> Geometry {
+        match geom {
+            geo_types::Geometry::Point(point) => Geometry::Point(point, srid),
+            geo_types::Geometry::Line(line) => Geometry::LineString(line.into(), srid),
+            geo_types::Geometry::LineString(linestring) => Geometry::LineString(linestring, srid),
+            geo_types::Geometry::Polygon(polygon) => Geometry::Polygon(polygon, srid),
+            geo_types::Geometry::MultiPoint(multipoint) => Geometry::MultiPoint(multipoint, srid),
+            geo_types::Geometry::MultiLineString(multilinestring) => {
+                Geometry::MultiLineString(multilinestring, srid)
+            }
+            geo_types::Geometry::MultiPolygon(multipolygon) => {
+                Geometry::MultiPolygon(multipolygon, srid)
+            }
+            geo_types::Geometry::GeometryCollection(collection) => Geometry::GeometryCollection(
+                collection
+                    .into_iter()
+                    .map(|g| Geometry::from_geo(g, srid))
+                    .collect(),
+                srid,
+            ),
+            geo_types::Geometry::Rect(rect) => Geometry::Polygon(rect.to_polygon(), srid),
+            geo_types::Geometry::Triangle(triangle) => {
+                Geometry::Polygon(triangle.to_polygon(), srid)
+            }
+        }
     }
 }
 
@@ -223,85 +442,209 @@ impl fmt::Display for Geometry {
 impl Geometry {
     /// Convert geometry to WKT string
     pub fn to_wkt(&self) -> String {
-        match self {
-            Geometry::Point(point, _) => {
-                format!("POINT({} {})", point.x(), point.y())
-            }
-            Geometry::LineString(linestring, _) => {
-                let coords: Vec<String> = linestring
-                    .coords()
-                    .map(|c| format!("{} {}", c.x, c.y))
-                    .collect();
-                format!("LINESTRING({})", coords.join(","))
-            }
-            Geometry::Polygon(polygon, _) => {
-                let exterior: Vec<String> = polygon
-                    .exterior()
-                    .coords()
-                    .map(|c| format!("{} {}", c.x, c.y))
-                    .collect();
-                let mut wkt = format!("POLYGON(({})", exterior.join(","));
-
-                for interior in polygon.interiors() {
-                    let interior_coords: Vec<String> = interior
-                        .coords()
-                        .map(|c| format!("{} {}", c.x, c.y))
-                        .collect();
-                    wkt.push_str(&format!(",({})", interior_coords.join(",")));
-                }
-                wkt.push(')');
-                wkt
-            }
+        self.to_wkt_with_precision(None)
+    }
+
+    /// Convert geometry to WKT string with coordinates rounded to at most
+    /// `precision` decimal places, see `utils::format_number`
+    ///
+    /// Z and M ordinates are written after x and y, with the ISO dimension
+    /// tag after the type name, e.g. `POINT Z (1 2 3)`.
+    pub fn to_wkt_with_precision(&self, precision: Option<u32>) -> String {
+        let writer = WktWriter {
+            precision,
+            ordinates: OrdinateCursor::new(self),
+        };
+        writer.geometry(self.xy())
+    }
+
+    /// Convert geometry to EWKT, prefixing the SRID when it is set
+    ///
+    /// This is the canonical text form of the type: coordinates are printed
+    /// with the shortest representation that parses back to the same value.
+    pub fn to_ewkt(&self) -> String {
+        if self.srid() == 0 {
+            self.to_wkt()
+        } else {
+            format!("SRID={};{}", self.srid(), self.to_wkt())
+        }
+    }
+}
+
+/// Cursor over the Z and M ordinates of a geometry, for writers walking its
+/// x/y geometry in vertex order
+pub(crate) struct OrdinateCursor<'a> {
+    ordinates: Option<&'a Ordinates>,
+    next: Cell<usize>,
+}
+
+impl<'a> OrdinateCursor<'a> {
+    pub(crate) fn new(geom: &'a Geometry) -> Self {
+        OrdinateCursor {
+            ordinates: geom.ordinates(),
+            next: Cell::new(0),
+        }
+    }
+
+    /// The Z and M ordinates of the geometry, None when it is 2D
+    pub(crate) fn ordinates(&self) -> Option<&'a Ordinates> {
+        self.ordinates
+    }
+
+    pub(crate) fn has_z(&self) -> bool {
+        self.ordinates
+            .is_some_and(|ordinates| ordinates.z.is_some())
+    }
+
+    /// Z and M of the next vertex
+    pub(crate) fn next(&self) -> (Option<f64>, Option<f64>) {
+        let Some(ordinates) = self.ordinates else {
+            return (None, None);
+        };
+        let vertex = self.next.replace(self.next.get() + 1);
+        (
+            ordinates.z.as_ref().map(|z| z[vertex]),
+            ordinates.m.as_ref().map(|m| m[vertex]),
+        )
+    }
+
+    /// Pass over the vertices of a part that is not written
+    pub(crate) fn skip(&self, count: usize) {
+        self.next.set(self.next.get() + count);
+    }
+}
+
+/// WKT output of an x/y geometry, consuming its Z and M ordinates in vertex
+/// order
+struct WktWriter<'a> {
+    precision: Option<u32>,
+    ordinates: OrdinateCursor<'a>,
+}
+
+impl WktWriter<'_> {
+    /// Dimension tag following the type name
+    fn tag(&self) -> &'static str {
+        match self.ordinates.ordinates() {
+            None => "",
+            Some(Ordinates {
+                z: Some(_),
+                m: Some(_),
+            }) => " ZM",
+            Some(Ordinates { z: Some(_), .. }) => " Z",
+            Some(_) => " M",
+        }
+    }
+
+    fn coord(&self, coord: Coord<f64>) -> String {
+        let mut text = format!(
+            "{} {}",
+            format_number(coord.x, self.precision),
+            format_number(coord.y, self.precision)
+        );
+        let (z, m) = self.ordinates.next();
+        for value in [z, m].into_iter().flatten() {
+            text.push(' ');
+            text.push_str(&format_number(value, self.precision));
+        }
+        text
+    }
+
+    fn list<T>(&self, items: impl Iterator<Item = T>, item: impl Fn(T) -> String) -> String {
+        format!("({})", items.map(item).collect::<Vec<_>>().join(","))
+    }
+
+    fn line(&self, line: &LineString<f64>) -> String {
+        self.list(line.coords(), |c| self.coord(*c))
+    }
+
+    fn polygon(&self, polygon: &Polygon<f64>) -> String {
+        self.list(
+            std::iter::once(polygon.exterior()).chain(polygon.interiors()),
+            |ring| self.line(ring),
+        )
+    }
+
+    fn geometry(&self, geom: &Geometry) -> String {
+        let name = match geom {
+            Geometry::Point(_, _) => "POINT",
+            Geometry::LineString(_, _) => "LINESTRING",
+            Geometry::Polygon(_, _) => "POLYGON",
+            Geometry::MultiPoint(_, _) => "MULTIPOINT",
+            Geometry::MultiLineString(_, _) => "MULTILINESTRING",
+            Geometry::MultiPolygon(_, _) => "MULTIPOLYGON",
+            Geometry::GeometryCollection(_, _) => "GEOMETRYCOLLECTION",
+            Geometry::Zm(base, _) => return self.geometry(base),
+        };
+        if geom.is_empty() {
+            // An empty point still has its (NaN) vertex
+            self.ordinates.skip(geom.coordinates().len());
+            return format!("{}{} EMPTY", name, self.tag());
+        }
+
+        let body = match geom {
+            Geometry::Point(point, _) => format!("({})", self.coord(point.0)),
+            Geometry::LineString(linestring, _) => self.line(linestring),
+            Geometry::Polygon(polygon, _) => self.polygon(polygon),
             Geometry::MultiPoint(multipoint, _) => {
-                let points: Vec<String> = multipoint
-                    .iter()
-                    .map(|p| format!("({} {})", p.x(), p.y()))
-                    .collect();
-                format!("MULTIPOINT({})", points.join(","))
+                self.list(multipoint.iter(), |p| format!("({})", self.coord(p.0)))
             }
             Geometry::MultiLineString(multilinestring, _) => {
-                let linestrings: Vec<String> = multilinestring
-                    .iter()
-                    .map(|ls| {
-                        let coords: Vec<String> =
-                            ls.coords().map(|c| format!("{} {}", c.x, c.y)).collect();
-                        format!("({})", coords.join(","))
-                    })
-                    .collect();
-                format!("MULTILINESTRING({})", linestrings.join(","))
+                self.list(multilinestring.iter(), |ls| self.line(ls))
             }
             Geometry::MultiPolygon(multipolygon, _) => {
-                let polygons: Vec<String> = multipolygon
-                    .iter()
-                    .map(|poly| {
-                        let exterior: Vec<String> = poly
-                            .exterior()
-                            .coords()
-                            .map(|c| format!("{} {}", c.x, c.y))
-                            .collect();
-                        let mut poly_wkt = format!("(({})", exterior.join(","));
-
-                        for interior in poly.interiors() {
-                            let interior_coords: Vec<String> = interior
-                                .coords()
-                                .map(|c| format!("{} {}", c.x, c.y))
-                                .collect();
-                            poly_wkt.push_str(&format!(",({})", interior_coords.join(",")));
-                        }
-                        poly_wkt.push(')');
-                        poly_wkt
-                    })
-                    .collect();
-                format!("MULTIPOLYGON({})", polygons.join(","))
+                self.list(multipolygon.iter(), |poly| self.polygon(poly))
             }
             Geometry::GeometryCollection(geometries, _) => {
-                let geoms: Vec<String> = geometries.iter().map(|g| g.to_wkt()).collect();
-                format!("GEOMETRYCOLLECTION({})", geoms.join(","))
+                self.list(geometries.iter(), |g| self.geometry(g))
             }
+            Geometry::Zm(..) => unreachable!("handled above"),
+        };
+        match self.tag() {
+            "" => format!("{}{}", name, body),
+            tag => format!("{}{} {}", name, tag, body),
         }
     }
 }
 
+/// Geometries are stored in the header-prefixed binary format of
+/// `crate::serialization`, so that the datum can be inspected without decoding
+impl Serialize for Geometry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&crate::serialization::serialize(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Geometry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Geometry;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a serialized geometry")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Geometry, E> {
+                crate::serialization::deserialize(bytes).map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Geometry, A::Error> {
+                let mut bytes = Vec::new();
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
 /// Input/Output functions for PostgreSQL integration
 impl pgrx::InOutFuncs for Geometry {
     fn input(input: &std::ffi::CStr) -> Self
@@ -309,31 +652,55 @@ impl pgrx::InOutFuncs for Geometry {
         Self: Sized,
     {
         let input_str = input.to_str().expect("Invalid UTF-8 in geometry input");
-
-        // Simple WKT parsing for input
-        if input_str.trim().to_uppercase().starts_with("POINT") {
-            // Parse POINT(x y)
-            if let Some(coords_start) = input_str.find('(') {
-                if let Some(coords_end) = input_str.find(')') {
-                    let coords_str = &input_str[coords_start + 1..coords_end];
-                    let coords: Vec<&str> = coords_str.split_whitespace().collect();
-                    if coords.len() >= 2 {
-                        if let (Ok(x), Ok(y)) = (coords[0].parse::<f64>(), coords[1].parse::<f64>())
-                        {
-                            return Geometry::Point(Point::new(x, y), 0);
-                        }
-                    }
-                }
+        match Geometry::from_text(input_str) {
+            Ok(geom) => {
+                // Always longitude/latitude, whatever rostgis.strict_axis_order,
+                // so that dumps restore the same geometries in any session
+                crate::lonlat::check_lon_lat_range(&geom);
+                geom
             }
+            Err(e) => error!("invalid input syntax for type geometry: {}", e),
         }
-
-        // Fallback: create a point at origin
-        Geometry::Point(Point::new(0.0, 0.0), 0)
     }
 
     fn output(&self, buffer: &mut pgrx::StringInfo) {
-        buffer.push_str(&self.to_wkt());
+        buffer.push_str(&self.to_ewkt());
+    }
+}
+
+impl Geometry {
+    /// Parse the text input of the type: EWKT, WKT, or hex-encoded (E)WKB
+    pub fn from_text(text: &str) -> Result<Geometry, crate::utils::RostGisError> {
+        use crate::utils::RostGisError;
+
+        let text = text.trim();
+        if is_hex_wkb(text) {
+            let bytes =
+                crate::utils::hex_to_bytes(text).map_err(|e| RostGisError::new(&e.to_string()))?;
+            crate::ewkb::read_wkb(&bytes)
+        } else {
+            crate::functions::parse_ewkt(text)
+        }
+    }
+}
+
+/// Whether the text input of the type is hex-encoded (E)WKB rather than
+/// (E)WKT
+fn is_hex_wkb(text: &str) -> bool {
+    let text = text.trim();
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Refuse a geometry with Z or M ordinates where `what`, e.g. "the
+/// geography type", would drop them
+pub fn reject_zm(geom: &Geometry, what: &str) -> Result<(), crate::utils::RostGisError> {
+    if geom.has_z() || geom.has_m() {
+        return Err(crate::utils::RostGisError::new(&format!(
+            "Z and M coordinates are not supported by {}",
+            what
+        )));
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -362,9 +729,133 @@ mod tests {
         assert_eq!(point_with_srid.srid(), 4326);
     }
 
+    #[test]
+    fn test_coordinates() {
+        let polygon = Geometry::Polygon(
+            Polygon::new(
+                LineString::from(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)]),
+                vec![],
+            ),
+            0,
+        );
+        assert_eq!(polygon.coordinates().len(), 4);
+
+        let collection = Geometry::GeometryCollection(
+            vec![Geometry::Point(Point::new(1.0, 2.0), 0), polygon],
+            0,
+        );
+        let coords = collection.coordinates();
+        assert_eq!(coords.len(), 5);
+        assert_eq!(coords[0], (1.0, 2.0));
+    }
+
+    #[test]
+    fn test_geo_conversion_roundtrip() {
+        let line = Geometry::LineString(LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]), 4326);
+        assert_eq!(Geometry::from_geo(line.to_geo(), 4326), line);
+
+        let rect = geo_types::Rect::new((0.0, 0.0), (1.0, 1.0));
+        let polygon = Geometry::from_geo(geo_types::Geometry::Rect(rect), 0);
+        assert_eq!(polygon.geometry_type(), "ST_Polygon");
+    }
+
     #[test]
     fn test_wkt_output() {
         let point = Geometry::Point(Point::new(1.0, 2.0), 0);
         assert_eq!(point.to_wkt(), "POINT(1 2)");
     }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let line = Geometry::LineString(LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]), 4326);
+        let json = serde_json::to_string(&line).unwrap();
+        assert_eq!(serde_json::from_str::<Geometry>(&json).unwrap(), line);
+    }
+
+    #[test]
+    fn test_text_input_formats() {
+        let point = Geometry::Point(Point::new(1.0, 2.0), 4326);
+        assert_eq!(Geometry::from_text("SRID=4326;POINT(1 2)").unwrap(), point);
+        assert_eq!(
+            Geometry::from_text("0101000020E6100000000000000000F03F0000000000000040").unwrap(),
+            point
+        );
+        assert_eq!(point.to_ewkt(), "SRID=4326;POINT(1 2)");
+
+        assert!(Geometry::from_text("POINT(1 2").is_err());
+    }
+
+    #[test]
+    fn test_zm_ordinates() {
+        for (wkt, has_z, has_m) in [
+            ("POINT Z (1 2 3)", true, false),
+            ("POINT M (1 2 4)", false, true),
+            ("POINT ZM (1 2 3 4)", true, true),
+            ("POINT Z EMPTY", true, false),
+            ("LINESTRING M (0 0 5,1 1 6)", false, true),
+            ("POLYGON ZM ((0 0 1 2,4 0 1 2,4 4 1 2,0 0 3 4))", true, true),
+            ("MULTIPOINT Z ((0 0 1),(1 1 2))", true, false),
+            (
+                "MULTILINESTRING Z ((0 0 1,1 1 2),(2 2 3,3 3 4))",
+                true,
+                false,
+            ),
+            (
+                "GEOMETRYCOLLECTION Z (POINT Z (1 2 3),LINESTRING Z (0 0 0,1 1 1))",
+                true,
+                false,
+            ),
+        ] {
+            let geom = Geometry::from_text(&format!("SRID=4326;{}", wkt)).unwrap();
+            assert_eq!(geom.to_wkt(), wkt);
+            assert_eq!((geom.has_z(), geom.has_m()), (has_z, has_m), "{}", wkt);
+
+            // The header flags record the dimensions of the stored body
+            let stored = crate::serialization::serialize(&geom);
+            let header = crate::serialization::GeometryHeader::peek(&stored).unwrap();
+            assert_eq!((header.has_z(), header.has_m()), (has_z, has_m), "{}", wkt);
+            let read = crate::serialization::deserialize(&stored).unwrap();
+            assert_eq!(read.to_ewkt(), geom.to_ewkt());
+        }
+
+        // Unclosed rings are closed with the ordinates of their first vertex
+        let polygon = Geometry::from_text("POLYGON Z ((0 0 1,4 0 2,4 4 3))").unwrap();
+        assert_eq!(polygon.to_wkt(), "POLYGON Z ((0 0 1,4 0 2,4 4 3,0 0 1))");
+
+        let point = Geometry::from_text("POINT ZM (1 2 3 4)").unwrap();
+        assert_eq!((point.x(), point.y()), (Some(1.0), Some(2.0)));
+        assert_eq!((point.z(), point.m()), (Some(3.0), Some(4.0)));
+        assert_eq!(point.xy(), &Geometry::Point(Point::new(1.0, 2.0), 0));
+        assert!(Geometry::from_text("LINESTRING(0 0 1,1 1)").is_err());
+        assert!(Geometry::Point(Point::new(1.0, 2.0), 0)
+            .with_ordinates(Some(vec![1.0, 2.0]), None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_empty_geometries() {
+        let empty_point = Geometry::from_text("POINT EMPTY").unwrap();
+        let empty_line = Geometry::from_text("LINESTRING EMPTY").unwrap();
+        let point = Geometry::Point(Point::new(0.0, 0.0), 0);
+
+        // geo sees an empty point as an empty MultiPoint, not NaN coordinates
+        assert_eq!(
+            empty_point.to_geo(),
+            geo_types::Geometry::MultiPoint(geo_types::MultiPoint(vec![]))
+        );
+
+        // No box operator matches an empty geometry
+        for empty in [&empty_point, &empty_line] {
+            assert!(!empty.bbox_overlaps(&point));
+            assert!(!point.bbox_overlaps(empty));
+            assert!(!empty.bbox_contains(&point));
+            assert!(!point.bbox_contains(empty));
+            assert!(!empty.bbox_left(&point) && !empty.bbox_right(&point));
+            assert!(!empty.bbox_below(&point) && !empty.bbox_above(&point));
+            assert!(!empty.bbox_comparable(&point));
+            assert!(!empty.bbox_same(&point));
+        }
+        assert!(empty_point.bbox_same(&empty_line));
+        assert!(point.bbox_same(&point) && point.bbox_comparable(&point));
+    }
 }
@@ -0,0 +1,364 @@
+use crate::box3d::Box3D;
+use crate::serialization::{deserialize, GeometryHeader};
+use crate::spatial_index::{
+    cached_query_key, gist_entries, replace_entry_key, split_empty_apart, split_min_fill,
+    split_offsets, BBox,
+};
+use crate::utils::RostGisError;
+use pgrx::prelude::*;
+
+// N-dimensional GiST operator class (gist_geometry_ops_nd)
+//
+// Indexes the x, y and z extents of geometries as box3d keys, so that &&&
+// filters, against a geometry or a box3d, can use an index, like the
+// operator class of the same name in PostGIS:
+//
+//   CREATE INDEX scans_geom_nd_idx ON scans USING gist (geom gist_geometry_ops_nd);
+//   SELECT * FROM scans WHERE geom &&& 'BOX3D(0 0 10,5 5 20)'::box3d;
+//
+// It is not the default operator class of geometry; rostgis_gist_ops is.
+// The support functions follow the 2D ones of the spatial_index module, with
+// the same internal calling convention and the same NaN key for empty
+// geometries: pages split along the axis their entries' centres spread most
+// on, where the two sides overlap least, and an entry goes to the page whose
+// box grows least in volume, or in the sum of its extents while the boxes
+// are flat. Keys span the Z ordinates of the geometries, so stacked features
+// such as floors of a building fall on different pages; the keys of
+// geometries without Z lie flat at z = 0.
+
+/// Cost of inserting an entry into a page: the enlargement of its volume,
+/// or of the sum of its extents when the volume does not grow, as for
+/// boxes flat in a dimension. Empty entries go with empty entries.
+pub fn nd_penalty(original: &Box3D, new_entry: &Box3D) -> f64 {
+    match (original.is_empty(), new_entry.is_empty()) {
+        (true, true) => return 0.0,
+        (false, false) => {}
+        _ => return f32::MAX as f64,
+    }
+    let union = original.union(new_entry);
+    let volume = union.volume() - original.volume();
+    if volume > 0.0 {
+        volume
+    } else {
+        union.margin() - original.margin()
+    }
+}
+
+/// Split the entries of a full page in two: the entries are sorted along
+/// the axis their centres spread most on, and cut where the boxes of the two
+/// sides overlap least (by volume, then by the extents of the overlap), then
+/// have the least extents, each side keeping at least `min_fill` of the
+/// entries. Empty entries are kept apart from the others. Returns the
+/// positions of the entries of each side.
+pub fn split_entries_nd(entries: &[Box3D], min_fill: f64) -> (Vec<usize>, Vec<usize>) {
+    let mut order = match split_empty_apart(entries.iter().map(Box3D::is_empty)) {
+        Ok(split) => return split,
+        Err(order) => order,
+    };
+    let n = order.len();
+    if n <= 1 {
+        return (order, Vec::new());
+    }
+    let centre = |i: usize, axis: usize| {
+        let bbox = &entries[i];
+        match axis {
+            0 => bbox.min_x + bbox.max_x,
+            1 => bbox.min_y + bbox.max_y,
+            _ => bbox.min_z + bbox.max_z,
+        }
+    };
+    let spread = |axis: usize| {
+        let centres = order.iter().map(|&i| centre(i, axis));
+        centres.clone().fold(f64::NEG_INFINITY, f64::max) - centres.fold(f64::INFINITY, f64::min)
+    };
+    let axis = (0..3)
+        .max_by(|&a, &b| spread(a).total_cmp(&spread(b)).then(b.cmp(&a)))
+        .unwrap_or(0);
+    order.sort_by(|&a, &b| centre(a, axis).total_cmp(&centre(b, axis)));
+
+    let cover = |side: &[usize]| {
+        side.iter()
+            .skip(1)
+            .fold(entries[side[0]].clone(), |cover, &i| {
+                cover.union(&entries[i])
+            })
+    };
+    let least = ((n as f64 * min_fill).ceil() as usize).clamp(1, n / 2);
+    let cut = (least..=n - least)
+        .min_by(|&a, &b| {
+            let cost = |k: usize| {
+                let (left, right) = (cover(&order[..k]), cover(&order[k..]));
+                let overlap = left.intersection(&right);
+                (
+                    overlap.as_ref().map_or(0.0, Box3D::volume),
+                    overlap.as_ref().map_or(0.0, Box3D::margin),
+                    left.margin() + right.margin(),
+                )
+            };
+            let (cost_a, cost_b) = (cost(a), cost(b));
+            cost_a
+                .0
+                .total_cmp(&cost_b.0)
+                .then(cost_a.1.total_cmp(&cost_b.1))
+                .then(cost_a.2.total_cmp(&cost_b.2))
+        })
+        .unwrap_or(n / 2);
+    let right = order.split_off(cut);
+    (order, right)
+}
+
+/// Index key of a stored geometry: its box, at z = 0 without Z, or the
+/// empty key
+pub fn stored_key_nd(bytes: &[u8]) -> Result<Box3D, RostGisError> {
+    if GeometryHeader::peek(bytes)?.has_z() {
+        return Ok(Box3D::from_geometry(&deserialize(bytes)?).unwrap_or_else(Box3D::empty));
+    }
+    let bbox = BBox::stored_key(bytes)?;
+    Ok(if bbox.is_empty() {
+        Box3D::empty()
+    } else {
+        Box3D::from_bbox(&bbox)
+    })
+}
+
+/// Whether an entry may match a &&& query (strategy 3, the only one). An
+/// inner key covers the boxes under it, so the test is the same on inner
+/// and leaf entries, and exact at leaves.
+pub fn nd_consistent(key: &Box3D, query: &Box3D) -> bool {
+    !key.is_empty() && !query.is_empty() && key.overlaps(query)
+}
+
+unsafe fn box3d_key(datum: pg_sys::Datum) -> Box3D {
+    Box3D::from_datum(datum, false).expect("GiST keys are never NULL")
+}
+
+fn store_key(key: Box3D) -> Result<pg_sys::Datum, RostGisError> {
+    key.into_datum()
+        .ok_or_else(|| RostGisError::new("Failed to store a GiST key"))
+}
+
+/// GiST consistent function (support function 1). The query is a geometry
+/// or, for &&& (geometry, box3d), a box3d, told apart by the subtype.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_consistent_nd(
+    entry: Internal,
+    query: &[u8],
+    _strategy: i16,
+    subtype: pg_sys::Oid,
+    recheck: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(entry), Some(recheck)) = (entry.unwrap(), recheck.unwrap()) else {
+        return Ok(false);
+    };
+    unsafe {
+        let entry = &*entry.cast_mut_ptr::<pg_sys::GISTENTRY>();
+        *recheck.cast_mut_ptr::<bool>() = false;
+        let query = cached_query_key(fcinfo, query, |bytes| {
+            if subtype != Box3D::type_oid() {
+                return stored_key_nd(bytes);
+            }
+            pgrx::pg_getarg_datum(fcinfo, 1)
+                .and_then(|datum| Box3D::from_datum(datum, false))
+                .ok_or_else(|| RostGisError::new("The query box3d is NULL"))
+        })?;
+        Ok(nd_consistent(&box3d_key(entry.key), &query))
+    }
+}
+
+/// GiST union function (support function 2): the box covering all entries
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_union_nd(entryvec: Internal, _size: Internal) -> Box3D {
+    let Some(entryvec) = entryvec.unwrap() else {
+        return Box3D::empty();
+    };
+    unsafe {
+        gist_entries(entryvec)
+            .iter()
+            .map(|entry| box3d_key(entry.key))
+            .reduce(|cover, bbox| cover.union(&bbox))
+            .unwrap_or_else(Box3D::empty)
+    }
+}
+
+/// GiST compress function (support function 3): turns the geometries of
+/// leaf entries into their keys
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_compress_nd(
+    entry: Internal,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(datum) = entry.unwrap() else {
+        return Ok(Internal::from(None));
+    };
+    unsafe {
+        let entry = &*datum.cast_mut_ptr::<pg_sys::GISTENTRY>();
+        if !entry.leafkey {
+            return Ok(Internal::from(Some(datum)));
+        }
+        let geometry = <&[u8]>::from_datum(entry.key, false)
+            .ok_or_else(|| RostGisError::new("GiST leaf entries are never NULL"))?;
+        let key = store_key(stored_key_nd(geometry)?)?;
+        Ok(Internal::from(Some(replace_entry_key(entry, key))))
+    }
+}
+
+/// GiST penalty function (support function 5)
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_penalty_nd(
+    original: Internal,
+    new_entry: Internal,
+    penalty: Internal,
+) -> Internal {
+    let (Some(original), Some(new_entry), Some(penalty)) =
+        (original.unwrap(), new_entry.unwrap(), penalty.unwrap())
+    else {
+        return Internal::from(None);
+    };
+    unsafe {
+        let original = box3d_key((*original.cast_mut_ptr::<pg_sys::GISTENTRY>()).key);
+        let new_entry = box3d_key((*new_entry.cast_mut_ptr::<pg_sys::GISTENTRY>()).key);
+        *penalty.cast_mut_ptr::<f32>() = nd_penalty(&original, &new_entry) as f32;
+    }
+    Internal::from(Some(penalty))
+}
+
+/// GiST picksplit function (support function 6), keeping at least
+/// rostgis.gist_fill_factor of the entries on each side
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_picksplit_nd(
+    entryvec: Internal,
+    splitvec: Internal,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(entryvec), Some(splitvec)) = (entryvec.unwrap(), splitvec.unwrap()) else {
+        return Ok(Internal::from(None));
+    };
+    unsafe {
+        // Entries are numbered from FirstOffsetNumber, 1
+        let keys: Vec<Box3D> = gist_entries(entryvec)
+            .iter()
+            .skip(1)
+            .map(|entry| box3d_key(entry.key))
+            .collect();
+        let (left, right) = split_entries_nd(&keys, split_min_fill());
+        let union = |side: &[usize]| {
+            store_key(
+                side.iter()
+                    .map(|&i| keys[i].clone())
+                    .reduce(|cover, bbox| cover.union(&bbox))
+                    .unwrap_or_else(Box3D::empty),
+            )
+        };
+        let split = &mut *splitvec.cast_mut_ptr::<pg_sys::GIST_SPLITVEC>();
+        (split.spl_left, split.spl_nleft) = split_offsets(&left);
+        split.spl_ldatum = union(&left)?;
+        (split.spl_right, split.spl_nright) = split_offsets(&right);
+        split.spl_rdatum = union(&right)?;
+    }
+    Ok(Internal::from(Some(splitvec)))
+}
+
+/// GiST same function (support function 7)
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_same_nd(a: Box3D, b: Box3D, result: Internal) -> Internal {
+    let result = result.unwrap();
+    if let Some(result) = result {
+        unsafe { *result.cast_mut_ptr::<bool>() = a == b || (a.is_empty() && b.is_empty()) };
+    }
+    Internal::from(result)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.geometry_gist_consistent_nd(
+    internal, @extschema@.geometry, smallint, oid, internal) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_consistent_nd_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_union_nd(internal, internal) RETURNS @extschema@.box3d
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_union_nd_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_compress_nd(internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_compress_nd_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_penalty_nd(internal, internal, internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_penalty_nd_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_picksplit_nd(internal, internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_picksplit_nd_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_same_nd(@extschema@.box3d, @extschema@.box3d, internal)
+    RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_same_nd_wrapper';
+
+CREATE OPERATOR CLASS @extschema@.gist_geometry_ops_nd
+    FOR TYPE @extschema@.geometry USING gist AS
+        STORAGE @extschema@.box3d,
+        OPERATOR 3  @extschema@.&&& (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 3  @extschema@.&&& (@extschema@.geometry, @extschema@.box3d),
+        FUNCTION 1 @extschema@.geometry_gist_consistent_nd(
+            internal, @extschema@.geometry, smallint, oid, internal),
+        FUNCTION 2 @extschema@.geometry_gist_union_nd(internal, internal),
+        FUNCTION 3 @extschema@.geometry_gist_compress_nd(internal),
+        FUNCTION 5 @extschema@.geometry_gist_penalty_nd(internal, internal, internal),
+        FUNCTION 6 @extschema@.geometry_gist_picksplit_nd(internal, internal),
+        FUNCTION 7 @extschema@.geometry_gist_same_nd(
+            @extschema@.box3d, @extschema@.box3d, internal);
+"#,
+    name = "gist_nd_operator_class",
+    requires = [Geometry, Box3D, geometry_overlap_3d, geometry_overlap_box3d],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(x: f64, y: f64, z: f64, size: f64) -> Box3D {
+        Box3D::new((x, y, z), (x + size, y + size, z + size))
+    }
+
+    #[test]
+    fn test_nd_penalty() {
+        let page = cube(0.0, 0.0, 0.0, 10.0);
+        assert_eq!(nd_penalty(&page, &cube(1.0, 1.0, 1.0, 1.0)), 0.0);
+        assert_eq!(nd_penalty(&page, &cube(0.0, 0.0, 10.0, 10.0)), 1000.0);
+        // Flat boxes grow in extent only
+        let flat = Box3D::new((0.0, 0.0, 0.0), (10.0, 10.0, 0.0));
+        let beside = Box3D::new((12.0, 0.0, 0.0), (13.0, 1.0, 0.0));
+        assert_eq!(nd_penalty(&flat, &beside), 3.0);
+        assert_eq!(nd_penalty(&Box3D::empty(), &Box3D::empty()), 0.0);
+        assert_eq!(nd_penalty(&page, &Box3D::empty()), f32::MAX as f64);
+    }
+
+    #[test]
+    fn test_split_entries_nd() {
+        // Two stacks of cubes, one above the other
+        let mut entries = Vec::new();
+        for i in 0..5 {
+            entries.push(cube(i as f64 * 0.1, 0.0, 0.0, 1.0));
+            entries.push(cube(i as f64 * 0.1, 0.0, 100.0, 1.0));
+        }
+        let (low, high) = split_entries_nd(&entries, 0.4);
+        assert_eq!((low.len(), high.len()), (5, 5));
+        assert!(low.iter().all(|&i| entries[i].max_z <= 1.0));
+        assert!(high.iter().all(|&i| entries[i].min_z >= 100.0));
+
+        let (one, none) = split_entries_nd(&[cube(0.0, 0.0, 0.0, 1.0)], 0.4);
+        assert_eq!((one.len(), none.len()), (1, 0));
+        let mixed = [Box3D::empty(), cube(0.0, 0.0, 0.0, 1.0)];
+        assert_eq!(split_entries_nd(&mixed, 0.4), (vec![1], vec![0]));
+    }
+
+    #[test]
+    fn test_nd_consistent() {
+        use crate::functions::geometry_from_wkt;
+        use crate::serialization::serialize;
+
+        let key = |wkt: &str| stored_key_nd(&serialize(&geometry_from_wkt(wkt).unwrap())).unwrap();
+        let query = Box3D::new((0.0, 0.0, -1.0), (2.0, 2.0, 1.0));
+        assert!(nd_consistent(&key("POINT(1 1)"), &query));
+        assert!(nd_consistent(&key("LINESTRING(-5 -5, 0.5 0.5)"), &query));
+        assert!(!nd_consistent(&key("POINT(3 1)"), &query));
+        // Keys span the Z ordinates, or lie at z = 0 without them
+        let above = Box3D::new((0.0, 0.0, 1.0), (2.0, 2.0, 3.0));
+        assert!(!nd_consistent(&key("POINT(1 1)"), &above));
+        assert!(nd_consistent(&key("POINT Z (1 1 2)"), &above));
+        assert!(!nd_consistent(&key("POINT Z (1 1 2)"), &query));
+        assert!(nd_consistent(&key("LINESTRING Z (1 1 -5, 1 1 5)"), &above));
+        assert!(key("POINT EMPTY").is_empty());
+        assert!(!nd_consistent(&key("POINT EMPTY"), &query));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::geography::Geography;
+use crate::geometry::Geometry;
+use crate::utils::{srid, RostGisError};
+use geo::{Distance, Geodesic, InterpolatePoint};
+use geo_types::{Coord, LineString, Point};
+use pgrx::prelude::*;
+
+// Great circle routes (ST_GreatCircle)
+//
+// The shortest path between two lon/lat points on the WGS84 ellipsoid, as a
+// line with a vertex at least every max_segment_length meters, for drawing
+// flight paths and shipping lanes on a map:
+//
+//   SELECT ST_GreatCircle(origin, destination, 100000) FROM flights;
+//
+// Vertices are evenly spaced along the geodesic, so no segment is longer
+// than max_segment_length. Longitudes stay continuous where the route
+// crosses the antimeridian, going past 180 or -180 so the line draws without
+// a jump across the map; ST_SplitAtDateline cuts it there. Antipodal points
+// have no single shortest path, and one of them is returned.
+
+/// The point of a geometry given to ST_GreatCircle
+fn route_end(geom: &Geometry) -> Result<Point<f64>, RostGisError> {
+    match geom {
+        Geometry::Point(point, _) if !geom.is_empty() => {
+            Geography::from_geometry(geom.clone())?;
+            Ok(*point)
+        }
+        other => Err(RostGisError::new(&format!(
+            "ST_GreatCircle requires two non-empty points, got {}",
+            other.geometry_type()
+        ))),
+    }
+}
+
+/// The geodesic from `from` to `to`, densified so no segment is longer than
+/// `max_segment_length` meters
+pub fn great_circle(
+    from: &Geometry,
+    to: &Geometry,
+    max_segment_length: f64,
+) -> Result<Geometry, RostGisError> {
+    if from.srid() != to.srid() {
+        return Err(RostGisError::new(&format!(
+            "ST_GreatCircle: points have different SRIDs ({} and {})",
+            from.srid(),
+            to.srid()
+        )));
+    }
+    if !(max_segment_length > 0.0 && max_segment_length.is_finite()) {
+        return Err(RostGisError::new(
+            "ST_GreatCircle: max_segment_length must be a positive number of meters",
+        ));
+    }
+    let (start, end) = (route_end(from)?, route_end(to)?);
+
+    let segments = (Geodesic.distance(start, end) / max_segment_length)
+        .ceil()
+        .max(1.0);
+    if segments > 1_000_000.0 {
+        return Err(RostGisError::new(
+            "ST_GreatCircle: max_segment_length gives more than 1000000 segments",
+        ));
+    }
+    let segments = segments as usize;
+
+    let mut previous = start.x();
+    let coords = (0..=segments)
+        .map(|i| match i {
+            0 => start,
+            i if i == segments => end,
+            i => Geodesic.point_at_ratio_between(start, end, i as f64 / segments as f64),
+        })
+        .map(|point| {
+            // Unwrap the longitude next to the previous vertex
+            let mut x = point.x();
+            x += 360.0 * ((previous - x) / 360.0).round();
+            previous = x;
+            Coord { x, y: point.y() }
+        })
+        .collect();
+    Ok(Geometry::LineString(LineString(coords), srid::WGS84))
+}
+
+/// PostgreSQL function returning the geodesic between two lon/lat points
+/// with a vertex at least every `max_segment_length` meters
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_greatcircle(
+    from: Geometry,
+    to: Geometry,
+    max_segment_length: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(great_circle(&from, &to, max_segment_length)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+    use geo::Length;
+
+    fn route(from: (f64, f64), to: (f64, f64), max_segment_length: f64) -> LineString<f64> {
+        match great_circle(
+            &make_point(from.0, from.1),
+            &make_point(to.0, to.1),
+            max_segment_length,
+        )
+        .unwrap()
+        {
+            Geometry::LineString(line, srid) => {
+                assert_eq!(srid, srid::WGS84);
+                line
+            }
+            other => panic!("expected a line, got {}", other.to_wkt()),
+        }
+    }
+
+    #[test]
+    fn test_great_circle() {
+        // Frankfurt to New York is about 6300 km
+        let line = route((8.57, 50.03), (-73.78, 40.64), 100_000.0);
+        assert_eq!(line.0.len(), 64);
+        assert_eq!(line.0[0], Coord { x: 8.57, y: 50.03 });
+        assert_eq!(
+            line.0[63],
+            Coord {
+                x: -73.78,
+                y: 40.64
+            }
+        );
+        let steps: Vec<f64> = line
+            .lines()
+            .map(|segment| Geodesic.distance(Point(segment.start), Point(segment.end)))
+            .collect();
+        assert!(steps.iter().all(|step| *step <= 100_000.0));
+        assert!(steps.iter().all(|step| (step - steps[0]).abs() < 1.0));
+        // The route bends north of both ends
+        assert!(line.0.iter().any(|coord| coord.y > 52.0));
+        assert!(
+            (Geodesic.length(&line)
+                - Geodesic.distance(Point::new(8.57, 50.03), Point::new(-73.78, 40.64)))
+            .abs()
+                < 1.0
+        );
+    }
+
+    #[test]
+    fn test_great_circle_short_and_coincident() {
+        let line = route((0.0, 0.0), (1.0, 0.0), 1_000_000.0);
+        assert_eq!(
+            line.0,
+            vec![Coord { x: 0.0, y: 0.0 }, Coord { x: 1.0, y: 0.0 }]
+        );
+        let line = route((5.0, 5.0), (5.0, 5.0), 1000.0);
+        assert_eq!(line.0.len(), 2);
+    }
+
+    #[test]
+    fn test_great_circle_antimeridian() {
+        // Tokyo to San Francisco crosses the antimeridian eastwards
+        let line = route((139.78, 35.55), (-122.38, 37.62), 500_000.0);
+        assert!(line
+            .lines()
+            .all(|segment| (segment.end.x - segment.start.x).abs() < 10.0));
+        assert_eq!(line.0.last().unwrap().x, -122.38 + 360.0);
+    }
+
+    #[test]
+    fn test_great_circle_invalid() {
+        let a = make_point(0.0, 0.0);
+        let b = make_point(1.0, 1.0);
+        assert!(great_circle(&a, &b, 0.0).is_err());
+        assert!(great_circle(&a, &b, f64::NAN).is_err());
+        assert!(great_circle(&a, &b, 1e-3).is_err());
+        assert!(great_circle(&a, &make_point(200.0, 0.0), 1000.0).is_err());
+        assert!(great_circle(&a, &b.clone().with_srid(3857), 1000.0).is_err());
+        assert!(great_circle(
+            &a.clone().with_srid(3857),
+            &b.clone().with_srid(3857),
+            1000.0
+        )
+        .is_err());
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(great_circle(&a, &line, 1000.0).is_err());
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert!(great_circle(&a, &empty, 1000.0).is_err());
+    }
+}
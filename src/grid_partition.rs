@@ -0,0 +1,329 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::BBox;
+use crate::utils::{extension_schema, quote_ident, quote_table};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use std::collections::BTreeMap;
+
+// Partitioning by spatial grid (rostgis_partition_by_grid)
+//
+// Rows are assigned to the square grid cell, aligned on the coordinate
+// origin, that holds the centre of their bounding box. A cell (i, j) is
+// keyed by one bigint, i in the high and j in the low 32 bits, so every
+// cell has its own key without knowing the extent of the data.
+//
+// The helper copies a table into a new table list-partitioned on that key,
+// with one partition per occupied cell and a default partition for NULL
+// and empty geometries and for cells occupied later. Since the planner
+// cannot prune partitions on &&, the extent of the data of every cell is
+// recorded in rostgis_grid_partitions, and rostgis_grid_cells turns a search
+// area into the keys of the cells that can hold matches:
+//
+//   SELECT * FROM roads_grid
+//   WHERE roads_grid_cell(geom) = ANY (rostgis_grid_cells('roads_grid', area))
+//     AND geom && area;
+//
+// A row trigger on the partitioned table widens the recorded extents as
+// geometries are inserted or updated, including those of cells held by the
+// default partition. Extents never shrink when rows are deleted.
+
+/// Rows read per batch while scanning the source table
+const SCAN_BATCH: i32 = 10_000;
+
+/// Grid cell holding the centre of a bounding box
+pub fn grid_cell(bbox: &BBox, cell_size: f64) -> (i32, i32) {
+    let centre_x = (bbox.min_x + bbox.max_x) / 2.0;
+    let centre_y = (bbox.min_y + bbox.max_y) / 2.0;
+    (
+        (centre_x / cell_size).floor() as i32,
+        (centre_y / cell_size).floor() as i32,
+    )
+}
+
+/// Key of a grid cell
+pub fn cell_key((i, j): (i32, i32)) -> i64 {
+    (i64::from(i) << 32) | i64::from(j as u32)
+}
+
+/// Grid cell of a key
+pub fn key_cell(key: i64) -> (i32, i32) {
+    ((key >> 32) as i32, key as i32)
+}
+
+fn check_cell_size(cell_size: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !cell_size.is_finite() || cell_size <= 0.0 {
+        return Err("grid cell size must be a positive number".into());
+    }
+    Ok(())
+}
+
+/// Grid cell key of a geometry, NULL for empty geometries
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_grid_cell(
+    geom: Geometry,
+    cell_size: f64,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    check_cell_size(cell_size)?;
+    if geom.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(cell_key(grid_cell(
+        &BBox::from_geometry(&geom),
+        cell_size,
+    ))))
+}
+
+/// Copy a table into a new table partitioned by grid cell
+///
+/// For a table `roads` this creates the routing function
+/// `roads_grid_cell(geometry)`, the partitioned table `roads_grid` keyed by
+/// it with one partition `roads_grid_<i>_<j>` per occupied cell and the
+/// default partition `roads_grid_default`, records the partitions in
+/// rostgis_grid_partitions and keeps them up to date with the trigger
+/// `roads_grid_extend`. The source table is left unchanged. Returns the
+/// number of cell partitions.
+#[pg_extern]
+pub fn rostgis_partition_by_grid(
+    table_name: &str,
+    geom_column: &str,
+    cell_size: f64,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    check_cell_size(cell_size)?;
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+    let (schema, name) = Spi::get_two_with_args::<String, String>(
+        "SELECT quote_ident(n.nspname), c.relname
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+         WHERE c.oid = $1::regclass",
+        &[table_name.into()],
+    )?;
+    let (schema, name) = schema.zip(name).ok_or("Table not found")?;
+    let derived = |suffix: String| -> Result<String, pgrx::spi::Error> {
+        Ok(format!(
+            "{}.{}",
+            schema,
+            quote_ident(&format!("{}{}", name, suffix))?
+        ))
+    };
+
+    // Extent of the data in every occupied cell
+    let extents = Spi::connect(
+        |client| -> Result<BTreeMap<(i32, i32), BBox>, pgrx::spi::Error> {
+            let mut cursor = client.open_cursor(
+                &format!(
+                    "SELECT {} FROM {} WHERE {} IS NOT NULL",
+                    column, relation, column
+                ),
+                &[],
+            );
+            let mut extents: BTreeMap<(i32, i32), BBox> = BTreeMap::new();
+            loop {
+                let batch = cursor.fetch(SCAN_BATCH.into())?;
+                if batch.is_empty() {
+                    break;
+                }
+                for row in batch {
+                    let Some(geom) = row.get::<Geometry>(1)? else {
+                        continue;
+                    };
+                    if geom.is_empty() {
+                        continue;
+                    }
+                    let bbox = BBox::from_geometry(&geom);
+                    extents
+                        .entry(grid_cell(&bbox, cell_size))
+                        .and_modify(|extent| *extent = extent.union(&bbox))
+                        .or_insert(bbox);
+                }
+            }
+            Ok(extents)
+        },
+    )?;
+
+    let parent = derived("_grid".to_string())?;
+    let routing = derived("_grid_cell".to_string())?;
+    // The routing function is called with the search_path of whoever writes
+    // to the table, so it names the extension's schema
+    let rostgis = extension_schema()?;
+    Spi::run(&format!(
+        "CREATE FUNCTION {routing}({rostgis}.geometry) RETURNS bigint
+         IMMUTABLE STRICT PARALLEL SAFE LANGUAGE sql
+         AS $$ SELECT {rostgis}.rostgis_grid_cell($1, {cell_size}) $$"
+    ))?;
+    Spi::run(&format!(
+        "CREATE TABLE {parent} (LIKE {relation} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)
+         PARTITION BY LIST ({routing}({column}))"
+    ))?;
+    for (&(i, j), extent) in &extents {
+        let partition = derived(format!("_grid_{}_{}", i, j))?;
+        let key = cell_key((i, j));
+        Spi::run(&format!(
+            "CREATE TABLE {partition} PARTITION OF {parent} FOR VALUES IN ({key})"
+        ))?;
+        Spi::run_with_args(
+            &format!(
+                "INSERT INTO {rostgis}.rostgis_grid_partitions
+                 (parent, partition, cell, cell_size, min_x, min_y, max_x, max_y)
+                 VALUES ($1::regclass, $2::regclass, $3, $4, $5, $6, $7, $8)"
+            ),
+            &[
+                parent.clone().into(),
+                partition.into(),
+                key.into(),
+                cell_size.into(),
+                extent.min_x.into(),
+                extent.min_y.into(),
+                extent.max_x.into(),
+                extent.max_y.into(),
+            ],
+        )?;
+    }
+    let default = derived("_grid_default".to_string())?;
+    Spi::run(&format!(
+        "CREATE TABLE {default} PARTITION OF {parent} DEFAULT"
+    ))?;
+    Spi::run(&format!("INSERT INTO {parent} SELECT * FROM {relation}"))?;
+    // Created after the copy, whose extents are already recorded
+    let trigger = quote_ident(&format!("{}_grid_extend", name))?;
+    Spi::run(&format!(
+        "CREATE TRIGGER {trigger} AFTER INSERT OR UPDATE OF {column} ON {parent}
+         FOR EACH ROW EXECUTE FUNCTION {rostgis}.rostgis_grid_extend({column}, '{cell_size}')"
+    ))?;
+    notice!(
+        "rostgis_partition_by_grid: copied {} into {} grid partitions of {}",
+        relation,
+        extents.len(),
+        parent
+    );
+
+    Ok(extents.len() as i64)
+}
+
+/// Widen the recorded extent of the grid cell of a geometry in a partition
+/// to cover it, called by the trigger of grid partitioned tables
+#[pg_extern(strict)]
+pub fn rostgis_grid_record(
+    partition: &str,
+    geom: Geometry,
+    cell_size: f64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    check_cell_size(cell_size)?;
+    if geom.is_empty() {
+        return Ok(());
+    }
+    let bbox = BBox::from_geometry(&geom);
+    let schema = extension_schema()?;
+    Spi::run_with_args(
+        &format!(
+            "INSERT INTO {schema}.rostgis_grid_partitions AS p
+             (parent, partition, cell, cell_size, min_x, min_y, max_x, max_y)
+             VALUES (pg_partition_root($1::regclass), $1::regclass, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (partition, cell) DO UPDATE
+             SET min_x = least(p.min_x, excluded.min_x),
+                 min_y = least(p.min_y, excluded.min_y),
+                 max_x = greatest(p.max_x, excluded.max_x),
+                 max_y = greatest(p.max_y, excluded.max_y)
+             WHERE excluded.min_x < p.min_x OR excluded.min_y < p.min_y
+                OR excluded.max_x > p.max_x OR excluded.max_y > p.max_y"
+        ),
+        &[
+            partition.into(),
+            cell_key(grid_cell(&bbox, cell_size)).into(),
+            cell_size.into(),
+            bbox.min_x.into(),
+            bbox.min_y.into(),
+            bbox.max_x.into(),
+            bbox.max_y.into(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Keys of the grid cells of a table whose data can overlap the bounding
+/// box of a geometry
+#[pg_extern(stable, strict)]
+pub fn rostgis_grid_cells(
+    table_name: &str,
+    geom: Geometry,
+) -> Result<Vec<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    if geom.is_empty() {
+        return Ok(Vec::new());
+    }
+    let bbox = BBox::from_geometry(&geom);
+    let schema = extension_schema()?;
+    Ok(Spi::get_one_with_args::<Vec<i64>>(
+        &format!(
+            "SELECT coalesce(array_agg(DISTINCT cell ORDER BY cell), '{{}}')
+             FROM {schema}.rostgis_grid_partitions
+             WHERE parent = $1::regclass
+               AND min_x <= $4 AND max_x >= $2 AND min_y <= $5 AND max_y >= $3"
+        ),
+        &[
+            table_name.into(),
+            bbox.min_x.into(),
+            bbox.min_y.into(),
+            bbox.max_x.into(),
+            bbox.max_y.into(),
+        ],
+    )?
+    .unwrap_or_default())
+}
+
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.rostgis_grid_partitions (
+    parent regclass NOT NULL,
+    partition regclass NOT NULL,
+    cell bigint NOT NULL,
+    cell_size double precision NOT NULL,
+    min_x double precision NOT NULL,
+    min_y double precision NOT NULL,
+    max_x double precision NOT NULL,
+    max_y double precision NOT NULL,
+    PRIMARY KEY (partition, cell)
+);
+CREATE INDEX ON @extschema@.rostgis_grid_partitions (parent);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.rostgis_grid_partitions', '');
+
+-- Row trigger of grid partitioned tables; its arguments name the geometry
+-- column and the cell size
+CREATE FUNCTION @extschema@.rostgis_grid_extend() RETURNS trigger
+LANGUAGE plpgsql AS $$
+DECLARE
+    geom @extschema@.geometry;
+BEGIN
+    EXECUTE format('SELECT ($1).%I', TG_ARGV[0]) INTO geom USING NEW;
+    PERFORM @extschema@.rostgis_grid_record(
+        TG_RELID::regclass::text, geom, TG_ARGV[1]::double precision);
+    RETURN NULL;
+END
+$$;
+"#,
+    name = "grid_partitions",
+    requires = [Geometry, rostgis_grid_record],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_cell() {
+        let cell = |x: f64, y: f64| grid_cell(&BBox::new(x, y, x, y), 10.0);
+        assert_eq!(cell(5.0, 5.0), (0, 0));
+        assert_eq!(cell(10.0, 19.9), (1, 1));
+        assert_eq!(cell(-0.1, -25.0), (-1, -3));
+        // A box is placed by its centre
+        assert_eq!(grid_cell(&BBox::new(8.0, 0.0, 14.0, 2.0), 10.0), (1, 0));
+    }
+
+    #[test]
+    fn test_cell_key() {
+        for cell in [(0, 0), (1, -1), (-1, 1), (i32::MIN, i32::MAX), (-7, -9)] {
+            assert_eq!(key_cell(cell_key(cell)), cell);
+        }
+        assert_ne!(cell_key((0, -1)), cell_key((-1, 0)));
+        assert_eq!(cell_key((1, 2)), (1 << 32) + 2);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::ewkb::collect_children;
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use byteorder::{ByteOrder, LittleEndian};
+use geo_types::{Coord, LineString, Point, Polygon};
+use pgrx::prelude::*;
+
+// PostGIS on-disk geometries (rostgis_from_postgis)
+//
+// Decodes the gserialized format PostGIS stores geometry and geography
+// values in, for tools that move data at the binary level or read a PostGIS
+// database's files:
+//
+//   SELECT rostgis_from_postgis(raw_value) FROM postgis_dump;
+//
+// A value is read as stored, starting with its varlena header, either the
+// 4-byte one or the 1-byte one of short values. Both formats are read:
+// version 1 (PostGIS 2) and version 2 (PostGIS 3), told apart by a flag.
+// The header holds the SRID in 21 bits and the flags, then an optional
+// float bounding box and the geometry: for each part a type and a count,
+// then the coordinates as doubles, padded to 8 bytes. Coordinates are read
+// little-endian, the byte order of the machines PostGIS runs on in
+// practice. Z and M ordinates are kept; curves, triangles and TINs are not
+// supported. Compressed or TOAST-ed values must be detoasted first, as
+// PostgreSQL does when the value is selected.
+
+const FLAG_Z: u8 = 0x01;
+const FLAG_M: u8 = 0x02;
+const FLAG_BBOX: u8 = 0x04;
+const FLAG_GEODETIC: u8 = 0x08;
+/// Version 2 only: 8 bytes of extended flags follow the header
+const FLAG_EXTENDED: u8 = 0x10;
+/// Set in version 2 headers
+const FLAG_VERSION_2: u8 = 0x40;
+
+/// Reader of the geometry part of a gserialized value
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    has_z: bool,
+    has_m: bool,
+    /// Z and M ordinates of the vertices read so far
+    z: Vec<f64>,
+    m: Vec<f64>,
+}
+
+impl Reader<'_> {
+    fn dimensions(&self) -> usize {
+        2 + usize::from(self.has_z) + usize::from(self.has_m)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&[u8], RostGisError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| RostGisError::new("Unexpected end of PostGIS geometry"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, RostGisError> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    /// A count of elements of at least `element_size` bytes each
+    fn read_count(&mut self, element_size: usize) -> Result<usize, RostGisError> {
+        let count = self.read_u32()? as usize;
+        if count.saturating_mul(element_size) > self.bytes.len() - self.position {
+            return Err(RostGisError::new(
+                "Invalid element count in PostGIS geometry",
+            ));
+        }
+        Ok(count)
+    }
+
+    fn read_coords(&mut self, count: usize) -> Result<Vec<Coord<f64>>, RostGisError> {
+        let point_size = 8 * self.dimensions();
+        let (has_z, has_m) = (self.has_z, self.has_m);
+        let data = self.take(count * point_size)?;
+        let mut coords = Vec::with_capacity(count);
+        let (mut z, mut m) = (Vec::new(), Vec::new());
+        for point in data.chunks_exact(point_size) {
+            coords.push(Coord {
+                x: LittleEndian::read_f64(&point[..8]),
+                y: LittleEndian::read_f64(&point[8..16]),
+            });
+            // M follows Z when both are present
+            if has_z {
+                z.push(LittleEndian::read_f64(&point[16..24]));
+            }
+            if has_m {
+                let offset = if has_z { 24 } else { 16 };
+                m.push(LittleEndian::read_f64(&point[offset..offset + 8]));
+            }
+        }
+        self.z.extend(z);
+        self.m.extend(m);
+        Ok(coords)
+    }
+
+    fn read_geometry(&mut self, srid: i32, depth: usize) -> Result<Geometry, RostGisError> {
+        if depth > 32 {
+            return Err(RostGisError::new("PostGIS geometry nesting is too deep"));
+        }
+        let geometry_type = self.read_u32()?;
+        let point_size = 8 * self.dimensions();
+        Ok(match geometry_type {
+            1 => {
+                let count = self.read_count(point_size)?;
+                let point = match self.read_coords(count)?.first() {
+                    Some(coord) => Point(*coord),
+                    None => {
+                        // An empty point has NaN ordinates, as when stored
+                        if self.has_z {
+                            self.z.push(f64::NAN);
+                        }
+                        if self.has_m {
+                            self.m.push(f64::NAN);
+                        }
+                        Point::new(f64::NAN, f64::NAN)
+                    }
+                };
+                Geometry::Point(point, srid)
+            }
+            2 => {
+                let count = self.read_count(point_size)?;
+                Geometry::LineString(LineString(self.read_coords(count)?), srid)
+            }
+            3 => {
+                let ring_count = self.read_count(4)?;
+                let mut point_counts = Vec::with_capacity(ring_count);
+                for _ in 0..ring_count {
+                    point_counts.push(self.read_u32()? as usize);
+                }
+                // The ring sizes are padded to 8 bytes
+                if ring_count % 2 == 1 {
+                    self.take(4)?;
+                }
+                let mut rings = Vec::with_capacity(ring_count);
+                for count in point_counts {
+                    rings.push(LineString(self.read_coords(count)?));
+                }
+                let mut rings = rings.into_iter();
+                let exterior = rings.next().unwrap_or(LineString(vec![]));
+                Geometry::Polygon(Polygon::new(exterior, rings.collect()), srid)
+            }
+            4..=7 => {
+                let count = self.read_count(8)?;
+                let mut children = Vec::with_capacity(count);
+                for _ in 0..count {
+                    children.push(self.read_geometry(srid, depth + 1)?);
+                }
+                collect_children(geometry_type, children, srid)?
+            }
+            other => {
+                return Err(RostGisError::new(&format!(
+                    "Unsupported PostGIS geometry type: {}",
+                    other
+                )))
+            }
+        })
+    }
+}
+
+/// Decode a PostGIS gserialized value, varlena header included
+pub fn read_gserialized(bytes: &[u8]) -> Result<Geometry, RostGisError> {
+    let first = *bytes
+        .first()
+        .ok_or_else(|| RostGisError::new("Empty PostGIS geometry"))?;
+    let (header_size, size) = if first == 0x01 {
+        return Err(RostGisError::new(
+            "PostGIS geometry is a TOAST pointer; detoast it first",
+        ));
+    } else if first & 0x01 == 0x01 {
+        (1, (first >> 1) as usize)
+    } else if first & 0x03 == 0x02 {
+        return Err(RostGisError::new(
+            "PostGIS geometry is compressed; detoast it first",
+        ));
+    } else if bytes.len() < 4 {
+        return Err(RostGisError::new("Unexpected end of PostGIS geometry"));
+    } else {
+        (4, (LittleEndian::read_u32(&bytes[..4]) >> 2) as usize)
+    };
+    if size != bytes.len() {
+        return Err(RostGisError::new(&format!(
+            "PostGIS geometry header gives {} bytes, got {}",
+            size,
+            bytes.len()
+        )));
+    }
+    // Short values are stored with a 1-byte header and unaligned, but what
+    // follows the header is the same
+    let content = &bytes[header_size..];
+    let header = content
+        .get(..4)
+        .ok_or_else(|| RostGisError::new("Unexpected end of PostGIS geometry"))?;
+
+    // 21-bit signed SRID, big-endian
+    let srid = ((header[0] as i32) << 16) | ((header[1] as i32) << 8) | header[2] as i32;
+    let srid = (srid << 11) >> 11;
+    let flags = header[3];
+    let (has_z, has_m) = (flags & FLAG_Z != 0, flags & FLAG_M != 0);
+    let dimensions = 2 + usize::from(has_z) + usize::from(has_m);
+
+    let mut position = 4;
+    if flags & FLAG_VERSION_2 != 0 && flags & FLAG_EXTENDED != 0 {
+        position += 8;
+    }
+    if flags & FLAG_BBOX != 0 {
+        let box_dimensions = if flags & FLAG_GEODETIC != 0 {
+            3
+        } else {
+            dimensions
+        };
+        position += 2 * 4 * box_dimensions;
+    }
+    let mut reader = Reader {
+        bytes: content,
+        position: position.min(content.len()),
+        has_z,
+        has_m,
+        z: Vec::new(),
+        m: Vec::new(),
+    };
+    let geom = reader.read_geometry(srid, 0)?;
+    if reader.position != content.len() {
+        return Err(RostGisError::new("Trailing bytes after PostGIS geometry"));
+    }
+    geom.with_ordinates(has_z.then_some(reader.z), has_m.then_some(reader.m))
+}
+
+/// PostgreSQL function decoding a geometry stored by PostGIS
+#[pg_extern(immutable, strict, parallel_safe)]
+fn rostgis_from_postgis(data: &[u8]) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(read_gserialized(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    /// A gserialized value with a 4-byte header around `content`
+    fn value(content: &[u8]) -> Vec<u8> {
+        let mut bytes = (((content.len() + 4) as u32) << 2).to_le_bytes().to_vec();
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn doubles(values: &[f64]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_point() {
+        // PostGIS 3: 'SRID=4326;POINT(1 2)'
+        let mut content = vec![0x00, 0x10, 0xe6, 0x40];
+        content.extend(words(&[1, 1]));
+        content.extend(doubles(&[1.0, 2.0]));
+        let geom = read_gserialized(&value(&content)).unwrap();
+        assert_eq!(geom.to_wkt(), "POINT(1 2)");
+        assert_eq!(geom.srid(), 4326);
+
+        // The same value with a 1-byte header
+        let mut short = vec![(((content.len() + 1) << 1) | 1) as u8];
+        short.extend(&content);
+        assert_eq!(read_gserialized(&short).unwrap(), geom);
+
+        // PostGIS 2 flags, no SRID, POINT EMPTY
+        let mut content = vec![0, 0, 0, 0];
+        content.extend(words(&[1, 0]));
+        assert!(read_gserialized(&value(&content)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_polygon_with_bbox_and_z() {
+        // PostGIS 2: 'POLYGON Z((0 0 1, 4 0 1, 4 4 2, 0 0 1))' with its box
+        let mut content = vec![0, 0, 0, FLAG_Z | FLAG_BBOX];
+        content.extend([0u8; 24]);
+        content.extend(words(&[3, 1, 4, 0]));
+        content.extend(doubles(&[
+            0.0, 0.0, 1.0, 4.0, 0.0, 1.0, 4.0, 4.0, 2.0, 0.0, 0.0, 1.0,
+        ]));
+        assert_eq!(
+            read_gserialized(&value(&content)).unwrap().to_wkt(),
+            "POLYGON Z ((0 0 1,4 0 1,4 4 2,0 0 1))"
+        );
+
+        // PostGIS 3: 'MULTIPOINT ZM (1 2 3 4, EMPTY)'
+        let mut content = vec![0, 0, 0, FLAG_VERSION_2 | FLAG_Z | FLAG_M];
+        content.extend(words(&[4, 2, 1, 1]));
+        content.extend(doubles(&[1.0, 2.0, 3.0, 4.0]));
+        content.extend(words(&[1, 0]));
+        let geom = read_gserialized(&value(&content)).unwrap();
+        assert!(geom.has_z() && geom.has_m());
+        assert_eq!(geom.ordinates().unwrap().m.as_ref().unwrap()[0], 4.0);
+
+        // 'POINT M (1 2 5)'
+        let mut content = vec![0, 0, 0, FLAG_M];
+        content.extend(words(&[1, 1]));
+        content.extend(doubles(&[1.0, 2.0, 5.0]));
+        assert_eq!(
+            read_gserialized(&value(&content)).unwrap().to_wkt(),
+            "POINT M (1 2 5)"
+        );
+    }
+
+    #[test]
+    fn test_collections() {
+        // PostGIS 3, SRID -1 is read as such, extended flags skipped:
+        // MULTILINESTRING((0 0, 1 1), (2 2, 3 3))
+        let mut content = vec![0x1f, 0xff, 0xff, FLAG_VERSION_2 | FLAG_EXTENDED];
+        content.extend([0u8; 8]);
+        content.extend(words(&[5, 2, 2, 2]));
+        content.extend(doubles(&[0.0, 0.0, 1.0, 1.0]));
+        content.extend(words(&[2, 2]));
+        content.extend(doubles(&[2.0, 2.0, 3.0, 3.0]));
+        let geom = read_gserialized(&value(&content)).unwrap();
+        assert_eq!(
+            geom,
+            geometry_from_wkt("MULTILINESTRING((0 0, 1 1), (2 2, 3 3))")
+                .unwrap()
+                .with_srid(-1)
+        );
+
+        let mut content = vec![0, 0, 0, FLAG_VERSION_2];
+        content.extend(words(&[7, 2, 1, 1]));
+        content.extend(doubles(&[5.0, 6.0]));
+        content.extend(words(&[3, 0]));
+        assert_eq!(
+            read_gserialized(&value(&content)).unwrap().to_wkt(),
+            "GEOMETRYCOLLECTION(POINT(5 6),POLYGON EMPTY)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let mut content = vec![0, 0, 0, 0];
+        content.extend(words(&[1, 1]));
+        content.extend(doubles(&[1.0, 2.0]));
+        let mut bytes = value(&content);
+        assert!(read_gserialized(&bytes[..bytes.len() - 1]).is_err());
+        bytes[0] |= 0x02;
+        assert!(read_gserialized(&bytes).is_err());
+        assert!(read_gserialized(&[0x01, 0x12]).is_err());
+        assert!(read_gserialized(&[]).is_err());
+
+        // A circular string
+        let mut content = vec![0, 0, 0, 0];
+        content.extend(words(&[8, 0]));
+        assert!(read_gserialized(&value(&content)).is_err());
+        // More points than bytes
+        let mut content = vec![0, 0, 0, 0];
+        content.extend(words(&[2, 1000]));
+        assert!(read_gserialized(&value(&content)).is_err());
+    }
+}
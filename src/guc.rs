@@ -0,0 +1,255 @@
+use crate::utils::{srid, RostGisError};
+use pgrx::prelude::*;
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
+
+/// Coordinate axis order used by text/XML/JSON writers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PostgresGucEnum)]
+pub enum AxisOrder {
+    /// x/longitude first (GeoJSON, WKT and most software)
+    LonLat,
+    /// y/latitude first (OGC-strict EPSG:4326 axis order)
+    LatLon,
+}
+
+impl AxisOrder {
+    /// Parse a per-call axis order option
+    pub fn parse(value: &str) -> Result<Self, RostGisError> {
+        match value.trim().to_lowercase().as_str() {
+            "lonlat" | "xy" => Ok(AxisOrder::LonLat),
+            "latlon" | "yx" => Ok(AxisOrder::LatLon),
+            other => Err(RostGisError::new(&format!(
+                "Invalid axis order '{}': expected 'lonlat' or 'latlon'",
+                other
+            ))),
+        }
+    }
+
+    /// Order a coordinate pair for output
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            AxisOrder::LonLat => (x, y),
+            AxisOrder::LatLon => (y, x),
+        }
+    }
+}
+
+/// Body encoding of stored geometries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PostgresGucEnum)]
+pub enum StorageEncoding {
+    /// Little-endian WKB (the default)
+    Wkb,
+    /// Delta-encoded varint coordinates, see the compact module
+    Compact,
+}
+
+/// rostgis.axis_order: default axis order for GeoJSON and GML output
+pub static AXIS_ORDER: GucSetting<AxisOrder> = GucSetting::<AxisOrder>::new(AxisOrder::LonLat);
+
+/// rostgis.strict_axis_order: whether EPSG:4326 coordinates are exchanged in
+/// OGC-strict latitude/longitude order by the WKT functions, GeoJSON, GML and
+/// the boxes of ST_Transform; they are stored, written by the type output and
+/// as WKB in longitude/latitude order either way
+pub static STRICT_AXIS_ORDER: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// rostgis.check_lonlat_range: whether geometry input warns about SRID 4326
+/// coordinates outside the longitude/latitude range
+pub static CHECK_LONLAT_RANGE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// rostgis.output_precision: decimal places of coordinates in ST_AsText,
+/// ST_AsGeoJSON and ST_AsGML; -1 writes the shortest lossless digits
+pub static OUTPUT_PRECISION: GucSetting<i32> = GucSetting::<i32>::new(-1);
+
+/// rostgis.trust_valid_flag: whether ST_IsValid and the ST_Union inputs may
+/// rely on the known-valid flag stored by ST_MakeValid instead of re-checking
+pub static TRUST_VALID_FLAG: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/// rostgis.stats: whether to collect the usage counters of rostgis_stat
+pub static STATS: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// rostgis.storage_encoding: body encoding of the geometries this session
+/// stores; columns declared with the Compact typmod always use compact
+pub static STORAGE_ENCODING: GucSetting<StorageEncoding> =
+    GucSetting::<StorageEncoding>::new(StorageEncoding::Wkb);
+
+/// rostgis.overlay_fallback: whether a polygon overlay that fails on
+/// robustness issues is retried on snapped inputs instead of erroring
+pub static OVERLAY_FALLBACK: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/// rostgis.gist_fill_factor: smallest share of the entries of a GiST page,
+/// in percent, that each side of a page split keeps
+pub static GIST_FILL_FACTOR: GucSetting<i32> = GucSetting::<i32>::new(40);
+
+/// rostgis.gist_margin_weight: weight of the margin enlargement against the
+/// area enlargement in the GiST insertion penalty
+pub static GIST_MARGIN_WEIGHT: GucSetting<f64> = GucSetting::<f64>::new(0.0);
+
+/// Axis order for exchanging a geometry of `srid`: an explicit option wins,
+/// then latlon for EPSG:4326 in strict mode, then `default`
+pub fn axis_order_for(
+    srid: i32,
+    explicit: Option<AxisOrder>,
+    default: AxisOrder,
+    strict: bool,
+) -> AxisOrder {
+    match explicit {
+        Some(order) => order,
+        None if strict && srid == srid::WGS84 => AxisOrder::LatLon,
+        None => default,
+    }
+}
+
+/// Resolve a per-call axis order option for GeoJSON or GML of a geometry of
+/// `srid`, falling back to rostgis.strict_axis_order and rostgis.axis_order
+pub fn resolve_axis_order(value: Option<&str>, srid: i32) -> Result<AxisOrder, RostGisError> {
+    let explicit = value.map(AxisOrder::parse).transpose()?;
+    Ok(axis_order_for(
+        srid,
+        explicit,
+        AXIS_ORDER.get(),
+        STRICT_AXIS_ORDER.get(),
+    ))
+}
+
+/// Axis order of WKT and of the boxes of ST_Transform for `srid`, which is
+/// lonlat unless rostgis.strict_axis_order applies
+pub fn text_axis_order(srid: i32) -> AxisOrder {
+    axis_order_for(srid, None, AxisOrder::LonLat, STRICT_AXIS_ORDER.get())
+}
+
+/// Decimal places of coordinates in text output: a per-call maxdecimaldigits
+/// wins over rostgis.output_precision; None (-1) is the shortest lossless
+/// form
+pub fn output_precision(explicit: Option<i32>) -> Result<Option<u32>, RostGisError> {
+    let digits = explicit.unwrap_or_else(|| OUTPUT_PRECISION.get());
+    match digits {
+        -1 => Ok(None),
+        0..=15 => Ok(Some(digits as u32)),
+        _ => Err(RostGisError::new(&format!(
+            "Invalid maxdecimaldigits {}: expected -1 (full precision) or 0 to 15",
+            digits
+        ))),
+    }
+}
+
+/// Register all RostGIS configuration parameters
+pub fn init() {
+    GucRegistry::define_enum_guc(
+        c"rostgis.axis_order",
+        c"Default coordinate axis order for GeoJSON and GML output.",
+        c"Either lonlat (x/y, the default) or latlon (y/x, OGC-strict EPSG:4326 order).",
+        &AXIS_ORDER,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"rostgis.strict_axis_order",
+        c"Exchange EPSG:4326 coordinates in latitude/longitude order.",
+        c"When on, ST_GeomFromText, ST_AsText, ST_AsGeoJSON, ST_AsGML and ST_Transform of boxes use the OGC-strict latitude/longitude order for SRID 4326, as WFS 2.0 services expect. Storage, the text form of the geometry type and WKB keep longitude/latitude.",
+        &STRICT_AXIS_ORDER,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"rostgis.check_lonlat_range",
+        c"Warn about SRID 4326 input with coordinates out of range.",
+        c"When on, geometry input, ST_GeomFromText, ST_GeomFromWKB and ST_SetSRID raise a WARNING for SRID 4326 geometries with coordinates outside [-180 -90, 180 90], and point out coordinates that look like latitude/longitude.",
+        &CHECK_LONLAT_RANGE,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"rostgis.output_precision",
+        c"Decimal places of coordinates in ST_AsText, ST_AsGeoJSON and ST_AsGML.",
+        c"Coordinates are rounded to at most this many decimal places, without trailing zeros; -1 (the default) writes the shortest digits that read back to the same value. The maxdecimaldigits argument of each function overrides it. Type output (geometry, bbox and box3d text) is always lossless.",
+        &OUTPUT_PRECISION,
+        -1,
+        15,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"rostgis.trust_valid_flag",
+        c"Trust the known-valid flag stored with geometries.",
+        c"When on (the default), ST_IsValid and ST_Union skip the validity check of geometries stored by ST_MakeValid.",
+        &TRUST_VALID_FLAG,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"rostgis.stats",
+        c"Collect RostGIS usage counters.",
+        c"Counts are reported by rostgis_stat(); they cover all backends when RostGIS is in shared_preload_libraries.",
+        &STATS,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"rostgis.storage_encoding",
+        c"Encoding of stored geometries.",
+        c"Either wkb (the default) or compact, which stores coordinates as delta-encoded varints at the smallest lossless decimal precision. Both are read transparently.",
+        &STORAGE_ENCODING,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"rostgis.overlay_fallback",
+        c"Retry failed overlays on snapped inputs.",
+        c"When on (the default), a union, intersection, difference or buffer that fails on nearly coincident edges is retried with its inputs snapped to a grid relative to their magnitude, and the retry is reported with a NOTICE.",
+        &OVERLAY_FALLBACK,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"rostgis.gist_fill_factor",
+        c"Smallest share of entries, in percent, each side of a GiST page split keeps.",
+        c"Page splits of the rostgis_gist_ops index choose the split with the least overlap among those leaving at least this share of the entries on each page. Lower values allow tighter but less full pages; 50 always splits in half.",
+        &GIST_FILL_FACTOR,
+        10,
+        50,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_float_guc(
+        c"rostgis.gist_margin_weight",
+        c"Weight of the margin enlargement in the GiST insertion penalty.",
+        c"The penalty of inserting an entry into an index page is the enlargement of the page's area plus this weight times the enlargement of its margin (half perimeter). The default 0 only considers area; a positive weight favours square pages, and matters most for points, which have no area.",
+        &GIST_MARGIN_WEIGHT,
+        0.0,
+        1e6,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axis_order_parse() {
+        assert_eq!(AxisOrder::parse("LatLon").unwrap(), AxisOrder::LatLon);
+        assert_eq!(AxisOrder::parse("xy").unwrap(), AxisOrder::LonLat);
+        assert!(AxisOrder::parse("zyx").is_err());
+        assert_eq!(AxisOrder::LatLon.apply(1.0, 2.0), (2.0, 1.0));
+    }
+
+    #[test]
+    fn test_axis_order_for() {
+        use AxisOrder::*;
+        // Strict mode only concerns EPSG:4326
+        assert_eq!(axis_order_for(4326, None, LonLat, true), LatLon);
+        assert_eq!(axis_order_for(3857, None, LonLat, true), LonLat);
+        assert_eq!(axis_order_for(4326, None, LonLat, false), LonLat);
+        assert_eq!(axis_order_for(0, None, LatLon, false), LatLon);
+        // An explicit option wins
+        assert_eq!(axis_order_for(4326, Some(LonLat), LonLat, true), LonLat);
+    }
+
+    #[test]
+    fn test_explicit_output_precision() {
+        assert_eq!(output_precision(Some(6)).unwrap(), Some(6));
+        assert_eq!(output_precision(Some(-1)).unwrap(), None);
+        assert!(output_precision(Some(16)).is_err());
+        assert!(output_precision(Some(-2)).is_err());
+    }
+}
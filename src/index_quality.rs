@@ -0,0 +1,252 @@
+use crate::spatial_index::BBox;
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+// GiST index quality (rostgis_index_quality)
+//
+// Measures how well the pages of a rostgis_gist_ops index partition space,
+// to compare settings of rostgis.gist_fill_factor and
+// rostgis.gist_margin_weight on a rebuilt index:
+//
+//   SET rostgis.gist_margin_weight = 1;
+//   REINDEX INDEX roads_geom_idx;
+//   SELECT * FROM rostgis_index_quality('roads_geom_idx');
+//
+// The index is given as a regclass, so its name is resolved like any
+// relation name, through the search_path or qualified with its schema.
+//
+// overlap is, over the inner pages, the area shared by pairs of entries
+// relative to the area of the entries: 0 when no two subtrees overlap, and
+// the lower the fewer subtrees a search descends into. coverage is, over the
+// leaf pages, the area of the entries relative to the area of the page's
+// box: near 1 when pages hold no dead space. Both are averaged over the
+// pages whose entries have an area, and are NULL when none has, as for the
+// leaf pages of an index of points. The keys of empty geometries, which have
+// no box, count as entries but not towards the areas. Pages are read with
+// the pageinspect extension, which must be installed and needs superuser.
+
+/// Entries of an index page
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexPage {
+    pub leaf: bool,
+    pub keys: Vec<BBox>,
+}
+
+/// Statistics of the pages of an index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexQuality {
+    pub pages: i64,
+    pub leaf_pages: i64,
+    pub entries: i64,
+    pub avg_leaf_entries: Option<f64>,
+    pub overlap: Option<f64>,
+    pub coverage: Option<f64>,
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+impl IndexQuality {
+    /// Measure the pages of an index
+    pub fn measure(pages: &[IndexPage]) -> Self {
+        let leaves: Vec<&IndexPage> = pages.iter().filter(|page| page.leaf).collect();
+        let entries: usize = leaves.iter().map(|page| page.keys.len()).sum();
+        let leaf_entries: Vec<f64> = leaves.iter().map(|page| page.keys.len() as f64).collect();
+
+        let mut overlaps = Vec::new();
+        let mut coverages = Vec::new();
+        for page in pages {
+            let boxes: Vec<&BBox> = page.keys.iter().filter(|key| !key.is_empty()).collect();
+            let area: f64 = boxes.iter().map(|key| key.area()).sum();
+            if area <= 0.0 {
+                continue;
+            }
+            if page.leaf {
+                let cover = boxes[1..]
+                    .iter()
+                    .fold(boxes[0].clone(), |cover, key| cover.union(key));
+                coverages.push(area / cover.area());
+            } else {
+                let mut shared = 0.0;
+                for (i, a) in boxes.iter().enumerate() {
+                    for b in &boxes[i + 1..] {
+                        shared += a.intersection_area(b);
+                    }
+                }
+                overlaps.push(shared / area);
+            }
+        }
+
+        IndexQuality {
+            pages: pages.len() as i64,
+            leaf_pages: leaves.len() as i64,
+            entries: entries as i64,
+            avg_leaf_entries: mean(&leaf_entries),
+            overlap: mean(&overlaps),
+            coverage: mean(&coverages),
+        }
+    }
+}
+
+/// Pages of a GiST index, read with pageinspect
+fn read_pages(
+    index: pg_sys::Oid,
+) -> Result<Vec<IndexPage>, Box<dyn std::error::Error + Send + Sync>> {
+    let (name, method) = Spi::get_two_with_args::<String, String>(
+        "SELECT $1::regclass::text,
+                (SELECT am.amname::text FROM pg_class c JOIN pg_am am ON am.oid = c.relam
+                 WHERE c.oid = $1)",
+        &[index.into()],
+    )?;
+    if method.as_deref() != Some("gist") {
+        return Err(format!(
+            "rostgis_index_quality: {} is not a GiST index",
+            name.unwrap_or_default()
+        )
+        .into());
+    }
+    if Spi::get_one::<bool>("SELECT to_regprocedure('gist_page_items(bytea, regclass)') IS NULL")?
+        .unwrap_or(true)
+    {
+        return Err("rostgis_index_quality requires the pageinspect extension".into());
+    }
+
+    Spi::connect(|client| {
+        let rows = client.select(
+            "SELECT blkno, 'leaf' = ANY(o.flags), i.keys
+             FROM generate_series(0, pg_relation_size($1::regclass)
+                                     / current_setting('block_size')::bigint - 1) AS blkno,
+                  LATERAL get_raw_page($1::regclass::text, blkno::int) AS page,
+                  LATERAL gist_page_opaque_info(page) AS o
+                  LEFT JOIN LATERAL gist_page_items(page, $1::regclass) AS i ON true
+             WHERE NOT 'deleted' = ANY(o.flags) AND NOT coalesce(i.dead, false)
+             ORDER BY blkno, i.itemoffset",
+            None,
+            &[index.into()],
+        )?;
+        let mut pages: Vec<IndexPage> = Vec::new();
+        let mut current = None;
+        for row in rows {
+            let (Some(blkno), Some(leaf)) = (row.get::<i64>(1)?, row.get::<bool>(2)?) else {
+                continue;
+            };
+            if current != Some(blkno) {
+                current = Some(blkno);
+                pages.push(IndexPage {
+                    leaf,
+                    keys: Vec::new(),
+                });
+            }
+            let key = row.get::<String>(3)?.and_then(|keys| {
+                let start = keys.find("BOX(")?;
+                BBox::parse(&keys[start..])
+            });
+            if let (Some(key), Some(page)) = (key, pages.last_mut()) {
+                page.keys.push(key);
+            }
+        }
+        Ok(pages)
+    })
+}
+
+/// PostgreSQL function measuring the overlap and coverage of the pages of a
+/// GiST index. It takes a regclass, which pgrx has no Rust type for, so its
+/// SQL signature is declared in the extension_sql! block below.
+#[allow(clippy::type_complexity)]
+#[pg_extern(strict, sql = false)]
+fn rostgis_index_quality(
+    index: pg_sys::Oid,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(pages, i64),
+            name!(leaf_pages, i64),
+            name!(entries, i64),
+            name!(avg_leaf_entries, Option<f64>),
+            name!(overlap, Option<f64>),
+            name!(coverage, Option<f64>),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let quality = IndexQuality::measure(&read_pages(index)?);
+    Ok(TableIterator::once((
+        quality.pages,
+        quality.leaf_pages,
+        quality.entries,
+        quality.avg_leaf_entries,
+        quality.overlap,
+        quality.coverage,
+    )))
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.rostgis_index_quality(index regclass)
+    RETURNS TABLE (
+        pages bigint,
+        leaf_pages bigint,
+        entries bigint,
+        avg_leaf_entries double precision,
+        overlap double precision,
+        coverage double precision
+    )
+    STRICT LANGUAGE c AS 'MODULE_PATHNAME', 'rostgis_index_quality_wrapper';
+"#,
+    name = "rostgis_index_quality",
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let leaf = |keys: Vec<BBox>| IndexPage { leaf: true, keys };
+        let pages = vec![
+            IndexPage {
+                leaf: false,
+                keys: vec![BBox::new(0.0, 0.0, 2.0, 2.0), BBox::new(1.0, 1.0, 3.0, 3.0)],
+            },
+            leaf(vec![
+                BBox::new(0.0, 0.0, 1.0, 1.0),
+                BBox::new(1.0, 1.0, 2.0, 2.0),
+            ]),
+            leaf(vec![BBox::new(1.0, 1.0, 3.0, 3.0)]),
+        ];
+        let quality = IndexQuality::measure(&pages);
+        assert_eq!(quality.pages, 3);
+        assert_eq!(quality.leaf_pages, 2);
+        assert_eq!(quality.entries, 3);
+        assert_eq!(quality.avg_leaf_entries, Some(1.5));
+        // 1 of 8 units of area is shared
+        assert_eq!(quality.overlap, Some(0.125));
+        // Half of the first page is dead space, none of the second
+        assert_eq!(quality.coverage, Some(0.75));
+    }
+
+    #[test]
+    fn test_measure_points() {
+        let point = BBox::new(1.0, 1.0, 1.0, 1.0);
+        let quality = IndexQuality::measure(&[IndexPage {
+            leaf: true,
+            keys: vec![point.clone(), point],
+        }]);
+        assert_eq!(quality.entries, 2);
+        assert_eq!(quality.overlap, None);
+        assert_eq!(quality.coverage, None);
+        assert_eq!(IndexQuality::measure(&[]).avg_leaf_entries, None);
+    }
+
+    #[test]
+    fn test_measure_empty_keys() {
+        let quality = IndexQuality::measure(&[IndexPage {
+            leaf: true,
+            keys: vec![BBox::empty(), BBox::new(0.0, 0.0, 1.0, 1.0)],
+        }]);
+        assert_eq!(quality.entries, 2);
+        assert_eq!(quality.coverage, Some(1.0));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::{GeometryWithId, SpatialIndex};
+use crate::utils::{aggregate_context, RostGisError};
+use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
+
+// Inverse distance weighted interpolation (ST_IDW, ST_IDWGrid)
+//
+// The aggregates collect (point, value) samples in their transition state
+// and interpolate at the query point(s) in the final function, looking up
+// the nearest samples in an R*-tree built once per group.
+
+/// Default power of the inverse distance weights
+pub const DEFAULT_POWER: f64 = 2.0;
+
+/// Default number of nearest samples used for each estimate
+pub const DEFAULT_NEIGHBORS: i32 = 12;
+
+/// Samples collected for interpolation
+#[derive(Debug, Default)]
+pub struct IdwSamples {
+    points: Vec<GeometryWithId>,
+    values: Vec<f64>,
+}
+
+impl IdwSamples {
+    /// Add a sample; empty points and non-finite values are skipped
+    pub fn add(&mut self, sample: &Geometry, value: f64) -> Result<(), RostGisError> {
+        if !matches!(sample, Geometry::Point(..)) {
+            return Err(RostGisError::new("ST_IDW samples must be points"));
+        }
+        if sample.is_empty() || !value.is_finite() {
+            return Ok(());
+        }
+        self.points.push(GeometryWithId::new(
+            self.values.len() as i64,
+            sample.clone(),
+        ));
+        self.values.push(value);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Interpolate at each query location
+    ///
+    /// `neighbors` limits the estimate to the nearest samples, 0 using all of
+    /// them. A location without samples gives None.
+    pub fn interpolate(
+        &self,
+        locations: &[[f64; 2]],
+        power: f64,
+        neighbors: i32,
+    ) -> Result<Vec<Option<f64>>, RostGisError> {
+        if !power.is_finite() || power <= 0.0 {
+            return Err(RostGisError::new("IDW power must be positive"));
+        }
+        if neighbors < 0 {
+            return Err(RostGisError::new("IDW neighbor count cannot be negative"));
+        }
+
+        let index = SpatialIndex::from_geometries(self.points.clone());
+        let k = if neighbors == 0 {
+            self.len()
+        } else {
+            neighbors as usize
+        };
+        Ok(locations
+            .iter()
+            .map(|&location| idw_estimate(&index, &self.values, location, power, k))
+            .collect())
+    }
+}
+
+/// Weighted average of the k nearest samples around a location
+///
+/// A sample at the location itself is returned exactly (averaged when
+/// several coincide) instead of dividing by a zero distance.
+fn idw_estimate(
+    index: &SpatialIndex,
+    values: &[f64],
+    location: [f64; 2],
+    power: f64,
+    k: usize,
+) -> Option<f64> {
+    let (mut weighted, mut weights) = (0.0, 0.0);
+    let (mut exact, mut exact_count) = (0.0, 0);
+    for sample in index.k_nearest_neighbors(location, k) {
+        let value = values[sample.id as usize];
+        let dx = sample.bbox.min_x - location[0];
+        let dy = sample.bbox.min_y - location[1];
+        let distance = dx.hypot(dy);
+        if distance == 0.0 {
+            exact += value;
+            exact_count += 1;
+        } else if exact_count == 0 {
+            let weight = distance.powf(-power);
+            weighted += weight * value;
+            weights += weight;
+        }
+    }
+
+    if exact_count > 0 {
+        Some(exact / exact_count as f64)
+    } else if weights > 0.0 {
+        Some(weighted / weights)
+    } else {
+        None
+    }
+}
+
+/// Query locations of a Point or MultiPoint, empty points giving None
+fn query_locations(query: &Geometry) -> Result<Vec<Option<[f64; 2]>>, RostGisError> {
+    match query {
+        Geometry::Point(point, _) => Ok(vec![(!query.is_empty()).then(|| [point.x(), point.y()])]),
+        Geometry::MultiPoint(points, _) => Ok(points
+            .0
+            .iter()
+            .map(|p| (!p.x().is_nan()).then(|| [p.x(), p.y()]))
+            .collect()),
+        _ => Err(RostGisError::new(
+            "IDW query locations must be a point or multipoint",
+        )),
+    }
+}
+
+/// Transition state of the IDW aggregates
+///
+/// The query, power and neighbor count are taken from the first row.
+struct IdwState {
+    samples: IdwSamples,
+    query: Geometry,
+    power: f64,
+    neighbors: i32,
+}
+
+impl IdwState {
+    fn estimates(&self) -> Result<Vec<Option<f64>>, RostGisError> {
+        let locations = query_locations(&self.query)?;
+        let known: Vec<[f64; 2]> = locations.iter().flatten().copied().collect();
+        let mut estimates = self
+            .samples
+            .interpolate(&known, self.power, self.neighbors)?
+            .into_iter();
+        Ok(locations
+            .iter()
+            .map(|location| location.and_then(|_| estimates.next().flatten()))
+            .collect())
+    }
+}
+
+/// Add a row to the aggregate state, allocated in the aggregate context
+unsafe fn idw_accumulate(
+    fcinfo: pg_sys::FunctionCallInfo,
+    mut state: Internal,
+    sample: Option<Geometry>,
+    value: Option<f64>,
+    query: Option<Geometry>,
+    power: f64,
+    neighbors: i32,
+) -> Result<Internal, RostGisError> {
+    let aggcontext = aggregate_context(fcinfo, "ST_IDW transition")?;
+    let Some(query) = query else {
+        return Err(RostGisError::new("ST_IDW query location cannot be NULL"));
+    };
+
+    let idw = PgMemoryContexts::For(aggcontext).switch_to(|_| {
+        state.get_or_insert_with(|| IdwState {
+            samples: IdwSamples::default(),
+            query,
+            power,
+            neighbors,
+        })
+    });
+    if let (Some(sample), Some(value)) = (sample, value) {
+        idw.samples.add(&sample, value)?;
+    }
+    Ok(state)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_idw_transfn(
+    state: Internal,
+    sample: Option<Geometry>,
+    value: Option<f64>,
+    query: Option<Geometry>,
+    power: Option<f64>,
+    neighbors: Option<i32>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe {
+        idw_accumulate(
+            fcinfo,
+            state,
+            sample,
+            value,
+            query,
+            power.unwrap_or(DEFAULT_POWER),
+            neighbors.unwrap_or(DEFAULT_NEIGHBORS),
+        )?
+    })
+}
+
+#[pg_extern(immutable, parallel_safe, name = "st_idw_transfn")]
+fn st_idw_transfn_power(
+    state: Internal,
+    sample: Option<Geometry>,
+    value: Option<f64>,
+    query: Option<Geometry>,
+    power: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe {
+        idw_accumulate(
+            fcinfo,
+            state,
+            sample,
+            value,
+            query,
+            power.unwrap_or(DEFAULT_POWER),
+            DEFAULT_NEIGHBORS,
+        )?
+    })
+}
+
+#[pg_extern(immutable, parallel_safe, name = "st_idw_transfn")]
+fn st_idw_transfn_default(
+    state: Internal,
+    sample: Option<Geometry>,
+    value: Option<f64>,
+    query: Option<Geometry>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe {
+        idw_accumulate(
+            fcinfo,
+            state,
+            sample,
+            value,
+            query,
+            DEFAULT_POWER,
+            DEFAULT_NEIGHBORS,
+        )?
+    })
+}
+
+/// Final function of ST_IDW: the estimate at the query point
+#[pg_extern(immutable, parallel_safe)]
+pub fn st_idw_finalfn(
+    state: Internal,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(idw) = (unsafe { state.get::<IdwState>() }) else {
+        return Ok(None);
+    };
+    if !matches!(idw.query, Geometry::Point(..)) {
+        return Err("ST_IDW query location must be a point, use ST_IDWGrid for several".into());
+    }
+    Ok(idw.estimates()?.into_iter().next().flatten())
+}
+
+/// Final function of ST_IDWGrid: one estimate per query point
+#[pg_extern(immutable, parallel_safe)]
+pub fn st_idwgrid_finalfn(
+    state: Internal,
+) -> Result<Option<Vec<Option<f64>>>, Box<dyn std::error::Error + Send + Sync>> {
+    match unsafe { state.get::<IdwState>() } {
+        Some(idw) => Ok(Some(idw.estimates()?)),
+        None => Ok(None),
+    }
+}
+
+extension_sql!(
+    r#"
+CREATE AGGREGATE @extschema@.st_idw(@extschema@.geometry, float8, @extschema@.geometry) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idw_finalfn
+);
+CREATE AGGREGATE @extschema@.st_idw(@extschema@.geometry, float8, @extschema@.geometry, float8) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idw_finalfn
+);
+CREATE AGGREGATE @extschema@.st_idw(@extschema@.geometry, float8, @extschema@.geometry, float8, integer) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idw_finalfn
+);
+
+CREATE AGGREGATE @extschema@.st_idwgrid(@extschema@.geometry, float8, @extschema@.geometry) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idwgrid_finalfn
+);
+CREATE AGGREGATE @extschema@.st_idwgrid(@extschema@.geometry, float8, @extschema@.geometry, float8) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idwgrid_finalfn
+);
+CREATE AGGREGATE @extschema@.st_idwgrid(@extschema@.geometry, float8, @extschema@.geometry, float8, integer) (
+    SFUNC = @extschema@.st_idw_transfn, STYPE = internal, FINALFUNC = @extschema@.st_idwgrid_finalfn
+);
+"#,
+    name = "idw_aggregates",
+    requires = [
+        Geometry,
+        st_idw_transfn,
+        st_idw_transfn_power,
+        st_idw_transfn_default,
+        st_idw_finalfn,
+        st_idwgrid_finalfn
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn samples(points: &[(f64, f64, f64)]) -> IdwSamples {
+        let mut samples = IdwSamples::default();
+        for &(x, y, value) in points {
+            samples.add(&make_point(x, y), value).unwrap();
+        }
+        samples
+    }
+
+    #[test]
+    fn test_idw_weights() {
+        let samples = samples(&[(0.0, 0.0, 10.0), (2.0, 0.0, 20.0)]);
+        let estimates = samples
+            .interpolate(&[[1.0, 0.0], [0.5, 0.0], [2.0, 0.0]], 2.0, 0)
+            .unwrap();
+        assert_eq!(estimates[0], Some(15.0));
+        // Weights 1/0.25 and 1/2.25
+        let expected = (10.0 / 0.25 + 20.0 / 2.25) / (1.0 / 0.25 + 1.0 / 2.25);
+        assert!((estimates[1].unwrap() - expected).abs() < 1e-12);
+        // Exact hit on a sample
+        assert_eq!(estimates[2], Some(20.0));
+    }
+
+    #[test]
+    fn test_idw_nearest_neighbors() {
+        let samples = samples(&[(0.0, 0.0, 1.0), (1.0, 0.0, 1.0), (100.0, 0.0, 1000.0)]);
+        let all = samples.interpolate(&[[0.5, 0.0]], 1.0, 0).unwrap();
+        let nearest = samples.interpolate(&[[0.5, 0.0]], 1.0, 2).unwrap();
+        assert!(all[0].unwrap() > 1.0);
+        assert_eq!(nearest[0], Some(1.0));
+
+        assert!(samples.interpolate(&[[0.5, 0.0]], 0.0, 2).is_err());
+        assert!(samples.interpolate(&[[0.5, 0.0]], 2.0, -1).is_err());
+        assert_eq!(
+            IdwSamples::default()
+                .interpolate(&[[0.0, 0.0]], 2.0, 0)
+                .unwrap(),
+            vec![None]
+        );
+    }
+
+    #[test]
+    fn test_idw_grid_locations() {
+        let mut state = IdwState {
+            samples: samples(&[(0.0, 0.0, 4.0)]),
+            query: geometry_from_wkt("MULTIPOINT((1 1), (2 2))").unwrap(),
+            power: 2.0,
+            neighbors: 0,
+        };
+        assert_eq!(state.estimates().unwrap(), vec![Some(4.0), Some(4.0)]);
+        state.query = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert_eq!(state.estimates().unwrap(), vec![None]);
+        state.query = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(state.estimates().is_err());
+
+        let mut samples = IdwSamples::default();
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(samples.add(&line, 1.0).is_err());
+        samples.add(&make_point(0.0, 0.0), f64::NAN).unwrap();
+        assert!(samples.is_empty());
+    }
+}
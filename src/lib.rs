@@ -4,137 +4,841 @@ use pgrx::prelude::*;
 ::pgrx::pg_module_magic!();
 
 // Re-export modules
+pub mod affine;
+pub mod analyze;
+pub mod autocorrelation;
+pub mod box3d;
+pub mod buffer;
+pub mod build_area;
+pub mod clustering;
+pub mod compact;
+pub mod dateline;
+pub mod dimensions;
+pub mod direction;
+pub mod dissolve;
+pub mod dump;
+pub mod ewkb;
+pub mod explain;
+pub mod features;
+pub mod fences;
 pub mod functions;
+pub mod geography;
+pub mod geojsonseq;
 pub mod geometry;
+pub mod gist_nd;
+pub mod great_circle;
+pub mod grid_partition;
+pub mod gserialized;
+pub mod guc;
+pub mod index_quality;
+pub mod interpolation;
+pub mod line_merge;
+pub mod lonlat;
+pub mod map_matching;
+pub mod measure3d;
+#[cfg(feature = "mvt")]
+pub mod mvt;
+pub mod nearest;
+pub mod noding;
+pub mod operator_catalog;
+pub mod ordering;
+pub mod orthogonalize;
+pub mod overlay;
+pub mod precision;
+pub mod prepared;
+pub mod profile;
+#[cfg(feature = "raster")]
+pub mod render;
+pub mod sampling;
+pub mod serialization;
+pub mod session_store;
+pub mod simplify;
+pub mod skeleton;
+pub mod solid;
 pub mod spatial_index;
+pub mod spatial_ref_sys;
+pub mod stats;
+pub mod subdivide;
+pub mod table_cache;
+#[cfg(feature = "proj")]
+pub mod transform;
+pub mod typmod;
+pub mod union;
 pub mod utils;
 pub mod vectorized_ops;
+pub mod watch;
 
 use functions::*;
 use geometry::Geometry;
+use serialization::GeometryHeader;
 // Import spatial indexing support
 // Note: GistBBox functions available but using simpler bbox approach for now
 use spatial_index::BBox;
 
 // Extension initialization
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    guc::init();
+    stats::init();
+    fences::init();
+}
+
 #[pg_extern]
 fn rostgis_version() -> &'static str {
     "RostGIS 0.1.0 - PostGIS-compatible spatial extension for PostgreSQL"
 }
 
+// NULL and empty geometries
+//
+// Functions are STRICT, so NULL in gives NULL out, unless an optional
+// argument has to accept NULL; those map a NULL geometry to NULL
+// themselves. Empty geometries behave as in PostGIS: measures are 0,
+// ST_Distance and Box2D are NULL, ST_Envelope is the empty geometry itself,
+// predicates and box operators are false except ST_Equals between two
+// empties, and aggregates skip NULLs.
+
 // Core geometry creation functions
-#[pg_extern]
+/// Read WKT or EWKT in the axis order of rostgis.strict_axis_order
+fn geometry_from_text_input(
+    wkt: &str,
+    srid: Option<i32>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let mut geom = parse_ewkt(wkt)?;
+    if let Some(srid) = srid {
+        geom = geom.with_srid(srid);
+    }
+    let axis_order = guc::text_axis_order(geom.srid());
+    let geom = with_axis_order(geom, axis_order);
+    lonlat::check_lon_lat_range(&geom);
+    Ok(geom)
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
 fn st_geomfromtext(wkt: &str) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
-    geometry_from_wkt(wkt)
+    geometry_from_text_input(wkt, None)
 }
 
-#[pg_extern]
+#[pg_extern(stable, strict, parallel_safe, name = "st_geomfromtext")]
+fn st_geomfromtext_srid(
+    wkt: &str,
+    srid: i32,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    geometry_from_text_input(wkt, Some(srid))
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
 fn st_geomfromwkt(wkt: &str) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
-    geometry_from_wkt(wkt)
+    geometry_from_text_input(wkt, None)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_geomfromwkb(wkb_hex: &str) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
-    geometry_from_wkb(wkb_hex)
+    let bytes = utils::hex_to_bytes(wkb_hex.trim())?;
+    let geom = ewkb::read_wkb(&bytes)?;
+    lonlat::check_lon_lat_range(&geom);
+    Ok(geom)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_makepoint(x: f64, y: f64) -> Geometry {
     make_point(x, y)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_point(x: f64, y: f64) -> Geometry {
     make_point(x, y)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_makepointz(x: f64, y: f64, z: f64) -> Geometry {
     make_point_z(x, y, z)
 }
 
+#[pg_extern(immutable, strict, parallel_safe, name = "st_makeline")]
+fn st_makeline_arrays(
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    make_line_from_arrays(&xs, &ys, None)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_makeline")]
+fn st_makeline_arrays_z(
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    zs: Vec<f64>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    make_line_from_arrays(&xs, &ys, Some(&zs))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_makepolygonfromarrays")]
+fn st_makepolygonfromarrays(
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    make_polygon_from_arrays(&xs, &ys, None)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_makepolygonfromarrays")]
+fn st_makepolygonfromarrays_z(
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    zs: Vec<f64>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    make_polygon_from_arrays(&xs, &ys, Some(&zs))
+}
+
 // Geometry output functions
-#[pg_extern]
-fn st_astext(geom: Geometry) -> String {
-    geometry_as_text(geom)
+//
+// Coordinates are written with rostgis.output_precision decimal places, or
+// maxdecimaldigits where given; see utils::format_number
+fn geometry_as_text_with_precision(
+    geom: Geometry,
+    maxdecimaldigits: Option<i32>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let precision = guc::output_precision(maxdecimaldigits)?;
+    let axis_order = guc::text_axis_order(geom.srid());
+    Ok(with_axis_order(geom, axis_order).to_wkt_with_precision(precision))
 }
 
-#[pg_extern]
-fn st_aswkt(geom: Geometry) -> String {
-    geometry_as_text(geom)
+#[pg_extern(stable, strict, parallel_safe)]
+fn st_astext(geom: Geometry) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    geometry_as_text_with_precision(geom, None)
 }
 
-#[pg_extern]
+#[pg_extern(stable, strict, parallel_safe, name = "st_astext")]
+fn st_astext_precision(
+    geom: Geometry,
+    maxdecimaldigits: i32,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    geometry_as_text_with_precision(geom, Some(maxdecimaldigits))
+}
+
+#[pg_extern(stable, strict, parallel_safe)]
+fn st_aswkt(geom: Geometry) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    geometry_as_text_with_precision(geom, None)
+}
+
+#[pg_extern(stable, strict, parallel_safe, name = "st_aswkt")]
+fn st_aswkt_precision(
+    geom: Geometry,
+    maxdecimaldigits: i32,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    geometry_as_text_with_precision(geom, Some(maxdecimaldigits))
+}
+
+/// Dump verification: true when the text output of a geometry reads back
+/// identically, e.g. `SELECT count(*) FROM t WHERE NOT rostgis_roundtrip_check(geom)`
+#[pg_extern(immutable, strict, parallel_safe)]
+fn rostgis_roundtrip_check(geom: Geometry) -> bool {
+    text_roundtrips(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_aswkb(geom: Geometry) -> String {
     geometry_as_wkb(geom)
 }
 
-#[pg_extern]
-fn st_asgeojson(geom: Geometry) -> String {
-    geometry_as_geojson(geom)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asbinary(geom: Geometry) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    ewkb::check_wkb_size(&geom, false, "ST_AsBinary")?;
+    Ok(ewkb::write_wkb(&geom, false))
+}
+
+/// EWKB of a geometry, carrying its SRID
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asewkb(geom: Geometry) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    ewkb::check_wkb_size(&geom, true, "ST_AsEWKB")?;
+    Ok(ewkb::write_wkb(&geom, true))
+}
+
+/// Numbered pieces of the WKB or EWKB of a geometry; with compact storage a
+/// geometry can need more WKB than one bytea holds
+#[allow(clippy::type_complexity)]
+fn wkb_chunks(
+    geom: &Geometry,
+    with_srid: bool,
+    chunk_size: i32,
+) -> Result<Vec<(i32, Vec<u8>)>, Box<dyn std::error::Error + Send + Sync>> {
+    if chunk_size < 1 {
+        return Err("WKB chunk size must be positive".into());
+    }
+    let chunks = ewkb::write_wkb_chunks(geom, with_srid, chunk_size as usize);
+    Ok((1..).zip(chunks).collect())
+}
+
+/// PostgreSQL function streaming the EWKB of a geometry in pieces of at most
+/// `chunk_size` bytes, to be concatenated in seq order by the client, e.g.
+/// for continent-scale multipolygons past the 1 GB bytea limit
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asewkbchunks(
+    geom: Geometry,
+    chunk_size: default!(i32, 67108864),
+) -> Result<
+    TableIterator<'static, (name!(seq, i32), name!(chunk, Vec<u8>))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    Ok(TableIterator::new(wkb_chunks(&geom, true, chunk_size)?))
+}
+
+/// PostgreSQL function streaming the WKB of a geometry in pieces of at most
+/// `chunk_size` bytes
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asbinarychunks(
+    geom: Geometry,
+    chunk_size: default!(i32, 67108864),
+) -> Result<
+    TableIterator<'static, (name!(seq, i32), name!(chunk, Vec<u8>))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    Ok(TableIterator::new(wkb_chunks(&geom, false, chunk_size)?))
+}
+
+// Box output functions, using the geometry the box converts to
+#[pg_extern(stable, strict, parallel_safe, name = "st_astext")]
+fn st_astext_bbox(bbox: BBox) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let precision = guc::output_precision(None)?;
+    Ok(bbox.to_geometry().to_wkt_with_precision(precision))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_asbinary")]
+fn st_asbinary_bbox(bbox: BBox) -> Vec<u8> {
+    ewkb::write_wkb(&bbox.to_geometry(), false)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "geometry")]
+fn geometry_from_bbox(bbox: BBox) -> Geometry {
+    bbox.to_geometry()
+}
+
+extension_sql!(
+    r#"
+CREATE CAST (@extschema@.bbox AS @extschema@.geometry) WITH FUNCTION @extschema@.geometry(@extschema@.bbox) AS IMPLICIT;
+"#,
+    name = "bbox_casts",
+    requires = [Geometry, BBox, geometry_from_bbox],
+);
+
+// The optional axis order and precision keep these from being STRICT, so a NULL geometry
+// is mapped to NULL explicitly
+#[pg_extern(stable, parallel_safe)]
+fn st_asgeojson(
+    geom: Option<Geometry>,
+    axis_order: default!(Option<&str>, "NULL"),
+    maxdecimaldigits: default!(Option<i32>, "NULL"),
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(geom) = geom else {
+        return Ok(None);
+    };
+    let axis_order = guc::resolve_axis_order(axis_order, geom.srid())?;
+    let precision = guc::output_precision(maxdecimaldigits)?;
+    Ok(Some(geometry_as_geojson_with_axis_order(
+        geom, axis_order, precision,
+    )))
+}
+
+#[pg_extern(stable, parallel_safe)]
+fn st_asgml(
+    geom: Option<Geometry>,
+    axis_order: default!(Option<&str>, "NULL"),
+    srs_dimension: default!(bool, true),
+    maxdecimaldigits: default!(Option<i32>, "NULL"),
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(geom) = geom else {
+        return Ok(None);
+    };
+    let axis_order = guc::resolve_axis_order(axis_order, geom.srid())?;
+    let precision = guc::output_precision(maxdecimaldigits)?;
+    Ok(Some(geometry_as_gml(
+        &geom,
+        axis_order,
+        srs_dimension,
+        precision,
+    )))
 }
 
 // Geometry property functions
-#[pg_extern]
-fn st_x(geom: Geometry) -> Option<f64> {
-    geometry_x(geom)
+//
+// ST_X, ST_Y, ST_SRID, ST_GeometryType and ST_NPoints take the raw datum and
+// detoast only the slice holding its fixed-size serialization header. Their
+// SQL signatures use the geometry type and are declared in the
+// extension_sql! block below.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_x(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe { serialization::peek_argument(fcinfo, 0) }?.x())
 }
 
-#[pg_extern]
-fn st_y(geom: Geometry) -> Option<f64> {
-    geometry_y(geom)
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_y(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe { serialization::peek_argument(fcinfo, 0) }?.y())
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_z(geom: Geometry) -> Option<f64> {
     geometry_z(geom)
 }
 
-#[pg_extern]
-fn st_geometrytype(geom: Geometry) -> String {
-    geometry_type(geom)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_xcoords(geom: Geometry) -> Vec<f64> {
+    geometry_x_coords(geom)
 }
 
-#[pg_extern]
-fn st_srid(geom: Geometry) -> i32 {
-    geometry_srid(geom)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_ycoords(geom: Geometry) -> Vec<f64> {
+    geometry_y_coords(geom)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_zcoords(geom: Geometry) -> Option<Vec<f64>> {
+    geometry_z_coords(geom)
+}
+
+// Linestring vertex accessors; NULL for other geometries and for vertex
+// numbers past either end
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_numpoints(geom: Geometry) -> Option<i32> {
+    line_num_points(&geom)
+}
+
+/// Point n of a linestring, from 1, or counting back from the last point
+/// for negative n
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_pointn(geom: Geometry, n: i32) -> Option<Geometry> {
+    line_point_n(&geom, n)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_startpoint(geom: Geometry) -> Option<Geometry> {
+    line_point_n(&geom, 1)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_endpoint(geom: Geometry) -> Option<Geometry> {
+    line_point_n(&geom, -1)
+}
+
+// Collection member accessors; other geometries are collections of one
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_numgeometries(geom: Geometry) -> i32 {
+    num_geometries(&geom)
+}
+
+/// Member n of a collection, from 1
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_geometryn(geom: Geometry, n: i32) -> Option<Geometry> {
+    geometry_n(&geom, n)
+}
+
+// Polygon ring accessors; rings are returned as linestrings, and NULL for
+// other geometries
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_exteriorring(geom: Geometry) -> Option<Geometry> {
+    exterior_ring(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_numinteriorrings(geom: Geometry) -> Option<i32> {
+    num_interior_rings(&geom)
+}
+
+/// Hole n of a polygon, from 1
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_interiorringn(geom: Geometry, n: i32) -> Option<Geometry> {
+    interior_ring_n(&geom, n)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_geometrytype(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe { serialization::peek_argument(fcinfo, 0) }?
+        .geometry_type()
+        .to_string())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_srid(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(unsafe { serialization::peek_argument(fcinfo, 0) }?.srid)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_npoints(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let header = unsafe { serialization::peek_argument(fcinfo, 0) }?;
+    // An empty point is stored with one NaN position
+    Ok(if header.is_empty() {
+        0
+    } else {
+        header.npoints as i32
+    })
+}
+
+/// Body encoding of a stored geometry, 'wkb' or 'compact'
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn rostgis_storage_encoding(
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<&'static str, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(
+        if unsafe { serialization::peek_argument(fcinfo, 0) }?.is_compact() {
+            "compact"
+        } else {
+            "wkb"
+        },
+    )
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.st_x(@extschema@.geometry) RETURNS double precision
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_x_wrapper';
+CREATE FUNCTION @extschema@.st_y(@extschema@.geometry) RETURNS double precision
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_y_wrapper';
+CREATE FUNCTION @extschema@.st_geometrytype(@extschema@.geometry) RETURNS text
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_geometrytype_wrapper';
+CREATE FUNCTION @extschema@.st_srid(@extschema@.geometry) RETURNS integer
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_srid_wrapper';
+CREATE FUNCTION @extschema@.st_npoints(@extschema@.geometry) RETURNS integer
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_npoints_wrapper';
+CREATE FUNCTION @extschema@.rostgis_storage_encoding(@extschema@.geometry) RETURNS text
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'rostgis_storage_encoding_wrapper';
+"#,
+    name = "geometry_header_accessors",
+    requires = [Geometry],
+);
+
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_setsrid(geom: Geometry, srid: i32) -> Geometry {
-    set_geometry_srid(geom, srid)
+    let geom = set_geometry_srid(geom, srid);
+    lonlat::check_lon_lat_range(&geom);
+    geom
 }
 
 // Geometry relationship functions
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_isempty(geom: Geometry) -> bool {
+    geom.is_empty()
+}
+
+// Validity functions
+//
+// ST_MakeValid stores a known-valid flag in the serialization header, which
+// ST_IsValid trusts (unless rostgis.trust_valid_flag is off) so repeated
+// checks on repaired data skip decoding. The flag is only set on geometries
+// that passed the check, so trusting it never changes the answer and
+// ST_IsValid stays IMMUTABLE. Like the header accessors above they work on
+// the raw datum and are declared in the extension_sql! block.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_isvalid(geom: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    if guc::TRUST_VALID_FLAG.get() && GeometryHeader::peek(geom)?.is_known_valid() {
+        stats::count(stats::Counter::ValidFlagHits);
+        return Ok(true);
+    }
+    Ok(geometry_is_valid(&serialization::deserialize(geom)?))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_isvalidcached(geom: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(GeometryHeader::peek(geom)?.is_known_valid())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_makevalid(geom: Geometry) -> pg_sys::Datum {
+    store_repaired(&make_valid(&geom))
+}
+
+/// ST_MakeValid with a parameter string such as 'method=structure
+/// keepcollapsed=true'
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_makevalid_params(
+    geom: Geometry,
+    params: &str,
+) -> Result<pg_sys::Datum, Box<dyn std::error::Error + Send + Sync>> {
+    let options = RepairOptions::parse(params)?;
+    Ok(store_repaired(&make_valid_with(&geom, options)))
+}
+
+/// Datum of a repaired geometry, flagged when it is known to be valid
+fn store_repaired(repaired: &Geometry) -> pg_sys::Datum {
+    let bytes = if geometry_is_valid(repaired) {
+        serialization::serialize_valid(repaired)
+    } else {
+        serialization::serialize(repaired)
+    };
+    serialization::into_datum(&bytes)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.st_isvalid(@extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_isvalid_wrapper';
+CREATE FUNCTION @extschema@.st_isvalidcached(@extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_isvalidcached_wrapper';
+CREATE FUNCTION @extschema@.st_makevalid(@extschema@.geometry) RETURNS @extschema@.geometry
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_makevalid_wrapper';
+CREATE FUNCTION @extschema@.st_makevalid(@extschema@.geometry, text) RETURNS @extschema@.geometry
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_makevalid_params_wrapper';
+"#,
+    name = "validity_functions",
+    requires = [Geometry],
+);
+
+// Precision functions
+#[pg_extern(immutable, strict, parallel_safe, name = "st_snaptogrid")]
+fn st_snaptogrid(
+    geom: Geometry,
+    size: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(precision::snap_to_grid(
+        &geom,
+        &precision::Grid::uniform(size)?,
+    ))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_snaptogrid")]
+fn st_snaptogrid_origin(
+    geom: Geometry,
+    origin_x: f64,
+    origin_y: f64,
+    size_x: f64,
+    size_y: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let grid = precision::Grid::new(origin_x, origin_y, size_x, size_y)?;
+    Ok(precision::snap_to_grid(&geom, &grid))
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_reduceprecision(
+    geom: Geometry,
+    grid_size: f64,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(precision::reduce_precision(&geom, grid_size)?)
+}
+
+// Vector tile functions
+#[cfg(feature = "mvt")]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_asmvtgeom(
+    geom: Geometry,
+    bounds: BBox,
+    extent: default!(i32, 4096),
+    buffer: default!(i32, 256),
+    clip_geom: default!(bool, true),
+    make_valid: default!(bool, true),
+    min_area: default!(f64, 0.0),
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    let options = mvt::MvtOptions {
+        extent,
+        buffer,
+        clip_geom,
+        make_valid,
+        min_area,
+    };
+    let tile_geom = mvt::as_mvt_geom(&geom, &bounds, &options);
+    overlay::report_fallbacks();
+    Ok(tile_geom?)
+}
+
+// Antimeridian functions
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_splitatdateline(
+    geom: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(dateline::split_at_dateline(&geom)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_mergeatdateline(geom: Geometry) -> Geometry {
+    dateline::merge_at_dateline(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_equals(geom1: Geometry, geom2: Geometry) -> bool {
     geometries_equal(geom1, geom2)
 }
 
-#[pg_extern]
-fn st_distance(geom1: Geometry, geom2: Geometry) -> f64 {
-    geometries_distance(geom1, geom2)
+/// Distance between two geometries, NULL when either is empty
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_distance(geom1: Geometry, geom2: Geometry) -> Option<f64> {
+    distance_within(&geom1, &geom2, f64::INFINITY)
 }
 
-#[pg_extern]
+/// Distance between two geometries, or NULL as soon as they are proven to be
+/// farther apart than `max_distance`
+#[pg_extern(immutable, strict, parallel_safe, name = "st_distance")]
+fn st_distance_bounded(geom1: Geometry, geom2: Geometry, max_distance: f64) -> Option<f64> {
+    distance_within(&geom1, &geom2, max_distance)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_area(geom: Geometry) -> f64 {
     geometry_area(geom)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_length(geom: Geometry) -> f64 {
     geometry_length(geom)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_perimeter(geom: Geometry) -> f64 {
     geometry_perimeter(geom)
 }
 
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_pointonsurface(geom: Geometry) -> Geometry {
+    point_on_surface(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_boundary(geom: Geometry) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(boundary(&geom)?)
+}
+
+/// Move many vertices at once, given their numbers (as in ST_PointN,
+/// negative from the end) and new points
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_updatevertices(
+    geom: Geometry,
+    vertices: Array<'_, i32>,
+    points: Array<'_, Geometry>,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    if vertices.len() != points.len() {
+        return Err("ST_UpdateVertices needs one point per vertex number".into());
+    }
+    let edits = vertices
+        .iter()
+        .zip(points.iter())
+        .map(|edit| match edit {
+            (Some(vertex), Some(Geometry::Point(point, _))) if point.x().is_finite() => {
+                Ok((vertex, point.0))
+            }
+            (Some(_), Some(other)) => Err(format!(
+                "ST_UpdateVertices requires non-empty points, got {}",
+                other.geometry_type()
+            )),
+            _ => Err("ST_UpdateVertices vertex numbers and points cannot be NULL".to_string()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(update_vertices(&geom, &edits)?)
+}
+
+/// Drop polygons and holes below an area and lines below a length, e.g.
+/// `ST_RemoveSmallParts(ST_Difference(a, b), 1.0)` to clean overlay slivers
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_removesmallparts(geom: Geometry, min_area: f64, min_length: default!(f64, 0.0)) -> Geometry {
+    remove_small_parts(&geom, min_area, min_length)
+}
+
+// Azimuths and angles, clockwise from north in radians like PostGIS, with
+// degree variants normalized to [0, 360)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_azimuth(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(azimuth(&geom1, &geom2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_azimuthdeg(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(azimuth(&geom1, &geom2)?.map(|a| normalize_degrees(a.to_degrees())))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_angle(
+    point1: Option<Geometry>,
+    point2: Option<Geometry>,
+    point3: Option<Geometry>,
+    point4: default!(Option<Geometry>, "NULL"),
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(p1), Some(p2), Some(p3)) = (point1, point2, point3) else {
+        return Ok(None);
+    };
+    Ok(angle(&p1, &p2, &p3, point4.as_ref())?)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_angledeg(
+    point1: Option<Geometry>,
+    point2: Option<Geometry>,
+    point3: Option<Geometry>,
+    point4: default!(Option<Geometry>, "NULL"),
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(st_angle(point1, point2, point3, point4)?.map(|a| normalize_degrees(a.to_degrees())))
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_angle")]
+fn st_angle_lines(
+    line1: Geometry,
+    line2: Geometry,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(angle_between_lines(&line1, &line2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, name = "st_angledeg")]
+fn st_angledeg_lines(
+    line1: Geometry,
+    line2: Geometry,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(angle_between_lines(&line1, &line2)?.map(|a| normalize_degrees(a.to_degrees())))
+}
+
+/// Normalize an angle in radians to [0, 2π)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_normalizeangle(angle: f64) -> f64 {
+    normalize_radians(angle)
+}
+
+/// Normalize an angle in degrees to [0, 360)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_normalizeangledeg(angle: f64) -> f64 {
+    normalize_degrees(angle)
+}
+
 // Spatial indexing functions
-#[pg_extern]
-fn st_envelope(geom: Geometry) -> BBox {
+/// The bounding box of a geometry as a geometry with its SRID, like PostGIS
+/// ST_Envelope: a polygon, or a point or line when the box is degenerate.
+/// An empty geometry is its own envelope.
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_envelope(geom: Geometry) -> Geometry {
+    if geom.is_empty() {
+        return geom;
+    }
     BBox::from_geometry(&geom)
+        .to_geometry()
+        .with_srid(geom.srid())
+}
+
+/// The bounding box of a geometry, NULL for an empty geometry as in PostGIS
+#[pg_extern(immutable, strict, parallel_safe)]
+fn box2d(geom: Geometry) -> Option<BBox> {
+    (!geom.is_empty()).then(|| BBox::from_geometry(&geom))
+}
+
+/// Create a rectangular polygon from its corners, like PostGIS
+/// ST_MakeEnvelope; a degenerate box becomes a point or a line
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_makeenvelope(xmin: f64, ymin: f64, xmax: f64, ymax: f64, srid: default!(i32, 0)) -> Geometry {
+    BBox::new(
+        xmin.min(xmax),
+        ymin.min(ymax),
+        xmin.max(xmax),
+        ymin.max(ymax),
+    )
+    .to_geometry()
+    .with_srid(srid)
 }
 
 /// Simple compress function for spatial indexing
@@ -153,6 +857,7 @@ fn geometry_to_box(geom: Geometry) -> String {
 #[pg_operator(immutable, parallel_safe)]
 #[opname(&&)]
 fn geometry_overlap(left: Geometry, right: Geometry) -> bool {
+    stats::count(stats::Counter::BBoxTests);
     left.bbox_overlaps(&right)
 }
 
@@ -202,79 +907,141 @@ fn geometry_within_bbox(left: Geometry, right: Geometry) -> bool {
 #[pg_operator(immutable, parallel_safe)]
 #[opname(&<)]
 fn geometry_overleft(left: Geometry, right: Geometry) -> bool {
-    !left.bbox_right(&right)
+    left.bbox_comparable(&right) && !left.bbox_right(&right)
 }
 
 /// Overlap right operator (&>)
 #[pg_operator(immutable, parallel_safe)]
 #[opname(&>)]
 fn geometry_overright(left: Geometry, right: Geometry) -> bool {
-    !left.bbox_left(&right)
+    left.bbox_comparable(&right) && !left.bbox_left(&right)
 }
 
 /// Overlap below operator (&<|)
 #[pg_operator(immutable, parallel_safe)]
 #[opname(&<|)]
 fn geometry_overbelow(left: Geometry, right: Geometry) -> bool {
-    !left.bbox_above(&right)
+    left.bbox_comparable(&right) && !left.bbox_above(&right)
 }
 
 /// Overlap above operator (|&>)
 #[pg_operator(immutable, parallel_safe)]
 #[opname(|&>)]
 fn geometry_overabove(left: Geometry, right: Geometry) -> bool {
-    !left.bbox_below(&right)
+    left.bbox_comparable(&right) && !left.bbox_below(&right)
+}
+
+/// Distance operator (<->), the same as ST_Distance. With a GiST index,
+/// `ORDER BY geom <-> point LIMIT n` scans the index nearest first.
+#[pg_operator(immutable, parallel_safe)]
+#[opname(<->)]
+#[commutator(<->)]
+fn geometry_distance_knn(left: Geometry, right: Geometry) -> Option<f64> {
+    distance_within(&left, &right, f64::INFINITY)
 }
 
 /// Same bounding box operator (~=)
 #[pg_operator(immutable, parallel_safe)]
 #[opname(~=)]
 fn geometry_same_bbox(left: Geometry, right: Geometry) -> bool {
-    let (min_x1, min_y1, max_x1, max_y1) = left.bounding_box();
-    let (min_x2, min_y2, max_x2, max_y2) = right.bounding_box();
-
-    (min_x1 - min_x2).abs() < f64::EPSILON
-        && (min_y1 - min_y2).abs() < f64::EPSILON
-        && (max_x1 - max_x2).abs() < f64::EPSILON
-        && (max_y1 - max_y2).abs() < f64::EPSILON
+    left.bbox_same(&right)
 }
 
 // Spatial relationship functions that can use indexes
-#[pg_extern]
-fn st_intersects(geom1: Geometry, geom2: Geometry) -> bool {
-    // First check bounding box overlap (can use index)
-    if !geom1.bbox_overlaps(&geom2) {
-        return false;
-    }
+//
+// ST_Intersects, ST_Contains and ST_Within take the raw datums, so that an
+// argument that stays constant across calls is recognised by its bytes and
+// only decoded once, when it is prepared (see the prepared module). Their
+// SQL signatures are declared in the extension_sql! block below.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_intersects(
+    geom1: &[u8],
+    geom2: &[u8],
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    // Bounding box overlap is checked first (can use index), then the exact
+    // shapes
+    Ok(unsafe { prepared::with_prepared_cache(fcinfo, |cache| cache.intersects(geom1, geom2))? })
+}
 
-    // For now, if bboxes overlap, assume intersection
-    // In a full implementation, this would do exact geometric intersection testing
-    true
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_contains(
+    geom1: &[u8],
+    geom2: &[u8],
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    // First check bounding box containment (can use index), then exact
+    // containment
+    Ok(unsafe { prepared::with_prepared_cache(fcinfo, |cache| cache.contains(geom1, geom2))? })
 }
 
-#[pg_extern]
-fn st_contains(geom1: Geometry, geom2: Geometry) -> bool {
-    // First check bounding box containment (can use index)
-    if !geom1.bbox_contains(&geom2) {
-        return false;
-    }
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_within(
+    geom1: &[u8],
+    geom2: &[u8],
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    st_contains(geom2, geom1, fcinfo)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.st_intersects(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_intersects_wrapper';
+CREATE FUNCTION @extschema@.st_contains(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_contains_wrapper';
+CREATE FUNCTION @extschema@.st_within(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_within_wrapper';
+"#,
+    name = "prepared_predicates",
+    requires = [Geometry],
+);
 
-    // For now, if bbox contains, assume geometric containment
-    // In a full implementation, this would do exact geometric containment testing
-    true
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_crosses(geom1: Geometry, geom2: Geometry) -> bool {
+    geometries_cross(&geom1, &geom2)
 }
 
-#[pg_extern]
-fn st_within(geom1: Geometry, geom2: Geometry) -> bool {
-    st_contains(geom2, geom1)
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_relate(geom1: Geometry, geom2: Geometry) -> String {
+    geometry_relate(&geom1, &geom2)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, strict, parallel_safe, name = "st_relate")]
+fn st_relate_pattern(
+    geom1: Geometry,
+    geom2: Geometry,
+    pattern: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(geometry_relate_pattern(&geom1, &geom2, pattern)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_relatematch(
+    matrix: &str,
+    pattern: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(relate_match(matrix, pattern)?)
+}
+
+/// Containment of many candidates in one geometry, preparing it once
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_containsany(geom: Geometry, candidates: Vec<Option<Geometry>>) -> Vec<Option<bool>> {
+    contains_any(&geom, &candidates)
+}
+
+/// Number of candidates contained in a geometry, preparing it once
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_containscount(geom: Geometry, candidates: Vec<Option<Geometry>>) -> i64 {
+    contains_any(&geom, &candidates)
+        .into_iter()
+        .filter(|contained| *contained == Some(true))
+        .count() as i64
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
 fn st_dwithin(geom1: Geometry, geom2: Geometry, distance: f64) -> bool {
-    // This is a simplified implementation
-    // A proper implementation would expand the bounding box by the distance
-    let actual_distance = geometries_distance(geom1, geom2);
-    actual_distance <= distance
+    distance_within(&geom1, &geom2, distance).is_some()
 }
 
 // Test module
@@ -292,43 +1059,222 @@ mod tests {
 
     #[pg_test]
     fn test_st_makepoint() {
-        let point = crate::st_makepoint(1.0, 2.0);
-        assert_eq!(crate::st_x(point.clone()).unwrap(), 1.0);
-        assert_eq!(crate::st_y(point.clone()).unwrap(), 2.0);
-        assert_eq!(crate::st_geometrytype(point), "ST_Point");
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_X(ST_MakePoint(1, 2))").unwrap(),
+            Some(1.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_Y(ST_MakePoint(1, 2))").unwrap(),
+            Some(2.0)
+        );
+        assert_eq!(
+            Spi::get_one::<String>("SELECT ST_GeometryType(ST_MakePoint(1, 2))").unwrap(),
+            Some("ST_Point".to_string())
+        );
     }
 
     #[pg_test]
     fn test_st_geomfromtext() {
         let result = crate::st_geomfromtext("POINT(1 2)");
         assert!(result.is_ok());
-        let geom = result.unwrap();
-        assert_eq!(crate::st_x(geom.clone()).unwrap(), 1.0);
-        assert_eq!(crate::st_y(geom.clone()).unwrap(), 2.0);
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_X(ST_GeomFromText('POINT(1 2)'))").unwrap(),
+            Some(1.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_Y(ST_GeomFromText('POINT(1 2)'))").unwrap(),
+            Some(2.0)
+        );
     }
 
     #[pg_test]
     fn test_st_astext() {
         let point = crate::st_makepoint(1.0, 2.0);
-        let wkt = crate::st_astext(point);
+        let wkt = crate::st_astext(point).unwrap();
         assert_eq!(wkt, "POINT(1 2)");
     }
 
+    #[pg_test]
+    fn test_text_output_roundtrip() {
+        assert_eq!(
+            Spi::get_one::<String>("SELECT ST_SetSRID(ST_MakePoint(1, 2), 4326)::text").unwrap(),
+            Some("SRID=4326;POINT(1 2)".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<bool>(
+                "SELECT bool_and(rostgis_roundtrip_check(g) AND g::text::geometry::text = g::text)
+                 FROM (VALUES ('SRID=3857;MULTIPOLYGON(((0 0,1 0,1 1,0 0)))'::geometry),
+                              ('POINT EMPTY'::geometry),
+                              ('GEOMETRYCOLLECTION(POINT(0.1 0.2),LINESTRING EMPTY)'::geometry)) t(g)"
+            )
+            .unwrap(),
+            Some(true)
+        );
+    }
+
     #[pg_test]
     fn test_st_distance() {
         let point1 = crate::st_makepoint(0.0, 0.0);
         let point2 = crate::st_makepoint(3.0, 4.0);
-        let distance = crate::st_distance(point1, point2);
+        let distance = crate::st_distance(point1, point2).unwrap();
         assert!((distance - 5.0).abs() < 1e-10);
     }
 
+    #[pg_test]
+    fn test_null_and_empty_semantics() {
+        // NULL in, NULL out, also for functions with optional arguments
+        let nulls = Spi::get_one::<i64>(
+            "SELECT num_nulls(ST_AsText(NULL::geometry), ST_Area(NULL), ST_AsGeoJSON(NULL),
+                              ST_AsGML(NULL), ST_Intersects(NULL, 'POINT(0 0)'))",
+        )
+        .unwrap();
+        assert_eq!(nulls, Some(5));
+
+        let row = Spi::get_one::<String>(
+            "SELECT concat_ws(',',
+                 ST_Area('POLYGON EMPTY'::geometry), ST_Length('LINESTRING EMPTY'::geometry),
+                 ST_NPoints('POINT EMPTY'::geometry),
+                 ST_Distance('POINT EMPTY'::geometry, 'POINT(0 0)') IS NULL,
+                 Box2D('POINT EMPTY'::geometry) IS NULL,
+                 ST_AsText(ST_Envelope('LINESTRING EMPTY'::geometry)),
+                 ST_Intersects('POINT EMPTY'::geometry, 'POINT(0 0)'),
+                 ST_Contains('POLYGON((0 0, 1 0, 1 1, 0 0))', 'POINT EMPTY'::geometry),
+                 ST_DWithin('POINT EMPTY'::geometry, 'POINT(0 0)', 10),
+                 ST_Relate('POINT EMPTY'::geometry, 'POINT(0 0)'),
+                 'POINT EMPTY'::geometry && 'POINT(0 0)'::geometry,
+                 'LINESTRING EMPTY'::geometry &< 'POINT(0 0)'::geometry,
+                 ST_Equals('POINT EMPTY'::geometry, 'GEOMETRYCOLLECTION EMPTY'),
+                 ST_AsGeoJSON('POINT EMPTY'::geometry))",
+        )
+        .unwrap();
+        assert_eq!(
+            row.as_deref(),
+            Some(
+                r#"0,0,0,t,t,LINESTRING EMPTY,f,f,f,FFFFFF0F2,f,f,t,{"type":"Point","coordinates":[]}"#
+            )
+        );
+    }
+
     #[pg_test]
     fn test_st_srid() {
-        let point = crate::st_makepoint(1.0, 2.0);
-        assert_eq!(crate::st_srid(point), 0); // Default SRID
+        assert_eq!(
+            Spi::get_one::<i32>("SELECT ST_SRID(ST_MakePoint(1, 2))").unwrap(),
+            Some(0)
+        ); // Default SRID
+
+        assert_eq!(
+            Spi::get_one::<i32>("SELECT ST_SRID(ST_SetSRID(ST_MakePoint(1, 2), 4326))").unwrap(),
+            Some(4326)
+        );
+    }
+
+    #[pg_test]
+    fn test_header_accessors_of_toasted_geometries() {
+        // Lines long enough to be stored out of line, compressed or not
+        Spi::run(
+            "CREATE TABLE long_lines (geom geometry);
+             INSERT INTO long_lines
+             SELECT ST_SetSRID(ST_MakeLine(array_agg(i::float8), array_agg((i % 2)::float8)), 3857)
+             FROM generate_series(1, 20000) i;
+             ALTER TABLE long_lines ALTER COLUMN geom SET STORAGE EXTERNAL;
+             INSERT INTO long_lines SELECT * FROM long_lines;",
+        )
+        .unwrap();
+        let (srid, npoints, kind) = Spi::get_three::<i32, i64, String>(
+            "SELECT min(ST_SRID(geom)), sum(ST_NPoints(geom)), min(ST_GeometryType(geom))
+             FROM long_lines",
+        )
+        .unwrap();
+        assert_eq!(srid, Some(3857));
+        assert_eq!(npoints, Some(40000));
+        assert_eq!(kind.as_deref(), Some("ST_LineString"));
+        let first =
+            Spi::get_two::<f64, f64>("SELECT min(ST_X(geom)), min(ST_Y(geom)) FROM long_lines")
+                .unwrap();
+        assert_eq!(first, (Some(1.0), Some(1.0)));
+    }
+
+    #[pg_test]
+    fn test_line_accessors() {
+        let (count, second, last) = Spi::get_three::<i32, String, String>(
+            "SELECT ST_NumPoints(g), ST_AsText(ST_PointN(g, 2)), ST_AsText(ST_PointN(g, -1))
+             FROM (SELECT 'LINESTRING(0 0, 1 2, 3 4)'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(count, Some(3));
+        assert_eq!(second.as_deref(), Some("POINT(1 2)"));
+        assert_eq!(last.as_deref(), Some("POINT(3 4)"));
+        let (start, end) = Spi::get_two::<String, String>(
+            "SELECT ST_AsText(ST_StartPoint(g)), ST_AsText(ST_EndPoint(g))
+             FROM (SELECT 'LINESTRING(0 0, 1 2, 3 4)'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(
+            (start.as_deref(), end.as_deref()),
+            (Some("POINT(0 0)"), Some("POINT(3 4)"))
+        );
+        let (not_a_line, past_end) = Spi::get_two::<i32, String>(
+            "SELECT ST_NumPoints('POINT(1 1)'::geometry),
+                    ST_AsText(ST_PointN('LINESTRING(0 0, 1 1)'::geometry, 3))",
+        )
+        .unwrap();
+        assert_eq!((not_a_line, past_end), (None, None));
+    }
+
+    #[pg_test]
+    fn test_ring_accessors() {
+        let (exterior, holes, second) = Spi::get_three::<String, i32, String>(
+            "SELECT ST_AsText(ST_ExteriorRing(g)), ST_NumInteriorRings(g),
+                    ST_AsText(ST_InteriorRingN(g, 2))
+             FROM (SELECT 'POLYGON((0 0, 10 0, 10 10, 0 10, 0 0),
+                                   (1 1, 2 1, 2 2, 1 1), (5 5, 6 5, 6 6, 5 5))'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(
+            exterior.as_deref(),
+            Some("LINESTRING(0 0,10 0,10 10,0 10,0 0)")
+        );
+        assert_eq!(holes, Some(2));
+        assert_eq!(second.as_deref(), Some("LINESTRING(5 5,6 5,6 6,5 5)"));
+        let (not_a_polygon, past_end) = Spi::get_two::<i32, String>(
+            "SELECT ST_NumInteriorRings('LINESTRING(0 0, 1 1)'::geometry),
+                    ST_AsText(ST_InteriorRingN('POLYGON((0 0, 1 0, 1 1, 0 0))'::geometry, 1))",
+        )
+        .unwrap();
+        assert_eq!((not_a_polygon, past_end), (None, None));
+    }
 
-        let point_with_srid = crate::st_setsrid(crate::st_makepoint(1.0, 2.0), 4326);
-        assert_eq!(crate::st_srid(point_with_srid), 4326);
+    #[pg_test]
+    fn test_collection_accessors() {
+        let (count, second, past_end) = Spi::get_three::<i32, String, String>(
+            "SELECT ST_NumGeometries(g), ST_AsText(ST_GeometryN(g, 2)), ST_AsText(ST_GeometryN(g, 3))
+             FROM (SELECT 'MULTIPOINT(0 0, 1 2)'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+        assert_eq!(second.as_deref(), Some("POINT(1 2)"));
+        assert_eq!(past_end, None);
+        let srid = Spi::get_one::<i32>(
+            "SELECT ST_SRID(ST_GeometryN(ST_SetSRID('MULTIPOINT(0 0, 1 2)'::geometry, 4326), 1))",
+        )
+        .unwrap();
+        assert_eq!(srid, Some(4326));
+        let (single, member) = Spi::get_two::<i32, String>(
+            "SELECT ST_NumGeometries('LINESTRING(0 0, 1 1)'::geometry),
+                    ST_AsText(ST_GeometryN('LINESTRING(0 0, 1 1)'::geometry, 1))",
+        )
+        .unwrap();
+        assert_eq!(single, Some(1));
+        assert_eq!(member.as_deref(), Some("LINESTRING(0 0,1 1)"));
+    }
+
+    #[pg_test]
+    fn test_st_npoints() {
+        assert_eq!(
+            Spi::get_one::<i32>("SELECT ST_NPoints(ST_GeomFromText('LINESTRING(0 0, 1 1, 2 2)'))")
+                .unwrap(),
+            Some(3)
+        );
     }
 
     #[pg_test]
@@ -341,20 +1287,2314 @@ mod tests {
         assert!(crate::geometry_overlap(point1.clone(), point1.clone()));
 
         // Test spatial relationships
-        assert!(crate::st_intersects(point1.clone(), point1.clone()));
+        assert_eq!(
+            Spi::get_one::<bool>("SELECT ST_Intersects(ST_MakePoint(0, 0), ST_MakePoint(0, 0))")
+                .unwrap(),
+            Some(true)
+        );
         assert!(crate::st_dwithin(point1.clone(), point2.clone(), 2.0));
         assert!(!crate::st_dwithin(point1.clone(), point3.clone(), 1.0));
     }
 
     #[pg_test]
-    fn test_st_envelope() {
-        let point = crate::st_makepoint(1.0, 2.0);
-        let bbox = crate::st_envelope(point);
-        // For a point, the envelope should be the point coordinates
-        assert_eq!(bbox.min_x, 1.0);
-        assert_eq!(bbox.min_y, 2.0);
-        assert_eq!(bbox.max_x, 1.0);
-        assert_eq!(bbox.max_y, 2.0);
+    fn test_distance_operator() {
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_MakePoint(0, 0) <-> ST_MakePoint(3, 4)").unwrap(),
+            Some(5.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT 'POINT EMPTY'::geometry <-> ST_MakePoint(3, 4)").unwrap(),
+            None
+        );
+        let nearest = Spi::get_one::<Vec<i32>>(
+            "SELECT array_agg(x ORDER BY ST_MakePoint(x, 0) <-> ST_MakePoint(4.2, 1))
+             FROM generate_series(0, 9) x",
+        )
+        .unwrap();
+        assert_eq!(nearest.unwrap()[..3], [4, 5, 3]);
+    }
+
+    #[pg_test(error = "Z and M coordinates are not supported by the geography type")]
+    fn test_geography_refuses_z_input() {
+        Spi::run("SELECT 'POINT Z (10 50 100)'::geography").unwrap();
+    }
+
+    #[pg_test]
+    fn test_geography_distance() {
+        let inside = Spi::get_one::<f64>(
+            "SELECT ST_Distance('POLYGON((0 0, 2 0, 2 2, 0 2, 0 0))'::geography,
+                                'POINT(1 1)'::geography)",
+        )
+        .unwrap();
+        assert_eq!(inside, Some(0.0));
+
+        // Closest to the middle of the segment, about 1.1 km from it
+        let beside = Spi::get_one::<f64>(
+            "SELECT ST_Distance('LINESTRING(0 0, 2 0)'::geography, 'POINT(1 0.01)'::geography)",
+        )
+        .unwrap()
+        .unwrap();
+        assert!((beside - 1105.7).abs() < 1.0);
+
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Distance('POINT EMPTY'::geography, 'POINT(1 1)'::geography)"
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[pg_test]
+    fn test_gist_nearest_neighbours() {
+        create_indexed_shapes("gist_knn_shapes", "");
+        for query in ["POINT(5.2 3.1)", "LINESTRING(20 -3, 25 4)", "POINT(30 30)"] {
+            let select = format!(
+                "SELECT array_agg(d) FROM (
+                     SELECT geom <-> '{query}'::geometry AS d FROM gist_knn_shapes
+                     ORDER BY geom <-> '{query}'::geometry LIMIT 100) nearest"
+            );
+            Spi::run("SET LOCAL enable_seqscan = off; SET LOCAL enable_bitmapscan = off").unwrap();
+            let plan = explain(&select);
+            assert!(
+                plan.contains("Index Scan") && plan.contains("Order By"),
+                "{}",
+                plan
+            );
+            let indexed = Spi::get_one::<Vec<f64>>(&select).unwrap();
+            Spi::run("SET LOCAL enable_seqscan = on; SET LOCAL enable_indexscan = off").unwrap();
+            let sorted = Spi::get_one::<Vec<f64>>(&select).unwrap();
+            Spi::run("RESET enable_seqscan; RESET enable_indexscan; RESET enable_bitmapscan")
+                .unwrap();
+            assert_eq!(indexed, sorted, "{}", query);
+        }
+        // Empty geometries, at no distance, come after all others
+        Spi::run("SET LOCAL enable_seqscan = off").unwrap();
+        let last = Spi::get_one::<bool>(
+            "SELECT bool_and(ST_IsEmpty(geom)) FROM (
+                 SELECT geom FROM gist_knn_shapes
+                 ORDER BY geom <-> 'POINT(0 0)'::geometry OFFSET 2400) rest",
+        )
+        .unwrap();
+        assert_eq!(last, Some(true));
+    }
+
+    #[pg_test]
+    fn test_gist_operator_class() {
+        let (default, operators) = Spi::get_two::<bool, i64>(
+            "SELECT c.opcdefault,
+                    (SELECT count(*) FROM pg_amop WHERE amopfamily = c.opcfamily)
+             FROM pg_opclass c JOIN pg_am am ON am.oid = c.opcmethod
+             WHERE c.opcname = 'rostgis_gist_ops' AND am.amname = 'gist'",
+        )
+        .unwrap();
+        assert_eq!(default, Some(true));
+        assert_eq!(operators, Some(13));
+        let ordering = Spi::get_one::<String>(
+            "SELECT amopopr::regoperator::text FROM pg_amop
+             WHERE amoppurpose = 'o'
+               AND amopfamily = (SELECT opcfamily FROM pg_opclass
+                                 WHERE opcname = 'rostgis_gist_ops')",
+        )
+        .unwrap();
+        assert_eq!(ordering.as_deref(), Some("<->(geometry,geometry)"));
+    }
+
+    #[pg_test]
+    fn test_gist_nd_operator_class() {
+        let (default, operators) = Spi::get_two::<bool, i64>(
+            "SELECT c.opcdefault,
+                    (SELECT count(*) FROM pg_amop WHERE amopfamily = c.opcfamily)
+             FROM pg_opclass c JOIN pg_am am ON am.oid = c.opcmethod
+             WHERE c.opcname = 'gist_geometry_ops_nd' AND am.amname = 'gist'",
+        )
+        .unwrap();
+        assert_eq!((default, operators), (Some(false), Some(2)));
+        create_indexed_shapes("gist_nd_shapes", "gist_geometry_ops_nd");
+        Spi::run(
+            "INSERT INTO gist_nd_shapes
+             SELECT id, ST_MakePointZ(id % 17, id % 13, id % 7 * 0.5)
+             FROM generate_series(3001, 4000) id",
+        )
+        .unwrap();
+        for query in [
+            "'BOX3D(3 2 -1,9 6 1)'::box3d",
+            "'BOX3D(3 2 1,9 6 2)'::box3d",
+            "POLYGON((3 2, 9 2, 9 6, 3 6, 3 2))",
+            "POINT(5 3)",
+            "POINT EMPTY",
+        ] {
+            let (indexed, sequential) = index_and_seq_scan_ids("gist_nd_shapes", "&&&", query);
+            assert_eq!(indexed, sequential, "{}", query);
+        }
+        let (indexed, _) =
+            index_and_seq_scan_ids("gist_nd_shapes", "&&&", "'BOX3D(3 2 -1,9 6 1)'::box3d");
+        assert!(indexed.is_some_and(|ids| ids.len() > 100));
+        // Only points with z in [1, 2] are above the flat shapes
+        let (indexed, _) =
+            index_and_seq_scan_ids("gist_nd_shapes", "&&&", "'BOX3D(3 2 1,9 6 2)'::box3d");
+        assert!(indexed.is_some_and(|ids| !ids.is_empty() && ids.iter().all(|&id| id > 3000)));
+        let opclass = Spi::get_one::<String>(
+            "SELECT opclass FROM rostgis_operators()
+             WHERE operator_name = '&&&' AND right_type = 'box3d' AND left_type = 'geometry'",
+        )
+        .unwrap();
+        assert_eq!(opclass.as_deref(), Some("gist_geometry_ops_nd"));
+    }
+
+    #[pg_test]
+    fn test_rostgis_operators() {
+        let (opclass, purpose) = Spi::get_two::<String, String>(
+            "SELECT opclass, purpose FROM rostgis_operators()
+             WHERE operator_name = '<->' AND left_type = 'geometry'",
+        )
+        .unwrap();
+        assert_eq!(opclass.as_deref(), Some("rostgis_gist_ops"));
+        assert_eq!(purpose.as_deref(), Some("order"));
+        let (strategy, description) = Spi::get_two::<i16, String>(
+            "SELECT strategy, description FROM rostgis_operators()
+             WHERE operator_name = '&&' AND left_type = 'geometry'",
+        )
+        .unwrap();
+        assert_eq!(strategy, Some(3));
+        assert_eq!(description.as_deref(), Some("Bounding boxes intersect"));
+        // The geography && has no operator class
+        let accelerated = Spi::get_one::<bool>(
+            "SELECT index_accelerated FROM rostgis_operators()
+             WHERE operator_name = '&&' AND left_type = 'geography'",
+        )
+        .unwrap();
+        assert_eq!(accelerated, Some(false));
+        let btree = Spi::get_one::<i64>(
+            "SELECT count(*) FROM rostgis_operators() WHERE access_method = 'btree'",
+        )
+        .unwrap();
+        assert_eq!(btree, Some(5));
+    }
+
+    #[pg_test]
+    fn test_output_of_collections() {
+        let geojson = Spi::get_one::<String>(
+            "SELECT ST_AsGeoJSON('MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)))'::geometry)",
+        )
+        .unwrap();
+        assert_eq!(
+            geojson.as_deref(),
+            Some(r#"{"type":"MultiPolygon","coordinates":[[[[0,0],[1,0],[1,1],[0,0]]]]}"#)
+        );
+        let wkb =
+            Spi::get_one::<String>("SELECT ST_AsWKB('GEOMETRYCOLLECTION(POINT(1 2))'::geometry)")
+                .unwrap();
+        assert_eq!(
+            wkb.as_deref(),
+            Some("0107000000010000000101000000000000000000F03F0000000000000040")
+        );
+    }
+
+    #[pg_test]
+    fn test_btree_operator_class() {
+        let sorted = Spi::get_one::<String>(
+            "SELECT string_agg(ST_AsText(g), ';' ORDER BY g) FROM (VALUES
+                 ('POINT(2 1)'::geometry), ('POINT(1 5)'), ('POINT EMPTY'), ('POINT(1 2)'))
+             AS t(g)",
+        )
+        .unwrap();
+        assert_eq!(
+            sorted.as_deref(),
+            Some("POINT EMPTY;POINT(1 2);POINT(1 5);POINT(2 1)")
+        );
+        let distinct = Spi::get_one::<i64>(
+            "SELECT count(DISTINCT g) FROM (VALUES ('POINT(1 2)'::geometry),
+                 (ST_MakePoint(1, 2)), ('LINESTRING(0 0, 1 1)'), ('SRID=4326;POINT(1 2)'))
+             AS t(g)",
+        )
+        .unwrap();
+        assert_eq!(distinct, Some(3));
+
+        Spi::run("CREATE TEMP TABLE unique_shapes (g geometry UNIQUE)").unwrap();
+        Spi::run("INSERT INTO unique_shapes VALUES ('LINESTRING(0 0, 1 1)'), ('POINT(0 0)')")
+            .unwrap();
+
+        Spi::run("SET LOCAL enable_hashjoin = off; SET LOCAL enable_nestloop = off").unwrap();
+        let joined = Spi::get_one::<i64>(
+            "SELECT count(*) FROM unique_shapes a JOIN unique_shapes b ON a.g = b.g",
+        )
+        .unwrap();
+        assert_eq!(joined, Some(2));
+    }
+
+    #[pg_test(error = "duplicate key value violates unique constraint \"unique_shapes_g_key\"")]
+    fn test_btree_unique_violation() {
+        Spi::run("CREATE TEMP TABLE unique_shapes (g geometry UNIQUE)").unwrap();
+        Spi::run("INSERT INTO unique_shapes VALUES ('LINESTRING(0 0, 1 1)')").unwrap();
+        Spi::run("INSERT INTO unique_shapes VALUES ('LINESTRING(0 0, 1 1)')").unwrap();
+    }
+
+    /// Plan of a query, one line per row of EXPLAIN
+    fn explain(query: &str) -> String {
+        Spi::connect(|client| -> Result<String, pgrx::spi::Error> {
+            let rows = client.select(&format!("EXPLAIN (COSTS OFF) {}", query), None, &[])?;
+            let mut plan = String::new();
+            for row in rows {
+                plan.push_str(&row.get::<String>(1)?.unwrap_or_default());
+                plan.push('\n');
+            }
+            Ok(plan)
+        })
+        .unwrap()
+    }
+
+    /// Fill a table with points, lines, polygons and empty geometries, and
+    /// index it with a GiST operator class, the default one when `opclass`
+    /// is empty
+    fn create_indexed_shapes(table: &str, opclass: &str) {
+        Spi::run(&format!(
+            "CREATE TABLE {table} AS
+             SELECT id, CASE id % 5
+                 WHEN 0 THEN ST_MakePoint(id % 17, id % 13)
+                 WHEN 1 THEN ST_GeomFromText(format('LINESTRING(%s %s, %s %s)',
+                                 id % 11, id % 7, id % 11 + 3, id % 7 + 2))
+                 WHEN 2 THEN ST_MakeEnvelope(id % 19, id % 5, id % 19 + 2, id % 5 + 1.5)
+                 WHEN 3 THEN 'POINT EMPTY'::geometry
+                 ELSE ST_MakePoint(id % 23 * 0.5, id % 29 * 0.5) END AS geom
+             FROM generate_series(1, 3000) id"
+        ))
+        .unwrap();
+        Spi::run(&format!(
+            "CREATE INDEX ON {table} USING gist (geom {opclass})"
+        ))
+        .unwrap();
+        Spi::run(&format!("ANALYZE {table}")).unwrap();
+    }
+
+    /// Ids of the rows of `table` matching `geom <operator> query`, with the
+    /// index and with a sequential scan; `query` is a geometry, or any value
+    /// given with its cast
+    fn index_and_seq_scan_ids(
+        table: &str,
+        operator: &str,
+        query: &str,
+    ) -> (Option<Vec<i32>>, Option<Vec<i32>>) {
+        let select = format!(
+            "SELECT array_agg(id ORDER BY id) FROM {table} WHERE geom {operator} {}",
+            if query.contains("::") {
+                query.to_string()
+            } else {
+                format!("'{query}'::geometry")
+            }
+        );
+        Spi::run("SET LOCAL enable_seqscan = off").unwrap();
+        let plan = explain(&select);
+        assert!(plan.contains("Index"), "{} {}: {}", operator, query, plan);
+        let indexed = Spi::get_one::<Vec<i32>>(&select).unwrap();
+        Spi::run(
+            "SET LOCAL enable_seqscan = on; SET LOCAL enable_indexscan = off;
+             SET LOCAL enable_bitmapscan = off",
+        )
+        .unwrap();
+        let sequential = Spi::get_one::<Vec<i32>>(&select).unwrap();
+        Spi::run("RESET enable_seqscan; RESET enable_indexscan; RESET enable_bitmapscan").unwrap();
+        (indexed, sequential)
+    }
+
+    #[pg_test]
+    fn test_gist_index_scans_match_seq_scans() {
+        create_indexed_shapes("gist_shapes", "");
+        let operators = [
+            "<<", "&<", "&&", "&>", ">>", "~=", "~", "@", "<<|", "&<|", "|&>", "|>>",
+        ];
+        let queries = [
+            "POLYGON((3 2, 9 2, 9 6, 3 6, 3 2))",
+            "LINESTRING(4 1, 12 8)",
+            "POINT(5 3)",
+            "POINT(4.5 7)",
+            "POINT EMPTY",
+        ];
+        for operator in operators {
+            for query in queries {
+                let (indexed, sequential) = index_and_seq_scan_ids("gist_shapes", operator, query);
+                assert_eq!(indexed, sequential, "{} {}", operator, query);
+            }
+        }
+        // Empty geometries are found through ~= only
+        let (indexed, _) = index_and_seq_scan_ids("gist_shapes", "~=", "POINT EMPTY");
+        assert_eq!(indexed.map(|ids| ids.len()), Some(600));
+    }
+
+    #[pg_test]
+    fn test_gist_tuning() {
+        // Trees built with other margin weights and fill factors find the
+        // same rows
+        Spi::run("SET rostgis.gist_margin_weight = 2").unwrap();
+        Spi::run("SET rostgis.gist_fill_factor = 50").unwrap();
+        create_indexed_shapes("gist_tuned_shapes", "");
+        for query in ["POLYGON((3 2, 9 2, 9 6, 3 6, 3 2))", "POINT(5 3)"] {
+            let (indexed, sequential) = index_and_seq_scan_ids("gist_tuned_shapes", "&&", query);
+            assert!(indexed.is_some());
+            assert_eq!(indexed, sequential, "{}", query);
+        }
+    }
+
+    #[pg_test(error = "rostgis_index_quality: rostgis_index_quality_t_pkey is not a GiST index")]
+    fn test_rostgis_index_quality_not_gist() {
+        Spi::run("CREATE TABLE rostgis_index_quality_t (id int PRIMARY KEY)").unwrap();
+        Spi::run("SELECT * FROM rostgis_index_quality('rostgis_index_quality_t_pkey')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_rostgis_index_quality() {
+        Spi::run("CREATE EXTENSION IF NOT EXISTS pageinspect").unwrap();
+        create_indexed_shapes("quality_shapes", "");
+        let (leaf_pages, entries) = Spi::get_two::<i64, i64>(
+            "SELECT leaf_pages, entries FROM rostgis_index_quality('quality_shapes_geom_idx')",
+        )
+        .unwrap();
+        assert!(leaf_pages.is_some_and(|pages| pages > 1));
+        assert_eq!(entries, Some(3000));
+        let (overlap, coverage) = Spi::get_two::<f64, f64>(
+            "SELECT overlap, coverage
+             FROM rostgis_index_quality('quality_shapes_geom_idx'::regclass)",
+        )
+        .unwrap();
+        assert!(overlap.is_some_and(|overlap| overlap >= 0.0));
+        assert!(coverage.is_some_and(|coverage| coverage > 0.0 && coverage <= 1.0));
+    }
+
+    #[pg_test]
+    fn test_prepared_predicates_in_join() {
+        // The polygon is repeated on every row of the join and gets prepared
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*)
+             FROM generate_series(0, 9) AS x,
+                  (SELECT ST_GeomFromText('POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))') AS geom) p
+             WHERE ST_Contains(p.geom, ST_MakePoint(x + 0.5, 1))
+               AND ST_Intersects(p.geom, ST_MakePoint(x + 0.5, 1))",
+        )
+        .unwrap();
+        assert_eq!(count, Some(10));
+
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*)
+             FROM generate_series(0, 9) AS x,
+                  (SELECT ST_GeomFromText('POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))') AS geom) p
+             WHERE ST_Within(ST_MakePoint(5, x + 0.5), p.geom)",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+    }
+
+    #[pg_test]
+    fn test_prepared_argument_decoded_once() {
+        Spi::run("SET rostgis.stats = on").unwrap();
+        Spi::run("SELECT rostgis_stat_reset()").unwrap();
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM generate_series(1, 100) AS x
+             WHERE ST_Intersects('POLYGON((0 0, 200 0, 200 200, 0 200, 0 0))'::geometry,
+                                 ST_MakePoint(x, x))",
+        )
+        .unwrap();
+        assert_eq!(count, Some(100));
+        // Each point is decoded once; the polygon only until it is prepared
+        let decoded = Spi::get_one::<i64>(
+            "SELECT calls FROM rostgis_stat() WHERE counter = 'geometries_decoded'",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(decoded < 110, "{} geometries decoded", decoded);
+    }
+
+    #[pg_test]
+    fn test_explain_spatial() {
+        Spi::run(
+            "CREATE TABLE explain_points AS
+             SELECT ST_MakePoint(x, y) AS geom
+             FROM generate_series(1, 50) x, generate_series(1, 50) y",
+        )
+        .unwrap();
+        let access = Spi::get_one::<String>(
+            "SELECT access FROM rostgis_explain_spatial(
+                 'SELECT * FROM explain_points
+                  WHERE ST_Intersects(geom, ''POLYGON((0 0,5 0,5 5,0 5,0 0))''::geometry)')",
+        )
+        .unwrap();
+        assert_eq!(access, Some("filter".to_string()));
+
+        // Mixed-case names are quoted, so the row count is still found
+        Spi::run(
+            "CREATE TABLE \"Explain Points\" AS SELECT * FROM explain_points;
+             ANALYZE \"Explain Points\"",
+        )
+        .unwrap();
+        let (relation, selectivity) = Spi::get_two::<String, f64>(
+            "SELECT relation, estimated_selectivity FROM rostgis_explain_spatial(
+                 'SELECT * FROM \"Explain Points\"
+                  WHERE geom && ''POLYGON((0 0,5 0,5 5,0 5,0 0))''::geometry', false)",
+        )
+        .unwrap();
+        assert_eq!(relation.as_deref(), Some("\"Explain Points\""));
+        assert!(selectivity.is_some());
+
+        // and so are keywords
+        Spi::run(
+            "CREATE TABLE \"order\" AS SELECT * FROM explain_points;
+             ANALYZE \"order\"",
+        )
+        .unwrap();
+        let (relation, selectivity) = Spi::get_two::<String, f64>(
+            "SELECT relation, estimated_selectivity FROM rostgis_explain_spatial(
+                 'SELECT * FROM \"order\"
+                  WHERE geom && ''POLYGON((0 0,5 0,5 5,0 5,0 0))''::geometry', false)",
+        )
+        .unwrap();
+        assert_eq!(relation.as_deref(), Some("\"order\""));
+        assert!(selectivity.is_some());
+    }
+
+    #[pg_test]
+    fn test_azimuth_degrees() {
+        let azimuth =
+            Spi::get_one::<f64>("SELECT ST_AzimuthDeg(ST_MakePoint(0, 0), ST_MakePoint(-1, -1))")
+                .unwrap()
+                .unwrap();
+        assert!((azimuth - 225.0).abs() < 1e-9);
+
+        let angle = Spi::get_one::<f64>(
+            "SELECT ST_AngleDeg(ST_MakePoint(-1, 0), ST_MakePoint(0, 0), ST_MakePoint(0, 1))",
+        )
+        .unwrap()
+        .unwrap();
+        assert!((angle - 90.0).abs() < 1e-9);
+
+        assert_eq!(
+            Spi::get_one::<f64>("SELECT ST_NormalizeAngleDeg(-30)").unwrap(),
+            Some(330.0)
+        );
+    }
+
+    #[pg_test]
+    fn test_st_union_aggregate() {
+        let areas = Spi::get_one::<String>(
+            "SELECT string_agg(ST_GeometryType(u) || ':' || ST_Area(u), ',' ORDER BY g)
+             FROM (
+                 SELECT g, ST_Union(ST_GeomFromText(format(
+                     'POLYGON((%1$s 0,%2$s 0,%2$s 1,%1$s 1,%1$s 0))', x, x + 1))) AS u
+                 FROM generate_series(0, 199) x, LATERAL (SELECT x % 2 AS g) grp
+                 GROUP BY g
+             ) unions",
+        )
+        .unwrap();
+        assert_eq!(
+            areas,
+            Some("ST_MultiPolygon:100,ST_MultiPolygon:100".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_simplify() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_Simplify(
+                     'LINESTRING(0 0,1 0.1,2 -0.1,3 0.05,4 0,5 3)'::geometry, 0.5))"
+            )
+            .unwrap(),
+            Some("LINESTRING(0 0,4 0,5 3)".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_Simplify('POLYGON((0 0,1 0,1 1,0 1,0 0))'::geometry, 10))"
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            Spi::get_one::<i32>(
+                "SELECT ST_NPoints(ST_SimplifyPreserveTopology(
+                     'POLYGON((0 0,1 0,1 1,0 1,0 0))'::geometry, 10))"
+            )
+            .unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_SimplifyVW(
+                     'LINESTRING(0 0,5 0,5.01 10,5.02 0,10 0)'::geometry, 1))"
+            )
+            .unwrap(),
+            Some("LINESTRING(0 0,10 0)".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_knn_matrix() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(i || '>' || j || ':' || distance, ',' ORDER BY i, distance)
+                 FROM rostgis_knn_matrix(ARRAY[
+                     'POINT(0 0)'::geometry, 'POINT(1 0)'::geometry, 'POINT(3 0)'::geometry
+                 ], 1)"
+            )
+            .unwrap(),
+            Some("1>2:1,2>1:1,3>2:2".to_string())
+        );
+    }
+
+    #[pg_test(error = "RostGIS Error: rostgis_knn_matrix requires points, got ST_LineString")]
+    fn test_rostgis_knn_matrix_non_point() {
+        Spi::run("SELECT * FROM rostgis_knn_matrix(ARRAY['LINESTRING(0 0,1 1)'::geometry], 1)")
+            .unwrap();
+    }
+
+    #[pg_test]
+    fn test_rostgis_distance_matrix() {
+        let matrix = Spi::get_one::<Vec<Option<f64>>>(
+            "SELECT rostgis_distance_matrix(
+                 ARRAY['POINT(0 0)'::geometry, 'POINT(3 4)', NULL])",
+        )
+        .unwrap();
+        assert_eq!(
+            matrix,
+            Some(vec![
+                Some(0.0),
+                Some(5.0),
+                None,
+                Some(5.0),
+                Some(0.0),
+                None,
+                None,
+                None,
+                None
+            ])
+        );
+    }
+
+    #[pg_test]
+    fn test_bulk_bboxes() {
+        // Long arrays span several batches of the temporary memory context
+        let boxes = Spi::get_one::<Vec<Option<String>>>(
+            "SELECT bulk_bboxes(array_agg(
+                 CASE WHEN i % 1000 = 0 THEN NULL
+                      ELSE ST_MakeEnvelope(i, 0, i + 1, 2) END ORDER BY i))
+             FROM generate_series(1, 3000) AS i",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(boxes.len(), 3000);
+        assert_eq!(boxes[0], Some("BOX(1 0,2 2)".to_string()));
+        assert_eq!(boxes[999], None);
+        assert_eq!(boxes[2998], Some("BOX(2999 0,3000 2)".to_string()));
+    }
+
+    #[pg_test]
+    fn test_bulk_barrier_crossings() {
+        let counts = Spi::get_one::<Vec<Option<i32>>>(
+            "SELECT bulk_barrier_crossings(
+                 ARRAY['LINESTRING(0 0, 20 0)'::geometry, NULL, 'LINESTRING(0 5, 1 5)'],
+                 ARRAY['POLYGON((4 -1, 6 -1, 6 1, 4 1, 4 -1))'::geometry,
+                       'POLYGON((10 -1, 12 -1, 12 1, 10 1, 10 -1))'])",
+        )
+        .unwrap();
+        assert_eq!(counts, Some(vec![Some(2), None, Some(0)]));
+        assert_eq!(
+            Spi::get_one::<bool>(
+                "SELECT ST_Crosses('LINESTRING(0 0, 20 0)', 'POLYGON((4 -1, 6 -1, 6 1, 4 1, 4 -1))')"
+            )
+            .unwrap(),
+            Some(true)
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_index_join() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(i || '>' || j, ',' ORDER BY i, j)
+                 FROM rostgis_index_join(
+                     ARRAY['POLYGON((0 0, 4 0, 4 4, 0 0))'::geometry],
+                     ARRAY['POINT(3 1)'::geometry, 'POINT(1 3)'::geometry, NULL],
+                     'contains')"
+            )
+            .unwrap(),
+            Some("1>1".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_bulk_join() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(i || '>' || j, ',' ORDER BY i, j)
+                 FROM bulk_join(
+                     ARRAY['POINT(3 1)'::geometry, 'POINT(1 3)'::geometry, NULL],
+                     ARRAY['POLYGON((0 0, 4 0, 4 4, 0 0))'::geometry],
+                     'within')"
+            )
+            .unwrap(),
+            Some("1>1".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_approximatemedialaxis() {
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Length(ST_ApproximateMedialAxis(
+                     'POLYGON((0 0,10 0,10 2,0 2,0 0))'::geometry))"
+            )
+            .unwrap(),
+            Some(8.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Length(ST_StraightSkeleton('POLYGON((0 0,4 0,4 4,0 4,0 0))'::geometry))"
+            )
+            .unwrap()
+            .map(|length| (length * 1e9).round() / 1e9),
+            Some((4.0 * 8f64.sqrt() * 1e9).round() / 1e9)
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_stat() {
+        let decoded = "SELECT calls FROM rostgis_stat() WHERE counter = 'geometries_decoded'";
+        Spi::run("SET rostgis.stats = on").unwrap();
+        Spi::run("SELECT rostgis_stat_reset()").unwrap();
+        Spi::run(
+            "SELECT 'POINT(0 0)'::geometry && ST_MakePoint(x, x) FROM generate_series(1, 5) x",
+        )
+        .unwrap();
+        assert!(Spi::get_one::<i64>(decoded).unwrap().unwrap() >= 10);
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT calls FROM rostgis_stat() WHERE counter = 'bbox_tests'")
+                .unwrap(),
+            Some(5)
+        );
+
+        Spi::run("SELECT rostgis_stat_reset()").unwrap();
+        Spi::run("SET rostgis.stats = off").unwrap();
+        Spi::run("SELECT ST_AsText(ST_MakePoint(1, 2))").unwrap();
+        assert_eq!(Spi::get_one::<i64>(decoded).unwrap(), Some(0));
+    }
+
+    #[pg_test(error = "permission denied for function rostgis_stat_reset")]
+    fn test_rostgis_stat_reset_needs_superuser() {
+        Spi::run("CREATE ROLE rostgis_stat_user; SET ROLE rostgis_stat_user").unwrap();
+        Spi::run("SELECT rostgis_stat_reset()").unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_makevalid_flag() {
+        let bowtie = "'POLYGON((0 0,2 2,2 0,0 2,0 0))'::geometry";
+        assert_eq!(
+            Spi::get_one::<bool>(&format!("SELECT ST_IsValid({})", bowtie)).unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            Spi::get_one::<bool>(&format!("SELECT ST_IsValidCached({})", bowtie)).unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            Spi::get_one::<bool>(&format!(
+                "SELECT ST_IsValidCached(ST_MakeValid({0})) AND ST_IsValid(ST_MakeValid({0}))",
+                bowtie
+            ))
+            .unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>(&format!("SELECT ST_Area(ST_MakeValid({}))", bowtie)).unwrap(),
+            Some(2.0)
+        );
+
+        // Rebuilding the geometry drops the flag
+        assert_eq!(
+            Spi::get_one::<bool>(&format!(
+                "SELECT ST_IsValidCached(ST_SetSRID(ST_MakeValid({}), 3857))",
+                bowtie
+            ))
+            .unwrap(),
+            Some(false)
+        );
+    }
+
+    #[pg_test]
+    fn test_st_union_valid_flag() {
+        let hits = "SELECT calls FROM rostgis_stat() WHERE counter = 'valid_flag_hits'";
+        Spi::run("SET rostgis.stats = on").unwrap();
+        Spi::run("SELECT rostgis_stat_reset()").unwrap();
+
+        // Invalid inputs are repaired before the overlay
+        let bowtie = "'POLYGON((0 0,2 2,2 0,0 2,0 0))'::geometry";
+        assert_eq!(
+            Spi::get_one::<f64>(&format!("SELECT ST_Area(ST_Union(ARRAY[{}]))", bowtie)).unwrap(),
+            Some(2.0)
+        );
+        assert_eq!(Spi::get_one::<i64>(hits).unwrap(), Some(0));
+
+        // Repaired inputs skip the check, in both forms
+        Spi::run(&format!(
+            "SELECT ST_Union(ARRAY[ST_MakeValid({0}), ST_MakeValid({0})])",
+            bowtie
+        ))
+        .unwrap();
+        Spi::run(&format!(
+            "SELECT ST_Union(ST_MakeValid({})) FROM generate_series(1, 3)",
+            bowtie
+        ))
+        .unwrap();
+        assert_eq!(Spi::get_one::<i64>(hits).unwrap(), Some(5));
+
+        Spi::run("SET rostgis.trust_valid_flag = off").unwrap();
+        Spi::run(&format!("SELECT ST_Union(ARRAY[ST_MakeValid({})])", bowtie)).unwrap();
+        assert_eq!(Spi::get_one::<i64>(hits).unwrap(), Some(5));
+        Spi::run("SET rostgis.stats = off").unwrap();
+
+        // Trusting the flag never changes an answer, so ST_IsValid can back
+        // an expression index
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT provolatile::text FROM pg_proc WHERE proname = 'st_isvalid'"
+            )
+            .unwrap()
+            .as_deref(),
+            Some("i")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_unaryunion() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_GeometryType(u) || ':' || ST_Area(u) FROM ST_UnaryUnion(
+                     'MULTIPOLYGON(((0 0,2 0,2 2,0 2,0 0)),((1 1,3 1,3 3,1 3,1 1)))'::geometry) u"
+            )
+            .unwrap()
+            .as_deref(),
+            Some("ST_Polygon:7")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_union_array() {
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Area(ST_Union(ARRAY[
+                     'POLYGON((0 0,2 0,2 2,0 2,0 0))'::geometry,
+                     'POLYGON((1 1,3 1,3 3,1 3,1 1))'::geometry,
+                     NULL]))"
+            )
+            .unwrap(),
+            Some(7.0)
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_GeometryType(ST_Union(array_agg(ST_MakePoint(x, 0))))
+                 FROM generate_series(1, 3) x"
+            )
+            .unwrap(),
+            Some("ST_MultiPoint".to_string())
+        );
+        // Crossing lines are noded, as by the aggregate and ST_UnaryUnion
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_Union(ARRAY[
+                     'LINESTRING(0 0, 2 2)'::geometry,
+                     'LINESTRING(0 2, 2 0)'::geometry]))"
+            )
+            .unwrap(),
+            Some("MULTILINESTRING((0 0,1 1),(1 1,2 2),(0 2,1 1),(1 1,2 0))".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_idw() {
+        let estimate = Spi::get_one::<f64>(
+            "SELECT ST_IDW(ST_MakePoint(x, 0), x * 10.0, ST_MakePoint(1, 0))
+             FROM (VALUES (0.0), (2.0)) AS s(x)",
+        )
+        .unwrap();
+        assert_eq!(estimate, Some(10.0));
+
+        let grid = Spi::get_one::<Vec<Option<f64>>>(
+            "SELECT ST_IDWGrid(ST_MakePoint(x, 0), x, 'MULTIPOINT((0 0),(4 0))'::geometry, 2, 1)
+             FROM (VALUES (0.0), (2.0), (4.0)) AS s(x)",
+        )
+        .unwrap();
+        assert_eq!(grid, Some(vec![Some(0.0), Some(4.0)]));
+    }
+
+    #[pg_test]
+    fn test_rostgis_map_match() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(coalesce(road_id::text, '-'), ',' ORDER BY seq)
+                 FROM rostgis_map_match(
+                     'LINESTRING(1 0.3,2 0.6,3 0.4,50 50)'::geometry,
+                     ARRAY['LINESTRING(0 0,10 0)'::geometry, 'LINESTRING(0 1,10 1)'::geometry],
+                     ARRAY[7, 8]::bigint[],
+                     2)"
+            )
+            .unwrap(),
+            Some("7,7,7,-".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_moransi() {
+        let statistics = Spi::get_two::<f64, f64>(
+            "SELECT ST_MoransI(ST_MakePoint(x, 0), v, 'distance', 1),
+                    ST_GearysC(ST_MakePoint(x, 0), v, 'distance', 1)
+             FROM (VALUES (0, 1.0), (1, 0.0), (2, 1.0), (3, 0.0)) AS s(x, v)",
+        )
+        .unwrap();
+        assert_eq!(statistics, (Some(-1.0), Some(1.5)));
+    }
+
+    #[pg_test(
+        error = "RostGIS Error: unknown weighting scheme 'queen', expected 'distance' or 'knn'"
+    )]
+    fn test_st_moransi_unknown_weights() {
+        Spi::run(
+            "SELECT ST_MoransI(ST_MakePoint(x, 0), x, 'queen', 1) FROM generate_series(1, 3) AS x",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_buffer() {
+        assert_eq!(
+            Spi::get_one::<bool>(
+                "SELECT ST_IsEmpty(ST_Buffer('POLYGON((0 0,2 0,2 2,0 2,0 0))'::geometry, -1.5))"
+            )
+            .unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_GeometryType(ST_BufferStyle(
+                     'POLYGON((0 0,10 0,10 5,20 5,20 6,10 6,10 10,0 10,0 0))'::geometry, 1, 'open'))"
+            )
+            .unwrap(),
+            Some("ST_Polygon".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Area(ST_Buffer('LINESTRING(0 0,10 0)'::geometry, 1, 'endcap=flat side=left'))"
+            )
+            .unwrap()
+            .map(|area| area.round()),
+            Some(10.0)
+        );
+        let (setback, outer) = Spi::get_two::<f64, f64>(
+            "SELECT ST_Area(ST_RingOffset(g, -1, 'join=mitre')),
+                    ST_Area(ST_RingOffset(g, 1, 'join=mitre'))
+             FROM (SELECT 'POLYGON((0 0,10 0,10 10,0 10,0 0))'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(
+            (setback.map(f64::round), outer.map(f64::round)),
+            (Some(36.0), Some(44.0))
+        );
+        // The side of ST_Buffer has no effect on polygons
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Area(ST_Buffer('POLYGON((0 0,10 0,10 10,0 10,0 0))'::geometry,
+                                          1, 'side=left join=mitre'))"
+            )
+            .unwrap()
+            .map(f64::round),
+            Some(144.0)
+        );
+    }
+
+    #[pg_test]
+    fn test_overlay_fallback_setting() {
+        // Overlays that do not fail give the same result either way
+        for setting in ["on", "off"] {
+            Spi::run(&format!("SET LOCAL rostgis.overlay_fallback = {}", setting)).unwrap();
+            assert_eq!(
+                Spi::get_one::<f64>(
+                    "SELECT ST_Area(ST_Union(ARRAY[
+                         'POLYGON((0 0,2 0,2 2,0 2,0 0))'::geometry,
+                         'POLYGON((1 0,3 0,3 2,1 2,1 0))'::geometry]))"
+                )
+                .unwrap(),
+                Some(6.0)
+            );
+        }
+    }
+
+    #[pg_test]
+    fn test_st_pointonsurface() {
+        assert_eq!(
+            Spi::get_one::<bool>(
+                "SELECT ST_Intersects(g, ST_PointOnSurface(g))
+                 FROM (SELECT 'POLYGON((0 0,10 0,10 10,8 10,8 2,2 2,2 10,0 10,0 0))'::geometry AS g) s"
+            )
+            .unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            Spi::get_one::<String>("SELECT ST_AsText(ST_PointOnSurface('POINT(1 2)'::geometry))")
+                .unwrap(),
+            Some("POINT(1 2)".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_boundary() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_Boundary('LINESTRING(1 1,0 0,-1 1)'::geometry))"
+            )
+            .unwrap(),
+            Some("MULTIPOINT((1 1),(-1 1))".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_Boundary('POLYGON((0 0,1 0,1 1,0 0))'::geometry))"
+            )
+            .unwrap(),
+            Some("LINESTRING(0 0,1 0,1 1,0 0)".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_makevalid_params() {
+        let polygon = "'POLYGON((0 0,10 0,10 10,0 10,0 0),(8 2,12 2,12 4,8 4,8 2))'::geometry";
+        assert_eq!(
+            Spi::get_one::<f64>(&format!("SELECT ST_Area(ST_MakeValid({}))", polygon)).unwrap(),
+            Some(100.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>(&format!(
+                "SELECT ST_Area(ST_MakeValid({}, 'method=structure'))",
+                polygon
+            ))
+            .unwrap(),
+            Some(96.0)
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_MakeValid('LINESTRING(1 1,1 1)'::geometry, 'keepcollapsed=true'))"
+            )
+            .unwrap(),
+            Some("POINT(1 1)".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_MakeValid('LINESTRING(1 1,1 1)'::geometry))"
+            )
+            .unwrap(),
+            Some("POINT(1 1)".to_string())
+        );
+    }
+
+    #[pg_test(error = "RostGIS Error: Invalid value 'fix' for repair parameter 'method'")]
+    fn test_st_makevalid_invalid_params() {
+        Spi::run("SELECT ST_MakeValid('POINT(0 0)'::geometry, 'method=fix')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_compact_storage() {
+        Spi::run(
+            "CREATE TABLE compact_tracks (
+                id int,
+                plain geometry(LineString, 4326),
+                packed geometry(LineString, 4326, Compact)
+            )",
+        )
+        .unwrap();
+        Spi::run(
+            "INSERT INTO compact_tracks VALUES (1,
+                'SRID=4326;LINESTRING(13.4049537 52.5200066,13.4051 52.5201)',
+                'SRID=4326;LINESTRING(13.4049537 52.5200066,13.4051 52.5201)')",
+        )
+        .unwrap();
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT rostgis_storage_encoding(plain) || ',' || rostgis_storage_encoding(packed)
+                 FROM compact_tracks"
+            )
+            .unwrap(),
+            Some("wkb,compact".to_string())
+        );
+        assert_eq!(
+            Spi::get_one::<bool>("SELECT ST_AsText(plain) = ST_AsText(packed) FROM compact_tracks")
+                .unwrap(),
+            Some(true)
+        );
+
+        Spi::run("SET rostgis.storage_encoding = compact").unwrap();
+        Spi::run("INSERT INTO compact_tracks (id, plain) SELECT 2, plain FROM compact_tracks")
+            .unwrap();
+        Spi::run("RESET rostgis.storage_encoding").unwrap();
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT rostgis_storage_encoding(plain) || ' ' || ST_AsText(plain)
+                 FROM compact_tracks WHERE id = 2"
+            )
+            .unwrap(),
+            Some("compact LINESTRING(13.4049537 52.5200066,13.4051 52.5201)".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_st_updatevertices() {
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT ST_AsText(ST_UpdateVertices(
+                    'POLYGON((0 0,4 0,4 4,0 4,0 0))'::geometry,
+                    ARRAY[1, 3],
+                    ARRAY['POINT(1 1)'::geometry, 'POINT(5 5)'::geometry]))"
+            )
+            .unwrap(),
+            Some("POLYGON((1 1,4 0,5 5,0 4,1 1))".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_closest_feature() {
+        Spi::run(
+            "CREATE TABLE closest_features (id int, geom geometry);
+             INSERT INTO closest_features
+             SELECT i, ST_MakePoint(i % 17, i / 17) FROM generate_series(0, 288) AS i;
+             INSERT INTO closest_features VALUES (-1, 'LINESTRING(0 20,20 0)'::geometry);",
+        )
+        .unwrap();
+        let ids = Spi::get_one::<String>(
+            "SELECT string_agg(f.id::text, ',' ORDER BY c.rank)
+             FROM rostgis_closest_feature('closest_features', 'geom', ST_MakePoint(3.2, 2.9), 3) c
+             JOIN closest_features f ON f.ctid = c.row_ctid",
+        )
+        .unwrap();
+        assert_eq!(ids, Some("54,55,37".to_string()));
+    }
+
+    #[pg_test]
+    fn test_rostgis_partition_by_grid() {
+        Spi::run(
+            "CREATE TABLE grid_points (id int, geom geometry);
+             INSERT INTO grid_points
+             SELECT i, ST_MakePoint(i % 20, i / 20) FROM generate_series(0, 399) AS i;
+             INSERT INTO grid_points VALUES (400, NULL);",
+        )
+        .unwrap();
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT rostgis_partition_by_grid('grid_points', 'geom', 10)")
+                .unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM grid_points_grid_1_0").unwrap(),
+            Some(100)
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM grid_points_grid_default").unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            Spi::get_one::<i64>(
+                "SELECT count(*) FROM grid_points_grid
+                 WHERE grid_points_grid_cell(geom)
+                       = ANY (rostgis_grid_cells('grid_points_grid', 'POINT(5 5)'::geometry))"
+            )
+            .unwrap(),
+            Some(100)
+        );
+
+        // Rows inserted later are found whether they reach beyond the extent
+        // of their cell or land in a cell of the default partition
+        Spi::run(
+            "INSERT INTO grid_points_grid VALUES
+                 (401, 'LINESTRING(-45 5, 55 5)'), (402, 'POINT(105 105)')",
+        )
+        .unwrap();
+        let matches = |area: &str| {
+            Spi::get_one::<Vec<i32>>(&format!(
+                "SELECT array_agg(id ORDER BY id) FROM grid_points_grid
+                 WHERE grid_points_grid_cell(geom)
+                       = ANY (rostgis_grid_cells('grid_points_grid', '{area}'::geometry))
+                   AND geom && '{area}'::geometry"
+            ))
+            .unwrap()
+        };
+        assert_eq!(matches("POINT(50 5)"), Some(vec![401]));
+        assert_eq!(matches("POINT(105 105)"), Some(vec![402]));
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM grid_points_grid_default").unwrap(),
+            Some(2)
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_dissolve() {
+        Spi::run(
+            "CREATE TABLE dissolve_parcels (county text, zone int, geom geometry);
+             INSERT INTO dissolve_parcels
+             SELECT CASE WHEN i < 10 THEN 'north' ELSE 'south' END, i % 2,
+                    ST_MakeEnvelope(i, 0, i + 1, 1)
+             FROM generate_series(0, 19) AS i;
+             INSERT INTO dissolve_parcels VALUES ('north', 0, NULL);",
+        )
+        .unwrap();
+        let (area, members) = Spi::get_two::<f64, i64>(
+            "SELECT ST_Area(geom), members
+             FROM rostgis_dissolve('dissolve_parcels', 'geom', ARRAY['county'])
+             WHERE group_key = '{\"county\": \"north\"}'",
+        )
+        .unwrap();
+        assert_eq!(area, Some(10.0));
+        assert_eq!(members, Some(10));
+        let groups = Spi::get_one::<i64>(
+            "SELECT count(*) FROM rostgis_dissolve('dissolve_parcels', 'geom',
+                                                   ARRAY['county', 'zone'])",
+        )
+        .unwrap();
+        assert_eq!(groups, Some(4));
+        let whole = Spi::get_one::<String>(
+            "SELECT ST_AsText(geom) FROM rostgis_dissolve('dissolve_parcels', 'geom')",
+        )
+        .unwrap();
+        assert_eq!(whole.map(|wkt| wkt.starts_with("POLYGON")), Some(true));
+    }
+
+    #[pg_test]
+    fn test_st_lookslikelatlon() {
+        let (swapped, ordered, either) = Spi::get_three::<bool, bool, bool>(
+            "SELECT ST_LooksLikeLatLon(ST_GeomFromText('POINT(-33.87 151.21)')),
+                    ST_LooksLikeLatLon(ST_GeomFromText('POINT(151.21 -33.87)')),
+                    ST_LooksLikeLatLon(ST_GeomFromText('POINT(13.4 52.5)'))",
+        )
+        .unwrap();
+        assert_eq!((swapped, ordered, either), (Some(true), Some(false), None));
+
+        // The range check only warns
+        Spi::run("SET rostgis.check_lonlat_range = on").unwrap();
+        let wkt = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_FlipCoordinates(ST_SetSRID(ST_MakePoint(-33.87, 151.21), 4326)))",
+        )
+        .unwrap();
+        assert_eq!(wkt.as_deref(), Some("POINT(151.21 -33.87)"));
+        Spi::run("RESET rostgis.check_lonlat_range").unwrap();
+    }
+
+    #[pg_test]
+    fn test_3d_measures() {
+        let (distance, intersects, within) = Spi::get_three::<f64, bool, bool>(
+            "SELECT ST_3DDistance(a, b), ST_3DIntersects(a, b), ST_3DDWithin(a, b, 3)
+             FROM (SELECT ST_GeomFromText('LINESTRING(0 0, 10 0)') AS a,
+                          ST_MakePoint(5, 3) AS b) AS g",
+        )
+        .unwrap();
+        assert_eq!(
+            (distance, intersects, within),
+            (Some(3.0), Some(false), Some(true))
+        );
+
+        let (closest, shortest) = Spi::get_two::<String, String>(
+            "SELECT ST_AsText(ST_3DClosestPoint(a, b)), ST_AsText(ST_3DShortestLine(a, b))
+             FROM (SELECT ST_GeomFromText('LINESTRING(0 0, 10 0)') AS a,
+                          ST_MakePoint(5, 3) AS b) AS g",
+        )
+        .unwrap();
+        assert_eq!(closest.as_deref(), Some("POINT(5 0)"));
+        assert_eq!(shortest.as_deref(), Some("LINESTRING(5 0,5 3)"));
+        let (length, perimeter) = Spi::get_two::<f64, f64>(
+            "SELECT ST_3DLength('LINESTRING(0 0, 3 4)'::geometry),
+                    ST_3DPerimeter('POLYGON((0 0, 3 0, 3 3, 0 3, 0 0))'::geometry)",
+        )
+        .unwrap();
+        assert_eq!((length, perimeter), (Some(5.0), Some(12.0)));
+    }
+
+    #[pg_test]
+    fn test_3d_measures_use_z() {
+        let (distance, intersects, within) = Spi::get_three::<f64, bool, bool>(
+            "SELECT ST_3DDistance('POINT Z (0 0 0)'::geometry, 'POINT Z (0 0 10)'::geometry),
+                    ST_3DIntersects('POINT Z (5 5 4)'::geometry,
+                                    'POLYGON Z ((0 0 0, 10 0 0, 10 10 0, 0 10 0, 0 0 0))'::geometry),
+                    ST_3DDWithin('POINT Z (5 5 4)'::geometry,
+                                 'POLYGON Z ((0 0 0, 10 0 0, 10 10 0, 0 10 0, 0 0 0))'::geometry, 4)",
+        )
+        .unwrap();
+        assert_eq!(distance, Some(10.0));
+        assert_eq!(intersects, Some(false));
+        assert_eq!(within, Some(true));
+    }
+
+    #[pg_test]
+    fn test_3d_length_and_shortest_line_use_z() {
+        let (length, line) = Spi::get_two::<f64, String>(
+            "SELECT ST_3DLength('LINESTRING Z (0 0 0, 0 0 5)'::geometry),
+                    ST_AsText(ST_3DShortestLine('LINESTRING Z (0 0 0, 10 0 0)'::geometry,
+                                                'LINESTRING Z (5 -5 3, 5 5 3)'::geometry))",
+        )
+        .unwrap();
+        assert_eq!(length, Some(5.0));
+        assert_eq!(line.as_deref(), Some("LINESTRING Z (5 0 0,5 0 3)"));
+    }
+
+    #[pg_test]
+    fn test_z_and_m_storage() {
+        let (point, z) = Spi::get_two::<String, f64>(
+            "SELECT ST_AsText(ST_MakePointZ(1, 2, 3)), ST_Z(ST_MakePointZ(1, 2, 3))",
+        )
+        .unwrap();
+        assert_eq!(point.as_deref(), Some("POINT Z (1 2 3)"));
+        assert_eq!(z, Some(3.0));
+
+        // Text, WKB and the stored form keep Z and M
+        let (text, wkb) = Spi::get_two::<String, String>(
+            "SELECT 'SRID=4326;LINESTRING ZM (0 0 1 2, 1 1 3 4)'::geometry::text,
+                    ST_AsText(ST_GeomFromWKB(encode(ST_AsBinary('POLYGON M ((0 0 1, 1 0 2, 1 1 3, 0 0 1))'::geometry), 'hex')))",
+        )
+        .unwrap();
+        assert_eq!(
+            text.as_deref(),
+            Some("SRID=4326;LINESTRING ZM (0 0 1 2,1 1 3 4)")
+        );
+        assert_eq!(
+            wkb.as_deref(),
+            Some("POLYGON M ((0 0 1,1 0 2,1 1 3,0 0 1))")
+        );
+
+        Spi::run("CREATE TABLE zm_test (geom geometry(PointZ, 4326))").unwrap();
+        Spi::run("INSERT INTO zm_test VALUES ('SRID=4326;POINT Z (1 2 3)')").unwrap();
+        let json = Spi::get_one::<String>("SELECT ST_AsGeoJSON(geom) FROM zm_test").unwrap();
+        assert_eq!(
+            json.as_deref(),
+            Some(r#"{"type":"Point","coordinates":[1,2,3]}"#)
+        );
+    }
+
+    #[pg_test]
+    fn test_st_makeline_z_array() {
+        let line = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_MakeLine(ARRAY[0, 1]::float8[], ARRAY[0, 1]::float8[], ARRAY[5, 6]::float8[]))",
+        )
+        .unwrap();
+        assert_eq!(line.as_deref(), Some("LINESTRING Z (0 0 5,1 1 6)"));
+    }
+
+    #[pg_test]
+    fn test_st_zcoords() {
+        let (zs, flat) = Spi::get_two::<Vec<f64>, Vec<f64>>(
+            "SELECT ST_ZCoords('LINESTRING Z (0 0 5, 1 1 6)'::geometry),
+                    ST_ZCoords('LINESTRING(0 0, 1 1)'::geometry)",
+        )
+        .unwrap();
+        assert_eq!(zs, Some(vec![5.0, 6.0]));
+        assert_eq!(flat, None);
+    }
+
+    #[pg_test]
+    fn test_force_dimensions() {
+        let two = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Force2D(ST_SetSRID('LINESTRING(0 0, 1 1)'::geometry, 3857)))",
+        )
+        .unwrap();
+        assert_eq!(two.as_deref(), Some("LINESTRING(0 0,1 1)"));
+        let (three, four, dropped) = Spi::get_three::<String, String, String>(
+            "SELECT ST_AsText(ST_Force3D('LINESTRING(0 0, 1 1)'::geometry, 5)),
+                    ST_AsText(ST_Force4D(ST_MakePointZ(1, 2, 3))),
+                    ST_AsText(ST_Force2D('POINT ZM (1 2 3 4)'::geometry))",
+        )
+        .unwrap();
+        assert_eq!(three.as_deref(), Some("LINESTRING Z (0 0 5,1 1 5)"));
+        assert_eq!(four.as_deref(), Some("POINT ZM (1 2 3 0)"));
+        assert_eq!(dropped.as_deref(), Some("POINT(1 2)"));
+    }
+
+    #[pg_test]
+    fn test_session_geometry_store() {
+        Spi::run(
+            "SELECT rostgis_set_geom('zone', 'POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))'::geometry)",
+        )
+        .unwrap();
+        let (text, inside, outside) = Spi::get_three::<String, bool, bool>(
+            "SELECT ST_AsText(rostgis_get_geom('zone')),
+                    rostgis_geom_contains('zone', ST_MakePoint(5, 5)),
+                    rostgis_geom_intersects('zone', ST_MakePoint(20, 20))",
+        )
+        .unwrap();
+        assert_eq!(text.as_deref(), Some("POLYGON((0 0,10 0,10 10,0 10,0 0))"));
+        assert_eq!((inside, outside), (Some(true), Some(false)));
+
+        let removed = Spi::get_one::<bool>("SELECT rostgis_unset_geom('zone')").unwrap();
+        assert_eq!(removed, Some(true));
+    }
+
+    #[pg_test(
+        error = "RostGIS Error: No geometry named 'zone' is set, store one with rostgis_set_geom"
+    )]
+    fn test_session_geometry_store_unset() {
+        Spi::run("SELECT rostgis_set_geom('zone', 'POINT(1 1)'::geometry)").unwrap();
+        Spi::run("SELECT rostgis_unset_geom('zone')").unwrap();
+        Spi::run("SELECT rostgis_get_geom('zone')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_geojsonseq_view() {
+        Spi::run(
+            "CREATE TABLE seq_parcels (parcel_id int, name text, geom geometry);
+             INSERT INTO seq_parcels VALUES (1, 'a\"b', ST_MakePoint(1.5, 2)), (2, NULL, NULL)",
+        )
+        .unwrap();
+        let view = Spi::get_one::<String>(
+            "SELECT rostgis_geojsonseq_view('seq_parcels', id_column => 'parcel_id')",
+        )
+        .unwrap();
+        assert_eq!(view.as_deref(), Some("public.seq_parcels_geojsonseq"));
+        let features = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(feature ORDER BY feature) FROM seq_parcels_geojsonseq",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            features,
+            vec![
+                r#"{"type":"Feature","id":1,"geometry":{"type":"Point","coordinates":[1.5,2]},"properties":{"name":"a\"b"}}"#,
+                r#"{"type":"Feature","id":2,"geometry":null,"properties":{"name":null}}"#,
+            ]
+        );
+
+        Spi::run(
+            "SELECT rostgis_geojsonseq_view('seq_parcels', view_name => 'seq_rs',
+                                            record_separator => true)",
+        )
+        .unwrap();
+        let separated =
+            Spi::get_one::<bool>("SELECT bool_and(feature LIKE E'\\x1e{%') FROM seq_rs").unwrap();
+        assert_eq!(separated, Some(true));
+    }
+
+    #[pg_test]
+    fn test_affine_transformations() {
+        let (translated, scaled, affine) = Spi::get_three::<String, String, String>(
+            "SELECT ST_AsText(ST_Translate(g, 10, -1)),
+                    ST_AsText(ST_Scale(g, 'POINT(2 3)'::geometry, 'POINT(1 1)'::geometry)),
+                    ST_AsText(ST_Affine(g, 1, 1, 0, 2, 0, 5))
+             FROM (SELECT 'LINESTRING(1 1, 2 3)'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(translated.as_deref(), Some("LINESTRING(11 0,12 2)"));
+        assert_eq!(scaled.as_deref(), Some("LINESTRING(1 1,3 7)"));
+        assert_eq!(affine.as_deref(), Some("LINESTRING(2 7,5 11)"));
+
+        let rotated = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Rotate('POINT(2 1)'::geometry, pi() / 2, 'POINT(1 1)'::geometry), 6)",
+        )
+        .unwrap();
+        assert_eq!(rotated.as_deref(), Some("POINT(1 2)"));
+        let about_coordinates = Spi::get_one::<bool>(
+            "SELECT ST_Equals(ST_Rotate(g, 1.0, 3, 4), ST_Rotate(g, 1.0, ST_MakePoint(3, 4)))
+             FROM (SELECT 'POLYGON((0 0, 1 0, 1 1, 0 0))'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(about_coordinates, Some(true));
+
+        // 3D forms keeping geometries in the z = 0 plane
+        let flat = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Translate(ST_Scale('POINT(1 2)'::geometry, 2, 2, 5), 1, 1, 0))",
+        )
+        .unwrap();
+        assert_eq!(flat.as_deref(), Some("POINT(3 5)"));
+        let lifted = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Translate(ST_Scale('POINT Z (1 2 3)'::geometry, 2, 2, 5), 1, 1, 1))",
+        )
+        .unwrap();
+        assert_eq!(lifted.as_deref(), Some("POINT Z (3 5 16)"));
+    }
+
+    #[pg_test(
+        error = "RostGIS Error: ST_Translate: the geometry has no Z, so a transformation giving z values other than 0 is not supported"
+    )]
+    fn test_st_translate_out_of_plane() {
+        Spi::run("SELECT ST_Translate('POINT(1 2)'::geometry, 0, 0, 1)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_dump_functions() {
+        let (parts, second) = Spi::get_two::<i64, String>(
+            "SELECT count(*), max(ST_AsText(geom)) FILTER (WHERE path = '{2}')
+             FROM ST_Dump('MULTIPOLYGON(((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))'::geometry)",
+        )
+        .unwrap();
+        assert_eq!(parts, Some(2));
+        assert_eq!(second.as_deref(), Some("POLYGON((5 5,6 5,6 6,5 5))"));
+        let member = Spi::get_one::<String>(
+            "SELECT ST_AsText((ST_Dump('GEOMETRYCOLLECTION(POINT(1 2))'::geometry)).geom)",
+        )
+        .unwrap();
+        assert_eq!(member.as_deref(), Some("POINT(1 2)"));
+
+        let (vertices, hole_vertex) = Spi::get_two::<i64, String>(
+            "SELECT count(*), max(ST_AsText(geom)) FILTER (WHERE path = '{2,2}')
+             FROM ST_DumpPoints('POLYGON((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))'::geometry)",
+        )
+        .unwrap();
+        assert_eq!(vertices, Some(8));
+        assert_eq!(hole_vertex.as_deref(), Some("POINT(2 1)"));
+
+        let rings = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(path[1] || ':' || ST_AsText(geom) ORDER BY path)
+             FROM ST_DumpRings('POLYGON((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))'::geometry)",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            rings,
+            vec![
+                "0:POLYGON((0 0,4 0,4 4,0 0))",
+                "1:POLYGON((1 1,2 1,2 2,1 1))"
+            ]
+        );
+        let empty =
+            Spi::get_one::<i64>("SELECT count(*) FROM ST_Dump('POINT EMPTY'::geometry)").unwrap();
+        assert_eq!(empty, Some(0));
+    }
+
+    #[pg_test(error = "RostGIS Error: ST_DumpRings: only polygons have rings, not ST_Point")]
+    fn test_st_dumprings_not_polygon() {
+        Spi::run("SELECT count(*) FROM ST_DumpRings('POINT(0 0)'::geometry)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_geometry_dump_type() {
+        let (path, geom) = Spi::get_two::<Vec<i32>, String>(
+            "SELECT (dp).path, ST_AsText((dp).geom)
+             FROM (SELECT ST_Dump('MULTIPOINT(1 2, 3 4)'::geometry) AS dp) AS t
+             ORDER BY (dp).path DESC LIMIT 1",
+        )
+        .unwrap();
+        assert_eq!(path, Some(vec![2]));
+        assert_eq!(geom.as_deref(), Some("POINT(3 4)"));
+        let type_name =
+            Spi::get_one::<String>("SELECT pg_typeof(ST_DumpPoints('POINT(1 2)'::geometry))::text")
+                .unwrap();
+        assert_eq!(type_name.as_deref(), Some("geometry_dump"));
+        let declared = Spi::get_one::<String>(
+            "SELECT ST_AsText((ROW('{1}', 'POINT(5 6)'::geometry)::geometry_dump).geom)",
+        )
+        .unwrap();
+        assert_eq!(declared.as_deref(), Some("POINT(5 6)"));
+    }
+
+    #[pg_test]
+    fn test_st_profile() {
+        let (vertices, length) = Spi::get_two::<i64, f64>(
+            "SELECT count(*), max(distance)
+             FROM ST_Profile(ST_GeomFromText('LINESTRING(0 0, 0 3, 4 3)'))",
+        )
+        .unwrap();
+        assert_eq!((vertices, length), (Some(3), Some(7.0)));
+        let bearing = Spi::get_one::<f64>(
+            "SELECT degrees(bearing)
+             FROM ST_Profile(ST_GeomFromText('LINESTRING(0 0, 0 3, 4 3)'))
+             WHERE vertex = 3",
+        )
+        .unwrap();
+        assert_eq!(bearing, Some(90.0));
+    }
+
+    #[pg_test]
+    fn test_box3d() {
+        let text = Spi::get_one::<String>("SELECT 'BOX3D(4 5 6, 1 2 3)'::box3d::text").unwrap();
+        assert_eq!(text.as_deref(), Some("BOX3D(1 2 3,4 5 6)"));
+        let text =
+            Spi::get_one::<String>("SELECT ST_GeomFromText('LINESTRING(0 1, 4 -2)')::box3d::text")
+                .unwrap();
+        assert_eq!(text.as_deref(), Some("BOX3D(0 -2 0,4 1 0)"));
+        let text =
+            Spi::get_one::<String>("SELECT 'LINESTRING Z (0 1 5, 4 -2 -3)'::geometry::box3d::text")
+                .unwrap();
+        assert_eq!(text.as_deref(), Some("BOX3D(0 -2 -3,4 1 5)"));
+
+        let (above, through) = Spi::get_two::<bool, bool>(
+            "SELECT 'BOX3D(0 0 0,10 10 10)'::box3d &&& 'BOX3D(5 5 11,20 20 20)'::box3d,
+                    ST_MakePoint(5, 5) &&& 'BOX3D(0 0 -1,10 10 1)'::box3d",
+        )
+        .unwrap();
+        assert_eq!((above, through), (Some(false), Some(true)));
+        let flat =
+            Spi::get_one::<bool>("SELECT ST_MakePoint(5, 5) &&& 'BOX3D(0 0 1,10 10 2)'::box3d")
+                .unwrap();
+        assert_eq!(flat, Some(false));
+        let (lifted, apart) = Spi::get_two::<bool, bool>(
+            "SELECT ST_MakePointZ(5, 5, 1.5) &&& 'BOX3D(0 0 1,10 10 2)'::box3d,
+                    ST_MakePointZ(5, 5, 1.5) &&& ST_MakePoint(5, 5)",
+        )
+        .unwrap();
+        assert_eq!((lifted, apart), (Some(true), Some(false)));
+        let wkt = Spi::get_one::<String>("SELECT ST_AsText('BOX3D(0 0 0,2 1 5)'::box3d::geometry)")
+            .unwrap();
+        assert_eq!(wkt.as_deref(), Some("POLYGON((0 0,0 1,2 1,2 0,0 0))"));
+    }
+
+    #[pg_test]
+    fn test_box3d_cast_from_geometry_on_assignment() {
+        Spi::run(
+            "CREATE TABLE scan_extents (extent box3d);
+             INSERT INTO scan_extents VALUES (ST_MakePoint(1, 2));",
+        )
+        .unwrap();
+        let extent = Spi::get_one::<String>("SELECT extent::text FROM scan_extents").unwrap();
+        assert_eq!(extent.as_deref(), Some("BOX3D(1 2 0,1 2 0)"));
+        let context = Spi::get_one::<String>(
+            "SELECT castcontext::text FROM pg_cast
+             WHERE castsource = 'geometry'::regtype AND casttarget = 'box3d'::regtype",
+        )
+        .unwrap();
+        assert_eq!(context.as_deref(), Some("a"));
+    }
+
+    #[pg_test]
+    fn test_st_volume() {
+        // A 2 x 3 x 4 box and the same box with its lid removed
+        let (volume, closed, open) = Spi::get_three::<f64, bool, f64>(
+            "SELECT ST_Volume(box), ST_IsClosed(box), ST_Volume(lidless)
+             FROM (SELECT
+                 'POLYHEDRALSURFACE Z (
+                     ((0 0 0,0 3 0,2 3 0,2 0 0,0 0 0)), ((0 0 4,2 0 4,2 3 4,0 3 4,0 0 4)),
+                     ((0 0 0,2 0 0,2 0 4,0 0 4,0 0 0)), ((2 0 0,2 3 0,2 3 4,2 0 4,2 0 0)),
+                     ((2 3 0,0 3 0,0 3 4,2 3 4,2 3 0)), ((0 3 0,0 0 0,0 0 4,0 3 4,0 3 0)))'
+                     ::polyhedralsurface AS box,
+                 'POLYHEDRALSURFACE Z (
+                     ((0 0 0,0 3 0,2 3 0,2 0 0,0 0 0)),
+                     ((0 0 0,2 0 0,2 0 4,0 0 4,0 0 0)), ((2 0 0,2 3 0,2 3 4,2 0 4,2 0 0)),
+                     ((2 3 0,0 3 0,0 3 4,2 3 4,2 3 0)), ((0 3 0,0 0 0,0 0 4,0 3 4,0 3 0)))'
+                     ::polyhedralsurface AS lidless) AS t",
+        )
+        .unwrap();
+        assert_eq!(volume.map(f64::round), Some(24.0));
+        assert_eq!(closed, Some(true));
+        assert_eq!(open, Some(0.0));
+
+        let text = Spi::get_one::<String>(
+            "SELECT 'SRID=3857;POLYHEDRALSURFACE(((0 0,1 0,0 1,0 0)))'::polyhedralsurface::text",
+        )
+        .unwrap();
+        assert_eq!(
+            text.as_deref(),
+            Some("SRID=3857;POLYHEDRALSURFACE Z (((0 0 0,1 0 0,0 1 0,0 0 0)))")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_extrude() {
+        let (volume, closed, faces) = Spi::get_three::<f64, bool, String>(
+            "SELECT ST_Volume(solid), ST_IsClosed(solid), solid::text
+             FROM (SELECT ST_Extrude('POLYGON((0 0, 2 0, 2 1, 0 1, 0 0))'::geometry, 3)
+                   AS solid) AS t",
+        )
+        .unwrap();
+        assert_eq!(volume.map(f64::round), Some(6.0));
+        assert_eq!(closed, Some(true));
+        assert!(faces
+            .unwrap()
+            .starts_with("POLYHEDRALSURFACE Z (((0 0 0,0 1 0,2 1 0,2 0 0,0 0 0)),"));
+    }
+
+    #[pg_test]
+    fn test_geometry_statistics() {
+        Spi::run(
+            "CREATE TABLE analyzed_points (geom geometry);
+             INSERT INTO analyzed_points
+             SELECT ST_MakePoint(i % 100, i / 100) FROM generate_series(0, 9999) AS i;
+             INSERT INTO analyzed_points SELECT NULL FROM generate_series(1, 100);
+             ANALYZE analyzed_points;",
+        )
+        .unwrap();
+        let (kind, nullfrac) = Spi::get_two::<i16, f32>(
+            "SELECT stakind1, stanullfrac FROM pg_statistic
+             WHERE starelid = 'analyzed_points'::regclass",
+        )
+        .unwrap();
+        assert_eq!(kind, Some(analyze::STATISTIC_KIND_BOX_HISTOGRAM));
+        assert!((nullfrac.unwrap() - 0.0099).abs() < 0.001);
+
+        // A quarter of the points, where the default estimate is 0.5%
+        let plan = Spi::explain(
+            "SELECT * FROM analyzed_points WHERE geom && ST_MakeEnvelope(0, 0, 49.5, 49.5)",
+        )
+        .unwrap();
+        let rows = plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap();
+        assert!((2000.0..3000.0).contains(&rows), "estimated {} rows", rows);
+    }
+
+    #[pg_test]
+    fn test_st_distance_bounded() {
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Distance('LINESTRING(0 0,10 0)'::geometry, 'POINT(5 3)'::geometry, 5)"
+            )
+            .unwrap(),
+            Some(3.0)
+        );
+        assert_eq!(
+            Spi::get_one::<f64>(
+                "SELECT ST_Distance('LINESTRING(0 0,10 0)'::geometry, 'POINT(5 3)'::geometry, 2)"
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            Spi::get_one::<bool>(
+                "SELECT ST_DWithin('POLYGON((0 0,4 0,4 4,0 4,0 0))'::geometry,
+                                   'LINESTRING(6 0,6 4)'::geometry, 2)"
+            )
+            .unwrap(),
+            Some(true)
+        );
+    }
+
+    #[pg_test]
+    fn test_normalize_directions() {
+        Spi::run(
+            "CREATE TABLE test_rivers (id int, geom geometry);
+             INSERT INTO test_rivers VALUES
+                 (1, 'LINESTRING(10 10, 5 5)'),
+                 (2, 'LINESTRING(1 1, 4 4)'),
+                 (3, 'LINESTRING(6 0, 9 0)')",
+        )
+        .unwrap();
+        let reversed = Spi::get_one::<i64>(
+            "SELECT rostgis_normalize_directions('test_rivers', 'geom', 'POINT(0 0)'::geometry)",
+        )
+        .unwrap();
+        assert_eq!(reversed, Some(2));
+        let starts = Spi::get_one::<String>(
+            "SELECT string_agg(ST_AsText(ST_NormalizeDirection(geom, 0.0)), ';' ORDER BY id)
+             FROM test_rivers",
+        )
+        .unwrap();
+        // Pointing north turns the lines running south, not the one running west
+        assert_eq!(
+            starts.as_deref(),
+            Some("LINESTRING(5 5,10 10);LINESTRING(1 1,4 4);LINESTRING(9 0,6 0)")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_removesmallparts() {
+        let wkt = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_RemoveSmallParts(
+                 'MULTIPOLYGON(((0 0, 10 0, 10 10, 0 10, 0 0)), ((20 0, 21 0, 21 0.1, 20 0)))'::geometry,
+                 1.0))",
+        )
+        .unwrap();
+        assert_eq!(
+            wkt.as_deref(),
+            Some("MULTIPOLYGON(((0 0,10 0,10 10,0 10,0 0)))")
+        );
+    }
+
+    #[pg_test]
+    fn test_strict_axis_order() {
+        Spi::run("SET LOCAL rostgis.strict_axis_order = on").unwrap();
+        let row = Spi::get_three::<f64, String, String>(
+            "SELECT ST_X(g), ST_AsText(g), ST_AsGeoJSON(g)
+             FROM (SELECT ST_GeomFromText('POINT(52.52 13.4)', 4326) AS g) AS t",
+        )
+        .unwrap();
+        // Stored as lon/lat, exchanged as lat/lon
+        assert_eq!(row.0, Some(13.4));
+        assert_eq!(row.1.as_deref(), Some("POINT(52.52 13.4)"));
+        assert_eq!(
+            row.2.as_deref(),
+            Some(r#"{"type":"Point","coordinates":[52.52,13.4]}"#)
+        );
+        // Other SRIDs are unaffected
+        let text =
+            Spi::get_one::<String>("SELECT ST_AsText(ST_GeomFromText('POINT(52.52 13.4)', 3857))")
+                .unwrap();
+        assert_eq!(text.as_deref(), Some("POINT(52.52 13.4)"));
+
+        // The text form of the type stays longitude/latitude
+        let row = Spi::get_three::<f64, String, String>(
+            "SELECT ST_X(g), g::text, ST_AsText(g)
+             FROM (SELECT 'SRID=4326;POINT(13.4 52.52)'::geometry AS g) AS t",
+        )
+        .unwrap();
+        assert_eq!(row.0, Some(13.4));
+        assert_eq!(row.1.as_deref(), Some("SRID=4326;POINT(13.4 52.52)"));
+        assert_eq!(row.2.as_deref(), Some("POINT(52.52 13.4)"));
+    }
+
+    #[cfg(feature = "proj")]
+    #[pg_test]
+    fn test_strict_axis_order_transform() {
+        Spi::run("SET LOCAL rostgis.strict_axis_order = on").unwrap();
+        // A box at latitude 10, longitude 0, and back
+        let near = Spi::get_two::<bool, bool>(
+            "SELECT ST_DWithin(b::geometry, ST_MakePoint(0, 1118890), 1),
+                    ST_DWithin(ST_Transform(b, 3857, 4326)::geometry, ST_MakePoint(10, 0), 1e-9)
+             FROM ST_Transform(Box2D(ST_MakePoint(10, 0)), 4326, 3857) b",
+        )
+        .unwrap();
+        assert_eq!(near, (Some(true), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_output_precision() {
+        let point = "'POINT(1.23456 -0.0001)'::geometry";
+        let row = Spi::get_three::<String, String, String>(&format!(
+            "SELECT ST_AsText({0}, 2), ST_AsGeoJSON({0}, maxdecimaldigits => 3),
+                    ST_AsGML({0}, srs_dimension => false, maxdecimaldigits => 1)",
+            point
+        ))
+        .unwrap();
+        assert_eq!(row.0.as_deref(), Some("POINT(1.23 0)"));
+        assert_eq!(
+            row.1.as_deref(),
+            Some(r#"{"type":"Point","coordinates":[1.235,0]}"#)
+        );
+        assert_eq!(
+            row.2.as_deref(),
+            Some("<gml:Point><gml:pos>1.2 0</gml:pos></gml:Point>")
+        );
+
+        Spi::run("SET LOCAL rostgis.output_precision = 1").unwrap();
+        let (text, canonical) =
+            Spi::get_two::<String, String>(&format!("SELECT ST_AsText({0}), {0}::text", point))
+                .unwrap();
+        assert_eq!(text.as_deref(), Some("POINT(1.2 0)"));
+        // The type output stays lossless
+        assert_eq!(canonical.as_deref(), Some("POINT(1.23456 -0.0001)"));
+        let full = Spi::get_one::<String>(&format!("SELECT ST_AsText({}, -1)", point)).unwrap();
+        assert_eq!(full.as_deref(), Some("POINT(1.23456 -0.0001)"));
+
+        // Full-precision GML reads back to the same coordinates
+        let round_trip = Spi::get_one::<bool>(
+            "SELECT split_part(pos, ' ', 1)::float8 = ST_X(g) AND split_part(pos, ' ', 2)::float8 = ST_Y(g)
+             FROM (SELECT g, substring(ST_AsGML(g, srs_dimension => false, maxdecimaldigits => -1)
+                                       FROM '<gml:pos>(.*)</gml:pos>') AS pos
+                   FROM (SELECT ST_MakePoint(0.1 + 0.2, -1e-7) AS g) p) t",
+        )
+        .unwrap();
+        assert_eq!(round_trip, Some(true));
+    }
+
+    #[pg_test(
+        error = "RostGIS Error: Invalid maxdecimaldigits 16: expected -1 (full precision) or 0 to 15"
+    )]
+    fn test_output_precision_out_of_range() {
+        Spi::run("SELECT ST_AsText('POINT(1 2)'::geometry, 16)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_node() {
+        let wkt = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Node('LINESTRING(0 0, 10 10, 0 10, 10 0)'::geometry))",
+        )
+        .unwrap();
+        assert_eq!(
+            wkt.as_deref(),
+            Some("MULTILINESTRING((0 0,5 5),(5 5,10 10,0 10,5 5),(5 5,10 0))")
+        );
+    }
+
+    #[cfg(feature = "proj")]
+    #[pg_test]
+    fn test_st_transform_bbox() {
+        let result = Spi::get_two::<bool, bool>(
+            "SELECT ST_Contains(b::geometry, ST_MakePoint(111319, 111325)),
+                    ST_Contains(b::geometry, ST_MakePoint(111320, 0))
+             FROM ST_Transform(Box2D('LINESTRING(-1 -1, 1 1)'::geometry), 4326, 3857) b",
+        )
+        .unwrap();
+        assert_eq!(result, (Some(true), Some(false)));
+        let boxes = Spi::get_one::<String>(
+            "SELECT array_agg(ST_AsText(b))::text FROM unnest(ST_Transform(ARRAY[
+                 Box2D(ST_MakePoint(0, 0)), NULL, Box2D(ST_MakePoint(2, 3))
+             ], 3857, 3857)) b",
+        )
+        .unwrap();
+        assert_eq!(
+            boxes.as_deref(),
+            Some("{\"POINT(0 0)\",NULL,\"POINT(2 3)\"}")
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_from_postgis() {
+        // PostGIS 3 stores 'SRID=4326;POINT(1 2)' as these bytes
+        let (wkt, srid) = Spi::get_two::<String, i32>(
+            "SELECT ST_AsText(g), ST_SRID(g) FROM rostgis_from_postgis(
+                 '\\x800000000010e640010000000100000000000000000000f03f0000000000000040') g",
+        )
+        .unwrap();
+        assert_eq!(wkt.as_deref(), Some("POINT(1 2)"));
+        assert_eq!(srid, Some(4326));
+    }
+
+    #[pg_test]
+    fn test_st_linemerge() {
+        let merge = |sql: &str| Spi::get_one::<String>(sql).unwrap();
+        assert_eq!(
+            merge(
+                "SELECT ST_AsText(ST_LineMerge(
+                     'MULTILINESTRING((0 0, 1 1), (2 2, 1 1))'::geometry))"
+            )
+            .as_deref(),
+            Some("LINESTRING(0 0,1 1,2 2)")
+        );
+        assert_eq!(
+            merge(
+                "SELECT ST_AsText(ST_LineMerge(
+                     'MULTILINESTRING((0 0, 1 1), (2 2, 1 1))'::geometry, true))"
+            )
+            .as_deref(),
+            Some("MULTILINESTRING((0 0,1 1),(2 2,1 1))")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_greatcircle() {
+        let (points, srid) = Spi::get_two::<i32, i32>(
+            "SELECT ST_NPoints(g), ST_SRID(g) FROM (SELECT ST_GreatCircle(
+                 ST_MakePoint(8.57, 50.03), ST_MakePoint(-73.78, 40.64), 100000) AS g) r",
+        )
+        .unwrap();
+        assert_eq!(points, Some(64));
+        assert_eq!(srid, Some(4326));
+    }
+
+    #[pg_test(
+        error = "RostGIS Error: ST_GreatCircle: max_segment_length must be a positive number of meters"
+    )]
+    fn test_st_greatcircle_zero_segment_length() {
+        Spi::run("SELECT ST_GreatCircle(ST_MakePoint(0, 0), ST_MakePoint(1, 1), 0)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_buildarea() {
+        let area = Spi::get_one::<f64>(
+            "SELECT ST_Area(ST_BuildArea(ST_Collect(
+                 'LINESTRING(0 0, 10 0, 10 10, 0 10, 0 0)'::geometry,
+                 'LINESTRING(2 2, 2 8, 8 8, 8 2, 2 2)'::geometry)))",
+        )
+        .unwrap();
+        assert_eq!(area, Some(64.0));
+        let open = Spi::get_one::<bool>(
+            "SELECT ST_BuildArea('LINESTRING(0 0, 1 1, 2 0)'::geometry) IS NULL",
+        )
+        .unwrap();
+        assert_eq!(open, Some(true));
+    }
+
+    #[pg_test]
+    fn test_st_polygonize() {
+        let faces = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Polygonize(fence))
+             FROM (VALUES ('LINESTRING(0 0, 10 0, 10 10, 0 10, 0 0)'::geometry),
+                          ('LINESTRING(4 -1, 4 11)'),
+                          (NULL)) fences(fence)",
+        )
+        .unwrap();
+        assert_eq!(
+            faces.as_deref(),
+            Some(
+                "GEOMETRYCOLLECTION(POLYGON((0 0,4 0,4 10,0 10,0 0)),\
+                 POLYGON((4 0,10 0,10 10,4 10,4 0)))"
+            )
+        );
+        let array = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_Polygonize(ARRAY['LINESTRING(0 0, 1 0, 1 1, 0 0)'::geometry]))",
+        )
+        .unwrap();
+        assert_eq!(
+            array.as_deref(),
+            Some("GEOMETRYCOLLECTION(POLYGON((0 0,1 0,1 1,0 0)))")
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_watch() {
+        Spi::run(
+            "INSERT INTO rostgis_watch (name, region)
+             VALUES ('depot', 'POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))');
+             CREATE TABLE watched_vehicles (id int, geom geometry);
+             CREATE TRIGGER vehicles_watch AFTER INSERT OR UPDATE ON watched_vehicles
+                 FOR EACH ROW EXECUTE FUNCTION rostgis_watch_notify('geom');
+             INSERT INTO watched_vehicles VALUES (1, 'POINT(5 5)'), (2, 'POINT(50 50)'), (3, NULL)",
+        )
+        .unwrap();
+        let count = "SELECT count(*) FROM rostgis_watch_notifications(
+                         'POINT(9 9)', 'public.watched_vehicles', 'UPDATE')";
+        assert_eq!(Spi::get_one::<i64>(count).unwrap(), Some(1));
+        let payload = Spi::get_one::<String>(
+            "SELECT payload FROM rostgis_watch_notifications(
+                 'POINT(5 5)', 'public.watched_vehicles', 'INSERT')",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(payload.contains(r#""watch":"depot""#));
+        assert!(payload.contains(r#""geometry":{"coordinates":[5,5],"type":"Point"}"#));
+
+        // A new region is seen by the next statement
+        Spi::run(
+            "INSERT INTO rostgis_watch (name, channel, region)
+             VALUES ('yard', 'yard_alerts', 'POLYGON((8 8, 20 8, 20 20, 8 20, 8 8))')",
+        )
+        .unwrap();
+        assert_eq!(Spi::get_one::<i64>(count).unwrap(), Some(2));
+
+        // Versions are drawn from a sequence, so they are never reused
+        let (version, last) = Spi::get_two::<i64, i64>(
+            "SELECT version, (SELECT last_value FROM rostgis_table_versions_seq)
+             FROM rostgis_table_versions WHERE table_name = 'rostgis_watch'",
+        )
+        .unwrap();
+        assert_eq!(version, last);
+    }
+
+    #[pg_test]
+    fn test_rostgis_fences() {
+        Spi::run(
+            "INSERT INTO rostgis_fences VALUES
+                 ('depot', 'POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))'),
+                 ('yard', 'POLYGON((5 5, 20 5, 20 20, 5 20, 5 5))')",
+        )
+        .unwrap();
+        assert_eq!(
+            Spi::get_one::<bool>("SELECT ST_InFence('POINT(1 1)', 'depot')").unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            Spi::get_one::<bool>("SELECT ST_InFence('POINT(15 15)', 'depot')").unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            Spi::get_one::<Vec<String>>("SELECT rostgis_classify_fences('POINT(7 7)'::geometry)")
+                .unwrap(),
+            Some(vec!["depot".to_string(), "yard".to_string()])
+        );
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(i || ':' || fence_name, ',' ORDER BY i, fence_name)
+                 FROM rostgis_classify_fences(ARRAY['POINT(1 1)'::geometry, NULL, 'POINT(15 15)'])"
+            )
+            .unwrap()
+            .as_deref(),
+            Some("1:depot,3:yard")
+        );
+
+        // Changes to the registry are seen by the next statement
+        Spi::run("DELETE FROM rostgis_fences WHERE fence_name = 'yard'").unwrap();
+        assert_eq!(
+            Spi::get_one::<Vec<String>>("SELECT rostgis_classify_fences('POINT(7 7)'::geometry)")
+                .unwrap(),
+            Some(vec!["depot".to_string()])
+        );
+        // and by the rest of a statement making them
+        Spi::run(
+            "DO $$
+             BEGIN
+                 PERFORM ST_InFence('POINT(30 30)', 'depot');
+                 INSERT INTO rostgis_fences VALUES
+                     ('field', 'POLYGON((25 25, 40 25, 40 40, 25 40, 25 25))');
+                 IF NOT ST_InFence('POINT(30 30)', 'field') THEN
+                     RAISE 'the new fence is not seen';
+                 END IF;
+             END
+             $$",
+        )
+        .unwrap();
+
+        // A change rolled back is not seen
+        Spi::run(
+            "DO $$
+             BEGIN
+                 BEGIN
+                     DELETE FROM rostgis_fences WHERE fence_name = 'depot';
+                     RAISE EXCEPTION 'undo';
+                 EXCEPTION WHEN raise_exception THEN
+                 END;
+                 IF NOT ST_InFence('POINT(1 1)', 'depot') THEN
+                     RAISE 'the rolled back deletion is seen';
+                 END IF;
+             END
+             $$",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "RostGIS Error: unknown fence \"nowhere\"")]
+    fn test_st_infence_unknown() {
+        Spi::run("SELECT ST_InFence('POINT(1 1)', 'nowhere')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_asewkbchunks() {
+        let matches = Spi::get_one::<bool>(
+            "WITH g AS (SELECT ST_SetSRID('LINESTRING(0 0, 1 1, 2 0, 3 5)'::geometry, 4326) AS geom)
+             SELECT (SELECT string_agg(chunk, ''::bytea ORDER BY seq)
+                     FROM g, ST_AsEWKBChunks(g.geom, 10)) = ST_AsEWKB(geom)
+                AND ST_AsEWKB(geom) <> ST_AsBinary(geom)
+             FROM g",
+        )
+        .unwrap();
+        assert_eq!(matches, Some(true));
+        let chunks = Spi::get_one::<i64>(
+            "SELECT count(*) FROM ST_AsBinaryChunks('LINESTRING(0 0, 1 1, 2 0, 3 5)'::geometry, 10)",
+        )
+        .unwrap();
+        // 9 header and count bytes and 4 points of 16 bytes
+        assert_eq!(chunks, Some(8));
+    }
+
+    #[pg_test]
+    fn test_st_orthogonalizepolygon() {
+        let wkt = Spi::get_one::<String>(
+            "SELECT ST_AsText(ST_OrthogonalizePolygon(
+                 'POLYGON((0 0, 10 0.3, 10 10, 0.2 10, 0 0))'::geometry))",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(wkt.starts_with("POLYGON(("));
+        let area = Spi::get_one::<f64>(
+            "SELECT ST_Area(ST_OrthogonalizePolygon(
+                 'POLYGON((0 0, 10 0.3, 10 10, 0.2 10, 0 0))'::geometry, 10))",
+        )
+        .unwrap()
+        .unwrap();
+        assert!((area - 100.0).abs() < 3.0);
+    }
+
+    #[pg_test]
+    fn test_st_subdivide() {
+        let counts = Spi::get_two::<i64, i64>(
+            "SELECT count(*), max(ST_NPoints(piece))::bigint
+             FROM ST_Subdivide(ST_Buffer('POINT(0 0)'::geometry, 10, 200), 32) AS piece",
+        )
+        .unwrap();
+        assert!(counts.0.unwrap() > 8);
+        assert!(counts.1.unwrap() <= 32);
+    }
+
+    #[pg_test]
+    fn test_rostgis_features() {
+        let features = Spi::get_one::<String>(
+            "SELECT string_agg(feature || '=' || enabled, ',' ORDER BY feature)
+             FROM rostgis_features()",
+        )
+        .unwrap();
+        let expected = format!(
+            "mvt={},proj={},raster={}",
+            cfg!(feature = "mvt"),
+            cfg!(feature = "proj"),
+            cfg!(feature = "raster")
+        );
+        assert_eq!(features, Some(expected));
+    }
+
+    #[cfg(feature = "raster")]
+    #[pg_test]
+    fn test_rostgis_render() {
+        let png = Spi::get_one::<Vec<u8>>(
+            "SELECT rostgis_render(ARRAY['POLYGON((0 0,4 0,4 3,0 3,0 0))'::geometry],
+                                   64, 48, '{\"fill\": \"#ff000080\"}')",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[cfg(feature = "raster")]
+    #[pg_test(error = "rostgis_render image size must be between 1 and 4096 pixels")]
+    fn test_rostgis_render_invalid_size() {
+        Spi::run("SELECT rostgis_render(ARRAY['POINT(0 0)'::geometry], 0, 10)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_st_envelope() {
+        let point = crate::st_makepoint(1.0, 2.0);
+        let bbox = crate::box2d(point).unwrap();
+        // For a point, the box should be the point coordinates
+        assert_eq!(bbox.min_x, 1.0);
+        assert_eq!(bbox.min_y, 2.0);
+        assert_eq!(bbox.max_x, 1.0);
+        assert_eq!(bbox.max_y, 2.0);
+
+        let (point, polygon) = Spi::get_two::<String, String>(
+            "SELECT ST_Envelope(ST_MakePoint(1, 2))::text,
+                    ST_Envelope('SRID=4326;LINESTRING(0 0, 2 1, 1 3)'::geometry)::text",
+        )
+        .unwrap();
+        assert_eq!(point.as_deref(), Some("POINT(1 2)"));
+        assert_eq!(
+            polygon.as_deref(),
+            Some("SRID=4326;POLYGON((0 0,0 3,2 3,2 0,0 0))")
+        );
+        assert_eq!(
+            Spi::get_one::<String>("SELECT ST_AsText(ST_Envelope('POLYGON EMPTY'::geometry))")
+                .unwrap()
+                .as_deref(),
+            Some("POLYGON EMPTY")
+        );
+    }
+
+    #[pg_test]
+    fn test_st_makeenvelope() {
+        let (wkt, srid) = Spi::get_two::<String, i32>(
+            "SELECT ST_AsText(e), ST_SRID(e) FROM ST_MakeEnvelope(10, 10, 0, 0, 4326) e",
+        )
+        .unwrap();
+        assert_eq!(wkt.as_deref(), Some("POLYGON((0 0,0 10,10 10,10 0,0 0))"));
+        assert_eq!(srid, Some(4326));
+    }
+
+    #[pg_test]
+    fn test_rostgis_sample_extent() {
+        Spi::run(
+            "CREATE TABLE test_sample AS
+             SELECT i AS id, ST_MakePoint(i % 100, i / 100) AS geom
+             FROM generate_series(0, 9999) i",
+        )
+        .unwrap();
+        let (windows, rows) = Spi::get_two::<i64, i64>(
+            "SELECT count(DISTINCT sample_window), count(*)
+             FROM rostgis_sample_extent('test_sample', 'geom', 5, 3, 0.1, seed => 1)",
+        )
+        .unwrap();
+        assert_eq!((windows, rows), (Some(5), Some(15)));
+        // Every sampled row lies in its window, and a seed repeats the sample
+        let (outside, repeated) = Spi::get_two::<i64, bool>(
+            "WITH s AS (
+                 SELECT * FROM rostgis_sample_extent('test_sample', 'geom', 5, 3, 0.1, seed => 1))
+             SELECT count(*) FILTER (WHERE NOT t.geom && s.window_extent),
+                    array_agg(t.id ORDER BY t.id) = (
+                        SELECT array_agg(t2.id ORDER BY t2.id)
+                        FROM rostgis_sample_extent('test_sample', 'geom', 5, 3, 0.1, seed => 1) s2
+                        JOIN test_sample t2 ON t2.ctid = s2.row_ctid)
+             FROM s JOIN test_sample t ON t.ctid = s.row_ctid",
+        )
+        .unwrap();
+        assert_eq!((outside, repeated), (Some(0), Some(true)));
+        // Restricted to a region
+        let outside = Spi::get_one::<i64>(
+            "SELECT count(*) FROM rostgis_sample_extent('test_sample', 'geom', 4, 10, 0.5,
+                 ST_MakeEnvelope(0, 0, 9, 9)) s
+             JOIN test_sample t ON t.ctid = s.row_ctid
+             WHERE NOT t.geom && ST_MakeEnvelope(0, 0, 9, 9)",
+        )
+        .unwrap();
+        assert_eq!(outside, Some(0));
+    }
+
+    #[pg_test]
+    fn test_extension_schema_qualified() {
+        let schema = crate::utils::extension_schema().unwrap();
+        Spi::run(&format!(
+            "CREATE TABLE public.qualified_points AS
+             SELECT i AS id, {schema}.ST_MakePoint(i, 0) AS geom FROM generate_series(0, 9) AS i"
+        ))
+        .unwrap();
+        // Functions running SQL internally must not rely on the search_path
+        Spi::run("SET LOCAL search_path TO pg_catalog").unwrap();
+        let ids = Spi::get_one::<String>(&format!(
+            "SELECT string_agg(f.id::text, ',' ORDER BY c.rank)
+             FROM {schema}.rostgis_closest_feature(
+                 'public.qualified_points', 'geom', {schema}.ST_MakePoint(3.2, 0), 2) c
+             JOIN public.qualified_points f ON f.ctid = c.row_ctid"
+        ))
+        .unwrap();
+        assert_eq!(ids, Some("3,4".to_string()));
+        let sampled = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM {schema}.rostgis_sample_extent(
+                 'public.qualified_points', 'geom', 4, 10, 0.5, NULL, 1)"
+        ))
+        .unwrap();
+        assert_eq!(sampled, Some(10));
+        assert_eq!(
+            Spi::get_one::<bool>(&format!("SELECT {schema}.rostgis_srid_exists(4326)")).unwrap(),
+            Some(true)
+        );
+    }
+
+    #[pg_test]
+    fn test_rostgis_cluster_table() {
+        Spi::run(
+            "CREATE TABLE cluster_points (
+                 id int GENERATED ALWAYS AS IDENTITY,
+                 geom geometry
+             );
+             INSERT INTO cluster_points (geom)
+             SELECT ST_MakePoint(x % 10, x / 10) FROM generate_series(99, 0, -1) x",
+        )
+        .unwrap();
+        let rewritten =
+            Spi::get_one::<i64>("SELECT rostgis_cluster_table('cluster_points', 'geom', 30)")
+                .unwrap();
+        assert_eq!(rewritten, Some(100));
+        // The rows keep their identity values
+        let kept = Spi::get_one::<bool>(
+            "SELECT bool_and(ST_Equals(geom, ST_MakePoint((100 - id) % 10, (100 - id) / 10)))
+                    AND count(*) = 100
+             FROM cluster_points",
+        )
+        .unwrap();
+        assert_eq!(kept, Some(true));
+        let next = Spi::get_one::<i32>(
+            "INSERT INTO cluster_points (geom) VALUES ('POINT(0 0)') RETURNING id",
+        )
+        .unwrap();
+        assert_eq!(next, Some(101));
+    }
+
+    #[pg_test]
+    fn test_cluster_window_functions() {
+        let ids = Spi::get_one::<String>(
+            "SELECT string_agg(cid::text, ',' ORDER BY id)
+             FROM (
+                 SELECT id, ST_ClusterWithinWin(geom, 1.5) OVER () AS cid
+                 FROM (VALUES (1, ST_MakePoint(0, 0)), (2, ST_MakePoint(5, 0)),
+                              (3, ST_MakePoint(1, 0))) AS t(id, geom)
+             ) c",
+        )
+        .unwrap();
+        assert_eq!(ids.as_deref(), Some("0,1,0"));
+
+        let ids = Spi::get_one::<String>(
+            "SELECT string_agg(cid::text, ',' ORDER BY id)
+             FROM (
+                 SELECT id, ST_ClusterIntersectingWin(geom) OVER (PARTITION BY part) AS cid
+                 FROM (VALUES (1, 'a', ST_GeomFromText('LINESTRING(0 0, 2 2)')),
+                              (2, 'a', ST_GeomFromText('LINESTRING(0 2, 2 0)')),
+                              (3, 'b', ST_GeomFromText('POINT(9 9)'))) AS t(id, part, geom)
+             ) c",
+        )
+        .unwrap();
+        assert_eq!(ids.as_deref(), Some("0,0,0"));
+    }
+
+    #[pg_test]
+    fn test_spatial_ref_sys() {
+        assert!(crate::spatial_ref_sys::rostgis_srid_exists(4326).unwrap());
+        assert!(!crate::spatial_ref_sys::rostgis_srid_exists(123456).unwrap());
+
+        let proj4 = crate::spatial_ref_sys::rostgis_proj4text(4326).unwrap();
+        assert_eq!(
+            proj4.as_deref(),
+            Some("+proj=longlat +datum=WGS84 +no_defs")
+        );
+
+        let (zones, south) = Spi::get_two::<i64, String>(
+            "SELECT count(*), max(proj4text) FILTER (WHERE srid = 32733)
+             FROM spatial_ref_sys WHERE srid BETWEEN 32601 AND 32760",
+        )
+        .unwrap();
+        assert_eq!(zones, Some(120));
+        assert_eq!(
+            south.as_deref(),
+            Some("+proj=utm +zone=33 +south +datum=WGS84 +units=m +no_defs")
+        );
+        let srtext = crate::spatial_ref_sys::rostgis_srtext(32633)
+            .unwrap()
+            .unwrap();
+        assert!(srtext.starts_with("PROJCS[\"WGS 84 / UTM zone 33N\""));
+        assert!(srtext.contains("PARAMETER[\"central_meridian\",15]"));
     }
 }
 
@@ -0,0 +1,307 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::{Euclidean, Length};
+use geo_types::{Coord, LineString, MultiLineString};
+use pgrx::prelude::*;
+use std::collections::HashMap;
+
+// Line merging (ST_LineMerge)
+//
+// Lines are joined where their ends meet and no third line meets them, like
+// in PostGIS; lines crossing elsewhere are not noded first:
+//
+//   ST_LineMerge('MULTILINESTRING((0 0, 1 1), (2 2, 1 1), (1 1, 3 0))')
+//   = MULTILINESTRING((0 0,1 1),(2 2,1 1),(1 1,3 0))  -- three lines meet
+//   ST_LineMerge('MULTILINESTRING((0 0, 1 1), (2 2, 1 1))')
+//   = LINESTRING(0 0,1 1,2 2)
+//
+// Lines may be reversed to be joined. Each merged line then runs in the
+// direction of most of its length, so lines digitized the same way keep
+// their direction. Directed merging never reverses a line: lines are joined
+// where exactly one of them ends and one starts, which is what one-way roads
+// and river flowlines need. Rings whose every end meets exactly one other
+// line become closed lines.
+
+/// Lines of a lineal geometry, without repeated points
+fn lines(geom: &Geometry, into: &mut Vec<LineString<f64>>) -> Result<(), RostGisError> {
+    let mut push = |line: &LineString<f64>| {
+        let mut coords = line.0.clone();
+        coords.dedup();
+        if coords.len() > 1 {
+            into.push(LineString(coords));
+        }
+    };
+    match geom {
+        Geometry::LineString(line, _) => push(line),
+        Geometry::MultiLineString(multi_line, _) => multi_line.iter().for_each(push),
+        Geometry::GeometryCollection(members, _) => {
+            for member in members {
+                lines(member, into)?;
+            }
+        }
+        _ => {
+            return Err(RostGisError::new(&format!(
+                "ST_LineMerge requires linework, got {}",
+                geom.geometry_type()
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn node_key(coord: Coord<f64>) -> (u64, u64) {
+    // -0.0 and 0.0 are the same node
+    ((coord.x + 0.0).to_bits(), (coord.y + 0.0).to_bits())
+}
+
+/// Lines by the nodes at their ends
+struct Graph {
+    lines: Vec<LineString<f64>>,
+    /// Lines starting and ending at each node
+    starts: HashMap<(u64, u64), Vec<usize>>,
+    ends: HashMap<(u64, u64), Vec<usize>>,
+}
+
+impl Graph {
+    fn new(lines: Vec<LineString<f64>>) -> Self {
+        let mut starts: HashMap<_, Vec<usize>> = HashMap::new();
+        let mut ends: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, line) in lines.iter().enumerate() {
+            starts.entry(node_key(line.0[0])).or_default().push(i);
+            ends.entry(node_key(*line.0.last().unwrap()))
+                .or_default()
+                .push(i);
+        }
+        Graph {
+            lines,
+            starts,
+            ends,
+        }
+    }
+
+    fn starting(&self, node: (u64, u64)) -> &[usize] {
+        self.starts.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    fn ending(&self, node: (u64, u64)) -> &[usize] {
+        self.ends.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether merged lines run through a node rather than end there
+    fn passes(&self, node: (u64, u64), directed: bool) -> bool {
+        let (starting, ending) = (self.starting(node).len(), self.ending(node).len());
+        if directed {
+            starting == 1 && ending == 1
+        } else {
+            starting + ending == 2
+        }
+    }
+
+    /// The line at a node other than `line`, and whether it starts there
+    fn next(&self, node: (u64, u64), line: usize, directed: bool) -> Option<(usize, bool)> {
+        if directed {
+            return self.starting(node).first().map(|&i| (i, true));
+        }
+        let starting = self.starting(node).iter().map(|&i| (i, true));
+        let ending = self.ending(node).iter().map(|&i| (i, false));
+        let mut others = starting.chain(ending);
+        // A line closed on itself meets itself at the node
+        let first = others.next()?;
+        if first.0 != line {
+            Some(first)
+        } else {
+            others.next().filter(|other| other.0 != line)
+        }
+    }
+}
+
+/// Merge lines starting with `first`, followed from its start when
+/// `forward`, marking them used
+fn walk(
+    graph: &Graph,
+    first: usize,
+    forward: bool,
+    directed: bool,
+    used: &mut [bool],
+) -> LineString<f64> {
+    let mut coords: Vec<Coord<f64>> = Vec::new();
+    let (mut along, mut against) = (0.0, 0.0);
+    let (mut line, mut forward) = (first, forward);
+    loop {
+        used[line] = true;
+        let part = &graph.lines[line];
+        let length = Euclidean.length(part);
+        let mut part_coords = part.0.clone();
+        if forward {
+            along += length;
+        } else {
+            against += length;
+            part_coords.reverse();
+        }
+        let skip = usize::from(!coords.is_empty());
+        coords.extend(part_coords.into_iter().skip(skip));
+
+        let node = node_key(*coords.last().unwrap());
+        if !graph.passes(node, directed) {
+            break;
+        }
+        match graph.next(node, line, directed) {
+            Some((next, starts)) if !used[next] => (line, forward) = (next, starts),
+            _ => break,
+        }
+    }
+    if against > along {
+        coords.reverse();
+    }
+    LineString(coords)
+}
+
+/// Merge the lines of a lineal geometry; a single merged line is returned
+/// as a LineString
+pub fn line_merge(geom: &Geometry, directed: bool) -> Result<Geometry, RostGisError> {
+    let mut input = Vec::new();
+    lines(geom, &mut input)?;
+    let graph = Graph::new(input);
+    let mut used = vec![false; graph.lines.len()];
+    let mut merged = Vec::new();
+
+    // Merged lines from the nodes they cannot pass, then the rings left
+    for i in 0..graph.lines.len() {
+        let line = &graph.lines[i];
+        if !used[i] && !graph.passes(node_key(line.0[0]), directed) {
+            merged.push(walk(&graph, i, true, directed, &mut used));
+        }
+        let end = node_key(*line.0.last().unwrap());
+        if !used[i] && !directed && !graph.passes(end, directed) {
+            merged.push(walk(&graph, i, false, directed, &mut used));
+        }
+    }
+    for i in 0..graph.lines.len() {
+        if !used[i] {
+            merged.push(walk(&graph, i, true, directed, &mut used));
+        }
+    }
+
+    let srid = geom.srid();
+    Ok(match merged.len() {
+        0 => Geometry::GeometryCollection(Vec::new(), srid),
+        1 => Geometry::LineString(merged.pop().unwrap(), srid),
+        _ => Geometry::MultiLineString(MultiLineString(merged), srid),
+    })
+}
+
+/// PostgreSQL function merging lines that meet at their ends; `directed`
+/// only joins a line's end to another's start
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_linemerge(
+    geom: Geometry,
+    directed: default!(bool, false),
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(line_merge(&geom, directed)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn merge_wkt(wkt: &str, directed: bool) -> String {
+        line_merge(&geometry_from_wkt(wkt).unwrap(), directed)
+            .unwrap()
+            .to_wkt()
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 1), (1 1, 2 2, 3 2))", false),
+            "LINESTRING(0 0,1 1,2 2,3 2)"
+        );
+        // The second line is reversed to be joined
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 0 5, 1 5), (3 5, 2 5, 1 5))", false),
+            "LINESTRING(0 0,0 5,1 5,2 5,3 5)"
+        );
+        // Three lines meet at 1 1
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 1), (2 2, 1 1), (1 1, 3 0))", false),
+            "MULTILINESTRING((0 0,1 1),(2 2,1 1),(1 1,3 0))"
+        );
+        // Lines that do not meet at their ends are kept apart
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 2 2), (0 2, 2 0))", false),
+            "MULTILINESTRING((0 0,2 2),(0 2,2 0))"
+        );
+    }
+
+    #[test]
+    fn test_direction_of_most_length() {
+        // Most of the length runs from 10 0 to 0 0
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 0), (10 0, 1 0))", false),
+            "LINESTRING(10 0,1 0,0 0)"
+        );
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((1 0, 0 0), (1 0, 10 0))", false),
+            "LINESTRING(0 0,1 0,10 0)"
+        );
+    }
+
+    #[test]
+    fn test_directed() {
+        // Lines pointing at each other are not joined
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 1), (2 2, 1 1))", true),
+            "MULTILINESTRING((0 0,1 1),(2 2,1 1))"
+        );
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((1 1, 2 2), (0 0, 1 1))", true),
+            "LINESTRING(0 0,1 1,2 2)"
+        );
+        // A confluence: two lines flow into one, which is not merged
+        assert_eq!(
+            merge_wkt(
+                "MULTILINESTRING((0 0, 1 1), (0 2, 1 1), (1 1, 2 1), (2 1, 3 1))",
+                true
+            ),
+            "MULTILINESTRING((0 0,1 1),(0 2,1 1),(1 1,2 1,3 1))"
+        );
+    }
+
+    #[test]
+    fn test_rings() {
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 0, 1 1), (1 1, 0 1, 0 0))", false),
+            "LINESTRING(0 0,1 0,1 1,0 1,0 0)"
+        );
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 0, 1 1), (1 1, 0 1, 0 0))", true),
+            "LINESTRING(0 0,1 0,1 1,0 1,0 0)"
+        );
+        assert_eq!(
+            merge_wkt("MULTILINESTRING((0 0, 1 0, 1 1, 0 0), (5 5, 6 6))", false),
+            "MULTILINESTRING((5 5,6 6),(0 0,1 0,1 1,0 0))"
+        );
+    }
+
+    #[test]
+    fn test_line_merge_input() {
+        assert_eq!(
+            merge_wkt("LINESTRING(0 0, 1 1, 2 0)", false),
+            "LINESTRING(0 0,1 1,2 0)"
+        );
+        assert_eq!(
+            merge_wkt("MULTILINESTRING EMPTY", false),
+            "GEOMETRYCOLLECTION EMPTY"
+        );
+        assert!(line_merge(&geometry_from_wkt("POINT(0 0)").unwrap(), false).is_err());
+        let merged = line_merge(
+            &geometry_from_wkt("MULTILINESTRING((0 0, 1 1), (1 1, 2 2))")
+                .unwrap()
+                .with_srid(4326),
+            false,
+        )
+        .unwrap();
+        assert_eq!(merged.srid(), 4326);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::functions::swap_axes;
+use crate::geometry::Geometry;
+use crate::guc;
+use crate::utils::srid;
+use pgrx::prelude::*;
+
+// Longitude/latitude sanity checks (ST_LooksLikeLatLon)
+//
+// Data exported in latitude/longitude order and loaded as x/y ends up with
+// its axes swapped, and nothing fails until distances and joins come out
+// wrong. ST_LooksLikeLatLon tells from the coordinate ranges whether a
+// geometry is in that order:
+//
+//   SELECT count(*) FROM stations WHERE ST_LooksLikeLatLon(geom);
+//
+// It is true when every x is a valid latitude and every y a valid longitude
+// but some y is not a valid latitude, false when the coordinates only make
+// sense in longitude/latitude order (or in neither, as projected ones), and
+// NULL when both orders fit, as for most places between 90 degrees east and
+// west, where the ranges cannot tell. ST_FlipCoordinates swaps the axes of
+// the rows found:
+//
+//   UPDATE stations SET geom = ST_FlipCoordinates(geom)
+//   WHERE ST_LooksLikeLatLon(geom);
+//
+// With rostgis.check_lonlat_range on, geometry input, ST_GeomFromText,
+// ST_GeomFromWKB and ST_SetSRID warn about SRID 4326 geometries with
+// coordinates outside [-180 -90, 180 90], pointing out swapped axes when the
+// coordinates look like latitude/longitude, so such data is caught at load
+// time.
+
+/// Whether a coordinate is a valid longitude/latitude pair
+fn in_lon_lat_range(x: f64, y: f64) -> bool {
+    (-180.0..=180.0).contains(&x) && (-90.0..=90.0).contains(&y)
+}
+
+/// Whether the coordinates of a geometry are in latitude/longitude order;
+/// None when both orders fit or the geometry is empty
+pub fn looks_like_lat_lon(geom: &Geometry) -> Option<bool> {
+    // Empty points have NaN coordinates
+    let coordinates: Vec<(f64, f64)> = geom
+        .coordinates()
+        .into_iter()
+        .filter(|(x, y)| !x.is_nan() && !y.is_nan())
+        .collect();
+    if coordinates.is_empty() {
+        return None;
+    }
+    let lon_lat = coordinates.iter().all(|&(x, y)| in_lon_lat_range(x, y));
+    let lat_lon = coordinates.iter().all(|&(x, y)| in_lon_lat_range(y, x));
+    match (lon_lat, lat_lon) {
+        (true, true) => None,
+        (false, true) => Some(true),
+        _ => Some(false),
+    }
+}
+
+/// First coordinate of a geometry outside the longitude/latitude range
+pub fn first_out_of_range(geom: &Geometry) -> Option<(f64, f64)> {
+    geom.coordinates()
+        .into_iter()
+        .find(|&(x, y)| !in_lon_lat_range(x, y) && !x.is_nan() && !y.is_nan())
+}
+
+/// Warning for an SRID 4326 geometry with coordinates out of range
+pub fn lon_lat_range_warning(geom: &Geometry) -> Option<String> {
+    if geom.srid() != srid::WGS84 {
+        return None;
+    }
+    let (x, y) = first_out_of_range(geom)?;
+    let mut message = format!(
+        "SRID {} geometry has coordinates out of range [-180 -90, 180 90]: {} {}",
+        srid::WGS84,
+        x,
+        y
+    );
+    if looks_like_lat_lon(geom) == Some(true) {
+        message.push_str("; they look like latitude/longitude, swap them with ST_FlipCoordinates");
+    }
+    Some(message)
+}
+
+/// Warn about an SRID 4326 geometry with coordinates out of range if
+/// rostgis.check_lonlat_range is on
+pub fn check_lon_lat_range(geom: &Geometry) {
+    if !guc::CHECK_LONLAT_RANGE.get() {
+        return;
+    }
+    if let Some(message) = lon_lat_range_warning(geom) {
+        warning!("{}", message);
+    }
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_lookslikelatlon(geom: Geometry) -> Option<bool> {
+    looks_like_lat_lon(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_flipcoordinates(geom: Geometry) -> Geometry {
+    swap_axes(&geom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    #[test]
+    fn test_looks_like_lat_lon() {
+        let looks = |wkt: &str| looks_like_lat_lon(&geometry_from_wkt(wkt).unwrap());
+        // Sydney and Los Angeles, latitude first
+        assert_eq!(
+            looks("LINESTRING(-33.87 151.21, 34.05 -118.24)"),
+            Some(true)
+        );
+        assert_eq!(
+            looks("LINESTRING(151.21 -33.87, -118.24 34.05)"),
+            Some(false)
+        );
+        // Berlin fits both orders
+        assert_eq!(looks("POINT(13.4 52.5)"), None);
+        assert_eq!(looks("POINT(52.5 13.4)"), None);
+        // Web Mercator metres fit neither
+        assert_eq!(looks("POINT(1491681 6893040)"), Some(false));
+        assert_eq!(looks("POINT EMPTY"), None);
+    }
+
+    #[test]
+    fn test_flip_coordinates() {
+        let flip = |wkt: &str| {
+            let geom = geometry_from_wkt(wkt).unwrap().with_srid(4326);
+            let flipped = swap_axes(&geom);
+            // Flipping twice gives the geometry back
+            assert_eq!(swap_axes(&flipped).to_ewkt(), geom.to_ewkt());
+            flipped.to_ewkt()
+        };
+        assert_eq!(
+            flip("POINT(-33.87 151.21)"),
+            "SRID=4326;POINT(151.21 -33.87)"
+        );
+        assert_eq!(flip("POINT EMPTY"), "SRID=4326;POINT EMPTY");
+        assert_eq!(
+            flip("LINESTRING(1 2, 3 4, 5 6)"),
+            "SRID=4326;LINESTRING(2 1,4 3,6 5)"
+        );
+        assert_eq!(flip("LINESTRING EMPTY"), "SRID=4326;LINESTRING EMPTY");
+        // Rings keep their vertex order and holes
+        assert_eq!(
+            flip("POLYGON((0 0, 4 0, 4 2, 0 0), (1 0.5, 2 0.5, 2 1, 1 0.5))"),
+            "SRID=4326;POLYGON((0 0,0 4,2 4,0 0),(0.5 1,0.5 2,1 2,0.5 1))"
+        );
+        assert_eq!(
+            flip("MULTIPOINT(1 2, 3 4)"),
+            "SRID=4326;MULTIPOINT((2 1),(4 3))"
+        );
+        assert_eq!(
+            flip("MULTIPOLYGON(((0 0, 1 0, 1 3, 0 0)))"),
+            "SRID=4326;MULTIPOLYGON(((0 0,0 1,3 1,0 0)))"
+        );
+        assert_eq!(
+            flip("MULTILINESTRING((1 2, 3 4), (5 6, 7 8))"),
+            "SRID=4326;MULTILINESTRING((2 1,4 3),(6 5,8 7))"
+        );
+        // Members of collections are flipped in place, empty ones included
+        assert_eq!(
+            flip("GEOMETRYCOLLECTION(POINT(1 2), POINT EMPTY, LINESTRING(3 4, 5 6))"),
+            "SRID=4326;GEOMETRYCOLLECTION(POINT(2 1),POINT EMPTY,LINESTRING(4 3,6 5))"
+        );
+        assert_eq!(
+            flip("GEOMETRYCOLLECTION EMPTY"),
+            "SRID=4326;GEOMETRYCOLLECTION EMPTY"
+        );
+    }
+
+    #[test]
+    fn test_lon_lat_range_warning() {
+        let geom = |wkt: &str| geometry_from_wkt(wkt).unwrap().with_srid(4326);
+        assert_eq!(lon_lat_range_warning(&geom("POINT(13.4 52.5)")), None);
+        assert_eq!(
+            lon_lat_range_warning(&geom("POINT(200 10)")).unwrap(),
+            "SRID 4326 geometry has coordinates out of range [-180 -90, 180 90]: 200 10"
+        );
+        assert!(lon_lat_range_warning(&geom("POINT(-33.87 151.21)"))
+            .unwrap()
+            .ends_with("swap them with ST_FlipCoordinates"));
+        assert_eq!(lon_lat_range_warning(&geom("POINT EMPTY")), None);
+        // Only SRID 4326 is checked
+        let projected = geometry_from_wkt("POINT(1491681 6893040)").unwrap();
+        assert_eq!(lon_lat_range_warning(&projected), None);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::Intersects;
+use geo_types::{Line, MultiLineString, Point};
+use pgrx::prelude::*;
+use rstar::primitives::GeomWithData;
+use rstar::{PointDistance, RTree};
+use std::collections::HashMap;
+
+// Map matching (rostgis_map_match)
+//
+// Every GPS point of a track is matched to one of the roads within the
+// search radius by a Viterbi search over the whole track. A candidate road
+// costs its distance to the point; moving from one road to another costs a
+// quarter of the search radius when the two roads touch and the full radius
+// when they do not. The match thus sticks to a road through noisy fixes and
+// only jumps to an unconnected road when the points clearly follow it.
+//
+// Points without any road in range stay unmatched and split the track into
+// independently matched runs.
+
+/// Share of the search radius charged for moving onto a touching road
+const CONNECTED_TRANSITION: f64 = 0.25;
+
+/// A road within the search radius of a track point
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    road: usize,
+    distance: f64,
+}
+
+/// Candidates of one track point along a run, with the best predecessor of
+/// each candidate
+struct Step {
+    point: usize,
+    candidates: Vec<Candidate>,
+    previous: Vec<usize>,
+}
+
+/// Match each track point to a road, as (road position, distance)
+///
+/// `None` points, like empty points of the track, stay unmatched.
+pub fn map_match(
+    track: &[Option<[f64; 2]>],
+    roads: &[MultiLineString<f64>],
+    search_radius: f64,
+) -> Vec<Option<(usize, f64)>> {
+    let segments: RTree<GeomWithData<Line<f64>, usize>> = RTree::bulk_load(
+        roads
+            .iter()
+            .enumerate()
+            .flat_map(|(road, lines)| {
+                lines
+                    .iter()
+                    .flat_map(|line| line.lines())
+                    .map(move |segment| GeomWithData::new(segment, road))
+            })
+            .collect(),
+    );
+
+    let candidates = |point: [f64; 2]| -> Vec<Candidate> {
+        let point = Point::from(point);
+        let mut nearest: HashMap<usize, f64> = HashMap::new();
+        for segment in segments.locate_within_distance(point, search_radius * search_radius) {
+            let distance = segment.distance_2(&point).sqrt();
+            nearest
+                .entry(segment.data)
+                .and_modify(|best| *best = best.min(distance))
+                .or_insert(distance);
+        }
+        let mut candidates: Vec<Candidate> = nearest
+            .into_iter()
+            .map(|(road, distance)| Candidate { road, distance })
+            .collect();
+        candidates.sort_by_key(|candidate| candidate.road);
+        candidates
+    };
+
+    let mut touching: HashMap<(usize, usize), bool> = HashMap::new();
+    let mut transition = |from: usize, to: usize| -> f64 {
+        if from == to {
+            return 0.0;
+        }
+        let key = (from.min(to), from.max(to));
+        let touches = *touching
+            .entry(key)
+            .or_insert_with(|| roads[from].intersects(&roads[to]));
+        if touches {
+            CONNECTED_TRANSITION * search_radius
+        } else {
+            search_radius
+        }
+    };
+
+    let mut matched = vec![None; track.len()];
+    let mut run: Vec<Step> = Vec::new();
+    let mut costs: Vec<f64> = Vec::new();
+    for (i, point) in track.iter().enumerate() {
+        let here = point.map(candidates).unwrap_or_default();
+        if here.is_empty() {
+            finish_run(&run, &costs, &mut matched);
+            run.clear();
+            continue;
+        }
+
+        let Some(last) = run.last() else {
+            costs = here.iter().map(|candidate| candidate.distance).collect();
+            run.push(Step {
+                point: i,
+                candidates: here,
+                previous: Vec::new(),
+            });
+            continue;
+        };
+        let mut next_costs = Vec::with_capacity(here.len());
+        let mut previous = Vec::with_capacity(here.len());
+        for candidate in &here {
+            let (best, cost) = last
+                .candidates
+                .iter()
+                .enumerate()
+                .map(|(k, from)| (k, costs[k] + transition(from.road, candidate.road)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .expect("a run never holds a step without candidates");
+            next_costs.push(cost + candidate.distance);
+            previous.push(best);
+        }
+        costs = next_costs;
+        run.push(Step {
+            point: i,
+            candidates: here,
+            previous,
+        });
+    }
+    finish_run(&run, &costs, &mut matched);
+    matched
+}
+
+/// Trace the cheapest path of a run back from its last step
+fn finish_run(run: &[Step], costs: &[f64], matched: &mut [Option<(usize, f64)>]) {
+    if run.is_empty() {
+        return;
+    }
+    let mut k = (0..costs.len())
+        .min_by(|&a, &b| costs[a].total_cmp(&costs[b]))
+        .unwrap_or(0);
+    for step in run.iter().rev() {
+        let candidate = step.candidates[k];
+        matched[step.point] = Some((candidate.road, candidate.distance));
+        if !step.previous.is_empty() {
+            k = step.previous[k];
+        }
+    }
+}
+
+/// Points of a GPS track given as a LineString, MultiPoint or single Point
+fn track_points(track: &Geometry) -> Result<Vec<Option<[f64; 2]>>, RostGisError> {
+    match track {
+        Geometry::LineString(line, _) => Ok(line.0.iter().map(|c| Some([c.x, c.y])).collect()),
+        Geometry::MultiPoint(points, _) => Ok(points
+            .0
+            .iter()
+            .map(|p| (!p.x().is_nan()).then(|| [p.x(), p.y()]))
+            .collect()),
+        Geometry::Point(point, _) => Ok(vec![(!track.is_empty()).then(|| [point.x(), point.y()])]),
+        other => Err(RostGisError::new(&format!(
+            "map matching requires a LineString or MultiPoint track, got {}",
+            other.geometry_type()
+        ))),
+    }
+}
+
+/// Lines of a road, given as a LineString or MultiLineString
+fn road_lines(road: &Geometry) -> Result<MultiLineString<f64>, RostGisError> {
+    match road {
+        Geometry::LineString(line, _) => Ok(MultiLineString(vec![line.clone()])),
+        Geometry::MultiLineString(lines, _) => Ok(lines.clone()),
+        other => Err(RostGisError::new(&format!(
+            "map matching requires LineString roads, got {}",
+            other.geometry_type()
+        ))),
+    }
+}
+
+/// PostgreSQL function matching a GPS track to roads
+///
+/// Returns one row per track point with the id of the matched road and the
+/// distance to it, both NULL for points without a road within
+/// `search_radius`. NULL roads are ignored.
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_map_match(
+    track: Geometry,
+    roads: Array<'_, Geometry>,
+    road_ids: Array<'_, i64>,
+    search_radius: f64,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(seq, i32),
+            name!(road_id, Option<i64>),
+            name!(distance, Option<f64>),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    if !search_radius.is_finite() || search_radius <= 0.0 {
+        return Err("map matching search radius must be positive".into());
+    }
+    if roads.len() != road_ids.len() {
+        return Err("map matching needs one id per road".into());
+    }
+    let points = track_points(&track)?;
+    let lines = roads
+        .iter()
+        .map(|road| match road {
+            Some(road) => road_lines(&road),
+            None => Ok(MultiLineString(Vec::new())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let ids = road_ids
+        .iter()
+        .map(|id| id.ok_or_else(|| RostGisError::new("map matching road ids cannot be NULL")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let matched = map_match(&points, &lines, search_radius);
+    Ok(TableIterator::new(matched.into_iter().enumerate().map(
+        move |(i, matched)| {
+            (
+                i as i32 + 1,
+                matched.map(|(road, _)| ids[road]),
+                matched.map(|(_, distance)| distance),
+            )
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::LineString;
+
+    fn road(coords: &[(f64, f64)]) -> MultiLineString<f64> {
+        MultiLineString(vec![LineString::from(coords.to_vec())])
+    }
+
+    fn matched_roads(matched: &[Option<(usize, f64)>]) -> Vec<Option<usize>> {
+        matched.iter().map(|m| m.map(|(road, _)| road)).collect()
+    }
+
+    #[test]
+    fn test_sticks_to_road_through_noise() {
+        // Two parallel roads a metre apart; the second fix is nearer the
+        // other road, which is not connected
+        let roads = [
+            road(&[(0.0, 0.0), (10.0, 0.0)]),
+            road(&[(0.0, 1.0), (10.0, 1.0)]),
+        ];
+        let track = [
+            Some([1.0, 0.3]),
+            Some([2.0, 0.6]),
+            Some([3.0, 0.4]),
+            Some([4.0, 0.3]),
+        ];
+        let matched = map_match(&track, &roads, 2.0);
+        assert_eq!(matched_roads(&matched), vec![Some(0); 4]);
+        assert!((matched[1].unwrap().1 - 0.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_turns_onto_connected_road() {
+        let roads = [
+            road(&[(0.0, 0.0), (10.0, 0.0)]),
+            road(&[(10.0, 0.0), (10.0, 10.0)]),
+        ];
+        let track = [Some([8.0, 0.1]), Some([9.9, 2.0]), Some([10.1, 5.0])];
+        let matched = map_match(&track, &roads, 3.0);
+        assert_eq!(matched_roads(&matched), vec![Some(0), Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn test_unmatched_points_split_track() {
+        let roads = [road(&[(0.0, 0.0), (10.0, 0.0)])];
+        let track = [Some([1.0, 0.5]), Some([5.0, 50.0]), None, Some([9.0, -0.5])];
+        let matched = map_match(&track, &roads, 1.0);
+        assert_eq!(matched_roads(&matched), vec![Some(0), None, None, Some(0)]);
+        assert!(map_match(&[], &roads, 1.0).is_empty());
+        assert_eq!(map_match(&track[..1], &[], 1.0), vec![None]);
+    }
+}
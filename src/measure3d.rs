@@ -0,0 +1,776 @@
+use crate::functions::{
+    distance_within, geometries_intersect, geometry_length, geometry_perimeter, segments,
+};
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo::Intersects;
+use geo_types::{Coord, Line, LineString, Point, Polygon};
+use pgrx::prelude::*;
+use rstar::{RTree, RTreeObject, AABB};
+
+// 3D measurement (ST_3DDistance, ST_3DIntersects, ST_3DDWithin,
+// ST_3DClosestPoint, ST_3DShortestLine, ST_3DLength, ST_3DPerimeter)
+//
+// The 3D counterparts of ST_Distance, ST_Intersects, ST_DWithin,
+// ST_ClosestPoint, ST_ShortestLine, ST_Length and ST_Perimeter, under the
+// names PostGIS uses, for building-model and airspace queries:
+//
+//   SELECT b.id FROM buildings b, flight_paths f
+//   WHERE f.id = 12 AND ST_3DDWithin(b.geom, f.geom, 150);
+//   SELECT ST_3DShortestLine(b.geom, f.geom) FROM buildings b, flight_paths f;
+//
+// As in PostGIS, ST_3DLength measures lines only and ST_3DPerimeter polygon
+// rings only, so each is 0 for the other kind of geometry.
+//
+// Z is read from the geometries, 0 for vertices of a geometry without Z.
+// Polygons are planar faces: a point above a polygon is as far from it as
+// from its plane. When neither geometry has Z the measures are the 2D ones,
+// computed as ST_Distance and ST_Intersects do. Unlike the 2D functions,
+// these refuse geometries of different SRIDs, as PostGIS does.
+
+/// Refuse a pair of geometries in different coordinate systems
+fn check_pair(a: &Geometry, b: &Geometry, function: &str) -> Result<(), RostGisError> {
+    if a.srid() != b.srid() {
+        return Err(RostGisError::new(&format!(
+            "{}: geometries have different SRIDs ({} and {})",
+            function,
+            a.srid(),
+            b.srid()
+        )));
+    }
+    Ok(())
+}
+
+/// Smallest 3D distance between two geometries; None if either is empty
+pub fn distance_3d(a: &Geometry, b: &Geometry) -> Result<Option<f64>, RostGisError> {
+    check_pair(a, b, "ST_3DDistance")?;
+    if !a.has_z() && !b.has_z() {
+        return Ok(distance_within(a, b, f64::INFINITY));
+    }
+    Ok(closest_pair(a, b).map(|pair| pair.distance))
+}
+
+/// Whether two geometries share a point in 3D
+pub fn intersects_3d(a: &Geometry, b: &Geometry) -> Result<bool, RostGisError> {
+    check_pair(a, b, "ST_3DIntersects")?;
+    if !a.has_z() && !b.has_z() {
+        return Ok(geometries_intersect(a, b));
+    }
+    Ok(closest_pair(a, b).is_some_and(|pair| pair.distance == 0.0))
+}
+
+/// Whether two geometries are within `distance` of each other in 3D
+pub fn dwithin_3d(a: &Geometry, b: &Geometry, distance: f64) -> Result<bool, RostGisError> {
+    check_pair(a, b, "ST_3DDWithin")?;
+    if !a.has_z() && !b.has_z() {
+        return Ok(distance_within(a, b, distance).is_some());
+    }
+    Ok(closest_pair(a, b).is_some_and(|pair| pair.distance <= distance))
+}
+
+/// A pair of points, such as the ends of a shortest line
+type PointPair = (Coord<f64>, Coord<f64>);
+
+/// Point of a segment closest to a point
+fn closest_on_segment(point: Coord<f64>, segment: &Line<f64>) -> Coord<f64> {
+    let delta = segment.delta();
+    let length_squared = delta.x * delta.x + delta.y * delta.y;
+    if length_squared == 0.0 {
+        return segment.start;
+    }
+    let offset = point - segment.start;
+    let t = ((offset.x * delta.x + offset.y * delta.y) / length_squared).clamp(0.0, 1.0);
+    segment.start + delta * t
+}
+
+/// Closest pair of points of two segments, the first on `a`
+fn closest_between_segments(a: &Line<f64>, b: &Line<f64>) -> PointPair {
+    match line_intersection(*a, *b) {
+        Some(LineIntersection::SinglePoint { intersection, .. }) => (intersection, intersection),
+        Some(LineIntersection::Collinear { intersection }) => {
+            (intersection.start, intersection.start)
+        }
+        None => [
+            (a.start, closest_on_segment(a.start, b)),
+            (a.end, closest_on_segment(a.end, b)),
+            (closest_on_segment(b.start, a), b.start),
+            (closest_on_segment(b.end, a), b.end),
+        ]
+        .into_iter()
+        .min_by(|p, q| gap(p).total_cmp(&gap(q)))
+        .unwrap_or((a.start, b.start)),
+    }
+}
+
+/// Length of the line between a pair of points
+fn gap((a, b): &PointPair) -> f64 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+/// Closest pair of points of two 2D geometries, the first on `a`; None if
+/// either is empty
+///
+/// Intersecting geometries share a vertex of one lying in the other, or a
+/// point where their segments cross, which is the pair returned. Otherwise
+/// only the segments of `b` within the distance of each segment of `a` are
+/// compared, found through an R*-tree as in `distance_within`.
+fn closest_points_2d(a: &Geometry, b: &Geometry) -> Option<PointPair> {
+    let distance = distance_within(a, b, f64::INFINITY)?;
+    if distance == 0.0 {
+        let lies_in = |x: f64, y: f64, other: &Geometry| {
+            !x.is_nan() && geometries_intersect(&Geometry::Point(Point::new(x, y), 0), other)
+        };
+        let vertex = a
+            .coordinates()
+            .into_iter()
+            .find(|&(x, y)| lies_in(x, y, b))
+            .or_else(|| b.coordinates().into_iter().find(|&(x, y)| lies_in(x, y, a)));
+        if let Some((x, y)) = vertex {
+            return Some((Coord { x, y }, Coord { x, y }));
+        }
+    }
+
+    let tree = RTree::bulk_load(segments(b));
+    let mut best: Option<PointPair> = None;
+    for segment in segments(a) {
+        let envelope = segment.envelope();
+        let search = AABB::from_corners(
+            Point::new(
+                envelope.lower().x() - distance,
+                envelope.lower().y() - distance,
+            ),
+            Point::new(
+                envelope.upper().x() + distance,
+                envelope.upper().y() + distance,
+            ),
+        );
+        for other in tree.locate_in_envelope_intersecting(&search) {
+            let pair = closest_between_segments(&segment, other);
+            if best.as_ref().is_none_or(|best| gap(&pair) < gap(best)) {
+                best = Some(pair);
+            }
+        }
+    }
+    best
+}
+
+/// A point in 3D
+type P3 = [f64; 3];
+
+fn sub(a: P3, b: P3) -> P3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn along(a: P3, direction: P3, t: f64) -> P3 {
+    [
+        a[0] + direction[0] * t,
+        a[1] + direction[1] * t,
+        a[2] + direction[2] * t,
+    ]
+}
+
+fn dot(a: P3, b: P3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: P3) -> f64 {
+    dot(a, a).sqrt()
+}
+
+/// A polygon as a planar face
+struct Face {
+    rings: Vec<Vec<P3>>,
+    /// Unit normal and a point of the plane; None for a polygon without
+    /// area, which is measured by its rings only
+    plane: Option<(P3, P3)>,
+    /// The rings projected onto the coordinate plane the face is most
+    /// parallel to, and the two axes kept
+    projected: Polygon<f64>,
+    axes: (usize, usize),
+}
+
+impl Face {
+    fn new(rings: Vec<Vec<P3>>) -> Face {
+        // Newell's method, robust for non-convex rings
+        let mut normal = [0.0; 3];
+        for (a, b) in rings[0].iter().zip(rings[0].iter().skip(1)) {
+            normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+            normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+            normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+        }
+        let norm = length(normal);
+        let plane = (norm > 0.0).then(|| {
+            (
+                [normal[0] / norm, normal[1] / norm, normal[2] / norm],
+                rings[0][0],
+            )
+        });
+        let dominant = (0..3)
+            .max_by(|&i, &j| normal[i].abs().total_cmp(&normal[j].abs()))
+            .unwrap_or(2);
+        let axes = match dominant {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let project = |ring: &Vec<P3>| {
+            LineString::from(
+                ring.iter()
+                    .map(|p| (p[axes.0], p[axes.1]))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let projected = Polygon::new(project(&rings[0]), rings[1..].iter().map(project).collect());
+        Face {
+            rings,
+            plane,
+            projected,
+            axes,
+        }
+    }
+
+    /// Whether a point of the plane lies in the face
+    fn contains(&self, point: P3) -> bool {
+        self.projected.intersects(&Coord {
+            x: point[self.axes.0],
+            y: point[self.axes.1],
+        })
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (P3, P3)> + '_ {
+        self.rings
+            .iter()
+            .flat_map(|ring| ring.iter().zip(ring.iter().skip(1)).map(|(a, b)| (*a, *b)))
+    }
+}
+
+/// A point, segment or polygon of a geometry in 3D
+enum Part {
+    Point(P3),
+    Segment(P3, P3),
+    Face(Face),
+}
+
+impl RTreeObject for Part {
+    type Envelope = AABB<P3>;
+
+    fn envelope(&self) -> AABB<P3> {
+        match self {
+            Part::Point(p) => AABB::from_point(*p),
+            Part::Segment(a, b) => AABB::from_corners(*a, *b),
+            Part::Face(face) => AABB::from_points(face.rings[0].iter()),
+        }
+    }
+}
+
+/// Closest pair of points between two parts of geometries, with their
+/// distance
+#[derive(Clone, Copy)]
+struct Closest {
+    distance: f64,
+    on_a: P3,
+    on_b: P3,
+}
+
+impl Closest {
+    fn new(on_a: P3, on_b: P3) -> Closest {
+        Closest {
+            distance: length(sub(on_a, on_b)),
+            on_a,
+            on_b,
+        }
+    }
+
+    fn swapped(self) -> Closest {
+        Closest {
+            on_a: self.on_b,
+            on_b: self.on_a,
+            ..self
+        }
+    }
+
+    fn min(self, other: Closest) -> Closest {
+        if other.distance < self.distance {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+fn point_segment(p: P3, (a, b): (P3, P3)) -> Closest {
+    let direction = sub(b, a);
+    let length_squared = dot(direction, direction);
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (dot(sub(p, a), direction) / length_squared).clamp(0.0, 1.0)
+    };
+    Closest::new(p, along(a, direction, t))
+}
+
+/// Closest points of two segments (Ericson, Real-Time Collision Detection,
+/// 5.1.9)
+fn segment_segment((p1, q1): (P3, P3), (p2, q2): (P3, P3)) -> Closest {
+    let d1 = sub(q1, p1);
+    let d2 = sub(q2, p2);
+    let r = sub(p1, p2);
+    let (a, e, f) = (dot(d1, d1), dot(d2, d2), dot(d2, r));
+    if a == 0.0 {
+        return point_segment(p1, (p2, q2));
+    }
+    if e == 0.0 {
+        return point_segment(p2, (p1, q1)).swapped();
+    }
+    let (b, c) = (dot(d1, d2), dot(d1, r));
+    let denominator = a * e - b * b;
+    let mut s = if denominator != 0.0 {
+        ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mut t = (b * s + f) / e;
+    if t < 0.0 {
+        t = 0.0;
+        s = (-c / a).clamp(0.0, 1.0);
+    } else if t > 1.0 {
+        t = 1.0;
+        s = ((b - c) / a).clamp(0.0, 1.0);
+    }
+    Closest::new(along(p1, d1, s), along(p2, d2, t))
+}
+
+fn point_face(p: P3, face: &Face) -> Closest {
+    if let Some((normal, origin)) = face.plane {
+        let foot = along(p, normal, -dot(sub(p, origin), normal));
+        if face.contains(foot) {
+            return Closest::new(p, foot);
+        }
+    }
+    face.edges()
+        .map(|edge| point_segment(p, edge))
+        .reduce(Closest::min)
+        .unwrap_or(Closest::new(p, face.rings[0][0]))
+}
+
+fn segment_face((a, b): (P3, P3), face: &Face) -> Closest {
+    if let Some((normal, origin)) = face.plane {
+        let (da, db) = (dot(sub(a, origin), normal), dot(sub(b, origin), normal));
+        // The segment crosses the plane inside the face
+        if da * db <= 0.0 && da != db {
+            let crossing = along(a, sub(b, a), da / (da - db));
+            if face.contains(crossing) {
+                return Closest::new(crossing, crossing);
+            }
+        }
+    }
+    face.edges()
+        .map(|edge| segment_segment((a, b), edge))
+        .fold(point_face(a, face).min(point_face(b, face)), Closest::min)
+}
+
+fn face_face(a: &Face, b: &Face) -> Closest {
+    let from_a = a.edges().map(|edge| segment_face(edge, b));
+    let from_b = b.edges().map(|edge| segment_face(edge, a).swapped());
+    from_a
+        .chain(from_b)
+        .reduce(Closest::min)
+        .unwrap_or(Closest::new(a.rings[0][0], b.rings[0][0]))
+}
+
+fn closest_parts(a: &Part, b: &Part) -> Closest {
+    match (a, b) {
+        (Part::Point(p), Part::Point(q)) => Closest::new(*p, *q),
+        (Part::Point(p), Part::Segment(c, d)) => point_segment(*p, (*c, *d)),
+        (Part::Point(p), Part::Face(face)) => point_face(*p, face),
+        (Part::Segment(a, b), Part::Segment(c, d)) => segment_segment((*a, *b), (*c, *d)),
+        (Part::Segment(a, b), Part::Face(face)) => segment_face((*a, *b), face),
+        (Part::Face(f), Part::Face(g)) => face_face(f, g),
+        (Part::Segment(..) | Part::Face(_), _) => closest_parts(b, a).swapped(),
+    }
+}
+
+/// Points, segments and faces of a geometry, its vertices taking their Z,
+/// or 0 without one
+fn parts_3d(geom: &Geometry) -> Vec<Part> {
+    let zs = geom.ordinates().and_then(|ordinates| ordinates.z.as_ref());
+    let vertices: Vec<P3> = geom
+        .coordinates()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (x, y))| [x, y, zs.map_or(0.0, |zs| zs[i])])
+        .collect();
+
+    fn walk(geom: &Geometry, vertices: &[P3], next: &mut usize, parts: &mut Vec<Part>) {
+        let mut take = |count: usize| {
+            let taken = &vertices[*next..*next + count];
+            *next += count;
+            taken
+        };
+        let mut line = |line: &LineString<f64>, parts: &mut Vec<Part>| {
+            let points = take(line.0.len());
+            parts.extend(
+                points
+                    .windows(2)
+                    .map(|pair| Part::Segment(pair[0], pair[1])),
+            );
+            if let [point] = points {
+                parts.push(Part::Point(*point));
+            }
+        };
+        match geom {
+            Geometry::Point(..) | Geometry::MultiPoint(..) => {
+                let points = take(geom.coordinates().len());
+                parts.extend(
+                    points
+                        .iter()
+                        .filter(|p| !p[0].is_nan())
+                        .map(|p| Part::Point(*p)),
+                );
+            }
+            Geometry::LineString(linestring, _) => line(linestring, parts),
+            Geometry::MultiLineString(linestrings, _) => {
+                for linestring in linestrings {
+                    line(linestring, parts);
+                }
+            }
+            Geometry::Polygon(..) | Geometry::MultiPolygon(..) => {
+                let polygons: Vec<&Polygon<f64>> = match geom {
+                    Geometry::Polygon(polygon, _) => vec![polygon],
+                    Geometry::MultiPolygon(polygons, _) => polygons.iter().collect(),
+                    _ => unreachable!(),
+                };
+                for polygon in polygons {
+                    let rings: Vec<Vec<P3>> = std::iter::once(polygon.exterior())
+                        .chain(polygon.interiors())
+                        .map(|ring| take(ring.0.len()).to_vec())
+                        .filter(|ring| !ring.is_empty())
+                        .collect();
+                    if !rings.is_empty() {
+                        parts.push(Part::Face(Face::new(rings)));
+                    }
+                }
+            }
+            Geometry::GeometryCollection(members, _) => {
+                for member in members {
+                    walk(member, vertices, next, parts);
+                }
+            }
+            Geometry::Zm(base, _) => walk(base, vertices, next, parts),
+        }
+    }
+
+    let mut parts = Vec::new();
+    walk(geom.xy(), &vertices, &mut 0, &mut parts);
+    parts
+}
+
+/// Closest pair of points of two geometries in 3D, the first on `a`; None
+/// if either is empty
+///
+/// Only the parts of `b` within the best distance so far of each part of
+/// `a` are compared, found through an R*-tree.
+fn closest_pair(a: &Geometry, b: &Geometry) -> Option<Closest> {
+    let parts_a = parts_3d(a);
+    let tree = RTree::bulk_load(parts_3d(b));
+    let mut best = closest_parts(parts_a.first()?, tree.iter().next()?);
+    for part in &parts_a {
+        let envelope = part.envelope();
+        let (lower, upper) = (envelope.lower(), envelope.upper());
+        let d = best.distance;
+        let search = AABB::from_corners(
+            [lower[0] - d, lower[1] - d, lower[2] - d],
+            [upper[0] + d, upper[1] + d, upper[2] + d],
+        );
+        for other in tree.locate_in_envelope_intersecting(&search) {
+            best = best.min(closest_parts(part, other));
+            if best.distance == 0.0 {
+                return Some(best);
+            }
+        }
+    }
+    Some(best)
+}
+
+/// Point with Z
+fn point_z(point: P3, srid: i32) -> Geometry {
+    crate::functions::make_point_z(point[0], point[1], point[2]).with_srid(srid)
+}
+
+/// Closest pair of points of two geometries, the first on `a`, as points
+/// with Z when either geometry has Z; None if either is empty
+fn closest_points_3d(
+    a: &Geometry,
+    b: &Geometry,
+    function: &str,
+) -> Result<Option<(Geometry, Geometry)>, RostGisError> {
+    check_pair(a, b, function)?;
+    let srid = a.srid();
+    if !a.has_z() && !b.has_z() {
+        return Ok(closest_points_2d(a, b).map(|(from, to)| {
+            (
+                Geometry::Point(Point::from(from), srid),
+                Geometry::Point(Point::from(to), srid),
+            )
+        }));
+    }
+    Ok(closest_pair(a, b).map(|pair| (point_z(pair.on_a, srid), point_z(pair.on_b, srid))))
+}
+
+/// Point of `a` closest to `b` in 3D; None if either is empty
+pub fn closest_point_3d(a: &Geometry, b: &Geometry) -> Result<Option<Geometry>, RostGisError> {
+    Ok(closest_points_3d(a, b, "ST_3DClosestPoint")?.map(|(point, _)| point))
+}
+
+/// Shortest line from `a` to `b` in 3D; None if either is empty
+pub fn shortest_line_3d(a: &Geometry, b: &Geometry) -> Result<Option<Geometry>, RostGisError> {
+    Ok(
+        closest_points_3d(a, b, "ST_3DShortestLine")?.map(|(from, to)| {
+            let line = Geometry::LineString(
+                LineString::from(vec![
+                    (from.x().unwrap_or_default(), from.y().unwrap_or_default()),
+                    (to.x().unwrap_or_default(), to.y().unwrap_or_default()),
+                ]),
+                a.srid(),
+            );
+            match (from.z(), to.z()) {
+                (Some(z1), Some(z2)) => line
+                    .with_ordinates(Some(vec![z1, z2]), None)
+                    .expect("a line of two vertices"),
+                _ => line,
+            }
+        }),
+    )
+}
+
+/// Sum of the 3D lengths of the lines (or with `rings`, the polygon rings)
+/// of a geometry
+fn parts_length(geom: &Geometry, rings: bool) -> f64 {
+    let edge_length = |(a, b): (P3, P3)| length(sub(a, b));
+    parts_3d(geom)
+        .iter()
+        .map(|part| match part {
+            Part::Segment(a, b) if !rings => edge_length((*a, *b)),
+            Part::Face(face) if rings => face.edges().map(edge_length).sum(),
+            _ => 0.0,
+        })
+        .sum()
+}
+
+/// 3D length of the lines of a geometry
+pub fn length_3d(geom: &Geometry) -> f64 {
+    if !geom.has_z() {
+        return lines_length_2d(geom);
+    }
+    parts_length(geom, false)
+}
+
+fn lines_length_2d(geom: &Geometry) -> f64 {
+    match geom.xy() {
+        Geometry::LineString(..) | Geometry::MultiLineString(..) => {
+            geometry_length(geom.xy().clone())
+        }
+        Geometry::GeometryCollection(members, _) => members.iter().map(lines_length_2d).sum(),
+        _ => 0.0,
+    }
+}
+
+/// 3D perimeter of the polygons of a geometry
+pub fn perimeter_3d(geom: &Geometry) -> f64 {
+    if !geom.has_z() {
+        return rings_length_2d(geom);
+    }
+    parts_length(geom, true)
+}
+
+fn rings_length_2d(geom: &Geometry) -> f64 {
+    match geom.xy() {
+        Geometry::Polygon(..) | Geometry::MultiPolygon(..) => geometry_perimeter(geom.xy().clone()),
+        Geometry::GeometryCollection(members, _) => members.iter().map(rings_length_2d).sum(),
+        _ => 0.0,
+    }
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3ddistance(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<Option<f64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(distance_3d(&geom1, &geom2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3dintersects(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(intersects_3d(&geom1, &geom2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3ddwithin(
+    geom1: Geometry,
+    geom2: Geometry,
+    distance: f64,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(dwithin_3d(&geom1, &geom2, distance)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3dclosestpoint(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(closest_point_3d(&geom1, &geom2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3dshortestline(
+    geom1: Geometry,
+    geom2: Geometry,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(shortest_line_3d(&geom1, &geom2)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3dlength(geom: Geometry) -> f64 {
+    length_3d(&geom)
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_3dperimeter(geom: Geometry) -> f64 {
+    perimeter_3d(&geom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    #[test]
+    fn test_measures_3d() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 10 0)").unwrap();
+        let point = geometry_from_wkt("POINT(5 3)").unwrap();
+        assert_eq!(distance_3d(&line, &point).unwrap(), Some(3.0));
+        assert!(!intersects_3d(&line, &point).unwrap());
+        assert!(dwithin_3d(&line, &point, 3.0).unwrap());
+        assert!(!dwithin_3d(&line, &point, 2.9).unwrap());
+
+        let crossing = geometry_from_wkt("LINESTRING(5 -1, 5 1)").unwrap();
+        assert!(intersects_3d(&line, &crossing).unwrap());
+        let empty = geometry_from_wkt("POINT EMPTY").unwrap();
+        assert_eq!(distance_3d(&line, &empty).unwrap(), None);
+    }
+
+    #[test]
+    fn test_measures_3d_need_same_srid() {
+        let a = geometry_from_wkt("POINT(0 0)").unwrap();
+        let b = a.clone().with_srid(4326);
+        assert!(distance_3d(&a, &b).is_err());
+        assert!(intersects_3d(&a, &b).is_err());
+        assert!(dwithin_3d(&a, &b, 1.0).is_err());
+        assert!(shortest_line_3d(&a, &b).is_err());
+    }
+
+    #[test]
+    fn test_shortest_line_3d() {
+        let wkt = |a: &str, b: &str| {
+            let (a, b) = (geometry_from_wkt(a).unwrap(), geometry_from_wkt(b).unwrap());
+            shortest_line_3d(&a, &b).unwrap().map(|line| line.to_wkt())
+        };
+        assert_eq!(
+            wkt("LINESTRING(0 0, 10 0)", "POINT(5 3)").as_deref(),
+            Some("LINESTRING(5 0,5 3)")
+        );
+        assert_eq!(
+            wkt("LINESTRING(0 3, 4 3)", "LINESTRING(2 0, 2 1)").as_deref(),
+            Some("LINESTRING(2 3,2 1)")
+        );
+        // Crossing and contained geometries share a point
+        assert_eq!(
+            wkt("LINESTRING(0 0, 2 2)", "LINESTRING(0 2, 2 0)").as_deref(),
+            Some("LINESTRING(1 1,1 1)")
+        );
+        assert_eq!(
+            wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))", "POINT(3 4)").as_deref(),
+            Some("LINESTRING(3 4,3 4)")
+        );
+        assert_eq!(wkt("POINT(0 0)", "POINT EMPTY"), None);
+
+        let line = geometry_from_wkt("LINESTRING(0 0, 10 0)").unwrap();
+        let point = geometry_from_wkt("POINT(5 3)").unwrap();
+        assert_eq!(
+            closest_point_3d(&point, &line).unwrap().unwrap().to_wkt(),
+            "POINT(5 3)"
+        );
+    }
+
+    #[test]
+    fn test_length_and_perimeter_3d() {
+        let square = geometry_from_wkt("POLYGON((0 0, 3 0, 3 3, 0 3, 0 0))").unwrap();
+        let line = geometry_from_wkt("LINESTRING(0 0, 3 4)").unwrap();
+        let measures = |geom| (length_3d(geom), perimeter_3d(geom));
+        assert_eq!(measures(&line), (5.0, 0.0));
+        assert_eq!(measures(&square), (0.0, 12.0));
+        let collection = geometry_from_wkt(
+            "GEOMETRYCOLLECTION(LINESTRING(0 0, 3 4), POLYGON((0 0, 1 0, 1 1, 0 0)))",
+        )
+        .unwrap();
+        assert_eq!(length_3d(&collection), 5.0);
+    }
+
+    #[test]
+    fn test_measures_3d_with_z() {
+        let geom = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let distance = |a: &str, b: &str| distance_3d(&geom(a), &geom(b)).unwrap();
+        assert_eq!(distance("POINT Z (0 0 0)", "POINT Z (0 0 10)"), Some(10.0));
+        let square = "POLYGON Z ((0 0 0, 10 0 0, 10 10 0, 0 10 0, 0 0 0))";
+        assert_eq!(distance("POINT Z (5 5 4)", square), Some(4.0));
+        assert_eq!(distance("POINT Z (13 14 0)", square), Some(5.0));
+        // A missing z is 0
+        assert_eq!(distance("POINT(5 5)", square), Some(0.0));
+        let wall = "POLYGON Z ((0 0 0, 10 0 0, 10 0 10, 0 0 10, 0 0 0))";
+        assert_eq!(distance("POINT Z (5 3 5)", wall), Some(3.0));
+        assert_eq!(
+            distance(
+                "LINESTRING Z (0 0 0, 10 0 0)",
+                "LINESTRING Z (5 -5 3, 5 5 3)"
+            ),
+            Some(3.0)
+        );
+
+        let above = geom("POINT Z (5 5 4)");
+        assert!(!intersects_3d(&above, &geom(square)).unwrap());
+        assert!(dwithin_3d(&above, &geom(square), 4.0).unwrap());
+        assert!(!dwithin_3d(&above, &geom(square), 3.9).unwrap());
+        let piercing = geom("LINESTRING Z (5 5 -1, 5 5 1)");
+        assert!(intersects_3d(&piercing, &geom(square)).unwrap());
+        assert!(!intersects_3d(&piercing, &geom(wall)).unwrap());
+    }
+
+    #[test]
+    fn test_shortest_line_and_length_with_z() {
+        let geom = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let (a, b) = (
+            geom("LINESTRING Z (0 0 0, 10 0 0)"),
+            geom("LINESTRING Z (5 -5 3, 5 5 3)"),
+        );
+        assert_eq!(
+            shortest_line_3d(&a, &b).unwrap().unwrap().to_wkt(),
+            "LINESTRING Z (5 0 0,5 0 3)"
+        );
+        let square = geom("POLYGON Z ((0 0 0, 10 0 0, 10 10 0, 0 10 0, 0 0 0))");
+        assert_eq!(
+            closest_point_3d(&square, &geom("POINT Z (5 5 4)"))
+                .unwrap()
+                .unwrap()
+                .to_wkt(),
+            "POINT Z (5 5 0)"
+        );
+
+        assert_eq!(length_3d(&geom("LINESTRING Z (0 0 0, 0 3 4)")), 5.0);
+        let wall = geom("POLYGON Z ((0 0 0, 3 0 0, 3 0 4, 0 0 4, 0 0 0))");
+        assert_eq!((length_3d(&wall), perimeter_3d(&wall)), (0.0, 14.0));
+    }
+}
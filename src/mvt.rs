@@ -0,0 +1,255 @@
+use crate::functions::geometry_is_valid;
+use crate::geometry::Geometry;
+use crate::overlay::overlay;
+use crate::precision::{snap_to_grid, Grid};
+use crate::spatial_index::BBox;
+use crate::utils::RostGisError;
+use geo::orient::Direction;
+use geo::{unary_union, Area, BooleanOps, MapCoords, Orient};
+use geo_types::{
+    Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon, Rect,
+};
+
+/// Options of ST_AsMVTGeom
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MvtOptions {
+    /// Tile extent in integer tile coordinates
+    pub extent: i32,
+    /// Buffer around the tile, in tile coordinates, kept when clipping
+    pub buffer: i32,
+    /// Clip geometries to the tile plus buffer
+    pub clip_geom: bool,
+    /// Repair polygons made invalid by integer snapping
+    pub make_valid: bool,
+    /// Drop polygons smaller than this area, in squared tile units
+    pub min_area: f64,
+}
+
+impl Default for MvtOptions {
+    fn default() -> Self {
+        MvtOptions {
+            extent: 4096,
+            buffer: 256,
+            clip_geom: true,
+            make_valid: true,
+            min_area: 0.0,
+        }
+    }
+}
+
+/// Geometry parts split by dimension
+#[derive(Default)]
+struct Parts {
+    points: Vec<Point<f64>>,
+    lines: Vec<LineString<f64>>,
+    polygons: Vec<Polygon<f64>>,
+}
+
+impl Parts {
+    fn collect(geom: &Geometry, parts: &mut Parts) {
+        match geom {
+            Geometry::Zm(base, _) => Parts::collect(base, parts),
+            Geometry::Point(point, _) => parts.points.push(*point),
+            Geometry::MultiPoint(multipoint, _) => parts.points.extend(multipoint.0.iter()),
+            Geometry::LineString(linestring, _) => parts.lines.push(linestring.clone()),
+            Geometry::MultiLineString(multilinestring, _) => {
+                parts.lines.extend(multilinestring.0.iter().cloned())
+            }
+            Geometry::Polygon(polygon, _) => parts.polygons.push(polygon.clone()),
+            Geometry::MultiPolygon(multipolygon, _) => {
+                parts.polygons.extend(multipolygon.0.iter().cloned())
+            }
+            Geometry::GeometryCollection(geometries, _) => {
+                for child in geometries {
+                    Parts::collect(child, parts);
+                }
+            }
+        }
+    }
+}
+
+/// Transform a geometry into Mapbox Vector Tile coordinate space
+///
+/// Coordinates are mapped from `bounds` to a grid of `extent` units with the
+/// y axis pointing down, optionally clipped to the tile plus buffer, and
+/// snapped to integers. Integer snapping can turn thin polygons into bowties
+/// or slivers: with `make_valid` such polygons are rebuilt by a self-union
+/// and re-snapped, and polygons whose area is below `min_area` are dropped.
+/// Collections keep only their highest-dimension parts. Returns `None` when
+/// nothing is left.
+pub fn as_mvt_geom(
+    geom: &Geometry,
+    bounds: &BBox,
+    options: &MvtOptions,
+) -> Result<Option<Geometry>, RostGisError> {
+    let width = bounds.max_x - bounds.min_x;
+    let height = bounds.max_y - bounds.min_y;
+    if width <= 0.0 || height <= 0.0 {
+        return Err(RostGisError::new(
+            "MVT bounds must have a positive width and height",
+        ));
+    }
+    if options.extent <= 0 || options.buffer < 0 {
+        return Err(RostGisError::new(
+            "MVT extent must be positive and buffer non-negative",
+        ));
+    }
+
+    let extent = options.extent as f64;
+    let to_tile = |c: Coord<f64>| Coord {
+        x: (c.x - bounds.min_x) * extent / width,
+        y: (bounds.max_y - c.y) * extent / height,
+    };
+
+    let mut parts = Parts::default();
+    Parts::collect(geom, &mut parts);
+
+    let clip_rect = Rect::new(
+        Coord {
+            x: -options.buffer as f64,
+            y: -options.buffer as f64,
+        },
+        Coord {
+            x: extent + options.buffer as f64,
+            y: extent + options.buffer as f64,
+        },
+    );
+    let grid = Grid::uniform(1.0)?;
+    let snap = |geom: Geometry| snap_to_grid(&geom, &grid);
+
+    // Highest dimension first, as MVT features hold a single geometry type
+    if !parts.polygons.is_empty() {
+        let mut polygons = MultiPolygon(parts.polygons).map_coords(to_tile);
+        if options.clip_geom {
+            polygons = overlay(
+                "ST_AsMVTGeom",
+                &[polygons, MultiPolygon(vec![clip_rect.to_polygon()])],
+                |inputs| inputs[0].intersection(&inputs[1]),
+            )?;
+        }
+
+        let mut snapped = match snap(Geometry::MultiPolygon(polygons, 0)) {
+            Geometry::MultiPolygon(snapped, _) => snapped,
+            _ => unreachable!(),
+        };
+        if options.make_valid && !geometry_is_valid(&Geometry::MultiPolygon(snapped.clone(), 0)) {
+            let repaired = unary_union(snapped.0.iter());
+            snapped = match snap(Geometry::MultiPolygon(repaired, 0)) {
+                Geometry::MultiPolygon(snapped, _) => snapped,
+                _ => unreachable!(),
+            };
+            snapped
+                .0
+                .retain(|polygon| geometry_is_valid(&Geometry::Polygon(polygon.clone(), 0)));
+        }
+        snapped.0.retain(|polygon| {
+            let area = polygon.unsigned_area();
+            area > 0.0 && area >= options.min_area
+        });
+
+        // MVT exterior rings have a positive surveyor's area in tile space
+        let oriented = snapped.orient(Direction::Default);
+        return Ok(match oriented.0.len() {
+            0 => None,
+            1 => Some(Geometry::Polygon(oriented.0[0].clone(), geom.srid())),
+            _ => Some(Geometry::MultiPolygon(oriented, geom.srid())),
+        });
+    }
+
+    if !parts.lines.is_empty() {
+        let mut lines = MultiLineString(parts.lines).map_coords(to_tile);
+        if options.clip_geom {
+            lines = clip_rect.to_polygon().clip(&lines, false);
+        }
+        let lines = match snap(Geometry::MultiLineString(lines, 0)) {
+            Geometry::MultiLineString(lines, _) => lines,
+            _ => unreachable!(),
+        };
+        return Ok(match lines.0.len() {
+            0 => None,
+            1 => Some(Geometry::LineString(lines.0[0].clone(), geom.srid())),
+            _ => Some(Geometry::MultiLineString(lines, geom.srid())),
+        });
+    }
+
+    let mut points = MultiPoint(parts.points).map_coords(to_tile);
+    if options.clip_geom {
+        points.0.retain(|point| {
+            let (min, max) = (clip_rect.min(), clip_rect.max());
+            point.x() >= min.x && point.x() <= max.x && point.y() >= min.y && point.y() <= max.y
+        });
+    }
+    let points = match snap(Geometry::MultiPoint(points, 0)) {
+        Geometry::MultiPoint(points, _) => points,
+        _ => unreachable!(),
+    };
+    Ok(match points.0.len() {
+        0 => None,
+        1 => Some(Geometry::Point(points.0[0], geom.srid())),
+        _ => Some(Geometry::MultiPoint(points, geom.srid())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn tile() -> BBox {
+        BBox::new(0.0, 0.0, 4096.0, 4096.0)
+    }
+
+    #[test]
+    fn test_point_flips_y() {
+        let result = as_mvt_geom(&make_point(10.2, 20.7), &tile(), &MvtOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, make_point(10.0, 4075.0));
+    }
+
+    #[test]
+    fn test_clip_outside() {
+        let far = make_point(10000.0, 10000.0);
+        assert_eq!(
+            as_mvt_geom(&far, &tile(), &MvtOptions::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_small_polygon_dropping() {
+        let small = geometry_from_wkt("POLYGON((0 0, 3 0, 3 3, 0 3, 0 0))").unwrap();
+        let options = MvtOptions {
+            min_area: 16.0,
+            ..MvtOptions::default()
+        };
+        assert_eq!(as_mvt_geom(&small, &tile(), &options).unwrap(), None);
+        assert!(as_mvt_geom(&small, &tile(), &MvtOptions::default())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_snapping_repairs_self_touching_ring() {
+        // Snapping pulls the 0.4 vertex onto the bottom edge
+        let polygon = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 5 0.4, 0 10, 0 0))").unwrap();
+
+        let naive = MvtOptions {
+            make_valid: false,
+            ..MvtOptions::default()
+        };
+        let snapped = as_mvt_geom(&polygon, &tile(), &naive).unwrap().unwrap();
+        assert!(!geometry_is_valid(&snapped));
+
+        let repaired = as_mvt_geom(&polygon, &tile(), &MvtOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(geometry_is_valid(&repaired));
+    }
+
+    #[test]
+    fn test_invalid_bounds() {
+        let bounds = BBox::new(0.0, 0.0, 0.0, 10.0);
+        assert!(as_mvt_geom(&make_point(0.0, 0.0), &bounds, &MvtOptions::default()).is_err());
+    }
+}
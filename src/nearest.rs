@@ -0,0 +1,262 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::BBox;
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use geo::{Distance, Euclidean};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+// Nearest rows of a table (rostgis_closest_feature)
+//
+// Until the planner can order an index scan by distance, the nearest rows
+// are found with bounding box windows the && index can answer. Any n rows
+// give an upper bound for the distance of the n-th nearest one. Starting
+// from a small fraction of that bound, windows around the query box are
+// doubled, each fetching only the rows the previous one missed, until n
+// rows lie within the window's margin: a row whose box is outside the
+// window is farther than the margin, so no unseen row can be closer. At the
+// bound itself the window holds the answer whatever the data looks like.
+
+/// The first window margin is the upper bound divided by this
+const FIRST_WINDOW_DIVISOR: f64 = 1024.0;
+
+/// Query box grown by `margin` on every side
+fn window(query: &BBox, margin: f64) -> BBox {
+    BBox::new(
+        query.min_x - margin,
+        query.min_y - margin,
+        query.max_x + margin,
+        query.max_y + margin,
+    )
+}
+
+/// Find the `n` rows nearest to `query`, ordered by distance
+///
+/// `sample(n)` returns any `n` rows, or all rows of a smaller table, and
+/// `fetch(window, seen)` the rows whose bounding box overlaps `window` but
+/// not `seen`. Rows with empty geometries must not be returned.
+pub fn closest_rows<R, E>(
+    query: &Geometry,
+    n: usize,
+    sample: impl FnOnce(usize) -> Result<Vec<(R, Geometry)>, E>,
+    mut fetch: impl FnMut(&BBox, Option<&BBox>) -> Result<Vec<(R, Geometry)>, E>,
+) -> Result<Vec<(R, f64)>, E> {
+    let query_geo = query.to_geo();
+    let distance = |geom: &Geometry| Euclidean.distance(&query_geo, &geom.to_geo());
+    let nearest = |mut rows: Vec<(R, f64)>| {
+        rows.sort_by(|a, b| a.1.total_cmp(&b.1));
+        rows.truncate(n);
+        rows
+    };
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+
+    let sampled: Vec<(R, f64)> = sample(n)?
+        .into_iter()
+        .map(|(row, geom)| (row, distance(&geom)))
+        .collect();
+    let bound = sampled.iter().map(|(_, d)| *d).fold(0.0, f64::max);
+    if sampled.len() < n || bound == 0.0 {
+        return Ok(nearest(sampled));
+    }
+
+    let query_box = BBox::from_geometry(query);
+    let mut rows: Vec<(R, f64)> = Vec::new();
+    let mut seen: Option<BBox> = None;
+    let mut margin = bound / FIRST_WINDOW_DIVISOR;
+    loop {
+        let margin_window = window(&query_box, margin);
+        for (row, geom) in fetch(&margin_window, seen.as_ref())? {
+            rows.push((row, distance(&geom)));
+        }
+        let within = rows.iter().filter(|(_, d)| *d <= margin).count();
+        if within >= n || margin >= bound {
+            return Ok(nearest(rows));
+        }
+        seen = Some(margin_window);
+        margin = (margin * 2.0).min(bound);
+    }
+}
+
+/// PostgreSQL function returning the `n` rows of a table nearest to a
+/// geometry, by true distance rather than bounding box distance
+///
+/// Rows are identified by their ctid, e.g.
+/// `SELECT r.*, c.distance FROM rostgis_closest_feature('roads', 'geom', q, 5) c
+///  JOIN roads r ON r.ctid = c.row_ctid ORDER BY c.rank`. The geometry column
+/// should have an index so the windows are answered by index scans.
+#[allow(clippy::type_complexity)]
+#[pg_extern(stable, strict)]
+pub fn rostgis_closest_feature(
+    table_name: &str,
+    geom_column: &str,
+    query_geom: Geometry,
+    n: i32,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(rank, i32),
+            name!(row_ctid, pg_sys::ItemPointerData),
+            name!(distance, f64),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    if n < 0 {
+        return Err("rostgis_closest_feature row count cannot be negative".into());
+    }
+    if query_geom.is_empty() {
+        return Err(Box::new(RostGisError::new(
+            "rostgis_closest_feature query geometry cannot be empty",
+        )));
+    }
+
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+    let schema = extension_schema()?;
+    let rows_where = |condition: &str| {
+        format!(
+            "SELECT ctid, {column} FROM {relation}
+             WHERE {condition} AND NOT {schema}.st_isempty({column})"
+        )
+    };
+
+    let nearest = Spi::connect(|client| {
+        let read = |table: pgrx::spi::SpiTupleTable| {
+            let mut rows = Vec::new();
+            for row in table {
+                if let (Some(ctid), Some(geom)) = (
+                    row.get::<pg_sys::ItemPointerData>(1)?,
+                    row.get::<Geometry>(2)?,
+                ) {
+                    rows.push((ctid, geom));
+                }
+            }
+            Ok::<_, pgrx::spi::Error>(rows)
+        };
+        closest_rows(
+            &query_geom,
+            n as usize,
+            |limit| {
+                let query = format!(
+                    "{} LIMIT {}",
+                    rows_where(&format!("{column} IS NOT NULL")),
+                    limit
+                );
+                read(client.select(&query, None, &[])?)
+            },
+            |window, seen| {
+                let window = window.to_geometry();
+                match seen {
+                    None => read(client.select(
+                        &rows_where(&format!("{column} OPERATOR({schema}.&&) $1")),
+                        None,
+                        &[window.into()],
+                    )?),
+                    Some(seen) => read(client.select(
+                        &rows_where(&format!(
+                            "{column} OPERATOR({schema}.&&) $1
+                             AND NOT {column} OPERATOR({schema}.&&) $2"
+                        )),
+                        None,
+                        &[window.into(), seen.to_geometry().into()],
+                    )?),
+                }
+            },
+        )
+    })?;
+
+    Ok(TableIterator::new(nearest.into_iter().enumerate().map(
+        |(i, (ctid, distance))| (i as i32 + 1, ctid, distance),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+    use std::cell::Cell;
+
+    /// Answer the search callbacks from a vector of rows
+    fn search(rows: &[Geometry], query: &Geometry, n: usize) -> (Vec<(usize, f64)>, usize) {
+        let windows = Cell::new(0);
+        let result = closest_rows::<usize, ()>(
+            query,
+            n,
+            |limit| Ok(rows.iter().cloned().enumerate().take(limit).collect()),
+            |window, seen| {
+                windows.set(windows.get() + 1);
+                Ok(rows
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .filter(|(_, geom)| {
+                        let bbox = BBox::from_geometry(geom);
+                        bbox.overlaps(window) && !seen.is_some_and(|seen| bbox.overlaps(seen))
+                    })
+                    .collect())
+            },
+        )
+        .unwrap();
+        (result, windows.get())
+    }
+
+    #[test]
+    fn test_true_distance_order() {
+        // The long diagonal line has the nearest bounding box but is far
+        // from the query point itself
+        let rows = [
+            geometry_from_wkt("LINESTRING(0 100, 100 0)").unwrap(),
+            make_point(60.0, 60.0),
+            make_point(1.0, 2.0),
+            make_point(3.0, 0.0),
+        ];
+        let (nearest, windows) = search(&rows, &make_point(0.0, 0.0), 2);
+        assert_eq!(
+            nearest.iter().map(|(row, _)| *row).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!((nearest[0].1 - 5f64.sqrt()).abs() < 1e-12);
+        assert!(windows > 1);
+
+        // The line is nearer than the far point
+        let (nearest, _) = search(&rows, &make_point(0.0, 0.0), 4);
+        assert_eq!(
+            nearest.iter().map(|(row, _)| *row).collect::<Vec<_>>(),
+            vec![2, 3, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_small_tables() {
+        let rows = [make_point(5.0, 0.0), make_point(1.0, 0.0)];
+        let (nearest, windows) = search(&rows, &make_point(0.0, 0.0), 3);
+        assert_eq!(nearest, vec![(1, 1.0), (0, 5.0)]);
+        assert_eq!(windows, 0);
+        assert!(search(&rows, &make_point(0.0, 0.0), 0).0.is_empty());
+        assert!(search(&[], &make_point(0.0, 0.0), 1).0.is_empty());
+    }
+
+    #[test]
+    fn test_windows_match_brute_force() {
+        let rows: Vec<Geometry> = (0..200)
+            .map(|i| {
+                let i = i as f64;
+                make_point((i * 7.3) % 101.0, (i * 3.1) % 53.0)
+            })
+            .collect();
+        let query = make_point(40.0, 20.0);
+        let (nearest, _) = search(&rows, &query, 10);
+
+        let mut expected: Vec<(usize, f64)> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, geom)| (i, Euclidean.distance(&query.to_geo(), &geom.to_geo())))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        // Rows at equal distances may come in any order
+        let distances = |rows: &[(usize, f64)]| rows.iter().map(|(_, d)| *d).collect::<Vec<_>>();
+        assert_eq!(distances(&nearest), distances(&expected[..10]));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo_types::{Coord, Line, LineString, MultiLineString};
+use pgrx::prelude::*;
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, RTreeObject};
+use std::collections::HashSet;
+
+// Noding of linework (ST_Node)
+//
+// Every pair of segments whose boxes overlap is intersected. A crossing
+// point is inserted into both segments, computed once so both get the same
+// coordinate, and a collinear overlap inserts its two ends. Inserted points
+// and vertices touched by another segment are nodes, where the lines are
+// split; input vertices elsewhere are kept inside the pieces, like in
+// PostGIS:
+//
+//   ST_Node('LINESTRING(0 0, 10 10, 0 10, 10 0)')
+//   = MULTILINESTRING((0 0,5 5),(5 5,10 10,0 10,5 5),(5 5,10 0))
+//
+// The result is dissolved: a segment traced again, in either direction, by a
+// later line or part of a line is left out, and the piece being built ends
+// there.
+
+type IndexedSegment = GeomWithData<Line<f64>, (usize, usize)>;
+
+/// Lines of a lineal geometry, without repeated points
+fn lines(geom: &Geometry, into: &mut Vec<LineString<f64>>) -> Result<(), RostGisError> {
+    let mut push = |line: &LineString<f64>| {
+        let mut coords = line.0.clone();
+        coords.dedup();
+        if coords.len() > 1 {
+            into.push(LineString(coords));
+        }
+    };
+    match geom {
+        Geometry::LineString(line, _) => push(line),
+        Geometry::MultiLineString(multi_line, _) => multi_line.iter().for_each(push),
+        Geometry::GeometryCollection(members, _) => {
+            for member in members {
+                lines(member, into)?;
+            }
+        }
+        _ => {
+            return Err(RostGisError::new(&format!(
+                "ST_Node requires linework, got {}",
+                geom.geometry_type()
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// A segment with its ends in a fixed order, so both directions match
+fn segment_key(a: Coord<f64>, b: Coord<f64>) -> [u64; 4] {
+    let (a, b) = if (a.x, a.y) <= (b.x, b.y) {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    [a.x.to_bits(), a.y.to_bits(), b.x.to_bits(), b.y.to_bits()]
+}
+
+/// Node the lines of a geometry at all their intersections
+pub fn node(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    let mut input = Vec::new();
+    lines(geom, &mut input)?;
+
+    // Points inserted into or touching each segment of each line
+    let mut splits: Vec<Vec<Vec<Coord<f64>>>> = input
+        .iter()
+        .map(|line| vec![Vec::new(); line.0.len() - 1])
+        .collect();
+    let segments: Vec<IndexedSegment> = input
+        .iter()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            line.lines()
+                .enumerate()
+                .map(move |(j, segment)| GeomWithData::new(segment, (i, j)))
+        })
+        .collect();
+    let tree = RTree::bulk_load(segments);
+
+    for segment in tree.iter() {
+        let (i, j) = segment.data;
+        for other in tree.locate_in_envelope_intersecting(&segment.geom().envelope()) {
+            let (k, l) = other.data;
+            if (k, l) <= (i, j) {
+                continue;
+            }
+            let points = match line_intersection(*segment.geom(), *other.geom()) {
+                None => continue,
+                Some(LineIntersection::SinglePoint { intersection, .. }) => {
+                    // Consecutive segments of a line meet at their shared vertex
+                    let shared = i == k
+                        && ((l == j + 1 && intersection == segment.geom().end)
+                            || (j == 0
+                                && l + 1 == input[i].0.len() - 1
+                                && intersection == segment.geom().start));
+                    if shared {
+                        continue;
+                    }
+                    vec![intersection]
+                }
+                Some(LineIntersection::Collinear { intersection }) => {
+                    vec![intersection.start, intersection.end]
+                }
+            };
+            splits[i][j].extend(&points);
+            splits[k][l].extend(&points);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut pieces = Vec::new();
+    for (line, line_splits) in input.iter().zip(splits) {
+        // Vertices of the line with the inserted points, and whether each is
+        // a node
+        let mut vertices: Vec<(Coord<f64>, bool)> = vec![(line.0[0], true)];
+        for (segment, mut points) in line.lines().zip(line_splits) {
+            let along = |point: &Coord<f64>| {
+                let (d, p) = (segment.delta(), *point - segment.start);
+                p.x * d.x + p.y * d.y
+            };
+            points.sort_by(|a, b| along(a).total_cmp(&along(b)));
+            let mut end_is_node = false;
+            for point in points {
+                if point == segment.start {
+                    vertices.last_mut().unwrap().1 = true;
+                } else if point == segment.end {
+                    end_is_node = true;
+                } else if vertices.last().unwrap().0 != point {
+                    vertices.push((point, true));
+                }
+            }
+            if vertices.last().unwrap().0 != segment.end {
+                vertices.push((segment.end, end_is_node));
+            } else {
+                vertices.last_mut().unwrap().1 |= end_is_node;
+            }
+        }
+        vertices.last_mut().unwrap().1 = true;
+
+        let mut piece: Vec<Coord<f64>> = Vec::new();
+        for pair in vertices.windows(2) {
+            let ((a, _), (b, b_is_node)) = (pair[0], pair[1]);
+            if !seen.insert(segment_key(a, b)) {
+                if piece.len() > 1 {
+                    pieces.push(LineString(std::mem::take(&mut piece)));
+                }
+                piece.clear();
+                continue;
+            }
+            if piece.is_empty() {
+                piece.push(a);
+            }
+            piece.push(b);
+            if b_is_node {
+                pieces.push(LineString(std::mem::take(&mut piece)));
+            }
+        }
+    }
+    Ok(Geometry::MultiLineString(
+        MultiLineString(pieces),
+        geom.srid(),
+    ))
+}
+
+/// PostgreSQL function noding linework at all its intersections
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_node(geom: Geometry) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(node(&geom)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn node_wkt(wkt: &str) -> String {
+        node(&geometry_from_wkt(wkt).unwrap()).unwrap().to_wkt()
+    }
+
+    #[test]
+    fn test_self_intersection() {
+        assert_eq!(
+            node_wkt("LINESTRING(0 0, 10 10, 0 10, 10 0)"),
+            "MULTILINESTRING((0 0,5 5),(5 5,10 10,0 10,5 5),(5 5,10 0))"
+        );
+    }
+
+    #[test]
+    fn test_crossings_and_touches() {
+        // A crossing, and a line ending inside another
+        assert_eq!(
+            node_wkt("MULTILINESTRING((0 0, 10 0), (5 -5, 5 5), (0 0, 2 2, 4 0))"),
+            "MULTILINESTRING((0 0,4 0),(4 0,5 0),(5 0,10 0),(5 -5,5 0),(5 0,5 5),(0 0,2 2,4 0))"
+        );
+        // Lines that only meet at their ends are unchanged
+        assert_eq!(
+            node_wkt("MULTILINESTRING((0 0, 1 1, 2 0), (2 0, 3 3))"),
+            "MULTILINESTRING((0 0,1 1,2 0),(2 0,3 3))"
+        );
+    }
+
+    #[test]
+    fn test_overlaps_dissolved() {
+        assert_eq!(
+            node_wkt("MULTILINESTRING((0 0, 10 0), (8 0, 5 0, 2 0))"),
+            "MULTILINESTRING((0 0,2 0),(2 0,5 0),(5 0,8 0),(8 0,10 0))"
+        );
+        // A line traced twice
+        assert_eq!(
+            node_wkt("GEOMETRYCOLLECTION(LINESTRING(0 0, 1 1), LINESTRING(1 1, 0 0))"),
+            "MULTILINESTRING((0 0,1 1))"
+        );
+    }
+
+    #[test]
+    fn test_node_input() {
+        assert_eq!(node_wkt("MULTILINESTRING EMPTY"), "MULTILINESTRING EMPTY");
+        assert!(node(&geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap()).is_err());
+        let srid = node(
+            &geometry_from_wkt("LINESTRING(0 0, 1 1)")
+                .unwrap()
+                .with_srid(4326),
+        )
+        .unwrap()
+        .srid();
+        assert_eq!(srid, 4326);
+    }
+}
@@ -0,0 +1,145 @@
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+// Operator catalog (rostgis_operators)
+//
+// Lists the operators of the installed extension with the operator classes
+// they belong to, so tools can find out what an install supports instead of
+// hard-coding it:
+//
+//   SELECT DISTINCT operator_name FROM rostgis_operators()
+//   WHERE left_type = 'geometry' AND index_accelerated;
+//
+// The catalog is read rather than described here, so the result reflects
+// what CREATE EXTENSION (or ALTER EXTENSION UPDATE) actually created. An
+// operator has one row per operator class it is in, or a single row with
+// NULL class columns when it is in none; index_accelerated tells them
+// apart. purpose is 'search' for operators that filter index scans and
+// 'order' for those that order them, like <-> in nearest-neighbour queries.
+
+/// What an operator computes, by name
+const DESCRIPTIONS: [(&str, &str); 19] = [
+    ("&&", "Bounding boxes intersect"),
+    ("&&&", "3D bounding boxes intersect"),
+    ("<<", "Bounding box is strictly left of"),
+    (">>", "Bounding box is strictly right of"),
+    ("<<|", "Bounding box is strictly below"),
+    ("|>>", "Bounding box is strictly above"),
+    ("&<", "Bounding box does not extend to the right of"),
+    ("&>", "Bounding box does not extend to the left of"),
+    ("&<|", "Bounding box does not extend above"),
+    ("|&>", "Bounding box does not extend below"),
+    ("~", "Bounding box contains"),
+    ("@", "Bounding box is contained by"),
+    ("~=", "Bounding boxes are the same"),
+    ("<->", "Distance, for nearest-neighbour ordering"),
+    ("<", "Sorts before"),
+    ("<=", "Sorts before or equal"),
+    ("=", "Same SRID, type and coordinates"),
+    (">=", "Sorts after or equal"),
+    (">", "Sorts after"),
+];
+
+/// Description of an operator
+pub fn operator_description(name: &str) -> Option<&'static str> {
+    DESCRIPTIONS
+        .iter()
+        .find(|(operator, _)| *operator == name)
+        .map(|(_, description)| *description)
+}
+
+/// Purpose of an operator in an operator class, from pg_amop.amoppurpose
+pub fn operator_purpose(amoppurpose: &str) -> Option<&'static str> {
+    match amoppurpose {
+        "s" => Some("search"),
+        "o" => Some("order"),
+        _ => None,
+    }
+}
+
+/// PostgreSQL function listing the extension's operators, the operator
+/// classes they belong to and whether an index can evaluate them
+#[allow(clippy::type_complexity)]
+#[pg_extern(stable)]
+pub fn rostgis_operators() -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(operator_name, String),
+            name!(left_type, String),
+            name!(right_type, String),
+            name!(function_name, String),
+            name!(description, Option<String>),
+            name!(access_method, Option<String>),
+            name!(opclass, Option<String>),
+            name!(strategy, Option<i16>),
+            name!(purpose, Option<String>),
+            name!(index_accelerated, bool),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let rows = Spi::connect(|client| {
+        let rows = client.select(
+            "SELECT o.oprname::text, format_type(o.oprleft, NULL), format_type(o.oprright, NULL),
+                    o.oprcode::regproc::text, am.amname::text, c.opcname::text,
+                    ao.amopstrategy, ao.amoppurpose::text
+             FROM pg_catalog.pg_operator o
+             JOIN pg_catalog.pg_depend d
+               ON d.classid = 'pg_catalog.pg_operator'::regclass AND d.objid = o.oid
+              AND d.deptype = 'e'
+             JOIN pg_catalog.pg_extension e ON e.oid = d.refobjid AND e.extname = 'rostgis'
+             LEFT JOIN pg_catalog.pg_amop ao ON ao.amopopr = o.oid
+             LEFT JOIN pg_catalog.pg_am am ON am.oid = ao.amopmethod
+             LEFT JOIN pg_catalog.pg_opclass c
+               ON c.opcfamily = ao.amopfamily AND c.opcintype = ao.amoplefttype
+             ORDER BY 2, 1, 3, 5, 6",
+            None,
+            &[],
+        )?;
+        let mut result = Vec::new();
+        for row in rows {
+            let name = row.get::<String>(1)?.unwrap_or_default();
+            let method = row.get::<String>(5)?;
+            result.push((
+                name.clone(),
+                row.get::<String>(2)?.unwrap_or_default(),
+                row.get::<String>(3)?.unwrap_or_default(),
+                row.get::<String>(4)?.unwrap_or_default(),
+                operator_description(&name).map(str::to_string),
+                method.clone(),
+                row.get::<String>(6)?,
+                row.get::<i16>(7)?,
+                row.get::<String>(8)?
+                    .as_deref()
+                    .and_then(operator_purpose)
+                    .map(str::to_string),
+                method.is_some(),
+            ));
+        }
+        Ok::<_, pgrx::spi::Error>(result)
+    })?;
+    Ok(TableIterator::new(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operator_description() {
+        assert_eq!(operator_description("&&"), Some("Bounding boxes intersect"));
+        assert_eq!(
+            operator_description("<->"),
+            Some("Distance, for nearest-neighbour ordering")
+        );
+        assert_eq!(operator_description("?#"), None);
+    }
+
+    #[test]
+    fn test_operator_purpose() {
+        assert_eq!(operator_purpose("s"), Some("search"));
+        assert_eq!(operator_purpose("o"), Some("order"));
+        assert_eq!(operator_purpose("x"), None);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::serialization;
+use pgrx::prelude::*;
+use std::cmp::Ordering;
+
+// Geometry ordering (btree operator class)
+//
+// A total order of geometries, so geometry columns can be sorted, grouped,
+// made unique and merge-joined as in PostGIS:
+//
+//   SELECT DISTINCT geom FROM parcels ORDER BY geom;
+//   ALTER TABLE parcels ADD UNIQUE (geom);
+//
+// Geometries are compared on their stored form (see serialization::compare):
+// empty ones first, then by SRID, type and first point, so points sort by X
+// then Y. The order of other geometries is consistent but carries no spatial
+// meaning. = holds for geometries with the same SRID and coordinates; for
+// spatial equality use ST_Equals. Like the header accessors, the functions
+// take the raw datum and are declared in the extension_sql! block below.
+
+fn compare(a: &[u8], b: &[u8]) -> Result<Ordering, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(serialization::compare(a, b)?)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_cmp(a: &[u8], b: &[u8]) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)? as i32)
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_lt(a: &[u8], b: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)?.is_lt())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_le(a: &[u8], b: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)?.is_le())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_eq(a: &[u8], b: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)?.is_eq())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_ge(a: &[u8], b: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)?.is_ge())
+}
+
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gt(a: &[u8], b: &[u8]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(compare(a, b)?.is_gt())
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.geometry_cmp(@extschema@.geometry, @extschema@.geometry) RETURNS integer
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_cmp_wrapper';
+CREATE FUNCTION @extschema@.geometry_lt(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_lt_wrapper';
+CREATE FUNCTION @extschema@.geometry_le(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_le_wrapper';
+CREATE FUNCTION @extschema@.geometry_eq(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_eq_wrapper';
+CREATE FUNCTION @extschema@.geometry_ge(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_ge_wrapper';
+CREATE FUNCTION @extschema@.geometry_gt(@extschema@.geometry, @extschema@.geometry) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gt_wrapper';
+
+CREATE OPERATOR @extschema@.< (
+    LEFTARG = @extschema@.geometry, RIGHTARG = @extschema@.geometry,
+    FUNCTION = @extschema@.geometry_lt,
+    COMMUTATOR = >, NEGATOR = >=,
+    RESTRICT = scalarltsel, JOIN = scalarltjoinsel
+);
+CREATE OPERATOR @extschema@.<= (
+    LEFTARG = @extschema@.geometry, RIGHTARG = @extschema@.geometry,
+    FUNCTION = @extschema@.geometry_le,
+    COMMUTATOR = >=, NEGATOR = >,
+    RESTRICT = scalarlesel, JOIN = scalarlejoinsel
+);
+CREATE OPERATOR @extschema@.= (
+    LEFTARG = @extschema@.geometry, RIGHTARG = @extschema@.geometry,
+    FUNCTION = @extschema@.geometry_eq,
+    COMMUTATOR = =,
+    RESTRICT = eqsel, JOIN = eqjoinsel, MERGES
+);
+CREATE OPERATOR @extschema@.>= (
+    LEFTARG = @extschema@.geometry, RIGHTARG = @extschema@.geometry,
+    FUNCTION = @extschema@.geometry_ge,
+    COMMUTATOR = <=, NEGATOR = <,
+    RESTRICT = scalargesel, JOIN = scalargejoinsel
+);
+CREATE OPERATOR @extschema@.> (
+    LEFTARG = @extschema@.geometry, RIGHTARG = @extschema@.geometry,
+    FUNCTION = @extschema@.geometry_gt,
+    COMMUTATOR = <, NEGATOR = <=,
+    RESTRICT = scalargtsel, JOIN = scalargtjoinsel
+);
+
+CREATE OPERATOR CLASS @extschema@.rostgis_btree_ops
+    DEFAULT FOR TYPE @extschema@.geometry USING btree AS
+        OPERATOR 1 @extschema@.<  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 2 @extschema@.<= (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 3 @extschema@.=  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 4 @extschema@.>= (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 5 @extschema@.>  (@extschema@.geometry, @extschema@.geometry),
+        FUNCTION 1 @extschema@.geometry_cmp(@extschema@.geometry, @extschema@.geometry);
+"#,
+    name = "btree_operator_class",
+    requires = ["geometry_header_accessors"],
+);
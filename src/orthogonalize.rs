@@ -0,0 +1,286 @@
+use crate::functions::geometry_is_valid;
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo_types::{Coord, LineString, MultiPolygon, Polygon};
+use pgrx::prelude::*;
+use std::f64::consts::FRAC_PI_2;
+
+// Footprint squaring (ST_OrthogonalizePolygon)
+//
+// Digitized or machine-extracted building outlines have corners that are
+// meant to be right angles but are a few degrees off. The main orientation of
+// a polygon is the length-weighted mean of its edge directions modulo 90
+// degrees, averaged on the circle by multiplying the angles by four. In a
+// frame rotated to that orientation, every edge within the threshold of
+// horizontal or vertical is made exactly so: a run of such edges in the same
+// direction becomes one straight edge at the length-weighted mean of their
+// midpoints, and the vertex between a horizontal and a vertical run takes one
+// ordinate from each, which makes it a right angle. Edges further off, such
+// as the diagonal of a bay window, keep their direction as far as their
+// squared neighbours allow.
+//
+// Holes are squared to the orientation of their polygon's exterior, so they
+// stay parallel to its walls.
+
+/// Default threshold in degrees
+pub const DEFAULT_THRESHOLD: f64 = 15.0;
+
+/// Orientations closer than this to zero, in radians, are rounding noise of
+/// axis-aligned rings, which are squared without rotating them
+const ALIGNED: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+/// Main orientation of a ring in radians, in [-45, 45) degrees
+fn orientation(coords: &[Coord<f64>]) -> f64 {
+    let (cos, sin) = coords.windows(2).fold((0.0, 0.0), |(cos, sin), edge| {
+        let delta = edge[1] - edge[0];
+        let length = delta.x.hypot(delta.y);
+        let angle = 4.0 * delta.y.atan2(delta.x);
+        (cos + length * angle.cos(), sin + length * angle.sin())
+    });
+    let angle = sin.atan2(cos) / 4.0;
+    if angle.abs() < ALIGNED {
+        0.0
+    } else {
+        angle
+    }
+}
+
+/// Square one closed ring in the frame rotated by `angle` around `origin`,
+/// or None when it would collapse
+fn square_ring(
+    ring: &LineString<f64>,
+    origin: Coord<f64>,
+    angle: f64,
+    threshold: f64,
+) -> Option<LineString<f64>> {
+    let (sin, cos) = angle.sin_cos();
+    let into_frame = |coord: &Coord<f64>| {
+        let d = *coord - origin;
+        Coord {
+            x: d.x * cos + d.y * sin,
+            y: d.y * cos - d.x * sin,
+        }
+    };
+    let from_frame = |coord: Coord<f64>| Coord {
+        x: origin.x + coord.x * cos - coord.y * sin,
+        y: origin.y + coord.x * sin + coord.y * cos,
+    };
+
+    // Distinct vertices; edge i runs from vertex i to vertex i + 1
+    let mut vertices: Vec<Coord<f64>> = ring.0.iter().map(into_frame).collect();
+    vertices.dedup();
+    if vertices.len() > 1 && vertices.first() == vertices.last() {
+        vertices.pop();
+    }
+    let n = vertices.len();
+    if n < 3 {
+        return None;
+    }
+
+    let axes: Vec<Option<Axis>> = (0..n)
+        .map(|i| {
+            let delta = vertices[(i + 1) % n] - vertices[i];
+            let angle = delta.y.atan2(delta.x).abs();
+            if angle.min(std::f64::consts::PI - angle) <= threshold {
+                Some(Axis::Horizontal)
+            } else if (angle - FRAC_PI_2).abs() <= threshold {
+                Some(Axis::Vertical)
+            } else {
+                None
+            }
+        })
+        .collect();
+    // Start at the beginning of a run; a ring of one run cannot be squared
+    let start = (0..n).find(|&i| axes[i] != axes[(i + n - 1) % n])?;
+
+    let mut squared = vertices.clone();
+    let mut interior = vec![false; n];
+    let mut i = 0;
+    while i < n {
+        let first = (start + i) % n;
+        let mut length = 1;
+        while i + length < n && axes[(first + length) % n] == axes[first] {
+            length += 1;
+        }
+        if let Some(axis) = axes[first] {
+            let ordinate = |coord: &Coord<f64>| match axis {
+                Axis::Horizontal => coord.y,
+                Axis::Vertical => coord.x,
+            };
+            let (mut weighted, mut total) = (0.0, 0.0);
+            for edge in first..first + length {
+                let (a, b) = (&vertices[edge % n], &vertices[(edge + 1) % n]);
+                let edge_length = (b.x - a.x).hypot(b.y - a.y);
+                weighted += edge_length * (ordinate(a) + ordinate(b)) / 2.0;
+                total += edge_length;
+            }
+            let level = weighted / total;
+            for vertex in first..=first + length {
+                match axis {
+                    Axis::Horizontal => squared[vertex % n].y = level,
+                    Axis::Vertical => squared[vertex % n].x = level,
+                }
+            }
+            for vertex in first + 1..first + length {
+                interior[vertex % n] = true;
+            }
+        }
+        i += length;
+    }
+
+    let mut coords: Vec<Coord<f64>> = (0..n)
+        .filter(|&vertex| !interior[vertex])
+        .map(|vertex| squared[vertex])
+        .collect();
+    coords.dedup();
+    if coords.len() > 1 && coords.first() == coords.last() {
+        coords.pop();
+    }
+    if coords.len() < 3 {
+        return None;
+    }
+    coords.push(coords[0]);
+    Some(LineString(coords.into_iter().map(from_frame).collect()))
+}
+
+/// Square one polygon, or None when a ring would collapse
+fn square_polygon(polygon: &Polygon<f64>, threshold: f64) -> Option<Polygon<f64>> {
+    let exterior = polygon.exterior();
+    let origin = *exterior.0.first()?;
+    let angle = orientation(&exterior.0);
+    let squared_exterior = square_ring(exterior, origin, angle, threshold)?;
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .map(|ring| square_ring(ring, origin, angle, threshold))
+        .collect::<Option<Vec<_>>>()?;
+    Some(Polygon::new(squared_exterior, interiors))
+}
+
+/// Snap the near-right angles of polygons to right angles
+///
+/// `threshold` is the largest deviation in degrees, below 45, of an edge from
+/// the polygon's main axes for it to be squared. Polygons whose squared form
+/// would collapse or become invalid are returned unchanged.
+pub fn orthogonalize_polygon(geom: &Geometry, threshold: f64) -> Result<Geometry, RostGisError> {
+    if !(0.0..45.0).contains(&threshold) {
+        return Err(RostGisError::new(&format!(
+            "ST_OrthogonalizePolygon threshold must be between 0 and 45 degrees, got {}",
+            threshold
+        )));
+    }
+    let threshold = threshold.to_radians();
+    let square = |polygon: &Polygon<f64>| {
+        square_polygon(polygon, threshold)
+            .filter(|squared| geometry_is_valid(&Geometry::Polygon(squared.clone(), 0)))
+            .unwrap_or_else(|| polygon.clone())
+    };
+    match geom {
+        Geometry::Polygon(polygon, srid) => Ok(Geometry::Polygon(square(polygon), *srid)),
+        Geometry::MultiPolygon(polygons, srid) => Ok(Geometry::MultiPolygon(
+            MultiPolygon(polygons.iter().map(square).collect()),
+            *srid,
+        )),
+        _ => Err(RostGisError::new(&format!(
+            "ST_OrthogonalizePolygon requires a Polygon or MultiPolygon, got {}",
+            geom.geometry_type()
+        ))),
+    }
+}
+
+/// PostgreSQL function squaring the corners of building footprints
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_orthogonalizepolygon(
+    geom: Geometry,
+    threshold: default!(f64, 15.0),
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(orthogonalize_polygon(&geom, threshold)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    /// Cosines of the angles at the vertices of every ring
+    fn corner_cosines(geom: &Geometry) -> Vec<f64> {
+        let Geometry::Polygon(polygon, _) = geom else {
+            panic!("expected a polygon");
+        };
+        std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .flat_map(|ring| {
+                let coords = &ring.0[..ring.0.len() - 1];
+                let n = coords.len();
+                (0..n).map(move |i| {
+                    let a = coords[(i + n - 1) % n] - coords[i];
+                    let b = coords[(i + 1) % n] - coords[i];
+                    (a.x * b.x + a.y * b.y) / (a.x.hypot(a.y) * b.x.hypot(b.y))
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_square_rotated_footprint() {
+        // An L-shaped outline rotated by about 30 degrees, digitized roughly,
+        // with a collinear vertex along one wall
+        let footprint = geometry_from_wkt(
+            "POLYGON((0 0, 8.7 5, 11.66 0, 14.6 -5.1, 9.4 -8.1, 7.9 -5.3, 2.5 -8.6, 0 0))",
+        )
+        .unwrap();
+        let squared = orthogonalize_polygon(&footprint, DEFAULT_THRESHOLD).unwrap();
+        let cosines = corner_cosines(&squared);
+        assert_eq!(cosines.len(), 6);
+        assert!(cosines.iter().all(|cos| cos.abs() < 1e-9), "{:?}", cosines);
+        let area = |geom: &Geometry| crate::functions::geometry_area(geom.clone());
+        assert!((area(&squared) / area(&footprint) - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_hole_follows_exterior() {
+        let polygon = geometry_from_wkt(
+            "POLYGON((0 0, 10 0.3, 10 10, 0.2 10, 0 0), (2 2, 2.1 5, 5 5, 5 2.2, 2 2))",
+        )
+        .unwrap();
+        let squared = orthogonalize_polygon(&polygon, DEFAULT_THRESHOLD).unwrap();
+        assert!(corner_cosines(&squared).iter().all(|cos| cos.abs() < 1e-9));
+        let Geometry::Polygon(squared, _) = squared else {
+            panic!("expected a polygon");
+        };
+        assert_eq!(squared.interiors().len(), 1);
+    }
+
+    #[test]
+    fn test_keeps_other_angles() {
+        // Too far from right angles to be squared
+        let triangle = geometry_from_wkt("POLYGON((0 0, 10 0, 5 8, 0 0))").unwrap();
+        let result = orthogonalize_polygon(&triangle, DEFAULT_THRESHOLD).unwrap();
+        assert_eq!(corner_cosines(&result).len(), 3);
+        // Squared already
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        assert_eq!(
+            orthogonalize_polygon(&square, DEFAULT_THRESHOLD).unwrap(),
+            square
+        );
+        // A threshold of zero only squares what is square already
+        let skewed = geometry_from_wkt("POLYGON((0 0, 4 0.1, 4 4, 0 4, 0 0))").unwrap();
+        assert_eq!(orthogonalize_polygon(&skewed, 0.0).unwrap(), skewed);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(orthogonalize_polygon(&line, DEFAULT_THRESHOLD).is_err());
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        assert!(orthogonalize_polygon(&square, 45.0).is_err());
+        assert!(orthogonalize_polygon(&square, -1.0).is_err());
+        assert!(orthogonalize_polygon(&square, f64::NAN).is_err());
+    }
+}
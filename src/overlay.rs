@@ -0,0 +1,259 @@
+use crate::functions::make_valid;
+use crate::geometry::Geometry;
+use crate::guc;
+use crate::precision::{snap_to_grid, Grid};
+use crate::serialization::{self, GeometryHeader};
+use crate::stats::{self, Counter};
+use crate::utils::RostGisError;
+use geo::CoordsIter;
+use geo_types::MultiPolygon;
+use pgrx::prelude::*;
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+
+// Overlay robustness fallback
+//
+// Polygon overlays (union, intersection, difference) run in floating point
+// and can fail on nearly coincident edges or vertices: the overlay panics, or
+// produces non-finite coordinates. Instead of failing the whole query, a
+// failed overlay is retried on its inputs snapped to successively coarser
+// grids, like the snap-rounding fallback of GEOS OverlayNG. The grids are
+// powers of ten relative to the magnitude of the coordinates, so the first
+// one only moves vertices by rounding noise:
+//
+//   magnitude 1e6 (projected metres)   grids 1e-6, 1e-3, 1
+//
+// Each fallback is reported with a NOTICE by the SQL function that ran the
+// overlay. With rostgis.overlay_fallback off, or when every grid fails, the
+// overlay errors.
+//
+// Stored inputs are checked for validity and repaired first, as an overlay
+// of invalid polygons is meaningless. Geometries flagged known-valid by
+// ST_MakeValid skip the check unless rostgis.trust_valid_flag is off.
+
+/// Grid sizes tried, relative to the largest absolute coordinate and rounded
+/// up to a power of ten
+pub const SNAP_PRECISIONS: [f64; 3] = [1e-12, 1e-9, 1e-6];
+
+thread_local! {
+    /// Fallbacks taken since they were last reported
+    static FALLBACKS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Message of a caught panic
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "overlay panicked".to_string()
+    }
+}
+
+/// Run an overlay once, turning a panic or a non-finite result into an error
+fn attempt<F>(inputs: &[MultiPolygon<f64>], op: &F) -> Result<MultiPolygon<f64>, String>
+where
+    F: Fn(&[MultiPolygon<f64>]) -> MultiPolygon<f64>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(|| op(inputs)))
+        .map_err(|payload| panic_message(payload.as_ref()))?;
+    if result
+        .coords_iter()
+        .all(|coord| coord.x.is_finite() && coord.y.is_finite())
+    {
+        Ok(result)
+    } else {
+        Err("non-finite coordinates in the result".to_string())
+    }
+}
+
+/// Snap polygons to a grid, dropping those that collapse
+fn snap_polygons(polygons: &MultiPolygon<f64>, grid: &Grid) -> MultiPolygon<f64> {
+    match snap_to_grid(&Geometry::MultiPolygon(polygons.clone(), 0), grid) {
+        Geometry::MultiPolygon(snapped, _) => snapped,
+        _ => unreachable!(),
+    }
+}
+
+/// Run a polygon overlay, retrying it on snapped inputs when it fails and
+/// `fallback` is set
+pub fn overlay_with_fallback<F>(
+    operation: &str,
+    inputs: &[MultiPolygon<f64>],
+    op: F,
+    fallback: bool,
+) -> Result<MultiPolygon<f64>, RostGisError>
+where
+    F: Fn(&[MultiPolygon<f64>]) -> MultiPolygon<f64>,
+{
+    let failure = match attempt(inputs, &op) {
+        Ok(result) => return Ok(result),
+        Err(failure) => failure,
+    };
+    if !fallback {
+        return Err(RostGisError::new(&format!(
+            "{}: overlay failed: {}",
+            operation, failure
+        )));
+    }
+
+    let magnitude = inputs
+        .iter()
+        .flat_map(|polygons| polygons.coords_iter())
+        .filter(|coord| coord.x.is_finite() && coord.y.is_finite())
+        .fold(1.0f64, |magnitude, coord| {
+            magnitude.max(coord.x.abs()).max(coord.y.abs())
+        });
+    let grid_size = |precision: f64| 10f64.powf((magnitude * precision).log10().ceil());
+    for precision in SNAP_PRECISIONS {
+        let size = grid_size(precision);
+        let grid = Grid::uniform(size)?;
+        let snapped: Vec<MultiPolygon<f64>> = inputs
+            .iter()
+            .map(|polygons| snap_polygons(polygons, &grid))
+            .collect();
+        if let Ok(result) = attempt(&snapped, &op) {
+            let message = format!(
+                "{}: overlay failed ({}), retried on inputs snapped to a grid of {:e}",
+                operation, failure, size
+            );
+            FALLBACKS.with(|fallbacks| {
+                let mut fallbacks = fallbacks.borrow_mut();
+                if !fallbacks.contains(&message) {
+                    fallbacks.push(message);
+                }
+            });
+            return Ok(result);
+        }
+    }
+    Err(RostGisError::new(&format!(
+        "{}: overlay failed: {}, also on inputs snapped to a grid of {:e}",
+        operation,
+        failure,
+        grid_size(SNAP_PRECISIONS[SNAP_PRECISIONS.len() - 1])
+    )))
+}
+
+/// Run a polygon overlay with the fallback of rostgis.overlay_fallback
+pub fn overlay<F>(
+    operation: &str,
+    inputs: &[MultiPolygon<f64>],
+    op: F,
+) -> Result<MultiPolygon<f64>, RostGisError>
+where
+    F: Fn(&[MultiPolygon<f64>]) -> MultiPolygon<f64>,
+{
+    overlay_with_fallback(operation, inputs, op, guc::OVERLAY_FALLBACK.get())
+}
+
+/// Decode the stored input of an overlay, repairing it when it is invalid
+pub fn overlay_input(datum: &[u8]) -> Result<Geometry, RostGisError> {
+    let trusted = guc::TRUST_VALID_FLAG.get() && GeometryHeader::peek(datum)?.is_known_valid();
+    let geom = serialization::deserialize(datum)?;
+    if trusted {
+        stats::count(Counter::ValidFlagHits);
+        return Ok(geom);
+    }
+    Ok(make_valid(&geom))
+}
+
+/// Fallbacks taken since the last call, each described once
+pub fn take_fallbacks() -> Vec<String> {
+    FALLBACKS.with(|fallbacks| std::mem::take(&mut *fallbacks.borrow_mut()))
+}
+
+/// Report the fallbacks taken by the current SQL function as NOTICEs
+pub fn report_fallbacks() {
+    for message in take_fallbacks() {
+        notice!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::{Area, BooleanOps};
+    use geo_types::{polygon, Polygon};
+
+    /// Square with its lower left corner moved by `offset`
+    fn square(x: f64, offset: f64) -> MultiPolygon<f64> {
+        let square: Polygon<f64> = polygon![
+            (x: x + offset, y: offset),
+            (x: x + 2.0, y: 0.0),
+            (x: x + 2.0, y: 2.0),
+            (x: x, y: 2.0),
+            (x: x + offset, y: offset),
+        ];
+        MultiPolygon(vec![square])
+    }
+
+    /// Union that fails on a corner moved by rounding noise
+    fn fragile_union(inputs: &[MultiPolygon<f64>]) -> MultiPolygon<f64> {
+        let noisy = inputs
+            .iter()
+            .flat_map(|polygons| polygons.coords_iter())
+            .any(|coord| coord.y == 1e-13);
+        if noisy {
+            panic!("found non-noded intersection");
+        }
+        inputs[0].union(&inputs[1])
+    }
+
+    #[test]
+    fn test_overlay_input() {
+        let bowtie = crate::functions::geometry_from_wkt("POLYGON((0 0,2 2,2 0,0 2,0 0))").unwrap();
+        let repaired = overlay_input(&serialization::serialize(&bowtie)).unwrap();
+        assert!(crate::functions::geometry_is_valid(&repaired));
+        assert_eq!(repaired.geometry_type(), "ST_MultiPolygon");
+
+        // A flagged input is taken as it is, without the check
+        let flagged = serialization::serialize_valid(&bowtie);
+        assert_eq!(overlay_input(&flagged).unwrap(), bowtie);
+    }
+
+    #[test]
+    fn test_overlay_without_failure() {
+        take_fallbacks();
+        let union = overlay_with_fallback(
+            "ST_Union",
+            &[square(0.0, 0.0), square(1.0, 0.0)],
+            fragile_union,
+            true,
+        )
+        .unwrap();
+        assert!((union.unsigned_area() - 6.0).abs() < 1e-12);
+        assert!(take_fallbacks().is_empty());
+    }
+
+    #[test]
+    fn test_overlay_fallback() {
+        take_fallbacks();
+        let inputs = [square(0.0, 1e-13), square(1.0, 0.0)];
+        let union = overlay_with_fallback("ST_Union", &inputs, fragile_union, true).unwrap();
+        assert!((union.unsigned_area() - 6.0).abs() < 1e-9);
+        let fallbacks = take_fallbacks();
+        assert_eq!(
+            fallbacks,
+            vec!["ST_Union: overlay failed (found non-noded intersection), \
+                  retried on inputs snapped to a grid of 1e-11"
+                .to_string()]
+        );
+
+        // Disabled, the failure is an error
+        let error = overlay_with_fallback("ST_Union", &inputs, fragile_union, false).unwrap_err();
+        assert!(error.message.contains("found non-noded intersection"));
+        assert!(take_fallbacks().is_empty());
+    }
+
+    #[test]
+    fn test_overlay_fallback_exhausted() {
+        let always = |_: &[MultiPolygon<f64>]| -> MultiPolygon<f64> { panic!("no way") };
+        assert!(overlay_with_fallback("ST_Buffer", &[square(0.0, 0.0)], always, true).is_err());
+        let non_finite = |_: &[MultiPolygon<f64>]| square(f64::NAN, 0.0);
+        let error =
+            overlay_with_fallback("ST_Buffer", &[square(0.0, 0.0)], non_finite, true).unwrap_err();
+        assert!(error.message.contains("non-finite"));
+    }
+}
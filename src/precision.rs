@@ -0,0 +1,321 @@
+use crate::geometry::Geometry;
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use geo_types::{Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+/// Grid used to snap coordinates; a size of zero leaves that axis untouched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub size_x: f64,
+    pub size_y: f64,
+}
+
+impl Grid {
+    pub fn new(
+        origin_x: f64,
+        origin_y: f64,
+        size_x: f64,
+        size_y: f64,
+    ) -> Result<Self, RostGisError> {
+        if size_x.is_nan() || size_y.is_nan() || size_x < 0.0 || size_y < 0.0 {
+            return Err(RostGisError::new("Grid size must be zero or positive"));
+        }
+        Ok(Grid {
+            origin_x,
+            origin_y,
+            size_x,
+            size_y,
+        })
+    }
+
+    /// Square grid anchored at the origin
+    pub fn uniform(size: f64) -> Result<Self, RostGisError> {
+        Grid::new(0.0, 0.0, size, size)
+    }
+
+    fn snap_value(value: f64, origin: f64, size: f64) -> f64 {
+        if size == 0.0 {
+            value
+        } else {
+            ((value - origin) / size).round() * size + origin
+        }
+    }
+
+    fn snap(&self, coord: &Coord<f64>) -> Coord<f64> {
+        Coord {
+            x: Self::snap_value(coord.x, self.origin_x, self.size_x),
+            y: Self::snap_value(coord.y, self.origin_y, self.size_y),
+        }
+    }
+
+    /// Snap a coordinate sequence, dropping consecutive duplicates
+    fn snap_line(&self, line: &LineString<f64>) -> LineString<f64> {
+        let mut coords: Vec<Coord<f64>> = Vec::with_capacity(line.0.len());
+        for coord in &line.0 {
+            let snapped = self.snap(coord);
+            if coords.last() != Some(&snapped) {
+                coords.push(snapped);
+            }
+        }
+        LineString(coords)
+    }
+
+    /// Snap a polygon; collapsed holes are dropped, a collapsed shell
+    /// collapses the whole polygon
+    fn snap_polygon(&self, polygon: &Polygon<f64>) -> Option<Polygon<f64>> {
+        let exterior = self.snap_line(polygon.exterior());
+        if exterior.0.len() < 4 {
+            return None;
+        }
+        let interiors = polygon
+            .interiors()
+            .iter()
+            .map(|ring| self.snap_line(ring))
+            .filter(|ring| ring.0.len() >= 4)
+            .collect();
+        Some(Polygon::new(exterior, interiors))
+    }
+}
+
+/// Snap every vertex of a geometry to a grid (ST_SnapToGrid)
+///
+/// Consecutive duplicate vertices are removed. Lines reduced to fewer than two
+/// vertices and rings reduced to fewer than four are collapsed: they are
+/// dropped from multi-geometries, or become empty otherwise.
+pub fn snap_to_grid(geom: &Geometry, grid: &Grid) -> Geometry {
+    let srid = geom.srid();
+    match geom {
+        Geometry::Zm(base, _) => snap_to_grid(base, grid),
+        Geometry::Point(point, _) => Geometry::Point(Point(grid.snap(&point.0)), srid),
+        Geometry::LineString(linestring, _) => {
+            let snapped = grid.snap_line(linestring);
+            if snapped.0.len() < 2 {
+                Geometry::LineString(LineString(vec![]), srid)
+            } else {
+                Geometry::LineString(snapped, srid)
+            }
+        }
+        Geometry::Polygon(polygon, _) => Geometry::Polygon(
+            grid.snap_polygon(polygon)
+                .unwrap_or_else(|| Polygon::new(LineString(vec![]), vec![])),
+            srid,
+        ),
+        Geometry::MultiPoint(multipoint, _) => {
+            let mut points: Vec<Point<f64>> = Vec::with_capacity(multipoint.0.len());
+            for point in &multipoint.0 {
+                let snapped = Point(grid.snap(&point.0));
+                if !points.contains(&snapped) {
+                    points.push(snapped);
+                }
+            }
+            Geometry::MultiPoint(MultiPoint(points), srid)
+        }
+        Geometry::MultiLineString(multilinestring, _) => Geometry::MultiLineString(
+            MultiLineString(
+                multilinestring
+                    .0
+                    .iter()
+                    .map(|line| grid.snap_line(line))
+                    .filter(|line| line.0.len() >= 2)
+                    .collect(),
+            ),
+            srid,
+        ),
+        Geometry::MultiPolygon(multipolygon, _) => Geometry::MultiPolygon(
+            MultiPolygon(
+                multipolygon
+                    .0
+                    .iter()
+                    .filter_map(|polygon| grid.snap_polygon(polygon))
+                    .collect(),
+            ),
+            srid,
+        ),
+        Geometry::GeometryCollection(geometries, _) => Geometry::GeometryCollection(
+            geometries
+                .iter()
+                .map(|child| snap_to_grid(child, grid))
+                .filter(|child| matches!(child, Geometry::Point(_, _)) || !child.is_empty())
+                .collect(),
+            srid,
+        ),
+    }
+}
+
+/// Reduce coordinate precision to a grid size (ST_ReducePrecision)
+///
+/// Snaps to a uniform grid anchored at the origin. Unlike the GEOS
+/// implementation topology is not repaired, so results should be checked
+/// with ST_IsValid.
+pub fn reduce_precision(geom: &Geometry, grid_size: f64) -> Result<Geometry, RostGisError> {
+    Ok(snap_to_grid(geom, &Grid::uniform(grid_size)?))
+}
+
+/// Apply ST_SnapToGrid or ST_ReducePrecision to every row of a table
+///
+/// Rows are processed in batches. A snapped geometry is only written back when
+/// it changed, did not collapse to empty and is still valid (or the original
+/// was already invalid); skipped rows are counted in the report. With
+/// `dry_run` the table is left untouched and only the report is produced.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn rostgis_snap_table(
+    table_name: &str,
+    geom_column: &str,
+    grid_size: f64,
+    method: default!(&str, "'snaptogrid'"),
+    dry_run: default!(bool, false),
+    batch_size: default!(i32, 10000),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(rows_scanned, i64),
+            name!(rows_changed, i64),
+            name!(rows_invalidated, i64),
+            name!(rows_collapsed, i64),
+            name!(rows_updated, i64),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    if batch_size <= 0 {
+        return Err("batch_size must be positive".into());
+    }
+    Grid::uniform(grid_size)?;
+    let snap_call = match method.to_lowercase().as_str() {
+        "snaptogrid" => "st_snaptogrid",
+        "reduceprecision" => "st_reduceprecision",
+        _ => return Err("method must be 'snaptogrid' or 'reduceprecision'".into()),
+    };
+
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+    let schema = extension_schema()?;
+
+    // Number the rows once so batches are stable while the table is updated
+    Spi::run("DROP TABLE IF EXISTS pg_temp.__rostgis_snap")?;
+    Spi::run(&format!(
+        "CREATE TEMP TABLE __rostgis_snap AS
+         SELECT ctid AS row_ctid, row_number() OVER () AS __rostgis_ord
+         FROM {relation} WHERE {column} IS NOT NULL"
+    ))?;
+    Spi::run("CREATE INDEX ON __rostgis_snap (__rostgis_ord)")?;
+
+    let total = Spi::get_one::<i64>("SELECT count(*) FROM __rostgis_snap")?.unwrap_or(0);
+
+    let (mut changed, mut invalidated, mut collapsed, mut updated) = (0i64, 0i64, 0i64, 0i64);
+    let mut scanned: i64 = 0;
+
+    while scanned < total {
+        let upper = scanned + batch_size as i64;
+        let batch = format!(
+            "SELECT t.ctid AS row_ctid, t.{column} AS before,
+                    {schema}.{snap_call}(t.{column}, {grid_size}) AS after
+             FROM {relation} t
+             JOIN __rostgis_snap s ON t.ctid = s.row_ctid
+             WHERE s.__rostgis_ord > {scanned} AND s.__rostgis_ord <= {upper}"
+        );
+
+        let counts = Spi::get_three::<i64, i64, i64>(&format!(
+            "SELECT
+                 count(*) FILTER (WHERE NOT {schema}.st_equals(before, after)),
+                 count(*) FILTER (WHERE NOT {schema}.st_equals(before, after)
+                     AND NOT {schema}.st_isempty(after)
+                     AND {schema}.st_isvalid(before) AND NOT {schema}.st_isvalid(after)),
+                 count(*) FILTER (WHERE NOT {schema}.st_isempty(before)
+                     AND {schema}.st_isempty(after))
+             FROM ({batch}) b"
+        ))?;
+        let counts = (
+            counts.0.unwrap_or(0),
+            counts.1.unwrap_or(0),
+            counts.2.unwrap_or(0),
+        );
+        changed += counts.0;
+        invalidated += counts.1;
+        collapsed += counts.2;
+
+        if !dry_run {
+            updated += Spi::get_one::<i64>(&format!(
+                "WITH written AS (
+                     UPDATE {relation} t SET {column} = b.after
+                     FROM ({batch}) b
+                     WHERE t.ctid = b.row_ctid
+                       AND NOT {schema}.st_equals(b.before, b.after)
+                       AND NOT {schema}.st_isempty(b.after)
+                       AND ({schema}.st_isvalid(b.after) OR NOT {schema}.st_isvalid(b.before))
+                     RETURNING 1
+                 )
+                 SELECT count(*) FROM written"
+            ))?
+            .unwrap_or(0);
+        }
+
+        scanned = upper.min(total);
+        notice!(
+            "rostgis_snap_table: processed {} of {} rows of {}",
+            scanned,
+            total,
+            relation
+        );
+    }
+
+    Spi::run("DROP TABLE __rostgis_snap")?;
+
+    Ok(TableIterator::once((
+        scanned,
+        changed,
+        invalidated,
+        collapsed,
+        updated,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_snap_point() {
+        let grid = Grid::uniform(0.5).unwrap();
+        assert_eq!(
+            snap_to_grid(&make_point(1.26, -0.74), &grid),
+            make_point(1.5, -0.5)
+        );
+
+        let offset = Grid::new(0.1, 0.0, 1.0, 0.0).unwrap();
+        assert_eq!(
+            snap_to_grid(&make_point(1.3, 7.77), &offset),
+            make_point(1.1, 7.77)
+        );
+    }
+
+    #[test]
+    fn test_snap_removes_duplicates_and_collapses() {
+        let grid = Grid::uniform(1.0).unwrap();
+        let line = geometry_from_wkt("LINESTRING(0 0, 0.1 0.1, 2 2)").unwrap();
+        assert_eq!(
+            snap_to_grid(&line, &grid),
+            geometry_from_wkt("LINESTRING(0 0, 2 2)").unwrap()
+        );
+
+        let sliver = geometry_from_wkt("POLYGON((0 0, 0.2 0, 0.2 0.2, 0 0))").unwrap();
+        assert!(snap_to_grid(&sliver, &grid).is_empty());
+    }
+
+    #[test]
+    fn test_reduce_precision() {
+        let polygon = geometry_from_wkt("POLYGON((0.01 0, 4 0.02, 4 4, 0 3.99, 0.01 0))").unwrap();
+        let reduced = reduce_precision(&polygon, 0.1).unwrap();
+        assert_eq!(
+            reduced,
+            geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap()
+        );
+        assert!(reduce_precision(&polygon, -1.0).is_err());
+    }
+}
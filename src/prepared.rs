@@ -0,0 +1,215 @@
+use crate::geometry::Geometry;
+use crate::serialization::deserialize;
+use crate::utils::RostGisError;
+use geo::{PreparedGeometry, Relate};
+use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
+
+// Prepared geometry caching for binary predicates
+//
+// In a nested-loop join one argument of ST_Intersects/ST_Contains is usually
+// the same geometry for many consecutive calls. Building the edge index of a
+// prepared geometry costs more than a single unprepared test, so a geometry
+// is only prepared once it has been seen twice in a row in the same argument
+// position. Arguments are compared by their stored bytes, before they are
+// decoded, so a cached argument is not deserialized again on every call. The
+// cache lives in fn_extra and is freed with the function's memory context at
+// the end of the query.
+
+/// Argument position of the cached geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachedArgument {
+    First,
+    Second,
+}
+
+struct PreparedEntry {
+    argument: CachedArgument,
+    datum: Vec<u8>,
+    geometry: Geometry,
+    prepared: PreparedGeometry<'static, geo::Geometry<f64>>,
+}
+
+/// Per-call-site cache of a prepared geometry
+#[derive(Default)]
+pub struct PreparedCache {
+    entry: Option<PreparedEntry>,
+    last_first: Option<Vec<u8>>,
+    last_second: Option<Vec<u8>>,
+}
+
+impl PreparedCache {
+    /// Argument position currently prepared, if any
+    pub fn cached_argument(&self) -> Option<CachedArgument> {
+        self.entry.as_ref().map(|entry| entry.argument)
+    }
+
+    /// Return the prepared entry matching one of the stored arguments,
+    /// preparing a geometry that repeats from the previous call
+    fn lookup(
+        &mut self,
+        datum1: &[u8],
+        datum2: &[u8],
+    ) -> Result<Option<&PreparedEntry>, RostGisError> {
+        let hit = match &self.entry {
+            Some(entry) if entry.argument == CachedArgument::First => entry.datum == datum1,
+            Some(entry) => entry.datum == datum2,
+            None => false,
+        };
+
+        if !hit {
+            let repeated = if self.last_first.as_deref() == Some(datum1) {
+                Some((CachedArgument::First, datum1))
+            } else if self.last_second.as_deref() == Some(datum2) {
+                Some((CachedArgument::Second, datum2))
+            } else {
+                None
+            };
+
+            match repeated {
+                Some((argument, datum)) => {
+                    let geometry = deserialize(datum)?;
+                    self.entry = Some(PreparedEntry {
+                        argument,
+                        datum: datum.to_vec(),
+                        prepared: PreparedGeometry::from(geometry.to_geo()),
+                        geometry,
+                    });
+                    self.last_first = None;
+                    self.last_second = None;
+                }
+                None => {
+                    self.last_first = Some(datum1.to_vec());
+                    self.last_second = Some(datum2.to_vec());
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(self.entry.as_ref())
+    }
+
+    /// ST_Intersects of two stored geometries, with the bounding box
+    /// pre-filter and a prepared side
+    pub fn intersects(&mut self, datum1: &[u8], datum2: &[u8]) -> Result<bool, RostGisError> {
+        Ok(match self.lookup(datum1, datum2)? {
+            Some(entry) if entry.argument == CachedArgument::First => {
+                let geom2 = deserialize(datum2)?;
+                entry.geometry.bbox_overlaps(&geom2)
+                    && entry.prepared.relate(&geom2.to_geo()).is_intersects()
+            }
+            Some(entry) => {
+                let geom1 = deserialize(datum1)?;
+                geom1.bbox_overlaps(&entry.geometry)
+                    && entry.prepared.relate(&geom1.to_geo()).is_intersects()
+            }
+            None => {
+                crate::functions::geometries_intersect(&deserialize(datum1)?, &deserialize(datum2)?)
+            }
+        })
+    }
+
+    /// ST_Contains of two stored geometries, with the bounding box
+    /// pre-filter and a prepared side
+    pub fn contains(&mut self, datum1: &[u8], datum2: &[u8]) -> Result<bool, RostGisError> {
+        Ok(match self.lookup(datum1, datum2)? {
+            Some(entry) if entry.argument == CachedArgument::First => {
+                let geom2 = deserialize(datum2)?;
+                entry.geometry.bbox_contains(&geom2)
+                    && entry.prepared.relate(&geom2.to_geo()).is_contains()
+            }
+            Some(entry) => {
+                let geom1 = deserialize(datum1)?;
+                geom1.bbox_contains(&entry.geometry)
+                    && entry.prepared.relate(&geom1.to_geo()).is_within()
+            }
+            None => {
+                let (geom1, geom2) = (deserialize(datum1)?, deserialize(datum2)?);
+                geom1.bbox_contains(&geom2) && geom1.to_geo().relate(&geom2.to_geo()).is_contains()
+            }
+        })
+    }
+}
+
+/// Run a closure with the prepared geometry cache of the calling function
+///
+/// # Safety
+/// `fcinfo` must be the call info PostgreSQL passed to the current function.
+pub unsafe fn with_prepared_cache<R>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    f: impl FnOnce(&mut PreparedCache) -> R,
+) -> R {
+    let flinfo = (*fcinfo).flinfo;
+    if (*flinfo).fn_extra.is_null() {
+        let cache = PgMemoryContexts::For((*flinfo).fn_mcxt)
+            .leak_and_drop_on_delete(PreparedCache::default());
+        (*flinfo).fn_extra = cache as *mut std::ffi::c_void;
+    }
+    f(&mut *((*flinfo).fn_extra as *mut PreparedCache))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+    use crate::serialization::serialize;
+
+    fn stored(wkt: &str) -> Vec<u8> {
+        serialize(&geometry_from_wkt(wkt).unwrap())
+    }
+
+    fn point(x: f64, y: f64) -> Vec<u8> {
+        serialize(&make_point(x, y))
+    }
+
+    #[test]
+    fn test_prepares_repeated_argument() {
+        let polygon = stored("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))");
+        let mut cache = PreparedCache::default();
+
+        assert!(cache.intersects(&polygon, &point(5.0, 1.0)).unwrap());
+        assert_eq!(cache.cached_argument(), None);
+
+        // Second sighting of the polygon prepares it
+        assert!(!cache.intersects(&polygon, &point(5.0, 8.0)).unwrap());
+        assert_eq!(cache.cached_argument(), Some(CachedArgument::First));
+        assert!(cache.intersects(&polygon, &point(10.0, 5.0)).unwrap());
+        assert!(!cache.intersects(&polygon, &point(50.0, 5.0)).unwrap());
+
+        // The same geometry stored again is recognised by its bytes
+        let copy = stored("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))");
+        assert!(cache.intersects(&copy, &point(5.0, 1.0)).unwrap());
+        assert_eq!(cache.cached_argument(), Some(CachedArgument::First));
+    }
+
+    #[test]
+    fn test_prepares_second_argument() {
+        let square = stored("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))");
+        let small = stored("POLYGON((4 4, 6 4, 6 6, 4 6, 4 4))");
+        let centre = point(5.0, 5.0);
+        let mut cache = PreparedCache::default();
+
+        assert!(cache.contains(&square, &centre).unwrap());
+        assert!(cache.contains(&small, &centre).unwrap());
+        assert_eq!(cache.cached_argument(), Some(CachedArgument::Second));
+
+        // A prepared second argument answers through the inverse relation
+        let line = stored("LINESTRING(0 0, 10 10)");
+        assert!(cache.contains(&line, &centre).unwrap());
+        let corner = point(0.0, 0.0);
+        assert!(!cache.contains(&line, &corner).unwrap());
+        assert!(!cache.contains(&small, &corner).unwrap());
+    }
+
+    #[test]
+    fn test_contains_is_exact() {
+        // Inside the bounding box but in the notch of the polygon
+        let concave = stored("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))");
+        let mut cache = PreparedCache::default();
+        for _ in 0..3 {
+            assert!(!cache.contains(&concave, &point(5.0, 8.0)).unwrap());
+            assert!(cache.contains(&concave, &point(5.0, 1.0)).unwrap());
+        }
+        assert!(cache.contains(&concave, b"not a geometry").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::functions::normalize_radians;
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo_types::LineString;
+use pgrx::prelude::*;
+
+// Line profiles (ST_Profile)
+//
+// One row per vertex of a line with its chainage, the distance along the
+// line from its start, which is what elevation and chainage charts plot:
+//
+//   SELECT distance, z FROM ST_Profile((SELECT geom FROM trails WHERE id = 7));
+//   SELECT t.id, p.* FROM trails t, ST_Profile(t.geom) p;
+//
+// segment_length is the length of the segment ending at the vertex and
+// bearing its azimuth in radians clockwise from north, as ST_Azimuth; both
+// describe the way into the vertex, so the first vertex has a length of 0
+// and no bearing, and so does a vertex repeating the one before it.
+// Distances are in the units of the coordinates, as ST_Length. The parts of
+// a multi-line are numbered from 1 and chained, the distance continuing
+// from the end of one part at the start of the next without the gap
+// between them. z is the Z of the vertex, NULL for a line without Z.
+
+/// A vertex of a line profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileVertex {
+    /// Part of a multi-line, from 1
+    pub part: i32,
+    /// Vertex within the part, from 1
+    pub vertex: i32,
+    pub x: f64,
+    pub y: f64,
+    pub z: Option<f64>,
+    /// Length of the segment ending at the vertex
+    pub segment_length: f64,
+    /// Distance along the line from its start
+    pub distance: f64,
+    /// Azimuth of the segment ending at the vertex, in radians
+    pub bearing: Option<f64>,
+}
+
+/// Profile of a line or multi-line
+pub fn profile(geom: &Geometry) -> Result<Vec<ProfileVertex>, RostGisError> {
+    let parts: Vec<&LineString<f64>> = match geom.xy() {
+        Geometry::LineString(line, _) => vec![line],
+        Geometry::MultiLineString(lines, _) => lines.iter().collect(),
+        _ => {
+            return Err(RostGisError::new(&format!(
+                "ST_Profile needs a line, got {}",
+                geom.geometry_type()
+            )))
+        }
+    };
+
+    let zs = geom.ordinates().and_then(|ordinates| ordinates.z.as_ref());
+    let mut vertices = Vec::new();
+    let mut distance = 0.0;
+    for (part, line) in parts.iter().enumerate() {
+        let mut previous = None;
+        for (vertex, coord) in line.coords().enumerate() {
+            let (segment_length, bearing) = match previous {
+                Some(previous) => {
+                    let delta = *coord - previous;
+                    let bearing = (delta.x != 0.0 || delta.y != 0.0)
+                        .then(|| normalize_radians(delta.x.atan2(delta.y)));
+                    (delta.x.hypot(delta.y), bearing)
+                }
+                None => (0.0, None),
+            };
+            distance += segment_length;
+            vertices.push(ProfileVertex {
+                part: part as i32 + 1,
+                vertex: vertex as i32 + 1,
+                x: coord.x,
+                y: coord.y,
+                z: zs.map(|zs| zs[vertices.len()]),
+                segment_length,
+                distance,
+                bearing,
+            });
+            previous = Some(*coord);
+        }
+    }
+    Ok(vertices)
+}
+
+/// PostgreSQL function returning the vertices of a line with their distance
+/// along it, segment length and bearing
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_profile(
+    line: Geometry,
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(part, i32),
+            name!(vertex, i32),
+            name!(x, f64),
+            name!(y, f64),
+            name!(z, Option<f64>),
+            name!(segment_length, f64),
+            name!(distance, f64),
+            name!(bearing, Option<f64>),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    Ok(TableIterator::new(profile(&line)?.into_iter().map(|v| {
+        (
+            v.part,
+            v.vertex,
+            v.x,
+            v.y,
+            v.z,
+            v.segment_length,
+            v.distance,
+            v.bearing,
+        )
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_profile_of_line() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 0 3, 4 3, 4 3)").unwrap();
+        let vertices = profile(&line).unwrap();
+        let distances: Vec<f64> = vertices.iter().map(|v| v.distance).collect();
+        assert_eq!(distances, vec![0.0, 3.0, 7.0, 7.0]);
+        let lengths: Vec<f64> = vertices.iter().map(|v| v.segment_length).collect();
+        assert_eq!(lengths, vec![0.0, 3.0, 4.0, 0.0]);
+        let bearings: Vec<Option<f64>> = vertices.iter().map(|v| v.bearing).collect();
+        assert_eq!(bearings, vec![None, Some(0.0), Some(PI / 2.0), None]);
+        assert_eq!(
+            (vertices[2].x, vertices[2].y, vertices[2].z),
+            (4.0, 3.0, None)
+        );
+    }
+
+    #[test]
+    fn test_profile_of_multiline() {
+        let lines = geometry_from_wkt("MULTILINESTRING((0 0, 2 0), (10 10, 10 8))").unwrap();
+        let vertices = profile(&lines).unwrap();
+        let rows: Vec<(i32, i32, f64, Option<f64>)> = vertices
+            .iter()
+            .map(|v| (v.part, v.vertex, v.distance, v.bearing))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1, 1, 0.0, None),
+                (1, 2, 2.0, Some(PI / 2.0)),
+                (2, 1, 2.0, None),
+                (2, 2, 4.0, Some(PI)),
+            ]
+        );
+        let with_z =
+            geometry_from_wkt("MULTILINESTRING Z ((0 0 100, 2 0 110), (10 10 90, 10 8 95))")
+                .unwrap();
+        let zs: Vec<Option<f64>> = profile(&with_z).unwrap().iter().map(|v| v.z).collect();
+        assert_eq!(zs, vec![Some(100.0), Some(110.0), Some(90.0), Some(95.0)]);
+        assert!(profile(&geometry_from_wkt("POINT(0 0)").unwrap()).is_err());
+        assert!(profile(&geometry_from_wkt("LINESTRING EMPTY").unwrap())
+            .unwrap()
+            .is_empty());
+    }
+}
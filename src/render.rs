@@ -0,0 +1,510 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo_types::{Coord, LineString, Polygon};
+use pgrx::prelude::*;
+use serde_json::Value;
+
+// Quick-look rendering (rostgis_render)
+//
+// Geometries are drawn onto an RGBA canvas scaled to their common extent,
+// keeping the aspect ratio: polygons are filled with the even-odd rule,
+// then lines and polygon rings are stroked, then points drawn as discs.
+// Each layer is rasterized into a coverage mask first and blended once, so
+// overlapping features and segment joins do not darken translucent colours.
+//
+// The canvas is written as a PNG with the png crate.
+
+/// Largest width or height of a rendered image, in pixels
+pub const MAX_IMAGE_SIZE: i32 = 4096;
+
+/// An RGBA colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba(pub [u8; 4]);
+
+impl Rgba {
+    /// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`
+    pub fn parse(text: &str) -> Result<Self, RostGisError> {
+        let invalid = || RostGisError::new(&format!("invalid colour '{}'", text));
+        let hex = text.strip_prefix('#').ok_or_else(invalid)?;
+        let digits: Vec<u8> = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+            6 | 8 => hex.to_string(),
+            _ => return Err(invalid()),
+        }
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect::<Result<_, _>>()?;
+        Ok(Rgba([
+            digits[0],
+            digits[1],
+            digits[2],
+            digits.get(3).copied().unwrap_or(255),
+        ]))
+    }
+}
+
+/// Colours and sizes used by rostgis_render
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub background: Rgba,
+    pub fill: Rgba,
+    pub stroke: Rgba,
+    /// Line width in pixels
+    pub stroke_width: f64,
+    pub point_color: Rgba,
+    /// Point disc radius in pixels
+    pub point_radius: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            background: Rgba([255, 255, 255, 0]),
+            fill: Rgba([51, 136, 255, 102]),
+            stroke: Rgba([51, 136, 255, 255]),
+            stroke_width: 1.0,
+            point_color: Rgba([51, 136, 255, 255]),
+            point_radius: 3.0,
+        }
+    }
+}
+
+impl Style {
+    /// Style from a JSON object; missing keys keep their default
+    ///
+    /// Keys are `background`, `fill`, `stroke`, `point_color` (colours) and
+    /// `stroke_width`, `point_radius` (pixels).
+    pub fn from_json(json: &Value) -> Result<Self, RostGisError> {
+        let mut style = Style::default();
+        let Some(options) = json.as_object() else {
+            return Err(RostGisError::new("render style must be a JSON object"));
+        };
+        for (key, value) in options {
+            let colour = || match value.as_str() {
+                Some(text) => Rgba::parse(text),
+                None => Err(RostGisError::new(&format!(
+                    "style {} must be a string",
+                    key
+                ))),
+            };
+            let size = || match value.as_f64() {
+                Some(size) if size.is_finite() && (0.0..=100.0).contains(&size) => Ok(size),
+                _ => Err(RostGisError::new(&format!(
+                    "style {} must be a number between 0 and 100",
+                    key
+                ))),
+            };
+            match key.as_str() {
+                "background" => style.background = colour()?,
+                "fill" => style.fill = colour()?,
+                "stroke" => style.stroke = colour()?,
+                "point_color" => style.point_color = colour()?,
+                "stroke_width" => style.stroke_width = size()?,
+                "point_radius" => style.point_radius = size()?,
+                _ => {
+                    return Err(RostGisError::new(&format!(
+                        "unknown render style key '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(style)
+    }
+}
+
+/// Pixels covered by one layer of the image
+struct Mask {
+    width: usize,
+    height: usize,
+    covered: Vec<bool>,
+}
+
+impl Mask {
+    fn new(width: usize, height: usize) -> Self {
+        Mask {
+            width,
+            height,
+            covered: vec![false; width * height],
+        }
+    }
+
+    /// Pixel range whose centres may fall in [low, high] along an axis
+    fn span(low: f64, high: f64, size: usize) -> std::ops::Range<usize> {
+        let start = (low - 0.5).ceil().max(0.0) as usize;
+        let end = ((high - 0.5).floor() + 1.0).clamp(0.0, size as f64) as usize;
+        start..end.max(start)
+    }
+
+    /// Fill polygons with the even-odd rule, sampling at pixel centres
+    fn fill(&mut self, rings: &[Vec<Coord<f64>>]) {
+        let (min_y, max_y) = rings
+            .iter()
+            .flatten()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), c| {
+                (lo.min(c.y), hi.max(c.y))
+            });
+        let mut crossings = Vec::new();
+        for row in Self::span(min_y, max_y, self.height) {
+            let y = row as f64 + 0.5;
+            crossings.clear();
+            for ring in rings {
+                for edge in ring.windows(2) {
+                    let (a, b) = (edge[0], edge[1]);
+                    if (a.y <= y) != (b.y <= y) {
+                        crossings.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+                    }
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+            for pair in crossings.chunks_exact(2) {
+                for column in Self::span(pair[0], pair[1], self.width) {
+                    self.covered[row * self.width + column] = true;
+                }
+            }
+        }
+    }
+
+    /// Cover pixels whose centre is within `radius` of a segment
+    fn stroke_segment(&mut self, a: Coord<f64>, b: Coord<f64>, radius: f64) {
+        let ab = b - a;
+        let length_squared = ab.x * ab.x + ab.y * ab.y;
+        for row in Self::span(a.y.min(b.y) - radius, a.y.max(b.y) + radius, self.height) {
+            for column in Self::span(a.x.min(b.x) - radius, a.x.max(b.x) + radius, self.width) {
+                let p = Coord {
+                    x: column as f64 + 0.5,
+                    y: row as f64 + 0.5,
+                };
+                let t = if length_squared == 0.0 {
+                    0.0
+                } else {
+                    (((p.x - a.x) * ab.x + (p.y - a.y) * ab.y) / length_squared).clamp(0.0, 1.0)
+                };
+                let nearest = a + ab * t;
+                if (p.x - nearest.x).hypot(p.y - nearest.y) <= radius {
+                    self.covered[row * self.width + column] = true;
+                }
+            }
+        }
+    }
+
+    fn stroke(&mut self, line: &[Coord<f64>], width: f64) {
+        // Hairlines still cover the pixels they pass through
+        let radius = (width / 2.0).max(0.5);
+        match line {
+            [] => {}
+            [single] => self.stroke_segment(*single, *single, radius),
+            _ => {
+                for segment in line.windows(2) {
+                    self.stroke_segment(segment[0], segment[1], radius);
+                }
+            }
+        }
+    }
+}
+
+/// An RGBA image
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, background: Rgba) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![background.0; width * height],
+        }
+    }
+
+    /// Blend a colour over every covered pixel
+    fn paint(&mut self, mask: &Mask, colour: Rgba) {
+        let [r, g, b, a] = colour.0.map(f64::from);
+        let alpha = a / 255.0;
+        for (pixel, _) in self
+            .pixels
+            .iter_mut()
+            .zip(&mask.covered)
+            .filter(|(_, covered)| **covered)
+        {
+            let below = pixel.map(f64::from);
+            let below_alpha = below[3] / 255.0;
+            let out_alpha = alpha + below_alpha * (1.0 - alpha);
+            if out_alpha == 0.0 {
+                continue;
+            }
+            let blend = |top: f64, bottom: f64| {
+                ((top * alpha + bottom * below_alpha * (1.0 - alpha)) / out_alpha).round() as u8
+            };
+            *pixel = [
+                blend(r, below[0]),
+                blend(g, below[1]),
+                blend(b, below[2]),
+                (out_alpha * 255.0).round() as u8,
+            ];
+        }
+    }
+}
+
+/// Parts of the geometries by layer, in world coordinates
+#[derive(Default)]
+struct Layers {
+    polygons: Vec<Polygon<f64>>,
+    lines: Vec<LineString<f64>>,
+    points: Vec<Coord<f64>>,
+}
+
+impl Layers {
+    fn add(&mut self, geom: &Geometry) {
+        match geom {
+            Geometry::Zm(base, _) => self.add(base),
+            Geometry::Point(point, _) => {
+                if !geom.is_empty() {
+                    self.points.push(point.0);
+                }
+            }
+            Geometry::MultiPoint(points, _) => self.points.extend(
+                points
+                    .iter()
+                    .filter(|point| !point.x().is_nan())
+                    .map(|point| point.0),
+            ),
+            Geometry::LineString(line, _) => self.lines.push(line.clone()),
+            Geometry::MultiLineString(lines, _) => self.lines.extend(lines.iter().cloned()),
+            Geometry::Polygon(polygon, _) => self.polygons.push(polygon.clone()),
+            Geometry::MultiPolygon(polygons, _) => self.polygons.extend(polygons.iter().cloned()),
+            Geometry::GeometryCollection(members, _) => {
+                for member in members {
+                    self.add(member);
+                }
+            }
+        }
+    }
+
+    fn coords(&self) -> impl Iterator<Item = Coord<f64>> + '_ {
+        self.polygons
+            .iter()
+            .flat_map(|polygon| polygon.exterior().0.iter().copied())
+            .chain(self.lines.iter().flat_map(|line| line.0.iter().copied()))
+            .chain(self.points.iter().copied())
+    }
+}
+
+/// Draw geometries over their common extent
+pub fn render(geometries: &[Geometry], width: usize, height: usize, style: &Style) -> Canvas {
+    let mut layers = Layers::default();
+    for geom in geometries {
+        layers.add(geom);
+    }
+    let mut canvas = Canvas::new(width, height, style.background);
+
+    let (mut min, mut max) = (
+        Coord {
+            x: f64::INFINITY,
+            y: f64::INFINITY,
+        },
+        Coord {
+            x: f64::NEG_INFINITY,
+            y: f64::NEG_INFINITY,
+        },
+    );
+    for c in layers.coords() {
+        min = Coord {
+            x: min.x.min(c.x),
+            y: min.y.min(c.y),
+        };
+        max = Coord {
+            x: max.x.max(c.x),
+            y: max.y.max(c.y),
+        };
+    }
+    if min.x > max.x {
+        return canvas;
+    }
+
+    // Keep strokes and point discs at the edge of the extent in the image
+    let margin = 1.0 + (style.stroke_width / 2.0).max(style.point_radius);
+    let (room_x, room_y) = (
+        (width as f64 - 2.0 * margin).max(1.0),
+        (height as f64 - 2.0 * margin).max(1.0),
+    );
+    let (span_x, span_y) = (max.x - min.x, max.y - min.y);
+    let scale = match (span_x > 0.0, span_y > 0.0) {
+        (true, true) => (room_x / span_x).min(room_y / span_y),
+        (true, false) => room_x / span_x,
+        (false, true) => room_y / span_y,
+        (false, false) => 1.0,
+    };
+    let centre = (min + max) / 2.0;
+    let to_pixel = |c: Coord<f64>| Coord {
+        x: width as f64 / 2.0 + (c.x - centre.x) * scale,
+        y: height as f64 / 2.0 - (c.y - centre.y) * scale,
+    };
+    let pixels = |line: &LineString<f64>| -> Vec<Coord<f64>> {
+        line.0.iter().map(|&c| to_pixel(c)).collect()
+    };
+
+    let mut fill = Mask::new(width, height);
+    for polygon in &layers.polygons {
+        let rings: Vec<_> = std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .map(pixels)
+            .collect();
+        fill.fill(&rings);
+    }
+    canvas.paint(&fill, style.fill);
+
+    let mut stroke = Mask::new(width, height);
+    let rings = layers
+        .polygons
+        .iter()
+        .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()));
+    for line in rings.chain(&layers.lines) {
+        stroke.stroke(&pixels(line), style.stroke_width);
+    }
+    canvas.paint(&stroke, style.stroke);
+
+    let mut points = Mask::new(width, height);
+    for &point in &layers.points {
+        let centre = to_pixel(point);
+        points.stroke_segment(centre, centre, style.point_radius.max(0.5));
+    }
+    canvas.paint(&points, style.point_color);
+
+    canvas
+}
+
+/// Encode a canvas as an 8-bit RGBA PNG
+pub fn encode_png(canvas: &Canvas) -> Result<Vec<u8>, RostGisError> {
+    let encoding_error = |e: png::EncodingError| {
+        RostGisError::new(&format!("rostgis_render could not encode the image: {}", e))
+    };
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, canvas.width as u32, canvas.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    writer
+        .write_image_data(canvas.pixels.as_flattened())
+        .map_err(encoding_error)?;
+    writer.finish().map_err(encoding_error)?;
+    Ok(png)
+}
+
+/// PostgreSQL function rendering geometries to a PNG image over their
+/// extent, for quick previews. NULL elements are skipped.
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_render(
+    geometries: Array<'_, Geometry>,
+    width: i32,
+    height: i32,
+    style: default!(pgrx::JsonB, "'{}'"),
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    if !(1..=MAX_IMAGE_SIZE).contains(&width) || !(1..=MAX_IMAGE_SIZE).contains(&height) {
+        return Err(format!(
+            "rostgis_render image size must be between 1 and {} pixels",
+            MAX_IMAGE_SIZE
+        )
+        .into());
+    }
+    let style = Style::from_json(&style.0)?;
+    let geometries: Vec<Geometry> = geometries.iter().flatten().collect();
+    let canvas = render(&geometries, width as usize, height as usize, &style);
+    Ok(encode_png(&canvas)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn pixel(canvas: &Canvas, x: usize, y: usize) -> [u8; 4] {
+        canvas.pixels[y * canvas.width + x]
+    }
+
+    #[test]
+    fn test_parse_style() {
+        assert_eq!(Rgba::parse("#f00").unwrap(), Rgba([255, 0, 0, 255]));
+        assert_eq!(Rgba::parse("#10203040").unwrap(), Rgba([16, 32, 48, 64]));
+        assert!(Rgba::parse("red").is_err());
+        assert!(Rgba::parse("#12345").is_err());
+
+        let style =
+            Style::from_json(&serde_json::json!({"fill": "#00ff00", "stroke_width": 3})).unwrap();
+        assert_eq!(style.fill, Rgba([0, 255, 0, 255]));
+        assert_eq!(style.stroke_width, 3.0);
+        assert_eq!(style.stroke, Style::default().stroke);
+        assert!(Style::from_json(&serde_json::json!({"colour": "#fff"})).is_err());
+        assert!(Style::from_json(&serde_json::json!({"stroke_width": -1})).is_err());
+    }
+
+    #[test]
+    fn test_render_polygon() {
+        let style = Style {
+            background: Rgba([0, 0, 0, 255]),
+            fill: Rgba([255, 0, 0, 255]),
+            stroke: Rgba([0, 0, 255, 255]),
+            ..Style::default()
+        };
+        let square =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (4 4, 6 4, 6 6, 4 6, 4 4))")
+                .unwrap();
+        // Scaled by 1.2 to fit 12 pixels of height, spanning x 14 to 26
+        let canvas = render(&[square], 40, 20, &style);
+        // Centre of the hole, inside the fill, the outline and the margin
+        assert_eq!(pixel(&canvas, 20, 10), [0, 0, 0, 255]);
+        assert_eq!(pixel(&canvas, 16, 10), [255, 0, 0, 255]);
+        assert_eq!(pixel(&canvas, 14, 10), [0, 0, 255, 255]);
+        assert_eq!(pixel(&canvas, 12, 10), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_render_translucent_blend() {
+        let style = Style {
+            background: Rgba([255, 255, 255, 255]),
+            stroke: Rgba([0, 0, 0, 128]),
+            stroke_width: 3.0,
+            ..Style::default()
+        };
+        // Two crossing lines are blended once where they overlap
+        let lines = geometry_from_wkt("MULTILINESTRING((0 0, 10 10), (0 10, 10 0))").unwrap();
+        let canvas = render(&[lines], 21, 21, &style);
+        assert_eq!(pixel(&canvas, 10, 10), [127, 127, 127, 255]);
+        assert_eq!(pixel(&canvas, 10, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_render_single_point() {
+        let point = geometry_from_wkt("POINT(5 5)").unwrap();
+        let canvas = render(&[point], 9, 9, &Style::default());
+        assert_eq!(pixel(&canvas, 4, 4), Style::default().point_color.0);
+        assert_eq!(pixel(&canvas, 0, 0), Style::default().background.0);
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let mut canvas = Canvas::new(300, 200, Rgba([1, 2, 3, 4]));
+        canvas.pixels[200 * 300 - 1] = [255, 0, 0, 255];
+        let png = encode_png(&canvas).unwrap();
+        // A flat image compresses to a small fraction of its raw size
+        assert!(png.len() < 300 * 200 * 4 / 50);
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (300, 200));
+        assert_eq!(
+            (info.color_type, info.bit_depth),
+            (png::ColorType::Rgba, png::BitDepth::Eight)
+        );
+        assert_eq!(&decoded[..info.buffer_size()], canvas.pixels.as_flattened());
+    }
+}
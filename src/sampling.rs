@@ -0,0 +1,290 @@
+use crate::geometry::Geometry;
+use crate::spatial_index::BBox;
+use crate::utils::{extension_schema, quote_ident, quote_table, RostGisError};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use std::collections::HashSet;
+
+// Spatial sampling of large tables (rostgis_sample_extent)
+//
+// Validating a table of hundreds of millions of rows one full scan at a time
+// is too slow, and LIMIT or TABLESAMPLE pick rows in storage order or by
+// block, which says little about regions the loader got wrong. Instead the
+// extent is cut into a grid of cells, a few distinct cells are chosen at
+// random, and each is answered as an && window by the geometry index:
+//
+//   SELECT t.* FROM rostgis_sample_extent('parcels', 'geom', 20, 50) s
+//   JOIN parcels t ON t.ctid = s.row_ctid
+//
+// Within a window the rows are picked in an order derived from their ctid
+// and the seed, so a given seed returns the same sample while the table is
+// unchanged. A row overlapping several chosen cells is returned once, for
+// the first of them. Without an explicit extent it is estimated from a
+// TABLESAMPLE of about EXTENT_SAMPLE_ROWS rows, which may miss outliers at
+// the edges of the data.
+
+/// Rows read to estimate the extent of a table
+const EXTENT_SAMPLE_ROWS: f64 = 10_000.0;
+
+/// Small deterministic generator (SplitMix64), so a seed gives the same
+/// windows on every platform
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in [0, bound)
+    fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Choose `count` distinct cells of a grid over `extent` at random
+///
+/// The grid has `round(1 / fraction)` cells along each side, so every window
+/// spans `fraction` of the extent's width and height. When `count` covers the
+/// whole grid all cells are returned, in row order.
+pub fn sample_windows(
+    extent: &BBox,
+    count: usize,
+    fraction: f64,
+    seed: u64,
+) -> Result<Vec<BBox>, RostGisError> {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(RostGisError::new(&format!(
+            "rostgis_sample_extent window fraction must be in (0, 1], got {}",
+            fraction
+        )));
+    }
+    let side = (1.0 / fraction).round().clamp(1.0, u32::MAX as f64) as u64;
+    let total = side.saturating_mul(side);
+    let cells: Vec<u64> = if count as u64 >= total {
+        (0..total).collect()
+    } else {
+        let mut rng = SplitMix64(seed);
+        let mut chosen = HashSet::new();
+        let mut cells = Vec::with_capacity(count);
+        while cells.len() < count {
+            let cell = rng.below(total);
+            if chosen.insert(cell) {
+                cells.push(cell);
+            }
+        }
+        cells
+    };
+
+    let width = (extent.max_x - extent.min_x) / side as f64;
+    let height = (extent.max_y - extent.min_y) / side as f64;
+    // The last column and row end exactly on the extent
+    let edge = |min: f64, max: f64, step: f64, i: u64| {
+        if i == side {
+            max
+        } else {
+            min + step * i as f64
+        }
+    };
+    Ok(cells
+        .into_iter()
+        .map(|cell| {
+            let (i, j) = (cell % side, cell / side);
+            BBox::new(
+                edge(extent.min_x, extent.max_x, width, i),
+                edge(extent.min_y, extent.max_y, height, j),
+                edge(extent.min_x, extent.max_x, width, i + 1),
+                edge(extent.min_y, extent.max_y, height, j + 1),
+            )
+        })
+        .collect())
+}
+
+/// Bounding box of the non-empty geometries of a TABLESAMPLE of a table,
+/// with their SRID, or None when the sample holds none
+fn sampled_extent(
+    relation: &str,
+    column: &str,
+    percent: f64,
+) -> Result<Option<(BBox, i32)>, pgrx::spi::Error> {
+    let schema = extension_schema()?;
+    Spi::connect(|client| {
+        let rows = client.select(
+            &format!(
+                "SELECT {column} FROM {relation} TABLESAMPLE SYSTEM ($1::float4)
+                 WHERE {column} IS NOT NULL AND NOT {schema}.st_isempty({column})"
+            ),
+            None,
+            &[percent.into()],
+        )?;
+        let mut extent: Option<(BBox, i32)> = None;
+        for row in rows {
+            if let Some(geom) = row.get::<Geometry>(1)? {
+                let bbox = BBox::from_geometry(&geom);
+                extent = Some(match extent {
+                    Some((extent, srid)) => (extent.union(&bbox), srid),
+                    None => (bbox, geom.srid()),
+                });
+            }
+        }
+        Ok(extent)
+    })
+}
+
+/// PostgreSQL function returning a random spatial sample of the rows of a
+/// table, drawn from `windows` random cells of its extent
+///
+/// At most `rows_per_window` rows are taken from each cell, whose side is
+/// `window_fraction` of the extent's. `extent`, e.g. an ST_MakeEnvelope,
+/// restricts the sample to a region; `seed` makes it repeatable, otherwise it
+/// is drawn from random() and follows setseed(). Rows are identified by
+/// their ctid, and the geometry column should have an index.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+pub fn rostgis_sample_extent(
+    table_name: &str,
+    geom_column: &str,
+    windows: default!(i32, 10),
+    rows_per_window: default!(i32, 100),
+    window_fraction: default!(f64, 0.05),
+    extent: default!(Option<Geometry>, "NULL"),
+    seed: default!(Option<i64>, "NULL"),
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(sample_window, i32),
+            name!(window_extent, Geometry),
+            name!(row_ctid, pg_sys::ItemPointerData),
+        ),
+    >,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    if windows < 0 || rows_per_window < 0 {
+        return Err("rostgis_sample_extent counts cannot be negative".into());
+    }
+    let relation = quote_table(table_name)?;
+    let column = quote_ident(geom_column)?;
+
+    let extent = match extent {
+        Some(extent) if extent.is_empty() => None,
+        Some(extent) => Some((BBox::from_geometry(&extent), extent.srid())),
+        None => {
+            let rows = Spi::get_one_with_args::<f64>(
+                "SELECT reltuples::float8 FROM pg_class WHERE oid = $1::regclass",
+                &[relation.as_str().into()],
+            )?
+            .unwrap_or(-1.0);
+            let percent = if rows > EXTENT_SAMPLE_ROWS {
+                100.0 * EXTENT_SAMPLE_ROWS / rows
+            } else {
+                100.0
+            };
+            // A sample of few blocks can miss every row of a sparse table
+            match sampled_extent(&relation, &column, percent)? {
+                None if percent < 100.0 => sampled_extent(&relation, &column, 100.0)?,
+                extent => extent,
+            }
+        }
+    };
+    let Some((extent, srid)) = extent else {
+        return Ok(TableIterator::new(Vec::new()));
+    };
+
+    let seed = match seed {
+        Some(seed) => seed,
+        None => Spi::get_one::<i64>("SELECT (random() * 9007199254740991)::int8")?.unwrap_or(0),
+    };
+    let boxes: Vec<Geometry> =
+        sample_windows(&extent, windows as usize, window_fraction, seed as u64)?
+            .iter()
+            .map(|window| window.to_geometry().with_srid(srid))
+            .collect();
+
+    let schema = extension_schema()?;
+    let sample = Spi::connect(|client| {
+        let rows = client.select(
+            &format!(
+                "SELECT min(w.n)::int4, t.ctid
+                 FROM unnest($1::{schema}.geometry[]) WITH ORDINALITY AS w(box, n)
+                 CROSS JOIN LATERAL (
+                     SELECT ctid FROM {relation}
+                     WHERE {column} OPERATOR({schema}.&&) w.box
+                       AND NOT {schema}.st_isempty({column})
+                     ORDER BY hashtextextended(ctid::text, $2)
+                     LIMIT $3) t
+                 GROUP BY t.ctid
+                 ORDER BY 1, hashtextextended(t.ctid::text, $2)"
+            ),
+            None,
+            &[
+                boxes.clone().into(),
+                seed.into(),
+                (rows_per_window as i64).into(),
+            ],
+        )?;
+        let mut sample = Vec::new();
+        for row in rows {
+            if let (Some(window), Some(ctid)) =
+                (row.get::<i32>(1)?, row.get::<pg_sys::ItemPointerData>(2)?)
+            {
+                sample.push((window, ctid));
+            }
+        }
+        Ok::<_, pgrx::spi::Error>(sample)
+    })?;
+
+    Ok(TableIterator::new(sample.into_iter().map(
+        move |(window, ctid)| (window, boxes[window as usize - 1].clone(), ctid),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_windows() {
+        let extent = BBox::new(0.0, 0.0, 100.0, 50.0);
+        let windows = sample_windows(&extent, 10, 0.1, 42).unwrap();
+        assert_eq!(windows.len(), 10);
+        for window in &windows {
+            assert!(window.within(&extent));
+            assert!((window.max_x - window.min_x - 10.0).abs() < 1e-9);
+            assert!((window.max_y - window.min_y - 5.0).abs() < 1e-9);
+        }
+        // Distinct cells, the same for the same seed
+        let corners: HashSet<[u64; 2]> = windows
+            .iter()
+            .map(|w| [w.min_x.to_bits(), w.min_y.to_bits()])
+            .collect();
+        assert_eq!(corners.len(), 10);
+        assert_eq!(sample_windows(&extent, 10, 0.1, 42).unwrap(), windows);
+        assert_ne!(sample_windows(&extent, 10, 0.1, 43).unwrap(), windows);
+    }
+
+    #[test]
+    fn test_sample_whole_grid() {
+        let extent = BBox::new(-1.0, -1.0, 2.0, 2.0);
+        let windows = sample_windows(&extent, 100, 1.0 / 3.0, 7).unwrap();
+        assert_eq!(windows.len(), 9);
+        assert_eq!(windows[0], BBox::new(-1.0, -1.0, 0.0, 0.0));
+        assert_eq!(windows[8], BBox::new(1.0, 1.0, 2.0, 2.0));
+        assert_eq!(
+            sample_windows(&extent, 1, 1.0, 7).unwrap(),
+            vec![extent.clone()]
+        );
+        assert!(sample_windows(&extent, 0, 0.5, 7).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_fraction() {
+        let extent = BBox::new(0.0, 0.0, 1.0, 1.0);
+        assert!(sample_windows(&extent, 1, 0.0, 0).is_err());
+        assert!(sample_windows(&extent, 1, 1.5, 0).is_err());
+        assert!(sample_windows(&extent, 1, f64::NAN, 0).is_err());
+    }
+}
@@ -0,0 +1,497 @@
+use crate::compact;
+use crate::ewkb::{read_wkb, write_wkb};
+use crate::geometry::Geometry;
+use crate::guc::{self, StorageEncoding};
+use crate::stats::{self, Counter};
+use crate::typmod::geometry_type_code;
+use crate::utils::RostGisError;
+use byteorder::{ByteOrder, LittleEndian};
+use pgrx::pg_sys;
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+// On-disk geometry format
+//
+// Every stored geometry starts with a fixed-size header so that cheap
+// accessors (ST_X, ST_Y, ST_SRID, ST_GeometryType, ST_NPoints) can answer
+// without decoding the body:
+//
+//   offset  size  field
+//        0     1  format version
+//        1     1  geometry type code (WKB numbering, 1-7)
+//        2     1  flags (bit 0: Z, bit 1: M, bit 2: empty, bit 3: known valid,
+//                 bit 4: compact body)
+//        3     1  decimal precision of a compact body, else 0
+//        4     4  SRID (i32, little-endian)
+//        8     4  number of points (u32, little-endian)
+//       12     4  reserved
+//       16     8  X of the first point (NaN when empty)
+//       24     8  Y of the first point (NaN when empty)
+//       32     *  body: the geometry as little-endian WKB without SRID, or
+//                 in the compact encoding (see the compact module)
+//
+// The datum pgrx stores is this buffer wrapped in a CBOR byte string, so a
+// reader only has to skip the CBOR length prefix to reach the header.
+//
+// The known-valid flag is only ever set by ST_MakeValid on a geometry it has
+// checked, and is dropped by anything that rebuilds the geometry, so a set
+// flag always describes the stored body. ST_IsValid and the overlay inputs of
+// ST_Union skip their validity check on flagged geometries.
+//
+// The compact body is written when rostgis.storage_encoding is compact or
+// the target column has the Compact typmod, and only when it is lossless;
+// readers check the flag, so both encodings mix freely in a table.
+
+/// Current serialization format version
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of the fixed header in bytes
+pub const HEADER_SIZE: usize = 32;
+
+const FLAG_Z: u8 = 0b001;
+const FLAG_M: u8 = 0b010;
+const FLAG_EMPTY: u8 = 0b100;
+const FLAG_VALID: u8 = 0b1000;
+const FLAG_COMPACT: u8 = 0b1_0000;
+
+/// Fixed-size header of a serialized geometry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeometryHeader {
+    pub type_code: u8,
+    pub flags: u8,
+    pub srid: i32,
+    pub npoints: u32,
+    pub first_x: f64,
+    pub first_y: f64,
+}
+
+impl GeometryHeader {
+    /// Build the header describing a geometry
+    pub fn from_geometry(geom: &Geometry) -> Self {
+        let coords = geom.coordinates();
+        let (first_x, first_y) = coords.first().copied().unwrap_or((f64::NAN, f64::NAN));
+
+        let mut flags = 0;
+        if geom.has_z() {
+            flags |= FLAG_Z;
+        }
+        if geom.has_m() {
+            flags |= FLAG_M;
+        }
+        if geom.is_empty() {
+            flags |= FLAG_EMPTY;
+        }
+
+        GeometryHeader {
+            type_code: geometry_type_code(geom) as u8,
+            flags,
+            srid: geom.srid(),
+            npoints: coords.len() as u32,
+            first_x,
+            first_y,
+        }
+    }
+
+    /// Write the header into the first HEADER_SIZE bytes of a buffer
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = FORMAT_VERSION;
+        buffer[1] = self.type_code;
+        buffer[2] = self.flags;
+        buffer[3] = 0;
+        LittleEndian::write_i32(&mut buffer[4..8], self.srid);
+        LittleEndian::write_u32(&mut buffer[8..12], self.npoints);
+        LittleEndian::write_u32(&mut buffer[12..16], 0);
+        LittleEndian::write_f64(&mut buffer[16..24], self.first_x);
+        LittleEndian::write_f64(&mut buffer[24..32], self.first_y);
+    }
+
+    /// Read the header of a serialized geometry, or of a stored datum
+    /// (CBOR-wrapped), without touching the body
+    pub fn peek(bytes: &[u8]) -> Result<Self, RostGisError> {
+        let bytes = unwrap_datum(bytes)?;
+        if bytes.len() < HEADER_SIZE {
+            return Err(RostGisError::new("Serialized geometry is truncated"));
+        }
+        if bytes[0] != FORMAT_VERSION {
+            return Err(RostGisError::new(&format!(
+                "Unsupported geometry serialization version: {}",
+                bytes[0]
+            )));
+        }
+        if !(1..=7).contains(&bytes[1]) {
+            return Err(RostGisError::new(&format!(
+                "Invalid geometry type code in header: {}",
+                bytes[1]
+            )));
+        }
+
+        Ok(GeometryHeader {
+            type_code: bytes[1],
+            flags: bytes[2],
+            srid: LittleEndian::read_i32(&bytes[4..8]),
+            npoints: LittleEndian::read_u32(&bytes[8..12]),
+            first_x: LittleEndian::read_f64(&bytes[16..24]),
+            first_y: LittleEndian::read_f64(&bytes[24..32]),
+        })
+    }
+
+    pub fn has_z(&self) -> bool {
+        self.flags & FLAG_Z != 0
+    }
+
+    pub fn has_m(&self) -> bool {
+        self.flags & FLAG_M != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags & FLAG_EMPTY != 0
+    }
+
+    /// Whether the geometry was checked to be valid when it was stored
+    pub fn is_known_valid(&self) -> bool {
+        self.flags & FLAG_VALID != 0
+    }
+
+    /// Whether the body uses the compact encoding
+    pub fn is_compact(&self) -> bool {
+        self.flags & FLAG_COMPACT != 0
+    }
+
+    /// X coordinate, for non-empty points only (matches Geometry::x)
+    pub fn x(&self) -> Option<f64> {
+        (self.type_code == 1 && !self.is_empty()).then_some(self.first_x)
+    }
+
+    /// Y coordinate, for non-empty points only (matches Geometry::y)
+    pub fn y(&self) -> Option<f64> {
+        (self.type_code == 1 && !self.is_empty()).then_some(self.first_y)
+    }
+
+    /// Geometry type name as returned by ST_GeometryType
+    pub fn geometry_type(&self) -> &'static str {
+        match self.type_code {
+            1 => "ST_Point",
+            2 => "ST_LineString",
+            3 => "ST_Polygon",
+            4 => "ST_MultiPoint",
+            5 => "ST_MultiLineString",
+            6 => "ST_MultiPolygon",
+            _ => "ST_GeometryCollection",
+        }
+    }
+}
+
+/// Serialize a geometry into the on-disk format, with the body encoding
+/// selected by rostgis.storage_encoding
+pub fn serialize(geom: &Geometry) -> Vec<u8> {
+    serialize_with(geom, guc::STORAGE_ENCODING.get(), false)
+}
+
+/// Serialize a geometry with a compact body when that is lossless
+pub fn serialize_compact(geom: &Geometry) -> Vec<u8> {
+    serialize_with(geom, StorageEncoding::Compact, false)
+}
+
+/// Serialize a geometry with the given body encoding, setting the
+/// known-valid flag if asked to
+pub fn serialize_with(geom: &Geometry, encoding: StorageEncoding, known_valid: bool) -> Vec<u8> {
+    stats::count(Counter::GeometriesEncoded);
+    let compacted = match encoding {
+        StorageEncoding::Wkb => None,
+        StorageEncoding::Compact => compact::encode(geom),
+    };
+    let (body, precision) = match compacted {
+        Some((precision, body)) => (body, Some(precision)),
+        None => (write_wkb(geom, false), None),
+    };
+
+    let mut buffer = vec![0u8; HEADER_SIZE + body.len()];
+    GeometryHeader::from_geometry(geom).write(&mut buffer);
+    if let Some(precision) = precision {
+        buffer[2] |= FLAG_COMPACT;
+        buffer[3] = precision;
+    }
+    if known_valid {
+        buffer[2] |= FLAG_VALID;
+    }
+    buffer[HEADER_SIZE..].copy_from_slice(&body);
+    buffer
+}
+
+/// Serialize a geometry already checked to be valid, setting the known-valid
+/// flag
+pub fn serialize_valid(geom: &Geometry) -> Vec<u8> {
+    serialize_with(geom, guc::STORAGE_ENCODING.get(), true)
+}
+
+/// A serialized geometry, handed to serde as a byte string like `Geometry`
+/// hands over its own serialization
+struct SerializedGeometry<'a>(&'a [u8]);
+
+impl Serialize for SerializedGeometry<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Datum of a serialized geometry, encoded by pgrx exactly as it encodes a
+/// returned `Geometry`, for functions that return a raw datum to keep the
+/// header flags they set
+pub fn into_datum(bytes: &[u8]) -> pg_sys::Datum {
+    pgrx::datum::cbor_encode(SerializedGeometry(bytes)).into()
+}
+
+/// Deserialize a geometry from the on-disk format
+pub fn deserialize(bytes: &[u8]) -> Result<Geometry, RostGisError> {
+    stats::count(Counter::GeometriesDecoded);
+    let header = GeometryHeader::peek(bytes)?;
+    let bytes = unwrap_datum(bytes)?;
+    let body = &bytes[HEADER_SIZE..];
+    let geom = if header.is_compact() {
+        compact::decode(header.type_code, bytes[3], body)?
+    } else {
+        read_wkb(body)?
+    };
+    Ok(geom.with_srid(header.srid))
+}
+
+/// Total order of two serialized geometries, or stored datums: empty
+/// geometries first, then by SRID, type code, first point and WKB body
+///
+/// Geometries are equal when they have the same SRID and coordinates,
+/// whichever encoding their bodies are stored in and whether or not they are
+/// flagged valid. Only bodies in the compact encoding are decoded, and only
+/// when everything in the headers ties.
+pub fn compare(a: &[u8], b: &[u8]) -> Result<Ordering, RostGisError> {
+    let (header_a, header_b) = (GeometryHeader::peek(a)?, GeometryHeader::peek(b)?);
+    let ordering = header_b
+        .is_empty()
+        .cmp(&header_a.is_empty())
+        .then(header_a.srid.cmp(&header_b.srid))
+        .then(header_a.type_code.cmp(&header_b.type_code))
+        .then(header_a.first_x.total_cmp(&header_b.first_x))
+        .then(header_a.first_y.total_cmp(&header_b.first_y))
+        .then(header_a.npoints.cmp(&header_b.npoints));
+    if ordering != Ordering::Equal {
+        return Ok(ordering);
+    }
+
+    Ok(wkb_body(a, &header_a)?.cmp(&wkb_body(b, &header_b)?))
+}
+
+/// The body of a serialized geometry as WKB, decoding a compact one
+fn wkb_body<'a>(bytes: &'a [u8], header: &GeometryHeader) -> Result<Cow<'a, [u8]>, RostGisError> {
+    Ok(if header.is_compact() {
+        Cow::Owned(write_wkb(&deserialize(bytes)?, false))
+    } else {
+        Cow::Borrowed(&unwrap_datum(bytes)?[HEADER_SIZE..])
+    })
+}
+
+/// Bytes at the start of a stored datum that hold the header: the longest
+/// CBOR byte string prefix and the header itself
+const DATUM_HEADER_BYTES: usize = 9 + HEADER_SIZE;
+
+/// Read the header of the geometry passed as argument `arg` of a function,
+/// detoasting only the slice of the datum that holds it, so that a large
+/// TOAST-ed geometry is neither fetched nor decompressed in full
+///
+/// # Safety
+///
+/// `fcinfo` must be the call info of a function whose argument `arg` is a
+/// non-NULL geometry.
+pub unsafe fn peek_argument(
+    fcinfo: pg_sys::FunctionCallInfo,
+    arg: usize,
+) -> Result<GeometryHeader, RostGisError> {
+    let datum = pgrx::pg_getarg_datum(fcinfo, arg)
+        .ok_or_else(|| RostGisError::new("The geometry argument is NULL"))?;
+    let slice = pg_sys::pg_detoast_datum_slice(datum.cast_mut_ptr(), 0, DATUM_HEADER_BYTES as i32);
+    GeometryHeader::peek(pgrx::varlena::varlena_to_byte_slice(slice))
+}
+
+/// Strip the CBOR byte string prefix pgrx puts in front of the payload, if any
+fn unwrap_datum(bytes: &[u8]) -> Result<&[u8], RostGisError> {
+    let first = match bytes.first() {
+        Some(first) => *first,
+        None => return Err(RostGisError::new("Serialized geometry is empty")),
+    };
+    if first == FORMAT_VERSION {
+        return Ok(bytes);
+    }
+
+    // CBOR major type 2 (byte string); the header alone is longer than 23
+    // bytes so the length always follows in 1, 2, 4 or 8 bytes
+    let length_bytes = match first {
+        0x58 => 1,
+        0x59 => 2,
+        0x5a => 4,
+        0x5b => 8,
+        _ => return Err(RostGisError::new("Unrecognized geometry datum layout")),
+    };
+    let prefix = 1 + length_bytes;
+    if bytes.len() < prefix {
+        return Err(RostGisError::new("Serialized geometry is truncated"));
+    }
+    let length = bytes[1..prefix]
+        .iter()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte)) as usize;
+
+    // A datum detoasted as a slice by peek_argument stops short of the
+    // declared length; the header is all that is read from it
+    Ok(&bytes[prefix..bytes.len().min(prefix.saturating_add(length))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_roundtrip() {
+        let polygon = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))")
+            .unwrap()
+            .with_srid(3857);
+        let bytes = serialize(&polygon);
+        assert_eq!(deserialize(&bytes).unwrap(), polygon);
+
+        let header = GeometryHeader::peek(&bytes).unwrap();
+        assert_eq!(header.geometry_type(), "ST_Polygon");
+        assert_eq!(header.srid, 3857);
+        assert_eq!(header.npoints, 5);
+        assert_eq!(header.x(), None);
+    }
+
+    #[test]
+    fn test_point_header() {
+        let point = make_point(1.5, -2.5).with_srid(4326);
+        let header = GeometryHeader::peek(&serialize(&point)).unwrap();
+        assert_eq!(header.x(), Some(1.5));
+        assert_eq!(header.y(), Some(-2.5));
+        assert_eq!(header.srid, 4326);
+        assert!(!header.is_empty());
+    }
+
+    /// A serialized geometry in the CBOR byte string pgrx stores it in
+    fn cbor_wrapped(bytes: &[u8]) -> Vec<u8> {
+        let mut datum = match bytes.len() {
+            0..=0xff => vec![0x58, bytes.len() as u8],
+            length => {
+                let mut prefix = vec![0x59];
+                prefix.extend_from_slice(&(length as u16).to_be_bytes());
+                prefix
+            }
+        };
+        datum.extend_from_slice(bytes);
+        datum
+    }
+
+    #[test]
+    fn test_peek_cbor_wrapped_datum() {
+        let datum = cbor_wrapped(&serialize(&make_point(3.0, 4.0)));
+
+        // Only the header needs to be present for peek
+        let header = GeometryHeader::peek(&datum[..2 + HEADER_SIZE]).unwrap();
+        assert_eq!(header.x(), Some(3.0));
+        assert!(deserialize(&datum[..2 + HEADER_SIZE]).is_err());
+        assert_eq!(deserialize(&datum).unwrap(), make_point(3.0, 4.0));
+    }
+
+    #[test]
+    fn test_known_valid_flag() {
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        assert!(!GeometryHeader::peek(&serialize(&square))
+            .unwrap()
+            .is_known_valid());
+
+        let flagged = serialize_valid(&square);
+        assert!(GeometryHeader::peek(&flagged).unwrap().is_known_valid());
+        assert_eq!(deserialize(&flagged).unwrap(), square);
+
+        // The flag survives the datum wrapping, whatever the length prefix
+        let long_line = crate::functions::make_line_from_arrays(
+            &(0..100).map(f64::from).collect::<Vec<_>>(),
+            &[0.0; 100],
+            None,
+        )
+        .unwrap();
+        for geom in [square, long_line] {
+            let datum = cbor_wrapped(&serialize_valid(&geom));
+            assert!(GeometryHeader::peek(&datum).unwrap().is_known_valid());
+            assert_eq!(deserialize(&datum).unwrap(), geom);
+        }
+    }
+
+    #[test]
+    fn test_compact_body() {
+        let line = geometry_from_wkt(
+            "LINESTRING(13.4049537 52.5200066, 13.4051 52.5201, 13.4052 52.5203)",
+        )
+        .unwrap()
+        .with_srid(4326);
+        let plain = serialize(&line);
+        let compacted = serialize_compact(&line);
+        let header = GeometryHeader::peek(&compacted).unwrap();
+        assert!(header.is_compact());
+        assert!(!GeometryHeader::peek(&plain).unwrap().is_compact());
+        assert_eq!(header.npoints, 3);
+        assert_eq!(header.srid, 4326);
+        assert!(compacted.len() - HEADER_SIZE < (plain.len() - HEADER_SIZE) / 2);
+        assert_eq!(deserialize(&cbor_wrapped(&compacted)).unwrap(), line);
+
+        // Without a lossless precision the body stays WKB
+        let third = make_point(1.0 / 3.0, 0.0);
+        let fallback = serialize_compact(&third);
+        assert!(!GeometryHeader::peek(&fallback).unwrap().is_compact());
+        assert_eq!(deserialize(&fallback).unwrap(), third);
+    }
+
+    #[test]
+    fn test_compare() {
+        let wkt = |wkt: &str| serialize(&geometry_from_wkt(wkt).unwrap());
+        let mut sorted = [
+            wkt("POINT EMPTY"),
+            wkt("POINT(-1 5)"),
+            wkt("POINT(0 0)"),
+            wkt("POINT(0 1)"),
+            wkt("LINESTRING(0 0, 1 2)"),
+            wkt("LINESTRING(0 0, 1 3)"),
+            wkt("LINESTRING(0 0, 1 1, 3 3)"),
+            serialize(&make_point(0.0, 0.0).with_srid(4326)),
+        ];
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                assert_eq!(compare(a, b).unwrap(), i.cmp(&j), "{} vs {}", i, j);
+            }
+        }
+        sorted.reverse();
+        sorted.sort_by(|a, b| compare(a, b).unwrap());
+        assert_eq!(sorted[0], wkt("POINT EMPTY"));
+
+        // Equal whatever the body encoding and flags
+        let line = geometry_from_wkt("LINESTRING(13.40 52.52, 13.41 52.53)").unwrap();
+        let compacted = cbor_wrapped(&serialize_compact(&line));
+        assert!(GeometryHeader::peek(&compacted).unwrap().is_compact());
+        assert_eq!(
+            compare(&compacted, &serialize_valid(&line)).unwrap(),
+            Ordering::Equal
+        );
+        let other = geometry_from_wkt("LINESTRING(13.40 52.52, 13.42 52.53)").unwrap();
+        assert_eq!(
+            compare(&compacted, &serialize(&other)).unwrap(),
+            compare(&serialize(&line), &serialize(&other)).unwrap()
+        );
+        assert!(compare(&[], &compacted).is_err());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(GeometryHeader::peek(&[]).is_err());
+        assert!(GeometryHeader::peek(&[FORMAT_VERSION, 1, 0]).is_err());
+
+        let mut bytes = serialize(&make_point(0.0, 0.0));
+        bytes[1] = 42;
+        assert!(GeometryHeader::peek(&bytes).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::{PreparedGeometry, Relate};
+use pgrx::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// Session geometry variables (rostgis_set_geom, rostgis_get_geom)
+//
+// A large query polygon written into every statement is parsed, and
+// prepared by the predicates, again in each of them. Stored once under a
+// name, it stays parsed in backend memory for the rest of the session:
+//
+//   SELECT rostgis_set_geom('district', ST_GeomFromText('POLYGON((...))', 3857));
+//   SELECT count(*) FROM parcels WHERE geom && rostgis_get_geom('district')
+//     AND rostgis_geom_intersects('district', geom);
+//
+// rostgis_geom_intersects and rostgis_geom_contains test against the stored
+// geometry prepared once, on first use, and kept until it is replaced, where
+// the prepared-geometry cache of ST_Intersects and ST_Contains only lasts a
+// query. Variables belong to the backend and are not transactional: a
+// rolled back rostgis_set_geom still takes effect. Parallel workers do not
+// see them, so the functions reading them are parallel unsafe.
+
+struct StoredGeometry {
+    geometry: Geometry,
+    /// Prepared on the first predicate test
+    prepared: Option<PreparedGeometry<'static, geo::Geometry<f64>>>,
+}
+
+/// Named geometries of a session
+#[derive(Default)]
+pub struct GeometryStore {
+    entries: HashMap<String, StoredGeometry>,
+}
+
+impl GeometryStore {
+    /// Store a geometry under a name, replacing any geometry stored before
+    pub fn set(&mut self, name: &str, geometry: Geometry) {
+        self.entries.insert(
+            name.to_string(),
+            StoredGeometry {
+                geometry,
+                prepared: None,
+            },
+        );
+    }
+
+    /// Remove a stored geometry; false if there was none
+    pub fn unset(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    fn entry(&mut self, name: &str) -> Result<&mut StoredGeometry, RostGisError> {
+        self.entries.get_mut(name).ok_or_else(|| {
+            RostGisError::new(&format!(
+                "No geometry named '{}' is set, store one with rostgis_set_geom",
+                name
+            ))
+        })
+    }
+
+    /// The geometry stored under a name
+    pub fn get(&mut self, name: &str) -> Result<&Geometry, RostGisError> {
+        Ok(&self.entry(name)?.geometry)
+    }
+
+    /// Relate the stored geometry to another through its prepared form,
+    /// preparing it on first use
+    fn relate(
+        &mut self,
+        name: &str,
+        other: &Geometry,
+    ) -> Result<geo::relate::IntersectionMatrix, RostGisError> {
+        let entry = self.entry(name)?;
+        let geometry = &entry.geometry;
+        let prepared = entry
+            .prepared
+            .get_or_insert_with(|| PreparedGeometry::from(geometry.to_geo()));
+        Ok(prepared.relate(&other.to_geo()))
+    }
+
+    /// Whether the stored geometry intersects another
+    pub fn intersects(&mut self, name: &str, other: &Geometry) -> Result<bool, RostGisError> {
+        if !self.get(name)?.bbox_overlaps(other) {
+            return Ok(false);
+        }
+        Ok(self.relate(name, other)?.is_intersects())
+    }
+
+    /// Whether the stored geometry contains another
+    pub fn contains(&mut self, name: &str, other: &Geometry) -> Result<bool, RostGisError> {
+        if !self.get(name)?.bbox_contains(other) {
+            return Ok(false);
+        }
+        Ok(self.relate(name, other)?.is_contains())
+    }
+
+    /// Whether a stored geometry has been prepared
+    pub fn is_prepared(&self, name: &str) -> bool {
+        self.entries
+            .get(name)
+            .is_some_and(|entry| entry.prepared.is_some())
+    }
+}
+
+thread_local! {
+    static STORE: RefCell<GeometryStore> = RefCell::new(GeometryStore::default());
+}
+
+/// Run `f` on the geometry store of the session
+fn with_store<R>(
+    f: impl FnOnce(&mut GeometryStore) -> Result<R, RostGisError>,
+) -> Result<R, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(STORE.with(|store| f(&mut store.borrow_mut()))?)
+}
+
+/// PostgreSQL function storing a geometry under a name for the session,
+/// returning it
+#[pg_extern(volatile, strict)]
+pub fn rostgis_set_geom(
+    name: &str,
+    geom: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    with_store(|store| {
+        store.set(name, geom.clone());
+        Ok(geom)
+    })
+}
+
+/// PostgreSQL function removing a stored geometry; false if none was set
+#[pg_extern(volatile, strict)]
+pub fn rostgis_unset_geom(name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    with_store(|store| Ok(store.unset(name)))
+}
+
+/// PostgreSQL function returning a stored geometry
+#[pg_extern(stable, strict)]
+pub fn rostgis_get_geom(name: &str) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    with_store(|store| store.get(name).cloned())
+}
+
+/// PostgreSQL function testing whether a stored geometry intersects another
+#[pg_extern(stable, strict)]
+pub fn rostgis_geom_intersects(
+    name: &str,
+    geom: Geometry,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    with_store(|store| store.intersects(name, &geom))
+}
+
+/// PostgreSQL function testing whether a stored geometry contains another
+#[pg_extern(stable, strict)]
+pub fn rostgis_geom_contains(
+    name: &str,
+    geom: Geometry,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    with_store(|store| store.contains(name, &geom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_geometry_store() {
+        let mut store = GeometryStore::default();
+        let zone = geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 5 2, 0 10, 0 0))").unwrap();
+        store.set("zone", zone.clone());
+        assert_eq!(store.get("zone").unwrap(), &zone);
+        assert!(!store.is_prepared("zone"));
+
+        assert!(store.intersects("zone", &make_point(5.0, 1.0)).unwrap());
+        assert!(store.is_prepared("zone"));
+        // In the notch of the polygon, inside its box
+        assert!(!store.contains("zone", &make_point(5.0, 5.0)).unwrap());
+        assert!(!store.intersects("zone", &make_point(20.0, 20.0)).unwrap());
+
+        // Replacing a geometry drops its prepared form
+        store.set("zone", make_point(5.0, 5.0));
+        assert!(!store.is_prepared("zone"));
+        assert!(store.intersects("zone", &make_point(5.0, 5.0)).unwrap());
+
+        assert!(store.unset("zone"));
+        assert!(!store.unset("zone"));
+        assert!(store.get("zone").is_err());
+        assert!(store.intersects("zone", &make_point(5.0, 5.0)).is_err());
+    }
+}
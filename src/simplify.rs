@@ -0,0 +1,687 @@
+use crate::geometry::Geometry;
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo::{BoundingRect, Contains, Intersects};
+use geo_types::{Coord, Line, LineString, MultiLineString, MultiPolygon, Point, Polygon};
+use pgrx::prelude::*;
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, RTreeObject};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+// Line simplification (ST_Simplify, ST_SimplifyPreserveTopology, ST_SimplifyVW)
+//
+// ST_Simplify and ST_SimplifyPreserveTopology use Douglas-Peucker: a section
+// of a line is replaced by the segment joining its ends when no point of the
+// section is further than the tolerance from that segment, and is split at
+// its farthest point otherwise.
+//
+// The topology-preserving variant also refuses a shortcut that would cross
+// or overlap any other segment of the geometry, original or simplified, or
+// that would sweep over another ring or line. Closed rings keep three anchor
+// points, so a ring never collapses below a triangle.
+//
+// ST_SimplifyVW uses Visvalingam-Whyatt: the point whose triangle with its
+// neighbours has the smallest area is removed, repeatedly, until every
+// remaining triangle is at least the tolerance. Removing points by the area
+// they contribute rather than by their offset keeps the overall shape better
+// for cartographic generalisation.
+
+/// Point-to-segment distance; the distance to the point for a degenerate
+/// segment
+fn segment_distance(point: Coord<f64>, start: Coord<f64>, end: Coord<f64>) -> f64 {
+    let segment = end - start;
+    let length_squared = segment.x * segment.x + segment.y * segment.y;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.x - start.x) * segment.x + (point.y - start.y) * segment.y) / length_squared)
+            .clamp(0.0, 1.0)
+    };
+    let nearest = start + segment * t;
+    (point.x - nearest.x).hypot(point.y - nearest.y)
+}
+
+/// Index in `from..to` of the coordinate with the largest `distance`, and
+/// that distance
+fn farthest(
+    coords: &[Coord<f64>],
+    from: usize,
+    to: usize,
+    distance: impl Fn(Coord<f64>) -> f64,
+) -> (usize, f64) {
+    (from..to)
+        .map(|i| (i, distance(coords[i])))
+        .fold((from, -1.0), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        })
+}
+
+/// Mark the points Douglas-Peucker keeps strictly between `first` and
+/// `last`; `accept` is asked before any section is replaced by a shortcut
+fn douglas_peucker(
+    coords: &[Coord<f64>],
+    first: usize,
+    last: usize,
+    tolerance: f64,
+    kept: &mut [bool],
+    accept: &mut impl FnMut(usize, usize) -> bool,
+) {
+    let mut sections = vec![(first, last)];
+    while let Some((first, last)) = sections.pop() {
+        if last <= first + 1 {
+            continue;
+        }
+        let (farthest, distance) = farthest(coords, first + 1, last, |coord| {
+            segment_distance(coord, coords[first], coords[last])
+        });
+        if distance <= tolerance && accept(first, last) {
+            continue;
+        }
+        kept[farthest] = true;
+        sections.push((first, farthest));
+        sections.push((farthest, last));
+    }
+}
+
+fn kept_coords(coords: &[Coord<f64>], kept: &[bool]) -> Vec<Coord<f64>> {
+    coords
+        .iter()
+        .zip(kept)
+        .filter(|(_, keep)| **keep)
+        .map(|(coord, _)| *coord)
+        .collect()
+}
+
+fn is_closed(coords: &[Coord<f64>]) -> bool {
+    coords.len() > 1 && coords.first() == coords.last()
+}
+
+/// Douglas-Peucker on one line or ring, without any topology check
+fn simplify_coords(coords: &[Coord<f64>], tolerance: f64) -> Vec<Coord<f64>> {
+    if coords.len() < 3 {
+        return coords.to_vec();
+    }
+    let mut kept = vec![false; coords.len()];
+    kept[0] = true;
+    kept[coords.len() - 1] = true;
+    douglas_peucker(
+        coords,
+        0,
+        coords.len() - 1,
+        tolerance,
+        &mut kept,
+        &mut |_, _| true,
+    );
+    kept_coords(coords, &kept)
+}
+
+/// A ring keeps at least three distinct points
+fn ring_collapsed(coords: &[Coord<f64>]) -> bool {
+    coords.len() < 4
+}
+
+/// A line keeps two distinct points
+fn line_collapsed(coords: &[Coord<f64>]) -> bool {
+    coords.len() < 2 || coords.iter().all(|coord| *coord == coords[0])
+}
+
+/// Simplifies the coordinates of one line or ring; the flag tells rings
+/// from lines
+type Reduce<'a> = &'a dyn Fn(&[Coord<f64>], bool) -> Vec<Coord<f64>>;
+
+fn simplify_polygon(
+    polygon: &Polygon<f64>,
+    reduce: Reduce,
+    preserve_collapsed: bool,
+) -> Option<Polygon<f64>> {
+    let simplify_ring = |ring: &LineString<f64>| {
+        let coords = reduce(&ring.0, true);
+        if !ring_collapsed(&coords) {
+            Some(LineString(coords))
+        } else if preserve_collapsed {
+            Some(ring.clone())
+        } else {
+            None
+        }
+    };
+    let exterior = simplify_ring(polygon.exterior())?;
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .filter_map(simplify_ring)
+        .collect();
+    Some(Polygon::new(exterior, interiors))
+}
+
+fn simplify_line(
+    line: &LineString<f64>,
+    reduce: Reduce,
+    preserve_collapsed: bool,
+) -> Option<LineString<f64>> {
+    if line.0.is_empty() {
+        return None;
+    }
+    let coords = reduce(&line.0, false);
+    if !line_collapsed(&coords) {
+        Some(LineString(coords))
+    } else if preserve_collapsed {
+        Some(LineString(vec![line.0[0], line.0[line.0.len() - 1]]))
+    } else {
+        None
+    }
+}
+
+/// Douglas-Peucker simplification. Rings and lines that collapse are dropped
+/// unless `preserve_collapsed` is set; None when nothing is left.
+pub fn simplify(geom: &Geometry, tolerance: f64, preserve_collapsed: bool) -> Option<Geometry> {
+    if tolerance <= 0.0 {
+        return Some(geom.clone());
+    }
+    simplify_with(
+        geom,
+        &|coords, _| simplify_coords(coords, tolerance),
+        preserve_collapsed,
+    )
+}
+
+/// Visvalingam-Whyatt simplification, removing points whose effective area
+/// is below `tolerance`. Rings keep at least a triangle and lines their end
+/// points; None when a line degenerates to a single point.
+pub fn simplify_vw(geom: &Geometry, tolerance: f64) -> Option<Geometry> {
+    if tolerance <= 0.0 {
+        return Some(geom.clone());
+    }
+    simplify_with(
+        geom,
+        &|coords, ring| visvalingam(coords, tolerance, if ring { 4 } else { 2 }),
+        false,
+    )
+}
+
+/// Apply `reduce` to every line and ring of a geometry
+fn simplify_with(geom: &Geometry, reduce: Reduce, preserve_collapsed: bool) -> Option<Geometry> {
+    if geom.is_empty() {
+        return Some(geom.clone());
+    }
+    let srid = geom.srid();
+    match geom {
+        Geometry::Zm(base, _) => simplify_with(base, reduce, preserve_collapsed),
+        Geometry::Point(_, _) | Geometry::MultiPoint(_, _) => Some(geom.clone()),
+        Geometry::LineString(line, _) => simplify_line(line, reduce, preserve_collapsed)
+            .map(|line| Geometry::LineString(line, srid)),
+        Geometry::MultiLineString(lines, _) => {
+            let lines: Vec<_> = lines
+                .iter()
+                .filter_map(|line| simplify_line(line, reduce, preserve_collapsed))
+                .collect();
+            (!lines.is_empty()).then_some(Geometry::MultiLineString(MultiLineString(lines), srid))
+        }
+        Geometry::Polygon(polygon, _) => simplify_polygon(polygon, reduce, preserve_collapsed)
+            .map(|polygon| Geometry::Polygon(polygon, srid)),
+        Geometry::MultiPolygon(polygons, _) => {
+            let polygons: Vec<_> = polygons
+                .iter()
+                .filter_map(|polygon| simplify_polygon(polygon, reduce, preserve_collapsed))
+                .collect();
+            (!polygons.is_empty()).then_some(Geometry::MultiPolygon(MultiPolygon(polygons), srid))
+        }
+        Geometry::GeometryCollection(collection, _) => {
+            let members: Vec<_> = collection
+                .iter()
+                .filter_map(|member| simplify_with(member, reduce, preserve_collapsed))
+                .collect();
+            (!members.is_empty()).then_some(Geometry::GeometryCollection(members, srid))
+        }
+    }
+}
+
+/// A point and its effective area, ordered by area
+#[derive(PartialEq)]
+struct Candidate {
+    area: f64,
+    index: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.area
+            .total_cmp(&other.area)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+fn triangle_area(a: Coord<f64>, b: Coord<f64>, c: Coord<f64>) -> f64 {
+    ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)).abs() / 2.0
+}
+
+/// Visvalingam-Whyatt on one line or ring, keeping the end points and at
+/// least `min_points` points
+fn visvalingam(coords: &[Coord<f64>], tolerance: f64, min_points: usize) -> Vec<Coord<f64>> {
+    let n = coords.len();
+    if n <= min_points.max(2) {
+        return coords.to_vec();
+    }
+    let mut previous: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| (i + 1).min(n - 1)).collect();
+    let mut area = vec![f64::INFINITY; n];
+    let mut heap = BinaryHeap::new();
+    for i in 1..n - 1 {
+        area[i] = triangle_area(coords[i - 1], coords[i], coords[i + 1]);
+        heap.push(Reverse(Candidate {
+            area: area[i],
+            index: i,
+        }));
+    }
+
+    let mut kept = vec![true; n];
+    let mut remaining = n;
+    while let Some(Reverse(Candidate {
+        area: smallest,
+        index,
+    })) = heap.pop()
+    {
+        if !kept[index] || smallest != area[index] {
+            // Superseded by a later area of the same point
+            continue;
+        }
+        if smallest >= tolerance || remaining <= min_points {
+            break;
+        }
+        kept[index] = false;
+        remaining -= 1;
+        let (before, after) = (previous[index], next[index]);
+        next[before] = after;
+        previous[after] = before;
+
+        // A neighbour's area never drops below that of a point removed
+        // before it, so removal follows the order of significance
+        for neighbour in [before, after] {
+            if neighbour == 0 || neighbour == n - 1 {
+                continue;
+            }
+            area[neighbour] = triangle_area(
+                coords[previous[neighbour]],
+                coords[neighbour],
+                coords[next[neighbour]],
+            )
+            .max(smallest);
+            heap.push(Reverse(Candidate {
+                area: area[neighbour],
+                index: neighbour,
+            }));
+        }
+    }
+    kept_coords(coords, &kept)
+}
+
+type IndexedSegment = GeomWithData<Line<f64>, (usize, usize)>;
+
+/// Lines and rings of a geometry simplified together, each shortcut checked
+/// against everything else
+struct TopologySimplifier {
+    chains: Vec<Vec<Coord<f64>>>,
+    originals: RTree<IndexedSegment>,
+    replaced: Vec<Vec<bool>>,
+    shortcuts: RTree<Line<f64>>,
+}
+
+impl TopologySimplifier {
+    fn new(chains: Vec<Vec<Coord<f64>>>) -> Self {
+        let segments = chains
+            .iter()
+            .enumerate()
+            .flat_map(|(chain, coords)| {
+                coords.windows(2).enumerate().map(move |(i, pair)| {
+                    IndexedSegment::new(Line::new(pair[0], pair[1]), (chain, i))
+                })
+            })
+            .collect();
+        let replaced = chains
+            .iter()
+            .map(|coords| vec![false; coords.len().saturating_sub(1)])
+            .collect();
+        TopologySimplifier {
+            chains,
+            originals: RTree::bulk_load(segments),
+            replaced,
+            shortcuts: RTree::new(),
+        }
+    }
+
+    fn simplify(mut self, tolerance: f64) -> Vec<Vec<Coord<f64>>> {
+        let mut simplified = Vec::with_capacity(self.chains.len());
+        for chain in 0..self.chains.len() {
+            let coords = self.chains[chain].clone();
+            if coords.len() < 3 {
+                simplified.push(coords);
+                continue;
+            }
+            let mut kept = vec![false; coords.len()];
+            let anchors = anchors(&coords);
+            for anchor in &anchors {
+                kept[*anchor] = true;
+            }
+            for pair in anchors.windows(2) {
+                douglas_peucker(
+                    &coords,
+                    pair[0],
+                    pair[1],
+                    tolerance,
+                    &mut kept,
+                    &mut |first, last| self.accept(chain, first, last),
+                );
+            }
+            simplified.push(kept_coords(&coords, &kept));
+        }
+        simplified
+    }
+
+    /// Whether `chain` may replace the points between `first` and `last` by
+    /// a shortcut; an accepted shortcut is recorded
+    fn accept(&mut self, chain: usize, first: usize, last: usize) -> bool {
+        let coords = &self.chains[chain];
+        let shortcut = Line::new(coords[first], coords[last]);
+        let envelope = shortcut.envelope();
+
+        let crosses_original = self
+            .originals
+            .locate_in_envelope_intersecting(&envelope)
+            .any(|segment| {
+                let (other, index) = segment.data;
+                let replaced_here = other == chain && index >= first && index < last;
+                !replaced_here
+                    && !self.replaced[other][index]
+                    && conflicts(&shortcut, segment.geom())
+            });
+        if crosses_original
+            || self
+                .shortcuts
+                .locate_in_envelope_intersecting(&envelope)
+                .any(|other| conflicts(&shortcut, other))
+        {
+            return false;
+        }
+
+        // The area between the section and its shortcut must not hold
+        // another ring or line
+        let section = Polygon::new(LineString(coords[first..=last].to_vec()), Vec::new());
+        let swallows = section.bounding_rect().is_some_and(|bounds| {
+            self.chains.iter().enumerate().any(|(other, other_coords)| {
+                other != chain
+                    && other_coords.first().is_some_and(|coord| {
+                        bounds.intersects(coord) && section.contains(&Point(*coord))
+                    })
+            })
+        });
+        if swallows {
+            return false;
+        }
+
+        self.replaced[chain][first..last].fill(true);
+        self.shortcuts.insert(shortcut);
+        true
+    }
+}
+
+/// Whether a shortcut meets a segment anywhere but at its own ends, or runs
+/// along it
+fn conflicts(shortcut: &Line<f64>, segment: &Line<f64>) -> bool {
+    match line_intersection(*shortcut, *segment) {
+        None => false,
+        Some(LineIntersection::SinglePoint { intersection, .. }) => {
+            intersection != shortcut.start && intersection != shortcut.end
+        }
+        Some(LineIntersection::Collinear { intersection }) => {
+            intersection.start != intersection.end
+        }
+    }
+}
+
+/// Points a chain always keeps: its ends, plus for a closed ring the point
+/// farthest from the start and the point farthest from the line through
+/// both, so the ring keeps an area
+fn anchors(coords: &[Coord<f64>]) -> Vec<usize> {
+    let last = coords.len() - 1;
+    if !is_closed(coords) || coords.len() < 4 {
+        return vec![0, last];
+    }
+    let start = coords[0];
+    let (far, _) = farthest(coords, 1, last, |coord| {
+        (coord.x - start.x).hypot(coord.y - start.y)
+    });
+    let base = coords[far] - start;
+    let (side, _) = farthest(coords, 1, last, |coord| {
+        let offset = coord - start;
+        (base.x * offset.y - base.y * offset.x).abs()
+    });
+    let mut anchors = vec![0, far, side, last];
+    anchors.sort_unstable();
+    anchors.dedup();
+    anchors
+}
+
+/// Douglas-Peucker simplification that keeps the geometry's topology: no
+/// part starts crossing or touching another, no ring collapses, and holes
+/// stay inside their shells
+pub fn simplify_preserve_topology(geom: &Geometry, tolerance: f64) -> Geometry {
+    if tolerance <= 0.0 || geom.is_empty() {
+        return geom.clone();
+    }
+    let srid = geom.srid();
+    match geom {
+        Geometry::Zm(base, _) => simplify_preserve_topology(base, tolerance),
+        Geometry::Point(_, _) | Geometry::MultiPoint(_, _) => geom.clone(),
+        Geometry::LineString(line, _) => {
+            let mut chains = TopologySimplifier::new(vec![line.0.clone()]).simplify(tolerance);
+            Geometry::LineString(LineString(chains.remove(0)), srid)
+        }
+        Geometry::MultiLineString(lines, _) => {
+            let chains = lines.iter().map(|line| line.0.clone()).collect();
+            let simplified = TopologySimplifier::new(chains).simplify(tolerance);
+            Geometry::MultiLineString(
+                MultiLineString(simplified.into_iter().map(LineString).collect()),
+                srid,
+            )
+        }
+        Geometry::Polygon(polygon, _) => {
+            let polygons = simplify_polygons(std::slice::from_ref(polygon), tolerance);
+            Geometry::Polygon(polygons.into_iter().next().unwrap(), srid)
+        }
+        Geometry::MultiPolygon(polygons, _) => Geometry::MultiPolygon(
+            MultiPolygon(simplify_polygons(&polygons.0, tolerance)),
+            srid,
+        ),
+        Geometry::GeometryCollection(collection, _) => Geometry::GeometryCollection(
+            collection
+                .iter()
+                .map(|member| simplify_preserve_topology(member, tolerance))
+                .collect(),
+            srid,
+        ),
+    }
+}
+
+/// Simplify the rings of all polygons together, then reassemble them
+fn simplify_polygons(polygons: &[Polygon<f64>], tolerance: f64) -> Vec<Polygon<f64>> {
+    let chains = polygons
+        .iter()
+        .flat_map(|polygon| {
+            std::iter::once(polygon.exterior())
+                .chain(polygon.interiors())
+                .map(|ring| ring.0.clone())
+        })
+        .collect();
+    let mut rings = TopologySimplifier::new(chains)
+        .simplify(tolerance)
+        .into_iter()
+        .map(LineString);
+    polygons
+        .iter()
+        .map(|polygon| {
+            let exterior = rings.next().unwrap();
+            let interiors = rings.by_ref().take(polygon.interiors().len()).collect();
+            Polygon::new(exterior, interiors)
+        })
+        .collect()
+}
+
+/// PostgreSQL function for Douglas-Peucker simplification
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_simplify(
+    geom: Geometry,
+    tolerance: f64,
+    preserve_collapsed: default!(bool, false),
+) -> Option<Geometry> {
+    simplify(&geom, tolerance, preserve_collapsed)
+}
+
+/// PostgreSQL function for Visvalingam-Whyatt simplification
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_simplifyvw(geom: Geometry, tolerance: f64) -> Option<Geometry> {
+    simplify_vw(&geom, tolerance)
+}
+
+/// PostgreSQL function for topology-preserving simplification
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_simplifypreservetopology(geom: Geometry, tolerance: f64) -> Geometry {
+    simplify_preserve_topology(&geom, tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, geometry_is_valid};
+
+    fn npoints(geom: &Geometry) -> usize {
+        geom.coordinates().len()
+    }
+
+    #[test]
+    fn test_simplify_line() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 0.1, 2 -0.1, 3 0.05, 4 0, 5 3)")
+            .unwrap()
+            .with_srid(4326);
+        let simplified = simplify(&line, 0.5, false).unwrap();
+        assert_eq!(simplified.to_wkt(), "LINESTRING(0 0,4 0,5 3)");
+        assert_eq!(simplified.srid(), 4326);
+
+        // Zero tolerance keeps every point
+        assert_eq!(simplify(&line, 0.0, false).unwrap(), line);
+    }
+
+    #[test]
+    fn test_simplify_collapse() {
+        let small = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        assert!(simplify(&small, 10.0, false).is_none());
+        assert_eq!(simplify(&small, 10.0, true).unwrap(), small);
+
+        // A collapsing hole is dropped, the shell is kept
+        let holed = geometry_from_wkt(
+            "POLYGON((0 0, 100 0, 100 100, 0 100, 0 0), (10 10, 11 10, 11 11, 10 11, 10 10))",
+        )
+        .unwrap();
+        let Geometry::Polygon(polygon, _) = simplify(&holed, 5.0, false).unwrap() else {
+            panic!("expected a polygon");
+        };
+        assert!(polygon.interiors().is_empty());
+
+        // An empty member line is dropped, even when collapsed lines are kept
+        let lines = Geometry::MultiLineString(
+            MultiLineString(vec![
+                LineString(Vec::new()),
+                LineString::from(vec![(0.0, 0.0), (0.1, 0.0)]),
+            ]),
+            0,
+        );
+        assert_eq!(
+            simplify(&lines, 1.0, true).unwrap().to_wkt(),
+            "MULTILINESTRING((0 0,0.1 0))"
+        );
+    }
+
+    #[test]
+    fn test_simplify_vw() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 0.1, 2 -0.1, 3 0.05, 4 0, 5 3)")
+            .unwrap()
+            .with_srid(4326);
+        // The wiggles span triangles of 0.15 to 0.175
+        let simplified = simplify_vw(&line, 0.5).unwrap();
+        assert_eq!(simplified.to_wkt(), "LINESTRING(0 0,4 0,5 3)");
+        assert_eq!(simplified.srid(), 4326);
+        assert_eq!(simplify_vw(&line, 0.1).unwrap(), line);
+
+        // A far but thin spike goes, unlike with Douglas-Peucker
+        let spike = geometry_from_wkt("LINESTRING(0 0, 5 0, 5.01 10, 5.02 0, 10 0)").unwrap();
+        assert_eq!(npoints(&simplify_vw(&spike, 1.0).unwrap()), 2);
+        assert_eq!(npoints(&simplify(&spike, 1.0, false).unwrap()), 5);
+    }
+
+    #[test]
+    fn test_simplify_vw_keeps_rings() {
+        let polygon = geometry_from_wkt(
+            "POLYGON((0 0, 100 0, 100 100, 0 100, 0 0), (10 10, 11 10, 11 11, 10 11, 10 10))",
+        )
+        .unwrap();
+        let simplified = simplify_vw(&polygon, 1e6).unwrap();
+        let Geometry::Polygon(simplified, _) = simplified else {
+            panic!("expected a polygon");
+        };
+        assert_eq!(simplified.exterior().0.len(), 4);
+        assert_eq!(simplified.interiors().len(), 1);
+        assert_eq!(simplified.interiors()[0].0.len(), 4);
+    }
+
+    #[test]
+    fn test_preserve_topology_keeps_rings() {
+        let small = geometry_from_wkt("POLYGON((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        // Reduced to a triangle at most
+        let simplified = simplify_preserve_topology(&small, 10.0);
+        assert_eq!(npoints(&simplified), 4);
+        assert!(geometry_is_valid(&simplified));
+    }
+
+    #[test]
+    fn test_preserve_topology_keeps_hole_inside() {
+        // A small peak of the shell holds a hole; plain Douglas-Peucker cuts
+        // the peak off, leaving the hole outside
+        let polygon = geometry_from_wkt(
+            "POLYGON((0 0, 10 0, 10 10, 6 10, 5 13, 4 10, 0 10, 0 0), \
+             (4.9 10.5, 5.1 10.5, 5.1 11, 4.9 11, 4.9 10.5))",
+        )
+        .unwrap();
+        let Geometry::Polygon(plain, _) = simplify(&polygon, 5.0, false).unwrap() else {
+            panic!("expected a polygon");
+        };
+        assert!(plain.interiors().is_empty());
+
+        let preserved = simplify_preserve_topology(&polygon, 5.0);
+        assert!(geometry_is_valid(&preserved));
+        let Geometry::Polygon(preserved, _) = preserved else {
+            panic!("expected a polygon");
+        };
+        assert_eq!(preserved.interiors().len(), 1);
+    }
+
+    #[test]
+    fn test_preserve_topology_lines_do_not_cross() {
+        // The wiggle of the first line dips below the second one
+        let lines = geometry_from_wkt("MULTILINESTRING((0 0, 5 -3, 10 0), (4 -1, 6 -1))").unwrap();
+        let plain = simplify(&lines, 5.0, false).unwrap();
+        assert_eq!(npoints(&plain), 4);
+
+        let preserved = simplify_preserve_topology(&lines, 5.0);
+        assert_eq!(npoints(&preserved), 5);
+    }
+}
@@ -0,0 +1,668 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::orient::{Direction, Orient};
+use geo_types::{Coord, Line, LineString, MultiLineString, Polygon};
+use pgrx::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Straight skeleton (ST_StraightSkeleton, ST_ApproximateMedialAxis)
+//
+// The skeleton is traced by shrinking the polygon: every edge moves inward at
+// unit speed and every vertex slides along the bisector of its two edges. An
+// edge event is an edge shrinking to nothing, its two vertices merging; a
+// split event is a reflex vertex running into an opposite edge, cutting the
+// shrinking ring in two (or, for a hole, joining it to the outer ring). The
+// paths travelled by the vertices are the skeleton arcs.
+//
+// A vertex is stored as `anchor + t * velocity`, so it can be placed at any
+// time t. Events are predicted when vertices are created but checked against
+// the wavefront only when they come up, as the wavefront may have changed in
+// between. Split candidates of a reflex vertex are tried one at a time, the
+// earliest first.
+//
+// When two opposite parallel edges meet, their offsets coincide along a whole
+// segment; the vertex joining them slides to the nearer end of that segment,
+// which yields the skeleton arc running between the two edges.
+
+/// Edges whose normals are closer than this to opposite are parallel
+const ANTIPARALLEL: f64 = 1e-10;
+
+/// Relative tolerance on coordinates and event times
+const TOLERANCE: f64 = 1e-9;
+
+/// Original polygon edge, with the polygon interior on its left
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    start: Coord<f64>,
+    dir: Coord<f64>,
+    normal: Coord<f64>,
+}
+
+impl Edge {
+    fn new(start: Coord<f64>, end: Coord<f64>) -> Self {
+        let delta = end - start;
+        let length = delta.x.hypot(delta.y);
+        let dir = delta / length;
+        Edge {
+            start,
+            dir,
+            normal: Coord {
+                x: -dir.y,
+                y: dir.x,
+            },
+        }
+    }
+}
+
+/// Wavefront vertex between its incoming (left) and outgoing (right) edge
+#[derive(Debug, Clone)]
+struct Vertex {
+    origin: Coord<f64>,
+    anchor: Coord<f64>,
+    velocity: Coord<f64>,
+    edge_left: usize,
+    edge_right: usize,
+    prev: usize,
+    next: usize,
+    alive: bool,
+    boundary: bool,
+}
+
+impl Vertex {
+    fn at(&self, time: f64) -> Coord<f64> {
+        self.anchor + self.velocity * time
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EventKind {
+    /// The edge between two neighbouring vertices vanishes
+    Edge { left: usize, right: usize },
+    /// A reflex vertex reaches the offset of an edge
+    Split { vertex: usize, edge: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Event {
+    time: f64,
+    kind: EventKind,
+}
+
+impl Eq for Event {}
+
+impl Ord for Event {
+    // Earliest first, edge events before split events at the same time
+    fn cmp(&self, other: &Self) -> Ordering {
+        let rank = |kind: &EventKind| match kind {
+            EventKind::Edge { .. } => 0,
+            EventKind::Split { .. } => 1,
+        };
+        other
+            .time
+            .total_cmp(&self.time)
+            .then_with(|| rank(&other.kind).cmp(&rank(&self.kind)))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Skeleton arc, flagged when it starts at a vertex of the polygon
+#[derive(Debug, Clone, Copy)]
+struct Arc {
+    line: Line<f64>,
+    boundary: bool,
+}
+
+fn dot(a: Coord<f64>, b: Coord<f64>) -> f64 {
+    a.x * b.x + a.y * b.y
+}
+
+fn cross(a: Coord<f64>, b: Coord<f64>) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+struct Wavefront {
+    edges: Vec<Edge>,
+    vertices: Vec<Vertex>,
+    /// Vertices per edge they start, to find the live pieces of an edge
+    edge_starts: Vec<Vec<usize>>,
+    /// Split candidates per vertex, latest first so the next one is popped
+    candidates: Vec<Vec<(f64, usize)>>,
+    events: BinaryHeap<Event>,
+    arcs: Vec<Arc>,
+    now: f64,
+    eps: f64,
+}
+
+impl Wavefront {
+    fn new(polygon: &Polygon<f64>) -> Self {
+        let oriented = polygon.orient(Direction::Default);
+        let rings: Vec<Vec<Coord<f64>>> = std::iter::once(oriented.exterior())
+            .chain(oriented.interiors())
+            .map(ring_coords)
+            .filter(|ring| ring.len() >= 3)
+            .collect();
+
+        let (mut min, mut max) = (
+            Coord::from((f64::MAX, f64::MAX)),
+            Coord::from((f64::MIN, f64::MIN)),
+        );
+        for coord in rings.iter().flatten() {
+            min = Coord::from((min.x.min(coord.x), min.y.min(coord.y)));
+            max = Coord::from((max.x.max(coord.x), max.y.max(coord.y)));
+        }
+        let extent = (max.x - min.x).max(max.y - min.y).max(1.0);
+
+        let mut wavefront = Wavefront {
+            edges: Vec::new(),
+            vertices: Vec::new(),
+            edge_starts: Vec::new(),
+            candidates: Vec::new(),
+            events: BinaryHeap::new(),
+            arcs: Vec::new(),
+            now: 0.0,
+            eps: extent * TOLERANCE,
+        };
+
+        for ring in &rings {
+            let first_edge = wavefront.edges.len();
+            let first_vertex = wavefront.vertices.len();
+            let n = ring.len();
+            for i in 0..n {
+                wavefront.edges.push(Edge::new(ring[i], ring[(i + 1) % n]));
+                wavefront.edge_starts.push(Vec::new());
+            }
+            for (i, coord) in ring.iter().enumerate() {
+                wavefront.vertices.push(Vertex {
+                    origin: *coord,
+                    anchor: *coord,
+                    velocity: Coord::zero(),
+                    edge_left: first_edge + (i + n - 1) % n,
+                    edge_right: first_edge + i,
+                    prev: first_vertex + (i + n - 1) % n,
+                    next: first_vertex + (i + 1) % n,
+                    alive: true,
+                    boundary: true,
+                });
+                wavefront.candidates.push(Vec::new());
+                wavefront.edge_starts[first_edge + i].push(first_vertex + i);
+            }
+        }
+
+        // Neighbours need their velocities before events are predicted;
+        // collapses of the input itself (zero-angle spikes) are resolved
+        // before anything moves
+        for vertex in 0..wavefront.vertices.len() {
+            wavefront.set_velocity(vertex);
+        }
+        for vertex in 0..wavefront.vertices.len() {
+            if wavefront.vertices[vertex].alive {
+                wavefront.settle(vertex);
+            }
+        }
+        wavefront
+    }
+
+    fn run(mut self) -> Result<Vec<Arc>, RostGisError> {
+        // Every event consumes a vertex; stale events are discarded in O(1)
+        let n = self.vertices.len() + 1;
+        let mut budget = 64 * n * n + 1024;
+
+        while let Some(event) = self.events.pop() {
+            // Unit tests run outside a backend, with no interrupts to check
+            #[cfg(not(test))]
+            check_for_interrupts!();
+            budget = budget.checked_sub(1).ok_or_else(|| {
+                RostGisError::new("Straight skeleton did not converge on this polygon")
+            })?;
+            match event.kind {
+                EventKind::Edge { left, right } => self.edge_event(event.time, left, right),
+                EventKind::Split { vertex, edge } => self.split_event(event.time, vertex, edge),
+            }
+        }
+
+        // Anything left over is a degenerate remainder; close it where it is
+        for vertex in 0..self.vertices.len() {
+            if self.vertices[vertex].alive {
+                let end = self.vertices[vertex].at(self.now);
+                self.finish(vertex, end);
+            }
+        }
+
+        let eps = self.eps;
+        Ok(self
+            .arcs
+            .into_iter()
+            .filter(|arc| {
+                let delta = arc.line.delta();
+                delta.x.hypot(delta.y) > eps
+            })
+            .collect())
+    }
+
+    fn edge_event(&mut self, time: f64, left: usize, right: usize) {
+        if !self.vertices[left].alive
+            || !self.vertices[right].alive
+            || self.vertices[left].next != right
+        {
+            return;
+        }
+        self.now = self.now.max(time);
+        let point = self.vertices[left].at(self.now);
+        let prev = self.vertices[left].prev;
+        let next = self.vertices[right].next;
+
+        self.finish(left, point);
+        self.finish(right, point);
+        if next == left {
+            return;
+        }
+        if next == prev {
+            // Last triangle of a ring: all three meet here
+            self.finish(next, point);
+            return;
+        }
+
+        let edge_left = self.vertices[left].edge_left;
+        let edge_right = self.vertices[right].edge_right;
+        let vertex = self.add_vertex(point, edge_left, edge_right, prev, next);
+        self.settle(vertex);
+    }
+
+    fn split_event(&mut self, time: f64, vertex: usize, edge: usize) {
+        if !self.vertices[vertex].alive {
+            return;
+        }
+        let point = self.vertices[vertex].at(time);
+        let Some(start) = self.find_piece(vertex, edge, time, point) else {
+            self.next_candidate(vertex);
+            return;
+        };
+        self.now = self.now.max(time);
+
+        let end = self.vertices[start].next;
+        let prev = self.vertices[vertex].prev;
+        let next = self.vertices[vertex].next;
+        let edge_left = self.vertices[vertex].edge_left;
+        let edge_right = self.vertices[vertex].edge_right;
+        self.finish(vertex, point);
+
+        let first = self.add_vertex(point, edge_left, edge, prev, end);
+        let second = self.add_vertex(point, edge, edge_right, start, next);
+        self.settle(first);
+        self.settle(second);
+    }
+
+    /// Live piece of `edge` hit by `vertex` at `point`, given by the vertex
+    /// starting it
+    fn find_piece(
+        &self,
+        vertex: usize,
+        edge: usize,
+        time: f64,
+        point: Coord<f64>,
+    ) -> Option<usize> {
+        let dir = self.edges[edge].dir;
+        let along = dot(point, dir);
+        let v = &self.vertices[vertex];
+        self.edge_starts[edge].iter().copied().find(|&start| {
+            let s = &self.vertices[start];
+            if !s.alive || s.edge_right != edge {
+                return false;
+            }
+            let end = s.next;
+            if start == vertex || end == vertex || start == v.next || end == v.prev {
+                return false;
+            }
+            let from = dot(s.at(time), dir);
+            let to = dot(self.vertices[end].at(time), dir);
+            along >= from - self.eps && along <= to + self.eps
+        })
+    }
+
+    fn add_vertex(
+        &mut self,
+        point: Coord<f64>,
+        edge_left: usize,
+        edge_right: usize,
+        prev: usize,
+        next: usize,
+    ) -> usize {
+        let vertex = self.vertices.len();
+        self.vertices.push(Vertex {
+            origin: point,
+            anchor: point,
+            velocity: Coord::zero(),
+            edge_left,
+            edge_right,
+            prev,
+            next,
+            alive: true,
+            boundary: false,
+        });
+        self.candidates.push(Vec::new());
+        self.edge_starts[edge_right].push(vertex);
+        self.vertices[prev].next = vertex;
+        self.vertices[next].prev = vertex;
+        vertex
+    }
+
+    /// Retire a vertex, recording its path up to `end`
+    fn finish(&mut self, vertex: usize, end: Coord<f64>) {
+        let v = &mut self.vertices[vertex];
+        v.alive = false;
+        self.arcs.push(Arc {
+            line: Line::new(v.origin, end),
+            boundary: v.boundary,
+        });
+    }
+
+    /// Set a new vertex in motion and predict its events, first resolving
+    /// rings reduced to two vertices and vertices between opposite edges
+    fn settle(&mut self, vertex: usize) {
+        let mut pending = vec![vertex];
+        while let Some(vertex) = pending.pop() {
+            if !self.vertices[vertex].alive {
+                continue;
+            }
+            let Vertex {
+                origin,
+                edge_left,
+                edge_right,
+                prev,
+                next,
+                ..
+            } = self.vertices[vertex];
+
+            if prev == next {
+                // Two vertices left: the ring has collapsed onto a segment
+                let other = self.vertices[next].at(self.now);
+                self.finish(next, other);
+                self.finish(vertex, other);
+                continue;
+            }
+
+            if self.set_velocity(vertex) {
+                self.predict_edge(prev, vertex);
+                self.predict_edge(vertex, next);
+                let (a, b) = (self.edges[edge_left], self.edges[edge_right]);
+                if cross(a.dir, b.dir) < 0.0 {
+                    self.predict_splits(vertex);
+                }
+                continue;
+            }
+
+            // Opposite edges whose offsets coincide: slide to the nearer end
+            // of the shared segment, absorbing the neighbour there
+            let prev_point = self.vertices[prev].at(self.now);
+            let next_point = self.vertices[next].at(self.now);
+            let distance = |point: Coord<f64>| (point - origin).x.hypot((point - origin).y);
+            let slid = if distance(next_point) <= distance(prev_point) {
+                let after = self.vertices[next].next;
+                let edge_after = self.vertices[next].edge_right;
+                self.finish(vertex, next_point);
+                self.finish(next, next_point);
+                self.add_vertex(next_point, edge_left, edge_after, prev, after)
+            } else {
+                let before = self.vertices[prev].prev;
+                let edge_before = self.vertices[prev].edge_left;
+                self.finish(vertex, prev_point);
+                self.finish(prev, prev_point);
+                self.add_vertex(prev_point, edge_before, edge_right, before, next)
+            };
+            pending.push(slid);
+        }
+    }
+
+    /// Velocity keeping a vertex on the offsets of both its edges; false
+    /// when the edges are opposite and no such velocity exists
+    fn set_velocity(&mut self, vertex: usize) -> bool {
+        let v = &self.vertices[vertex];
+        let (a, b) = (self.edges[v.edge_left], self.edges[v.edge_right]);
+        let denominator = 1.0 + dot(a.normal, b.normal);
+        if denominator <= ANTIPARALLEL {
+            return false;
+        }
+        let velocity = (a.normal + b.normal) / denominator;
+        let v = &mut self.vertices[vertex];
+        v.velocity = velocity;
+        v.anchor = v.origin - velocity * self.now;
+        true
+    }
+
+    fn predict_edge(&mut self, left: usize, right: usize) {
+        let (l, r) = (&self.vertices[left], &self.vertices[right]);
+        if !l.alive || !r.alive || l.next != right {
+            return;
+        }
+        let dir = self.edges[l.edge_right].dir;
+        let closing = dot(l.velocity - r.velocity, dir);
+        if closing <= 0.0 {
+            return;
+        }
+        let time = dot(r.anchor - l.anchor, dir) / closing;
+        if time >= self.now - self.eps {
+            self.events.push(Event {
+                time: time.max(self.now),
+                kind: EventKind::Edge { left, right },
+            });
+        }
+    }
+
+    fn predict_splits(&mut self, vertex: usize) {
+        let v = &self.vertices[vertex];
+        let mut candidates: Vec<(f64, usize)> = self
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|(edge, _)| *edge != v.edge_left && *edge != v.edge_right)
+            .filter_map(|(edge, e)| {
+                let approach = 1.0 - dot(v.velocity, e.normal);
+                if approach <= 0.0 {
+                    return None;
+                }
+                let time = dot(v.anchor - e.start, e.normal) / approach;
+                (time >= self.now - self.eps).then_some((time.max(self.now), edge))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.candidates[vertex] = candidates;
+        self.next_candidate(vertex);
+    }
+
+    fn next_candidate(&mut self, vertex: usize) {
+        if let Some((time, edge)) = self.candidates[vertex].pop() {
+            self.events.push(Event {
+                time,
+                kind: EventKind::Split { vertex, edge },
+            });
+        }
+    }
+}
+
+/// Distinct coordinates of a closed ring, without the closing point
+fn ring_coords(ring: &LineString<f64>) -> Vec<Coord<f64>> {
+    let mut coords: Vec<Coord<f64>> = Vec::with_capacity(ring.0.len());
+    for coord in &ring.0 {
+        if coords.last() != Some(coord) {
+            coords.push(*coord);
+        }
+    }
+    while coords.len() > 1 && coords.first() == coords.last() {
+        coords.pop();
+    }
+    coords
+}
+
+fn polygons(geom: &Geometry, function: &str) -> Result<Vec<Polygon<f64>>, RostGisError> {
+    match geom {
+        Geometry::Polygon(polygon, _) => Ok(vec![polygon.clone()]),
+        Geometry::MultiPolygon(multi_polygon, _) => Ok(multi_polygon.0.clone()),
+        _ => Err(RostGisError::new(&format!(
+            "{} requires a Polygon or MultiPolygon, got {}",
+            function,
+            geom.geometry_type()
+        ))),
+    }
+}
+
+fn skeleton_arcs(geom: &Geometry, function: &str) -> Result<Vec<Arc>, RostGisError> {
+    let mut arcs = Vec::new();
+    for polygon in polygons(geom, function)? {
+        arcs.extend(Wavefront::new(&polygon).run()?);
+    }
+    Ok(arcs)
+}
+
+fn arcs_to_geometry(arcs: impl Iterator<Item = Arc>, srid: i32) -> Geometry {
+    let lines = arcs
+        .map(|arc| LineString::from(vec![arc.line.start, arc.line.end]))
+        .collect();
+    Geometry::MultiLineString(MultiLineString(lines), srid)
+}
+
+/// Straight skeleton of a polygon as a MultiLineString of its arcs, including
+/// the arcs running in from the polygon vertices
+pub fn straight_skeleton(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    let arcs = skeleton_arcs(geom, "ST_StraightSkeleton")?;
+    Ok(arcs_to_geometry(arcs.into_iter(), geom.srid()))
+}
+
+/// Approximate medial axis: the straight skeleton without the arcs that
+/// touch the polygon boundary, e.g. the centerline of a road casing
+pub fn approximate_medial_axis(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    let arcs = skeleton_arcs(geom, "ST_ApproximateMedialAxis")?;
+    Ok(arcs_to_geometry(
+        arcs.into_iter().filter(|arc| !arc.boundary),
+        geom.srid(),
+    ))
+}
+
+/// PostgreSQL function for the straight skeleton of a polygon
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_straightskeleton(
+    geom: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(straight_skeleton(&geom)?)
+}
+
+/// PostgreSQL function for the approximate medial axis of a polygon
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_approximatemedialaxis(
+    geom: Geometry,
+) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(approximate_medial_axis(&geom)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+
+    fn segments(geom: &Geometry) -> Vec<((f64, f64), (f64, f64))> {
+        let Geometry::MultiLineString(lines, _) = geom else {
+            panic!("expected a MultiLineString");
+        };
+        let round = |c: Coord<f64>| ((c.x * 1e6).round() / 1e6, (c.y * 1e6).round() / 1e6);
+        let mut segments: Vec<_> = lines
+            .0
+            .iter()
+            .map(|line| {
+                let (a, b) = (round(line.0[0]), round(line.0[1]));
+                if a <= b {
+                    (a, b)
+                } else {
+                    (b, a)
+                }
+            })
+            .collect();
+        segments.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        segments.dedup();
+        segments
+    }
+
+    fn total_length(geom: &Geometry) -> f64 {
+        segments(geom)
+            .iter()
+            .map(|((x1, y1), (x2, y2))| (x2 - x1).hypot(y2 - y1))
+            .sum()
+    }
+
+    #[test]
+    fn test_square_skeleton() {
+        let square = geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))")
+            .unwrap()
+            .with_srid(3857);
+        let skeleton = straight_skeleton(&square).unwrap();
+        assert_eq!(skeleton.srid(), 3857);
+        assert_eq!(
+            segments(&skeleton),
+            vec![
+                ((0.0, 0.0), (2.0, 2.0)),
+                ((0.0, 4.0), (2.0, 2.0)),
+                ((2.0, 2.0), (4.0, 0.0)),
+                ((2.0, 2.0), (4.0, 4.0)),
+            ]
+        );
+        assert!(segments(&approximate_medial_axis(&square).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_rectangle_medial_axis() {
+        // Clockwise input is reoriented
+        let rectangle = geometry_from_wkt("POLYGON((0 0, 0 2, 10 2, 10 0, 0 0))").unwrap();
+        assert_eq!(segments(&straight_skeleton(&rectangle).unwrap()).len(), 5);
+        assert_eq!(
+            segments(&approximate_medial_axis(&rectangle).unwrap()),
+            vec![((1.0, 1.0), (9.0, 1.0))]
+        );
+    }
+
+    #[test]
+    fn test_reflex_vertex() {
+        // L-shaped casing of two 2-wide arms; the medial axis follows both
+        let l_shape =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 2, 2 2, 2 10, 0 10, 0 0))").unwrap();
+        let axis = approximate_medial_axis(&l_shape).unwrap();
+        let segments = segments(&axis);
+        assert!(segments.contains(&((1.0, 1.0), (9.0, 1.0))));
+        assert!(segments.contains(&((1.0, 1.0), (1.0, 9.0))));
+        for ((x1, y1), (x2, y2)) in &segments {
+            // Every axis point stays on the centerline of an arm
+            assert!((y1 - 1.0).abs() < 1e-6 || (x1 - 1.0).abs() < 1e-6);
+            assert!((y2 - 1.0).abs() < 1e-6 || (x2 - 1.0).abs() < 1e-6);
+        }
+        assert!((total_length(&axis) - 16.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_polygon_with_hole() {
+        let ring =
+            geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (4 4, 6 4, 6 6, 4 6, 4 4))")
+                .unwrap();
+        let axis = approximate_medial_axis(&ring).unwrap();
+        // The axis is the square halfway between the two rings
+        assert!((total_length(&axis) - 24.0).abs() < 1e-6);
+        for ((x1, y1), (x2, y2)) in segments(&axis) {
+            for (x, y) in [(x1, y1), (x2, y2)] {
+                assert!(
+                    (x - 2.0).abs() < 1e-6
+                        || (x - 8.0).abs() < 1e-6
+                        || (y - 2.0).abs() < 1e-6
+                        || (y - 8.0).abs() < 1e-6
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_not_a_polygon() {
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap();
+        assert!(straight_skeleton(&line).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::geometry::Geometry;
+use crate::utils::{format_number, RostGisError};
+use geo::orient::Direction;
+use geo::Orient;
+use geo_types::LineString;
+use pgrx::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Polyhedral surfaces and solids (polyhedralsurface, ST_Volume)
+//
+// The geometry type stores x and y only, so 3D city models such as building
+// volumes have a type of their own: a polyhedral surface of planar faces in
+// x, y and z, each face a polygon whose rings are closed. It is written as
+// (E)WKT, the z coordinates optional:
+//
+//   SELECT 'POLYHEDRALSURFACE Z (((0 0 0,0 1 0,1 1 0,1 0 0,0 0 0)), ...)'::polyhedralsurface;
+//
+// A surface is closed, and so bounds a solid, when every edge of a face is
+// shared by exactly one other face running it the other way. ST_Volume is
+// the volume of such a solid and 0 for an open surface, as in PostGIS:
+//
+//   SELECT id, ST_Volume(model) FROM buildings WHERE ST_IsClosed(model);
+//
+// ST_Extrude builds the solid of a building from its footprint and height,
+// with the footprint as the floor at z = 0, a roof at the height and a wall
+// along every edge of the footprint's rings:
+//
+//   SELECT id, ST_Extrude(footprint, height) FROM buildings;
+
+/// Vertex of a polyhedral surface
+pub type Point3D = [f64; 3];
+
+/// Surface of planar polygon faces in three dimensions
+///
+/// Each face is a list of rings, the exterior first, each ring closed.
+#[derive(Debug, Clone, PartialEq, PostgresType, Serialize, Deserialize)]
+#[inoutfuncs]
+pub struct PolyhedralSurface {
+    pub faces: Vec<Vec<Vec<Point3D>>>,
+    pub srid: i32,
+}
+
+impl PolyhedralSurface {
+    pub fn new(faces: Vec<Vec<Vec<Point3D>>>, srid: i32) -> Self {
+        PolyhedralSurface { faces, srid }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    /// Edges of every ring of every face, in ring order, leaving out those of
+    /// repeated vertices
+    fn edges(&self) -> impl Iterator<Item = (Point3D, Point3D)> + '_ {
+        self.faces
+            .iter()
+            .flatten()
+            .flat_map(|ring| ring.windows(2).map(|pair| (pair[0], pair[1])))
+            .filter(|(start, end)| start != end)
+    }
+
+    /// Check if the surface bounds a solid: every edge is shared by exactly
+    /// two faces, which run it in opposite directions
+    pub fn is_closed(&self) -> bool {
+        // -0.0 and 0.0 are the same vertex
+        let key = |point: Point3D| point.map(|value| (value + 0.0).to_bits());
+        let mut edges: HashMap<([u64; 3], [u64; 3]), (u32, i32)> = HashMap::new();
+        for (start, end) in self.edges() {
+            let (start, end) = (key(start), key(end));
+            let (edge, direction) = if start < end {
+                ((start, end), 1)
+            } else {
+                ((end, start), -1)
+            };
+            let (uses, balance) = edges.entry(edge).or_default();
+            *uses += 1;
+            *balance += direction;
+        }
+        !edges.is_empty()
+            && edges
+                .values()
+                .all(|&(uses, balance)| uses == 2 && balance == 0)
+    }
+
+    /// Volume of the solid the surface bounds, 0 if it is not closed
+    ///
+    /// Each face adds the signed volume of the cone from the origin to it,
+    /// computed over a fan of triangles of each of its rings, so the faces
+    /// may be concave and have holes.
+    pub fn volume(&self) -> f64 {
+        if !self.is_closed() {
+            return 0.0;
+        }
+        let mut volume = 0.0;
+        for ring in self.faces.iter().flatten() {
+            let Some((&apex, rest)) = ring.split_first() else {
+                continue;
+            };
+            for pair in rest.windows(2) {
+                volume += determinant(apex, pair[0], pair[1]);
+            }
+        }
+        (volume / 6.0).abs()
+    }
+
+    /// Parse (E)WKT such as 'POLYHEDRALSURFACE Z (((0 0 0,1 0 0,0 1 0,0 0 0)))'
+    pub fn parse(input: &str) -> Result<Self, RostGisError> {
+        let invalid = |reason: &str| {
+            RostGisError::new(&format!(
+                "Invalid polyhedralsurface ({}): {}",
+                reason, input
+            ))
+        };
+        let mut text = input.trim();
+        let mut srid = 0;
+        if let Some((prefix, rest)) = text.split_once(';') {
+            srid = prefix
+                .trim()
+                .strip_prefix("SRID=")
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| invalid("bad SRID"))?;
+            text = rest.trim();
+        }
+
+        const TAG: &str = "POLYHEDRALSURFACE";
+        if !text
+            .get(..TAG.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(TAG))
+        {
+            return Err(invalid("expected POLYHEDRALSURFACE"));
+        }
+        text = text[TAG.len()..].trim_start();
+        if text.starts_with(['Z', 'z']) {
+            text = text[1..].trim_start();
+        }
+        if text.eq_ignore_ascii_case("EMPTY") {
+            return Ok(PolyhedralSurface::new(Vec::new(), srid));
+        }
+
+        let faces = split_list(text)
+            .ok_or_else(|| invalid("unbalanced parentheses"))?
+            .into_iter()
+            .map(|face| {
+                split_list(face)
+                    .ok_or_else(|| invalid("unbalanced parentheses"))?
+                    .into_iter()
+                    .map(|ring| parse_ring(ring).ok_or_else(|| invalid("bad ring")))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PolyhedralSurface::new(faces, srid))
+    }
+
+    /// Extended WKT of the surface
+    pub fn to_ewkt(&self) -> String {
+        let prefix = if self.srid != 0 {
+            format!("SRID={};", self.srid)
+        } else {
+            String::new()
+        };
+        if self.is_empty() {
+            return format!("{}POLYHEDRALSURFACE Z EMPTY", prefix);
+        }
+        let ring = |ring: &Vec<Point3D>| {
+            let points: Vec<String> = ring
+                .iter()
+                .map(|point| point.map(|value| format_number(value, None)).join(" "))
+                .collect();
+            format!("({})", points.join(","))
+        };
+        let faces: Vec<String> = self
+            .faces
+            .iter()
+            .map(|face| format!("({})", face.iter().map(ring).collect::<Vec<_>>().join(",")))
+            .collect();
+        format!("{}POLYHEDRALSURFACE Z ({})", prefix, faces.join(","))
+    }
+}
+
+/// Solid swept by polygons moved up by `height` (ST_Extrude)
+///
+/// The faces are oriented with their normals pointing out of the solid for
+/// a positive height. A negative height extrudes downwards.
+pub fn extrude(geom: &Geometry, height: f64) -> Result<PolyhedralSurface, RostGisError> {
+    if !height.is_finite() {
+        return Err(RostGisError::new("Extrusion height must be finite"));
+    }
+    let polygons = match geom {
+        Geometry::Polygon(polygon, _) => vec![polygon.clone()],
+        Geometry::MultiPolygon(multipolygon, _) => multipolygon.0.clone(),
+        other => {
+            return Err(RostGisError::new(&format!(
+                "ST_Extrude requires polygons, got {}",
+                other.geometry_type()
+            )))
+        }
+    };
+
+    let at = |ring: &LineString<f64>, z: f64| -> Vec<Point3D> {
+        ring.0.iter().map(|c| [c.x, c.y, z]).collect()
+    };
+    let mut faces = Vec::new();
+    for polygon in polygons {
+        if polygon.exterior().0.is_empty() {
+            continue;
+        }
+        // Counter-clockwise shells and clockwise holes face up
+        let polygon = polygon.orient(Direction::Default);
+        let rings: Vec<&LineString<f64>> = std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .collect();
+
+        let floor = rings
+            .iter()
+            .map(|ring| {
+                let mut floor = at(ring, 0.0);
+                floor.reverse();
+                floor
+            })
+            .collect();
+        faces.push(floor);
+        faces.push(rings.iter().map(|ring| at(ring, height)).collect());
+        for ring in &rings {
+            for pair in ring.0.windows(2).filter(|pair| pair[0] != pair[1]) {
+                let (a, b) = (pair[0], pair[1]);
+                faces.push(vec![vec![
+                    [a.x, a.y, 0.0],
+                    [b.x, b.y, 0.0],
+                    [b.x, b.y, height],
+                    [a.x, a.y, height],
+                    [a.x, a.y, 0.0],
+                ]]);
+            }
+        }
+    }
+    Ok(PolyhedralSurface::new(faces, geom.srid()))
+}
+
+/// Determinant of three vectors, six times the signed volume of the
+/// tetrahedron they span from the origin
+fn determinant(a: Point3D, b: Point3D, c: Point3D) -> f64 {
+    a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+        + a[2] * (b[0] * c[1] - b[1] * c[0])
+}
+
+/// Split '(a, b, ...)' into its comma separated items, commas inside nested
+/// parentheses excluded
+fn split_list(text: &str) -> Option<Vec<&str>> {
+    let body = text.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut items = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(body[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        if depth < 0 {
+            return None;
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    items.push(body[start..].trim());
+    Some(items)
+}
+
+/// Parse a closed ring '(x y z, ...)' of at least four points, z defaulting
+/// to 0
+fn parse_ring(text: &str) -> Option<Vec<Point3D>> {
+    let ring = text
+        .trim()
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .map(|point| {
+            let values: Vec<f64> = point
+                .split_whitespace()
+                .map(|value| value.parse().ok())
+                .collect::<Option<_>>()?;
+            match values[..] {
+                [x, y] => Some([x, y, 0.0]),
+                [x, y, z] => Some([x, y, z]),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<Point3D>>>()?;
+    (ring.len() >= 4 && ring.first() == ring.last()).then_some(ring)
+}
+
+impl pgrx::InOutFuncs for PolyhedralSurface {
+    fn input(input: &std::ffi::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        let input_str = input
+            .to_str()
+            .expect("Invalid UTF-8 in polyhedralsurface input");
+        match PolyhedralSurface::parse(input_str) {
+            Ok(surface) => surface,
+            Err(e) => error!("{}", e.message),
+        }
+    }
+
+    fn output(&self, buffer: &mut pgrx::StringInfo) {
+        buffer.push_str(&self.to_ewkt());
+    }
+}
+
+/// Volume of the solid a closed polyhedral surface bounds, 0 for an open
+/// surface
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_volume(surface: PolyhedralSurface) -> f64 {
+    surface.volume()
+}
+
+/// Solid of a polygon footprint extruded up by a height
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_extrude(
+    geom: Geometry,
+    height: f64,
+) -> Result<PolyhedralSurface, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(extrude(&geom, height)?)
+}
+
+/// Check if a polyhedral surface is closed, bounding a solid
+#[pg_extern(immutable, strict, parallel_safe, name = "st_isclosed")]
+fn st_isclosed_polyhedralsurface(surface: PolyhedralSurface) -> bool {
+    surface.is_closed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = "POLYHEDRALSURFACE Z (
+        ((0 0 0,0 1 0,1 1 0,1 0 0,0 0 0)),
+        ((0 0 1,1 0 1,1 1 1,0 1 1,0 0 1)),
+        ((0 0 0,1 0 0,1 0 1,0 0 1,0 0 0)),
+        ((1 0 0,1 1 0,1 1 1,1 0 1,1 0 0)),
+        ((1 1 0,0 1 0,0 1 1,1 1 1,1 1 0)),
+        ((0 1 0,0 0 0,0 0 1,0 1 1,0 1 0)))";
+
+    #[test]
+    fn test_parse_and_print() {
+        let cube = PolyhedralSurface::parse(CUBE).unwrap();
+        assert_eq!(cube.faces.len(), 6);
+        assert_eq!(PolyhedralSurface::parse(&cube.to_ewkt()).unwrap(), cube);
+
+        let face =
+            PolyhedralSurface::parse("SRID=3857;polyhedralsurface(((0 0,1 0,0 1,0 0)))").unwrap();
+        assert_eq!(face.srid, 3857);
+        assert_eq!(
+            face.to_ewkt(),
+            "SRID=3857;POLYHEDRALSURFACE Z (((0 0 0,1 0 0,0 1 0,0 0 0)))"
+        );
+        assert!(PolyhedralSurface::parse("POLYHEDRALSURFACE Z EMPTY")
+            .unwrap()
+            .is_empty());
+
+        for invalid in [
+            "POLYGON((0 0,1 0,0 1,0 0))",
+            "POLYHEDRALSURFACE(((0 0,1 0,0 1)))",
+            "POLYHEDRALSURFACE(((0 0,1 0,0 1,0 0))",
+            "POLYHEDRALSURFACE(((0 0,1 0,0 x,0 0)))",
+            "SRID=x;POLYHEDRALSURFACE EMPTY",
+        ] {
+            assert!(PolyhedralSurface::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_volume() {
+        let cube = PolyhedralSurface::parse(CUBE).unwrap();
+        assert!(cube.is_closed());
+        assert!((cube.volume() - 1.0).abs() < 1e-12);
+
+        // Turning every face around keeps the volume
+        let mut inverted = cube.clone();
+        for ring in inverted.faces.iter_mut().flatten() {
+            ring.reverse();
+        }
+        assert!((inverted.volume() - 1.0).abs() < 1e-12);
+
+        // Without its lid the cube is open
+        let mut open = cube.clone();
+        open.faces.remove(1);
+        assert!(!open.is_closed());
+        assert_eq!(open.volume(), 0.0);
+        assert!(!PolyhedralSurface::new(Vec::new(), 0).is_closed());
+    }
+
+    #[test]
+    fn test_extrude() {
+        use crate::functions::{geometry_from_wkt, make_point};
+
+        // A concave footprint with a courtyard, drawn clockwise
+        let footprint = geometry_from_wkt(
+            "POLYGON((0 0, 0 4, 2 4, 2 2, 6 2, 6 0, 0 0), (0.5 0.5, 1.5 0.5, 1.5 1.5, 0.5 1.5, 0.5 0.5))",
+        )
+        .unwrap()
+        .with_srid(3857);
+        let solid = extrude(&footprint, 3.0).unwrap();
+        assert_eq!(solid.srid, 3857);
+        // Floor, roof and one wall per edge of both rings
+        assert_eq!(solid.faces.len(), 2 + 6 + 4);
+        assert!(solid.is_closed());
+        assert!((solid.volume() - 3.0 * 15.0).abs() < 1e-9);
+        assert!((extrude(&footprint, -3.0).unwrap().volume() - 45.0).abs() < 1e-9);
+
+        let blocks = geometry_from_wkt(
+            "MULTIPOLYGON(((0 0, 1 0, 1 1, 0 1, 0 0)), ((5 5, 7 5, 7 7, 5 7, 5 5)))",
+        )
+        .unwrap();
+        assert!((extrude(&blocks, 2.0).unwrap().volume() - 10.0).abs() < 1e-9);
+
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert!(extrude(&empty, 1.0).unwrap().is_empty());
+        assert!(extrude(&make_point(0.0, 0.0), 1.0).is_err());
+        assert!(extrude(&footprint, f64::NAN).is_err());
+    }
+}
@@ -1,6 +1,11 @@
 use crate::geometry::Geometry;
+use crate::serialization::{deserialize, GeometryHeader};
+use crate::utils::{format_number, RostGisError};
+use geo::{PreparedGeometry, Relate};
 use pgrx::prelude::*;
-use rstar::{PointDistance, RTree, RTreeObject, AABB};
+use pgrx::PgMemoryContexts;
+use rstar::primitives::GeomWithData;
+use rstar::{Envelope, PointDistance, RTree, RTreeObject, SelectionFunction, AABB};
 use serde::{Deserialize, Serialize};
 
 /// Bounding box type for spatial indexing
@@ -81,6 +86,18 @@ impl BBox {
         (self.max_x - self.min_x) * (self.max_y - self.min_y)
     }
 
+    /// Half the perimeter of the bounding box
+    pub fn margin(&self) -> f64 {
+        (self.max_x - self.min_x) + (self.max_y - self.min_y)
+    }
+
+    /// Area shared by two bounding boxes
+    pub fn intersection_area(&self, other: &BBox) -> f64 {
+        let width = self.max_x.min(other.max_x) - self.min_x.max(other.min_x);
+        let height = self.max_y.min(other.max_y) - self.min_y.max(other.min_y);
+        width.max(0.0) * height.max(0.0)
+    }
+
     /// Calculate the union of two bounding boxes
     pub fn union(&self, other: &BBox) -> BBox {
         BBox::new(
@@ -96,147 +113,566 @@ impl BBox {
         let union = self.union(other);
         union.area() - self.area()
     }
+
+    /// Smallest distance between a point of this bbox and a point of
+    /// another, 0 when they overlap
+    pub fn distance(&self, other: &BBox) -> f64 {
+        let gap_x = (other.min_x - self.max_x)
+            .max(self.min_x - other.max_x)
+            .max(0.0);
+        let gap_y = (other.min_y - self.max_y)
+            .max(self.min_y - other.max_y)
+            .max(0.0);
+        gap_x.hypot(gap_y)
+    }
+
+    /// Convert to a geometry like PostGIS box2d::geometry: a polygon, or a
+    /// point/line when the box is degenerate
+    pub fn to_geometry(&self) -> Geometry {
+        let (min_x, min_y, max_x, max_y) = (self.min_x, self.min_y, self.max_x, self.max_y);
+        if min_x == max_x && min_y == max_y {
+            Geometry::Point(geo_types::Point::new(min_x, min_y), 0)
+        } else if min_x == max_x || min_y == max_y {
+            Geometry::LineString(
+                geo_types::LineString::from(vec![(min_x, min_y), (max_x, max_y)]),
+                0,
+            )
+        } else {
+            Geometry::Polygon(
+                geo_types::Polygon::new(
+                    geo_types::LineString::from(vec![
+                        (min_x, min_y),
+                        (min_x, max_y),
+                        (max_x, max_y),
+                        (max_x, min_y),
+                        (min_x, min_y),
+                    ]),
+                    vec![],
+                ),
+                0,
+            )
+        }
+    }
 }
 
 // ============================================================================
-// BASIC GIST SUPPORT FUNCTIONS
+// GIST SUPPORT FUNCTIONS
 // ============================================================================
+//
+// The support functions of rostgis_gist_ops use the calling convention of
+// GiST: they are passed pointers to GISTENTRY and GistEntryVector structures
+// as `internal` arguments rather than SQL values, so their SQL signatures
+// are declared in the extension_sql! block below. The key of a geometry is
+// the box compared by the box operators; empty geometries, which match no
+// operator but ~=, get the empty bbox, whose bounds are NaN.
+//
+// The penalty and picksplit functions read rostgis.gist_margin_weight and
+// rostgis.gist_fill_factor. These change the shape of the tree, never the
+// rows a scan returns, so the functions are still declared IMMUTABLE.
 
-/// Simple GiST compress function - converts geometry to bounding box
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_compress(geom: Geometry) -> BBox {
-    BBox::from_geometry(&geom)
-}
-
-/// Simple compress function for PostgreSQL box type compatibility
-/// Converts geometry to bounding box string in PostgreSQL box format
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_to_box_string(geom: Geometry) -> String {
-    let (min_x, min_y, max_x, max_y) = geom.bounding_box();
-    format!("(({},{}),({},{}))", min_x, min_y, max_x, max_y)
-}
+impl BBox {
+    /// Key of the empty geometries
+    pub fn empty() -> Self {
+        BBox::new(f64::NAN, f64::NAN, f64::NAN, f64::NAN)
+    }
 
-/// GiST union function - creates a bounding box that contains all input boxes
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_union(entries: Vec<BBox>) -> BBox {
-    if entries.is_empty() {
-        return BBox::new(0.0, 0.0, 0.0, 0.0);
+    pub fn is_empty(&self) -> bool {
+        self.min_x.is_nan()
     }
 
-    let mut result = entries[0].clone();
-    for entry in entries.iter().skip(1) {
-        result = result.union(entry);
+    /// Whether the box is a single point, as that of a geometry whose
+    /// vertices are all at the same place
+    pub fn is_point(&self) -> bool {
+        self.min_x == self.max_x && self.min_y == self.max_y
     }
-    result
-}
 
-/// GiST penalty function - calculates the cost of inserting a new entry
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_penalty(original: BBox, new_entry: BBox) -> f32 {
-    let enlargement = original.enlargement(&new_entry);
-    enlargement as f32
-}
+    /// Index key of a geometry: the box the box operators compare, or the
+    /// empty bbox
+    pub fn key_of(geom: &Geometry) -> Self {
+        match geom.operator_bbox() {
+            Some((min_x, min_y, max_x, max_y)) => BBox::new(min_x, min_y, max_x, max_y),
+            None => BBox::empty(),
+        }
+    }
 
-/// GiST same function - determines if two entries are the same
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_same(a: BBox, b: BBox) -> bool {
-    a == b
+    /// Index key of a stored geometry, read from the header alone for
+    /// points and empty geometries
+    pub fn stored_key(bytes: &[u8]) -> Result<Self, RostGisError> {
+        let header = GeometryHeader::peek(bytes)?;
+        if header.is_empty() {
+            return Ok(BBox::empty());
+        }
+        if let (Some(x), Some(y)) = (header.x(), header.y()) {
+            return Ok(BBox::new(x, y, x, y));
+        }
+        Ok(BBox::key_of(&deserialize(bytes)?))
+    }
 }
 
-/// GiST decompress function - passthrough since we store bboxes directly
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_decompress(bbox: BBox) -> BBox {
-    bbox
+/// Whether the key of a leaf entry, the box of an indexed geometry,
+/// satisfies the operator of a strategy against the box of the query. These
+/// are the conditions of the box operators themselves, so matches need no
+/// recheck.
+pub fn leaf_consistent(key: &BBox, query: &BBox, strategy: i16) -> bool {
+    if key.is_empty() || query.is_empty() {
+        // All empty geometries have the same box, and no other
+        return strategy == 6 && key.is_empty() && query.is_empty();
+    }
+    match strategy {
+        1 => key.left(query),
+        2 => !key.right(query),
+        3 => key.overlaps(query),
+        4 => !key.left(query),
+        5 => key.right(query),
+        6 => {
+            (key.min_x - query.min_x).abs() < f64::EPSILON
+                && (key.min_y - query.min_y).abs() < f64::EPSILON
+                && (key.max_x - query.max_x).abs() < f64::EPSILON
+                && (key.max_y - query.max_y).abs() < f64::EPSILON
+        }
+        7 => key.contains(query),
+        8 => key.within(query),
+        10 => key.below(query),
+        11 => !key.above(query),
+        12 => !key.below(query),
+        13 => key.above(query),
+        _ => false,
+    }
 }
 
-/// CRITICAL: GiST consistent function (Function 1) - This is REQUIRED by PostgreSQL
-/// This function determines whether a query matches an index entry
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_consistent(
-    key: BBox,
-    query: BBox,
-    strategy: i16,
-    _subtype: pgrx::pg_sys::Oid,
-    _recheck: bool,
-) -> bool {
-    // Strategy numbers correspond to different spatial operators
-    // For PostgreSQL GiST, strategy 3 is typically && (overlaps)
+/// Whether the subtree under the key of an inner entry, which covers the
+/// boxes of all its geometries, may hold a leaf satisfying the strategy
+pub fn inner_consistent(key: &BBox, query: &BBox, strategy: i16) -> bool {
+    if query.is_empty() {
+        // Empty geometries do not widen the keys above them, so any subtree
+        // may hold one
+        return strategy == 6;
+    }
+    if key.is_empty() {
+        return false;
+    }
     match strategy {
-        3 => {
-            // Strategy 3: && operator (bounding box overlap)
-            key.overlaps(&query)
-        }
-        1 => {
-            // Strategy 1: << operator (strictly left of)
-            key.left(&query)
-        }
-        2 => {
-            // Strategy 2: &< operator (does not extend to right of)
-            key.max_x <= query.max_x
-        }
-        4 => {
-            // Strategy 4: &> operator (does not extend to left of)
-            key.min_x >= query.min_x
-        }
-        5 => {
-            // Strategy 5: >> operator (strictly right of)
-            key.right(&query)
-        }
+        1 => key.min_x < query.min_x,
+        2 => key.min_x <= query.max_x,
+        3 | 8 => key.overlaps(query),
+        4 => key.max_x >= query.min_x,
+        5 => key.max_x > query.max_x,
         6 => {
-            // Strategy 6: ~= operator (same bounding box)
-            key == query
-        }
-        7 => {
-            // Strategy 7: ~ operator (contains)
-            key.contains(&query)
-        }
-        8 => {
-            // Strategy 8: @ operator (contained by)
-            query.contains(&key)
+            key.min_x < query.min_x + f64::EPSILON
+                && key.min_y < query.min_y + f64::EPSILON
+                && key.max_x > query.max_x - f64::EPSILON
+                && key.max_y > query.max_y - f64::EPSILON
         }
-        10 => {
-            // Strategy 10: <<| operator (strictly below)
-            key.below(&query)
-        }
-        11 => {
-            // Strategy 11: &<| operator (does not extend above)
-            key.max_y <= query.max_y
-        }
-        12 => {
-            // Strategy 12: |&> operator (does not extend below)
-            key.min_y >= query.min_y
+        7 => key.contains(query),
+        10 => key.min_y < query.min_y,
+        11 => key.min_y <= query.max_y,
+        12 => key.max_y >= query.min_y,
+        13 => key.max_y > query.max_y,
+        _ => false,
+    }
+}
+
+/// Cost of inserting an entry into a page: the enlargement of its area, plus
+/// `margin_weight` times the enlargement of its margin. Empty entries go
+/// with empty entries.
+pub fn gist_penalty(original: &BBox, new_entry: &BBox, margin_weight: f64) -> f64 {
+    match (original.is_empty(), new_entry.is_empty()) {
+        (true, true) => 0.0,
+        (false, false) => {
+            let margin_enlargement = original.union(new_entry).margin() - original.margin();
+            original.enlargement(new_entry) + margin_weight * margin_enlargement
         }
-        13 => {
-            // Strategy 13: |>> operator (strictly above)
-            key.above(&query)
+        _ => f32::MAX as f64,
+    }
+}
+
+/// Split the entries of a full page in two, R*-tree style: the entries are
+/// sorted along the axis their centres spread most on, and cut where the
+/// boxes of the two sides overlap least (then cover the least area), each
+/// side keeping at least `min_fill` of the entries. Empty entries are kept
+/// apart from the others. Returns the positions of the entries of each side.
+pub fn split_entries(entries: &[BBox], min_fill: f64) -> (Vec<usize>, Vec<usize>) {
+    let mut order = match split_empty_apart(entries.iter().map(BBox::is_empty)) {
+        Ok(split) => return split,
+        Err(order) => order,
+    };
+    let n = order.len();
+    if n <= 1 {
+        return (order, Vec::new());
+    }
+    let centre = |i: usize, axis: usize| {
+        let bbox = &entries[i];
+        if axis == 0 {
+            bbox.min_x + bbox.max_x
+        } else {
+            bbox.min_y + bbox.max_y
         }
+    };
+    let spread = |axis: usize| {
+        let centres = order.iter().map(|&i| centre(i, axis));
+        centres.clone().fold(f64::NEG_INFINITY, f64::max) - centres.fold(f64::INFINITY, f64::min)
+    };
+    let axis = if spread(1) > spread(0) { 1 } else { 0 };
+    order.sort_by(|&a, &b| centre(a, axis).total_cmp(&centre(b, axis)));
+
+    let cover = |side: &[usize]| {
+        side.iter()
+            .skip(1)
+            .fold(entries[side[0]].clone(), |cover, &i| {
+                cover.union(&entries[i])
+            })
+    };
+    let least = ((n as f64 * min_fill).ceil() as usize).clamp(1, n / 2);
+    let cut = (least..=n - least)
+        .min_by(|&a, &b| {
+            let cost = |k: usize| {
+                let (left, right) = (cover(&order[..k]), cover(&order[k..]));
+                (left.intersection_area(&right), left.area() + right.area())
+            };
+            let (cost_a, cost_b) = (cost(a), cost(b));
+            cost_a
+                .0
+                .total_cmp(&cost_b.0)
+                .then(cost_a.1.total_cmp(&cost_b.1))
+        })
+        .unwrap_or(n / 2);
+    let right = order.split_off(cut);
+    (order, right)
+}
+
+/// Split of a page holding empty entries: those on a side of their own, or
+/// cut in halves when all entries are empty. Otherwise, the positions of
+/// all entries, to be split by their boxes.
+pub(crate) fn split_empty_apart(
+    empty: impl Iterator<Item = bool>,
+) -> Result<(Vec<usize>, Vec<usize>), Vec<usize>> {
+    let empty: Vec<bool> = empty.collect();
+    let (mut empty, order): (Vec<usize>, Vec<usize>) = (0..empty.len()).partition(|&i| empty[i]);
+    if order.is_empty() {
+        let right = empty.split_off(empty.len().div_ceil(2));
+        Ok((empty, right))
+    } else if !empty.is_empty() {
+        Ok((order, empty))
+    } else {
+        Err(order)
+    }
+}
+
+/// Smallest share of entries each side of a split keeps, from
+/// rostgis.gist_fill_factor
+pub(crate) fn split_min_fill() -> f64 {
+    crate::guc::GIST_FILL_FACTOR.get() as f64 / 100.0
+}
+
+/// Whether a GiST entry is on a leaf page
+pub(crate) unsafe fn entry_on_leaf(entry: &pg_sys::GISTENTRY) -> bool {
+    let header = entry.page as *const pg_sys::PageHeaderData;
+    let opaque = entry.page.add((*header).pd_special as usize) as *const pg_sys::GISTPageOpaqueData;
+    u32::from((*opaque).flags) & pg_sys::F_LEAF != 0
+}
+
+/// The entries of a GistEntryVector
+pub(crate) unsafe fn gist_entries<'a>(entryvec: pg_sys::Datum) -> &'a [pg_sys::GISTENTRY] {
+    let entryvec = &*entryvec.cast_mut_ptr::<pg_sys::GistEntryVector>();
+    entryvec.vector.as_slice(entryvec.n as usize)
+}
+
+/// A GISTENTRY copied into the current memory context with a new key, as
+/// compress functions return
+pub(crate) unsafe fn replace_entry_key(
+    entry: &pg_sys::GISTENTRY,
+    key: pg_sys::Datum,
+) -> pg_sys::Datum {
+    let compressed =
+        pg_sys::palloc(std::mem::size_of::<pg_sys::GISTENTRY>()) as *mut pg_sys::GISTENTRY;
+    *compressed = pg_sys::GISTENTRY {
+        key,
+        rel: entry.rel,
+        page: entry.page,
+        offset: entry.offset,
+        leafkey: false,
+    };
+    pg_sys::Datum::from(compressed)
+}
+
+/// Positions of the entries of one side of a split, as the offsets GiST
+/// expects in a GIST_SPLITVEC (entries are numbered from 1)
+pub(crate) unsafe fn split_offsets(side: &[usize]) -> (*mut pg_sys::OffsetNumber, i32) {
+    let offsets = pg_sys::palloc(side.len().max(1) * std::mem::size_of::<pg_sys::OffsetNumber>())
+        as *mut pg_sys::OffsetNumber;
+    for (n, &i) in side.iter().enumerate() {
+        *offsets.add(n) = (i + 1) as pg_sys::OffsetNumber;
+    }
+    (offsets, side.len() as i32)
+}
+
+/// Key of the query of a scan, kept in fn_extra so that the query geometry
+/// is decoded once rather than for every entry visited
+pub(crate) unsafe fn cached_query_key<K: Clone + 'static>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    query: &[u8],
+    key_of: impl FnOnce(&[u8]) -> Result<K, RostGisError>,
+) -> Result<K, RostGisError> {
+    let flinfo = (*fcinfo).flinfo;
+    if (*flinfo).fn_extra.is_null() {
+        let cache =
+            PgMemoryContexts::For((*flinfo).fn_mcxt).leak_and_drop_on_delete(None::<(Vec<u8>, K)>);
+        (*flinfo).fn_extra = cache as *mut std::ffi::c_void;
+    }
+    let cache = &mut *((*flinfo).fn_extra as *mut Option<(Vec<u8>, K)>);
+    match cache {
+        Some((datum, key)) if datum.as_slice() == query => Ok(key.clone()),
         _ => {
-            // Default: assume overlap test for unknown strategies
-            key.overlaps(&query)
+            let key = key_of(query)?;
+            *cache = Some((query.to_vec(), key.clone()));
+            Ok(key)
         }
     }
 }
 
-/// GiST picksplit left function
-#[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_picksplit_left(entries: Vec<BBox>) -> Vec<BBox> {
-    if entries.len() <= 1 {
-        return entries;
+unsafe fn bbox_key(datum: pg_sys::Datum) -> BBox {
+    BBox::from_datum(datum, false).expect("GiST keys are never NULL")
+}
+
+/// GiST consistent function (support function 1)
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_consistent(
+    entry: Internal,
+    query: &[u8],
+    strategy: i16,
+    _subtype: pg_sys::Oid,
+    recheck: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(entry), Some(recheck)) = (entry.unwrap(), recheck.unwrap()) else {
+        return Ok(false);
+    };
+    unsafe {
+        let entry = &*entry.cast_mut_ptr::<pg_sys::GISTENTRY>();
+        *recheck.cast_mut_ptr::<bool>() = false;
+        let query = cached_query_key(fcinfo, query, BBox::stored_key)?;
+        let key = bbox_key(entry.key);
+        Ok(if entry_on_leaf(entry) {
+            leaf_consistent(&key, &query, strategy)
+        } else {
+            inner_consistent(&key, &query, strategy)
+        })
     }
-    let mid = entries.len() / 2;
-    entries[..mid].to_vec()
 }
 
-/// GiST picksplit right function
+/// GiST union function (support function 2): the box covering all entries
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_union(entryvec: Internal, _size: Internal) -> BBox {
+    let Some(entryvec) = entryvec.unwrap() else {
+        return BBox::empty();
+    };
+    unsafe {
+        gist_entries(entryvec)
+            .iter()
+            .map(|entry| bbox_key(entry.key))
+            .reduce(|cover, bbox| cover.union(&bbox))
+            .unwrap_or_else(BBox::empty)
+    }
+}
+
+/// GiST compress function (support function 3): turns the geometries of
+/// leaf entries into their keys
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_compress(
+    entry: Internal,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(datum) = entry.unwrap() else {
+        return Ok(Internal::from(None));
+    };
+    unsafe {
+        let entry = &*datum.cast_mut_ptr::<pg_sys::GISTENTRY>();
+        if !entry.leafkey {
+            return Ok(Internal::from(Some(datum)));
+        }
+        let geometry = <&[u8]>::from_datum(entry.key, false)
+            .ok_or_else(|| RostGisError::new("GiST leaf entries are never NULL"))?;
+        let key = BBox::stored_key(geometry)?
+            .into_datum()
+            .ok_or_else(|| RostGisError::new("Failed to store a GiST key"))?;
+        Ok(Internal::from(Some(replace_entry_key(entry, key))))
+    }
+}
+
+/// GiST penalty function (support function 5), weighted by
+/// rostgis.gist_margin_weight
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_penalty(original: Internal, new_entry: Internal, penalty: Internal) -> Internal {
+    let (Some(original), Some(new_entry), Some(penalty)) =
+        (original.unwrap(), new_entry.unwrap(), penalty.unwrap())
+    else {
+        return Internal::from(None);
+    };
+    unsafe {
+        let original = bbox_key((*original.cast_mut_ptr::<pg_sys::GISTENTRY>()).key);
+        let new_entry = bbox_key((*new_entry.cast_mut_ptr::<pg_sys::GISTENTRY>()).key);
+        *penalty.cast_mut_ptr::<f32>() =
+            gist_penalty(&original, &new_entry, crate::guc::GIST_MARGIN_WEIGHT.get()) as f32;
+    }
+    Internal::from(Some(penalty))
+}
+
+/// GiST picksplit function (support function 6), keeping at least
+/// rostgis.gist_fill_factor of the entries on each side
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_picksplit(
+    entryvec: Internal,
+    splitvec: Internal,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(entryvec), Some(splitvec)) = (entryvec.unwrap(), splitvec.unwrap()) else {
+        return Ok(Internal::from(None));
+    };
+    unsafe {
+        // Entries are numbered from FirstOffsetNumber, 1
+        let keys: Vec<BBox> = gist_entries(entryvec)
+            .iter()
+            .skip(1)
+            .map(|entry| bbox_key(entry.key))
+            .collect();
+        let (left, right) = split_entries(&keys, split_min_fill());
+        let union = |side: &[usize]| {
+            side.iter()
+                .map(|&i| keys[i].clone())
+                .reduce(|cover, bbox| cover.union(&bbox))
+                .unwrap_or_else(BBox::empty)
+                .into_datum()
+                .ok_or_else(|| RostGisError::new("Failed to store a GiST key"))
+        };
+        let split = &mut *splitvec.cast_mut_ptr::<pg_sys::GIST_SPLITVEC>();
+        (split.spl_left, split.spl_nleft) = split_offsets(&left);
+        split.spl_ldatum = union(&left)?;
+        (split.spl_right, split.spl_nright) = split_offsets(&right);
+        split.spl_rdatum = union(&right)?;
+    }
+    Ok(Internal::from(Some(splitvec)))
+}
+
+/// GiST same function (support function 7)
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_same(a: BBox, b: BBox, result: Internal) -> Internal {
+    let result = result.unwrap();
+    if let Some(result) = result {
+        unsafe { *result.cast_mut_ptr::<bool>() = a == b || (a.is_empty() && b.is_empty()) };
+    }
+    Internal::from(result)
+}
+
+/// Simple compress function for PostgreSQL box type compatibility
+/// Converts geometry to bounding box string in PostgreSQL box format
 #[pg_extern(immutable, parallel_safe)]
-pub fn geometry_gist_picksplit_right(entries: Vec<BBox>) -> Vec<BBox> {
-    if entries.len() <= 1 {
-        return Vec::new();
+pub fn geometry_to_box_string(geom: Geometry) -> String {
+    let (min_x, min_y, max_x, max_y) = geom.bounding_box();
+    format!("(({},{}),({},{}))", min_x, min_y, max_x, max_y)
+}
+
+/// GiST distance function (support function 8), ordering index scans by
+/// the <-> operator (strategy 15). The distance between bounding boxes never
+/// exceeds the one between the geometries, so entries are visited in a valid
+/// order; at leaves it is exact only when both boxes are points, and other
+/// leaves are rechecked with <->. Empty geometries come last.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn geometry_gist_distance(
+    entry: Internal,
+    query: &[u8],
+    _strategy: i16,
+    _subtype: pg_sys::Oid,
+    recheck: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(entry), Some(recheck)) = (entry.unwrap(), recheck.unwrap()) else {
+        return Ok(f64::INFINITY);
+    };
+    unsafe {
+        let entry = &*entry.cast_mut_ptr::<pg_sys::GISTENTRY>();
+        let key = bbox_key(entry.key);
+        let query = cached_query_key(fcinfo, query, BBox::stored_key)?;
+        if entry_on_leaf(entry) {
+            *recheck.cast_mut_ptr::<bool>() = !(key.is_point() && query.is_point());
+        }
+        Ok(if key.is_empty() || query.is_empty() {
+            f64::INFINITY
+        } else {
+            key.distance(&query)
+        })
     }
-    let mid = entries.len() / 2;
-    entries[mid..].to_vec()
 }
 
+// The default GiST operator class of geometry, so that
+// CREATE INDEX ... USING gist (geom) works without further setup. Strategy
+// numbers follow geometry_gist_consistent; <-> orders scans through
+// geometry_gist_distance, with the strategy number of the distance operators
+// of PostgreSQL's own GiST opclasses.
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.geometry_gist_consistent(
+    internal, @extschema@.geometry, smallint, oid, internal) RETURNS boolean
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_consistent_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_union(internal, internal) RETURNS @extschema@.bbox
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_union_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_compress(internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_compress_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_penalty(internal, internal, internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_penalty_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_picksplit(internal, internal) RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_picksplit_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_distance(
+    internal, @extschema@.geometry, smallint, oid, internal) RETURNS float8
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_distance_wrapper';
+CREATE FUNCTION @extschema@.geometry_gist_same(@extschema@.bbox, @extschema@.bbox, internal)
+    RETURNS internal
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_gist_same_wrapper';
+
+CREATE OPERATOR CLASS @extschema@.rostgis_gist_ops
+    DEFAULT FOR TYPE @extschema@.geometry USING gist AS
+        STORAGE @extschema@.bbox,
+        OPERATOR 1  @extschema@.<<  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 2  @extschema@.&<  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 3  @extschema@.&&  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 4  @extschema@.&>  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 5  @extschema@.>>  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 6  @extschema@.~=  (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 7  @extschema@.~   (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 8  @extschema@.@   (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 10 @extschema@.<<| (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 11 @extschema@.&<| (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 12 @extschema@.|&> (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 13 @extschema@.|>> (@extschema@.geometry, @extschema@.geometry),
+        OPERATOR 15 @extschema@.<-> (@extschema@.geometry, @extschema@.geometry)
+            FOR ORDER BY pg_catalog.float_ops,
+        FUNCTION 1 @extschema@.geometry_gist_consistent(
+            internal, @extschema@.geometry, smallint, oid, internal),
+        FUNCTION 2 @extschema@.geometry_gist_union(internal, internal),
+        FUNCTION 3 @extschema@.geometry_gist_compress(internal),
+        FUNCTION 5 @extschema@.geometry_gist_penalty(internal, internal, internal),
+        FUNCTION 6 @extschema@.geometry_gist_picksplit(internal, internal),
+        FUNCTION 7 @extschema@.geometry_gist_same(@extschema@.bbox, @extschema@.bbox, internal),
+        FUNCTION 8 @extschema@.geometry_gist_distance(
+            internal, @extschema@.geometry, smallint, oid, internal);
+"#,
+    name = "gist_operator_class",
+    requires = [
+        Geometry,
+        BBox,
+        geometry_left,
+        geometry_overleft,
+        geometry_overlap,
+        geometry_overright,
+        geometry_right,
+        geometry_same_bbox,
+        geometry_contains_bbox,
+        geometry_within_bbox,
+        geometry_below,
+        geometry_overbelow,
+        geometry_overabove,
+        geometry_above,
+        geometry_distance_knn,
+    ],
+);
+
 // ============================================================================
 // SPATIAL INDEX FUNCTIONALITY (R*-TREE)
 // ============================================================================
@@ -281,6 +717,141 @@ impl PointDistance for GeometryWithId {
     }
 }
 
+/// Exact test of indexed geometries against a query geometry
+#[derive(Debug, Clone, Copy)]
+pub enum IndexPredicate<'a> {
+    /// The indexed geometry intersects the query geometry
+    Intersects(&'a Geometry),
+    /// The indexed geometry contains the query geometry, e.g. the polygon
+    /// holding a point
+    Contains(&'a Geometry),
+    /// The indexed geometry lies within the query geometry
+    Within(&'a Geometry),
+    /// The indexed geometry is within a distance of the query geometry
+    DWithin(&'a Geometry, f64),
+}
+
+impl<'a> IndexPredicate<'a> {
+    /// Predicate for `ST_<name>(query, indexed)`, as in a join with the
+    /// query geometry on the left
+    pub fn from_name(name: &str, query: &'a Geometry, distance: f64) -> Result<Self, RostGisError> {
+        match name.trim().to_lowercase().as_str() {
+            "intersects" => Ok(IndexPredicate::Intersects(query)),
+            "contains" => Ok(IndexPredicate::Within(query)),
+            "within" => Ok(IndexPredicate::Contains(query)),
+            "dwithin" => Ok(IndexPredicate::DWithin(query, distance)),
+            other => Err(RostGisError::new(&format!(
+                "Unknown index predicate '{}', expected 'intersects', 'contains', 'within' or 'dwithin'",
+                other
+            ))),
+        }
+    }
+
+    fn query(&self) -> &'a Geometry {
+        match *self {
+            IndexPredicate::Intersects(query)
+            | IndexPredicate::Contains(query)
+            | IndexPredicate::Within(query)
+            | IndexPredicate::DWithin(query, _) => query,
+        }
+    }
+}
+
+/// Selection testing the predicate on every leaf the traversal reaches,
+/// with the query geometry prepared once
+struct PredicateSelection<'a> {
+    predicate: IndexPredicate<'a>,
+    prepared: PreparedGeometry<'static, geo::Geometry<f64>>,
+    /// Nodes whose envelope misses this cannot hold matches
+    window: AABB<[f64; 2]>,
+}
+
+impl<'a> PredicateSelection<'a> {
+    fn new(predicate: IndexPredicate<'a>) -> Self {
+        let query = predicate.query();
+        let bbox = BBox::from_geometry(query);
+        let margin = match predicate {
+            IndexPredicate::DWithin(_, distance) => distance.max(0.0),
+            _ => 0.0,
+        };
+        Self {
+            predicate,
+            prepared: PreparedGeometry::from(query.to_geo()),
+            window: AABB::from_corners(
+                [bbox.min_x - margin, bbox.min_y - margin],
+                [bbox.max_x + margin, bbox.max_y + margin],
+            ),
+        }
+    }
+
+    fn matches(&self, candidate: &GeometryWithId) -> bool {
+        let query = self.predicate.query();
+        if query.is_empty() || candidate.geometry.is_empty() {
+            return false;
+        }
+        let envelope = candidate.envelope();
+        match self.predicate {
+            IndexPredicate::Intersects(_) => {
+                envelope.intersects(&self.window)
+                    && self
+                        .prepared
+                        .relate(&candidate.geometry.to_geo())
+                        .is_intersects()
+            }
+            IndexPredicate::Contains(_) => {
+                envelope.contains_envelope(&self.window)
+                    && self
+                        .prepared
+                        .relate(&candidate.geometry.to_geo())
+                        .is_within()
+            }
+            IndexPredicate::Within(_) => {
+                self.window.contains_envelope(&envelope)
+                    && self
+                        .prepared
+                        .relate(&candidate.geometry.to_geo())
+                        .is_contains()
+            }
+            IndexPredicate::DWithin(query, distance) => {
+                envelope.intersects(&self.window)
+                    && crate::functions::distance_within(&candidate.geometry, query, distance)
+                        .is_some()
+            }
+        }
+    }
+}
+
+impl SelectionFunction<GeometryWithId> for PredicateSelection<'_> {
+    fn should_unpack_parent(&self, envelope: &AABB<[f64; 2]>) -> bool {
+        match self.predicate {
+            // A node holding a geometry that contains the query box
+            // contains it too
+            IndexPredicate::Contains(_) => envelope.contains_envelope(&self.window),
+            _ => envelope.intersects(&self.window),
+        }
+    }
+
+    fn should_unpack_leaf(&self, leaf: &GeometryWithId) -> bool {
+        self.matches(leaf)
+    }
+}
+
+/// Selection of the leaves overlapping a box that pass a callback
+struct FilteredSelection<F> {
+    envelope: AABB<[f64; 2]>,
+    keep: F,
+}
+
+impl<F: Fn(&GeometryWithId) -> bool> SelectionFunction<GeometryWithId> for FilteredSelection<F> {
+    fn should_unpack_parent(&self, envelope: &AABB<[f64; 2]>) -> bool {
+        envelope.intersects(&self.envelope)
+    }
+
+    fn should_unpack_leaf(&self, leaf: &GeometryWithId) -> bool {
+        leaf.envelope().intersects(&self.envelope) && (self.keep)(leaf)
+    }
+}
+
 /// High-performance spatial index using R*-tree
 pub struct SpatialIndex {
     rtree: RTree<GeometryWithId>,
@@ -314,7 +885,31 @@ impl SpatialIndex {
     /// Find all geometries that intersect with the given bounding box
     pub fn query_bbox(&self, bbox: &BBox) -> Vec<&GeometryWithId> {
         let envelope = AABB::from_corners([bbox.min_x, bbox.min_y], [bbox.max_x, bbox.max_y]);
-        self.rtree.locate_in_envelope(&envelope).collect()
+        self.rtree
+            .locate_in_envelope_intersecting(&envelope)
+            .collect()
+    }
+
+    /// Find the geometries whose bounding box intersects the given one and
+    /// that pass `keep`, which is called during the traversal rather than
+    /// on a list of candidates afterwards
+    pub fn query_bbox_filtered<F>(&self, bbox: &BBox, keep: F) -> Vec<&GeometryWithId>
+    where
+        F: Fn(&GeometryWithId) -> bool,
+    {
+        let envelope = AABB::from_corners([bbox.min_x, bbox.min_y], [bbox.max_x, bbox.max_y]);
+        self.rtree
+            .locate_with_selection_function(FilteredSelection { envelope, keep })
+            .collect()
+    }
+
+    /// Find the geometries satisfying an exact predicate; subtrees that
+    /// cannot hold a match are skipped and the query geometry is prepared
+    /// once for all tests
+    pub fn query_predicate(&self, predicate: IndexPredicate) -> Vec<&GeometryWithId> {
+        self.rtree
+            .locate_with_selection_function(PredicateSelection::new(predicate))
+            .collect()
     }
 
     /// Find the nearest neighbor to a point
@@ -327,6 +922,22 @@ impl SpatialIndex {
         self.rtree.nearest_neighbor_iter(&point).take(k).collect()
     }
 
+    /// Find the k nearest neighbors to a point that satisfy a predicate,
+    /// testing neighbours in order of distance until k have matched
+    pub fn k_nearest_neighbors_matching(
+        &self,
+        point: [f64; 2],
+        k: usize,
+        predicate: IndexPredicate,
+    ) -> Vec<&GeometryWithId> {
+        let selection = PredicateSelection::new(predicate);
+        self.rtree
+            .nearest_neighbor_iter(&point)
+            .filter(|candidate| selection.matches(candidate))
+            .take(k)
+            .collect()
+    }
+
     /// Find all geometries within distance of a point
     pub fn within_distance(&self, point: [f64; 2], distance: f64) -> Vec<&GeometryWithId> {
         self.rtree
@@ -356,6 +967,34 @@ impl Default for SpatialIndex {
     }
 }
 
+/// The k nearest neighbours of every point among the other points, as
+/// (point, neighbour, distance) rows holding positions in `points`, nearest
+/// first. All points go into one bulk-loaded R*-tree; None entries are
+/// skipped.
+pub fn knn_matrix(points: &[Option<[f64; 2]>], k: usize) -> Vec<(usize, usize, f64)> {
+    let tree = RTree::bulk_load(
+        points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, point)| point.map(|point| GeomWithData::new(point, i)))
+            .collect(),
+    );
+
+    let mut rows = Vec::with_capacity(tree.size() * k.min(tree.size()));
+    for (i, point) in points.iter().enumerate() {
+        let Some(point) = point else {
+            continue;
+        };
+        rows.extend(
+            tree.nearest_neighbor_iter_with_distance_2(point)
+                .filter(|(neighbour, _)| neighbour.data != i)
+                .take(k)
+                .map(|(neighbour, distance_2)| (i, neighbour.data, distance_2.sqrt())),
+        );
+    }
+    rows
+}
+
 // ============================================================================
 // POSTGRESQL FUNCTIONS FOR SPATIAL INDEXING DEMOS
 // ============================================================================
@@ -425,6 +1064,102 @@ pub fn rtree_range_query_demo(
         .collect()
 }
 
+/// PostgreSQL function for the k nearest neighbours of every point of an
+/// array, e.g. for nearest-neighbour statistics. `i` and `j` are array
+/// subscripts; NULL and empty points are skipped.
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_knn_matrix(
+    points: Array<'_, Geometry>,
+    k: i32,
+) -> Result<
+    TableIterator<'static, (name!(i, i32), name!(j, i32), name!(distance, f64))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    if k < 0 {
+        return Err(Box::new(RostGisError::new(
+            "rostgis_knn_matrix: k must not be negative",
+        )));
+    }
+    let coords = points
+        .iter()
+        .map(|point| match point {
+            None => Ok(None),
+            Some(point @ Geometry::Point(_, _)) => {
+                Ok(point.x().zip(point.y()).map(|(x, y)| [x, y]))
+            }
+            Some(other) => Err(RostGisError::new(&format!(
+                "rostgis_knn_matrix requires points, got {}",
+                other.geometry_type()
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let rows = knn_matrix(&coords, k as usize);
+    Ok(TableIterator::new(rows.into_iter().map(
+        |(i, j, distance)| (i as i32 + 1, j as i32 + 1, distance),
+    )))
+}
+
+/// Pairs of a left and a right geometry satisfying a predicate, as 0-based
+/// positions; the right geometries are indexed and the predicate is tested
+/// as `ST_<predicate>(left, right)` during the index traversal
+pub fn index_join(
+    left: &[Option<Geometry>],
+    right: &[Option<Geometry>],
+    predicate: &str,
+    distance: f64,
+) -> Result<Vec<(usize, usize)>, RostGisError> {
+    let index = SpatialIndex::from_geometries(
+        right
+            .iter()
+            .enumerate()
+            .filter_map(|(j, geom)| {
+                geom.as_ref()
+                    .filter(|geom| !geom.is_empty())
+                    .map(|geom| GeometryWithId::new(j as i64, geom.clone()))
+            })
+            .collect(),
+    );
+    let mut pairs = Vec::new();
+    for (i, geom) in left.iter().enumerate() {
+        let Some(geom) = geom else {
+            continue;
+        };
+        let mut matches: Vec<usize> = index
+            .query_predicate(IndexPredicate::from_name(predicate, geom, distance)?)
+            .into_iter()
+            .map(|candidate| candidate.id as usize)
+            .collect();
+        matches.sort_unstable();
+        pairs.extend(matches.into_iter().map(|j| (i, j)));
+    }
+    Ok(pairs)
+}
+
+/// PostgreSQL function joining two geometry arrays on an exact predicate,
+/// 'intersects', 'contains', 'within' or 'dwithin', through an R*-tree on
+/// the right array. `i` and `j` are array subscripts; NULL elements never
+/// match.
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn rostgis_index_join(
+    left: Array<'_, Geometry>,
+    right: Array<'_, Geometry>,
+    predicate: &str,
+    distance: default!(f64, 0.0),
+) -> Result<
+    TableIterator<'static, (name!(i, i32), name!(j, i32))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let left: Vec<Option<Geometry>> = left.iter().collect();
+    let right: Vec<Option<Geometry>> = right.iter().collect();
+    let pairs = index_join(&left, &right, predicate, distance)?;
+    Ok(TableIterator::new(
+        pairs.into_iter().map(|(i, j)| (i as i32 + 1, j as i32 + 1)),
+    ))
+}
+
 /// Input/Output functions for BBox
 impl pgrx::InOutFuncs for BBox {
     fn input(input: &std::ffi::CStr) -> Self
@@ -432,8 +1167,23 @@ impl pgrx::InOutFuncs for BBox {
         Self: Sized,
     {
         let input_str = input.to_str().expect("Invalid UTF-8 in bbox input");
+        BBox::parse(input_str).unwrap_or(BBox::new(0.0, 0.0, 0.0, 0.0))
+    }
+
+    fn output(&self, buffer: &mut pgrx::StringInfo) {
+        buffer.push_str(&format!(
+            "BOX({} {},{} {})",
+            format_number(self.min_x, None),
+            format_number(self.min_y, None),
+            format_number(self.max_x, None),
+            format_number(self.max_y, None)
+        ));
+    }
+}
 
-        // Parse format: BOX(min_x min_y,max_x max_y)
+impl BBox {
+    /// Parse the text form BOX(min_x min_y,max_x max_y)
+    pub fn parse(input_str: &str) -> Option<BBox> {
         if let Some(coords_start) = input_str.find('(') {
             if let Some(coords_end) = input_str.find(')') {
                 let coords_str = &input_str[coords_start + 1..coords_end];
@@ -449,22 +1199,13 @@ impl pgrx::InOutFuncs for BBox {
                             max_coords[0].parse::<f64>(),
                             max_coords[1].parse::<f64>(),
                         ) {
-                            return BBox::new(min_x, min_y, max_x, max_y);
+                            return Some(BBox::new(min_x, min_y, max_x, max_y));
                         }
                     }
                 }
             }
         }
-
-        // Fallback
-        BBox::new(0.0, 0.0, 0.0, 0.0)
-    }
-
-    fn output(&self, buffer: &mut pgrx::StringInfo) {
-        buffer.push_str(&format!(
-            "BOX({} {},{} {})",
-            self.min_x, self.min_y, self.max_x, self.max_y
-        ));
+        None
     }
 }
 
@@ -472,6 +1213,44 @@ impl pgrx::InOutFuncs for BBox {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_knn_matrix() {
+        let points = [
+            Some([0.0, 0.0]),
+            Some([1.0, 0.0]),
+            None,
+            Some([3.0, 0.0]),
+            Some([0.0, 0.5]),
+        ];
+        let rows = knn_matrix(&points, 2);
+        let neighbours = |i: usize| -> Vec<(usize, f64)> {
+            rows.iter()
+                .filter(|row| row.0 == i)
+                .map(|row| (row.1, row.2))
+                .collect()
+        };
+        assert_eq!(neighbours(0), vec![(4, 0.5), (1, 1.0)]);
+        assert_eq!(neighbours(3), vec![(1, 2.0), (0, 3.0)]);
+        assert!(neighbours(2).is_empty());
+        assert_eq!(rows.len(), 8);
+
+        // k beyond the number of other points returns all of them
+        assert_eq!(knn_matrix(&points, 10).len(), 12);
+        assert!(knn_matrix(&points, 0).is_empty());
+    }
+
+    #[test]
+    fn test_bbox_to_geometry() {
+        let polygon = BBox::new(0.0, 0.0, 2.0, 1.0).to_geometry();
+        assert_eq!(polygon.to_wkt(), "POLYGON((0 0,0 1,2 1,2 0,0 0))");
+
+        let line = BBox::new(0.0, 1.0, 2.0, 1.0).to_geometry();
+        assert_eq!(line.geometry_type(), "ST_LineString");
+
+        let point = BBox::new(3.0, 4.0, 3.0, 4.0).to_geometry();
+        assert_eq!(point.to_wkt(), "POINT(3 4)");
+    }
+
     #[test]
     fn test_bbox_creation() {
         let bbox = BBox::new(0.0, 0.0, 1.0, 1.0);
@@ -490,6 +1269,115 @@ mod tests {
         assert!(!bbox1.overlaps(&bbox3));
     }
 
+    #[test]
+    fn test_bbox_distance() {
+        let bbox = BBox::new(0.0, 0.0, 1.0, 1.0);
+        assert_eq!(bbox.distance(&BBox::new(0.5, 0.5, 2.0, 2.0)), 0.0);
+        assert_eq!(bbox.distance(&BBox::new(1.0, 3.0, 1.0, 3.0)), 2.0);
+        assert_eq!(bbox.distance(&BBox::new(4.0, 5.0, 6.0, 7.0)), 5.0);
+        assert_eq!(BBox::new(4.0, 5.0, 6.0, 7.0).distance(&bbox), 5.0);
+    }
+
+    #[test]
+    fn test_gist_penalty() {
+        let page = BBox::new(0.0, 0.0, 2.0, 2.0);
+        assert_eq!(
+            gist_penalty(&page, &BBox::new(1.0, 1.0, 1.0, 1.0), 1.0),
+            0.0
+        );
+        // Area grows by 2, the margin by 1
+        let entry = BBox::new(3.0, 0.0, 3.0, 0.0);
+        assert_eq!(gist_penalty(&page, &entry, 0.0), 2.0);
+        assert_eq!(gist_penalty(&page, &entry, 0.5), 2.5);
+    }
+
+    #[test]
+    fn test_gist_penalty_empty() {
+        let page = BBox::new(0.0, 0.0, 2.0, 2.0);
+        assert_eq!(gist_penalty(&BBox::empty(), &BBox::empty(), 1.0), 0.0);
+        assert_eq!(gist_penalty(&page, &BBox::empty(), 1.0), f32::MAX as f64);
+        assert_eq!(gist_penalty(&BBox::empty(), &page, 1.0), f32::MAX as f64);
+    }
+
+    #[test]
+    fn test_split_entries() {
+        let point = |x: f64, y: f64| BBox::new(x, y, x, y);
+        // Two clusters along y, given interleaved
+        let entries = vec![
+            point(0.0, 0.0),
+            point(1.0, 10.0),
+            point(0.0, 1.0),
+            point(1.0, 11.0),
+            point(0.5, 12.0),
+        ];
+        let (lower, upper) = split_entries(&entries, 0.4);
+        assert_eq!(lower, vec![0, 2]);
+        assert_eq!(upper.len(), 3);
+        // A fill factor of 50% splits in half
+        let (lower, upper) = split_entries(&entries, 0.5);
+        assert_eq!((lower.len(), upper.len()), (2, 3));
+        assert_eq!(
+            split_entries(&[point(0.0, 0.0)], 0.4).1,
+            Vec::<usize>::new()
+        );
+
+        // Empty entries go to a side of their own
+        let mixed = [point(0.0, 0.0), BBox::empty(), point(1.0, 1.0)];
+        assert_eq!(split_entries(&mixed, 0.4), (vec![0, 2], vec![1]));
+        let empties = [BBox::empty(), BBox::empty(), BBox::empty()];
+        assert_eq!(split_entries(&empties, 0.4), (vec![0, 1], vec![2]));
+    }
+
+    #[test]
+    fn test_consistent() {
+        let query = BBox::new(2.0, 2.0, 4.0, 4.0);
+        let leaf = |key: BBox, strategy: i16| leaf_consistent(&key, &query, strategy);
+        assert!(leaf(BBox::new(0.0, 0.0, 1.0, 1.0), 1));
+        assert!(!leaf(BBox::new(0.0, 0.0, 2.0, 1.0), 1));
+        // &< only asks that the key does not lie right of the query
+        assert!(leaf(BBox::new(3.0, 0.0, 9.0, 1.0), 2));
+        assert!(!leaf(BBox::new(5.0, 0.0, 9.0, 1.0), 2));
+        assert!(leaf(BBox::new(3.0, 3.0, 5.0, 5.0), 3));
+        assert!(leaf(query.clone(), 6));
+        assert!(leaf(BBox::new(1.0, 1.0, 5.0, 5.0), 7));
+        assert!(!leaf(BBox::new(3.0, 3.0, 5.0, 5.0), 7));
+        assert!(leaf(BBox::new(3.0, 3.0, 3.0, 3.0), 8));
+        assert!(leaf(BBox::new(0.0, 5.0, 1.0, 6.0), 13));
+        assert!(!leaf(BBox::new(0.0, 5.0, 1.0, 6.0), 10));
+        assert!(!leaf(BBox::empty(), 3));
+        assert!(!leaf(BBox::empty(), 6));
+        assert!(leaf_consistent(&BBox::empty(), &BBox::empty(), 6));
+        assert!(!leaf_consistent(&BBox::empty(), &BBox::empty(), 3));
+
+        // Inner keys cover leaves that would match even when the key itself
+        // does not
+        let node = BBox::new(0.0, 0.0, 10.0, 10.0);
+        for strategy in [1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13] {
+            assert!(inner_consistent(&node, &query, strategy), "{}", strategy);
+        }
+        let right = BBox::new(5.0, 0.0, 10.0, 10.0);
+        assert!(!inner_consistent(&right, &query, 1));
+        assert!(!inner_consistent(&right, &query, 2));
+        assert!(inner_consistent(&right, &query, 5));
+        assert!(!inner_consistent(&right, &query, 8));
+        assert!(!inner_consistent(&BBox::empty(), &query, 3));
+        assert!(inner_consistent(&right, &BBox::empty(), 6));
+        assert!(!inner_consistent(&right, &BBox::empty(), 3));
+    }
+
+    #[test]
+    fn test_stored_key() {
+        use crate::functions::geometry_from_wkt;
+        use crate::serialization::serialize;
+
+        let key =
+            |wkt: &str| BBox::stored_key(&serialize(&geometry_from_wkt(wkt).unwrap())).unwrap();
+        assert_eq!(key("POINT(1 2)"), BBox::new(1.0, 2.0, 1.0, 2.0));
+        assert_eq!(key("LINESTRING(0 5, 3 1)"), BBox::new(0.0, 1.0, 3.0, 5.0));
+        assert!(key("POINT EMPTY").is_empty());
+        assert!(key("GEOMETRYCOLLECTION(POINT EMPTY)").is_empty());
+    }
+
     #[test]
     fn test_spatial_index() {
         use crate::functions::make_point;
@@ -505,4 +1393,98 @@ mod tests {
         let nearest = index.nearest_neighbor([0.1, 0.1]).unwrap();
         assert_eq!(nearest.id, 1);
     }
+
+    #[test]
+    fn test_query_predicate() {
+        use crate::functions::{geometry_from_wkt, make_point};
+
+        let index = SpatialIndex::from_geometries(vec![
+            GeometryWithId::new(
+                1,
+                geometry_from_wkt("POLYGON((0 0, 10 0, 10 10, 0 0))").unwrap(),
+            ),
+            GeometryWithId::new(2, geometry_from_wkt("LINESTRING(0 2, 4 6)").unwrap()),
+            GeometryWithId::new(3, make_point(2.0, 1.0)),
+            GeometryWithId::new(4, make_point(20.0, 20.0)),
+        ]);
+        let ids = |found: Vec<&GeometryWithId>| {
+            let mut ids: Vec<i64> = found.iter().map(|g| g.id).collect();
+            ids.sort_unstable();
+            ids
+        };
+
+        // The point (1, 2) is in the boxes of the triangle and the line but
+        // on neither
+        let point = make_point(1.0, 2.0);
+        assert_eq!(
+            ids(index.query_bbox(&BBox::from_geometry(&point))),
+            vec![1, 2]
+        );
+        assert!(index
+            .query_predicate(IndexPredicate::Intersects(&point))
+            .is_empty());
+        assert_eq!(
+            ids(index.query_predicate(IndexPredicate::DWithin(&point, 1.0))),
+            vec![1, 2]
+        );
+
+        let inside = make_point(6.0, 2.0);
+        assert_eq!(
+            ids(index.query_predicate(IndexPredicate::Contains(&inside))),
+            vec![1]
+        );
+        let area = geometry_from_wkt("POLYGON((1 0, 5 0, 5 7, 1 7, 1 0))").unwrap();
+        assert_eq!(
+            ids(index.query_predicate(IndexPredicate::Within(&area))),
+            vec![3]
+        );
+        assert_eq!(
+            ids(index.query_predicate(IndexPredicate::Intersects(&area))),
+            vec![1, 2, 3]
+        );
+
+        // Filtered nearest neighbours skip the polygon and the line
+        let nearest = index.k_nearest_neighbors_matching(
+            [0.0, 0.0],
+            2,
+            IndexPredicate::Within(
+                &geometry_from_wkt("POLYGON((-50 -50, 50 -50, 50 50, -50 50, -50 -50))").unwrap(),
+            ),
+        );
+        assert_eq!(nearest.len(), 2);
+
+        // The callback sees only geometries overlapping the box
+        let filtered = index.query_bbox_filtered(&BBox::new(0.0, 0.0, 5.0, 5.0), |g| g.id != 2);
+        assert_eq!(ids(filtered), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_index_join() {
+        use crate::functions::{geometry_from_wkt, make_point};
+
+        let zones = vec![
+            Some(geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap()),
+            None,
+            Some(geometry_from_wkt("POLYGON((3 3, 8 3, 8 8, 3 8, 3 3))").unwrap()),
+        ];
+        let points = vec![
+            Some(make_point(1.0, 1.0)),
+            Some(make_point(3.5, 3.5)),
+            None,
+            Some(make_point(9.0, 9.0)),
+        ];
+        assert_eq!(
+            index_join(&zones, &points, "contains", 0.0).unwrap(),
+            vec![(0, 0), (0, 1), (2, 1)]
+        );
+        assert_eq!(
+            index_join(&points, &zones, "within", 0.0).unwrap(),
+            vec![(0, 0), (1, 0), (1, 2)]
+        );
+        assert_eq!(
+            index_join(&zones, &points, "dwithin", 1.5).unwrap(),
+            vec![(0, 0), (0, 1), (2, 1), (2, 3)]
+        );
+        assert!(index_join(&zones, &points, "touches", 0.0).is_err());
+    }
 }
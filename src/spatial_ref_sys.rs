@@ -0,0 +1,98 @@
+use crate::utils::extension_schema;
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+
+// The spatial_ref_sys catalog table, laid out exactly like the PostGIS one so
+// that clients (QGIS, GeoServer, ogr2ogr, ...) can query it unchanged. It is
+// registered as an extension configuration table so user-added rows survive
+// pg_dump/pg_restore while the bundled rows are recreated by the extension.
+//
+// Bundled are WGS 84, NAD83, Web Mercator and the 120 WGS 84 / UTM zones
+// (EPSG 32601-32660 north, 32701-32760 south), generated from the zone
+// number. Other systems are loaded from the PostGIS or EPSG definitions, see
+// the installation guide.
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.spatial_ref_sys (
+    srid integer NOT NULL PRIMARY KEY CHECK (srid > 0 AND srid <= 998999),
+    auth_name varchar(256),
+    auth_srid integer,
+    srtext varchar(2048),
+    proj4text varchar(2048)
+);
+
+INSERT INTO @extschema@.spatial_ref_sys (srid, auth_name, auth_srid, srtext, proj4text) VALUES
+(4326, 'EPSG', 4326,
+ 'GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]',
+ '+proj=longlat +datum=WGS84 +no_defs'),
+(4269, 'EPSG', 4269,
+ 'GEOGCS["NAD83",DATUM["North_American_Datum_1983",SPHEROID["GRS 1980",6378137,298.257222101,AUTHORITY["EPSG","7019"]],TOWGS84[0,0,0,0,0,0,0],AUTHORITY["EPSG","6269"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4269"]]',
+ '+proj=longlat +datum=NAD83 +no_defs'),
+(3857, 'EPSG', 3857,
+ 'PROJCS["WGS 84 / Pseudo-Mercator",GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]],PROJECTION["Mercator_1SP"],PARAMETER["central_meridian",0],PARAMETER["scale_factor",1],PARAMETER["false_easting",0],PARAMETER["false_northing",0],UNIT["metre",1,AUTHORITY["EPSG","9001"]],AXIS["X",EAST],AXIS["Y",NORTH],AUTHORITY["EPSG","3857"]]',
+ '+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +nadgrids=@null +wktext +no_defs');
+
+INSERT INTO @extschema@.spatial_ref_sys (srid, auth_name, auth_srid, srtext, proj4text)
+SELECT h.base + zone, 'EPSG', h.base + zone,
+       format('PROJCS["WGS 84 / UTM zone %s%s",GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]],PROJECTION["Transverse_Mercator"],PARAMETER["latitude_of_origin",0],PARAMETER["central_meridian",%s],PARAMETER["scale_factor",0.9996],PARAMETER["false_easting",500000],PARAMETER["false_northing",%s],UNIT["metre",1,AUTHORITY["EPSG","9001"]],AXIS["Easting",EAST],AXIS["Northing",NORTH],AUTHORITY["EPSG","%s"]]',
+              zone, h.letter, zone * 6 - 183, h.false_northing, h.base + zone),
+       format('+proj=utm +zone=%s%s +datum=WGS84 +units=m +no_defs', zone, h.south)
+FROM generate_series(1, 60) AS zone,
+     (VALUES (32600, 'N', 0, ''), (32700, 'S', 10000000, ' +south'))
+         AS h(base, letter, false_northing, south);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.spatial_ref_sys',
+    'WHERE srid NOT IN (4326, 4269, 3857) AND srid NOT BETWEEN 32601 AND 32660 AND srid NOT BETWEEN 32701 AND 32760');
+"#,
+    name = "spatial_ref_sys",
+);
+
+/// Check whether an SRID is defined in spatial_ref_sys
+pub fn srid_exists(srid: i32) -> Result<bool, pgrx::spi::Error> {
+    let schema = extension_schema()?;
+    Ok(Spi::get_one_with_args::<bool>(
+        &format!("SELECT EXISTS(SELECT 1 FROM {schema}.spatial_ref_sys WHERE srid = $1)"),
+        &[srid.into()],
+    )?
+    .unwrap_or(false))
+}
+
+/// Look up the PROJ definition string for an SRID
+pub fn lookup_proj4text(srid: i32) -> Result<Option<String>, pgrx::spi::Error> {
+    let schema = extension_schema()?;
+    Spi::get_one_with_args::<String>(
+        &format!("SELECT proj4text FROM {schema}.spatial_ref_sys WHERE srid = $1"),
+        &[srid.into()],
+    )
+}
+
+/// Look up the OGC WKT definition for an SRID
+pub fn lookup_srtext(srid: i32) -> Result<Option<String>, pgrx::spi::Error> {
+    let schema = extension_schema()?;
+    Spi::get_one_with_args::<String>(
+        &format!("SELECT srtext FROM {schema}.spatial_ref_sys WHERE srid = $1"),
+        &[srid.into()],
+    )
+}
+
+/// PostgreSQL function to check whether an SRID is known to the extension
+#[pg_extern(stable, parallel_safe)]
+pub fn rostgis_srid_exists(srid: i32) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(srid_exists(srid)?)
+}
+
+/// PostgreSQL function returning the PROJ definition of an SRID
+#[pg_extern(stable, parallel_safe)]
+pub fn rostgis_proj4text(
+    srid: i32,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(lookup_proj4text(srid)?)
+}
+
+/// PostgreSQL function returning the OGC WKT definition of an SRID
+#[pg_extern(stable, parallel_safe)]
+pub fn rostgis_srtext(
+    srid: i32,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(lookup_srtext(srid)?)
+}
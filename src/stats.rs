@@ -0,0 +1,261 @@
+use crate::guc;
+use pgrx::prelude::*;
+use pgrx::shmem::*;
+use pgrx::{pg_shmem_init, PgAtomic};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// Usage statistics (rostgis_stat)
+//
+// With rostgis.stats on, hot paths bump a counter of the current backend.
+// Those are plain backend-local integers, so counting costs an increment;
+// every FLUSH_INTERVAL events the backend adds what it counted to shared
+// atomics, one fetch_add per counter, so busy backends never wait on a lock.
+//
+// The shared counters need RostGIS in shared_preload_libraries. Without it
+// rostgis_stat reports the current backend only, with scope 'backend'.
+// Only superusers may call rostgis_stat_reset unless they grant it.
+
+/// Counted events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// Geometry datums decoded from the on-disk format
+    GeometriesDecoded,
+    /// Geometries encoded into the on-disk format
+    GeometriesEncoded,
+    /// Evaluations of the && bounding box operator
+    BBoxTests,
+    /// ST_IsValid calls answered from the stored known-valid flag
+    ValidFlagHits,
+}
+
+/// All counters, in reporting order
+pub const COUNTERS: [Counter; 4] = [
+    Counter::GeometriesDecoded,
+    Counter::GeometriesEncoded,
+    Counter::BBoxTests,
+    Counter::ValidFlagHits,
+];
+
+impl Counter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Counter::GeometriesDecoded => "geometries_decoded",
+            Counter::GeometriesEncoded => "geometries_encoded",
+            Counter::BBoxTests => "bbox_tests",
+            Counter::ValidFlagHits => "valid_flag_hits",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Events a backend counts before adding them to the shared counters
+const FLUSH_INTERVAL: u32 = 4096;
+
+static SHARED_DECODED: PgAtomic<AtomicU64> =
+    unsafe { PgAtomic::new(c"rostgis_stat_geometries_decoded") };
+static SHARED_ENCODED: PgAtomic<AtomicU64> =
+    unsafe { PgAtomic::new(c"rostgis_stat_geometries_encoded") };
+static SHARED_BBOX_TESTS: PgAtomic<AtomicU64> =
+    unsafe { PgAtomic::new(c"rostgis_stat_bbox_tests") };
+static SHARED_VALID_FLAG_HITS: PgAtomic<AtomicU64> =
+    unsafe { PgAtomic::new(c"rostgis_stat_valid_flag_hits") };
+
+/// Shared counters, indexed like COUNTERS
+static SHARED: [&PgAtomic<AtomicU64>; 4] = [
+    &SHARED_DECODED,
+    &SHARED_ENCODED,
+    &SHARED_BBOX_TESTS,
+    &SHARED_VALID_FLAG_HITS,
+];
+
+/// Whether the shared counters were set up, i.e. RostGIS is preloaded
+static SHARED_READY: AtomicBool = AtomicBool::new(false);
+
+/// Counts of the current backend not yet added to the shared counters
+#[derive(Default)]
+struct LocalCounters {
+    pending: [Cell<u64>; 4],
+    events: Cell<u32>,
+}
+
+thread_local! {
+    static LOCAL: LocalCounters = LocalCounters::default();
+}
+
+/// Request the shared counters; only possible while shared_preload_libraries
+/// is being processed
+pub fn init() {
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        pg_shmem_init!(SHARED_DECODED);
+        pg_shmem_init!(SHARED_ENCODED);
+        pg_shmem_init!(SHARED_BBOX_TESTS);
+        pg_shmem_init!(SHARED_VALID_FLAG_HITS);
+        SHARED_READY.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Count an event, if rostgis.stats is on
+#[inline]
+pub fn count(counter: Counter) {
+    if guc::STATS.get() {
+        record(counter);
+    }
+}
+
+fn record(counter: Counter) {
+    LOCAL.with(|local| {
+        let pending = &local.pending[counter.index()];
+        pending.set(pending.get() + 1);
+        local.events.set(local.events.get() + 1);
+        if local.events.get() >= FLUSH_INTERVAL {
+            flush(local);
+        }
+    });
+}
+
+/// Move the counts of the current backend to the shared counters
+fn flush(local: &LocalCounters) {
+    if !SHARED_READY.load(Ordering::Relaxed) {
+        // Nothing to flush to: the local counts are the whole picture
+        local.events.set(0);
+        return;
+    }
+    for (pending, shared) in local.pending.iter().zip(SHARED.iter()) {
+        if pending.get() > 0 {
+            shared.get().fetch_add(pending.take(), Ordering::Relaxed);
+        }
+    }
+    local.events.set(0);
+}
+
+/// Current counts and whether they cover the whole cluster or only the
+/// current backend. The counts of other backends lag by at most
+/// FLUSH_INTERVAL events each.
+pub fn snapshot() -> (bool, Vec<(Counter, u64)>) {
+    LOCAL.with(|local| {
+        flush(local);
+        let shared = SHARED_READY.load(Ordering::Relaxed);
+        let counts = COUNTERS
+            .iter()
+            .map(|counter| {
+                let value = if shared {
+                    SHARED[counter.index()].get().load(Ordering::Relaxed)
+                } else {
+                    local.pending[counter.index()].get()
+                };
+                (*counter, value)
+            })
+            .collect();
+        (shared, counts)
+    })
+}
+
+/// Zero the counters. Counts other backends have not flushed yet are added
+/// after the reset.
+pub fn reset() {
+    LOCAL.with(|local| {
+        for pending in &local.pending {
+            pending.set(0);
+        }
+        local.events.set(0);
+    });
+    if SHARED_READY.load(Ordering::Relaxed) {
+        for shared in SHARED.iter() {
+            shared.get().store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// PostgreSQL function reporting the RostGIS usage counters, collected while
+/// rostgis.stats is on
+#[pg_extern]
+fn rostgis_stat() -> TableIterator<
+    'static,
+    (
+        name!(counter, String),
+        name!(calls, i64),
+        name!(scope, String),
+    ),
+> {
+    let (shared, counts) = snapshot();
+    let scope = if shared { "cluster" } else { "backend" };
+    TableIterator::new(counts.into_iter().map(move |(counter, value)| {
+        (
+            counter.as_str().to_string(),
+            value as i64,
+            scope.to_string(),
+        )
+    }))
+}
+
+/// PostgreSQL function zeroing the RostGIS usage counters
+#[pg_extern]
+fn rostgis_stat_reset() {
+    reset();
+}
+
+// The counters are shared by the whole cluster, so only superusers (and
+// roles they grant it to) may zero them, as with pg_stat_reset
+extension_sql!(
+    r#"
+REVOKE EXECUTE ON FUNCTION @extschema@.rostgis_stat_reset() FROM PUBLIC;
+"#,
+    name = "stat_reset_privileges",
+    requires = [rostgis_stat_reset]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(counter: Counter) -> u64 {
+        snapshot()
+            .1
+            .into_iter()
+            .find(|(c, _)| *c == counter)
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_record_and_reset() {
+        reset();
+        for _ in 0..3 {
+            record(Counter::BBoxTests);
+        }
+        record(Counter::ValidFlagHits);
+
+        let (shared, _) = snapshot();
+        assert!(!shared);
+        assert_eq!(value(Counter::BBoxTests), 3);
+        assert_eq!(value(Counter::ValidFlagHits), 1);
+
+        // Crossing the flush interval keeps backend-local counts intact
+        for _ in 0..FLUSH_INTERVAL {
+            record(Counter::BBoxTests);
+        }
+        assert_eq!(value(Counter::BBoxTests), 3 + u64::from(FLUSH_INTERVAL));
+
+        reset();
+        assert!(snapshot().1.iter().all(|(_, value)| *value == 0));
+    }
+
+    #[test]
+    fn test_counter_names() {
+        let names: Vec<_> = COUNTERS.iter().map(Counter::as_str).collect();
+        assert_eq!(
+            names,
+            [
+                "geometries_decoded",
+                "geometries_encoded",
+                "bbox_tests",
+                "valid_flag_hits"
+            ]
+        );
+        assert!(COUNTERS.iter().enumerate().all(|(i, c)| c.index() == i));
+    }
+}
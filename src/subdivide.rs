@@ -0,0 +1,333 @@
+use crate::geometry::Geometry;
+use crate::utils::RostGisError;
+use geo::{BooleanOps, BoundingRect, CoordsIter};
+use geo_types::{Coord, LineString, MultiLineString, MultiPoint, MultiPolygon, Rect};
+use pgrx::prelude::*;
+
+// Subdivision of large geometries (ST_Subdivide)
+//
+// Like in PostGIS, a geometry with too many vertices is cut in half across
+// the longer side of its bounding box, and each half is clipped out and
+// subdivided again until every piece has at most max_vertices vertices.
+// The pieces have small bounding boxes, so an index on them answers a
+// spatial join with far fewer candidates than one box around a country or
+// an ocean. Polygon pieces share their cut edges; points on a cut go to the
+// upper half only.
+
+/// Smallest vertex limit accepted, as in PostGIS
+pub const MIN_VERTICES: usize = 5;
+
+/// Cuts never go deeper than this, whatever the vertex count
+const MAX_DEPTH: usize = 50;
+
+/// A geometry of one dimension, in the form clipping works on
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Points(MultiPoint<f64>),
+    Lines(MultiLineString<f64>),
+    Polygons(MultiPolygon<f64>),
+}
+
+impl Part {
+    fn coords_count(&self) -> usize {
+        match self {
+            Part::Points(points) => points.coords_count(),
+            Part::Lines(lines) => lines.coords_count(),
+            Part::Polygons(polygons) => polygons.coords_count(),
+        }
+    }
+
+    fn bounding_rect(&self) -> Option<Rect<f64>> {
+        match self {
+            Part::Points(points) => points.bounding_rect(),
+            Part::Lines(lines) => lines.bounding_rect(),
+            Part::Polygons(polygons) => polygons.bounding_rect(),
+        }
+    }
+
+    /// The part within one half of a box cut at `cut` across `axis`
+    fn clip(&self, half: &Rect<f64>, axis: usize, cut: f64, upper: bool) -> Part {
+        let ordinate = |coord: Coord<f64>| if axis == 0 { coord.x } else { coord.y };
+        match self {
+            Part::Points(points) => Part::Points(
+                points
+                    .iter()
+                    .filter(|point| (ordinate(point.0) >= cut) == upper)
+                    .copied()
+                    .collect(),
+            ),
+            Part::Lines(lines) => Part::Lines(MultiLineString(
+                lines
+                    .iter()
+                    .flat_map(|line| clip_line(line, axis, cut, upper))
+                    .collect(),
+            )),
+            Part::Polygons(polygons) => {
+                Part::Polygons(polygons.intersection(&MultiPolygon(vec![half.to_polygon()])))
+            }
+        }
+    }
+
+    fn into_geometry(self, srid: i32) -> Geometry {
+        match self {
+            Part::Points(mut points) if points.0.len() == 1 => {
+                Geometry::Point(points.0.remove(0), srid)
+            }
+            Part::Points(points) => Geometry::MultiPoint(points, srid),
+            Part::Lines(mut lines) if lines.0.len() == 1 => {
+                Geometry::LineString(lines.0.remove(0), srid)
+            }
+            Part::Lines(lines) => Geometry::MultiLineString(lines, srid),
+            Part::Polygons(mut polygons) if polygons.0.len() == 1 => {
+                Geometry::Polygon(polygons.0.remove(0), srid)
+            }
+            Part::Polygons(polygons) => Geometry::MultiPolygon(polygons, srid),
+        }
+    }
+}
+
+/// Pieces of a line on one side of a cut
+///
+/// Unlike clipping with a rectangle this also works for lines along the
+/// cut's axis, whose bounding boxes have no area.
+fn clip_line(line: &LineString<f64>, axis: usize, cut: f64, upper: bool) -> Vec<LineString<f64>> {
+    let ordinate = |coord: &Coord<f64>| if axis == 0 { coord.x } else { coord.y };
+    let inside = |coord: &Coord<f64>| {
+        if upper {
+            ordinate(coord) >= cut
+        } else {
+            ordinate(coord) <= cut
+        }
+    };
+    let crossing = |a: &Coord<f64>, b: &Coord<f64>| {
+        let t = (cut - ordinate(a)) / (ordinate(b) - ordinate(a));
+        let mut coord = *a + (*b - *a) * t;
+        if axis == 0 {
+            coord.x = cut;
+        } else {
+            coord.y = cut;
+        }
+        coord
+    };
+
+    let mut pieces = Vec::new();
+    let mut piece: Vec<Coord<f64>> = Vec::new();
+    for segment in line.0.windows(2) {
+        let (a, b) = (&segment[0], &segment[1]);
+        match (inside(a), inside(b)) {
+            (true, true) => {
+                if piece.is_empty() {
+                    piece.push(*a);
+                }
+                piece.push(*b);
+            }
+            (true, false) => {
+                if piece.is_empty() {
+                    piece.push(*a);
+                }
+                piece.push(crossing(a, b));
+                pieces.push(std::mem::take(&mut piece));
+            }
+            (false, true) => piece.extend([crossing(a, b), *b]),
+            (false, false) => {}
+        }
+    }
+    pieces.push(piece);
+    pieces
+        .into_iter()
+        .filter(|piece| piece.iter().any(|coord| *coord != piece[0]))
+        .map(LineString)
+        .collect()
+}
+
+/// Parts of a geometry by dimension, without empty points
+fn parts(geom: &Geometry, into: &mut Vec<Part>) {
+    match geom {
+        Geometry::Zm(base, _) => parts(base, into),
+        Geometry::Point(point, _) => {
+            if !geom.is_empty() {
+                into.push(Part::Points(MultiPoint(vec![*point])));
+            }
+        }
+        Geometry::MultiPoint(points, _) => into.push(Part::Points(MultiPoint(
+            points
+                .iter()
+                .filter(|point| !point.x().is_nan())
+                .copied()
+                .collect(),
+        ))),
+        Geometry::LineString(line, _) => {
+            into.push(Part::Lines(MultiLineString(vec![line.clone()])))
+        }
+        Geometry::MultiLineString(lines, _) => into.push(Part::Lines(lines.clone())),
+        Geometry::Polygon(polygon, _) => {
+            into.push(Part::Polygons(MultiPolygon(vec![polygon.clone()])))
+        }
+        Geometry::MultiPolygon(polygons, _) => into.push(Part::Polygons(polygons.clone())),
+        Geometry::GeometryCollection(members, _) => {
+            for member in members {
+                parts(member, into);
+            }
+        }
+    }
+}
+
+fn subdivide_part(part: Part, max_vertices: usize, depth: usize, pieces: &mut Vec<Part>) {
+    let count = part.coords_count();
+    let Some(bounds) = part.bounding_rect() else {
+        return;
+    };
+    if count == 0 {
+        return;
+    }
+    if count <= max_vertices
+        || depth >= MAX_DEPTH
+        || (bounds.width() == 0.0 && bounds.height() == 0.0)
+    {
+        pieces.push(part);
+        return;
+    }
+
+    let (min, max) = (bounds.min(), bounds.max());
+    let (axis, cut, halves) = if bounds.width() >= bounds.height() {
+        let cut = min.x + bounds.width() / 2.0;
+        (
+            0,
+            cut,
+            [
+                Rect::new(min, Coord { x: cut, y: max.y }),
+                Rect::new(Coord { x: cut, y: min.y }, max),
+            ],
+        )
+    } else {
+        let cut = min.y + bounds.height() / 2.0;
+        (
+            1,
+            cut,
+            [
+                Rect::new(min, Coord { x: max.x, y: cut }),
+                Rect::new(Coord { x: min.x, y: cut }, max),
+            ],
+        )
+    };
+    for (half, upper) in halves.iter().zip([false, true]) {
+        subdivide_part(
+            part.clip(half, axis, cut, upper),
+            max_vertices,
+            depth + 1,
+            pieces,
+        );
+    }
+}
+
+/// Cut a geometry into pieces of at most `max_vertices` vertices each
+///
+/// Collections are subdivided member by member, so every piece has a single
+/// dimension. Empty geometries have no pieces.
+pub fn subdivide(geom: &Geometry, max_vertices: usize) -> Result<Vec<Geometry>, RostGisError> {
+    if max_vertices < MIN_VERTICES {
+        return Err(RostGisError::new(&format!(
+            "ST_Subdivide: max_vertices must be at least {}",
+            MIN_VERTICES
+        )));
+    }
+    let mut whole = Vec::new();
+    parts(geom, &mut whole);
+    let mut pieces = Vec::new();
+    for part in whole {
+        subdivide_part(part, max_vertices, 0, &mut pieces);
+    }
+    Ok(pieces
+        .into_iter()
+        .map(|piece| piece.into_geometry(geom.srid()))
+        .collect())
+}
+
+/// PostgreSQL set-returning function cutting a geometry into pieces of at
+/// most `max_vertices` vertices
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn st_subdivide(
+    geom: Geometry,
+    max_vertices: default!(i32, 256),
+) -> Result<SetOfIterator<'static, Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    let max_vertices = usize::try_from(max_vertices).unwrap_or(0);
+    Ok(SetOfIterator::new(subdivide(&geom, max_vertices)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::geometry_from_wkt;
+    use geo::Area;
+    use geo_types::Point;
+
+    /// A polygon approximating a circle with `n` vertices
+    fn circle(n: usize) -> Geometry {
+        let ring: Vec<Coord<f64>> = (0..=n)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * (i % n) as f64 / n as f64;
+                Coord {
+                    x: 100.0 * angle.cos(),
+                    y: 100.0 * angle.sin(),
+                }
+            })
+            .collect();
+        Geometry::Polygon(geo_types::Polygon::new(LineString(ring), vec![]), 4326)
+    }
+
+    #[test]
+    fn test_subdivide_polygon() {
+        let polygon = circle(1000);
+        let pieces = subdivide(&polygon, 64).unwrap();
+        assert!(pieces.len() > 16);
+        assert!(pieces.iter().all(|piece| piece.coordinates().len() <= 64));
+        assert!(pieces.iter().all(|piece| piece.srid() == 4326));
+        let area: f64 = pieces
+            .iter()
+            .map(|piece| piece.to_geo().unsigned_area())
+            .sum();
+        // Up to rounding at the cuts
+        assert!((area / polygon.to_geo().unsigned_area() - 1.0).abs() < 1e-9);
+
+        // Small enough already
+        assert_eq!(subdivide(&polygon, 2000).unwrap(), vec![polygon]);
+    }
+
+    #[test]
+    fn test_subdivide_lines_and_points() {
+        let line = geometry_from_wkt(
+            "LINESTRING(0 0, 1 0, 2 0, 3 0, 4 0, 5 0, 6 0, 7 0, 8 0, 9 0, 10 0, 11 0)",
+        )
+        .unwrap();
+        let pieces = subdivide(&line, 5).unwrap();
+        assert!(pieces.len() >= 3);
+        let length: f64 = pieces
+            .iter()
+            .map(|piece| crate::functions::geometry_length(piece.clone()))
+            .sum();
+        assert!((length - 11.0).abs() < 1e-9);
+
+        let points: Vec<Point<f64>> = (0..20).map(|i| Point::new(i as f64, 0.0)).collect();
+        let multipoint = Geometry::MultiPoint(MultiPoint(points), 0);
+        let pieces = subdivide(&multipoint, 5).unwrap();
+        let count: usize = pieces.iter().map(|piece| piece.coordinates().len()).sum();
+        assert_eq!(count, 20);
+        assert!(pieces.iter().all(|piece| piece.coordinates().len() <= 5));
+    }
+
+    #[test]
+    fn test_subdivide_edge_cases() {
+        assert!(subdivide(&circle(10), 4).is_err());
+        let empty = geometry_from_wkt("POLYGON EMPTY").unwrap();
+        assert!(subdivide(&empty, 10).unwrap().is_empty());
+        // Coincident points cannot be cut apart
+        let stacked = Geometry::MultiPoint(MultiPoint(vec![Point::new(1.0, 1.0); 10]), 0);
+        assert_eq!(subdivide(&stacked, 5).unwrap(), vec![stacked]);
+        // Collections are split by member
+        let collection =
+            geometry_from_wkt("GEOMETRYCOLLECTION(POINT(0 0), LINESTRING(0 0, 1 1))").unwrap();
+        let pieces = subdivide(&collection, 5).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].geometry_type(), "ST_Point");
+    }
+}
@@ -0,0 +1,118 @@
+use crate::utils::extension_schema;
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread::LocalKey;
+
+// Backend caches of extension tables
+//
+// Tables read on hot paths, such as the regions of rostgis_watch, are
+// loaded into an R-tree once by each backend using them (the fences keep a
+// shared copy instead, see fences). A statement trigger on such a table
+// sets its row of rostgis_table_versions to a new value of a sequence, and
+// a backend loads the table again when the version it reads differs from
+// the one its cache was built at.
+//
+// Versions are rows like any other, so committed changes are seen from the
+// next statement. A cache may be loaded from changes that are then rolled
+// back, but their version was drawn from the sequence, which never hands
+// out a value twice, even to transactions that roll back: no later version
+// can match it, and the table is loaded again. The version is read once per
+// statement, and again after each change made within the statement, such
+// as by a trigger or a PL/pgSQL function.
+
+/// Statement of the current transaction: the transaction and statement
+/// start times, and the command counter, which advances after each change
+/// the statement makes
+pub type Statement = (pg_sys::TimestampTz, pg_sys::TimestampTz, pg_sys::CommandId);
+
+pub fn current_statement() -> Statement {
+    unsafe {
+        (
+            pg_sys::GetCurrentTransactionStartTimestamp(),
+            pg_sys::GetCurrentStatementStartTimestamp(),
+            pg_sys::GetCurrentCommandId(false),
+        )
+    }
+}
+
+/// Contents of a table cached by a backend, with the version they were
+/// loaded at and the last statement that found that version current
+pub struct CachedTable<T> {
+    version: i64,
+    checked_in: Statement,
+    contents: Rc<T>,
+}
+
+/// Cache of a table, kept in a thread local
+pub type TableCache<T> = RefCell<Option<CachedTable<T>>>;
+
+/// Contents of a table, from the backend's cache unless the table changed
+/// since they were loaded. `load` is given the quoted extension schema.
+pub fn cached<T>(
+    cache: &'static LocalKey<TableCache<T>>,
+    table: &str,
+    load: impl FnOnce(&str) -> Result<T, pgrx::spi::Error>,
+) -> Result<Rc<T>, pgrx::spi::Error> {
+    let statement = current_statement();
+    let checked = cache.with(|cache| {
+        cache
+            .borrow()
+            .as_ref()
+            .filter(|cached| cached.checked_in == statement)
+            .map(|cached| cached.contents.clone())
+    });
+    if let Some(contents) = checked {
+        return Ok(contents);
+    }
+
+    let schema = extension_schema()?;
+    let version = Spi::get_one_with_args::<i64>(
+        &format!("SELECT version FROM {schema}.rostgis_table_versions WHERE table_name = $1"),
+        &[table.into()],
+    )?
+    .unwrap_or(0);
+    let current = cache.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let cached = cache.as_mut().filter(|cached| cached.version == version)?;
+        cached.checked_in = statement;
+        Some(cached.contents.clone())
+    });
+    if let Some(contents) = current {
+        return Ok(contents);
+    }
+    let contents = Rc::new(load(&schema)?);
+    cache.with(|cache| {
+        *cache.borrow_mut() = Some(CachedTable {
+            version,
+            checked_in: statement,
+            contents: contents.clone(),
+        })
+    });
+    Ok(contents)
+}
+
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.rostgis_table_versions (
+    table_name text PRIMARY KEY,
+    version bigint NOT NULL DEFAULT 0
+);
+
+-- Versions are never reused, even by changes that are rolled back
+CREATE SEQUENCE @extschema@.rostgis_table_versions_seq;
+
+-- Statement trigger of the tables cached by backends
+CREATE FUNCTION @extschema@.rostgis_table_changed() RETURNS trigger
+LANGUAGE plpgsql AS $$
+BEGIN
+    UPDATE @extschema@.rostgis_table_versions
+    SET version = nextval('@extschema@.rostgis_table_versions_seq')
+    WHERE table_name = TG_TABLE_NAME;
+    RETURN NULL;
+END
+$$;
+"#,
+    name = "table_versions",
+);
@@ -0,0 +1,280 @@
+use crate::geometry::Geometry;
+use crate::guc::text_axis_order;
+use crate::spatial_index::BBox;
+use crate::spatial_ref_sys::lookup_proj4text;
+use crate::utils::{srid, RostGisError};
+use pgrx::prelude::*;
+use proj::Proj;
+
+// Coordinate reference system transformations through PROJ, using the
+// proj4text definitions from spatial_ref_sys
+//
+// Boxes carry no SRID, so ST_Transform takes and returns EPSG:4326 boxes in
+// the exchange axis order: latitude/longitude with rostgis.strict_axis_order
+// on, like the text formats.
+
+/// PROJ CRS definition of an SRID, from spatial_ref_sys
+pub fn crs_definition(srid: i32) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let proj4text = lookup_proj4text(srid)?
+        .ok_or_else(|| RostGisError::new(&format!("SRID {} not found in spatial_ref_sys", srid)))?;
+    // PROJ only treats a proj-string as a CRS when +type=crs is present
+    if proj4text.contains("+type=crs") {
+        Ok(proj4text)
+    } else {
+        Ok(format!("{} +type=crs", proj4text.trim()))
+    }
+}
+
+/// Build a transformation between two SRIDs
+pub fn transformer(
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<Proj, Box<dyn std::error::Error + Send + Sync>> {
+    let from = crs_definition(from_srid)?;
+    let to = crs_definition(to_srid)?;
+    Ok(Proj::new_known_crs(&from, &to, None)?)
+}
+
+/// Step, in degrees of latitude, used to sample the direction of true north
+const CONVERGENCE_STEP: f64 = 1e-5;
+
+/// Meridian convergence at a lon/lat position for a projection
+///
+/// `project` maps lon/lat degrees to grid coordinates. The result is the
+/// angle in radians from true north to grid north, positive when grid north
+/// lies east (clockwise) of true north, so that a true azimuth is the grid
+/// azimuth plus the convergence.
+pub fn grid_convergence<F>(project: F, lon: f64, lat: f64) -> Result<f64, RostGisError>
+where
+    F: Fn(f64, f64) -> Result<(f64, f64), RostGisError>,
+{
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(RostGisError::new("Latitude must be between -90 and 90"));
+    }
+
+    // Sample along the meridian, away from the pole
+    let (lat1, lat2) = if lat + CONVERGENCE_STEP <= 90.0 {
+        (lat, lat + CONVERGENCE_STEP)
+    } else {
+        (lat - CONVERGENCE_STEP, lat)
+    };
+    let (x1, y1) = project(lon, lat1)?;
+    let (x2, y2) = project(lon, lat2)?;
+
+    // Direction of true north measured clockwise from grid north
+    let true_north = (x2 - x1).atan2(y2 - y1);
+    Ok(-true_north)
+}
+
+/// PostgreSQL function returning the grid convergence of a projected CRS
+///
+/// The point is either given in lon/lat (SRID 0 or 4326) or in the projected
+/// CRS itself. The result is in radians, like ST_Azimuth.
+#[pg_extern(stable, strict, parallel_safe)]
+pub fn st_gridconvergence(
+    point: Geometry,
+    srid: i32,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    let (x, y) = match &point {
+        Geometry::Point(p, _) => (p.x(), p.y()),
+        _ => return Err("ST_GridConvergence requires a point".into()),
+    };
+
+    let to_grid = transformer(srid::WGS84, srid)?;
+    let (lon, lat) = match point.srid() {
+        srid::UNKNOWN | srid::WGS84 => (x, y),
+        point_srid if point_srid == srid => transformer(srid, srid::WGS84)?.convert((x, y))?,
+        point_srid => {
+            return Err(format!(
+                "Point SRID {} must be 4326 or match the target SRID {}",
+                point_srid, srid
+            )
+            .into())
+        }
+    };
+
+    Ok(grid_convergence(
+        |lon, lat| {
+            to_grid
+                .convert((lon, lat))
+                .map_err(|e| RostGisError::new(&format!("Projection failed: {}", e)))
+        },
+        lon,
+        lat,
+    )?)
+}
+
+/// Points sampled along each edge of a box, corners included, as GDAL does
+/// by default when transforming bounds
+pub const DENSIFY_POINTS: usize = 21;
+
+/// Bounding box of the image of a box under a projection
+///
+/// Edges bend under most projections, so the corners are not enough: each
+/// edge is sampled at `points_per_edge` points and the result bounds their
+/// images. Points the projection fails on are left out, which clips boxes
+/// reaching beyond a projection's domain to its valid part.
+pub fn transform_bbox<F>(
+    bbox: &BBox,
+    points_per_edge: usize,
+    project: F,
+) -> Result<BBox, RostGisError>
+where
+    F: Fn(f64, f64) -> Result<(f64, f64), RostGisError>,
+{
+    let steps = points_per_edge.max(2) - 1;
+    let mut result: Option<BBox> = None;
+    for i in 0..=steps {
+        let t = i as f64 / steps as f64;
+        let x = bbox.min_x + (bbox.max_x - bbox.min_x) * t;
+        let y = bbox.min_y + (bbox.max_y - bbox.min_y) * t;
+        for (px, py) in [
+            (x, bbox.min_y),
+            (x, bbox.max_y),
+            (bbox.min_x, y),
+            (bbox.max_x, y),
+        ] {
+            let Ok((tx, ty)) = project(px, py) else {
+                continue;
+            };
+            if !tx.is_finite() || !ty.is_finite() {
+                continue;
+            }
+            let point = BBox::new(tx, ty, tx, ty);
+            result = Some(match result {
+                Some(current) => current.union(&point),
+                None => point,
+            });
+        }
+    }
+    result
+        .ok_or_else(|| RostGisError::new("ST_Transform: no point of the box could be transformed"))
+}
+
+/// Transform boxes between SRIDs with a single PROJ transformation
+fn transform_bboxes(
+    boxes: impl IntoIterator<Item = Option<BBox>>,
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<Vec<Option<BBox>>, Box<dyn std::error::Error + Send + Sync>> {
+    if from_srid == to_srid {
+        return Ok(boxes.into_iter().collect());
+    }
+    let proj = transformer(from_srid, to_srid)?;
+    let (from_order, to_order) = (text_axis_order(from_srid), text_axis_order(to_srid));
+    let project = |x: f64, y: f64| {
+        proj.convert(from_order.apply(x, y))
+            .map(|(x, y)| to_order.apply(x, y))
+            .map_err(|e| RostGisError::new(&format!("Projection failed: {}", e)))
+    };
+    let mut transformed = Vec::new();
+    for bbox in boxes {
+        transformed.push(match bbox {
+            Some(bbox) => Some(transform_bbox(&bbox, DENSIFY_POINTS, project)?),
+            None => None,
+        });
+    }
+    Ok(transformed)
+}
+
+/// PostgreSQL function transforming a box from one SRID to another, without
+/// building a geometry: the box bounding its densified edges
+#[pg_extern(stable, strict, parallel_safe, name = "st_transform")]
+pub fn st_transform_bbox(
+    bbox: BBox,
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<BBox, Box<dyn std::error::Error + Send + Sync>> {
+    let transformed = transform_bboxes([Some(bbox)], from_srid, to_srid)?;
+    Ok(transformed.into_iter().flatten().next().unwrap())
+}
+
+/// PostgreSQL function transforming an array of boxes, e.g. the tiles of a
+/// seeding job, building the transformation once; NULL elements stay NULL
+#[pg_extern(stable, strict, parallel_safe, name = "st_transform")]
+pub fn st_transform_bboxes(
+    boxes: Array<'_, BBox>,
+    from_srid: i32,
+    to_srid: i32,
+) -> Result<Vec<Option<BBox>>, Box<dyn std::error::Error + Send + Sync>> {
+    transform_bboxes(boxes.iter(), from_srid, to_srid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convergence_of_conformal_cylinder() {
+        // Mercator meridians are parallel to grid north everywhere
+        let mercator = |lon: f64, lat: f64| {
+            Ok((
+                lon.to_radians(),
+                (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0)
+                    .tan()
+                    .ln(),
+            ))
+        };
+        assert!(grid_convergence(mercator, 120.0, 45.0).unwrap().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_convergence_of_rotated_grid() {
+        // Coordinates rotated 10 degrees counter-clockwise: true north shows
+        // up west of grid north, so grid north lies 10 degrees east of it
+        let angle = 10f64.to_radians();
+        let rotated = |lon: f64, lat: f64| {
+            Ok((
+                lon * angle.cos() - lat * angle.sin(),
+                lon * angle.sin() + lat * angle.cos(),
+            ))
+        };
+        let convergence = grid_convergence(rotated, 5.0, 5.0).unwrap();
+        assert!((convergence - angle).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_convergence_near_pole() {
+        let identity = |lon: f64, lat: f64| Ok((lon, lat));
+        assert!(grid_convergence(identity, 0.0, 90.0).unwrap().abs() < 1e-9);
+        assert!(grid_convergence(identity, 0.0, 91.0).is_err());
+    }
+
+    #[test]
+    fn test_transform_bbox() {
+        let bbox = BBox::new(0.0, 0.0, 10.0, 20.0);
+        let shifted = |x: f64, y: f64| Ok((x + 1.0, y * 2.0));
+        assert_eq!(
+            transform_bbox(&bbox, DENSIFY_POINTS, shifted).unwrap(),
+            BBox::new(1.0, 0.0, 11.0, 40.0)
+        );
+
+        // Edges bulge under a curving projection: the middle of the bottom
+        // edge goes lowest, which the corners alone would miss
+        let bend = |x: f64, y: f64| Ok((x, y - x * (10.0 - x)));
+        let transformed = transform_bbox(&bbox, DENSIFY_POINTS, bend).unwrap();
+        assert_eq!(transformed.min_y, -25.0);
+        assert_eq!(transform_bbox(&bbox, 2, bend).unwrap().min_y, 0.0);
+
+        // Points outside the projection's domain are left out
+        let half = |x: f64, y: f64| {
+            if x <= 5.0 {
+                Ok((x, y))
+            } else {
+                Err(RostGisError::new("out of domain"))
+            }
+        };
+        assert_eq!(
+            transform_bbox(&bbox, DENSIFY_POINTS, half).unwrap(),
+            BBox::new(0.0, 0.0, 5.0, 20.0)
+        );
+        let nowhere = |_: f64, _: f64| Ok((f64::NAN, f64::NAN));
+        assert!(transform_bbox(&bbox, DENSIFY_POINTS, nowhere).is_err());
+
+        // A degenerate box is a point
+        assert_eq!(
+            transform_bbox(&BBox::new(3.0, 4.0, 3.0, 4.0), DENSIFY_POINTS, shifted).unwrap(),
+            BBox::new(4.0, 8.0, 4.0, 8.0)
+        );
+    }
+}
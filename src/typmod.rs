@@ -0,0 +1,486 @@
+use crate::geography::Geography;
+use crate::geometry::Geometry;
+use crate::guc::{self, StorageEncoding};
+use crate::serialization::{self, GeometryHeader};
+use crate::utils::{srid, RostGisError};
+use pgrx::prelude::*;
+use std::ffi::{CStr, CString};
+
+// Type modifier support for geometry columns, e.g. `geometry(Point, 4326)`
+//
+// The typmod is packed into a single i32 using the same layout as PostGIS:
+//   bit 0      - M flag
+//   bit 1      - Z flag
+//   bits 2-7   - geometry type code (0 = any geometry)
+//   bits 8-28  - SRID (0 = unconstrained)
+//   bit 29     - Compact storage (rostgis only, e.g. `geometry(LineString, 4326, Compact)`)
+// A negative typmod means the column has no modifier at all.
+const TYPMOD_M_FLAG: i32 = 0x01;
+const TYPMOD_Z_FLAG: i32 = 0x02;
+const TYPMOD_TYPE_SHIFT: i32 = 2;
+const TYPMOD_TYPE_MASK: i32 = 0x3F;
+const TYPMOD_SRID_SHIFT: i32 = 8;
+const TYPMOD_SRID_MASK: i32 = 0x001F_FFFF;
+const TYPMOD_COMPACT_FLAG: i32 = 1 << 29;
+
+/// Maximum SRID that fits into the typmod encoding
+pub const TYPMOD_MAX_SRID: i32 = 999_999;
+
+/// Geometry type names accepted in typmods, indexed by their type code
+const TYPE_NAMES: [&str; 8] = [
+    "Geometry",
+    "Point",
+    "LineString",
+    "Polygon",
+    "MultiPoint",
+    "MultiLineString",
+    "MultiPolygon",
+    "GeometryCollection",
+];
+
+/// Decoded geometry type modifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GeometryTypmod {
+    pub type_code: i32,
+    pub srid: i32,
+    pub has_z: bool,
+    pub has_m: bool,
+    /// Store values with the compact body encoding
+    pub compact: bool,
+}
+
+impl GeometryTypmod {
+    /// Pack the modifier into its i32 representation
+    pub fn encode(&self) -> i32 {
+        let mut typmod = (self.srid & TYPMOD_SRID_MASK) << TYPMOD_SRID_SHIFT;
+        typmod |= (self.type_code & TYPMOD_TYPE_MASK) << TYPMOD_TYPE_SHIFT;
+        if self.has_z {
+            typmod |= TYPMOD_Z_FLAG;
+        }
+        if self.has_m {
+            typmod |= TYPMOD_M_FLAG;
+        }
+        if self.compact {
+            typmod |= TYPMOD_COMPACT_FLAG;
+        }
+        typmod
+    }
+
+    /// Unpack an i32 typmod, returning None for "no modifier"
+    pub fn decode(typmod: i32) -> Option<Self> {
+        if typmod < 0 {
+            return None;
+        }
+        Some(GeometryTypmod {
+            type_code: (typmod >> TYPMOD_TYPE_SHIFT) & TYPMOD_TYPE_MASK,
+            srid: (typmod >> TYPMOD_SRID_SHIFT) & TYPMOD_SRID_MASK,
+            has_z: typmod & TYPMOD_Z_FLAG != 0,
+            has_m: typmod & TYPMOD_M_FLAG != 0,
+            compact: typmod & TYPMOD_COMPACT_FLAG != 0,
+        })
+    }
+
+    /// Parse the modifier list given in a column definition, e.g. ["Point", "4326"]
+    /// or ["LineString", "4326", "Compact"]
+    pub fn parse(modifiers: &[&str]) -> Result<Self, RostGisError> {
+        let (modifiers, compact) = match modifiers.split_last() {
+            Some((last, rest)) if last.trim().eq_ignore_ascii_case("compact") => (rest, true),
+            _ => (modifiers, false),
+        };
+        if modifiers.is_empty() || modifiers.len() > 2 {
+            return Err(RostGisError::new(
+                "Invalid geometry type modifier: expected (type), (type, srid) or either followed by Compact",
+            ));
+        }
+
+        let (type_code, has_z, has_m) = parse_type_name(modifiers[0])?;
+
+        let srid = match modifiers.get(1) {
+            Some(srid_str) => {
+                let srid: i32 = srid_str.trim().parse().map_err(|_| {
+                    RostGisError::new(&format!("Invalid SRID in type modifier: {}", srid_str))
+                })?;
+                if !(0..=TYPMOD_MAX_SRID).contains(&srid) {
+                    return Err(RostGisError::new(&format!(
+                        "SRID {} in type modifier must be between 0 and {}",
+                        srid, TYPMOD_MAX_SRID
+                    )));
+                }
+                srid
+            }
+            None => 0,
+        };
+
+        Ok(GeometryTypmod {
+            type_code,
+            srid,
+            has_z,
+            has_m,
+            compact,
+        })
+    }
+
+    /// Type name with dimension suffix, e.g. "PointZ"
+    pub fn type_name(&self) -> String {
+        let mut type_name = TYPE_NAMES[self.type_code as usize].to_string();
+        if self.has_z {
+            type_name.push('Z');
+        }
+        if self.has_m {
+            type_name.push('M');
+        }
+        type_name
+    }
+
+    /// Number of coordinate dimensions (2 to 4)
+    pub fn dimensions(&self) -> i32 {
+        2 + self.has_z as i32 + self.has_m as i32
+    }
+
+    /// Parse a geography modifier: the SRID defaults to 4326, the only one
+    /// geography supports
+    pub fn parse_geography(modifiers: &[&str]) -> Result<Self, RostGisError> {
+        let mut typmod = Self::parse(modifiers)?;
+        if typmod.compact {
+            return Err(RostGisError::new(
+                "Compact storage is only supported for geometry columns",
+            ));
+        }
+        if typmod.srid == 0 {
+            typmod.srid = srid::WGS84;
+        }
+        if typmod.srid != srid::WGS84 {
+            return Err(RostGisError::new(&format!(
+                "Geography only supports SRID {}, got {}",
+                srid::WGS84,
+                typmod.srid
+            )));
+        }
+        Ok(typmod)
+    }
+
+    /// Render the modifier the way it appears in a column definition
+    pub fn to_modifier_string(&self) -> String {
+        if self.type_code == 0 && self.srid == 0 && !self.has_z && !self.has_m && !self.compact {
+            return String::new();
+        }
+
+        let mut modifier = format!("({}", self.type_name());
+        if self.srid != 0 {
+            modifier.push_str(&format!(",{}", self.srid));
+        }
+        if self.compact {
+            modifier.push_str(",Compact");
+        }
+        modifier.push(')');
+        modifier
+    }
+
+    /// Check that a geometry satisfies this modifier
+    pub fn check(&self, geom: &Geometry) -> Result<(), RostGisError> {
+        if self.srid != 0 && geom.srid() != self.srid {
+            return Err(RostGisError::new(&format!(
+                "Geometry SRID ({}) does not match column SRID ({})",
+                geom.srid(),
+                self.srid
+            )));
+        }
+
+        let geom_type_code = geometry_type_code(geom);
+        if self.type_code != 0 && geom_type_code != self.type_code {
+            return Err(RostGisError::new(&format!(
+                "Geometry type ({}) does not match column type ({})",
+                TYPE_NAMES[geom_type_code as usize], TYPE_NAMES[self.type_code as usize]
+            )));
+        }
+
+        if self.has_z != geom.has_z() {
+            return Err(RostGisError::new(if self.has_z {
+                "Column has Z dimension but geometry does not"
+            } else {
+                "Geometry has Z dimension but column does not"
+            }));
+        }
+
+        if self.has_m != geom.has_m() {
+            return Err(RostGisError::new(if self.has_m {
+                "Column has M dimension but geometry does not"
+            } else {
+                "Geometry has M dimension but column does not"
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a type name such as "Point", "LINESTRINGZ" or "PolygonZM"
+fn parse_type_name(name: &str) -> Result<(i32, bool, bool), RostGisError> {
+    let upper = name.trim().to_uppercase();
+
+    let (base, has_z, has_m) = if let Some(base) = upper.strip_suffix("ZM") {
+        (base, true, true)
+    } else if let Some(base) = upper.strip_suffix('Z') {
+        (base, true, false)
+    } else if let Some(base) = upper.strip_suffix('M') {
+        (base, false, true)
+    } else {
+        (upper.as_str(), false, false)
+    };
+
+    TYPE_NAMES
+        .iter()
+        .position(|type_name| type_name.to_uppercase() == base)
+        .map(|code| (code as i32, has_z, has_m))
+        .ok_or_else(|| RostGisError::new(&format!("Invalid geometry type modifier: {}", name)))
+}
+
+/// Type code of a geometry, matching the OGC/WKB numbering
+pub fn geometry_type_code(geom: &Geometry) -> i32 {
+    match geom {
+        Geometry::Zm(base, _) => geometry_type_code(base),
+        Geometry::Point(_, _) => 1,
+        Geometry::LineString(_, _) => 2,
+        Geometry::Polygon(_, _) => 3,
+        Geometry::MultiPoint(_, _) => 4,
+        Geometry::MultiLineString(_, _) => 5,
+        Geometry::MultiPolygon(_, _) => 6,
+        Geometry::GeometryCollection(_, _) => 7,
+    }
+}
+
+// ============================================================================
+// POSTGRESQL TYPMOD FUNCTIONS
+// ============================================================================
+
+/// Typmod input function: parses `geometry(Point, 4326)` style modifiers
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn geometry_typmod_in(
+    modifiers: pgrx::Array<&CStr>,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let mut parts = Vec::new();
+    for modifier in modifiers.iter() {
+        let modifier = modifier.ok_or("Type modifiers cannot be NULL")?;
+        parts.push(modifier.to_str()?);
+    }
+
+    Ok(GeometryTypmod::parse(&parts)?.encode())
+}
+
+/// Typmod output function: renders the modifier for `\d` and pg_dump
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn geometry_typmod_out(typmod: i32) -> CString {
+    let modifier = GeometryTypmod::decode(typmod)
+        .map(|t| t.to_modifier_string())
+        .unwrap_or_default();
+    CString::new(modifier).expect("typmod string contains no NUL bytes")
+}
+
+/// Length-coercion cast that enforces the column typmod on insert/update
+///
+/// It works on the raw datum so that Compact columns get the compact body
+/// whatever rostgis.storage_encoding says, and so that the known-valid flag
+/// survives the cast. Its SQL signature is declared below.
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+pub fn geometry_enforce_typmod(
+    geom: &[u8],
+    typmod: i32,
+    _is_explicit: bool,
+) -> Result<pg_sys::Datum, Box<dyn std::error::Error + Send + Sync>> {
+    let header = GeometryHeader::peek(geom)?;
+    let decoded = serialization::deserialize(geom)?;
+    let typmod = GeometryTypmod::decode(typmod);
+    if let Some(typmod) = typmod {
+        typmod.check(&decoded)?;
+    }
+    let encoding = match typmod {
+        Some(typmod) if typmod.compact => StorageEncoding::Compact,
+        _ => guc::STORAGE_ENCODING.get(),
+    };
+    Ok(serialization::into_datum(&serialization::serialize_with(
+        &decoded,
+        encoding,
+        header.is_known_valid(),
+    )))
+}
+
+extension_sql!(
+    r#"
+ALTER TYPE @extschema@.geometry SET (
+    TYPMOD_IN = @extschema@.geometry_typmod_in,
+    TYPMOD_OUT = @extschema@.geometry_typmod_out
+);
+
+CREATE FUNCTION @extschema@.geometry(@extschema@.geometry, integer, boolean) RETURNS @extschema@.geometry
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'geometry_enforce_typmod_wrapper';
+
+CREATE CAST (@extschema@.geometry AS @extschema@.geometry)
+    WITH FUNCTION @extschema@.geometry(@extschema@.geometry, integer, boolean) AS IMPLICIT;
+"#,
+    name = "geometry_typmod",
+    requires = [Geometry, geometry_typmod_in, geometry_typmod_out],
+);
+
+/// Typmod input function for geography: parses `geography(Point, 4326)`
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn geography_typmod_in(
+    modifiers: pgrx::Array<&CStr>,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let mut parts = Vec::new();
+    for modifier in modifiers.iter() {
+        let modifier = modifier.ok_or("Type modifiers cannot be NULL")?;
+        parts.push(modifier.to_str()?);
+    }
+
+    Ok(GeometryTypmod::parse_geography(&parts)?.encode())
+}
+
+/// Length-coercion cast that enforces a geography column typmod
+#[pg_extern(immutable, strict, parallel_safe, name = "geography")]
+pub fn geography_enforce_typmod(
+    geog: Geography,
+    typmod: i32,
+    _is_explicit: bool,
+) -> Result<Geography, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(typmod) = GeometryTypmod::decode(typmod) {
+        typmod.check(&geog.geometry)?;
+    }
+    Ok(geog)
+}
+
+/// Geometry type name encoded in a typmod ("Geometry" when unconstrained)
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn postgis_typmod_type(typmod: i32) -> String {
+    GeometryTypmod::decode(typmod)
+        .unwrap_or_default()
+        .type_name()
+}
+
+/// SRID encoded in a typmod (0 when unconstrained)
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn postgis_typmod_srid(typmod: i32) -> i32 {
+    GeometryTypmod::decode(typmod).unwrap_or_default().srid
+}
+
+/// Coordinate dimension encoded in a typmod (2 when unconstrained)
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn postgis_typmod_dims(typmod: i32) -> i32 {
+    GeometryTypmod::decode(typmod)
+        .unwrap_or_default()
+        .dimensions()
+}
+
+// The output function is shared with geometry since the encoding is the same.
+// geography_columns lists geography columns the way PostGIS does, for client
+// tools that discover layers through it.
+extension_sql!(
+    r#"
+ALTER TYPE @extschema@.geography SET (
+    TYPMOD_IN = @extschema@.geography_typmod_in,
+    TYPMOD_OUT = @extschema@.geometry_typmod_out
+);
+
+CREATE CAST (@extschema@.geography AS @extschema@.geography)
+    WITH FUNCTION @extschema@.geography(@extschema@.geography, integer, boolean) AS IMPLICIT;
+
+CREATE VIEW @extschema@.geography_columns AS
+SELECT
+    current_database()::varchar(256) AS f_table_catalog,
+    n.nspname::varchar(256) AS f_table_schema,
+    c.relname::varchar(256) AS f_table_name,
+    a.attname::varchar(256) AS f_geography_column,
+    @extschema@.postgis_typmod_dims(a.atttypmod) AS coord_dimension,
+    @extschema@.postgis_typmod_srid(a.atttypmod) AS srid,
+    @extschema@.postgis_typmod_type(a.atttypmod) AS type
+FROM pg_class c
+JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped
+JOIN pg_namespace n ON n.oid = c.relnamespace
+WHERE a.atttypid = '@extschema@.geography'::regtype
+  AND c.relkind IN ('r', 'v', 'm', 'f', 'p')
+  AND NOT pg_is_other_temp_schema(c.relnamespace)
+  AND has_table_privilege(c.oid, 'SELECT');
+"#,
+    name = "geography_typmod",
+    requires = [
+        Geography,
+        geometry_typmod_out,
+        geography_typmod_in,
+        geography_enforce_typmod,
+        postgis_typmod_type,
+        postgis_typmod_srid,
+        postgis_typmod_dims
+    ],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    #[test]
+    fn test_typmod_roundtrip() {
+        let typmod = GeometryTypmod::parse(&["Point", "4326"]).unwrap();
+        assert_eq!(typmod.type_code, 1);
+        assert_eq!(typmod.srid, 4326);
+
+        let decoded = GeometryTypmod::decode(typmod.encode()).unwrap();
+        assert_eq!(decoded, typmod);
+        assert_eq!(decoded.to_modifier_string(), "(Point,4326)");
+
+        let zm = GeometryTypmod::parse(&["polygonzm"]).unwrap();
+        assert!(zm.has_z && zm.has_m);
+        assert_eq!(zm.to_modifier_string(), "(PolygonZM)");
+
+        let compact = GeometryTypmod::parse(&["LineString", "4326", "Compact"]).unwrap();
+        assert!(compact.compact);
+        assert_eq!(compact.srid, 4326);
+        let decoded = GeometryTypmod::decode(compact.encode()).unwrap();
+        assert_eq!(decoded, compact);
+        assert_eq!(decoded.to_modifier_string(), "(LineString,4326,Compact)");
+        let any_compact = GeometryTypmod::parse(&["geometry", "compact"]).unwrap();
+        assert_eq!(any_compact.to_modifier_string(), "(Geometry,Compact)");
+        assert_eq!(any_compact.srid, 0);
+    }
+
+    #[test]
+    fn test_typmod_parse_errors() {
+        assert!(GeometryTypmod::parse(&["Circle"]).is_err());
+        assert!(GeometryTypmod::parse(&["Point", "abc"]).is_err());
+        assert!(GeometryTypmod::parse(&["Point", "-1"]).is_err());
+        assert!(GeometryTypmod::parse(&[]).is_err());
+        assert!(GeometryTypmod::parse(&["Compact"]).is_err());
+        assert!(GeometryTypmod::parse(&["Point", "Compact", "4326"]).is_err());
+    }
+
+    #[test]
+    fn test_typmod_enforcement() {
+        let typmod = GeometryTypmod::parse(&["Point", "4326"]).unwrap();
+
+        assert!(typmod.check(&make_point(1.0, 2.0).with_srid(4326)).is_ok());
+        assert!(typmod.check(&make_point(1.0, 2.0)).is_err());
+
+        let line = geometry_from_wkt("LINESTRING(0 0, 1 1)")
+            .unwrap()
+            .with_srid(4326);
+        assert!(typmod.check(&line).is_err());
+
+        let any_geometry = GeometryTypmod::parse(&["Geometry"]).unwrap();
+        assert!(any_geometry.check(&line).is_ok());
+        assert_eq!(GeometryTypmod::decode(-1), None);
+    }
+
+    #[test]
+    fn test_geography_typmod() {
+        let typmod = GeometryTypmod::parse_geography(&["Point"]).unwrap();
+        assert_eq!(typmod.srid, 4326);
+        assert_eq!(typmod.to_modifier_string(), "(Point,4326)");
+        assert!(GeometryTypmod::parse_geography(&["Point", "3857"]).is_err());
+        assert!(GeometryTypmod::parse_geography(&["Point", "Compact"]).is_err());
+
+        let decoded = GeometryTypmod::decode(typmod.encode()).unwrap();
+        assert_eq!(decoded.type_name(), "Point");
+        assert_eq!(decoded.dimensions(), 2);
+        assert_eq!(postgis_typmod_type(-1), "Geometry");
+        assert_eq!(postgis_typmod_srid(typmod.encode()), 4326);
+    }
+}
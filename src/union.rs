@@ -0,0 +1,516 @@
+use crate::geometry::Geometry;
+use crate::noding::node;
+use crate::overlay::{overlay, overlay_input, report_fallbacks};
+use crate::serialization;
+use crate::utils::{aggregate_context, RostGisError};
+use geo::{unary_union, BooleanOps, Intersects};
+use geo_types::{LineString, MultiLineString, MultiPoint, MultiPolygon, Point, Polygon};
+use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
+use std::collections::HashSet;
+
+// Cascaded union (ST_Union aggregate and array form, ST_UnaryUnion)
+//
+// Polygons are buffered and unioned a batch at a time. Batch results are
+// kept on levels like the digits of a binary counter: a new result is
+// unioned with the one already on its level and carried to the next, so
+// every union works on inputs of similar size and the state never holds
+// more than one batch plus one geometry per level.
+//
+// Lines are noded at their crossings as by ST_Node, segments traced twice
+// kept once, and their parts covered by the polygons dropped. Points are
+// deduplicated, and those covered by the polygons or the lines dropped.
+//
+// The array form and the transition function take raw datums, so that
+// inputs flagged known-valid skip the validity check of overlay_input; their
+// SQL signatures are declared with the aggregate.
+
+/// Polygons buffered before they are unioned
+const CASCADE_BATCH: usize = 64;
+
+/// Running union of a set of geometries
+#[derive(Debug, Default, Clone)]
+pub struct UnionAccumulator {
+    srid: Option<i32>,
+    pending: Vec<Polygon<f64>>,
+    levels: Vec<Option<MultiPolygon<f64>>>,
+    points: Vec<Point<f64>>,
+    lines: Vec<LineString<f64>>,
+}
+
+impl UnionAccumulator {
+    /// Add a geometry; all inputs must share the same SRID
+    pub fn add(&mut self, geom: &Geometry) -> Result<(), RostGisError> {
+        match self.srid {
+            None => self.srid = Some(geom.srid()),
+            Some(srid) if srid != geom.srid() => {
+                return Err(RostGisError::new(&format!(
+                    "ST_Union: mixed SRIDs {} and {}",
+                    srid,
+                    geom.srid()
+                )))
+            }
+            Some(_) => {}
+        }
+        self.add_parts(geom);
+        if self.pending.len() >= CASCADE_BATCH {
+            self.cascade()?;
+        }
+        Ok(())
+    }
+
+    fn add_parts(&mut self, geom: &Geometry) {
+        match geom {
+            Geometry::Zm(base, _) => self.add_parts(base),
+            Geometry::Point(point, _) => {
+                if !geom.is_empty() {
+                    self.points.push(*point);
+                }
+            }
+            Geometry::MultiPoint(points, _) => self.points.extend(points.0.iter().copied()),
+            Geometry::LineString(line, _) => {
+                if !line.0.is_empty() {
+                    self.lines.push(line.clone());
+                }
+            }
+            Geometry::MultiLineString(lines, _) => self
+                .lines
+                .extend(lines.0.iter().filter(|l| !l.0.is_empty()).cloned()),
+            Geometry::Polygon(polygon, _) => {
+                if !polygon.exterior().0.is_empty() {
+                    self.pending.push(polygon.clone());
+                }
+            }
+            Geometry::MultiPolygon(polygons, _) => self.pending.extend(polygons.0.iter().cloned()),
+            Geometry::GeometryCollection(geometries, _) => {
+                for child in geometries {
+                    self.add_parts(child);
+                }
+            }
+        }
+    }
+
+    /// Union the pending polygons and carry the result up the levels
+    fn cascade(&mut self) -> Result<(), RostGisError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut carry = union_polygons(vec![MultiPolygon(std::mem::take(&mut self.pending))])?;
+        for level in self.levels.iter_mut() {
+            match level.take() {
+                Some(existing) => carry = union_polygons(vec![existing, carry])?,
+                None => {
+                    *level = Some(carry);
+                    return Ok(());
+                }
+            }
+        }
+        self.levels.push(Some(carry));
+        Ok(())
+    }
+
+    /// Merge another accumulator, as the combine step of a parallel
+    /// aggregate
+    pub fn merge(&mut self, other: UnionAccumulator) -> Result<(), RostGisError> {
+        match (self.srid, other.srid) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(RostGisError::new(&format!(
+                    "ST_Union: mixed SRIDs {} and {}",
+                    a, b
+                )))
+            }
+            (None, srid) => self.srid = srid,
+            _ => {}
+        }
+        self.pending.extend(other.pending);
+        self.pending
+            .extend(other.levels.into_iter().flatten().flat_map(|mp| mp.0));
+        self.points.extend(other.points);
+        self.lines.extend(other.lines);
+        if self.pending.len() >= CASCADE_BATCH {
+            self.cascade()?;
+        }
+        Ok(())
+    }
+
+    /// Union of everything added; None when nothing was added
+    pub fn finish(&self) -> Result<Option<Geometry>, RostGisError> {
+        let Some(srid) = self.srid else {
+            return Ok(None);
+        };
+        let polygons = union_polygons(
+            std::iter::once(MultiPolygon(self.pending.clone()))
+                .chain(self.levels.iter().flatten().cloned())
+                .collect(),
+        )?;
+
+        let lines = self.noded_lines(&polygons, srid)?;
+
+        // Points are compared by bit pattern, with -0 taken as 0
+        let mut seen = HashSet::new();
+        let points: Vec<Point<f64>> = self
+            .points
+            .iter()
+            .filter(|point| seen.insert(((point.x() + 0.0).to_bits(), (point.y() + 0.0).to_bits())))
+            .filter(|point| !polygons.intersects(*point) && !lines.intersects(*point))
+            .copied()
+            .collect();
+
+        let mut parts = Vec::new();
+        match polygons.0.len() {
+            0 => {}
+            1 => parts.push(Geometry::Polygon(polygons.0[0].clone(), srid)),
+            _ => parts.push(Geometry::MultiPolygon(polygons, srid)),
+        }
+        match lines.0.len() {
+            0 => {}
+            1 => parts.push(Geometry::LineString(lines.0[0].clone(), srid)),
+            _ => parts.push(Geometry::MultiLineString(lines, srid)),
+        }
+        match points.len() {
+            0 => {}
+            1 => parts.push(Geometry::Point(points[0], srid)),
+            _ => parts.push(Geometry::MultiPoint(MultiPoint(points), srid)),
+        }
+
+        Ok(Some(match parts.len() {
+            0 => Geometry::GeometryCollection(vec![], srid),
+            1 => parts.remove(0),
+            _ => Geometry::GeometryCollection(parts, srid),
+        }))
+    }
+}
+
+impl UnionAccumulator {
+    /// The lines added, noded and without their parts covered by `polygons`
+    fn noded_lines(
+        &self,
+        polygons: &MultiPolygon<f64>,
+        srid: i32,
+    ) -> Result<MultiLineString<f64>, RostGisError> {
+        if self.lines.is_empty() {
+            return Ok(MultiLineString(vec![]));
+        }
+        let lines = MultiLineString(self.lines.clone());
+        let Geometry::MultiLineString(noded, _) = node(&Geometry::MultiLineString(lines, srid))?
+        else {
+            unreachable!("noding gives a multilinestring")
+        };
+        if polygons.0.is_empty() {
+            return Ok(noded);
+        }
+        // Each piece is clipped on its own, so that clipping does not join
+        // pieces again at their nodes; pieces along a polygon boundary are
+        // covered too
+        let outside = noded
+            .0
+            .into_iter()
+            .flat_map(|piece| polygons.clip(&MultiLineString(vec![piece]), true).0)
+            .filter(|piece| {
+                piece.lines().any(|segment| {
+                    let middle = (segment.start + segment.end) / 2.0;
+                    !polygons.intersects(&middle)
+                })
+            });
+        Ok(MultiLineString(outside.collect()))
+    }
+}
+
+/// Union of sets of polygons, through the overlay fallback
+fn union_polygons(sets: Vec<MultiPolygon<f64>>) -> Result<MultiPolygon<f64>, RostGisError> {
+    overlay("ST_Union", &sets, |sets| {
+        unary_union(sets.iter().flat_map(|set| set.0.iter()))
+    })
+}
+
+/// Union a set of geometries; None for an empty set
+pub fn union_all<'a>(
+    geometries: impl IntoIterator<Item = &'a Geometry>,
+) -> Result<Option<Geometry>, RostGisError> {
+    let mut accumulator = UnionAccumulator::default();
+    for geom in geometries {
+        accumulator.add(geom)?;
+    }
+    accumulator.finish()
+}
+
+/// Union of the parts of a single geometry (ST_UnaryUnion)
+///
+/// Overlapping polygons are dissolved and lines are noded at their
+/// crossings, with segments traced twice kept once. An empty geometry is
+/// returned unchanged.
+pub fn unary_union_geometry(geom: &Geometry) -> Result<Geometry, RostGisError> {
+    if geom.is_empty() {
+        return Ok(geom.clone());
+    }
+    Ok(union_all(std::iter::once(geom))?.expect("a geometry was added"))
+}
+
+/// PostgreSQL function dissolving the overlaps between the parts of a
+/// geometry, e.g. to repair a multipolygon whose polygons overlap
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_unaryunion(geom: Geometry) -> Result<Geometry, Box<dyn std::error::Error + Send + Sync>> {
+    let union = unary_union_geometry(&geom);
+    report_fallbacks();
+    Ok(union?)
+}
+
+/// PostgreSQL function for the union of an array of geometries, e.g.
+/// `ST_Union(ARRAY[a, b])` or `ST_Union(array_agg(geom))`
+#[pg_extern(immutable, strict, parallel_safe, sql = false)]
+fn st_union_array(
+    geometries: Array<'_, &[u8]>,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut accumulator = UnionAccumulator::default();
+    let union = geometries
+        .iter()
+        .flatten()
+        .try_for_each(|datum| accumulator.add(&overlay_input(datum)?))
+        .and_then(|_| accumulator.finish());
+    report_fallbacks();
+    Ok(union?)
+}
+
+/// Store an accumulator in the aggregate memory context
+unsafe fn store_state(
+    fcinfo: pg_sys::FunctionCallInfo,
+    state: UnionAccumulator,
+) -> Result<Internal, RostGisError> {
+    let context = aggregate_context(fcinfo, "ST_Union")?;
+    Ok(PgMemoryContexts::For(context).switch_to(|_| Internal::new(state)))
+}
+
+#[pg_extern(immutable, parallel_safe, sql = false)]
+fn st_union_transfn(
+    mut state: Internal,
+    geom: Option<&[u8]>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    unsafe {
+        if state.get::<UnionAccumulator>().is_none() {
+            state = store_state(fcinfo, UnionAccumulator::default())?;
+        }
+        if let Some(datum) = geom {
+            state
+                .get_mut::<UnionAccumulator>()
+                .expect("ST_Union state")
+                .add(&overlay_input(datum)?)?;
+        }
+    }
+    Ok(state)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_union_combinefn(
+    state1: Internal,
+    state2: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    unsafe {
+        let other = match state2.get::<UnionAccumulator>() {
+            Some(other) => other.clone(),
+            None => return Ok(state1),
+        };
+        match state1.get_mut::<UnionAccumulator>() {
+            Some(accumulator) => {
+                accumulator.merge(other)?;
+                Ok(state1)
+            }
+            None => Ok(store_state(fcinfo, other)?),
+        }
+    }
+}
+
+/// Serialize a partial union for transfer between parallel workers
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_union_serialfn(state: Internal) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let geometry = match unsafe { state.get::<UnionAccumulator>() } {
+        Some(accumulator) => accumulator.finish(),
+        None => Ok(None),
+    };
+    report_fallbacks();
+    Ok(match geometry? {
+        Some(geometry) => serialization::serialize(&geometry),
+        None => Vec::new(),
+    })
+}
+
+#[pg_extern(immutable, strict, parallel_safe)]
+fn st_union_deserialfn(
+    bytes: &[u8],
+    _internal: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Result<Internal, Box<dyn std::error::Error + Send + Sync>> {
+    let mut accumulator = UnionAccumulator::default();
+    if !bytes.is_empty() {
+        accumulator.add(&serialization::deserialize(bytes)?)?;
+    }
+    Ok(unsafe { store_state(fcinfo, accumulator)? })
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn st_union_finalfn(
+    state: Internal,
+) -> Result<Option<Geometry>, Box<dyn std::error::Error + Send + Sync>> {
+    let union = match unsafe { state.get::<UnionAccumulator>() } {
+        Some(accumulator) => accumulator.finish(),
+        None => Ok(None),
+    };
+    report_fallbacks();
+    Ok(union?)
+}
+
+extension_sql!(
+    r#"
+CREATE FUNCTION @extschema@.st_union(@extschema@.geometry[]) RETURNS @extschema@.geometry
+    IMMUTABLE STRICT PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_union_array_wrapper';
+CREATE FUNCTION @extschema@.st_union_transfn(internal, @extschema@.geometry) RETURNS internal
+    IMMUTABLE PARALLEL SAFE LANGUAGE c AS 'MODULE_PATHNAME', 'st_union_transfn_wrapper';
+CREATE AGGREGATE @extschema@.st_union(@extschema@.geometry) (
+    SFUNC = @extschema@.st_union_transfn,
+    STYPE = internal,
+    FINALFUNC = @extschema@.st_union_finalfn,
+    COMBINEFUNC = @extschema@.st_union_combinefn,
+    SERIALFUNC = @extschema@.st_union_serialfn,
+    DESERIALFUNC = @extschema@.st_union_deserialfn,
+    PARALLEL = SAFE
+);
+"#,
+    name = "union_aggregate",
+    requires = [
+        Geometry,
+        st_union_combinefn,
+        st_union_serialfn,
+        st_union_deserialfn,
+        st_union_finalfn
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+    use geo::Area;
+
+    fn square(x: f64, y: f64) -> Geometry {
+        geometry_from_wkt(&format!(
+            "POLYGON(({x} {y}, {} {y}, {} {}, {x} {}, {x} {y}))",
+            x + 1.0,
+            x + 1.0,
+            y + 1.0,
+            y + 1.0
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unary_union() {
+        let overlapping = geometry_from_wkt(
+            "MULTIPOLYGON(((0 0, 2 0, 2 2, 0 2, 0 0)), ((1 1, 3 1, 3 3, 1 3, 1 1)), \
+             ((5 5, 6 5, 6 6, 5 6, 5 5)))",
+        )
+        .unwrap()
+        .with_srid(3857);
+        let union = unary_union_geometry(&overlapping).unwrap();
+        assert_eq!(union.geometry_type(), "ST_MultiPolygon");
+        assert_eq!(union.srid(), 3857);
+        assert!((union.to_geo().unsigned_area() - 8.0).abs() < 1e-9);
+
+        // Lines are noded at their crossings, overlaps kept once
+        let lines = geometry_from_wkt(
+            "GEOMETRYCOLLECTION(LINESTRING(0 0, 2 2), LINESTRING(0 2, 2 0), LINESTRING(2 2, 0 0))",
+        )
+        .unwrap();
+        assert_eq!(
+            unary_union_geometry(&lines).unwrap().to_wkt(),
+            "MULTILINESTRING((0 0,1 1),(1 1,2 2),(0 2,1 1),(1 1,2 0))"
+        );
+        assert_eq!(
+            unary_union_geometry(&geometry_from_wkt("LINESTRING(0 0, 1 1)").unwrap())
+                .unwrap()
+                .to_wkt(),
+            "LINESTRING(0 0,1 1)"
+        );
+        let empty = geometry_from_wkt("MULTIPOLYGON EMPTY").unwrap();
+        assert_eq!(
+            unary_union_geometry(&empty).unwrap().to_wkt(),
+            "MULTIPOLYGON EMPTY"
+        );
+    }
+
+    #[test]
+    fn test_cascaded_union() {
+        // A 20x20 grid of unit squares, more than a few cascade batches
+        let mut accumulator = UnionAccumulator::default();
+        for i in 0..20 {
+            for j in 0..20 {
+                accumulator.add(&square(i as f64, j as f64)).unwrap();
+            }
+        }
+        assert!(accumulator.pending.len() < CASCADE_BATCH);
+        assert!(accumulator.levels.iter().flatten().count() > 0);
+
+        let union = accumulator.finish().unwrap().unwrap();
+        assert_eq!(union.geometry_type(), "ST_Polygon");
+        assert!((union.to_geo().unsigned_area() - 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_merge_partial_unions() {
+        let mut left = UnionAccumulator::default();
+        let mut right = UnionAccumulator::default();
+        left.add(&square(0.0, 0.0)).unwrap();
+        right.add(&square(1.0, 0.0)).unwrap();
+        right.add(&square(5.0, 5.0)).unwrap();
+        left.merge(right).unwrap();
+
+        let union = left.finish().unwrap().unwrap();
+        assert_eq!(union.geometry_type(), "ST_MultiPolygon");
+        assert!((union.to_geo().unsigned_area() - 3.0).abs() < 1e-9);
+
+        let mut other_srid = UnionAccumulator::default();
+        other_srid.add(&square(0.0, 0.0).with_srid(4326)).unwrap();
+        assert!(left.merge(other_srid).is_err());
+    }
+
+    #[test]
+    fn test_mixed_dimensions() {
+        let geometries = vec![
+            square(0.0, 0.0),
+            make_point(0.5, 0.5),
+            make_point(3.0, 3.0),
+            make_point(3.0, 3.0),
+            geometry_from_wkt("LINESTRING(5 5, 6 6)").unwrap(),
+        ];
+        let union = union_all(&geometries).unwrap().unwrap();
+        match union {
+            Geometry::GeometryCollection(parts, _) => {
+                let types: Vec<&str> = parts.iter().map(|p| p.geometry_type()).collect();
+                assert_eq!(types, vec!["ST_Polygon", "ST_LineString", "ST_Point"]);
+            }
+            other => panic!("expected a collection, got {:?}", other),
+        }
+
+        assert!(union_all(&[]).unwrap().is_none());
+
+        // Crossing lines are noded; lines and points inside the polygons, and
+        // points on the lines, are covered
+        let geometries = vec![
+            square(0.0, 0.0),
+            geometry_from_wkt("LINESTRING(-1 0.5, 0.5 0.5)").unwrap(),
+            geometry_from_wkt("LINESTRING(0 1, 1 1)").unwrap(),
+            geometry_from_wkt("LINESTRING(3 3, 5 5)").unwrap(),
+            geometry_from_wkt("LINESTRING(3 5, 5 3)").unwrap(),
+            make_point(4.5, 4.5),
+            make_point(-0.0, 7.0),
+            make_point(0.0, 7.0),
+        ];
+        assert_eq!(
+            union_all(&geometries).unwrap().unwrap().to_wkt(),
+            "GEOMETRYCOLLECTION(POLYGON((0 0,1 0,1 1,0 1,0 0)),\
+             MULTILINESTRING((-1 0.5,0 0.5),(3 3,4 4),(4 4,5 5),(3 5,4 4),(4 4,5 3)),POINT(0 7))"
+        );
+        let mixed = vec![make_point(0.0, 0.0), make_point(1.0, 1.0).with_srid(3857)];
+        assert!(union_all(&mixed).is_err());
+    }
+}
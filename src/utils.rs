@@ -1,4 +1,5 @@
 /// Utility functions for RostGIS extension
+use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
 
@@ -61,6 +62,112 @@ pub fn validate_srid(srid: i32) -> Result<i32, RostGisError> {
     }
 }
 
+/// Write a coordinate or other number for text output (WKT, GeoJSON, GML
+/// and the box types)
+///
+/// Without a precision, the shortest decimal that parses back to the same
+/// value; with one, rounded to at most that many decimal places. Either way
+/// without an exponent, trailing zeros or a negative zero, and with a point
+/// as the decimal separator whatever the locale.
+pub fn format_number(value: f64, precision: Option<u32>) -> String {
+    let shortest = format!("{}", value);
+    let text = match precision {
+        Some(digits)
+            if shortest
+                .split_once('.')
+                .is_some_and(|(_, decimals)| decimals.len() > digits as usize) =>
+        {
+            let rounded = format!("{:.*}", digits as usize, value);
+            if rounded.contains('.') {
+                rounded
+                    .trim_end_matches('0')
+                    .trim_end_matches('.')
+                    .to_string()
+            } else {
+                rounded
+            }
+        }
+        _ => shortest,
+    };
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text
+    }
+}
+
+/// Memory context of the aggregate calling a transition or combine function
+///
+/// Aggregate states must be allocated there, the current memory context
+/// being reset between rows.
+///
+/// # Safety
+/// `fcinfo` must be the call info PostgreSQL passed to the current function.
+pub unsafe fn aggregate_context(
+    fcinfo: pgrx::pg_sys::FunctionCallInfo,
+    function: &str,
+) -> Result<pgrx::pg_sys::MemoryContext, RostGisError> {
+    let mut context: pgrx::pg_sys::MemoryContext = std::ptr::null_mut();
+    if pgrx::pg_sys::AggCheckCallContext(fcinfo, &mut context) == 0 {
+        return Err(RostGisError::new(&format!(
+            "{} called outside of an aggregate",
+            function
+        )));
+    }
+    Ok(context)
+}
+
+/// Quoted name of the schema the extension is installed in
+///
+/// SQL run through SPI qualifies the extension's functions, operators, types
+/// and tables with it, so it works whatever the caller's search_path when
+/// RostGIS lives in a schema of its own (CREATE EXTENSION rostgis SCHEMA gis).
+///
+/// The extension is not relocatable, so the schema is looked up once per
+/// backend.
+pub fn extension_schema() -> Result<String, pgrx::spi::Error> {
+    thread_local! {
+        static SCHEMA: RefCell<Option<String>> = const { RefCell::new(None) };
+    }
+    if let Some(schema) = SCHEMA.with(|schema| schema.borrow().clone()) {
+        return Ok(schema);
+    }
+    let schema = pgrx::spi::Spi::get_one::<String>(
+        "SELECT pg_catalog.quote_ident(n.nspname)
+         FROM pg_catalog.pg_extension e
+         JOIN pg_catalog.pg_namespace n ON n.oid = e.extnamespace
+         WHERE e.extname = 'rostgis'",
+    )?;
+    match schema {
+        // Not cached while the extension is not installed, as it may be next
+        None => Ok("public".to_string()),
+        Some(schema) => {
+            SCHEMA.with(|cached| *cached.borrow_mut() = Some(schema.clone()));
+            Ok(schema)
+        }
+    }
+}
+
+/// Quote an identifier for SQL as quote_ident does: as it is when plain
+/// lower case and not a keyword, else in double quotes
+pub fn quote_ident(name: &str) -> Result<String, pgrx::spi::Error> {
+    Ok(pgrx::spi::Spi::get_one_with_args::<String>(
+        "SELECT pg_catalog.quote_ident($1)",
+        &[name.into()],
+    )?
+    .unwrap_or_default())
+}
+
+/// Name of a table quoted for SQL, qualified with its schema unless that is
+/// on the search_path; an error if there is no such table
+pub fn quote_table(table_name: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(pgrx::spi::Spi::get_one_with_args::<String>(
+        "SELECT $1::regclass::text",
+        &[table_name.into()],
+    )?
+    .ok_or("Table not found")?)
+}
+
 /// Common SRID constants
 pub mod srid {
     pub const UNKNOWN: i32 = 0;
@@ -91,6 +198,21 @@ mod tests {
         assert!(validate_srid(-2).is_err());
     }
 
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(1.0, None), "1");
+        assert_eq!(format_number(0.1 + 0.2, None), "0.30000000000000004");
+        assert_eq!(format_number(1e-7, None), "0.0000001");
+        assert_eq!(format_number(-0.0, None), "0");
+        assert_eq!(format_number(0.1 + 0.2, Some(15)), "0.3");
+        assert_eq!(format_number(12345.678, Some(15)), "12345.678");
+        assert_eq!(format_number(1.23456, Some(2)), "1.23");
+        assert_eq!(format_number(1.999, Some(2)), "2");
+        assert_eq!(format_number(2.5, Some(0)), "2");
+        assert_eq!(format_number(-0.0004, Some(3)), "0");
+        assert_eq!(format_number(-12.5, Some(3)), "-12.5");
+    }
+
     #[test]
     fn test_hex_with_prefix() {
         let hex = "0xdeadbeef";
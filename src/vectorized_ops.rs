@@ -1,5 +1,11 @@
+use crate::functions::{azimuth, distance_within, normalize_degrees};
 use crate::geometry::Geometry;
+use crate::spatial_index::{index_join, BBox, GeometryWithId, IndexPredicate, SpatialIndex};
+use crate::utils::RostGisError;
+use geo::{BooleanOps, Contains, Relate};
+use geo_types::{MultiLineString, MultiPolygon, Point};
 use pgrx::prelude::*;
+use pgrx::PgMemoryContexts;
 
 /// Vectorized geometry operations for bulk processing
 /// This provides significant performance improvements for large datasets
@@ -28,34 +34,93 @@ impl VectorizedOps {
         coordinates
     }
 
+    /// Distance between two points; 0 for other geometry types
+    pub fn point_distance(p1: &Geometry, p2: &Geometry) -> f64 {
+        match (p1, p2) {
+            (Geometry::Point(pt1, _), Geometry::Point(pt2, _)) => {
+                let dx = pt1.x() - pt2.x();
+                let dy = pt1.y() - pt2.y();
+                (dx * dx + dy * dy).sqrt()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Area of a polygon or multipolygon; 0 for other geometry types
+    pub fn polygon_area(geom: &Geometry) -> f64 {
+        use geo::Area;
+
+        match geom {
+            Geometry::Polygon(poly, _) => poly.unsigned_area(),
+            Geometry::MultiPolygon(multipoly, _) => multipoly.unsigned_area(),
+            _ => 0.0,
+        }
+    }
+
+    /// Azimuth between two points in degrees clockwise from north, in
+    /// [0, 360); None for coincident points or other geometry types
+    pub fn point_azimuth_deg(p1: &Geometry, p2: &Geometry) -> Option<f64> {
+        azimuth(p1, p2)
+            .ok()
+            .flatten()
+            .map(|a| normalize_degrees(a.to_degrees()))
+    }
+
+    /// Signed change of heading in degrees, in [-180, 180), positive when
+    /// turning clockwise
+    pub fn turn_angle_deg(heading1: f64, heading2: f64) -> f64 {
+        normalize_degrees(heading2 - heading1 + 180.0) - 180.0
+    }
+
+    /// Headings of the segments of a track of points
+    pub fn track_azimuths(track: &[Geometry]) -> Vec<Option<f64>> {
+        track
+            .windows(2)
+            .map(|pair| Self::point_azimuth_deg(&pair[0], &pair[1]))
+            .collect()
+    }
+
     /// Bulk distance calculation using vectorized operations
     pub fn bulk_distance_calculation(points1: Vec<Geometry>, points2: Vec<Geometry>) -> Vec<f64> {
         points1
-            .into_iter()
-            .zip(points2.into_iter())
-            .map(|(p1, p2)| match (p1, p2) {
-                (Geometry::Point(pt1, _), Geometry::Point(pt2, _)) => {
-                    let dx = pt1.x() - pt2.x();
-                    let dy = pt1.y() - pt2.y();
-                    (dx * dx + dy * dy).sqrt()
-                }
-                _ => 0.0,
-            })
+            .iter()
+            .zip(points2.iter())
+            .map(|(p1, p2)| Self::point_distance(p1, p2))
             .collect()
     }
 
+    /// Distances between every pair of geometries, row by row: entry
+    /// `i * n + j` is the distance between the i-th and j-th of the n
+    /// geometries. Pairs of points use the point kernel, other geometries
+    /// the ST_Distance algorithm; a NULL or empty geometry gives None.
+    pub fn distance_matrix(geometries: &[Option<Geometry>]) -> Vec<Option<f64>> {
+        let n = geometries.len();
+        let mut matrix = vec![None; n * n];
+        for i in 0..n {
+            let Some(g1) = geometries[i].as_ref().filter(|g| !g.is_empty()) else {
+                continue;
+            };
+            matrix[i * n + i] = Some(0.0);
+            for j in i + 1..n {
+                let Some(g2) = geometries[j].as_ref().filter(|g| !g.is_empty()) else {
+                    continue;
+                };
+                let distance = match (g1, g2) {
+                    (Geometry::Point(..), Geometry::Point(..)) => {
+                        Some(Self::point_distance(g1, g2))
+                    }
+                    _ => distance_within(g1, g2, f64::INFINITY),
+                };
+                matrix[i * n + j] = distance;
+                matrix[j * n + i] = distance;
+            }
+        }
+        matrix
+    }
+
     /// Bulk area calculation for polygons using vectorized operations
     pub fn bulk_area_calculation(polygons: Vec<Geometry>) -> Vec<f64> {
-        use geo::Area;
-
-        polygons
-            .into_iter()
-            .map(|geom| match geom {
-                Geometry::Polygon(poly, _) => poly.unsigned_area(),
-                Geometry::MultiPolygon(multipoly, _) => multipoly.unsigned_area(),
-                _ => 0.0,
-            })
-            .collect()
+        polygons.iter().map(Self::polygon_area).collect()
     }
 
     /// Bulk bounding box calculation
@@ -91,65 +156,375 @@ pub enum SpatialPredicate {
     Within,
 }
 
+/// Running totals behind bulk_geometry_stats
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GeometryStats {
+    pub count: usize,
+    pub total_area: f64,
+}
+
+impl GeometryStats {
+    pub fn add(&mut self, geom: &Geometry) {
+        self.count += 1;
+        self.total_area += VectorizedOps::polygon_area(geom);
+    }
+
+    pub fn average_area(&self) -> f64 {
+        if self.count > 0 {
+            self.total_area / self.count as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Human-readable report including the throughput for a processing time
+    pub fn report(&self, elapsed: std::time::Duration) -> String {
+        format!(
+            "Processed {} geometries in {:?}\nTotal area: {:.2}\nAverage area: {:.2}\nThroughput: {:.0} geom/sec",
+            self.count,
+            elapsed,
+            self.total_area,
+            self.average_area(),
+            self.count as f64 / elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Barrier polygons, e.g. rivers, motorways or protected areas, indexed for
+/// counting how often routes cross them
+///
+/// A line crosses a barrier when it runs through its interior and also
+/// outside it, as in ST_Crosses. Every piece of the line inside the barrier
+/// is one crossing, so a route passing through a lake twice crosses it
+/// twice. A line ending inside a barrier crosses it once; lines along its
+/// edge or entirely inside it do not cross it. The parts of a multi-line
+/// are counted one by one.
+pub struct BarrierSet {
+    index: SpatialIndex,
+    polygons: Vec<MultiPolygon<f64>>,
+}
+
+impl BarrierSet {
+    /// Index the barriers; they must be polygons or multipolygons, and
+    /// empty ones are ignored
+    pub fn new(barriers: impl IntoIterator<Item = Geometry>) -> Result<Self, RostGisError> {
+        let mut polygons = Vec::new();
+        let mut indexed = Vec::new();
+        for barrier in barriers {
+            let polygon = match &barrier {
+                _ if barrier.is_empty() => continue,
+                Geometry::Polygon(polygon, _) => MultiPolygon(vec![polygon.clone()]),
+                Geometry::MultiPolygon(polygons, _) => polygons.clone(),
+                other => {
+                    return Err(RostGisError::new(&format!(
+                        "barriers must be polygons, got {}",
+                        other.geometry_type()
+                    )))
+                }
+            };
+            indexed.push(GeometryWithId::new(polygons.len() as i64, barrier));
+            polygons.push(polygon);
+        }
+        Ok(Self {
+            index: SpatialIndex::from_geometries(indexed),
+            polygons,
+        })
+    }
+
+    /// Number of times a line or multi-line crosses the barriers
+    pub fn crossings(&self, line: &Geometry) -> Result<i32, RostGisError> {
+        let parts = match line {
+            Geometry::LineString(line, _) => vec![line.clone()],
+            Geometry::MultiLineString(lines, _) => lines.0.clone(),
+            other => {
+                return Err(RostGisError::new(&format!(
+                    "barrier crossings require lines, got {}",
+                    other.geometry_type()
+                )))
+            }
+        };
+        let mut count = 0;
+        for candidate in self.index.query_predicate(IndexPredicate::Intersects(line)) {
+            let barrier = &self.polygons[candidate.id as usize];
+            for part in &parts {
+                if !part.relate(barrier).is_crosses() {
+                    continue;
+                }
+                // Pieces inside the barrier, leaving out those along its edge
+                count += barrier
+                    .clip(&MultiLineString(vec![part.clone()]), false)
+                    .iter()
+                    .filter(|piece| {
+                        piece.lines().any(|segment| {
+                            let middle = (segment.start + segment.end) / 2.0;
+                            barrier.contains(&Point(middle))
+                        })
+                    })
+                    .count() as i32;
+            }
+        }
+        Ok(count)
+    }
+}
+
+// Array arguments are read element by element rather than converted to a
+// Vec<Geometry> up front. They are walked with an iterator, since looking an
+// element up by position scans the variable-length elements before it. Each
+// element is detoasted and decoded in a temporary memory context that is
+// reset every BATCH_SIZE elements, so the backend only holds one batch of
+// decoded input at a time instead of the whole array until the end of the
+// query.
+
+/// Number of array elements decoded between memory context resets
+pub const BATCH_SIZE: usize = 1024;
+
+/// Call `f` on every item, advancing the iterator in a temporary memory
+/// context that is reset after every batch, and collect the results
+fn map_batched<T, R>(items: impl IntoIterator<Item = T>, mut f: impl FnMut(T) -> R) -> Vec<R> {
+    let mut items = items.into_iter();
+    let mut results = Vec::with_capacity(items.size_hint().0);
+    let mut context = PgMemoryContexts::new("rostgis bulk batch");
+
+    let mut exhausted = false;
+    while !exhausted {
+        // SAFETY: results live in Rust memory and nothing allocated inside the
+        // context is kept past the reset
+        unsafe {
+            context.switch_to(|_| {
+                for _ in 0..BATCH_SIZE {
+                    match items.next() {
+                        Some(item) => results.push(f(item)),
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                    }
+                }
+            });
+            context.reset();
+        }
+    }
+
+    results
+}
+
 /// PostgreSQL function for bulk distance calculations
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_distances(points1: Vec<Geometry>, points2: Vec<Geometry>) -> Vec<f64> {
-    VectorizedOps::bulk_distance_calculation(points1, points2)
+pub fn bulk_distances(
+    points1: Array<'_, Geometry>,
+    points2: Array<'_, Geometry>,
+) -> Vec<Option<f64>> {
+    map_batched(points1.iter().zip(points2.iter()), |pair| match pair {
+        (Some(p1), Some(p2)) => Some(VectorizedOps::point_distance(&p1, &p2)),
+        _ => None,
+    })
+}
+
+/// Largest array rostgis_distance_matrix accepts, whose matrix holds 16M
+/// distances
+pub const MAX_MATRIX_GEOMETRIES: usize = 4096;
+
+/// PostgreSQL function returning the distances between every pair of an
+/// array of geometries, flattened row by row: the distance between elements
+/// i and j (from 1) is at index (i - 1) * n + j. NULL or empty elements give
+/// NULL distances.
+#[pg_extern(immutable, parallel_safe)]
+pub fn rostgis_distance_matrix(
+    geometries: Array<'_, Geometry>,
+) -> Result<Vec<Option<f64>>, Box<dyn std::error::Error + Send + Sync>> {
+    if geometries.len() > MAX_MATRIX_GEOMETRIES {
+        return Err(RostGisError::new(&format!(
+            "rostgis_distance_matrix is limited to {} geometries, got {}",
+            MAX_MATRIX_GEOMETRIES,
+            geometries.len()
+        ))
+        .into());
+    }
+    let geometries = map_batched(geometries.iter(), |geom| geom);
+    Ok(VectorizedOps::distance_matrix(&geometries))
 }
 
 /// PostgreSQL function for bulk area calculations
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_areas(polygons: Vec<Geometry>) -> Vec<f64> {
-    VectorizedOps::bulk_area_calculation(polygons)
+pub fn bulk_areas(polygons: Array<'_, Geometry>) -> Vec<Option<f64>> {
+    map_batched(polygons.iter(), |geom| {
+        geom.map(|geom| VectorizedOps::polygon_area(&geom))
+    })
 }
 
 /// PostgreSQL function for bulk bounding box calculations
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_bboxes(geometries: Vec<Geometry>) -> Vec<String> {
-    VectorizedOps::bulk_bounding_boxes(geometries)
-        .into_iter()
-        .map(|(min_x, min_y, max_x, max_y)| format!("BOX({} {},{} {})", min_x, min_y, max_x, max_y))
-        .collect()
+pub fn bulk_bboxes(geometries: Array<'_, Geometry>) -> Vec<Option<String>> {
+    map_batched(geometries.iter(), |geom| {
+        geom.map(|geom| {
+            let bbox = BBox::from_geometry(&geom);
+            format!(
+                "BOX({} {},{} {})",
+                bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y
+            )
+        })
+    })
+}
+
+/// Pairwise bounding box predicate over two arrays
+fn bulk_predicate(
+    geometries1: &Array<'_, Geometry>,
+    geometries2: &Array<'_, Geometry>,
+    predicate: SpatialPredicate,
+) -> Vec<Option<bool>> {
+    map_batched(
+        geometries1.iter().zip(geometries2.iter()),
+        |pair| match pair {
+            (Some(g1), Some(g2)) => Some(match predicate {
+                SpatialPredicate::Overlaps => g1.bbox_overlaps(&g2),
+                SpatialPredicate::Contains => g1.bbox_contains(&g2),
+                SpatialPredicate::Within => g1.bbox_within(&g2),
+            }),
+            _ => None,
+        },
+    )
 }
 
 /// PostgreSQL function for bulk spatial overlap testing
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_overlaps(geometries1: Vec<Geometry>, geometries2: Vec<Geometry>) -> Vec<bool> {
-    VectorizedOps::bulk_spatial_predicates(geometries1, geometries2, SpatialPredicate::Overlaps)
+pub fn bulk_overlaps(
+    geometries1: Array<'_, Geometry>,
+    geometries2: Array<'_, Geometry>,
+) -> Vec<Option<bool>> {
+    bulk_predicate(&geometries1, &geometries2, SpatialPredicate::Overlaps)
 }
 
 /// PostgreSQL function for bulk spatial contains testing
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_contains(geometries1: Vec<Geometry>, geometries2: Vec<Geometry>) -> Vec<bool> {
-    VectorizedOps::bulk_spatial_predicates(geometries1, geometries2, SpatialPredicate::Contains)
+pub fn bulk_contains(
+    geometries1: Array<'_, Geometry>,
+    geometries2: Array<'_, Geometry>,
+) -> Vec<Option<bool>> {
+    bulk_predicate(&geometries1, &geometries2, SpatialPredicate::Contains)
+}
+
+/// PostgreSQL function for bulk azimuths in degrees
+#[pg_extern(immutable, parallel_safe)]
+pub fn bulk_azimuths_deg(
+    points1: Array<'_, Geometry>,
+    points2: Array<'_, Geometry>,
+) -> Vec<Option<f64>> {
+    map_batched(points1.iter().zip(points2.iter()), |pair| match pair {
+        (Some(p1), Some(p2)) => VectorizedOps::point_azimuth_deg(&p1, &p2),
+        _ => None,
+    })
+}
+
+/// Heading in degrees of each segment of a track given as an ordered array
+/// of points, one element shorter than the track
+#[pg_extern(immutable, parallel_safe)]
+pub fn track_azimuths_deg(track: Array<'_, Geometry>) -> Vec<Option<f64>> {
+    let mut previous = None;
+    let mut headings = map_batched(track.iter(), |current| {
+        let heading = match (&previous, &current) {
+            (Some(p1), Some(p2)) => VectorizedOps::point_azimuth_deg(p1, p2),
+            _ => None,
+        };
+        previous = current;
+        heading
+    });
+    // The first point starts the track and has no heading
+    if !headings.is_empty() {
+        headings.remove(0);
+    }
+    headings
+}
+
+/// Turn in degrees at each interior point of a track, in [-180, 180) and
+/// positive clockwise
+#[pg_extern(immutable, parallel_safe)]
+pub fn track_turn_angles_deg(track: Array<'_, Geometry>) -> Vec<Option<f64>> {
+    let headings = track_azimuths_deg(track);
+    headings
+        .windows(2)
+        .map(|pair| match (pair[0], pair[1]) {
+            (Some(h1), Some(h2)) => Some(VectorizedOps::turn_angle_deg(h1, h2)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Number of times each line crosses a set of barrier polygons, e.g. the
+/// rivers and motorways a utility route would have to pass, with the
+/// barriers indexed once for the whole array. NULL lines give NULL counts.
+#[pg_extern(immutable, parallel_safe)]
+pub fn bulk_barrier_crossings(
+    lines: Array<'_, Geometry>,
+    barriers: Array<'_, Geometry>,
+) -> Result<Vec<Option<i32>>, Box<dyn std::error::Error + Send + Sync>> {
+    let barriers = BarrierSet::new(barriers.iter().flatten())?;
+    let counts = map_batched(lines.iter(), |line| {
+        line.map(|line| barriers.crossings(&line)).transpose()
+    });
+    Ok(counts.into_iter().collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Pairs (i, j) of elements of two arrays with `ST_<predicate>(left[i],
+/// right[j])`, as 0-based positions in (i, j) order
+///
+/// The larger array is bulk-loaded into the R*-tree and the smaller one
+/// queries it, with the converse predicate when the right array is the
+/// smaller one, so that each array is read once whatever their sizes.
+pub fn bulk_join_pairs(
+    left: &[Option<Geometry>],
+    right: &[Option<Geometry>],
+    predicate: &str,
+    distance: f64,
+) -> Result<Vec<(usize, usize)>, RostGisError> {
+    if left.len() >= right.len() {
+        let converse = match predicate.trim().to_lowercase().as_str() {
+            "contains" => "within".to_string(),
+            "within" => "contains".to_string(),
+            other => other.to_string(),
+        };
+        let mut pairs: Vec<(usize, usize)> = index_join(right, left, &converse, distance)?
+            .into_iter()
+            .map(|(j, i)| (i, j))
+            .collect();
+        pairs.sort_unstable();
+        Ok(pairs)
+    } else {
+        index_join(left, right, predicate, distance)
+    }
+}
+
+/// PostgreSQL function joining two geometry arrays on an exact predicate,
+/// 'intersects', 'contains', 'within' or 'dwithin' (within `distance`),
+/// e.g. `SELECT * FROM bulk_join(parcels, flood_zones, 'intersects')`.
+/// `i` and `j` are array subscripts; NULL elements never match.
+#[allow(clippy::type_complexity)]
+#[pg_extern(immutable, strict, parallel_safe)]
+pub fn bulk_join(
+    geoms1: Array<'_, Geometry>,
+    geoms2: Array<'_, Geometry>,
+    predicate: &str,
+    distance: default!(f64, 0.0),
+) -> Result<
+    TableIterator<'static, (name!(i, i32), name!(j, i32))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let left = map_batched(geoms1.iter(), |geom| geom);
+    let right = map_batched(geoms2.iter(), |geom| geom);
+    let pairs = bulk_join_pairs(&left, &right, predicate, distance)?;
+    Ok(TableIterator::new(
+        pairs.into_iter().map(|(i, j)| (i as i32 + 1, j as i32 + 1)),
+    ))
 }
 
 /// Performance-optimized bulk geometry processing with statistics
 #[pg_extern(immutable, parallel_safe)]
-pub fn bulk_geometry_stats(geometries: Vec<Geometry>) -> String {
+pub fn bulk_geometry_stats(geometries: Array<'_, Geometry>) -> String {
     let start_time = std::time::Instant::now();
 
-    let total_count = geometries.len();
-    let areas = VectorizedOps::bulk_area_calculation(geometries.clone());
-    let _bboxes = VectorizedOps::bulk_bounding_boxes(geometries);
+    let mut stats = GeometryStats::default();
+    map_batched(geometries.iter().flatten(), |geom| stats.add(&geom));
 
-    let total_area: f64 = areas.iter().sum();
-    let avg_area = if total_count > 0 {
-        total_area / total_count as f64
-    } else {
-        0.0
-    };
-
-    let processing_time = start_time.elapsed();
-
-    format!(
-        "Processed {} geometries in {:?}\nTotal area: {:.2}\nAverage area: {:.2}\nThroughput: {:.0} geom/sec",
-        total_count,
-        processing_time,
-        total_area,
-        avg_area,
-        total_count as f64 / processing_time.as_secs_f64()
-    )
+    stats.report(start_time.elapsed())
 }
 
 #[cfg(test)]
@@ -157,6 +532,33 @@ mod tests {
     use super::*;
     use crate::functions::make_point;
 
+    #[test]
+    fn test_bulk_join_pairs() {
+        let polygon =
+            crate::functions::geometry_from_wkt("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0))").unwrap();
+        let zones = vec![Some(polygon), None];
+        let points = vec![
+            Some(make_point(1.0, 1.0)),
+            Some(make_point(9.0, 9.0)),
+            None,
+            Some(make_point(2.0, 3.0)),
+        ];
+        // Either array may be the indexed one
+        assert_eq!(
+            bulk_join_pairs(&zones, &points, "contains", 0.0).unwrap(),
+            vec![(0, 0), (0, 3)]
+        );
+        assert_eq!(
+            bulk_join_pairs(&points, &zones, "Within", 0.0).unwrap(),
+            vec![(0, 0), (3, 0)]
+        );
+        assert_eq!(
+            bulk_join_pairs(&points, &zones, "dwithin", 7.1).unwrap(),
+            vec![(0, 0), (1, 0), (3, 0)]
+        );
+        assert!(bulk_join_pairs(&points, &zones, "touches", 0.0).is_err());
+    }
+
     #[test]
     fn test_bulk_distances() {
         let points1 = vec![make_point(0.0, 0.0), make_point(1.0, 1.0)];
@@ -167,6 +569,31 @@ mod tests {
         assert!((distances[0] - 5.0).abs() < 1e-10); // Distance from (0,0) to (3,4)
     }
 
+    #[test]
+    fn test_distance_matrix() {
+        use crate::functions::geometry_from_wkt;
+
+        let geometries = vec![
+            Some(make_point(0.0, 0.0)),
+            Some(make_point(3.0, 4.0)),
+            None,
+            Some(geometry_from_wkt("LINESTRING(0 2, 10 2)").unwrap()),
+            Some(geometry_from_wkt("POINT EMPTY").unwrap()),
+        ];
+        let matrix = VectorizedOps::distance_matrix(&geometries);
+        assert_eq!(matrix.len(), 25);
+        let at = |i: usize, j: usize| matrix[i * 5 + j];
+        assert_eq!(at(0, 1), Some(5.0));
+        assert_eq!(at(1, 0), Some(5.0));
+        assert_eq!(at(0, 3), Some(2.0));
+        assert_eq!(at(3, 1), Some(2.0));
+        assert_eq!(at(3, 3), Some(0.0));
+        assert_eq!(at(0, 2), None);
+        assert_eq!(at(2, 2), None);
+        assert_eq!(at(4, 0), None);
+        assert!(VectorizedOps::distance_matrix(&[]).is_empty());
+    }
+
     #[test]
     fn test_bulk_areas() {
         use crate::functions::geometry_from_wkt;
@@ -181,4 +608,66 @@ mod tests {
         assert!((areas[0] - 1.0).abs() < 1e-10); // 1x1 square
         assert!((areas[1] - 4.0).abs() < 1e-10); // 2x2 square
     }
+
+    #[test]
+    fn test_geometry_stats() {
+        use crate::functions::geometry_from_wkt;
+
+        let mut stats = GeometryStats::default();
+        stats.add(&geometry_from_wkt("POLYGON((0 0, 2 0, 2 2, 0 2, 0 0))").unwrap());
+        stats.add(&make_point(1.0, 1.0));
+        assert_eq!(stats.count, 2);
+        assert!((stats.total_area - 4.0).abs() < 1e-10);
+        assert!((stats.average_area() - 2.0).abs() < 1e-10);
+        assert_eq!(GeometryStats::default().average_area(), 0.0);
+    }
+
+    #[test]
+    fn test_track_headings() {
+        let track = vec![
+            make_point(0.0, 0.0),
+            make_point(0.0, 1.0),
+            make_point(1.0, 1.0),
+            make_point(1.0, 1.0),
+            make_point(0.0, 0.0),
+        ];
+        assert_eq!(
+            VectorizedOps::track_azimuths(&track),
+            vec![Some(0.0), Some(90.0), None, Some(225.0)]
+        );
+        assert_eq!(VectorizedOps::turn_angle_deg(0.0, 90.0), 90.0);
+        assert_eq!(VectorizedOps::turn_angle_deg(350.0, 10.0), 20.0);
+        assert_eq!(VectorizedOps::turn_angle_deg(90.0, 0.0), -90.0);
+    }
+
+    #[test]
+    fn test_barrier_crossings() {
+        use crate::functions::geometry_from_wkt;
+
+        let wkt = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let barriers = BarrierSet::new(vec![
+            // A river running north, and a U-shaped lake
+            wkt("POLYGON((4 -10, 6 -10, 6 10, 4 10, 4 -10))"),
+            wkt("POLYGON((10 0, 20 0, 20 10, 18 10, 18 2, 12 2, 12 10, 10 10, 10 0))"),
+            wkt("POLYGON EMPTY"),
+        ])
+        .unwrap();
+        let count = |line: &str| barriers.crossings(&wkt(line)).unwrap();
+
+        assert_eq!(count("LINESTRING(0 0, 8 0)"), 1);
+        // Across the river and twice through the arms of the lake
+        assert_eq!(count("LINESTRING(0 5, 25 5)"), 3);
+        // Along the river's bank, ending inside it, and entirely inside it
+        assert_eq!(count("LINESTRING(4 -5, 4 5)"), 0);
+        assert_eq!(count("LINESTRING(0 0, 5 0)"), 1);
+        assert_eq!(count("LINESTRING(4.5 0, 5.5 0)"), 0);
+        assert_eq!(count("MULTILINESTRING((0 0, 8 0), (0 1, 8 1))"), 2);
+        // Only the part across the river counts, not the one inside it
+        assert_eq!(count("MULTILINESTRING((0 0, 8 0), (4.5 1, 5.5 1))"), 1);
+        assert_eq!(count("MULTILINESTRING((0 5, 25 5), (14 1, 16 1))"), 3);
+        assert_eq!(count("LINESTRING(30 30, 40 40)"), 0);
+
+        assert!(barriers.crossings(&make_point(0.0, 0.0)).is_err());
+        assert!(BarrierSet::new(vec![wkt("LINESTRING(0 0, 1 1)")]).is_err());
+    }
 }
@@ -0,0 +1,295 @@
+use crate::functions::geometry_as_geojson;
+use crate::geometry::Geometry;
+use crate::spatial_index::{BBox, GeometryWithId, IndexPredicate, SpatialIndex};
+use crate::table_cache::{cached, TableCache};
+use pgrx::prelude::*;
+use pgrx::spi::Spi;
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Geofencing notifications (rostgis_watch)
+//
+// Watch regions are rows of rostgis_watch. A table watched with the row
+// trigger rostgis_watch_notify sends a notification, on the region's
+// channel, for every region an inserted, updated or deleted geometry
+// intersects:
+//
+//   INSERT INTO rostgis_watch (name, region)
+//   VALUES ('depot', ST_MakeEnvelope(0, 0, 100, 100, 3857));
+//   CREATE TRIGGER vehicles_watch AFTER INSERT OR UPDATE OF geom ON vehicles
+//       FOR EACH ROW EXECUTE FUNCTION rostgis_watch_notify('geom');
+//   LISTEN rostgis_watch;
+//
+// The payload is a JSON object with the region, the table, the operation
+// and the GeoJSON of the geometry:
+//
+//   {"geometry":{"coordinates":[50,50],"type":"Point"},"operation":"INSERT",
+//    "table":"public.vehicles","watch":"depot","watch_id":1}
+//
+// NOTIFY payloads are limited to 8000 bytes, so a geometry too large for
+// one is sent as "geometry":null with its "bbox" instead. Only regions with
+// the geometry's SRID are matched. Each backend keeps the regions in an
+// R-tree, loaded again after rostgis_watch changes (see table_cache).
+// Notifications are delivered when the transaction commits.
+
+/// Largest notification payload PostgreSQL accepts, in bytes
+pub const MAX_PAYLOAD: usize = 7999;
+
+/// A watch region, without its geometry
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchRegion {
+    pub watch_id: i32,
+    pub name: String,
+    pub channel: String,
+}
+
+/// Watch regions indexed in an R-tree
+pub struct WatchIndex {
+    index: SpatialIndex,
+    regions: HashMap<i64, WatchRegion>,
+}
+
+impl WatchIndex {
+    /// Index watch regions; empty ones never match and are left out
+    pub fn new(regions: impl IntoIterator<Item = (WatchRegion, Geometry)>) -> Self {
+        let mut indexed = Vec::new();
+        let mut by_id = HashMap::new();
+        for (region, geometry) in regions {
+            if geometry.is_empty() {
+                continue;
+            }
+            let id = region.watch_id as i64;
+            indexed.push(GeometryWithId::new(id, geometry));
+            by_id.insert(id, region);
+        }
+        Self {
+            index: SpatialIndex::from_geometries(indexed),
+            regions: by_id,
+        }
+    }
+
+    /// Regions a geometry intersects, in watch_id order
+    pub fn matches(&self, geom: &Geometry) -> Vec<&WatchRegion> {
+        if geom.is_empty() {
+            return Vec::new();
+        }
+        let mut matches: Vec<&WatchRegion> = self
+            .index
+            .query_predicate(IndexPredicate::Intersects(geom))
+            .into_iter()
+            .filter(|candidate| candidate.geometry.srid() == geom.srid())
+            .filter_map(|candidate| self.regions.get(&candidate.id))
+            .collect();
+        matches.sort_by_key(|region| region.watch_id);
+        matches
+    }
+}
+
+/// JSON payload of the notification for a geometry entering a region
+pub fn notification_payload(
+    region: &WatchRegion,
+    table: &str,
+    operation: &str,
+    geom: &Geometry,
+) -> String {
+    let geojson: serde_json::Value =
+        serde_json::from_str(&geometry_as_geojson(geom.clone())).unwrap_or_default();
+    let mut payload = json!({
+        "watch_id": region.watch_id,
+        "watch": region.name,
+        "table": table,
+        "operation": operation,
+        "geometry": geojson,
+    });
+    let text = payload.to_string();
+    if text.len() <= MAX_PAYLOAD {
+        return text;
+    }
+    let bbox = BBox::from_geometry(geom);
+    payload["geometry"] = serde_json::Value::Null;
+    payload["bbox"] = json!([bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y]);
+    payload.to_string()
+}
+
+thread_local! {
+    static WATCH_INDEX: TableCache<WatchIndex> = const { RefCell::new(None) };
+}
+
+/// Index of the watch regions
+fn watch_index() -> Result<Rc<WatchIndex>, pgrx::spi::Error> {
+    cached(&WATCH_INDEX, "rostgis_watch", |schema| {
+        Spi::connect(|client| {
+            let rows = client.select(
+                &format!("SELECT watch_id, name, channel, region FROM {schema}.rostgis_watch"),
+                None,
+                &[],
+            )?;
+            let mut regions = Vec::new();
+            for row in rows {
+                if let (Some(watch_id), Some(name), Some(channel), Some(region)) = (
+                    row.get::<i32>(1)?,
+                    row.get::<String>(2)?,
+                    row.get::<String>(3)?,
+                    row.get::<Geometry>(4)?,
+                ) {
+                    let info = WatchRegion {
+                        watch_id,
+                        name,
+                        channel,
+                    };
+                    regions.push((info, region));
+                }
+            }
+            Ok(WatchIndex::new(regions))
+        })
+    })
+}
+
+/// PostgreSQL function returning the notifications due for a geometry of a
+/// table: the channel and payload for each watch region it intersects. It
+/// is called by the rostgis_watch_notify trigger.
+#[allow(clippy::type_complexity)]
+#[pg_extern(stable, strict)]
+pub fn rostgis_watch_notifications(
+    geom: Geometry,
+    table_name: &str,
+    operation: &str,
+) -> Result<
+    TableIterator<'static, (name!(channel, String), name!(payload, String))>,
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let index = watch_index()?;
+    let notifications: Vec<(String, String)> = index
+        .matches(&geom)
+        .into_iter()
+        .map(|region| {
+            (
+                region.channel.clone(),
+                notification_payload(region, table_name, operation, &geom),
+            )
+        })
+        .collect();
+    Ok(TableIterator::new(notifications))
+}
+
+extension_sql!(
+    r#"
+CREATE TABLE @extschema@.rostgis_watch (
+    watch_id serial PRIMARY KEY,
+    name text NOT NULL,
+    channel text NOT NULL DEFAULT 'rostgis_watch',
+    region @extschema@.geometry NOT NULL
+);
+
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.rostgis_watch', '');
+SELECT pg_catalog.pg_extension_config_dump('@extschema@.rostgis_watch_watch_id_seq', '');
+
+INSERT INTO @extschema@.rostgis_table_versions (table_name) VALUES ('rostgis_watch');
+CREATE TRIGGER rostgis_watch_changed
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON @extschema@.rostgis_watch
+    FOR EACH STATEMENT EXECUTE FUNCTION @extschema@.rostgis_table_changed();
+
+-- Row trigger notifying the watch regions a geometry intersects; its
+-- argument names the geometry column, geom by default. Deletions report
+-- the old geometry.
+CREATE FUNCTION @extschema@.rostgis_watch_notify() RETURNS trigger
+LANGUAGE plpgsql AS $$
+DECLARE
+    geom @extschema@.geometry;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        EXECUTE format('SELECT ($1).%I', coalesce(TG_ARGV[0], 'geom')) INTO geom USING OLD;
+    ELSE
+        EXECUTE format('SELECT ($1).%I', coalesce(TG_ARGV[0], 'geom')) INTO geom USING NEW;
+    END IF;
+    PERFORM pg_catalog.pg_notify(n.channel, n.payload)
+    FROM @extschema@.rostgis_watch_notifications(
+        geom, format('%I.%I', TG_TABLE_SCHEMA, TG_TABLE_NAME), TG_OP) n;
+    RETURN NULL;
+END
+$$;
+"#,
+    name = "watch",
+    requires = [Geometry, "table_versions", rostgis_watch_notifications],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::{geometry_from_wkt, make_point};
+
+    fn region(watch_id: i32, name: &str) -> WatchRegion {
+        WatchRegion {
+            watch_id,
+            name: name.to_string(),
+            channel: "rostgis_watch".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_watch_matches() {
+        let wkt = |wkt: &str| geometry_from_wkt(wkt).unwrap();
+        let index = WatchIndex::new(vec![
+            (
+                region(2, "yard"),
+                wkt("POLYGON((5 5, 20 5, 20 20, 5 20, 5 5))"),
+            ),
+            (
+                region(1, "depot"),
+                wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))"),
+            ),
+            (region(3, "empty"), wkt("POLYGON EMPTY")),
+            (
+                region(4, "elsewhere"),
+                wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0))").with_srid(4326),
+            ),
+        ]);
+        let names = |geom: &Geometry| -> Vec<String> {
+            index
+                .matches(geom)
+                .into_iter()
+                .map(|region| region.name.clone())
+                .collect()
+        };
+        assert_eq!(names(&make_point(7.0, 7.0)), vec!["depot", "yard"]);
+        assert_eq!(names(&make_point(1.0, 1.0)), vec!["depot"]);
+        assert!(names(&make_point(30.0, 30.0)).is_empty());
+        assert!(names(&wkt("POINT EMPTY")).is_empty());
+        assert_eq!(
+            names(&make_point(1.0, 1.0).with_srid(4326)),
+            vec!["elsewhere"]
+        );
+    }
+
+    #[test]
+    fn test_notification_payload() {
+        let payload = notification_payload(
+            &region(1, "depot"),
+            "public.vehicles",
+            "INSERT",
+            &make_point(1.0, 2.0),
+        );
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "watch_id": 1,
+                "watch": "depot",
+                "table": "public.vehicles",
+                "operation": "INSERT",
+                "geometry": {"type": "Point", "coordinates": [1, 2]},
+            })
+        );
+
+        // Too large for a notification: the bounding box replaces it
+        let coords: Vec<String> = (0..1000).map(|i| format!("{} {}", i, i % 7)).collect();
+        let line = geometry_from_wkt(&format!("LINESTRING({})", coords.join(","))).unwrap();
+        let payload = notification_payload(&region(1, "depot"), "t", "UPDATE", &line);
+        assert!(payload.len() <= MAX_PAYLOAD);
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["geometry"], serde_json::Value::Null);
+        assert_eq!(payload["bbox"], json!([0.0, 0.0, 999.0, 6.0]));
+    }
+}
@@ -40,7 +40,7 @@ INSERT INTO spatial_test (name, geom) VALUES
 
 echo "6. Creating spatial index (this is the key test)..."
 psql $PGDB -c "
-CREATE INDEX spatial_test_geom_idx ON spatial_test USING GIST (geom gist_geometry_ops_simple);
+CREATE INDEX spatial_test_geom_idx ON spatial_test USING GIST (geom);
 "
 
 if [ $? -eq 0 ]; then
@@ -89,5 +89,5 @@ echo "then spatial indexing is working correctly!"
 echo ""
 echo "You can now use spatial indexes in your applications by:"
 echo "1. Creating tables with GEOMETRY columns"
-echo "2. Creating indexes with: CREATE INDEX ... USING GIST (geom_column gist_geometry_ops_simple);"
+echo "2. Creating indexes with: CREATE INDEX ... USING GIST (geom_column);"
 echo "3. Using spatial operators like &&, <<, >>, etc. in WHERE clauses" 
\ No newline at end of file
@@ -159,8 +159,8 @@ mod integration_tests {
         assert_eq!(geometry_x(point_3d.clone()).unwrap(), 1.0);
         assert_eq!(geometry_y(point_3d.clone()).unwrap(), 2.0);
 
-        // Z coordinate not fully implemented yet
-        assert_eq!(geometry_z(point_3d.clone()), None);
+        // Z coordinate is stored
+        assert_eq!(geometry_z(point_3d.clone()), Some(3.0));
 
         // Should still be a point type
         assert_eq!(geometry_type(point_3d), "ST_Point");